@@ -1,20 +1,33 @@
-use std::collections::VecDeque;
+use std::collections::{HashSet, VecDeque};
 use std::time::Duration;
 
 use modular_agent_core::{
-    ModularAgent, AgentContext, AgentData, AgentError, AgentOutput, AgentSpec, AgentValue, AsAgent,
-    modular_agent, async_trait,
+    ModularAgent, Agent, AgentContext, AgentData, AgentError, AgentOutput, AgentSpec, AgentValue,
+    AsAgent, modular_agent, async_trait,
 };
 use im::{Vector, vector};
 use mini_moka::sync::Cache;
 
+use crate::condition::{
+    CONFIG_KEY, CONFIG_OP, CONFIG_OPERAND, Condition, OP_DEFAULT, OP_DESCRIPTION,
+};
+use crate::contract::{CONFIG_INPUT_CONTRACT, ContractAgent, InputContract, contract_agents};
+use crate::data::get_nested_value;
+use crate::provenance::Traced;
+use crate::tenant::{CONFIG_PER_TENANT, TenantMap, tenant_key};
+
 const CATEGORY: &str = "Std/Array";
 
 const PORT_ARRAY: &str = "array";
+const PORT_DIFFERENCE: &str = "difference";
 const PORT_IN1: &str = "in1";
 const PORT_IN2: &str = "in2";
+const PORT_INTERSECTION: &str = "intersection";
+const PORT_MATCH: &str = "match";
+const PORT_REST: &str = "rest";
 const PORT_T: &str = "T";
 const PORT_F: &str = "F";
+const PORT_UNION: &str = "union";
 const PORT_VALUE: &str = "value";
 
 const CONFIG_N: &str = "n";
@@ -22,33 +35,60 @@ const CONFIG_USE_CTX: &str = "use_ctx";
 const CONFIG_TTL_SEC: &str = "ttl_sec";
 const CONFIG_CAPACITY: &str = "capacity";
 
+contract_agents!(
+    IsArrayAgent,
+    IsEmptyArrayAgent,
+    ArrayLengthAgent,
+    ArrayFirstAgent,
+    ArrayRestAgent,
+    ArrayLastAgent,
+    ArrayNthAgent,
+    ArrayTakeAgent,
+    MapAgent,
+    CollectAgent,
+    ZipToArrayAgent,
+    PartitionAgent,
+    SetOpsAgent,
+);
+
 /// Check if an input is an array.
 #[modular_agent(
     title = "IsArray",
     category = CATEGORY,
     inputs = [PORT_VALUE],
     outputs = [PORT_T, PORT_F],
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
 )]
 struct IsArrayAgent {
     data: AgentData,
+    contract: InputContract,
 }
 
 #[async_trait]
 impl AsAgent for IsArrayAgent {
-    fn new(ma: ModularAgent, id: String, spec: AgentSpec) -> Result<Self, AgentError> {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
         let data = AgentData::new(ma, id, spec);
-        Ok(Self { data })
+        Ok(Self { data, contract })
+    }
+
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
     }
+
     async fn process(
         &mut self,
         ctx: AgentContext,
-        _port: String,
+        port: String,
         value: AgentValue,
     ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
         if value.is_array() {
-            self.output(ctx, PORT_T, value).await
+            self.output(self.traced(ctx), PORT_T, value).await
         } else {
-            self.output(ctx, PORT_F, value).await
+            self.output(self.traced(ctx), PORT_F, value).await
         }
     }
 }
@@ -60,24 +100,34 @@ impl AsAgent for IsArrayAgent {
     category = CATEGORY,
     inputs = [PORT_ARRAY],
     outputs = [PORT_T, PORT_F],
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
 )]
 struct IsEmptyArrayAgent {
     data: AgentData,
+    contract: InputContract,
 }
 
 #[async_trait]
 impl AsAgent for IsEmptyArrayAgent {
-    fn new(ma: ModularAgent, id: String, spec: AgentSpec) -> Result<Self, AgentError> {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
         let data = AgentData::new(ma, id, spec);
-        Ok(Self { data })
+        Ok(Self { data, contract })
+    }
+
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
     }
 
     async fn process(
         &mut self,
         ctx: AgentContext,
-        _port: String,
+        port: String,
         value: AgentValue,
     ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
         let mut is_empty = false;
         if value.is_array() {
             let arr = value.as_array().unwrap();
@@ -86,9 +136,9 @@ impl AsAgent for IsEmptyArrayAgent {
             }
         }
         if is_empty {
-            self.output(ctx, PORT_T, value).await
+            self.output(self.traced(ctx), PORT_T, value).await
         } else {
-            self.output(ctx, PORT_F, value).await
+            self.output(self.traced(ctx), PORT_F, value).await
         }
     }
 }
@@ -101,31 +151,42 @@ impl AsAgent for IsEmptyArrayAgent {
     category = CATEGORY,
     inputs = [PORT_ARRAY],
     outputs = [PORT_VALUE],
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
 )]
 struct ArrayLengthAgent {
     data: AgentData,
+    contract: InputContract,
 }
 
 #[async_trait]
 impl AsAgent for ArrayLengthAgent {
-    fn new(ma: ModularAgent, id: String, spec: AgentSpec) -> Result<Self, AgentError> {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
         let data = AgentData::new(ma, id, spec);
-        Ok(Self { data })
+        Ok(Self { data, contract })
+    }
+
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
     }
 
     async fn process(
         &mut self,
         ctx: AgentContext,
-        _port: String,
+        port: String,
         value: AgentValue,
     ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
         let length = if value.is_array() {
             let arr = value.as_array().unwrap();
             arr.len() as i64
         } else {
             1
         };
-        self.output(ctx, PORT_VALUE, AgentValue::integer(length)).await
+        self.output(self.traced(ctx), PORT_VALUE, AgentValue::integer(length))
+            .await
     }
 }
 
@@ -137,35 +198,45 @@ impl AsAgent for ArrayLengthAgent {
     category = CATEGORY,
     inputs = [PORT_ARRAY],
     outputs = [PORT_VALUE],
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
 )]
 struct ArrayFirstAgent {
     data: AgentData,
+    contract: InputContract,
 }
 
 #[async_trait]
 impl AsAgent for ArrayFirstAgent {
-    fn new(ma: ModularAgent, id: String, spec: AgentSpec) -> Result<Self, AgentError> {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
         let data = AgentData::new(ma, id, spec);
-        Ok(Self { data })
+        Ok(Self { data, contract })
+    }
+
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
     }
 
     async fn process(
         &mut self,
         ctx: AgentContext,
-        _port: String,
+        port: String,
         value: AgentValue,
     ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
         match value {
             AgentValue::Array(mut arr) => {
                 if let Some(first_item) = arr.pop_front() {
-                    self.output(ctx, PORT_VALUE, first_item).await
+                    self.output(self.traced(ctx), PORT_VALUE, first_item).await
                 } else {
                     Err(AgentError::InvalidValue(
                         "Input array is empty, no first item".into(),
                     ))
                 }
             }
-            other => self.output(ctx, PORT_VALUE, other).await,
+            other => self.output(self.traced(ctx), PORT_VALUE, other).await,
         }
     }
 }
@@ -178,32 +249,46 @@ impl AsAgent for ArrayFirstAgent {
     category = CATEGORY,
     inputs = [PORT_ARRAY],
     outputs = [PORT_ARRAY],
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
 )]
 struct ArrayRestAgent {
     data: AgentData,
+    contract: InputContract,
 }
 
 #[async_trait]
 impl AsAgent for ArrayRestAgent {
-    fn new(ma: ModularAgent, id: String, spec: AgentSpec) -> Result<Self, AgentError> {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
         let data = AgentData::new(ma, id, spec);
-        Ok(Self { data })
+        Ok(Self { data, contract })
+    }
+
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
     }
 
     async fn process(
         &mut self,
         ctx: AgentContext,
-        _port: String,
+        port: String,
         value: AgentValue,
     ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
         if let Some(mut arr) = value.into_array() {
             if arr.is_empty() {
-                return self.output(ctx, PORT_ARRAY, AgentValue::array_default()).await;
+                return self
+                    .output(self.traced(ctx), PORT_ARRAY, AgentValue::array_default())
+                    .await;
             }
             arr.pop_front();
-            self.output(ctx, PORT_ARRAY, AgentValue::array(arr)).await
+            self.output(self.traced(ctx), PORT_ARRAY, AgentValue::array(arr))
+                .await
         } else {
-            self.output(ctx, PORT_ARRAY, AgentValue::array_default()).await
+            self.output(self.traced(ctx), PORT_ARRAY, AgentValue::array_default())
+                .await
         }
     }
 }
@@ -216,35 +301,45 @@ impl AsAgent for ArrayRestAgent {
     category = CATEGORY,
     inputs = [PORT_ARRAY],
     outputs = [PORT_VALUE],
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
 )]
 struct ArrayLastAgent {
     data: AgentData,
+    contract: InputContract,
 }
 
 #[async_trait]
 impl AsAgent for ArrayLastAgent {
-    fn new(ma: ModularAgent, id: String, spec: AgentSpec) -> Result<Self, AgentError> {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
         let data = AgentData::new(ma, id, spec);
-        Ok(Self { data })
+        Ok(Self { data, contract })
+    }
+
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
     }
 
     async fn process(
         &mut self,
         ctx: AgentContext,
-        _port: String,
+        port: String,
         value: AgentValue,
     ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
         match value {
             AgentValue::Array(mut arr) => {
                 if let Some(last_item) = arr.pop_back() {
-                    self.output(ctx, PORT_VALUE, last_item).await
+                    self.output(self.traced(ctx), PORT_VALUE, last_item).await
                 } else {
                     Err(AgentError::InvalidValue(
                         "Input array is empty, no last item".into(),
                     ))
                 }
             }
-            other => self.output(ctx, PORT_VALUE, other).await,
+            other => self.output(self.traced(ctx), PORT_VALUE, other).await,
         }
     }
 }
@@ -258,24 +353,34 @@ impl AsAgent for ArrayLastAgent {
     inputs = [PORT_ARRAY],
     outputs = [PORT_VALUE],
     integer_config(name = CONFIG_N, default = 0),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
 )]
 struct ArrayNthAgent {
     data: AgentData,
+    contract: InputContract,
 }
 
 #[async_trait]
 impl AsAgent for ArrayNthAgent {
-    fn new(ma: ModularAgent, id: String, spec: AgentSpec) -> Result<Self, AgentError> {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
         let data = AgentData::new(ma, id, spec);
-        Ok(Self { data })
+        Ok(Self { data, contract })
+    }
+
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
     }
 
     async fn process(
         &mut self,
         ctx: AgentContext,
-        _port: String,
+        port: String,
         value: AgentValue,
     ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
         let n = self
             .data
             .spec
@@ -291,7 +396,8 @@ impl AsAgent for ArrayNthAgent {
         match value {
             AgentValue::Array(arr) => {
                 if let Some(item) = arr.get(n) {
-                    self.output(ctx, PORT_VALUE, item.clone()).await
+                    self.output(self.traced(ctx), PORT_VALUE, item.clone())
+                        .await
                 } else {
                     Err(AgentError::InvalidValue(format!(
                         "Input array length {} is less than n+1={}",
@@ -302,7 +408,7 @@ impl AsAgent for ArrayNthAgent {
             }
             other => {
                 if n == 0 {
-                    self.output(ctx, PORT_VALUE, other).await
+                    self.output(self.traced(ctx), PORT_VALUE, other).await
                 } else {
                     Err(AgentError::InvalidValue(
                         "Input is not an array and n != 0".into(),
@@ -322,24 +428,34 @@ impl AsAgent for ArrayNthAgent {
     inputs = [PORT_ARRAY],
     outputs = [PORT_ARRAY],
     integer_config(name = CONFIG_N, default = 0),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
 )]
 struct ArrayTakeAgent {
     data: AgentData,
+    contract: InputContract,
 }
 
 #[async_trait]
 impl AsAgent for ArrayTakeAgent {
-    fn new(ma: ModularAgent, id: String, spec: AgentSpec) -> Result<Self, AgentError> {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
         let data = AgentData::new(ma, id, spec);
-        Ok(Self { data })
+        Ok(Self { data, contract })
+    }
+
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
     }
 
     async fn process(
         &mut self,
         ctx: AgentContext,
-        _port: String,
+        port: String,
         value: AgentValue,
     ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
         let n = self
             .data
             .spec
@@ -349,19 +465,27 @@ impl AsAgent for ArrayTakeAgent {
             .unwrap_or(0);
         if n <= 0 {
             // output empty array
-            return self.output(ctx, PORT_ARRAY, AgentValue::array_default()).await;
+            return self
+                .output(self.traced(ctx), PORT_ARRAY, AgentValue::array_default())
+                .await;
         }
         let n = n as usize;
 
         if value.is_array() {
             let arr = value.as_array().unwrap();
             if n >= arr.len() {
-                return self.output(ctx, PORT_ARRAY, value).await;
+                return self.output(self.traced(ctx), PORT_ARRAY, value).await;
             }
             let taken_items = arr.take(n);
-            self.output(ctx, PORT_ARRAY, AgentValue::array(taken_items)).await
+            self.output(self.traced(ctx), PORT_ARRAY, AgentValue::array(taken_items))
+                .await
         } else {
-            self.output(ctx, PORT_ARRAY, AgentValue::array(vector![value])).await
+            self.output(
+                self.traced(ctx),
+                PORT_ARRAY,
+                AgentValue::array(vector![value]),
+            )
+            .await
         }
     }
 }
@@ -373,35 +497,45 @@ impl AsAgent for ArrayTakeAgent {
     category = CATEGORY,
     inputs = [PORT_ARRAY],
     outputs = [PORT_VALUE],
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
 )]
 struct MapAgent {
     data: AgentData,
+    contract: InputContract,
 }
 
 #[async_trait]
 impl AsAgent for MapAgent {
-    fn new(ma: ModularAgent, id: String, spec: AgentSpec) -> Result<Self, AgentError> {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
         let data = AgentData::new(ma, id, spec);
-        Ok(Self { data })
+        Ok(Self { data, contract })
+    }
+
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
     }
 
     async fn process(
         &mut self,
         ctx: AgentContext,
-        _port: String,
+        port: String,
         value: AgentValue,
     ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
         match value {
             AgentValue::Array(arr) => {
                 let n = arr.len();
                 for (i, item) in arr.into_iter().enumerate() {
                     let c = ctx.push_map_frame(i, n)?;
-                    self.output(c, PORT_VALUE, item).await?;
+                    self.output(self.traced(c), PORT_VALUE, item).await?;
                 }
             }
             other => {
                 let c = ctx.push_map_frame(0, 1)?;
-                self.output(c, PORT_VALUE, other).await?;
+                self.output(self.traced(c), PORT_VALUE, other).await?;
             }
         }
         Ok(())
@@ -421,9 +555,11 @@ impl AsAgent for MapAgent {
     description = "Collects input values into an array",
     inputs = [PORT_VALUE],
     outputs = [PORT_ARRAY],
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
 )]
 struct CollectAgent {
     data: AgentData,
+    contract: InputContract,
 
     // Records the context ID being processed to prevent other contexts from mixing
     current_ctx_id: Option<usize>,
@@ -440,10 +576,12 @@ struct CollectAgent {
 
 #[async_trait]
 impl AsAgent for CollectAgent {
-    fn new(ma: ModularAgent, id: String, spec: AgentSpec) -> Result<Self, AgentError> {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
         let data = AgentData::new(ma, id, spec);
         Ok(Self {
             data,
+            contract,
             current_ctx_id: None,
             input_values: Vec::new(),
             expected_size: 0,
@@ -451,16 +589,23 @@ impl AsAgent for CollectAgent {
         })
     }
 
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
+    }
+
     async fn process(
         &mut self,
         ctx: AgentContext,
-        _port: String,
+        port: String,
         value: AgentValue,
     ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
         // Check for map frame
         // If not within a map, pass the value through as-is.
         let Some((idx, n)) = ctx.current_map_frame()? else {
-            return self.output(ctx, PORT_ARRAY, value).await;
+            return self.output(self.traced(ctx), PORT_ARRAY, value).await;
         };
 
         // Detect context switch and flush processing
@@ -514,7 +659,8 @@ impl AsAgent for CollectAgent {
 
             // Pop one map frame and output
             let next_ctx = ctx.pop_map_frame()?;
-            self.output(next_ctx, PORT_ARRAY, AgentValue::array(arr)).await
+            self.output(self.traced(next_ctx), PORT_ARRAY, AgentValue::array(arr))
+                .await
         } else {
             // Not yet complete, keep waiting
             Ok(())
@@ -547,7 +693,8 @@ impl CollectAgent {
 /// it emits them as [in1, in2].
 ///
 /// If in2 arrives repeatedly before in1, the in2 values are queued; when in1 arrives,
-/// they’re paired in order from the head of the queue and emitted.
+/// they’re paired in order from the head of the queue and emitted. With `per tenant`,
+/// each tenant id in the context has its own queues.
 ///
 /// When the `use_ctx` config is true, inputs are matched by context key (including map frames)
 /// so that mapped items zip correctly even when they interleave.
@@ -560,15 +707,18 @@ impl CollectAgent {
     boolean_config(name = CONFIG_USE_CTX),
     integer_config(name = CONFIG_TTL_SEC, default = 60), 
     integer_config(name = CONFIG_CAPACITY, default = 1000),
+    boolean_config(name = CONFIG_PER_TENANT, title = "per tenant"),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
 )]
 struct ZipToArrayAgent {
     data: AgentData,
+    contract: InputContract,
     n: usize,
     use_ctx: bool,
 
     ttl_sec: u64,
     capacity: u64,
-    queues: Vec<VecDeque<AgentValue>>, // for non-ctx mode
+    queues: TenantMap<Vec<VecDeque<AgentValue>>>, // for non-ctx mode, by tenant id
 
     // Context Key -> PendingZip
     ctx_buffers: Cache<String, PendingZip>,
@@ -615,7 +765,7 @@ impl ZipToArrayAgent {
     }
 
     fn reset_state(&mut self) {
-        self.queues = vec![VecDeque::new(); self.n];
+        self.queues.clear();
         self.ctx_buffers.invalidate_all();
     }
 }
@@ -624,6 +774,7 @@ impl ZipToArrayAgent {
 impl AsAgent for ZipToArrayAgent {
     fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
         let (n, use_ctx, ttl_sec, capacity) = Self::update_spec(&mut spec)?;
+        let contract = InputContract::update_spec(&mut spec)?;
 
         let cache = Cache::builder()
             .max_capacity(capacity) // Capacity limit (oldest entries are evicted on overflow)
@@ -634,17 +785,19 @@ impl AsAgent for ZipToArrayAgent {
 
         Ok(Self {
             data,
+            contract,
             n,
             use_ctx,
             ttl_sec,
             capacity,
-            queues: vec![VecDeque::new(); n],
+            queues: TenantMap::default(),
             ctx_buffers: cache,
         })
     }
 
     fn configs_changed(&mut self) -> Result<(), AgentError> {
         let (n, use_ctx, ttl_sec, capacity) = Self::update_spec(&mut self.data.spec)?;
+        self.reload_contract()?;
         let mut changed = false;
         if n != self.n {
             self.n = n;
@@ -685,6 +838,9 @@ impl AsAgent for ZipToArrayAgent {
         port: String,
         value: AgentValue,
     ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
         // Parse port number
         let Some(idx) = port
             .strip_prefix("in")
@@ -723,25 +879,365 @@ impl AsAgent for ZipToArrayAgent {
                     .map(|v| v.unwrap())
                     .collect();
 
-                return self.output(ctx, PORT_ARRAY, AgentValue::array(arr)).await;
+                return self
+                    .output(self.traced(ctx), PORT_ARRAY, AgentValue::array(arr))
+                    .await;
             }
 
             return Ok(());
         }
 
         // Simple FIFO mode processing
-        self.queues[idx].push_back(value);
+        let tenant = tenant_key(&ctx, self.configs()?.get_bool_or_default(CONFIG_PER_TENANT));
 
-        // Check if all queues have data
-        if self.queues.iter().all(|q| !q.is_empty()) {
-            let arr: Vector<AgentValue> = self.queues
-                .iter_mut()
-                .map(|q| q.pop_front().unwrap())
-                .collect();
+        // Once all queues have data
+        if let Some(values) = self.queues.push_zip(tenant, self.n, idx, value) {
+            let arr: Vector<AgentValue> = values.into_iter().collect();
 
-            self.output(ctx, PORT_ARRAY, AgentValue::array(arr)).await
+            self.output(self.traced(ctx), PORT_ARRAY, AgentValue::array(arr))
+                .await
         } else {
             Ok(())
         }
     }
 }
+
+/// Splits the input array into the items that match a condition and the rest.
+///
+/// Each item is tested with `key` (a key path, empty for the item itself),
+/// `op` and `operand`. Both arrays keep the input order and are emitted at once.
+/// If the input is not an array, it is treated as a single-item array.
+#[modular_agent(
+    title = "Partition",
+    category = CATEGORY,
+    inputs = [PORT_ARRAY],
+    outputs = [PORT_MATCH, PORT_REST],
+    string_config(name = CONFIG_KEY),
+    string_config(name = CONFIG_OP, default = OP_DEFAULT, description = OP_DESCRIPTION),
+    string_config(name = CONFIG_OPERAND),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
+)]
+struct PartitionAgent {
+    data: AgentData,
+    contract: InputContract,
+}
+
+#[async_trait]
+impl AsAgent for PartitionAgent {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
+        let data = AgentData::new(ma, id, spec);
+        Ok(Self { data, contract })
+    }
+
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
+    }
+
+    async fn process(
+        &mut self,
+        ctx: AgentContext,
+        port: String,
+        value: AgentValue,
+    ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
+        let condition = Condition::from_configs(self.configs()?)?;
+
+        let arr = match value {
+            AgentValue::Array(arr) => arr,
+            other => vector![other],
+        };
+        let (matched, rest): (Vector<AgentValue>, Vector<AgentValue>) =
+            arr.into_iter().partition(|v| condition.matches(v));
+
+        self.output(
+            self.traced(ctx.clone()),
+            PORT_MATCH,
+            AgentValue::array(matched),
+        )
+        .await?;
+        self.output(self.traced(ctx), PORT_REST, AgentValue::array(rest))
+            .await
+    }
+}
+/// Set operations on two arrays.
+///
+/// Arrays on in1 and in2 are paired like ZipToArray, per tenant with `per tenant`.
+/// Once both are present, it emits the union, the intersection and the difference (in1 - in2).
+/// Results keep the order of in1 followed by in2, without duplicates.
+///
+/// Items are compared by the value at the `key` path (e.g. `id` or `meta.id`),
+/// or by the whole item when `key` is empty.
+#[modular_agent(
+    title = "SetOps",
+    category = CATEGORY,
+    inputs = [PORT_IN1, PORT_IN2],
+    outputs = [PORT_UNION, PORT_INTERSECTION, PORT_DIFFERENCE],
+    string_config(name = CONFIG_KEY),
+    boolean_config(name = CONFIG_USE_CTX),
+    integer_config(name = CONFIG_TTL_SEC, default = 60),
+    integer_config(name = CONFIG_CAPACITY, default = 1000),
+    boolean_config(name = CONFIG_PER_TENANT, title = "per tenant"),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
+)]
+struct SetOpsAgent {
+    data: AgentData,
+    contract: InputContract,
+    ttl_sec: u64,
+    capacity: u64,
+    queues: TenantMap<Vec<VecDeque<Vector<AgentValue>>>>, // for non-ctx mode, by tenant id
+
+    // Context Key -> PendingZip
+    ctx_buffers: Cache<String, PendingZip>,
+}
+
+impl SetOpsAgent {
+    fn cache_configs(spec: &AgentSpec) -> (u64, u64) {
+        let ttl_sec = spec
+            .configs
+            .as_ref()
+            .map(|c| c.get_integer_or(CONFIG_TTL_SEC, 60))
+            .unwrap_or(60) as u64;
+        let capacity = spec
+            .configs
+            .as_ref()
+            .map(|c| c.get_integer_or(CONFIG_CAPACITY, 1000))
+            .unwrap_or(1000) as u64;
+        (ttl_sec, capacity)
+    }
+
+    fn reset_state(&mut self) {
+        self.queues.clear();
+        self.ctx_buffers.invalidate_all();
+    }
+
+    async fn output_set_ops(
+        &mut self,
+        ctx: AgentContext,
+        a: Vector<AgentValue>,
+        b: Vector<AgentValue>,
+    ) -> Result<(), AgentError> {
+        let key = self.configs()?.get_string_or_default(CONFIG_KEY);
+        let keys: Vec<&str> = if key.is_empty() {
+            Vec::new()
+        } else {
+            key.split('.').collect()
+        };
+
+        let (union, intersection, difference) = set_ops(&a, &b, &keys);
+        self.output(
+            self.traced(ctx.clone()),
+            PORT_UNION,
+            AgentValue::array(union),
+        )
+        .await?;
+        self.output(
+            self.traced(ctx.clone()),
+            PORT_INTERSECTION,
+            AgentValue::array(intersection),
+        )
+        .await?;
+        self.output(
+            self.traced(ctx),
+            PORT_DIFFERENCE,
+            AgentValue::array(difference),
+        )
+        .await
+    }
+}
+
+#[async_trait]
+impl AsAgent for SetOpsAgent {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
+        let (ttl_sec, capacity) = Self::cache_configs(&spec);
+        let cache = Cache::builder()
+            .max_capacity(capacity)
+            .time_to_live(Duration::from_secs(ttl_sec))
+            .build();
+        Ok(Self {
+            data: AgentData::new(ma, id, spec),
+            contract,
+            ttl_sec,
+            capacity,
+            queues: Default::default(),
+            ctx_buffers: cache,
+        })
+    }
+
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()?;
+        let (ttl_sec, capacity) = Self::cache_configs(&self.data.spec);
+        if ttl_sec != self.ttl_sec || capacity != self.capacity {
+            self.ttl_sec = ttl_sec;
+            self.capacity = capacity;
+            self.ctx_buffers = Cache::builder()
+                .max_capacity(capacity)
+                .time_to_live(Duration::from_secs(ttl_sec))
+                .build();
+        }
+        Ok(())
+    }
+
+    async fn stop(&mut self) -> Result<(), AgentError> {
+        self.reset_state();
+        Ok(())
+    }
+
+    async fn process(
+        &mut self,
+        ctx: AgentContext,
+        port: String,
+        value: AgentValue,
+    ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
+        let idx = match port.as_str() {
+            PORT_IN1 => 0,
+            PORT_IN2 => 1,
+            _ => return Err(AgentError::InvalidPin(port)),
+        };
+        let Some(arr) = value.into_array() else {
+            return Err(AgentError::InvalidArrayValue("Expected array".into()));
+        };
+
+        if self.configs()?.get_bool_or_default(CONFIG_USE_CTX) {
+            let ctx_key = ctx.ctx_key()?;
+
+            let mut entry = self
+                .ctx_buffers
+                .get(&ctx_key)
+                .unwrap_or_else(|| PendingZip {
+                    values: vec![None; 2],
+                    count: 0,
+                });
+            if entry.values[idx].is_none() {
+                entry.count += 1;
+            }
+            entry.values[idx] = Some(AgentValue::array(arr));
+
+            if entry.count < 2 {
+                self.ctx_buffers.insert(ctx_key, entry);
+                return Ok(());
+            }
+            self.ctx_buffers.invalidate(&ctx_key);
+
+            let mut values = entry
+                .values
+                .into_iter()
+                .flatten()
+                .filter_map(|v| v.into_array());
+            let (Some(a), Some(b)) = (values.next(), values.next()) else {
+                return Ok(());
+            };
+            return self.output_set_ops(ctx, a, b).await;
+        }
+
+        // Simple FIFO mode processing
+        let tenant = tenant_key(&ctx, self.configs()?.get_bool_or_default(CONFIG_PER_TENANT));
+        let Some([a, b]) = self
+            .queues
+            .push_zip(tenant, 2, idx, arr)
+            .and_then(|pair| <[_; 2]>::try_from(pair).ok())
+        else {
+            return Ok(());
+        };
+        self.output_set_ops(ctx, a, b).await
+    }
+}
+
+// Returns (union, intersection, difference) of a and b, compared by the value at keys.
+fn set_ops<K: AsRef<str>>(
+    a: &Vector<AgentValue>,
+    b: &Vector<AgentValue>,
+    keys: &[K],
+) -> (Vector<AgentValue>, Vector<AgentValue>, Vector<AgentValue>) {
+    let identity = |v: &AgentValue| {
+        get_nested_value(v, keys).map(|id| serde_json::to_string(id).unwrap_or_default())
+    };
+    let b_ids: HashSet<String> = b.iter().filter_map(identity).collect();
+
+    let mut union = Vector::new();
+    let mut intersection = Vector::new();
+    let mut difference = Vector::new();
+    let mut seen = HashSet::new();
+    for v in a {
+        let Some(id) = identity(v) else {
+            continue;
+        };
+        if !seen.insert(id.clone()) {
+            continue;
+        }
+        union.push_back(v.clone());
+        if b_ids.contains(&id) {
+            intersection.push_back(v.clone());
+        } else {
+            difference.push_back(v.clone());
+        }
+    }
+    for v in b {
+        if let Some(id) = identity(v)
+            && seen.insert(id)
+        {
+            union.push_back(v.clone());
+        }
+    }
+    (union, intersection, difference)
+}
+
+#[cfg(test)]
+mod tests {
+    use im::hashmap;
+
+    use super::*;
+
+    fn item(id: i64, name: &str) -> AgentValue {
+        AgentValue::object(hashmap! {
+            "id".to_string() => AgentValue::integer(id),
+            "name".to_string() => AgentValue::string(name),
+        })
+    }
+
+    #[test]
+    fn test_set_ops_values() {
+        let a = vector![
+            AgentValue::integer(1),
+            AgentValue::integer(2),
+            AgentValue::integer(2),
+            AgentValue::integer(3),
+        ];
+        let b = vector![AgentValue::integer(3), AgentValue::integer(4)];
+        let no_keys: [&str; 0] = [];
+
+        let (union, intersection, difference) = set_ops(&a, &b, &no_keys);
+        assert_eq!(
+            union,
+            vector![
+                AgentValue::integer(1),
+                AgentValue::integer(2),
+                AgentValue::integer(3),
+                AgentValue::integer(4),
+            ]
+        );
+        assert_eq!(intersection, vector![AgentValue::integer(3)]);
+        assert_eq!(
+            difference,
+            vector![AgentValue::integer(1), AgentValue::integer(2)]
+        );
+    }
+
+    #[test]
+    fn test_set_ops_key_path() {
+        let a = vector![item(1, "a"), item(2, "b")];
+        let b = vector![item(2, "B"), item(3, "c"), AgentValue::integer(4)];
+
+        // items are identified by id; items without the key are ignored
+        let (union, intersection, difference) = set_ops(&a, &b, &["id"]);
+        assert_eq!(union, vector![item(1, "a"), item(2, "b"), item(3, "c")]);
+        assert_eq!(intersection, vector![item(2, "b")]);
+        assert_eq!(difference, vector![item(1, "a")]);
+    }
+}
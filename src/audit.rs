@@ -0,0 +1,431 @@
+//! Audit log.
+//!
+//! When the `MODULAR_AGENT_AUDIT` environment variable is set, or [`set_audit_sink`] is
+//! called, the agents of this crate that change external state (file writes and moves,
+//! checkpoints, time series, watch state files, config updates, HTTP submissions) append
+//! an audit record before acting:
+//!
+//! `{time, agent, def_name, tenant, flow, action, target, payload_hash}`
+//!
+//! `payload_hash` is the SHA-256 of the JSON of the payload, so the records show what
+//! was done without copying the data. The sink is the path of a JSONL file to append to,
+//! or, with the `http` feature, an `http://` or `https://` URL the records are POSTed to
+//! as JSON. An action whose record cannot be written fails, so nothing is done
+//! unaudited. The Audit Query agent reads the records back from a file.
+
+use std::fs;
+use std::future::Future;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{LazyLock, Mutex, RwLock};
+
+use chrono::Utc;
+use im::hashmap;
+use modular_agent_core::{
+    Agent, AgentContext, AgentData, AgentError, AgentOutput, AgentSpec, AgentValue, AsAgent,
+    ModularAgent, async_trait, modular_agent,
+};
+use sha2::{Digest, Sha256};
+
+use crate::profile::ProfileConfigs;
+use crate::provenance::Traced;
+use crate::tenant::tenant_id;
+use crate::time::parse_duration_to_ms;
+
+const CATEGORY: &str = "Std/System";
+
+pub(crate) const AUDIT_ENV: &str = "MODULAR_AGENT_AUDIT";
+
+#[cfg(feature = "http")]
+pub(crate) const ACTION_HTTP_SUBMIT: &str = "http_submit";
+pub(crate) const ACTION_MOVE_FILE: &str = "move_file";
+pub(crate) const ACTION_SET_CONFIG: &str = "set_config";
+pub(crate) const ACTION_WRITE_FILE: &str = "write_file";
+
+const PORT_RECORDS: &str = "records";
+const PORT_TRIGGER: &str = "trigger";
+
+const CONFIG_ACTION: &str = "action";
+const CONFIG_AGENT: &str = "agent";
+const CONFIG_LIMIT: &str = "limit";
+const CONFIG_PATH: &str = "path";
+const CONFIG_SINCE: &str = "since";
+const CONFIG_TENANT: &str = "tenant";
+
+const KEY_ACTION: &str = "action";
+const KEY_AGENT: &str = "agent";
+const KEY_DEF_NAME: &str = "def_name";
+const KEY_FLOW: &str = "flow";
+const KEY_PAYLOAD_HASH: &str = "payload_hash";
+const KEY_TARGET: &str = "target";
+const KEY_TENANT: &str = "tenant";
+const KEY_TIME: &str = "time";
+
+const LIMIT_DEFAULT: i64 = 100;
+
+#[derive(Clone)]
+enum AuditSink {
+    File(PathBuf),
+    #[cfg(feature = "http")]
+    Http(String),
+}
+
+fn parse_sink(s: &str) -> Option<AuditSink> {
+    let s = s.trim();
+    if s.is_empty() {
+        return None;
+    }
+    if s.starts_with("http://") || s.starts_with("https://") {
+        #[cfg(feature = "http")]
+        return Some(AuditSink::Http(s.to_string()));
+        #[cfg(not(feature = "http"))]
+        {
+            log::error!(
+                "The audit sink {} is a URL, but the http feature is disabled; auditing to a file named so",
+                s
+            );
+        }
+    }
+    Some(AuditSink::File(PathBuf::from(s)))
+}
+
+static SINK: LazyLock<RwLock<Option<AuditSink>>> =
+    LazyLock::new(|| RwLock::new(std::env::var(AUDIT_ENV).ok().and_then(|s| parse_sink(&s))));
+
+fn current_sink() -> Option<AuditSink> {
+    SINK.read().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+/// Sets where the audit records go, in place of `MODULAR_AGENT_AUDIT`: the path of a JSONL
+/// file, or an `http://` or `https://` URL with the `http` feature. Blank turns auditing
+/// off.
+pub fn set_audit_sink(sink: &str) {
+    *SINK.write().unwrap_or_else(|e| e.into_inner()) = parse_sink(sink);
+}
+
+// Serializes the appends of the records, so that lines are never interleaved. The
+// appends run on the blocking pool, so waiting for the lock never blocks the runtime.
+static FILE_LOCK: Mutex<()> = Mutex::new(());
+
+pub(crate) fn payload_hash(payload: &AgentValue) -> String {
+    format!("{:x}", Sha256::digest(payload.to_json().to_string()))
+}
+
+fn audit_record(
+    agent_id: &str,
+    def_name: &str,
+    ctx: &AgentContext,
+    action: &str,
+    target: &str,
+    payload: &AgentValue,
+    time: i64,
+) -> AgentValue {
+    AgentValue::object(hashmap! {
+        KEY_TIME.to_string() => AgentValue::integer(time),
+        KEY_AGENT.to_string() => AgentValue::string(agent_id),
+        KEY_DEF_NAME.to_string() => AgentValue::string(def_name),
+        KEY_TENANT.to_string() => AgentValue::string(tenant_id(ctx)),
+        KEY_FLOW.to_string() => AgentValue::integer(ctx.id() as i64),
+        KEY_ACTION.to_string() => AgentValue::string(action),
+        KEY_TARGET.to_string() => AgentValue::string(target),
+        KEY_PAYLOAD_HASH.to_string() => AgentValue::string(payload_hash(payload)),
+    })
+}
+
+fn append_record(path: &Path, record: &AgentValue) -> Result<(), AgentError> {
+    let _lock = FILE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent).map_err(|e| {
+            AgentError::IoError(format!("Failed to create audit log directory: {}", e))
+        })?;
+    }
+    let mut f = fs::File::options()
+        .append(true)
+        .create(true)
+        .open(path)
+        .map_err(|e| {
+            AgentError::IoError(format!(
+                "Failed to open audit log {}: {}",
+                path.display(),
+                e
+            ))
+        })?;
+    writeln!(f, "{}", record.to_json()).map_err(|e| {
+        AgentError::IoError(format!(
+            "Failed to write audit log {}: {}",
+            path.display(),
+            e
+        ))
+    })
+}
+
+#[cfg(feature = "http")]
+fn post_record(url: &str, record: &AgentValue) -> Result<(), AgentError> {
+    let agent: ureq::Agent = ureq::Agent::config_builder()
+        .timeout_global(Some(std::time::Duration::from_secs(10)))
+        .build()
+        .into();
+    agent
+        .post(url)
+        .header("content-type", "application/json")
+        .send(record.to_json().to_string())
+        .map(|_| ())
+        .map_err(|e| AgentError::IoError(format!("Failed to post audit record: {}", e)))
+}
+
+/// Records that `agent` is about to do `action` on `target` with `payload`, when auditing
+/// is enabled.
+///
+/// The record is built at once, so the returned future does not borrow the agent.
+pub(crate) fn audit(
+    agent: &impl Agent,
+    ctx: &AgentContext,
+    action: &str,
+    target: &str,
+    payload: &AgentValue,
+) -> impl Future<Output = Result<(), AgentError>> + Send + 'static {
+    audit_by(agent.id(), agent.def_name(), ctx, action, target, payload)
+}
+
+/// Like [`audit`], for the tasks of an agent, where the agent itself is not available.
+pub(crate) fn audit_by(
+    agent_id: &str,
+    def_name: &str,
+    ctx: &AgentContext,
+    action: &str,
+    target: &str,
+    payload: &AgentValue,
+) -> impl Future<Output = Result<(), AgentError>> + Send + 'static {
+    let sink = current_sink();
+    let record = sink.as_ref().map(|_| {
+        audit_record(
+            agent_id,
+            def_name,
+            ctx,
+            action,
+            target,
+            payload,
+            Utc::now().timestamp_millis(),
+        )
+    });
+    async move {
+        let (Some(sink), Some(record)) = (sink, record) else {
+            return Ok(());
+        };
+        match sink {
+            AuditSink::File(path) => {
+                tokio::task::spawn_blocking(move || append_record(&path, &record))
+                    .await
+                    .map_err(|e| {
+                        AgentError::IoError(format!("Failed to append audit record: {}", e))
+                    })?
+            }
+            #[cfg(feature = "http")]
+            AuditSink::Http(url) => tokio::task::spawn_blocking(move || post_record(&url, &record))
+                .await
+                .map_err(|e| AgentError::IoError(format!("Failed to post audit record: {}", e)))?,
+        }
+    }
+}
+
+struct AuditFilter {
+    action: String,
+    agent: String,
+    tenant: String,
+    since: Option<i64>,
+}
+
+impl AuditFilter {
+    fn matches(&self, record: &AgentValue) -> bool {
+        let field = |key: &str, want: &str| want.is_empty() || record.get_str(key) == Some(want);
+        field(KEY_ACTION, &self.action)
+            && field(KEY_AGENT, &self.agent)
+            && field(KEY_TENANT, &self.tenant)
+            && self.since.is_none_or(|since| {
+                record
+                    .get(KEY_TIME)
+                    .and_then(|t| t.as_i64())
+                    .is_some_and(|t| t >= since)
+            })
+    }
+}
+
+/// Returns the last `limit` (0: all) records of the JSONL `text` matching `filter`, in
+/// the order they were written. Broken lines are skipped.
+fn query_records(text: &str, filter: &AuditFilter, limit: usize) -> Vec<AgentValue> {
+    let mut records: Vec<AgentValue> = text
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .filter_map(|line| serde_json::from_str::<serde_json::Value>(line).ok())
+        .filter_map(|json| AgentValue::from_json(json).ok())
+        .filter(|record| filter.matches(record))
+        .collect();
+    if limit > 0 && records.len() > limit {
+        records.drain(..records.len() - limit);
+    }
+    records
+}
+
+/// Reads audit records back.
+///
+/// On any value on `trigger`, outputs the last `limit` (0: all) records of the audit log
+/// at `path` (blank: the audit log file, see [`set_audit_sink`]) as an array on `records`.
+/// Blank filters match any record; `since` (ex. `1h`, `7d`) keeps the recent ones.
+#[modular_agent(
+    title = "Audit Query",
+    category = CATEGORY,
+    inputs = [PORT_TRIGGER],
+    outputs = [PORT_RECORDS],
+    string_config(name = CONFIG_PATH),
+    string_config(name = CONFIG_ACTION),
+    string_config(name = CONFIG_AGENT),
+    string_config(name = CONFIG_TENANT),
+    string_config(name = CONFIG_SINCE, description = "(ex. 1h, 7d) empty: all"),
+    integer_config(name = CONFIG_LIMIT, default = LIMIT_DEFAULT, description = "0: all"),
+)]
+struct AuditQueryAgent {
+    data: AgentData,
+}
+
+#[async_trait]
+impl AsAgent for AuditQueryAgent {
+    fn new(ma: ModularAgent, id: String, spec: AgentSpec) -> Result<Self, AgentError> {
+        Ok(Self {
+            data: AgentData::new(ma, id, spec),
+        })
+    }
+
+    async fn process(
+        &mut self,
+        ctx: AgentContext,
+        _port: String,
+        _value: AgentValue,
+    ) -> Result<(), AgentError> {
+        let configs = self.configs()?;
+        let path = configs.get_string_resolved(CONFIG_PATH)?;
+        let path = if !path.trim().is_empty() {
+            PathBuf::from(path.trim())
+        } else {
+            match current_sink() {
+                Some(AuditSink::File(path)) => path,
+                #[cfg(feature = "http")]
+                Some(AuditSink::Http(_)) => {
+                    return Err(AgentError::InvalidConfig(
+                        "The audit log is sent to a URL; set path to read a file".into(),
+                    ));
+                }
+                None => {
+                    return Err(AgentError::InvalidConfig(format!(
+                        "path is not set, and neither is {}",
+                        AUDIT_ENV
+                    )));
+                }
+            }
+        };
+        let since = configs.get_string_or_default(CONFIG_SINCE);
+        let since = if since.trim().is_empty() {
+            None
+        } else {
+            Some(Utc::now().timestamp_millis() - parse_duration_to_ms(&since)? as i64)
+        };
+        let filter = AuditFilter {
+            action: configs
+                .get_string_or_default(CONFIG_ACTION)
+                .trim()
+                .to_string(),
+            agent: configs
+                .get_string_or_default(CONFIG_AGENT)
+                .trim()
+                .to_string(),
+            tenant: configs
+                .get_string_or_default(CONFIG_TENANT)
+                .trim()
+                .to_string(),
+            since,
+        };
+        let limit = configs.get_integer_or(CONFIG_LIMIT, LIMIT_DEFAULT).max(0) as usize;
+
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => {
+                return Err(AgentError::IoError(format!(
+                    "Failed to read audit log {}: {}",
+                    path.display(),
+                    e
+                )));
+            }
+        };
+        let records = query_records(&text, &filter, limit);
+        self.output(
+            self.traced(ctx),
+            PORT_RECORDS,
+            AgentValue::array(records.into()),
+        )
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tenant::VAR_TENANT;
+
+    #[test]
+    fn test_audit_records() {
+        let path = std::env::temp_dir().join(format!(
+            "modular_agent_std_audit_{}.jsonl",
+            std::process::id()
+        ));
+        let _ = fs::remove_file(&path);
+
+        let payload = AgentValue::string("hello");
+        let ctx = AgentContext::new();
+        let acme = ctx.with_var(VAR_TENANT.to_string(), AgentValue::string("acme"));
+        let records = [
+            ("w1", &ctx, ACTION_WRITE_FILE, 1000),
+            ("w1", &acme, ACTION_WRITE_FILE, 2000),
+            ("m1", &acme, ACTION_MOVE_FILE, 3000),
+        ];
+        for (agent, ctx, action, time) in records {
+            let record = audit_record(agent, "def", ctx, action, "target", &payload, time);
+            append_record(&path, &record).unwrap();
+        }
+        let mut text = fs::read_to_string(&path).unwrap();
+        text.push_str("not json\n");
+
+        let record = &query_records(&text, &filter("", "", "", None), 0)[0];
+        assert_eq!(record.get_str(KEY_TENANT), Some(""));
+        assert_eq!(record.get_str(KEY_TARGET), Some("target"));
+        assert_eq!(
+            record.get_str(KEY_PAYLOAD_HASH),
+            Some("5aa762ae383fbb727af3c7a36d4940a5b8c40a989452d2304fc958ff3f354e7a")
+        );
+
+        let times = |filter: AuditFilter, limit: usize| -> Vec<i64> {
+            query_records(&text, &filter, limit)
+                .iter()
+                .filter_map(|r| r.get(KEY_TIME).and_then(|t| t.as_i64()))
+                .collect()
+        };
+        assert_eq!(times(filter("", "", "", None), 0), vec![1000, 2000, 3000]);
+        assert_eq!(times(filter("", "", "", None), 2), vec![2000, 3000]);
+        assert_eq!(
+            times(filter(ACTION_WRITE_FILE, "", "", None), 0),
+            vec![1000, 2000]
+        );
+        assert_eq!(times(filter("", "w1", "acme", None), 0), vec![2000]);
+        assert_eq!(times(filter("", "", "", Some(2500)), 0), vec![3000]);
+
+        let _ = fs::remove_file(&path);
+    }
+
+    fn filter(action: &str, agent: &str, tenant: &str, since: Option<i64>) -> AuditFilter {
+        AuditFilter {
+            action: action.to_string(),
+            agent: agent.to_string(),
+            tenant: tenant.to_string(),
+            since,
+        }
+    }
+}
@@ -0,0 +1,341 @@
+//! Binary values.
+//!
+//! A binary value is an array of integers from 0 to 255. The agents here also take a
+//! string as its UTF-8 bytes.
+
+use modular_agent_core::{
+    Agent, AgentContext, AgentData, AgentError, AgentOutput, AgentSpec, AgentValue, AsAgent,
+    ModularAgent, async_trait, modular_agent,
+};
+
+use crate::contract::{CONFIG_INPUT_CONTRACT, ContractAgent, InputContract, contract_agents};
+use crate::provenance::Traced;
+
+const CATEGORY: &str = "Std/Data";
+
+const PORT_BYTES: &str = "bytes";
+const PORT_CHUNKS: &str = "chunks";
+const PORT_HEX: &str = "hex";
+const PORT_LENGTH: &str = "length";
+
+const CONFIG_LENGTH: &str = "length";
+const CONFIG_N: &str = "n";
+const CONFIG_OFFSET: &str = "offset";
+
+const HEX_DUMP_N_DEFAULT: i64 = 64;
+
+/// Returns the bytes of a binary value or a string.
+pub(crate) fn value_to_bytes(value: &AgentValue) -> Result<Vec<u8>, AgentError> {
+    match value {
+        AgentValue::String(s) => Ok(s.as_bytes().to_vec()),
+        AgentValue::Array(arr) => arr
+            .iter()
+            .map(|v| {
+                v.as_i64()
+                    .and_then(|b| u8::try_from(b).ok())
+                    .ok_or_else(|| AgentError::InvalidValue(format!("Not a byte: {}", v.to_json())))
+            })
+            .collect(),
+        _ => Err(AgentError::InvalidValue(
+            "Binary value must be an array of bytes or a string".into(),
+        )),
+    }
+}
+
+/// Returns a binary value of the bytes.
+pub(crate) fn bytes_to_value(bytes: &[u8]) -> AgentValue {
+    AgentValue::array(
+        bytes
+            .iter()
+            .map(|b| AgentValue::integer(*b as i64))
+            .collect(),
+    )
+}
+
+// The range of `length` bytes (negative: to the end) at `offset` (negative: from the end),
+// within `len` bytes.
+fn slice_range(len: usize, offset: i64, length: i64) -> std::ops::Range<usize> {
+    let start = if offset < 0 {
+        len.saturating_sub(offset.unsigned_abs() as usize)
+    } else {
+        (offset as usize).min(len)
+    };
+    let end = if length < 0 {
+        len
+    } else {
+        start.saturating_add(length as usize).min(len)
+    };
+    start..end
+}
+
+// Renders bytes like `hexdump -C`: offset, 16 bytes in hex, and their printable ASCII.
+fn hex_dump(bytes: &[u8]) -> String {
+    let mut out = String::new();
+    for (i, line) in bytes.chunks(16).enumerate() {
+        let mut hex = String::new();
+        for (j, b) in line.iter().enumerate() {
+            if j == 8 {
+                hex.push(' ');
+            }
+            hex.push_str(&format!("{:02x} ", b));
+        }
+        let ascii: String = line
+            .iter()
+            .map(|b| {
+                if b.is_ascii_graphic() || *b == b' ' {
+                    *b as char
+                } else {
+                    '.'
+                }
+            })
+            .collect();
+        out.push_str(&format!("{:08x}  {:<49} |{}|\n", i * 16, hex, ascii));
+    }
+    out
+}
+
+contract_agents!(
+    BytesSliceAgent,
+    BytesConcatAgent,
+    BytesLengthAgent,
+    HexDumpAgent
+);
+
+/// Slices a binary value.
+///
+/// Outputs `length` bytes (-1: to the end) from `offset` (negative: from the end).
+/// The slice is shorter when the value ends before it.
+#[modular_agent(
+    title = "Bytes Slice",
+    category = CATEGORY,
+    inputs = [PORT_BYTES],
+    outputs = [PORT_BYTES],
+    integer_config(name = CONFIG_OFFSET),
+    integer_config(name = CONFIG_LENGTH, default = -1, description = "-1: to the end"),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
+)]
+struct BytesSliceAgent {
+    data: AgentData,
+    contract: InputContract,
+}
+
+#[async_trait]
+impl AsAgent for BytesSliceAgent {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
+        Ok(Self {
+            data: AgentData::new(ma, id, spec),
+            contract,
+        })
+    }
+
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
+    }
+
+    async fn process(
+        &mut self,
+        ctx: AgentContext,
+        port: String,
+        value: AgentValue,
+    ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
+        let config = self.configs()?;
+        let offset = config.get_integer_or_default(CONFIG_OFFSET);
+        let length = config.get_integer_or(CONFIG_LENGTH, -1);
+        let bytes = value_to_bytes(&value)?;
+        let slice = &bytes[slice_range(bytes.len(), offset, length)];
+        self.output(self.traced(ctx), PORT_BYTES, bytes_to_value(slice))
+            .await
+    }
+}
+
+/// Concatenates an array of binary values into one.
+#[modular_agent(
+    title = "Bytes Concat",
+    category = CATEGORY,
+    inputs = [PORT_CHUNKS],
+    outputs = [PORT_BYTES],
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
+)]
+struct BytesConcatAgent {
+    data: AgentData,
+    contract: InputContract,
+}
+
+#[async_trait]
+impl AsAgent for BytesConcatAgent {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
+        Ok(Self {
+            data: AgentData::new(ma, id, spec),
+            contract,
+        })
+    }
+
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
+    }
+
+    async fn process(
+        &mut self,
+        ctx: AgentContext,
+        port: String,
+        value: AgentValue,
+    ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
+        let Some(chunks) = value.as_array() else {
+            return Err(AgentError::InvalidArrayValue("Expected array".into()));
+        };
+        let mut bytes = Vec::new();
+        for chunk in chunks.iter() {
+            bytes.extend(value_to_bytes(chunk)?);
+        }
+        self.output(self.traced(ctx), PORT_BYTES, bytes_to_value(&bytes))
+            .await
+    }
+}
+
+/// Outputs the number of bytes of a binary value.
+#[modular_agent(
+    title = "Bytes Length",
+    category = CATEGORY,
+    inputs = [PORT_BYTES],
+    outputs = [PORT_LENGTH],
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
+)]
+struct BytesLengthAgent {
+    data: AgentData,
+    contract: InputContract,
+}
+
+#[async_trait]
+impl AsAgent for BytesLengthAgent {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
+        Ok(Self {
+            data: AgentData::new(ma, id, spec),
+            contract,
+        })
+    }
+
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
+    }
+
+    async fn process(
+        &mut self,
+        ctx: AgentContext,
+        port: String,
+        value: AgentValue,
+    ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
+        let len = value_to_bytes(&value)?.len();
+        self.output(
+            self.traced(ctx),
+            PORT_LENGTH,
+            AgentValue::integer(len as i64),
+        )
+        .await
+    }
+}
+
+/// Renders the first `n` bytes (-1: all) of a binary value as a hex dump, with the
+/// offsets and the printable ASCII characters like `hexdump -C`.
+#[modular_agent(
+    title = "Hex Dump",
+    category = CATEGORY,
+    inputs = [PORT_BYTES],
+    outputs = [PORT_HEX],
+    integer_config(name = CONFIG_N, default = HEX_DUMP_N_DEFAULT, description = "-1: all"),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
+)]
+struct HexDumpAgent {
+    data: AgentData,
+    contract: InputContract,
+}
+
+#[async_trait]
+impl AsAgent for HexDumpAgent {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
+        Ok(Self {
+            data: AgentData::new(ma, id, spec),
+            contract,
+        })
+    }
+
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
+    }
+
+    async fn process(
+        &mut self,
+        ctx: AgentContext,
+        port: String,
+        value: AgentValue,
+    ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
+        let n = self.configs()?.get_integer_or(CONFIG_N, HEX_DUMP_N_DEFAULT);
+        let bytes = value_to_bytes(&value)?;
+        let bytes = &bytes[slice_range(bytes.len(), 0, n)];
+        self.output(
+            self.traced(ctx),
+            PORT_HEX,
+            AgentValue::string(hex_dump(bytes)),
+        )
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_value_to_bytes() {
+        assert_eq!(
+            value_to_bytes(&bytes_to_value(&[0, 1, 255])).unwrap(),
+            vec![0, 1, 255]
+        );
+        assert_eq!(
+            value_to_bytes(&AgentValue::string("hé")).unwrap(),
+            vec![0x68, 0xc3, 0xa9]
+        );
+        assert!(value_to_bytes(&AgentValue::array(im::vector![AgentValue::integer(256)])).is_err());
+        assert!(value_to_bytes(&AgentValue::integer(1)).is_err());
+    }
+
+    #[test]
+    fn test_slice_range() {
+        assert_eq!(slice_range(10, 2, 3), 2..5);
+        assert_eq!(slice_range(10, 2, -1), 2..10);
+        assert_eq!(slice_range(10, -4, 2), 6..8);
+        assert_eq!(slice_range(10, 8, 5), 8..10);
+        assert_eq!(slice_range(10, 20, 5), 10..10);
+        assert_eq!(slice_range(10, -20, 2), 0..2);
+    }
+
+    #[test]
+    fn test_hex_dump() {
+        let bytes: Vec<u8> = (0x41..0x41 + 18).collect();
+        assert_eq!(
+            hex_dump(&bytes),
+            "00000000  41 42 43 44 45 46 47 48  49 4a 4b 4c 4d 4e 4f 50  |ABCDEFGHIJKLMNOP|\n\
+             00000010  51 52                                             |QR|\n"
+        );
+        assert_eq!(
+            hex_dump(&[0, b'a']),
+            format!("00000000  00 61 {:43} |.a|\n", "")
+        );
+        assert_eq!(hex_dump(&[]), "");
+    }
+}
@@ -0,0 +1,278 @@
+//! Screen capture agents, built on [xcap](https://crates.io/crates/xcap).
+
+use modular_agent_core::photon_rs::{self, PhotonImage};
+use modular_agent_core::{
+    Agent, AgentContext, AgentData, AgentError, AgentOutput, AgentSpec, AgentValue, AsAgent,
+    ModularAgent, async_trait, modular_agent,
+};
+use regex::Regex;
+use xcap::{Monitor, Window, XCapError};
+
+use crate::contract::{CONFIG_INPUT_CONTRACT, ContractAgent, InputContract, contract_agents};
+use crate::provenance::Traced;
+
+const CATEGORY: &str = "Std/Image";
+
+const PORT_ERROR: &str = "error";
+const PORT_IMAGE: &str = "image";
+const PORT_UNIT: &str = "unit";
+
+const CONFIG_LOGICAL_SIZE: &str = "logical_size";
+const CONFIG_MONITOR: &str = "monitor";
+const CONFIG_REGION: &str = "region";
+const CONFIG_TITLE: &str = "title";
+
+contract_agents!(CaptureWindowAgent);
+
+/// Capture Window agent
+///
+/// Captures the screen on each value on `unit`, and outputs the image on `image`.
+///
+/// With `title`, the frontmost window whose title matches the regex is captured,
+/// skipping minimized ones. Otherwise the monitor at index `monitor` is captured, in the
+/// order the system lists them, or only its `region` when set (`x, y, width, height`
+/// in logical pixels from the top left of the monitor).
+///
+/// Images are in physical pixels, so on a scaled display they are larger than the
+/// window or region on screen. With `logical size`, they are resized by the scale
+/// factor of the monitor to their logical size.
+///
+/// A failed capture, including no matching window, is output on `error` as a string,
+/// and the next value is captured again.
+#[modular_agent(
+    title = "Capture Window",
+    category = CATEGORY,
+    inputs = [PORT_UNIT],
+    outputs = [PORT_IMAGE, PORT_ERROR],
+    string_config(name = CONFIG_TITLE, description = "regex; captures a monitor if empty"),
+    integer_config(name = CONFIG_MONITOR),
+    string_config(name = CONFIG_REGION, description = "x, y, width, height; whole monitor if empty"),
+    boolean_config(name = CONFIG_LOGICAL_SIZE, title = "logical size"),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
+)]
+struct CaptureWindowAgent {
+    data: AgentData,
+    contract: InputContract,
+    target: CaptureTarget,
+}
+
+#[derive(Clone, Debug)]
+enum CaptureTarget {
+    Window(Regex),
+    Monitor {
+        index: usize,
+        region: Option<Region>,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Region {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+}
+
+impl CaptureWindowAgent {
+    fn target(spec: &AgentSpec) -> Result<CaptureTarget, AgentError> {
+        let config = spec.configs.as_ref().ok_or(AgentError::NoConfig)?;
+        let title = config.get_string_or_default(CONFIG_TITLE);
+        if !title.trim().is_empty() {
+            let re = Regex::new(title.trim()).map_err(|e| {
+                AgentError::InvalidConfig(format!("Invalid title regex '{}': {}", title, e))
+            })?;
+            return Ok(CaptureTarget::Window(re));
+        }
+        let index = config.get_integer_or_default(CONFIG_MONITOR);
+        if index < 0 {
+            return Err(AgentError::InvalidConfig(format!(
+                "Invalid monitor index: {}",
+                index
+            )));
+        }
+        Ok(CaptureTarget::Monitor {
+            index: index as usize,
+            region: parse_region(&config.get_string_or_default(CONFIG_REGION))?,
+        })
+    }
+}
+
+// Parses `x, y, width, height`. An empty string is the whole monitor.
+fn parse_region(region: &str) -> Result<Option<Region>, AgentError> {
+    if region.trim().is_empty() {
+        return Ok(None);
+    }
+    let invalid = || {
+        AgentError::InvalidConfig(format!(
+            "Invalid region '{}': expected x, y, width, height",
+            region
+        ))
+    };
+    let parts = region
+        .split(',')
+        .map(|part| part.trim().parse::<u32>().map_err(|_| invalid()))
+        .collect::<Result<Vec<_>, _>>()?;
+    let [x, y, width, height] = parts[..] else {
+        return Err(invalid());
+    };
+    if width == 0 || height == 0 {
+        return Err(invalid());
+    }
+    Ok(Some(Region {
+        x,
+        y,
+        width,
+        height,
+    }))
+}
+
+// The size of an image of `width` x `height` physical pixels on a monitor scaled by
+// `scale_factor`.
+fn logical_size(width: u32, height: u32, scale_factor: f32) -> (u32, u32) {
+    if !scale_factor.is_finite() || scale_factor <= 0.0 {
+        return (width, height);
+    }
+    let scale = |n: u32| ((n as f32 / scale_factor).round() as u32).max(1);
+    (scale(width), scale(height))
+}
+
+fn capture_error(e: XCapError) -> AgentError {
+    AgentError::IoError(format!("Failed to capture: {}", e))
+}
+
+// Captures the target, returning the image and the scale factor of its monitor.
+fn capture(target: &CaptureTarget) -> Result<(xcap::image::RgbaImage, f32), AgentError> {
+    match target {
+        CaptureTarget::Window(re) => {
+            let window = Window::all()
+                .map_err(capture_error)?
+                .into_iter()
+                .filter(|w| !w.is_minimized().unwrap_or(false))
+                .find(|w| w.title().is_ok_and(|title| re.is_match(&title)))
+                .ok_or_else(|| {
+                    AgentError::InvalidValue(format!("No window title matches '{}'", re))
+                })?;
+            let scale_factor = window
+                .current_monitor()
+                .and_then(|m| m.scale_factor())
+                .unwrap_or(1.0);
+            Ok((window.capture_image().map_err(capture_error)?, scale_factor))
+        }
+        CaptureTarget::Monitor { index, region } => {
+            let mut monitors = Monitor::all().map_err(capture_error)?;
+            if *index >= monitors.len() {
+                return Err(AgentError::InvalidValue(format!(
+                    "No monitor at index {} ({} found)",
+                    index,
+                    monitors.len()
+                )));
+            }
+            let monitor = monitors.swap_remove(*index);
+            let scale_factor = monitor.scale_factor().unwrap_or(1.0);
+            let image = match region {
+                Some(r) => monitor.capture_region(r.x, r.y, r.width, r.height),
+                None => monitor.capture_image(),
+            }
+            .map_err(capture_error)?;
+            Ok((image, scale_factor))
+        }
+    }
+}
+
+#[async_trait]
+impl AsAgent for CaptureWindowAgent {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
+        let target = Self::target(&spec)?;
+        Ok(Self {
+            data: AgentData::new(ma, id, spec),
+            contract,
+            target,
+        })
+    }
+
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()?;
+        self.target = Self::target(&self.data.spec)?;
+        Ok(())
+    }
+
+    async fn process(
+        &mut self,
+        ctx: AgentContext,
+        port: String,
+        value: AgentValue,
+    ) -> Result<(), AgentError> {
+        if self.check_input(&ctx, &port, value).await?.is_none() {
+            return Ok(());
+        }
+        let target = self.target.clone();
+        let logical = self.configs()?.get_bool_or_default(CONFIG_LOGICAL_SIZE);
+
+        let captured = tokio::task::spawn_blocking(move || -> Result<PhotonImage, AgentError> {
+            let (image, scale_factor) = capture(&target)?;
+            let (width, height) = image.dimensions();
+            let image = PhotonImage::new(image.into_raw(), width, height);
+            if !logical {
+                return Ok(image);
+            }
+            let (logical_width, logical_height) = logical_size(width, height, scale_factor);
+            if (logical_width, logical_height) == (width, height) {
+                return Ok(image);
+            }
+            Ok(photon_rs::transform::resize(
+                &image,
+                logical_width,
+                logical_height,
+                photon_rs::transform::SamplingFilter::Triangle,
+            ))
+        })
+        .await
+        .unwrap_or_else(|e| Err(AgentError::IoError(format!("Capture task failed: {}", e))));
+
+        match captured {
+            Ok(image) => {
+                self.output(self.traced(ctx), PORT_IMAGE, AgentValue::image(image))
+                    .await
+            }
+            Err(e) => {
+                self.output(
+                    self.traced(ctx),
+                    PORT_ERROR,
+                    AgentValue::string(e.to_string()),
+                )
+                .await
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_region() {
+        assert_eq!(parse_region("").unwrap(), None);
+        assert_eq!(
+            parse_region(" 10, 20,300 , 200 ").unwrap(),
+            Some(Region {
+                x: 10,
+                y: 20,
+                width: 300,
+                height: 200,
+            })
+        );
+        assert!(parse_region("10, 20, 300").is_err());
+        assert!(parse_region("10, 20, 0, 200").is_err());
+        assert!(parse_region("-10, 20, 300, 200").is_err());
+    }
+
+    #[test]
+    fn test_logical_size() {
+        assert_eq!(logical_size(2560, 1440, 2.0), (1280, 720));
+        assert_eq!(logical_size(1920, 1080, 1.5), (1280, 720));
+        assert_eq!(logical_size(1920, 1080, 1.0), (1920, 1080));
+        assert_eq!(logical_size(1920, 1080, 0.0), (1920, 1080));
+    }
+}
@@ -0,0 +1,484 @@
+//! Checkpoints.
+//!
+//! Checkpoint Save writes the value and the context of each flow passing by to a file in
+//! a directory, one file per flow, overwritten at each later checkpoint of the flow and
+//! removed when the flow is done. After a crash or a restart, Checkpoint Restore outputs
+//! the saved flows again, so that they resume from their last checkpoint.
+//!
+//! The items of a flow fanned out by Map or Repeat have a file each, named after the flow
+//! and their map indices, which is removed once they are collected and saved again.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use chrono::Utc;
+use modular_agent_core::{
+    Agent, AgentConfigs, AgentContext, AgentData, AgentError, AgentOutput, AgentSpec, AgentValue,
+    AsAgent, ModularAgent, async_trait, modular_agent,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::audit::{ACTION_WRITE_FILE, audit};
+use crate::contract::{CONFIG_INPUT_CONTRACT, ContractAgent, InputContract, contract_agents};
+use crate::profile::ProfileConfigs;
+use crate::provenance::{Traced, stamp};
+
+const CATEGORY: &str = "Std/Flow";
+
+const PORT_DONE: &str = "done";
+const PORT_TRIGGER: &str = "trigger";
+const PORT_VALUE: &str = "value";
+
+const CONFIG_DIR: &str = "dir";
+const CONFIG_ON_START: &str = "on_start";
+const CONFIG_STAGE: &str = "stage";
+
+const VAR_CHECKPOINT: &str = "checkpoint";
+
+const CHECKPOINT_EXT: &str = "json";
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Checkpoint {
+    id: String,
+    stage: String,
+    time: i64,
+    value: AgentValue,
+    ctx: AgentContext,
+}
+
+fn checkpoint_dir(configs: &AgentConfigs) -> Result<PathBuf, AgentError> {
+    let dir = configs.get_string_resolved(CONFIG_DIR)?;
+    if dir.trim().is_empty() {
+        return Err(AgentError::InvalidConfig("dir is not set".into()));
+    }
+    Ok(PathBuf::from(dir.trim()))
+}
+
+// The checkpoint id of the flow, which is kept in the context so that later checkpoints
+// of the flow overwrite the same file.
+fn checkpoint_id(ctx: &AgentContext) -> Option<String> {
+    match ctx.get_var(VAR_CHECKPOINT) {
+        Some(AgentValue::String(s)) if !s.is_empty() => Some(s.to_string()),
+        _ => None,
+    }
+}
+
+// The id of the checkpoint file of the flow `id` at the map frames of the context, so that
+// the items of a fan-out do not overwrite each other.
+fn checkpoint_file_id(id: &str, ctx: &AgentContext) -> Result<String, AgentError> {
+    // the id becomes a file name in dir
+    if id.starts_with('.')
+        || !id
+            .chars()
+            .all(|c| c.is_alphanumeric() || matches!(c, '_' | '-' | '.'))
+    {
+        return Err(AgentError::InvalidValue(format!(
+            "Invalid checkpoint id: {}",
+            id
+        )));
+    }
+    let mut file_id = id.to_string();
+    for (index, _) in ctx.map_frame_indices()? {
+        file_id.push_str(&format!("-m{}", index));
+    }
+    Ok(file_id)
+}
+
+fn checkpoint_path(dir: &Path, file_id: &str) -> PathBuf {
+    dir.join(format!("{}.{}", file_id, CHECKPOINT_EXT))
+}
+
+/// Writes the checkpoint atomically, so that a crash never leaves a partial file.
+fn write_checkpoint(dir: &Path, checkpoint: &Checkpoint) -> Result<(), AgentError> {
+    fs::create_dir_all(dir).map_err(|e| {
+        AgentError::InvalidValue(format!(
+            "Failed to create checkpoint dir {}: {}",
+            dir.display(),
+            e
+        ))
+    })?;
+    let json = serde_json::to_string(checkpoint)
+        .map_err(|e| AgentError::InvalidValue(format!("Failed to serialize checkpoint: {}", e)))?;
+    let path = checkpoint_path(dir, &checkpoint.id);
+    let tmp = dir.join(format!("{}.{}.tmp", checkpoint.id, CHECKPOINT_EXT));
+    fs::write(&tmp, json)
+        .and_then(|_| fs::rename(&tmp, &path))
+        .map_err(|e| {
+            AgentError::InvalidValue(format!(
+                "Failed to write checkpoint {}: {}",
+                path.display(),
+                e
+            ))
+        })
+}
+
+fn remove_file(path: &Path) -> Result<(), AgentError> {
+    match fs::remove_file(path) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(AgentError::InvalidValue(format!(
+            "Failed to remove checkpoint {}: {}",
+            path.display(),
+            e
+        ))),
+    }
+}
+
+fn remove_checkpoint(dir: &Path, file_id: &str) -> Result<(), AgentError> {
+    remove_file(&checkpoint_path(dir, file_id))
+}
+
+// Removes the checkpoints of the items fanned out below `file_id`.
+//
+// The dir is listed each time rather than indexed, so that files written or removed by
+// another process or by hand are seen too; listing is cheap next to writing the file.
+fn remove_item_checkpoints(dir: &Path, file_id: &str) -> Result<(), AgentError> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => {
+            return Err(AgentError::InvalidValue(format!(
+                "Failed to read checkpoint dir {}: {}",
+                dir.display(),
+                e
+            )));
+        }
+    };
+    let prefix = format!("{}-m", file_id);
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let is_item = path.extension().and_then(|e| e.to_str()) == Some(CHECKPOINT_EXT)
+            && path
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .is_some_and(|stem| {
+                    stem.strip_prefix(&prefix)
+                        .and_then(|rest| rest.chars().next())
+                        .is_some_and(|c| c.is_ascii_digit())
+                });
+        if is_item {
+            remove_file(&path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Reads the checkpoints of `stage` (blank: all) in the order they were saved.
+///
+/// Unreadable files are skipped with a warning.
+fn read_checkpoints(dir: &Path, stage: &str) -> Result<Vec<Checkpoint>, AgentError> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => {
+            return Err(AgentError::InvalidValue(format!(
+                "Failed to read checkpoint dir {}: {}",
+                dir.display(),
+                e
+            )));
+        }
+    };
+    let mut checkpoints = Vec::new();
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.extension().and_then(|e| e.to_str()) != Some(CHECKPOINT_EXT) {
+            continue;
+        }
+        let checkpoint = fs::read_to_string(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|s| serde_json::from_str::<Checkpoint>(&s).map_err(|e| e.to_string()));
+        match checkpoint {
+            Ok(checkpoint) if stage.is_empty() || checkpoint.stage == stage => {
+                checkpoints.push(checkpoint)
+            }
+            Ok(_) => {}
+            Err(e) => log::warn!("Skipping checkpoint {}: {}", path.display(), e),
+        }
+    }
+    checkpoints.sort_by(|a, b| (a.time, &a.id).cmp(&(b.time, &b.id)));
+    Ok(checkpoints)
+}
+
+/// Returns the saved context with a fresh id, since ids are only unique in a process.
+fn restored_ctx(checkpoint: &Checkpoint) -> Result<AgentContext, AgentError> {
+    let mut json = serde_json::to_value(&checkpoint.ctx)
+        .map_err(|e| AgentError::InvalidValue(format!("Invalid checkpoint context: {}", e)))?;
+    json["id"] = serde_json::json!(AgentContext::new().id());
+    let ctx: AgentContext = serde_json::from_value(json)
+        .map_err(|e| AgentError::InvalidValue(format!("Invalid checkpoint context: {}", e)))?;
+    if checkpoint_id(&ctx).is_some() {
+        return Ok(ctx);
+    }
+    Ok(ctx.with_var(
+        VAR_CHECKPOINT.to_string(),
+        AgentValue::string(checkpoint.id.clone()),
+    ))
+}
+
+contract_agents!(CheckpointSaveAgent);
+
+/// Saves the value and the context of the flow to `dir`, and passes the value on.
+///
+/// Each flow has one checkpoint file, overwritten by the later Checkpoint Save agents of
+/// the flow, so give each of them its own `stage` name. A value on `done` removes the
+/// checkpoint of its flow and is passed on; put it at the end of the pipeline.
+#[modular_agent(
+    title = "Checkpoint Save",
+    category = CATEGORY,
+    inputs = [PORT_VALUE, PORT_DONE],
+    outputs = [PORT_VALUE],
+    string_config(name = CONFIG_DIR),
+    string_config(name = CONFIG_STAGE),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
+    hint(color=4),
+)]
+struct CheckpointSaveAgent {
+    data: AgentData,
+    contract: InputContract,
+}
+
+#[async_trait]
+impl AsAgent for CheckpointSaveAgent {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
+        Ok(Self {
+            data: AgentData::new(ma, id, spec),
+            contract,
+        })
+    }
+
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
+    }
+
+    async fn process(
+        &mut self,
+        ctx: AgentContext,
+        port: String,
+        value: AgentValue,
+    ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
+        let configs = self.configs()?;
+        let dir = checkpoint_dir(configs)?;
+        match port.as_str() {
+            PORT_VALUE => {
+                let now = Utc::now().timestamp_millis();
+                let (id, ctx) = match checkpoint_id(&ctx) {
+                    Some(id) => (id, ctx),
+                    None => {
+                        let id = format!("{}-{}", now, ctx.id());
+                        let ctx = ctx.with_var(VAR_CHECKPOINT.to_string(), AgentValue::string(&id));
+                        (id, ctx)
+                    }
+                };
+                let file_id = checkpoint_file_id(&id, &ctx)?;
+                let checkpoint = Checkpoint {
+                    id: file_id,
+                    stage: configs
+                        .get_string_or_default(CONFIG_STAGE)
+                        .trim()
+                        .to_string(),
+                    time: now,
+                    value,
+                    ctx,
+                };
+                let target = checkpoint_path(&dir, &checkpoint.id).display().to_string();
+                audit(
+                    self,
+                    &checkpoint.ctx,
+                    ACTION_WRITE_FILE,
+                    &target,
+                    &checkpoint.value,
+                )
+                .await?;
+                write_checkpoint(&dir, &checkpoint)?;
+                remove_item_checkpoints(&dir, &checkpoint.id)?;
+                self.output(self.traced(checkpoint.ctx), PORT_VALUE, checkpoint.value)
+                    .await
+            }
+            PORT_DONE => {
+                if let Some(id) = checkpoint_id(&ctx) {
+                    let file_id = checkpoint_file_id(&id, &ctx)?;
+                    remove_checkpoint(&dir, &file_id)?;
+                    remove_item_checkpoints(&dir, &file_id)?;
+                }
+                self.output(self.traced(ctx), PORT_VALUE, value).await
+            }
+            _ => Err(AgentError::InvalidPin(port)),
+        }
+    }
+}
+
+/// Outputs the flows saved by Checkpoint Save in `dir`, with their contexts, so that they
+/// resume from their last checkpoint.
+///
+/// Only the checkpoints of `stage` are output, or all of them when it is blank; connect
+/// the output where the pipeline continues after that stage. They are output when the
+/// agent starts if `on start` is set, and whenever a value arrives on `trigger`.
+#[modular_agent(
+    title = "Checkpoint Restore",
+    category = CATEGORY,
+    inputs = [PORT_TRIGGER],
+    outputs = [PORT_VALUE],
+    string_config(name = CONFIG_DIR),
+    string_config(name = CONFIG_STAGE),
+    boolean_config(name = CONFIG_ON_START, default = true, title = "on start"),
+    hint(color=4),
+)]
+struct CheckpointRestoreAgent {
+    data: AgentData,
+}
+
+#[async_trait]
+impl AsAgent for CheckpointRestoreAgent {
+    fn new(ma: ModularAgent, id: String, spec: AgentSpec) -> Result<Self, AgentError> {
+        Ok(Self {
+            data: AgentData::new(ma, id, spec),
+        })
+    }
+
+    async fn start(&mut self) -> Result<(), AgentError> {
+        let configs = self.configs()?;
+        if !configs.get_bool_or(CONFIG_ON_START, true) {
+            return Ok(());
+        }
+        let dir = checkpoint_dir(configs)?;
+        let stage = configs
+            .get_string_or_default(CONFIG_STAGE)
+            .trim()
+            .to_string();
+        let ma = self.ma().clone();
+        let agent_id = self.id().to_string();
+        let def_name = self.def_name().to_string();
+        tokio::spawn(async move {
+            let checkpoints = match read_checkpoints(&dir, &stage) {
+                Ok(checkpoints) => checkpoints,
+                Err(e) => {
+                    log::error!("Failed to restore checkpoints: {}", e);
+                    return;
+                }
+            };
+            for checkpoint in checkpoints {
+                let ctx = match restored_ctx(&checkpoint) {
+                    Ok(ctx) => ctx,
+                    Err(e) => {
+                        log::warn!("Skipping checkpoint {}: {}", checkpoint.id, e);
+                        continue;
+                    }
+                };
+                if let Err(e) = ma
+                    .send_agent_out(
+                        agent_id.clone(),
+                        stamp(ctx, &agent_id, &def_name),
+                        PORT_VALUE.to_string(),
+                        checkpoint.value,
+                    )
+                    .await
+                {
+                    log::error!("Failed to send restored checkpoint: {}", e);
+                }
+            }
+        });
+        Ok(())
+    }
+
+    async fn process(
+        &mut self,
+        _ctx: AgentContext,
+        _port: String,
+        _value: AgentValue,
+    ) -> Result<(), AgentError> {
+        let configs = self.configs()?;
+        let dir = checkpoint_dir(configs)?;
+        let stage = configs.get_string_or_default(CONFIG_STAGE);
+        for checkpoint in read_checkpoints(&dir, stage.trim())? {
+            let ctx = restored_ctx(&checkpoint)?;
+            self.output(self.traced(ctx), PORT_VALUE, checkpoint.value)
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checkpoint_files() {
+        let dir = std::env::temp_dir().join(format!(
+            "modular_agent_std_checkpoint_{}",
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+
+        assert!(read_checkpoints(&dir, "").unwrap().is_empty());
+
+        let ctx = AgentContext::new().with_var("tenant".to_string(), AgentValue::string("acme"));
+        for (id, stage, time) in [("b", "parse", 2), ("a", "parse", 1), ("c", "embed", 3)] {
+            let checkpoint = Checkpoint {
+                id: id.to_string(),
+                stage: stage.to_string(),
+                time,
+                value: AgentValue::integer(time),
+                ctx: ctx.clone(),
+            };
+            write_checkpoint(&dir, &checkpoint).unwrap();
+        }
+        fs::write(dir.join("broken.json"), "{").unwrap();
+
+        let ids = |stage: &str| -> Vec<String> {
+            read_checkpoints(&dir, stage)
+                .unwrap()
+                .into_iter()
+                .map(|c| c.id)
+                .collect()
+        };
+        assert_eq!(ids(""), vec!["a", "b", "c"]);
+        assert_eq!(ids("parse"), vec!["a", "b"]);
+
+        let checkpoint = read_checkpoints(&dir, "embed").unwrap().remove(0);
+        assert_eq!(checkpoint.value, AgentValue::integer(3));
+        let restored = restored_ctx(&checkpoint).unwrap();
+        assert_ne!(restored.id(), ctx.id());
+        assert_eq!(
+            restored.get_var("tenant"),
+            Some(&AgentValue::string("acme"))
+        );
+        assert_eq!(checkpoint_id(&restored), Some("c".to_string()));
+
+        remove_checkpoint(&dir, "b").unwrap();
+        remove_checkpoint(&dir, "missing").unwrap();
+        assert_eq!(ids(""), vec!["a", "c"]);
+
+        // ids never leave the dir, and ids with dots do not collide
+        assert!(checkpoint_file_id("../escape", &ctx).is_err());
+        assert!(checkpoint_file_id("a/b", &ctx).is_err());
+        assert_eq!(checkpoint_path(&dir, "a.1"), dir.join("a.1.json"));
+        assert_ne!(checkpoint_path(&dir, "a.1"), checkpoint_path(&dir, "a.2"));
+
+        // the items of a fan-out have a file each, removed when the flow is saved again
+        let item = |i: usize| {
+            let ctx = ctx.push_map_frame(i, 2).unwrap();
+            checkpoint_file_id("a", &ctx).unwrap()
+        };
+        assert_eq!(item(1), "a-m1");
+        for i in 0..2 {
+            let checkpoint = Checkpoint {
+                id: item(i),
+                stage: "item".to_string(),
+                time: 10,
+                value: AgentValue::integer(i as i64),
+                ctx: ctx.clone(),
+            };
+            write_checkpoint(&dir, &checkpoint).unwrap();
+        }
+        assert_eq!(ids("item"), vec!["a-m0", "a-m1"]);
+        remove_item_checkpoints(&dir, "a").unwrap();
+        assert_eq!(ids(""), vec!["a", "c"]);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
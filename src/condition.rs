@@ -0,0 +1,163 @@
+use std::cmp::Ordering;
+
+use modular_agent_core::{AgentConfigs, AgentError, AgentValue};
+
+use crate::data::get_nested_value;
+
+pub(crate) const CONFIG_KEY: &str = "key";
+pub(crate) const CONFIG_OP: &str = "op";
+pub(crate) const CONFIG_OPERAND: &str = "operand";
+
+pub(crate) const OP_DEFAULT: &str = "==";
+pub(crate) const OP_DESCRIPTION: &str = "==, !=, <, <=, >, >=, contains, exists";
+
+/// A condition on a value: key path + operator + operand.
+///
+/// The operand is parsed as JSON when possible (`10`, `true`, `"a"`), otherwise
+/// it is taken as a plain string. Numbers are compared numerically regardless of
+/// integer/number type; strings are compared lexicographically.
+#[derive(Clone, Debug)]
+pub(crate) struct Condition {
+    keys: Vec<String>,
+    op: Op,
+    operand: AgentValue,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Contains,
+    Exists,
+}
+
+impl Condition {
+    pub(crate) fn new(key: &str, op: &str, operand: &str) -> Result<Self, AgentError> {
+        let keys = if key.is_empty() {
+            Vec::new()
+        } else {
+            key.split('.').map(|s| s.to_string()).collect()
+        };
+        let op = match op.trim() {
+            "" | "==" => Op::Eq,
+            "!=" => Op::Ne,
+            "<" => Op::Lt,
+            "<=" => Op::Le,
+            ">" => Op::Gt,
+            ">=" => Op::Ge,
+            "contains" => Op::Contains,
+            "exists" => Op::Exists,
+            other => {
+                return Err(AgentError::InvalidConfig(format!(
+                    "Unknown operator: {}",
+                    other
+                )));
+            }
+        };
+        let operand = serde_json::from_str::<serde_json::Value>(operand)
+            .ok()
+            .and_then(|v| AgentValue::from_json(v).ok())
+            .unwrap_or_else(|| AgentValue::string(operand));
+        Ok(Self { keys, op, operand })
+    }
+
+    /// Reads the condition from the `key`, `op` and `operand` configs.
+    pub(crate) fn from_configs(configs: &AgentConfigs) -> Result<Self, AgentError> {
+        Self::new(
+            &configs.get_string_or_default(CONFIG_KEY),
+            &configs.get_string_or(CONFIG_OP, OP_DEFAULT),
+            &configs.get_string_or_default(CONFIG_OPERAND),
+        )
+    }
+
+    pub(crate) fn matches(&self, value: &AgentValue) -> bool {
+        let Some(target) = get_nested_value(value, &self.keys) else {
+            return false;
+        };
+        match self.op {
+            Op::Exists => true,
+            Op::Eq => compare(target, &self.operand) == Some(Ordering::Equal),
+            Op::Ne => compare(target, &self.operand) != Some(Ordering::Equal),
+            Op::Lt => compare(target, &self.operand) == Some(Ordering::Less),
+            Op::Le => matches!(
+                compare(target, &self.operand),
+                Some(Ordering::Less | Ordering::Equal)
+            ),
+            Op::Gt => compare(target, &self.operand) == Some(Ordering::Greater),
+            Op::Ge => matches!(
+                compare(target, &self.operand),
+                Some(Ordering::Greater | Ordering::Equal)
+            ),
+            Op::Contains => match target {
+                AgentValue::String(s) => self.operand.as_str().is_some_and(|o| s.contains(o)),
+                AgentValue::Array(arr) => arr
+                    .iter()
+                    .any(|v| compare(v, &self.operand) == Some(Ordering::Equal)),
+                AgentValue::Object(obj) => {
+                    self.operand.as_str().is_some_and(|o| obj.contains_key(o))
+                }
+                _ => false,
+            },
+        }
+    }
+}
+
+pub(crate) fn compare(a: &AgentValue, b: &AgentValue) -> Option<Ordering> {
+    match (a, b) {
+        (
+            AgentValue::Integer(_) | AgentValue::Number(_),
+            AgentValue::Integer(_) | AgentValue::Number(_),
+        ) => a.as_f64()?.partial_cmp(&b.as_f64()?),
+        (AgentValue::String(a), AgentValue::String(b)) => Some(a.cmp(b)),
+        (AgentValue::Boolean(a), AgentValue::Boolean(b)) => Some(a.cmp(b)),
+        _ => (a == b).then_some(Ordering::Equal),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use im::{hashmap, vector};
+
+    use super::*;
+
+    #[test]
+    fn test_condition_compare() {
+        let v = AgentValue::object(hashmap! {
+            "a".to_string() => AgentValue::object(hashmap! {
+                "n".to_string() => AgentValue::integer(5),
+                "s".to_string() => AgentValue::string("hello"),
+            }),
+        });
+
+        let check = |key: &str, op: &str, operand: &str| {
+            Condition::new(key, op, operand).unwrap().matches(&v)
+        };
+        assert!(check("a.n", "==", "5"));
+        assert!(check("a.n", "==", "5.0"));
+        assert!(check("a.n", ">", "4.5"));
+        assert!(check("a.n", "<=", "5"));
+        assert!(!check("a.n", "<", "5"));
+        assert!(check("a.s", "==", "hello"));
+        assert!(check("a.s", "==", "\"hello\""));
+        assert!(check("a.s", "!=", "world"));
+        assert!(check("a.s", "contains", "ell"));
+        assert!(check("a", "contains", "n"));
+        assert!(check("a.s", "exists", ""));
+        assert!(!check("a.x", "exists", ""));
+        // missing keys never match, even with !=
+        assert!(!check("a.x", "!=", "1"));
+        assert!(Condition::new("a", "~", "").is_err());
+    }
+
+    #[test]
+    fn test_condition_array_contains() {
+        let v = AgentValue::array(vector![AgentValue::integer(1), AgentValue::string("x")]);
+        assert!(Condition::new("", "contains", "1").unwrap().matches(&v));
+        assert!(Condition::new("", "contains", "x").unwrap().matches(&v));
+        assert!(!Condition::new("", "contains", "2").unwrap().matches(&v));
+    }
+}
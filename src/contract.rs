@@ -0,0 +1,406 @@
+//! Input contracts.
+//!
+//! The agents of this crate that process input values take an `input_contract` config
+//! and check their inputs against it before processing them, so malformed values are
+//! caught at the boundary rather than deep inside the agent. Agents whose inputs only
+//! trigger, pause or resume them do not take it, and control pins such as `reset` are
+//! not checked. The config is an object of contracts by input pin (`*`: any pin):
+//!
+//! `{"value": {"type": "object", "required": ["id", "amount"], "on_invalid": "route"}}`
+//!
+//! - `type`: unit, boolean, integer, number (also integers), string, array or object
+//! - `min`, `max`: bounds of a number, or of the length of a string or an array
+//! - `regex`: a pattern strings must match
+//! - `required`: key paths (dot separated) objects must have
+//! - `on_invalid`: `reject` (default) fails with an error listing the violations;
+//!   `route` outputs `{pin, value, errors}` on a generated `invalid` pin instead
+
+use std::collections::HashMap;
+
+use im::hashmap;
+use modular_agent_core::{
+    Agent, AgentContext, AgentError, AgentOutput, AgentSpec, AgentValue, AgentValueMap, AsAgent,
+    async_trait,
+};
+use regex::Regex;
+
+use crate::data::get_nested_value;
+use crate::provenance::Traced;
+
+pub(crate) const CONFIG_INPUT_CONTRACT: &str = "input_contract";
+
+pub(crate) const PORT_INVALID: &str = "invalid";
+
+const ANY_PIN: &str = "*";
+
+const KEY_ERRORS: &str = "errors";
+const KEY_MAX: &str = "max";
+const KEY_MIN: &str = "min";
+const KEY_ON_INVALID: &str = "on_invalid";
+const KEY_PIN: &str = "pin";
+const KEY_REGEX: &str = "regex";
+const KEY_REQUIRED: &str = "required";
+const KEY_TYPE: &str = "type";
+const KEY_VALUE: &str = "value";
+
+const ON_INVALID_REJECT: &str = "reject";
+const ON_INVALID_ROUTE: &str = "route";
+
+const TYPE_ARRAY: &str = "array";
+const TYPE_BOOLEAN: &str = "boolean";
+const TYPE_INTEGER: &str = "integer";
+const TYPE_NUMBER: &str = "number";
+const TYPE_OBJECT: &str = "object";
+const TYPE_STRING: &str = "string";
+const TYPE_UNIT: &str = "unit";
+
+#[derive(Debug)]
+struct PinContract {
+    type_: Option<String>,
+    min: Option<f64>,
+    max: Option<f64>,
+    regex: Option<Regex>,
+    required: Vec<String>,
+    route: bool,
+}
+
+impl PinContract {
+    fn parse(pin: &str, value: &AgentValue) -> Result<Self, AgentError> {
+        let invalid =
+            |msg: String| AgentError::InvalidConfig(format!("input_contract of {}: {}", pin, msg));
+        if !value.is_object() {
+            return Err(invalid("not an object".into()));
+        }
+        let type_ = match value.get_str(KEY_TYPE).map(str::trim) {
+            None | Some("") => None,
+            Some(
+                t @ (TYPE_UNIT | TYPE_BOOLEAN | TYPE_INTEGER | TYPE_NUMBER | TYPE_STRING
+                | TYPE_ARRAY | TYPE_OBJECT),
+            ) => Some(t.to_string()),
+            Some(t) => return Err(invalid(format!("unknown type {}", t))),
+        };
+        let bound = |key: &str| match value.get(key) {
+            None => Ok(None),
+            Some(v) => v
+                .as_f64()
+                .map(Some)
+                .ok_or_else(|| invalid(format!("{} is not a number", key))),
+        };
+        let regex = match value.get_str(KEY_REGEX) {
+            Some(re) if !re.is_empty() => {
+                Some(Regex::new(re).map_err(|e| invalid(format!("invalid regex: {}", e)))?)
+            }
+            _ => None,
+        };
+        let required = match value.get(KEY_REQUIRED) {
+            None => Vec::new(),
+            Some(keys) => keys
+                .as_array()
+                .ok_or_else(|| invalid("required is not an array".into()))?
+                .iter()
+                .map(|key| {
+                    key.as_str()
+                        .map(str::to_string)
+                        .ok_or_else(|| invalid("required is not an array of strings".into()))
+                })
+                .collect::<Result<_, _>>()?,
+        };
+        let route = match value.get_str(KEY_ON_INVALID).map(str::trim) {
+            None | Some("") | Some(ON_INVALID_REJECT) => false,
+            Some(ON_INVALID_ROUTE) => true,
+            Some(other) => return Err(invalid(format!("unknown on_invalid {}", other))),
+        };
+        Ok(Self {
+            type_,
+            min: bound(KEY_MIN)?,
+            max: bound(KEY_MAX)?,
+            regex,
+            required,
+            route,
+        })
+    }
+
+    /// Returns the violations of the contract by `value`.
+    fn violations(&self, value: &AgentValue) -> Vec<String> {
+        let mut errors = Vec::new();
+        if let Some(type_) = &self.type_ {
+            let ok = match type_.as_str() {
+                TYPE_UNIT => value.is_unit(),
+                TYPE_BOOLEAN => value.is_boolean(),
+                TYPE_INTEGER => value.is_integer(),
+                TYPE_NUMBER => value.is_integer() || value.is_number(),
+                TYPE_STRING => value.is_string(),
+                TYPE_ARRAY => value.is_array(),
+                _ => value.is_object(),
+            };
+            if !ok {
+                errors.push(format!("expected {}", type_));
+                // the other constraints assume the type
+                return errors;
+            }
+        }
+
+        let measure = match value {
+            AgentValue::Integer(i) => Some(("value", *i as f64)),
+            AgentValue::Number(n) => Some(("value", *n)),
+            AgentValue::String(s) => Some(("length", s.chars().count() as f64)),
+            AgentValue::Array(a) => Some(("length", a.len() as f64)),
+            _ => None,
+        };
+        if let Some((what, x)) = measure {
+            if let Some(min) = self.min
+                && x < min
+            {
+                errors.push(format!("{} {} is less than {}", what, x, min));
+            }
+            if let Some(max) = self.max
+                && x > max
+            {
+                errors.push(format!("{} {} is greater than {}", what, x, max));
+            }
+        }
+
+        if let Some(regex) = &self.regex
+            && let Some(s) = value.as_str()
+            && !regex.is_match(s)
+        {
+            errors.push(format!("does not match {}", regex.as_str()));
+        }
+
+        for key in &self.required {
+            let path: Vec<&str> = key.split('.').collect();
+            if get_nested_value(value, &path).is_none() {
+                errors.push(format!("missing {}", key));
+            }
+        }
+        errors
+    }
+}
+
+/// The input contract of an agent, by input pin.
+#[derive(Debug, Default)]
+pub(crate) struct InputContract {
+    pins: HashMap<String, PinContract>,
+}
+
+impl InputContract {
+    fn parse(contract: &AgentValueMap<String, AgentValue>) -> Result<Self, AgentError> {
+        let pins = contract
+            .iter()
+            .map(|(pin, value)| Ok((pin.clone(), PinContract::parse(pin, value)?)))
+            .collect::<Result<_, AgentError>>()?;
+        Ok(Self { pins })
+    }
+
+    /// Reads the contract in the `input_contract` config of `spec`, and adds the
+    /// `invalid` output pin when some violations are routed.
+    pub(crate) fn update_spec(spec: &mut AgentSpec) -> Result<Self, AgentError> {
+        let contract = match spec.configs.as_ref() {
+            Some(configs) => Self::parse(&configs.get_object_or_default(CONFIG_INPUT_CONTRACT))?,
+            None => Self::default(),
+        };
+        let mut outputs = spec.outputs.take().unwrap_or_default();
+        outputs.retain(|port| port != PORT_INVALID);
+        if contract.routes() {
+            outputs.push(PORT_INVALID.to_string());
+        }
+        spec.outputs = Some(outputs);
+        Ok(contract)
+    }
+
+    /// Reads the contract again after the configs of `spec` changed.
+    ///
+    /// Returns true if the `invalid` pin was added or removed.
+    pub(crate) fn reload(&mut self, spec: &mut AgentSpec) -> Result<bool, AgentError> {
+        let routes = self.routes();
+        *self = Self::update_spec(spec)?;
+        Ok(self.routes() != routes)
+    }
+
+    /// Returns true if the agent has the `invalid` pin.
+    pub(crate) fn routes(&self) -> bool {
+        self.pins.values().any(|contract| contract.route)
+    }
+
+    /// Checks the `value` arriving on `port` of `agent`.
+    ///
+    /// Returns the value if it conforms. Otherwise, routes it to the `invalid` pin and
+    /// returns None, or fails with an error of `{pin, errors}`, as the contract says.
+    pub(crate) async fn check<A: Agent>(
+        &self,
+        agent: &A,
+        ctx: &AgentContext,
+        port: &str,
+        value: AgentValue,
+    ) -> Result<Option<AgentValue>, AgentError> {
+        let Some(contract) = self.pins.get(port).or_else(|| self.pins.get(ANY_PIN)) else {
+            return Ok(Some(value));
+        };
+        let errors = contract.violations(&value);
+        if errors.is_empty() {
+            return Ok(Some(value));
+        }
+        let errors = AgentValue::array(errors.into_iter().map(AgentValue::string).collect());
+        if contract.route {
+            let invalid = AgentValue::object(hashmap! {
+                KEY_PIN.to_string() => AgentValue::string(port),
+                KEY_VALUE.to_string() => value,
+                KEY_ERRORS.to_string() => errors,
+            });
+            agent
+                .output(agent.traced(ctx.clone()), PORT_INVALID, invalid)
+                .await?;
+            return Ok(None);
+        }
+        let error = AgentValue::object(hashmap! {
+            KEY_PIN.to_string() => AgentValue::string(port),
+            KEY_ERRORS.to_string() => errors,
+        });
+        Err(AgentError::InvalidValue(error.to_json().to_string()))
+    }
+}
+
+/// An agent checking its input values against its `input_contract` config.
+///
+/// The agent reads its contract with [`InputContract::update_spec`] in `new`, calls
+/// [`ContractAgent::reload_contract`] in `configs_changed` and checks each value with
+/// [`ContractAgent::check_input`] first thing in `process`, after handling control pins
+/// such as `reset`, which are not checked. The impl is generated by [`contract_agents`]
+/// for agents keeping the contract in a `contract` field.
+#[async_trait]
+pub(crate) trait ContractAgent: AsAgent + Sized {
+    fn contract(&self) -> &InputContract;
+
+    fn contract_mut(&mut self) -> &mut InputContract;
+
+    /// Reads the contract again after the configs changed, and emits the updated spec
+    /// when the `invalid` pin was added or removed.
+    fn reload_contract(&mut self) -> Result<(), AgentError> {
+        let mut contract = std::mem::take(self.contract_mut());
+        let changed = contract.reload(&mut self.mut_data().spec);
+        *self.contract_mut() = contract;
+        if changed? {
+            self.emit_agent_spec_updated();
+        }
+        Ok(())
+    }
+
+    /// Checks the `value` arriving on `port`, as [`InputContract::check`].
+    async fn check_input(
+        &self,
+        ctx: &AgentContext,
+        port: &str,
+        value: AgentValue,
+    ) -> Result<Option<AgentValue>, AgentError> {
+        self.contract().check(self, ctx, port, value).await
+    }
+}
+
+/// Implements [`ContractAgent`] for agents keeping their contract in a `contract` field.
+macro_rules! contract_agents {
+    ($($agent:ty),+ $(,)?) => {
+        $(
+            impl $crate::contract::ContractAgent for $agent {
+                fn contract(&self) -> &$crate::contract::InputContract {
+                    &self.contract
+                }
+
+                fn contract_mut(&mut self) -> &mut $crate::contract::InputContract {
+                    &mut self.contract
+                }
+            }
+        )+
+    };
+}
+
+pub(crate) use contract_agents;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn contract(json: serde_json::Value) -> Result<PinContract, AgentError> {
+        PinContract::parse("value", &AgentValue::from_json(json).unwrap())
+    }
+
+    fn violations(contract: &PinContract, json: serde_json::Value) -> Vec<String> {
+        contract.violations(&AgentValue::from_json(json).unwrap())
+    }
+
+    #[test]
+    fn test_pin_contract() {
+        let c = contract(serde_json::json!({
+            "type": "object",
+            "required": ["id", "user.name"],
+        }))
+        .unwrap();
+        assert!(violations(&c, serde_json::json!({"id": 1, "user": {"name": "a"}})).is_empty());
+        assert_eq!(
+            violations(&c, serde_json::json!({"user": {}})),
+            vec!["missing id", "missing user.name"]
+        );
+        assert_eq!(
+            violations(&c, serde_json::json!([1])),
+            vec!["expected object"]
+        );
+
+        let c = contract(serde_json::json!({"type": "number", "min": 0, "max": 10})).unwrap();
+        assert!(violations(&c, serde_json::json!(3)).is_empty());
+        assert!(violations(&c, serde_json::json!(2.5)).is_empty());
+        assert_eq!(
+            violations(&c, serde_json::json!(11)),
+            vec!["value 11 is greater than 10"]
+        );
+        assert_eq!(
+            violations(&c, serde_json::json!("3")),
+            vec!["expected number"]
+        );
+
+        let c = contract(serde_json::json!({"regex": "^[a-z]+$", "max": 3})).unwrap();
+        assert!(violations(&c, serde_json::json!("abc")).is_empty());
+        assert_eq!(
+            violations(&c, serde_json::json!("abcD")),
+            vec!["length 4 is greater than 3", "does not match ^[a-z]+$"]
+        );
+        assert_eq!(
+            violations(&c, serde_json::json!([1, 2, 3, 4])),
+            vec!["length 4 is greater than 3"]
+        );
+
+        assert!(contract(serde_json::json!({"type": "float"})).is_err());
+        assert!(contract(serde_json::json!({"regex": "("})).is_err());
+        assert!(contract(serde_json::json!({"on_invalid": "drop"})).is_err());
+        assert!(contract(serde_json::json!({"required": "id"})).is_err());
+    }
+
+    #[test]
+    fn test_update_spec() {
+        let mut configs = modular_agent_core::AgentConfigs::new();
+        configs.set(
+            CONFIG_INPUT_CONTRACT.into(),
+            AgentValue::from_json(serde_json::json!({
+                "*": {"type": "integer", "on_invalid": "route"},
+            }))
+            .unwrap(),
+        );
+        let mut spec = AgentSpec {
+            outputs: Some(vec!["value".to_string()]),
+            configs: Some(configs),
+            ..Default::default()
+        };
+        let contract = InputContract::update_spec(&mut spec).unwrap();
+        assert!(contract.routes());
+        assert_eq!(
+            spec.outputs,
+            Some(vec!["value".into(), PORT_INVALID.into()])
+        );
+
+        spec.configs
+            .as_mut()
+            .unwrap()
+            .set(CONFIG_INPUT_CONTRACT.into(), AgentValue::object_default());
+        let contract = InputContract::update_spec(&mut spec).unwrap();
+        assert!(!contract.routes());
+        assert_eq!(spec.outputs, Some(vec!["value".into()]));
+    }
+}
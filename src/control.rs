@@ -0,0 +1,39 @@
+//! Pause/resume control pins for source-like agents.
+//!
+//! Agents that emit values on their own (timers, watchers, pollers) take optional
+//! `pause` and `resume` input pins. Any value on `pause` quiesces the agent's
+//! outputs until a value arrives on `resume`. The agent keeps running while paused;
+//! values it would have emitted in the meantime are dropped. Starting the agent
+//! again clears the paused state.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+pub(crate) const PORT_PAUSE: &str = "pause";
+pub(crate) const PORT_RESUME: &str = "resume";
+
+/// Paused state shared between an agent and the tasks it spawns.
+#[derive(Clone, Default)]
+pub(crate) struct PauseState(Arc<AtomicBool>);
+
+impl PauseState {
+    pub(crate) fn is_paused(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn set_paused(&self, paused: bool) {
+        self.0.store(paused, Ordering::Relaxed);
+    }
+
+    /// Updates the state if `port` is a control pin.
+    ///
+    /// Returns true if the port was handled.
+    pub(crate) fn handle_port(&self, port: &str) -> bool {
+        match port {
+            PORT_PAUSE => self.set_paused(true),
+            PORT_RESUME => self.set_paused(false),
+            _ => return false,
+        }
+        true
+    }
+}
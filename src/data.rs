@@ -1,27 +1,142 @@
+use std::collections::{HashSet, VecDeque};
+use std::sync::{Arc, LazyLock, Mutex};
 use std::time::Duration;
-use std::{collections::VecDeque, vec};
+use std::vec;
 
+use chrono::{DateTime, Local, Utc};
+use handlebars::Handlebars;
 use im::{HashMap, Vector};
 use mini_moka::sync::Cache;
 use modular_agent_core::{
-    AgentConfigSpec, AgentConfigSpecs, AgentConfigs, AgentContext, AgentData, AgentError,
+    Agent, AgentConfigSpec, AgentConfigSpecs, AgentConfigs, AgentContext, AgentData, AgentError,
     AgentOutput, AgentSpec, AgentValue, AsAgent, ModularAgent, async_trait, modular_agent,
 };
 
+use regex::Regex;
+use sha2::{Digest, Sha256};
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+use crate::contract::{CONFIG_INPUT_CONTRACT, ContractAgent, InputContract, contract_agents};
+use crate::expr::{Expr, truthy};
+use crate::profile::ProfileConfigs;
+use crate::provenance::{Traced, stamp};
+use crate::scheduler::{Timer, schedule_supervised};
+use crate::string::handlebars_new;
+use crate::supervisor::{
+    CONFIG_MAX_RESTARTS, CONFIG_TASK_RESTARTS, MAX_RESTARTS_DEFAULT, reset_task_restarts,
+};
+use crate::tenant::{CONFIG_PER_TENANT, TenantMap, tenant_key};
+use crate::time::parse_duration_to_ms;
+
 const CATEGORY: &str = "Std/Data";
 
+const PORT_AGREEMENT: &str = "agreement";
+const PORT_DELTA: &str = "delta";
+const PORT_ARRAY: &str = "array";
+const PORT_ENVELOPE: &str = "envelope";
 const PORT_IN1: &str = "in1";
 const PORT_IN2: &str = "in2";
 const PORT_JSON: &str = "json";
+const PORT_META: &str = "meta";
+const PORT_NONE: &str = "none";
 const PORT_OBJECT: &str = "object";
+const PORT_RESET: &str = "reset";
+const PORT_UNCHANGED: &str = "unchanged";
 const PORT_VALUE: &str = "value";
 
+const CONFIG_AGG: &str = "agg";
+const CONFIG_ARRAY_INDEX: &str = "array_index";
+const CONFIG_COLUMN_KEY: &str = "column_key";
+const CONFIG_COLUMN_NAME: &str = "column_name";
+const CONFIG_EMPTY: &str = "empty";
+const CONFIG_GROUP_KEY: &str = "group_key";
+const CONFIG_ID_KEYS: &str = "id_keys";
 const CONFIG_KEY: &str = "key";
+const CONFIG_KEY_TEMPLATE: &str = "key_template";
+const CONFIG_KEYS: &str = "keys";
+const CONFIG_MASK: &str = "mask";
+const CONFIG_MODE: &str = "mode";
+const CONFIG_PATTERNS: &str = "patterns";
+const CONFIG_REGEX: &str = "regex";
+const CONFIG_SALT: &str = "salt";
+const CONFIG_SCORE_KEY: &str = "score_key";
 const CONFIG_VALUE: &str = "value";
 const CONFIG_N: &str = "n";
+const CONFIG_ROW_KEY: &str = "row_key";
+const CONFIG_SEP: &str = "sep";
+const CONFIG_SLIDE: &str = "slide";
+const CONFIG_SOURCE: &str = "source";
+const CONFIG_SPEC: &str = "spec";
+const CONFIG_TAGS: &str = "tags";
+const CONFIG_THRESHOLD: &str = "threshold";
 const CONFIG_USE_CTX: &str = "use_ctx";
 const CONFIG_TTL_SECONDS: &str = "ttl_sec";
 const CONFIG_CAPACITY: &str = "capacity";
+const CONFIG_COL: &str = "col";
+const CONFIG_REF: &str = "ref";
+const CONFIG_ROW: &str = "row";
+const CONFIG_VALUE_KEY: &str = "value_key";
+const CONFIG_VALUE_NAME: &str = "value_name";
+const CONFIG_WINDOW: &str = "window";
+const CONFIG_WINDOW_MODE: &str = "window_mode";
+
+const WINDOW_TUMBLING: &str = "tumbling";
+const WINDOW_SLIDING: &str = "sliding";
+const WINDOW_DEFAULT: &str = "10s";
+const SLIDE_DEFAULT: &str = "1s";
+
+const MODE_GET: &str = "get";
+const MODE_SET: &str = "set";
+const MODE_MASK: &str = "mask";
+const MODE_HASH: &str = "hash";
+const MASK_DEFAULT: &str = "***";
+const PATTERNS_DEFAULT: &str = "email, phone, credit_card";
+
+// Built-in redaction patterns. Overlapping matches, such as the phone numbers within
+// credit cards, are redacted as one.
+const REDACT_PATTERNS: &[(&str, &str)] = &[
+    ("credit_card", r"\b(?:\d[ -]?){12,18}\d\b"),
+    ("email", r"[A-Za-z0-9._%+-]+@[A-Za-z0-9.-]+\.[A-Za-z]{2,}"),
+    ("phone", r"\+?\(?\d[\d .()/-]{5,}\d"),
+];
+
+const PHONE_PATTERN: &str = "phone";
+
+static IP_ADDRESS: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\b\d{1,3}(?:\.\d{1,3}){3}\b").unwrap());
+static DATE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"\b(?:\d{4}[-./]\d{1,2}[-./]\d{1,2}|\d{1,2}[-./]\d{1,2}[-./]\d{2,4})\b").unwrap()
+});
+
+// Whether a match of the phone pattern is a phone number rather than a date, an IP
+// address or another run of numbers.
+fn is_phone(candidate: &str) -> bool {
+    let digits = candidate.chars().filter(|c| c.is_ascii_digit()).count();
+    (7..=15).contains(&digits) && !IP_ADDRESS.is_match(candidate) && !DATE.is_match(candidate)
+}
+
+contract_agents!(
+    GetValueAgent,
+    SetValueAgent,
+    ToObjectAgent,
+    ToJsonAgent,
+    FromJsonAgent,
+    DeltaAgent,
+    FlattenAgent,
+    UnflattenAgent,
+    PivotAgent,
+    UnpivotAgent,
+    CellsAgent,
+    AggregateAgent,
+    RedactAgent,
+    TransformAgent,
+    WrapAgent,
+    UnwrapAgent,
+    ZipToObjectAgent,
+    CoalesceAgent,
+    ConsensusAgent,
+);
 
 // Get Value
 #[modular_agent(
@@ -29,10 +144,12 @@ const CONFIG_CAPACITY: &str = "capacity";
     category = CATEGORY,
     inputs = [PORT_VALUE],
     outputs = [PORT_VALUE],
-    string_config(name = CONFIG_KEY)
+    string_config(name = CONFIG_KEY),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
 )]
 struct GetValueAgent {
     data: AgentData,
+    contract: InputContract,
     target_keys: Vec<String>,
 }
 
@@ -55,14 +172,17 @@ impl GetValueAgent {
 impl AsAgent for GetValueAgent {
     fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
         let target_keys = Self::update_spec(&mut spec)?;
+        let contract = InputContract::update_spec(&mut spec)?;
         Ok(Self {
             data: AgentData::new(ma, id, spec),
+            contract,
             target_keys,
         })
     }
 
     fn configs_changed(&mut self) -> Result<(), AgentError> {
         let target_keys = Self::update_spec(&mut self.data.spec)?;
+        self.reload_contract()?;
         self.target_keys = target_keys;
         Ok(())
     }
@@ -70,9 +190,12 @@ impl AsAgent for GetValueAgent {
     async fn process(
         &mut self,
         ctx: AgentContext,
-        _port: String,
+        port: String,
         value: AgentValue,
     ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
         if self.target_keys.is_empty() {
             return Ok(());
         }
@@ -97,7 +220,8 @@ impl AsAgent for GetValueAgent {
             _ => AgentValue::Unit,
         };
 
-        self.output(ctx, PORT_VALUE, output_value).await
+        self.output(self.traced(ctx), PORT_VALUE, output_value)
+            .await
     }
 }
 
@@ -109,9 +233,11 @@ impl AsAgent for GetValueAgent {
     outputs = [PORT_VALUE],
     string_config(name = CONFIG_KEY),
     object_config(name = CONFIG_VALUE),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
 )]
 struct SetValueAgent {
     data: AgentData,
+    contract: InputContract,
     target_keys: Vec<String>,
     target_value: AgentValue,
 }
@@ -137,8 +263,10 @@ impl SetValueAgent {
 impl AsAgent for SetValueAgent {
     fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
         let (target_keys, target_value) = Self::update_spec(&mut spec)?;
+        let contract = InputContract::update_spec(&mut spec)?;
         Ok(Self {
             data: AgentData::new(ma, id, spec),
+            contract,
             target_keys,
             target_value,
         })
@@ -146,6 +274,7 @@ impl AsAgent for SetValueAgent {
 
     fn configs_changed(&mut self) -> Result<(), AgentError> {
         let (target_keys, target_value) = Self::update_spec(&mut self.data.spec)?;
+        self.reload_contract()?;
         self.target_keys = target_keys;
         self.target_value = target_value;
         Ok(())
@@ -154,15 +283,18 @@ impl AsAgent for SetValueAgent {
     async fn process(
         &mut self,
         ctx: AgentContext,
-        _port: String,
-        mut value: AgentValue,
+        port: String,
+        value: AgentValue,
     ) -> Result<(), AgentError> {
+        let Some(mut value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
         if self.target_keys.is_empty() {
             return Ok(());
         }
 
         set_nested_value(&mut value, &self.target_keys, self.target_value.clone());
-        self.output(ctx, PORT_VALUE, value).await
+        self.output(self.traced(ctx), PORT_VALUE, value).await
     }
 }
 
@@ -172,10 +304,12 @@ impl AsAgent for SetValueAgent {
     category = CATEGORY,
     inputs = [PORT_VALUE],
     outputs = [PORT_VALUE],
-    string_config(name = CONFIG_KEY)
+    string_config(name = CONFIG_KEY),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
 )]
 struct ToObjectAgent {
     data: AgentData,
+    contract: InputContract,
     target_keys: Vec<String>,
 }
 
@@ -198,14 +332,17 @@ impl ToObjectAgent {
 impl AsAgent for ToObjectAgent {
     fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
         let target_keys = Self::update_spec(&mut spec)?;
+        let contract = InputContract::update_spec(&mut spec)?;
         Ok(Self {
             data: AgentData::new(ma, id, spec),
+            contract,
             target_keys,
         })
     }
 
     fn configs_changed(&mut self) -> Result<(), AgentError> {
         let target_keys = Self::update_spec(&mut self.data.spec)?;
+        self.reload_contract()?;
         self.target_keys = target_keys;
         Ok(())
     }
@@ -213,9 +350,12 @@ impl AsAgent for ToObjectAgent {
     async fn process(
         &mut self,
         ctx: AgentContext,
-        _port: String,
+        port: String,
         value: AgentValue,
     ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
         if self.target_keys.is_empty() {
             return Ok(());
         }
@@ -223,7 +363,7 @@ impl AsAgent for ToObjectAgent {
         let mut new_value = AgentValue::object_default();
         set_nested_value(&mut new_value, &self.target_keys, value);
 
-        self.output(ctx, PORT_VALUE, new_value).await
+        self.output(self.traced(ctx), PORT_VALUE, new_value).await
     }
 }
 
@@ -232,29 +372,40 @@ impl AsAgent for ToObjectAgent {
     title = "To JSON",
     category = CATEGORY,
     inputs = [PORT_VALUE],
-    outputs = [PORT_JSON]
+    outputs = [PORT_JSON],
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
 )]
 struct ToJsonAgent {
     data: AgentData,
+    contract: InputContract,
 }
 
 #[async_trait]
 impl AsAgent for ToJsonAgent {
-    fn new(ma: ModularAgent, id: String, spec: AgentSpec) -> Result<Self, AgentError> {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
         Ok(Self {
             data: AgentData::new(ma, id, spec),
+            contract,
         })
     }
 
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
+    }
+
     async fn process(
         &mut self,
         ctx: AgentContext,
-        _port: String,
+        port: String,
         value: AgentValue,
     ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
         let json = serde_json::to_string_pretty(&value)
             .map_err(|e| AgentError::InvalidValue(e.to_string()))?;
-        self.output(ctx, PORT_JSON, AgentValue::string(json))
+        self.output(self.traced(ctx), PORT_JSON, AgentValue::string(json))
             .await?;
         Ok(())
     }
@@ -265,227 +416,2126 @@ impl AsAgent for ToJsonAgent {
     title = "From JSON",
     category = CATEGORY,
     inputs = [PORT_JSON],
-    outputs = [PORT_VALUE]
+    outputs = [PORT_VALUE],
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
 )]
 struct FromJsonAgent {
     data: AgentData,
+    contract: InputContract,
 }
 
 #[async_trait]
 impl AsAgent for FromJsonAgent {
-    fn new(ma: ModularAgent, id: String, spec: AgentSpec) -> Result<Self, AgentError> {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
         Ok(Self {
             data: AgentData::new(ma, id, spec),
+            contract,
         })
     }
 
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
+    }
+
     async fn process(
         &mut self,
         ctx: AgentContext,
-        _port: String,
+        port: String,
         value: AgentValue,
     ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
         let s = value
             .as_str()
             .ok_or_else(|| AgentError::InvalidValue("not a string".to_string()))?;
         let json_value: serde_json::Value =
             serde_json::from_str(s).map_err(|e| AgentError::InvalidValue(e.to_string()))?;
         let value = AgentValue::from_json(json_value)?;
-        self.output(ctx, PORT_VALUE, value).await?;
+        self.output(self.traced(ctx), PORT_VALUE, value).await?;
         Ok(())
     }
 }
 
-fn get_nested_value<'a, K: AsRef<str>>(
-    value: &'a AgentValue,
-    keys: &[K],
-) -> Option<&'a AgentValue> {
-    let mut current_value = value;
-    for key in keys {
-        let obj = current_value.as_object()?;
-        current_value = obj.get(key.as_ref())?;
-    }
-    Some(current_value)
+/// Emits the difference between consecutive values.
+///
+/// For numbers, `delta` is the current value minus the previous one.
+/// For objects, `delta` is an object of the keys that were added or changed;
+/// removed keys are reported as unit. Any other value is emitted as is when it
+/// differs from the previous one.
+///
+/// When nothing has changed, the current value is emitted on `unchanged` instead.
+/// The first value only becomes the baseline. A value on the `reset` pin clears it.
+/// With `per tenant`, each tenant id in the context has its own baseline.
+#[modular_agent(
+    title = "Delta",
+    category = CATEGORY,
+    inputs = [PORT_VALUE, PORT_RESET],
+    outputs = [PORT_DELTA, PORT_UNCHANGED],
+    boolean_config(name = CONFIG_PER_TENANT, title = "per tenant"),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
+)]
+struct DeltaAgent {
+    data: AgentData,
+    contract: InputContract,
+    // previous value by tenant id
+    prev: TenantMap<AgentValue>,
 }
 
-fn set_nested_value<K: AsRef<str>>(root: &mut AgentValue, keys: &[K], new_value: AgentValue) {
-    if keys.is_empty() {
-        return;
+#[async_trait]
+impl AsAgent for DeltaAgent {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
+        Ok(Self {
+            data: AgentData::new(ma, id, spec),
+            contract,
+            prev: TenantMap::default(),
+        })
     }
 
-    // Split into the last key and the path before it
-    // keys = ["a", "b", "c"] -> path=["a", "b"], last_key="c"
-    let (last_key, path) = keys.split_last().unwrap();
-
-    let mut current = root;
-
-    // Traverse down to just before the target
-    for key in path {
-        // If current position is not an Object, forcibly overwrite it with an empty Object
-        if !current.is_object() {
-            *current = AgentValue::object_default();
-        }
-
-        let obj = current.as_object_mut().unwrap();
-
-        current = obj
-            .entry(key.as_ref().to_string())
-            .or_insert_with(AgentValue::object_default);
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
     }
 
-    // Set the value for the last key
-    if !current.is_object() {
-        *current = AgentValue::object_default();
+    async fn stop(&mut self) -> Result<(), AgentError> {
+        self.prev.clear();
+        Ok(())
     }
 
-    if let Some(obj) = current.as_object_mut() {
-        obj.insert(last_key.as_ref().to_string(), new_value);
+    async fn process(
+        &mut self,
+        ctx: AgentContext,
+        port: String,
+        value: AgentValue,
+    ) -> Result<(), AgentError> {
+        let tenant = tenant_key(&ctx, self.configs()?.get_bool_or_default(CONFIG_PER_TENANT));
+        if port == PORT_RESET {
+            self.prev.remove(&tenant);
+            return Ok(());
+        }
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
+
+        let Some(prev) = self.prev.insert(tenant, value.clone()) else {
+            return Ok(());
+        };
+        match delta_value(&prev, &value) {
+            Some(delta) => self.output(self.traced(ctx), PORT_DELTA, delta).await,
+            None => self.output(self.traced(ctx), PORT_UNCHANGED, value).await,
+        }
     }
 }
 
-/// Zips multiple inputs into an object.
-///
-/// The number of inputs n and keys are specified via configuration.
-///
-/// If n=2, it takes two inputs: in1 and in2. Once all inputs are present,
-/// it emits them as { key1: in1, key2: in2 }.
+/// Flattens a nested object into a flat object.
 ///
-/// If in2 arrives repeatedly before in1, the in2 values are queued; when in1 arrives,
-/// they’re paired in order from the head of the queue and emitted.
-///
-/// When the `use_ctx` config is true, inputs are matched by context key (including map frames)
-/// so that mapped items zip correctly even when they interleave.
+/// Nested keys are joined with `sep`, e.g. `{"a": {"b": 1}}` becomes `{"a.b": 1}`.
+/// When `array_index` is true, arrays are flattened too, using their indices as keys
+/// (`{"a": [1, 2]}` becomes `{"a.0": 1, "a.1": 2}`). Otherwise arrays are kept as values.
 #[modular_agent(
-    title = "ZipToObject",
+    title = "Flatten",
     category = CATEGORY,
-    inputs = [PORT_IN1, PORT_IN2],
-    outputs = [PORT_OBJECT],
-    integer_config(name = CONFIG_N, default = 2),
-    boolean_config(name = CONFIG_USE_CTX),
-    integer_config(name = CONFIG_TTL_SECONDS, default = 60),
-    integer_config(name = CONFIG_CAPACITY, default = 1000),
+    inputs = [PORT_VALUE],
+    outputs = [PORT_VALUE],
+    string_config(name = CONFIG_SEP, default = "."),
+    boolean_config(name = CONFIG_ARRAY_INDEX, default = true, title = "array index"),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
 )]
-struct ZipToObjectAgent {
+struct FlattenAgent {
     data: AgentData,
-    n: usize,
-    use_ctx: bool,
-    ttl_seconds: u64,
-    capacity: usize,
+    contract: InputContract,
+}
 
-    // Optimization: Pre-load and store key configuration (k1, k2...)
-    keys: Vec<String>,
+#[async_trait]
+impl AsAgent for FlattenAgent {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
+        Ok(Self {
+            data: AgentData::new(ma, id, spec),
+            contract,
+        })
+    }
+
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
+    }
 
-    // For simple mode: FIFO queues
-    queues: Vec<VecDeque<AgentValue>>,
+    async fn process(
+        &mut self,
+        ctx: AgentContext,
+        port: String,
+        value: AgentValue,
+    ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
+        let config = self.configs()?;
+        let sep = config.get_string_or(CONFIG_SEP, ".");
+        let array_index = config.get_bool_or(CONFIG_ARRAY_INDEX, true);
+
+        let flattenable = value.is_object() || (array_index && value.is_array());
+        if !flattenable {
+            return Err(AgentError::InvalidValue(
+                "Flatten requires an object".to_string(),
+            ));
+        }
 
-    // For use_ctx mode: Cache with TTL
-    ctx_buffers: Cache<String, PendingZip>,
+        let mut flat = HashMap::new();
+        flatten_value(&value, None, &sep, array_index, &mut flat);
+        self.output(self.traced(ctx), PORT_VALUE, AgentValue::object(flat))
+            .await
+    }
 }
 
-#[derive(Clone)]
-struct PendingZip {
-    values: Vec<Option<AgentValue>>,
-    count: usize,
+/// Restores a nested object from a flat object.
+///
+/// Keys are split by `sep`, e.g. `{"a.b": 1}` becomes `{"a": {"b": 1}}`.
+/// When `array_index` is true, objects whose keys are exactly 0, 1, 2... become arrays.
+#[modular_agent(
+    title = "Unflatten",
+    category = CATEGORY,
+    inputs = [PORT_VALUE],
+    outputs = [PORT_VALUE],
+    string_config(name = CONFIG_SEP, default = "."),
+    boolean_config(name = CONFIG_ARRAY_INDEX, default = true, title = "array index"),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
+)]
+struct UnflattenAgent {
+    data: AgentData,
+    contract: InputContract,
 }
 
-impl ZipToObjectAgent {
-    fn update_spec(
-        spec: &mut AgentSpec,
-    ) -> Result<(usize, bool, u64, u64, Vec<String>), AgentError> {
-        let n = spec
-            .configs
-            .as_ref()
-            .map(|cfg| cfg.get_integer_or(CONFIG_N, 2))
-            .unwrap_or(2) as usize;
-        let n = if n < 1 { 1 } else { n };
-
-        let use_ctx = spec
-            .configs
-            .as_ref()
-            .map(|cfg| cfg.get_bool_or_default(CONFIG_USE_CTX))
-            .unwrap_or(false);
-
-        let ttl_sec = spec
-            .configs
-            .as_ref()
-            .map(|c| c.get_integer_or("ttl_seconds", 60))
-            .unwrap_or(60) as u64;
-
-        let capacity = spec
-            .configs
-            .as_ref()
-            .map(|c| c.get_integer_or("capacity", 1000))
-            .unwrap_or(1000) as u64;
+#[async_trait]
+impl AsAgent for UnflattenAgent {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
+        Ok(Self {
+            data: AgentData::new(ma, id, spec),
+            contract,
+        })
+    }
 
-        // Dynamic generation of config definitions (ConfigSpecs)
-        let mut configs = AgentConfigs::new();
-        let mut config_specs = AgentConfigSpecs::default();
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
+    }
 
-        // Re-set required configurations
-        configs.set(CONFIG_N.to_string(), AgentValue::integer(n as i64));
-        let Some(n_spec) = spec
-            .config_specs
-            .as_ref()
-            .and_then(|cs| cs.get(CONFIG_N))
-            .cloned()
-        else {
-            return Err(AgentError::InvalidConfig("config n must be present".into()));
+    async fn process(
+        &mut self,
+        ctx: AgentContext,
+        port: String,
+        value: AgentValue,
+    ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
         };
-        config_specs.insert(CONFIG_N.to_string(), n_spec);
+        let config = self.configs()?;
+        let sep = config.get_string_or(CONFIG_SEP, ".");
+        let array_index = config.get_bool_or(CONFIG_ARRAY_INDEX, true);
 
-        let Some(use_ctx_spec) = spec
-            .config_specs
-            .as_ref()
-            .and_then(|cs| cs.get(CONFIG_USE_CTX))
-            .cloned()
-        else {
-            return Err(AgentError::InvalidConfig(
-                "config use_ctx must be present".into(),
+        let Some(flat) = value.as_object() else {
+            return Err(AgentError::InvalidValue(
+                "Unflatten requires an object".to_string(),
             ));
         };
-        config_specs.insert(CONFIG_USE_CTX.to_string(), use_ctx_spec);
-
-        let mut keys = Vec::with_capacity(n);
-        for i in 1..=n {
-            let key_name = format!("k{}", i);
-            let default_key = format!("in{}", i);
-            let v = spec
-                .configs
-                .as_ref()
-                .map(|cfg| cfg.get_string_or(&key_name, &default_key))
-                .unwrap_or(default_key);
-
-            keys.push(v.clone());
-
-            configs.set(key_name.clone(), AgentValue::string(v));
-            config_specs.insert(
-                key_name,
-                AgentConfigSpec {
-                    value: AgentValue::string_default(),
-                    type_: Some("string".to_string()),
-                    ..Default::default()
-                },
-            );
-        }
-
-        spec.configs = Some(configs);
-        spec.config_specs = Some(config_specs);
-
-        spec.inputs = Some((1..=n).map(|i| format!("in{}", i)).collect());
 
-        Ok((n as usize, use_ctx, ttl_sec, capacity, keys))
+        let value = unflatten_value(flat, &sep, array_index);
+        self.output(self.traced(ctx), PORT_VALUE, value).await
     }
+}
 
-    fn reset_state(&mut self) {
-        self.queues = vec![VecDeque::new(); self.n];
-        self.ctx_buffers.invalidate_all();
-    }
+/// Reshapes an array of records from long to wide.
+///
+/// Records are grouped by the value at `row_key`; each group becomes one record
+/// with the `row_key` field and one field per distinct `column_key` value, holding
+/// the value at `value_key`. Duplicates in a cell are combined with `agg`.
+/// Rows keep the order of their first appearance. A dotted `row_key` (e.g. `date.day`)
+/// is written back as a nested field.
+#[modular_agent(
+    title = "Pivot",
+    category = CATEGORY,
+    inputs = [PORT_ARRAY],
+    outputs = [PORT_ARRAY],
+    string_config(name = CONFIG_ROW_KEY, title = "row key"),
+    string_config(name = CONFIG_COLUMN_KEY, title = "column key"),
+    string_config(name = CONFIG_VALUE_KEY, title = "value key"),
+    string_config(name = CONFIG_AGG, default = AGG_LAST, description = AGG_DESCRIPTION),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
+)]
+struct PivotAgent {
+    data: AgentData,
+    contract: InputContract,
 }
 
 #[async_trait]
-impl AsAgent for ZipToObjectAgent {
+impl AsAgent for PivotAgent {
     fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
-        let (n, use_ctx, ttl_sec, capacity, keys) = Self::update_spec(&mut spec)?;
+        let contract = InputContract::update_spec(&mut spec)?;
+        Ok(Self {
+            data: AgentData::new(ma, id, spec),
+            contract,
+        })
+    }
+
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
+    }
+
+    async fn process(
+        &mut self,
+        ctx: AgentContext,
+        port: String,
+        value: AgentValue,
+    ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
+        let config = self.configs()?;
+        let row_key = config.get_string_or_default(CONFIG_ROW_KEY);
+        let column_key = config.get_string_or_default(CONFIG_COLUMN_KEY);
+        let value_key = config.get_string_or_default(CONFIG_VALUE_KEY);
+        let agg = config.get_string_or(CONFIG_AGG, AGG_LAST);
+        if row_key.is_empty() || column_key.is_empty() {
+            return Err(AgentError::InvalidConfig(
+                "row_key and column_key must be set".into(),
+            ));
+        }
+
+        let Some(records) = value.as_array() else {
+            return Err(AgentError::InvalidArrayValue("Expected array".into()));
+        };
+        let rows = pivot(records, &row_key, &column_key, &value_key, &agg)?;
+        self.output(self.traced(ctx), PORT_ARRAY, AgentValue::array(rows))
+            .await
+    }
+}
+
+/// Reshapes an array of records from wide to long.
+///
+/// Every field of a record other than the `id_keys` (comma separated) becomes
+/// a record of the id fields plus `column_name` (the field name) and `value_name`
+/// (the field value). Fields are emitted in key order.
+#[modular_agent(
+    title = "Unpivot",
+    category = CATEGORY,
+    inputs = [PORT_ARRAY],
+    outputs = [PORT_ARRAY],
+    string_config(name = CONFIG_ID_KEYS, title = "id keys"),
+    string_config(name = CONFIG_COLUMN_NAME, default = "column", title = "column name"),
+    string_config(name = CONFIG_VALUE_NAME, default = "value", title = "value name"),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
+)]
+struct UnpivotAgent {
+    data: AgentData,
+    contract: InputContract,
+}
+
+#[async_trait]
+impl AsAgent for UnpivotAgent {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
+        Ok(Self {
+            data: AgentData::new(ma, id, spec),
+            contract,
+        })
+    }
+
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
+    }
+
+    async fn process(
+        &mut self,
+        ctx: AgentContext,
+        port: String,
+        value: AgentValue,
+    ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
+        let config = self.configs()?;
+        let id_keys = config.get_string_or_default(CONFIG_ID_KEYS);
+        let id_keys: Vec<&str> = id_keys
+            .split(',')
+            .map(|s| s.trim())
+            .filter(|s| !s.is_empty())
+            .collect();
+        let column_name = config.get_string_or(CONFIG_COLUMN_NAME, "column");
+        let value_name = config.get_string_or(CONFIG_VALUE_NAME, "value");
+
+        let Some(records) = value.as_array() else {
+            return Err(AgentError::InvalidArrayValue("Expected array".into()));
+        };
+        let rows = unpivot(records, &id_keys, &column_name, &value_name);
+        self.output(self.traced(ctx), PORT_ARRAY, AgentValue::array(rows))
+            .await
+    }
+}
+
+/// Gets or sets cells of a table, given as an array of rows (arrays).
+///
+/// `ref` is an A1-style reference (`B2`, `$B$2`) or range (`A1:C3`); when blank, the cell
+/// at `row` and `col` (from 1) is used. In `get` mode, a cell outputs its value (null when
+/// missing) and a range outputs the rows of the range as a sub-table, cut to the cells
+/// present. In `set` mode, the cell or each cell of the range is set to `value`, and the
+/// table is padded with nulls where needed.
+///
+/// References are limited to 1048576 rows and 16384 columns (`XFD`), and `set` to ranges
+/// of up to 1048576 cells.
+#[modular_agent(
+    title = "Cells",
+    category = CATEGORY,
+    inputs = [PORT_ARRAY],
+    outputs = [PORT_VALUE],
+    string_config(name = CONFIG_MODE, default = MODE_GET, description = "get, set"),
+    string_config(name = CONFIG_REF, description = "ex. B2, A1:C3"),
+    integer_config(name = CONFIG_ROW, default = 1),
+    integer_config(name = CONFIG_COL, default = 1),
+    object_config(name = CONFIG_VALUE, description = "set only"),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
+)]
+struct CellsAgent {
+    data: AgentData,
+    contract: InputContract,
+}
+
+#[async_trait]
+impl AsAgent for CellsAgent {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
+        Ok(Self {
+            data: AgentData::new(ma, id, spec),
+            contract,
+        })
+    }
+
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
+    }
+
+    async fn process(
+        &mut self,
+        ctx: AgentContext,
+        port: String,
+        value: AgentValue,
+    ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
+        let config = self.configs()?;
+        let cell_ref = config.get_string_or_default(CONFIG_REF);
+        let range = if cell_ref.trim().is_empty() {
+            let row = config.get_integer_or(CONFIG_ROW, 1);
+            let col = config.get_integer_or(CONFIG_COL, 1);
+            if row < 1 || col < 1 {
+                return Err(AgentError::InvalidConfig(format!(
+                    "row and col start from 1, got {}, {}",
+                    row, col
+                )));
+            }
+            let cell = (row as usize - 1, col as usize - 1);
+            CellRange::new(cell, cell)?
+        } else {
+            parse_cell_range(&cell_ref)?
+        };
+
+        let Some(table) = value.as_array() else {
+            return Err(AgentError::InvalidArrayValue(
+                "Expected array of rows".into(),
+            ));
+        };
+        let output = match config.get_string_or(CONFIG_MODE, MODE_GET).as_str() {
+            MODE_GET => get_cells(table, &range)?,
+            MODE_SET => {
+                let cell_value = config.get(CONFIG_VALUE).cloned().unwrap_or_default();
+                AgentValue::Array(set_cells(table.clone(), &range, cell_value)?)
+            }
+            mode => {
+                return Err(AgentError::InvalidConfig(format!("Unknown mode: {}", mode)));
+            }
+        };
+        self.output(self.traced(ctx), PORT_VALUE, output).await
+    }
+}
+
+/// Aggregates a stream per group over time windows.
+///
+/// Incoming values are grouped by the value at `group_key` (all in one group when
+/// blank), and the values at `value_key` are combined with `agg` when a window closes.
+/// Each group is emitted as `{group, value, count}` in order of first appearance.
+///
+/// A tumbling window closes every `window` and starts empty. A sliding window is
+/// evaluated every `slide` over the values received during the last `window`.
+/// Nothing is emitted for a window without values.
+/// With `per tenant`, the values of each tenant id in the context are aggregated
+/// separately and emitted with the context of their tenant.
+#[modular_agent(
+    title = "Aggregate",
+    category = CATEGORY,
+    inputs = [PORT_VALUE],
+    outputs = [PORT_VALUE],
+    string_config(name = CONFIG_GROUP_KEY, title = "group key"),
+    string_config(name = CONFIG_VALUE_KEY, title = "value key"),
+    string_config(name = CONFIG_AGG, default = AGG_COUNT, description = AGG_DESCRIPTION),
+    string_config(name = CONFIG_WINDOW_MODE, default = WINDOW_TUMBLING, title = "window mode", description = "tumbling, sliding"),
+    string_config(name = CONFIG_WINDOW, default = WINDOW_DEFAULT, description = "(ex. 10s, 5m, 100ms, 1h, 1d)"),
+    string_config(name = CONFIG_SLIDE, default = SLIDE_DEFAULT, description = "sliding only"),
+    boolean_config(name = CONFIG_PER_TENANT, title = "per tenant"),
+    integer_config(name = CONFIG_MAX_RESTARTS, default = MAX_RESTARTS_DEFAULT, title = "max restarts", description = "-1: unlimited", detail),
+    integer_config(name = CONFIG_TASK_RESTARTS, title = "task restarts", readonly, detail),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
+)]
+struct AggregateAgent {
+    data: AgentData,
+    contract: InputContract,
+    events: Arc<Mutex<VecDeque<WindowEvent>>>,
+    timer: Option<Timer>,
+}
+
+#[derive(Clone)]
+struct WindowEvent {
+    time: Instant,
+    tenant: String,
+    ctx: AgentContext,
+    value: AgentValue,
+}
+
+// Splits the events of a window by tenant, in order of first appearance, with the
+// context of the last event of each tenant.
+fn tenant_windows(events: Vec<WindowEvent>) -> Vec<(AgentContext, Vec<AgentValue>)> {
+    let mut windows: Vec<(AgentContext, Vec<AgentValue>)> = Vec::new();
+    let mut window_index: HashMap<String, usize> = HashMap::new();
+    for event in events {
+        match window_index.get(&event.tenant) {
+            Some(&i) => {
+                let (ctx, values) = &mut windows[i];
+                *ctx = event.ctx;
+                values.push(event.value);
+            }
+            None => {
+                window_index.insert(event.tenant, windows.len());
+                windows.push((event.ctx, vec![event.value]));
+            }
+        }
+    }
+    windows
+}
+
+impl AggregateAgent {
+    fn start_timer(&mut self) -> Result<(), AgentError> {
+        let config = self.configs()?;
+        let group_key = config.get_string_or_default(CONFIG_GROUP_KEY);
+        let value_key = config.get_string_or_default(CONFIG_VALUE_KEY);
+        let agg = config.get_string_or(CONFIG_AGG, AGG_COUNT);
+        // validate the aggregation before starting
+        aggregate(&[], &agg)?;
+        let sliding = match config
+            .get_string_or(CONFIG_WINDOW_MODE, WINDOW_TUMBLING)
+            .trim()
+        {
+            "" | WINDOW_TUMBLING => false,
+            WINDOW_SLIDING => true,
+            other => {
+                return Err(AgentError::InvalidConfig(format!(
+                    "Unknown window mode: {}",
+                    other
+                )));
+            }
+        };
+        let window = Duration::from_millis(parse_duration_to_ms(
+            &config.get_string_or(CONFIG_WINDOW, WINDOW_DEFAULT),
+        )?);
+        let period = if sliding {
+            Duration::from_millis(parse_duration_to_ms(
+                &config.get_string_or(CONFIG_SLIDE, SLIDE_DEFAULT),
+            )?)
+        } else {
+            window
+        };
+        let max_restarts = config.get_integer_or(CONFIG_MAX_RESTARTS, MAX_RESTARTS_DEFAULT);
+
+        let events = self.events.clone();
+        let ma = self.ma().clone();
+        let agent_id = self.id().to_string();
+        let def_name = self.def_name().to_string();
+        let deadline = Instant::now() + period;
+        let timer = schedule_supervised(self, max_restarts, deadline, move |deadline| {
+            let windows = {
+                let mut events = events.lock().unwrap();
+                if sliding {
+                    let now = Instant::now();
+                    while events
+                        .front()
+                        .is_some_and(|e| now.duration_since(e.time) > window)
+                    {
+                        events.pop_front();
+                    }
+                }
+                if sliding {
+                    tenant_windows(events.iter().cloned().collect())
+                } else {
+                    tenant_windows(events.drain(..).collect())
+                }
+            };
+
+            for (ctx, window_events) in windows {
+                let groups = match aggregate_groups(&window_events, &group_key, &value_key, &agg) {
+                    Ok(groups) => groups,
+                    Err(e) => {
+                        log::error!("Failed to aggregate window: {}", e);
+                        continue;
+                    }
+                };
+                for group in groups {
+                    if let Err(e) = ma.try_send_agent_out(
+                        agent_id.clone(),
+                        stamp(ctx.clone(), &agent_id, &def_name),
+                        PORT_VALUE.to_string(),
+                        group,
+                    ) {
+                        log::error!("Failed to send aggregate output: {}", e);
+                    }
+                }
+            }
+            Some(deadline + period)
+        });
+        self.timer = Some(timer);
+        Ok(())
+    }
+
+    fn stop_timer(&mut self) {
+        // Dropping the timer cancels it
+        self.timer = None;
+    }
+}
+
+#[async_trait]
+impl AsAgent for AggregateAgent {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
+        Ok(Self {
+            data: AgentData::new(ma, id, spec),
+            contract,
+            events: Default::default(),
+            timer: None,
+        })
+    }
+
+    async fn start(&mut self) -> Result<(), AgentError> {
+        reset_task_restarts(self)?;
+        self.events.lock().unwrap().clear();
+        self.start_timer()
+    }
+
+    async fn stop(&mut self) -> Result<(), AgentError> {
+        self.stop_timer();
+        self.events.lock().unwrap().clear();
+        Ok(())
+    }
+
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()?;
+        if self.timer.is_some() {
+            self.stop_timer();
+            self.start_timer()?;
+        }
+        Ok(())
+    }
+
+    async fn process(
+        &mut self,
+        ctx: AgentContext,
+        port: String,
+        value: AgentValue,
+    ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
+        let tenant = tenant_key(&ctx, self.configs()?.get_bool_or_default(CONFIG_PER_TENANT));
+        self.events.lock().unwrap().push_back(WindowEvent {
+            time: Instant::now(),
+            tenant,
+            ctx,
+            value,
+        });
+        Ok(())
+    }
+}
+
+/// Masks or hashes sensitive content before it reaches displays, logs or files.
+///
+/// Values at the `keys` (comma separated key paths, applied to each element of arrays)
+/// are redacted entirely. In other strings, matches of the `patterns` (comma separated:
+/// email, phone, credit_card) and of the `regex` (one per line) are redacted.
+///
+/// In `mask` mode redacted content is replaced with `mask`. In `hash` mode it is replaced
+/// with the SHA-256 of `salt` and the content, so equal values can still be correlated.
+/// `salt` accepts `${env:NAME}` and `${profile:key}` placeholders.
+#[modular_agent(
+    title = "Redact",
+    category = CATEGORY,
+    inputs = [PORT_VALUE],
+    outputs = [PORT_VALUE],
+    string_config(name = CONFIG_KEYS, description = "comma separated key paths"),
+    string_config(name = CONFIG_PATTERNS, default = PATTERNS_DEFAULT, description = "email, phone, credit_card"),
+    text_config(name = CONFIG_REGEX, description = "one pattern per line"),
+    string_config(name = CONFIG_MODE, default = MODE_MASK, description = "mask, hash"),
+    string_config(name = CONFIG_MASK, default = MASK_DEFAULT),
+    string_config(name = CONFIG_SALT, detail),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
+)]
+struct RedactAgent {
+    data: AgentData,
+    contract: InputContract,
+    redactor: Redactor,
+}
+
+impl RedactAgent {
+    fn update_spec(spec: &AgentSpec) -> Result<Redactor, AgentError> {
+        let Some(config) = spec.configs.as_ref() else {
+            return Redactor::new("", PATTERNS_DEFAULT, "", MODE_MASK, MASK_DEFAULT, "");
+        };
+        Redactor::new(
+            &config.get_string_or_default(CONFIG_KEYS),
+            &config.get_string_or(CONFIG_PATTERNS, PATTERNS_DEFAULT),
+            &config.get_string_or_default(CONFIG_REGEX),
+            &config.get_string_or(CONFIG_MODE, MODE_MASK),
+            &config.get_string_or(CONFIG_MASK, MASK_DEFAULT),
+            &config.get_string_resolved(CONFIG_SALT)?,
+        )
+    }
+}
+
+#[async_trait]
+impl AsAgent for RedactAgent {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
+        let redactor = Self::update_spec(&spec)?;
+        Ok(Self {
+            data: AgentData::new(ma, id, spec),
+            contract,
+            redactor,
+        })
+    }
+
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()?;
+        self.redactor = Self::update_spec(&self.data.spec)?;
+        Ok(())
+    }
+
+    async fn process(
+        &mut self,
+        ctx: AgentContext,
+        port: String,
+        value: AgentValue,
+    ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
+        let value = self.redactor.redact(&value, &[]);
+        self.output(self.traced(ctx), PORT_VALUE, value).await
+    }
+}
+
+// Whether a match of a pattern is redacted.
+type AcceptMatch = fn(&str) -> bool;
+
+struct Redactor {
+    keys: Vec<Vec<String>>,
+    patterns: Vec<(Regex, AcceptMatch)>,
+    hash: bool,
+    mask: String,
+    salt: String,
+}
+
+impl Redactor {
+    fn new(
+        keys: &str,
+        patterns: &str,
+        regex: &str,
+        mode: &str,
+        mask: &str,
+        salt: &str,
+    ) -> Result<Self, AgentError> {
+        let keys = keys
+            .split(',')
+            .map(|k| k.trim())
+            .filter(|k| !k.is_empty())
+            .map(|k| k.split('.').map(|s| s.to_string()).collect())
+            .collect();
+
+        let names: Vec<&str> = patterns
+            .split(',')
+            .map(|p| p.trim())
+            .filter(|p| !p.is_empty())
+            .collect();
+        if let Some(name) = names
+            .iter()
+            .find(|name| !REDACT_PATTERNS.iter().any(|(n, _)| n == *name))
+        {
+            return Err(AgentError::InvalidConfig(format!(
+                "Unknown pattern: {}",
+                name
+            )));
+        }
+        let any: AcceptMatch = |_| true;
+        let mut sources: Vec<(&str, AcceptMatch)> = REDACT_PATTERNS
+            .iter()
+            .filter(|(n, _)| names.contains(n))
+            .map(|(n, re)| (*re, if *n == PHONE_PATTERN { is_phone } else { any }))
+            .collect();
+        sources.extend(
+            regex
+                .lines()
+                .map(|l| l.trim())
+                .filter(|l| !l.is_empty())
+                .map(|l| (l, any)),
+        );
+        let patterns = sources
+            .into_iter()
+            .map(|(re, accept)| {
+                Regex::new(re).map(|re| (re, accept)).map_err(|e| {
+                    AgentError::InvalidConfig(format!("Invalid regex '{}': {}", re, e))
+                })
+            })
+            .collect::<Result<_, _>>()?;
+
+        let hash = match mode.trim() {
+            "" | MODE_MASK => false,
+            MODE_HASH => true,
+            other => {
+                return Err(AgentError::InvalidConfig(format!(
+                    "Unknown mode: {}",
+                    other
+                )));
+            }
+        };
+
+        Ok(Self {
+            keys,
+            patterns,
+            hash,
+            mask: mask.to_string(),
+            salt: salt.to_string(),
+        })
+    }
+
+    fn redact(&self, value: &AgentValue, path: &[String]) -> AgentValue {
+        if !path.is_empty() && self.keys.iter().any(|k| k == path) {
+            let text = match value {
+                AgentValue::String(s) => s.to_string(),
+                v => serde_json::to_string(v).unwrap_or_default(),
+            };
+            return AgentValue::string(self.replacement(&text));
+        }
+        match value {
+            AgentValue::String(s) => AgentValue::string(self.redact_str(s)),
+            AgentValue::Array(arr) => {
+                AgentValue::array(arr.iter().map(|v| self.redact(v, path)).collect())
+            }
+            AgentValue::Object(obj) => AgentValue::object(
+                obj.iter()
+                    .map(|(k, v)| {
+                        let mut path = path.to_vec();
+                        path.push(k.clone());
+                        (k.clone(), self.redact(v, &path))
+                    })
+                    .collect(),
+            ),
+            other => other.clone(),
+        }
+    }
+
+    // All the patterns are matched against the original string, so that a pattern never
+    // matches the replacement of another.
+    fn redact_str(&self, s: &str) -> String {
+        let mut ranges: Vec<(usize, usize)> = self
+            .patterns
+            .iter()
+            .flat_map(|(re, accept)| {
+                re.find_iter(s)
+                    .filter(|m| accept(m.as_str()))
+                    .map(|m| (m.start(), m.end()))
+            })
+            .collect();
+        if ranges.is_empty() {
+            return s.to_string();
+        }
+        ranges.sort();
+        let mut merged: Vec<(usize, usize)> = Vec::new();
+        for (start, end) in ranges {
+            match merged.last_mut() {
+                Some(last) if start < last.1 => last.1 = last.1.max(end),
+                _ => merged.push((start, end)),
+            }
+        }
+        let mut out = String::with_capacity(s.len());
+        let mut pos = 0;
+        for (start, end) in merged {
+            out.push_str(&s[pos..start]);
+            out.push_str(&self.replacement(&s[start..end]));
+            pos = end;
+        }
+        out.push_str(&s[pos..]);
+        out
+    }
+
+    fn replacement(&self, text: &str) -> String {
+        if !self.hash {
+            return self.mask.clone();
+        }
+        let mut hasher = Sha256::new();
+        hasher.update(self.salt.as_bytes());
+        hasher.update(text.as_bytes());
+        hasher
+            .finalize()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+}
+
+// Transform
+/// Applies a transform spec to each input, for simple ETL without chains of agents.
+///
+/// The `spec` is a list of steps in YAML or JSON, applied in order. Each step is an object
+/// with one of the keys:
+///
+/// - `pick`: key paths to keep, ex. `{pick: [id, user.name]}`
+/// - `rename`: `{from: to}` key paths, moved all at once, or a list of them renamed in
+///   order, ex. `{rename: [{a: b}, {b: c}]}`
+/// - `default`: `{key: value}` set when the key is missing or null
+/// - `map`: `{key: {from: to}}` replaces values by a lookup table, others are kept
+/// - `filter`: an expression; the input is dropped when it is false
+/// - `compute`: `{key: expression}`; the expressions see the input before the step
+///
+/// Expressions work on the fields of the input, ex. `price * qty`, `lower(name)`,
+/// `status == "ok" && retries < 3`. Arrays are transformed element by element, and
+/// filtered elements are removed.
+#[modular_agent(
+    title = "Transform",
+    category = CATEGORY,
+    inputs = [PORT_VALUE],
+    outputs = [PORT_VALUE],
+    text_config(name = CONFIG_SPEC, description = "steps in YAML or JSON"),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
+)]
+struct TransformAgent {
+    data: AgentData,
+    steps: Vec<TransformStep>,
+    contract: InputContract,
+}
+
+#[async_trait]
+impl AsAgent for TransformAgent {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let steps = spec
+            .configs
+            .as_ref()
+            .map(|c| parse_transform(&c.get_string_or_default(CONFIG_SPEC)))
+            .transpose()?
+            .unwrap_or_default();
+        let contract = InputContract::update_spec(&mut spec)?;
+        Ok(Self {
+            data: AgentData::new(ma, id, spec),
+            steps,
+            contract,
+        })
+    }
+
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.steps = parse_transform(&self.configs()?.get_string_or_default(CONFIG_SPEC))?;
+        self.reload_contract()?;
+        Ok(())
+    }
+
+    async fn process(
+        &mut self,
+        ctx: AgentContext,
+        port: String,
+        value: AgentValue,
+    ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
+        let output = match value {
+            AgentValue::Array(arr) => {
+                let mut out = Vector::new();
+                for v in arr {
+                    if let Some(v) = transform(&self.steps, v)? {
+                        out.push_back(v);
+                    }
+                }
+                AgentValue::array(out)
+            }
+            v => match transform(&self.steps, v)? {
+                Some(v) => v,
+                None => return Ok(()),
+            },
+        };
+        self.output(self.traced(ctx), PORT_VALUE, output).await
+    }
+}
+
+enum TransformStep {
+    Pick(Vec<Vec<String>>),
+    // groups of renames, applied in order; the renames of a group are done at once
+    Rename(Vec<Vec<(Vec<String>, Vec<String>)>>),
+    Default(Vec<(Vec<String>, AgentValue)>),
+    Map(Vec<(Vec<String>, HashMap<String, AgentValue>)>),
+    Filter(Expr),
+    Compute(Vec<(Vec<String>, Expr)>),
+}
+
+fn parse_transform(spec: &str) -> Result<Vec<TransformStep>, AgentError> {
+    if spec.trim().is_empty() {
+        return Ok(Vec::new());
+    }
+    let json: serde_json::Value = match serde_json::from_str(spec) {
+        Ok(json) => json,
+        #[cfg(feature = "yaml")]
+        Err(_) => serde_yaml_ng::from_str(spec)
+            .map_err(|e| AgentError::InvalidConfig(format!("Invalid transform spec: {}", e)))?,
+        #[cfg(not(feature = "yaml"))]
+        Err(e) => {
+            return Err(AgentError::InvalidConfig(format!(
+                "Invalid transform spec: {}",
+                e
+            )));
+        }
+    };
+    let serde_json::Value::Array(steps) = json else {
+        return Err(AgentError::InvalidConfig(
+            "Transform spec must be a list of steps".into(),
+        ));
+    };
+
+    let path = |key: &str| -> Vec<String> { key.split('.').map(|s| s.to_string()).collect() };
+    let invalid = |step: &str| {
+        AgentError::InvalidConfig(format!("Invalid '{}' step in transform spec", step))
+    };
+    let mut parsed = Vec::new();
+    for step in steps {
+        let step = match step {
+            serde_json::Value::Object(obj) if obj.len() == 1 => obj,
+            step => {
+                return Err(AgentError::InvalidConfig(format!(
+                    "Each transform step must have one key: {}",
+                    step
+                )));
+            }
+        };
+        let (name, arg) = step.into_iter().next().unwrap();
+        let entries = || arg.as_object().ok_or_else(|| invalid(&name));
+        parsed.push(match name.as_str() {
+            "pick" => {
+                let keys = match &arg {
+                    serde_json::Value::String(key) => vec![path(key)],
+                    serde_json::Value::Array(keys) => keys
+                        .iter()
+                        .map(|k| k.as_str().map(path).ok_or_else(|| invalid(&name)))
+                        .collect::<Result<_, _>>()?,
+                    _ => return Err(invalid(&name)),
+                };
+                TransformStep::Pick(keys)
+            }
+            "rename" => {
+                let renames = |arg: &serde_json::Value| {
+                    arg.as_object()
+                        .ok_or_else(|| invalid(&name))?
+                        .iter()
+                        .map(|(from, to)| {
+                            Ok((path(from), path(to.as_str().ok_or_else(|| invalid(&name))?)))
+                        })
+                        .collect::<Result<Vec<_>, AgentError>>()
+                };
+                TransformStep::Rename(match &arg {
+                    serde_json::Value::Array(groups) => {
+                        groups.iter().map(renames).collect::<Result<_, _>>()?
+                    }
+                    arg => vec![renames(arg)?],
+                })
+            }
+            "default" => TransformStep::Default(
+                entries()?
+                    .iter()
+                    .map(|(k, v)| Ok((path(k), AgentValue::from_json(v.clone())?)))
+                    .collect::<Result<_, AgentError>>()?,
+            ),
+            "map" => TransformStep::Map(
+                entries()?
+                    .iter()
+                    .map(|(k, table)| {
+                        let table = table
+                            .as_object()
+                            .ok_or_else(|| invalid(&name))?
+                            .iter()
+                            .map(|(from, to)| {
+                                Ok((from.clone(), AgentValue::from_json(to.clone())?))
+                            })
+                            .collect::<Result<_, AgentError>>()?;
+                        Ok((path(k), table))
+                    })
+                    .collect::<Result<_, AgentError>>()?,
+            ),
+            "filter" => {
+                TransformStep::Filter(Expr::parse(arg.as_str().ok_or_else(|| invalid(&name))?)?)
+            }
+            "compute" => TransformStep::Compute(
+                entries()?
+                    .iter()
+                    .map(|(k, e)| {
+                        Ok((
+                            path(k),
+                            Expr::parse(e.as_str().ok_or_else(|| invalid(&name))?)?,
+                        ))
+                    })
+                    .collect::<Result<_, AgentError>>()?,
+            ),
+            other => {
+                return Err(AgentError::InvalidConfig(format!(
+                    "Unknown transform step: {}",
+                    other
+                )));
+            }
+        });
+    }
+    Ok(parsed)
+}
+
+// Returns None when the value is filtered out.
+fn transform(
+    steps: &[TransformStep],
+    mut value: AgentValue,
+) -> Result<Option<AgentValue>, AgentError> {
+    for step in steps {
+        match step {
+            TransformStep::Pick(keys) => {
+                let mut picked = AgentValue::object_default();
+                for key in keys {
+                    if let Some(v) = get_nested_value(&value, key) {
+                        set_nested_value(&mut picked, key, v.clone());
+                    }
+                }
+                value = picked;
+            }
+            TransformStep::Rename(groups) => {
+                for renames in groups {
+                    let taken = renames
+                        .iter()
+                        .filter_map(|(from, to)| Some((to, take_nested_value(&mut value, from)?)))
+                        .collect::<Vec<_>>();
+                    for (to, v) in taken {
+                        set_nested_value(&mut value, to, v);
+                    }
+                }
+            }
+            TransformStep::Default(defaults) => {
+                for (key, default) in defaults {
+                    if get_nested_value(&value, key).is_none_or(|v| v.is_unit()) {
+                        set_nested_value(&mut value, key, default.clone());
+                    }
+                }
+            }
+            TransformStep::Map(tables) => {
+                for (key, table) in tables {
+                    let mapped = get_nested_value(&value, key).and_then(|v| {
+                        let from = match v {
+                            AgentValue::String(s) => s.to_string(),
+                            v => v.to_json().to_string(),
+                        };
+                        table.get(&from).cloned()
+                    });
+                    if let Some(mapped) = mapped {
+                        set_nested_value(&mut value, key, mapped);
+                    }
+                }
+            }
+            TransformStep::Filter(expr) => {
+                if !truthy(&expr.eval(&value)?) {
+                    return Ok(None);
+                }
+            }
+            TransformStep::Compute(exprs) => {
+                let results = exprs
+                    .iter()
+                    .map(|(key, expr)| Ok((key, expr.eval(&value)?)))
+                    .collect::<Result<Vec<_>, AgentError>>()?;
+                for (key, v) in results {
+                    set_nested_value(&mut value, key, v);
+                }
+            }
+        }
+    }
+    Ok(Some(value))
+}
+
+// Envelopes
+//
+// An envelope carries a payload with its metadata, apart from the payload's own fields:
+// `{value, meta: {source, received_at, attempt, tags}}`. Any object with exactly the keys
+// `value` and `meta`, where `meta` is an object, is taken as an envelope.
+
+const KEY_ATTEMPT: &str = "attempt";
+const KEY_META: &str = "meta";
+const KEY_RECEIVED_AT: &str = "received_at";
+const KEY_SOURCE: &str = "source";
+const KEY_TAGS: &str = "tags";
+
+// Wrap
+/// Wraps the input value in an envelope with metadata.
+///
+/// `received_at` is the current time in milliseconds. Wrapping an envelope again keeps its
+/// payload and source (unless `source` is set), increments `attempt` and adds the tags.
+#[modular_agent(
+    title = "Wrap",
+    category = CATEGORY,
+    inputs = [PORT_VALUE],
+    outputs = [PORT_ENVELOPE],
+    string_config(name = CONFIG_SOURCE),
+    string_config(name = CONFIG_TAGS, description = "comma separated"),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
+)]
+struct WrapAgent {
+    data: AgentData,
+    contract: InputContract,
+}
+
+#[async_trait]
+impl AsAgent for WrapAgent {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
+        Ok(Self {
+            data: AgentData::new(ma, id, spec),
+            contract,
+        })
+    }
+
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
+    }
+
+    async fn process(
+        &mut self,
+        ctx: AgentContext,
+        port: String,
+        value: AgentValue,
+    ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
+        let config = self.configs()?;
+        let source = config.get_string_or_default(CONFIG_SOURCE);
+        let tags: Vec<String> = config
+            .get_string_or_default(CONFIG_TAGS)
+            .split(',')
+            .map(|t| t.trim().to_string())
+            .filter(|t| !t.is_empty())
+            .collect();
+        let envelope = wrap_envelope(value, &source, &tags, Utc::now().timestamp_millis());
+        self.output(self.traced(ctx), PORT_ENVELOPE, envelope).await
+    }
+}
+
+// Unwrap
+/// Outputs the payload of an envelope on `value` and its metadata on `meta`.
+///
+/// Values that are not envelopes are passed through on `value`.
+#[modular_agent(
+    title = "Unwrap",
+    category = CATEGORY,
+    inputs = [PORT_ENVELOPE],
+    outputs = [PORT_VALUE, PORT_META],
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
+)]
+struct UnwrapAgent {
+    data: AgentData,
+    contract: InputContract,
+}
+
+#[async_trait]
+impl AsAgent for UnwrapAgent {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
+        Ok(Self {
+            data: AgentData::new(ma, id, spec),
+            contract,
+        })
+    }
+
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
+    }
+
+    async fn process(
+        &mut self,
+        ctx: AgentContext,
+        port: String,
+        value: AgentValue,
+    ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
+        let Some((payload, meta)) = envelope_parts(&value) else {
+            return self.output(self.traced(ctx), PORT_VALUE, value).await;
+        };
+        let (payload, meta) = (payload.clone(), meta.clone());
+        self.output(self.traced(ctx.clone()), PORT_META, meta)
+            .await?;
+        self.output(self.traced(ctx), PORT_VALUE, payload).await
+    }
+}
+
+fn wrap_envelope(value: AgentValue, source: &str, tags: &[String], now: i64) -> AgentValue {
+    let (payload, mut meta) = match envelope_parts(&value) {
+        Some((payload, meta)) => (payload.clone(), meta.as_object().unwrap().clone()),
+        None => (value, HashMap::new()),
+    };
+
+    let attempt = meta.get(KEY_ATTEMPT).and_then(|v| v.as_i64()).unwrap_or(0) + 1;
+    meta.insert(KEY_ATTEMPT.to_string(), AgentValue::integer(attempt));
+    meta.insert(KEY_RECEIVED_AT.to_string(), AgentValue::integer(now));
+    if !source.is_empty() || !meta.contains_key(KEY_SOURCE) {
+        meta.insert(KEY_SOURCE.to_string(), AgentValue::string(source));
+    }
+    let mut all_tags = meta
+        .get(KEY_TAGS)
+        .and_then(|v| v.as_array())
+        .cloned()
+        .unwrap_or_default();
+    for tag in tags {
+        let tag = AgentValue::string(tag.as_str());
+        if !all_tags.contains(&tag) {
+            all_tags.push_back(tag);
+        }
+    }
+    meta.insert(KEY_TAGS.to_string(), AgentValue::array(all_tags));
+
+    let mut envelope = HashMap::new();
+    envelope.insert(PORT_VALUE.to_string(), payload);
+    envelope.insert(KEY_META.to_string(), AgentValue::object(meta));
+    AgentValue::object(envelope)
+}
+
+/// Returns the payload and the metadata object if `value` is an envelope.
+pub(crate) fn envelope_parts(value: &AgentValue) -> Option<(&AgentValue, &AgentValue)> {
+    let obj = value.as_object()?;
+    if obj.len() != 2 {
+        return None;
+    }
+    let meta = obj.get(KEY_META).filter(|meta| meta.is_object())?;
+    Some((obj.get(PORT_VALUE)?, meta))
+}
+
+/// Renders the metadata of an envelope on one line (ex. `camera #2 [a, b] 2026-01-01 12:00:00`).
+pub(crate) fn render_meta(meta: &AgentValue) -> String {
+    let mut parts = Vec::new();
+    if let Some(source) = meta.get_str(KEY_SOURCE).filter(|s| !s.is_empty()) {
+        parts.push(source.to_string());
+    }
+    if let Some(attempt) = meta.get_i64(KEY_ATTEMPT) {
+        parts.push(format!("#{}", attempt));
+    }
+    if let Some(tags) = meta.get(KEY_TAGS).and_then(|v| v.as_array())
+        && !tags.is_empty()
+    {
+        let tags: Vec<&str> = tags.iter().filter_map(|t| t.as_str()).collect();
+        parts.push(format!("[{}]", tags.join(", ")));
+    }
+    if let Some(time) = meta
+        .get_i64(KEY_RECEIVED_AT)
+        .and_then(DateTime::from_timestamp_millis)
+    {
+        parts.push(
+            time.with_timezone(&Local)
+                .format("%Y-%m-%d %H:%M:%S")
+                .to_string(),
+        );
+    }
+    parts.join(" ")
+}
+
+pub(crate) fn get_nested_value<'a, K: AsRef<str>>(
+    value: &'a AgentValue,
+    keys: &[K],
+) -> Option<&'a AgentValue> {
+    let mut current_value = value;
+    for key in keys {
+        let obj = current_value.as_object()?;
+        current_value = obj.get(key.as_ref())?;
+    }
+    Some(current_value)
+}
+
+fn set_nested_value<K: AsRef<str>>(root: &mut AgentValue, keys: &[K], new_value: AgentValue) {
+    if keys.is_empty() {
+        return;
+    }
+
+    // Split into the last key and the path before it
+    // keys = ["a", "b", "c"] -> path=["a", "b"], last_key="c"
+    let (last_key, path) = keys.split_last().unwrap();
+
+    let mut current = root;
+
+    // Traverse down to just before the target
+    for key in path {
+        // If current position is not an Object, forcibly overwrite it with an empty Object
+        if !current.is_object() {
+            *current = AgentValue::object_default();
+        }
+
+        let obj = current.as_object_mut().unwrap();
+
+        current = obj
+            .entry(key.as_ref().to_string())
+            .or_insert_with(AgentValue::object_default);
+    }
+
+    // Set the value for the last key
+    if !current.is_object() {
+        *current = AgentValue::object_default();
+    }
+
+    if let Some(obj) = current.as_object_mut() {
+        obj.insert(last_key.as_ref().to_string(), new_value);
+    }
+}
+
+fn take_nested_value<K: AsRef<str>>(root: &mut AgentValue, keys: &[K]) -> Option<AgentValue> {
+    let (last_key, path) = keys.split_last()?;
+    let mut current = root;
+    for key in path {
+        current = current.as_object_mut()?.get_mut(key.as_ref())?;
+    }
+    current.as_object_mut()?.remove(last_key.as_ref())
+}
+
+// A row of a pivot: the row key value, and the values of its cells by column, in
+// order of first appearance
+#[derive(Default)]
+struct PivotRow {
+    row: AgentValue,
+    cells: Vec<(String, Vec<AgentValue>)>,
+    // column -> index in cells
+    columns: HashMap<String, usize>,
+}
+
+fn pivot(
+    records: &Vector<AgentValue>,
+    row_key: &str,
+    column_key: &str,
+    value_key: &str,
+    agg: &str,
+) -> Result<Vector<AgentValue>, AgentError> {
+    let row_keys: Vec<&str> = row_key.split('.').collect();
+    let column_keys: Vec<&str> = column_key.split('.').collect();
+    let value_keys: Vec<&str> = if value_key.is_empty() {
+        Vec::new()
+    } else {
+        value_key.split('.').collect()
+    };
+
+    // rows in order of first appearance, and their index by row key value as JSON
+    let mut rows: Vec<PivotRow> = Vec::new();
+    let mut row_index: HashMap<String, usize> = HashMap::new();
+    for record in records {
+        let (Some(row), Some(column)) = (
+            get_nested_value(record, &row_keys),
+            get_nested_value(record, &column_keys),
+        ) else {
+            continue;
+        };
+        let Some(value) = get_nested_value(record, &value_keys) else {
+            continue;
+        };
+
+        let row_id = serde_json::to_string(row).unwrap_or_default();
+        let column = column
+            .as_str()
+            .map(|s| s.to_string())
+            .unwrap_or_else(|| serde_json::to_string(column).unwrap_or_default());
+
+        let idx = *row_index.entry(row_id).or_insert_with(|| {
+            rows.push(PivotRow {
+                row: row.clone(),
+                ..Default::default()
+            });
+            rows.len() - 1
+        });
+        let pivot_row = &mut rows[idx];
+        match pivot_row.columns.get(&column) {
+            Some(&i) => pivot_row.cells[i].1.push(value.clone()),
+            None => {
+                pivot_row
+                    .columns
+                    .insert(column.clone(), pivot_row.cells.len());
+                pivot_row.cells.push((column, vec![value.clone()]));
+            }
+        }
+    }
+
+    rows.into_iter()
+        .map(|pivot_row| {
+            let mut obj = HashMap::new();
+            for (column, values) in pivot_row.cells {
+                obj.insert(column, aggregate(&values, agg)?);
+            }
+            // a dotted row key is nested, like the key path it was read from
+            let mut obj = AgentValue::object(obj);
+            set_nested_value(&mut obj, &row_keys, pivot_row.row);
+            Ok(obj)
+        })
+        .collect()
+}
+
+fn unpivot(
+    records: &Vector<AgentValue>,
+    id_keys: &[&str],
+    column_name: &str,
+    value_name: &str,
+) -> Vector<AgentValue> {
+    let mut rows = Vector::new();
+    for record in records {
+        let Some(obj) = record.as_object() else {
+            continue;
+        };
+        let ids: HashMap<String, AgentValue> = obj
+            .iter()
+            .filter(|(k, _)| id_keys.contains(&k.as_str()))
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+
+        let mut fields: Vec<(&String, &AgentValue)> = obj
+            .iter()
+            .filter(|(k, _)| !id_keys.contains(&k.as_str()))
+            .collect();
+        fields.sort_by(|a, b| a.0.cmp(b.0));
+        for (k, v) in fields {
+            let mut row = ids.clone();
+            row.insert(column_name.to_string(), AgentValue::string(k.clone()));
+            row.insert(value_name.to_string(), v.clone());
+            rows.push_back(AgentValue::object(row));
+        }
+    }
+    rows
+}
+
+// Cells
+
+const MAX_CELL_ROWS: usize = 1 << 20;
+const MAX_CELL_COLS: usize = 1 << 14;
+const MAX_SET_CELLS: usize = 1 << 20;
+
+// 0-based (row, col), inclusive
+#[derive(Debug, PartialEq)]
+struct CellRange {
+    start: (usize, usize),
+    end: (usize, usize),
+}
+
+impl CellRange {
+    fn new(start: (usize, usize), end: (usize, usize)) -> Result<Self, AgentError> {
+        let range = CellRange {
+            start: (start.0.min(end.0), start.1.min(end.1)),
+            end: (start.0.max(end.0), start.1.max(end.1)),
+        };
+        if range.end.0 >= MAX_CELL_ROWS || range.end.1 >= MAX_CELL_COLS {
+            return Err(AgentError::InvalidConfig(format!(
+                "Cells are limited to {} rows and {} columns",
+                MAX_CELL_ROWS, MAX_CELL_COLS
+            )));
+        }
+        Ok(range)
+    }
+
+    fn num_cells(&self) -> usize {
+        (self.end.0 - self.start.0 + 1) * (self.end.1 - self.start.1 + 1)
+    }
+
+    fn is_cell(&self) -> bool {
+        self.start == self.end
+    }
+}
+
+// Parses `B2` or `$B$2` into 0-based (row, col).
+fn parse_cell(cell: &str) -> Option<(usize, usize)> {
+    let cell = cell.trim().replace('$', "").to_ascii_uppercase();
+    let digits = cell.find(|c: char| c.is_ascii_digit())?;
+    let (letters, digits) = cell.split_at(digits);
+    if letters.is_empty() || !letters.chars().all(|c| c.is_ascii_uppercase()) {
+        return None;
+    }
+    let col = letters.bytes().try_fold(0usize, |acc, b| {
+        acc.checked_mul(26)?.checked_add((b - b'A') as usize + 1)
+    })?;
+    let row = digits.parse::<usize>().ok().filter(|row| *row > 0)?;
+    Some((row - 1, col - 1))
+}
+
+fn parse_cell_range(cell_ref: &str) -> Result<CellRange, AgentError> {
+    let invalid = || AgentError::InvalidConfig(format!("Invalid cell reference: {}", cell_ref));
+    let (start, end) = match cell_ref.split_once(':') {
+        Some((start, end)) => (
+            parse_cell(start).ok_or_else(invalid)?,
+            parse_cell(end).ok_or_else(invalid)?,
+        ),
+        None => {
+            let cell = parse_cell(cell_ref).ok_or_else(invalid)?;
+            (cell, cell)
+        }
+    };
+    CellRange::new(start, end)
+}
+
+fn table_row(row: &AgentValue) -> Result<&Vector<AgentValue>, AgentError> {
+    row.as_array()
+        .ok_or_else(|| AgentError::InvalidArrayValue("Expected array of rows".into()))
+}
+
+fn get_cells(table: &Vector<AgentValue>, range: &CellRange) -> Result<AgentValue, AgentError> {
+    if range.is_cell() {
+        let (row, col) = range.start;
+        return match table.get(row) {
+            Some(cells) => Ok(table_row(cells)?.get(col).cloned().unwrap_or_default()),
+            None => Ok(AgentValue::unit()),
+        };
+    }
+    let mut rows = Vector::new();
+    for cells in table
+        .iter()
+        .skip(range.start.0)
+        .take(range.end.0 - range.start.0 + 1)
+    {
+        let cells = table_row(cells)?
+            .iter()
+            .skip(range.start.1)
+            .take(range.end.1 - range.start.1 + 1)
+            .cloned()
+            .collect();
+        rows.push_back(AgentValue::Array(cells));
+    }
+    Ok(AgentValue::Array(rows))
+}
+
+fn set_cells(
+    mut table: Vector<AgentValue>,
+    range: &CellRange,
+    value: AgentValue,
+) -> Result<Vector<AgentValue>, AgentError> {
+    if range.num_cells() > MAX_SET_CELLS {
+        return Err(AgentError::InvalidConfig(format!(
+            "Cannot set more than {} cells at once",
+            MAX_SET_CELLS
+        )));
+    }
+    while table.len() <= range.end.0 {
+        table.push_back(AgentValue::array_default());
+    }
+    for row in range.start.0..=range.end.0 {
+        let mut cells = table_row(&table[row])?.clone();
+        while cells.len() <= range.end.1 {
+            cells.push_back(AgentValue::unit());
+        }
+        for col in range.start.1..=range.end.1 {
+            cells.set(col, value.clone());
+        }
+        table.set(row, AgentValue::Array(cells));
+    }
+    Ok(table)
+}
+
+pub(crate) const AGG_FIRST: &str = "first";
+pub(crate) const AGG_LAST: &str = "last";
+pub(crate) const AGG_COUNT: &str = "count";
+pub(crate) const AGG_SUM: &str = "sum";
+pub(crate) const AGG_AVG: &str = "avg";
+pub(crate) const AGG_MIN: &str = "min";
+pub(crate) const AGG_MAX: &str = "max";
+pub(crate) const AGG_LIST: &str = "list";
+pub(crate) const AGG_DESCRIPTION: &str = "first, last, count, sum, avg, min, max, list";
+
+/// Combines values into one according to `agg`.
+///
+/// sum, avg, min and max use the numeric values only; sum stays an integer
+/// when every value is an integer. An empty input gives unit (0 for count and sum).
+pub(crate) fn aggregate(values: &[AgentValue], agg: &str) -> Result<AgentValue, AgentError> {
+    let numbers = || values.iter().filter_map(|v| v.as_f64());
+    let value = match agg.trim() {
+        AGG_FIRST => values.first().cloned().unwrap_or_default(),
+        "" | AGG_LAST => values.last().cloned().unwrap_or_default(),
+        AGG_COUNT => AgentValue::integer(values.len() as i64),
+        AGG_SUM => {
+            if values.iter().all(|v| v.is_integer()) {
+                AgentValue::integer(values.iter().filter_map(|v| v.as_i64()).sum())
+            } else {
+                AgentValue::number(numbers().sum())
+            }
+        }
+        AGG_AVG => {
+            let count = numbers().count();
+            if count == 0 {
+                AgentValue::unit()
+            } else {
+                AgentValue::number(numbers().sum::<f64>() / count as f64)
+            }
+        }
+        AGG_MIN => values
+            .iter()
+            .filter(|v| v.as_f64().is_some())
+            .min_by(|a, b| a.as_f64().unwrap().total_cmp(&b.as_f64().unwrap()))
+            .cloned()
+            .unwrap_or_default(),
+        AGG_MAX => values
+            .iter()
+            .filter(|v| v.as_f64().is_some())
+            .max_by(|a, b| a.as_f64().unwrap().total_cmp(&b.as_f64().unwrap()))
+            .cloned()
+            .unwrap_or_default(),
+        AGG_LIST => AgentValue::array(values.iter().cloned().collect()),
+        other => {
+            return Err(AgentError::InvalidConfig(format!(
+                "Unknown aggregation: {}",
+                other
+            )));
+        }
+    };
+    Ok(value)
+}
+
+/// Groups `values` by the value at `group_key` and aggregates the values at `value_key`.
+///
+/// Returns one `{group, value, count}` object per group, in order of first appearance.
+/// Values without `value_key` are skipped; a blank `group_key` puts all values in one
+/// group with a unit `group`.
+pub(crate) fn aggregate_groups(
+    values: &[AgentValue],
+    group_key: &str,
+    value_key: &str,
+    agg: &str,
+) -> Result<Vec<AgentValue>, AgentError> {
+    let group_keys: Vec<&str> = if group_key.is_empty() {
+        Vec::new()
+    } else {
+        group_key.split('.').collect()
+    };
+    let value_keys: Vec<&str> = if value_key.is_empty() {
+        Vec::new()
+    } else {
+        value_key.split('.').collect()
+    };
+
+    // groups in order of first appearance, and their index by group value as JSON
+    let mut groups: Vec<(AgentValue, Vec<AgentValue>)> = Vec::new();
+    let mut group_index: HashMap<String, usize> = HashMap::new();
+    for value in values {
+        let group = if group_keys.is_empty() {
+            AgentValue::unit()
+        } else {
+            match get_nested_value(value, &group_keys) {
+                Some(group) => group.clone(),
+                None => continue,
+            }
+        };
+        let Some(v) = get_nested_value(value, &value_keys) else {
+            continue;
+        };
+        let group_id = serde_json::to_string(&group).unwrap_or_default();
+        match group_index.get(&group_id) {
+            Some(&i) => groups[i].1.push(v.clone()),
+            None => {
+                group_index.insert(group_id, groups.len());
+                groups.push((group, vec![v.clone()]));
+            }
+        }
+    }
+
+    groups
+        .into_iter()
+        .map(|(group, vs)| {
+            let mut obj = HashMap::new();
+            obj.insert("group".to_string(), group);
+            obj.insert("value".to_string(), aggregate(&vs, agg)?);
+            obj.insert("count".to_string(), AgentValue::integer(vs.len() as i64));
+            Ok(AgentValue::object(obj))
+        })
+        .collect()
+}
+
+fn flatten_value(
+    value: &AgentValue,
+    prefix: Option<&str>,
+    sep: &str,
+    array_index: bool,
+    out: &mut HashMap<String, AgentValue>,
+) {
+    let join = |key: &str| match prefix {
+        Some(prefix) => format!("{}{}{}", prefix, sep, key),
+        None => key.to_string(),
+    };
+    match value {
+        // Empty containers are kept as values so that they survive unflatten
+        AgentValue::Object(obj) if !obj.is_empty() => {
+            for (k, v) in obj {
+                flatten_value(v, Some(&join(k)), sep, array_index, out);
+            }
+        }
+        AgentValue::Array(arr) if array_index && !arr.is_empty() => {
+            for (i, v) in arr.iter().enumerate() {
+                flatten_value(v, Some(&join(&i.to_string())), sep, array_index, out);
+            }
+        }
+        _ => {
+            if let Some(prefix) = prefix {
+                out.insert(prefix.to_string(), value.clone());
+            }
+        }
+    }
+}
+
+fn unflatten_value(flat: &HashMap<String, AgentValue>, sep: &str, array_index: bool) -> AgentValue {
+    let mut root = AgentValue::object_default();
+    for (k, v) in flat {
+        let keys: Vec<&str> = if sep.is_empty() {
+            vec![k.as_str()]
+        } else {
+            k.split(sep).collect()
+        };
+        set_nested_value(&mut root, &keys, v.clone());
+    }
+    if array_index {
+        indexed_objects_to_arrays(root)
+    } else {
+        root
+    }
+}
+
+// Converts objects with keys 0..n into arrays, recursively.
+fn indexed_objects_to_arrays(value: AgentValue) -> AgentValue {
+    let AgentValue::Object(obj) = value else {
+        return value;
+    };
+    let obj: HashMap<String, AgentValue> = obj
+        .into_iter()
+        .map(|(k, v)| (k, indexed_objects_to_arrays(v)))
+        .collect();
+
+    let is_indexed = !obj.is_empty()
+        && (0..obj.len()).all(|i| obj.contains_key(&i.to_string()))
+        && obj.keys().all(|k| k == "0" || !k.starts_with('0'));
+    if !is_indexed {
+        return AgentValue::Object(obj);
+    }
+
+    let arr: Vector<AgentValue> = (0..obj.len())
+        .map(|i| obj.get(&i.to_string()).cloned().unwrap())
+        .collect();
+    AgentValue::array(arr)
+}
+
+/// Returns the difference from `prev` to `curr`, or None if nothing changed.
+fn delta_value(prev: &AgentValue, curr: &AgentValue) -> Option<AgentValue> {
+    match (prev, curr) {
+        (AgentValue::Integer(p), AgentValue::Integer(c)) => {
+            (p != c).then(|| AgentValue::integer(c.wrapping_sub(*p)))
+        }
+        (
+            AgentValue::Integer(_) | AgentValue::Number(_),
+            AgentValue::Integer(_) | AgentValue::Number(_),
+        ) => {
+            let d = curr.as_f64()? - prev.as_f64()?;
+            (d != 0.0).then(|| AgentValue::number(d))
+        }
+        (AgentValue::Object(p), AgentValue::Object(c)) => {
+            let mut changed: HashMap<String, AgentValue> = c
+                .iter()
+                .filter(|(k, v)| p.get(*k) != Some(*v))
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect();
+            for k in p.keys() {
+                if !c.contains_key(k) {
+                    changed.insert(k.clone(), AgentValue::unit());
+                }
+            }
+            (!changed.is_empty()).then(|| AgentValue::object(changed))
+        }
+        _ => (prev != curr).then(|| curr.clone()),
+    }
+}
+
+/// Zips multiple inputs into an object.
+///
+/// The number of inputs n and keys are specified via configuration.
+///
+/// If n=2, it takes two inputs: in1 and in2. Once all inputs are present,
+/// it emits them as { key1: in1, key2: in2 }.
+///
+/// If in2 arrives repeatedly before in1, the in2 values are queued; when in1 arrives,
+/// they’re paired in order from the head of the queue and emitted. With `per tenant`,
+/// each tenant id in the context has its own queues.
+///
+/// When the `use_ctx` config is true, inputs are matched by context key (including map frames)
+/// so that mapped items zip correctly even when they interleave.
+///
+/// A blank key defaults to the name of the source pin connected to the input,
+/// or to the input name (in1, in2...) if there is none or the name is not unique.
+/// When `key_template` is set, it is rendered with each value to get its key instead
+/// (e.g. `{{id}}`), falling back to the key above if the result is empty. Two values
+/// with the same key are an error.
+#[modular_agent(
+    title = "ZipToObject",
+    category = CATEGORY,
+    inputs = [PORT_IN1, PORT_IN2],
+    outputs = [PORT_OBJECT],
+    integer_config(name = CONFIG_N, default = 2),
+    boolean_config(name = CONFIG_USE_CTX),
+    string_config(name = CONFIG_KEY_TEMPLATE),
+    integer_config(name = CONFIG_TTL_SECONDS, default = 60),
+    integer_config(name = CONFIG_CAPACITY, default = 1000),
+    boolean_config(name = CONFIG_PER_TENANT, title = "per tenant"),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
+)]
+struct ZipToObjectAgent {
+    data: AgentData,
+    contract: InputContract,
+    n: usize,
+    use_ctx: bool,
+    ttl_seconds: u64,
+    capacity: usize,
+
+    // Optimization: Pre-load and store key configuration (k1, k2...)
+    keys: Vec<String>,
+    // compiled key_template, if set
+    key_template: Option<Handlebars<'static>>,
+
+    // Source pin names connected to the inputs (in1 -> pin), looked up on start
+    source_pins: Arc<Mutex<HashMap<String, String>>>,
+    source_pins_task: Option<JoinHandle<()>>,
+
+    // For simple mode: FIFO queues by tenant id
+    queues: TenantMap<Vec<VecDeque<AgentValue>>>,
+
+    // For use_ctx mode: Cache with TTL
+    ctx_buffers: Cache<String, PendingZip>,
+}
+
+#[derive(Clone)]
+struct PendingZip {
+    values: Vec<Option<AgentValue>>,
+    count: usize,
+}
+
+impl ZipToObjectAgent {
+    fn update_spec(
+        spec: &mut AgentSpec,
+    ) -> Result<(usize, bool, u64, u64, Vec<String>), AgentError> {
+        let n = spec
+            .configs
+            .as_ref()
+            .map(|cfg| cfg.get_integer_or(CONFIG_N, 2))
+            .unwrap_or(2) as usize;
+        let n = if n < 1 { 1 } else { n };
+
+        let use_ctx = spec
+            .configs
+            .as_ref()
+            .map(|cfg| cfg.get_bool_or_default(CONFIG_USE_CTX))
+            .unwrap_or(false);
+
+        let ttl_sec = spec
+            .configs
+            .as_ref()
+            .map(|c| c.get_integer_or("ttl_seconds", 60))
+            .unwrap_or(60) as u64;
+
+        let capacity = spec
+            .configs
+            .as_ref()
+            .map(|c| c.get_integer_or("capacity", 1000))
+            .unwrap_or(1000) as u64;
+
+        // Dynamic generation of config definitions (ConfigSpecs)
+        let mut configs = AgentConfigs::new();
+        let mut config_specs = AgentConfigSpecs::default();
+
+        // Re-set required configurations
+        configs.set(CONFIG_N.to_string(), AgentValue::integer(n as i64));
+        let Some(n_spec) = spec
+            .config_specs
+            .as_ref()
+            .and_then(|cs| cs.get(CONFIG_N))
+            .cloned()
+        else {
+            return Err(AgentError::InvalidConfig("config n must be present".into()));
+        };
+        config_specs.insert(CONFIG_N.to_string(), n_spec);
+
+        let Some(use_ctx_spec) = spec
+            .config_specs
+            .as_ref()
+            .and_then(|cs| cs.get(CONFIG_USE_CTX))
+            .cloned()
+        else {
+            return Err(AgentError::InvalidConfig(
+                "config use_ctx must be present".into(),
+            ));
+        };
+        config_specs.insert(CONFIG_USE_CTX.to_string(), use_ctx_spec);
+
+        if let Some(cfg) = spec.configs.as_ref() {
+            configs.set(
+                CONFIG_KEY_TEMPLATE.to_string(),
+                AgentValue::string(cfg.get_string_or_default(CONFIG_KEY_TEMPLATE)),
+            );
+        }
+        if let Some(key_template_spec) = spec
+            .config_specs
+            .as_ref()
+            .and_then(|cs| cs.get(CONFIG_KEY_TEMPLATE))
+            .cloned()
+        {
+            config_specs.insert(CONFIG_KEY_TEMPLATE.to_string(), key_template_spec);
+        }
+
+        for key in [CONFIG_PER_TENANT, CONFIG_INPUT_CONTRACT] {
+            if let Some(value) = spec.configs.as_ref().and_then(|cfg| cfg.get(key).ok()) {
+                configs.set(key.to_string(), value.clone());
+            }
+            if let Some(key_spec) = spec
+                .config_specs
+                .as_ref()
+                .and_then(|cs| cs.get(key))
+                .cloned()
+            {
+                config_specs.insert(key.to_string(), key_spec);
+            }
+        }
+
+        let mut keys = Vec::with_capacity(n);
+        for i in 1..=n {
+            let key_name = format!("k{}", i);
+            let v = spec
+                .configs
+                .as_ref()
+                .map(|cfg| cfg.get_string_or_default(&key_name))
+                .unwrap_or_default();
+
+            keys.push(v.clone());
+
+            configs.set(key_name.clone(), AgentValue::string(v));
+            config_specs.insert(
+                key_name,
+                AgentConfigSpec {
+                    value: AgentValue::string_default(),
+                    type_: Some("string".to_string()),
+                    ..Default::default()
+                },
+            );
+        }
+
+        spec.configs = Some(configs);
+        spec.config_specs = Some(config_specs);
+
+        spec.inputs = Some((1..=n).map(|i| format!("in{}", i)).collect());
+
+        Ok((n as usize, use_ctx, ttl_sec, capacity, keys))
+    }
+
+    fn reset_state(&mut self) {
+        self.queues.clear();
+        self.ctx_buffers.invalidate_all();
+    }
+
+    fn key_template(spec: &AgentSpec) -> Result<Option<Handlebars<'static>>, AgentError> {
+        let key_template = spec
+            .configs
+            .as_ref()
+            .map(|cfg| cfg.get_string_or_default(CONFIG_KEY_TEMPLATE))
+            .unwrap_or_default();
+        if key_template.is_empty() {
+            return Ok(None);
+        }
+        let mut reg = handlebars_new();
+        reg.register_template_string(KEY_TEMPLATE_NAME, key_template)
+            .map_err(|e| AgentError::InvalidConfig(format!("Invalid key template: {}", e)))?;
+        Ok(Some(reg))
+    }
+
+    fn make_object(
+        &self,
+        values: impl IntoIterator<Item = AgentValue>,
+    ) -> Result<AgentValue, AgentError> {
+        let keys = {
+            let source_pins = self.source_pins.lock().unwrap();
+            let pins: Vec<Option<String>> = (1..=self.n)
+                .map(|i| source_pins.get(&format!("in{}", i)).cloned())
+                .collect();
+            resolve_zip_keys(&self.keys, &pins)
+        };
+        zip_object(keys, values, self.key_template.as_ref())
+    }
+}
+
+const KEY_TEMPLATE_NAME: &str = "key";
+
+// Builds the object of a zip. Keys are rendered with `key_template` when given, and a
+// key used twice is an error rather than overwriting the first value.
+fn zip_object(
+    keys: Vec<String>,
+    values: impl IntoIterator<Item = AgentValue>,
+    key_template: Option<&Handlebars<'static>>,
+) -> Result<AgentValue, AgentError> {
+    let mut map = HashMap::new();
+    for (k, v) in keys.into_iter().zip(values) {
+        let key = match key_template {
+            Some(reg) => reg.render(KEY_TEMPLATE_NAME, &v).map_err(|e| {
+                AgentError::InvalidValue(format!("Failed to render key template: {}", e))
+            })?,
+            None => String::new(),
+        };
+        let key = if key.is_empty() { k } else { key };
+        if map.contains_key(&key) {
+            return Err(AgentError::InvalidValue(format!(
+                "Duplicate key in zip: {}",
+                key
+            )));
+        }
+        map.insert(key, v);
+    }
+    Ok(AgentValue::Object(map))
+}
+
+// Resolves blank keys to the unique source pin name, or to the input name.
+fn resolve_zip_keys(keys: &[String], source_pins: &[Option<String>]) -> Vec<String> {
+    keys.iter()
+        .enumerate()
+        .map(|(i, k)| {
+            if !k.is_empty() {
+                return k.clone();
+            }
+            source_pins
+                .get(i)
+                .cloned()
+                .flatten()
+                .filter(|pin| {
+                    source_pins
+                        .iter()
+                        .filter(|p| p.as_ref() == Some(pin))
+                        .count()
+                        == 1
+                })
+                .unwrap_or_else(|| format!("in{}", i + 1))
+        })
+        .collect()
+}
+
+#[async_trait]
+impl AsAgent for ZipToObjectAgent {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let (n, use_ctx, ttl_sec, capacity, keys) = Self::update_spec(&mut spec)?;
+        let contract = InputContract::update_spec(&mut spec)?;
+        let key_template = Self::key_template(&spec)?;
         let cache = Cache::builder()
             .max_capacity(capacity)
             .time_to_live(Duration::from_secs(ttl_sec))
@@ -493,18 +2543,24 @@ impl AsAgent for ZipToObjectAgent {
         let data = AgentData::new(ma, id, spec);
         Ok(Self {
             data,
+            contract,
             n,
             use_ctx,
             ttl_seconds: ttl_sec,
             capacity: capacity as usize,
             keys,
-            queues: vec![VecDeque::new(); n],
+            key_template,
+            source_pins: Default::default(),
+            source_pins_task: None,
+            queues: TenantMap::default(),
             ctx_buffers: cache,
         })
     }
 
     fn configs_changed(&mut self) -> Result<(), AgentError> {
         let (n, use_ctx, ttl_sec, capacity, keys) = Self::update_spec(&mut self.data.spec)?;
+        self.reload_contract()?;
+        self.key_template = Self::key_template(&self.data.spec)?;
         let mut changed = false;
         if n != self.n {
             self.n = n;
@@ -535,12 +2591,437 @@ impl AsAgent for ZipToObjectAgent {
                 .build();
             self.emit_agent_spec_updated();
         }
-        Ok(())
+        Ok(())
+    }
+
+    async fn start(&mut self) -> Result<(), AgentError> {
+        // The preset is locked while its agents are starting,
+        // so look up the connections once it is released.
+        let ma = self.ma().clone();
+        let preset_id = self.preset_id().to_string();
+        let agent_id = self.id().to_string();
+        let source_pins = self.source_pins.clone();
+        let task = self.runtime().spawn(async move {
+            let Some(preset) = ma.get_preset(&preset_id) else {
+                return;
+            };
+            let pins = preset
+                .lock()
+                .await
+                .spec()
+                .connections
+                .iter()
+                .filter(|c| c.target == agent_id)
+                .map(|c| (c.target_handle.clone(), c.source_handle.clone()))
+                .collect();
+            *source_pins.lock().unwrap() = pins;
+        });
+        self.source_pins_task = Some(task);
+        Ok(())
+    }
+
+    async fn stop(&mut self) -> Result<(), AgentError> {
+        self.reset_state();
+        Ok(())
+    }
+
+    async fn process(
+        &mut self,
+        ctx: AgentContext,
+        port: String,
+        value: AgentValue,
+    ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
+        // Parse port number
+        let Some(idx) = port
+            .strip_prefix("in")
+            .and_then(|s| s.parse::<usize>().ok())
+            .filter(|&i| i >= 1 && i <= self.n)
+            .map(|i| i - 1)
+        else {
+            return Err(AgentError::InvalidValue(format!(
+                "Invalid input port: {}",
+                port
+            )));
+        };
+
+        // Values may arrive before the source pins are looked up
+        if let Some(task) = self.source_pins_task.take() {
+            let _ = task.await;
+        }
+
+        // Context Mode
+        if self.use_ctx {
+            let ctx_key = ctx.ctx_key()?;
+
+            let mut entry = self
+                .ctx_buffers
+                .get(&ctx_key)
+                .unwrap_or_else(|| PendingZip {
+                    values: vec![None; self.n],
+                    count: 0,
+                });
+
+            if entry.values[idx].is_none() {
+                entry.count += 1;
+            }
+            entry.values[idx] = Some(value);
+
+            if entry.count == self.n {
+                self.ctx_buffers.invalidate(&ctx_key);
+
+                // Zip keys and values, then collect
+                let object = self.make_object(entry.values.into_iter().map(|v| v.unwrap()))?;
+
+                return self.output(self.traced(ctx), PORT_OBJECT, object).await;
+            } else {
+                self.ctx_buffers.insert(ctx_key, entry);
+            }
+            return Ok(());
+        }
+
+        // Simple FIFO Mode
+        let tenant = tenant_key(&ctx, self.configs()?.get_bool_or_default(CONFIG_PER_TENANT));
+        if let Some(values) = self.queues.push_zip(tenant, self.n, idx, value) {
+            // Combine the heads with keys to create Map
+            let object = self.make_object(values)?;
+
+            self.output(self.traced(ctx), PORT_OBJECT, object).await
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Emits the first non-empty value among its inputs.
+///
+/// Values of in1..inN are paired like ZipToObject; once all are present, the first
+/// non-empty one is emitted on `value`. An array on the `array` pin is handled at once
+/// in the same way. If every value is empty, unit is emitted on `none`. With `per
+/// tenant`, each tenant id in the context has its own queues.
+///
+/// The `empty` config selects what counts as empty:
+/// - `unit`: unit only
+/// - `empty`: also empty strings, arrays and objects
+/// - `falsy`: also false and zero
+#[modular_agent(
+    title = "Coalesce",
+    category = CATEGORY,
+    inputs = [PORT_IN1, PORT_IN2, PORT_ARRAY],
+    outputs = [PORT_VALUE, PORT_NONE],
+    integer_config(name = CONFIG_N, default = 2),
+    string_config(name = CONFIG_EMPTY, default = EMPTY_RULE_UNIT, description = "unit, empty, falsy"),
+    boolean_config(name = CONFIG_USE_CTX),
+    integer_config(name = CONFIG_TTL_SECONDS, default = 60),
+    integer_config(name = CONFIG_CAPACITY, default = 1000),
+    boolean_config(name = CONFIG_PER_TENANT, title = "per tenant"),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
+)]
+struct CoalesceAgent {
+    data: AgentData,
+    contract: InputContract,
+    n: usize,
+
+    // For simple mode: FIFO queues by tenant id
+    queues: TenantMap<Vec<VecDeque<AgentValue>>>,
+
+    // For use_ctx mode: Cache with TTL
+    ctx_buffers: Cache<String, PendingZip>,
+}
+
+impl CoalesceAgent {
+    fn update_spec(spec: &mut AgentSpec) -> Result<(usize, u64, u64), AgentError> {
+        let n = spec
+            .configs
+            .as_ref()
+            .map(|cfg| cfg.get_integer_or(CONFIG_N, 2))
+            .unwrap_or(2)
+            .max(1) as usize;
+
+        let ttl_sec = spec
+            .configs
+            .as_ref()
+            .map(|cfg| cfg.get_integer_or(CONFIG_TTL_SECONDS, 60))
+            .unwrap_or(60) as u64;
+
+        let capacity = spec
+            .configs
+            .as_ref()
+            .map(|cfg| cfg.get_integer_or(CONFIG_CAPACITY, 1000))
+            .unwrap_or(1000) as u64;
+
+        let mut inputs: Vec<String> = (1..=n).map(|i| format!("in{}", i)).collect();
+        inputs.push(PORT_ARRAY.to_string());
+        spec.inputs = Some(inputs);
+
+        Ok((n, ttl_sec, capacity))
+    }
+
+    fn new_cache(ttl_sec: u64, capacity: u64) -> Cache<String, PendingZip> {
+        Cache::builder()
+            .max_capacity(capacity)
+            .time_to_live(Duration::from_secs(ttl_sec))
+            .build()
+    }
+
+    async fn output_first(
+        &mut self,
+        ctx: AgentContext,
+        values: impl IntoIterator<Item = AgentValue>,
+    ) -> Result<(), AgentError> {
+        let rule =
+            EmptyRule::from_str(&self.configs()?.get_string_or(CONFIG_EMPTY, EMPTY_RULE_UNIT))?;
+        match values.into_iter().find(|v| !rule.is_empty(v)) {
+            Some(value) => self.output(self.traced(ctx), PORT_VALUE, value).await,
+            None => {
+                self.output(self.traced(ctx), PORT_NONE, AgentValue::unit())
+                    .await
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl AsAgent for CoalesceAgent {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let (n, ttl_sec, capacity) = Self::update_spec(&mut spec)?;
+        let contract = InputContract::update_spec(&mut spec)?;
+        Ok(Self {
+            data: AgentData::new(ma, id, spec),
+            contract,
+            n,
+            queues: TenantMap::default(),
+            ctx_buffers: Self::new_cache(ttl_sec, capacity),
+        })
+    }
+
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        let (n, ttl_sec, capacity) = Self::update_spec(&mut self.data.spec)?;
+        self.reload_contract()?;
+        self.ctx_buffers = Self::new_cache(ttl_sec, capacity);
+        self.queues.clear();
+        if n != self.n {
+            self.n = n;
+            self.emit_agent_spec_updated();
+        }
+        Ok(())
+    }
+
+    async fn stop(&mut self) -> Result<(), AgentError> {
+        self.queues.clear();
+        self.ctx_buffers.invalidate_all();
+        Ok(())
+    }
+
+    async fn process(
+        &mut self,
+        ctx: AgentContext,
+        port: String,
+        value: AgentValue,
+    ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
+        if port == PORT_ARRAY {
+            let Some(arr) = value.into_array() else {
+                return Err(AgentError::InvalidArrayValue("Expected array".into()));
+            };
+            return self.output_first(ctx, arr).await;
+        }
+
+        let Some(idx) = port
+            .strip_prefix("in")
+            .and_then(|s| s.parse::<usize>().ok())
+            .filter(|&i| i >= 1 && i <= self.n)
+            .map(|i| i - 1)
+        else {
+            return Err(AgentError::InvalidPin(port));
+        };
+
+        // Context Mode
+        if self.configs()?.get_bool_or_default(CONFIG_USE_CTX) {
+            let ctx_key = ctx.ctx_key()?;
+
+            let mut entry = self
+                .ctx_buffers
+                .get(&ctx_key)
+                .unwrap_or_else(|| PendingZip {
+                    values: vec![None; self.n],
+                    count: 0,
+                });
+
+            if entry.values[idx].is_none() {
+                entry.count += 1;
+            }
+            entry.values[idx] = Some(value);
+
+            if entry.count < self.n {
+                self.ctx_buffers.insert(ctx_key, entry);
+                return Ok(());
+            }
+            self.ctx_buffers.invalidate(&ctx_key);
+            return self
+                .output_first(ctx, entry.values.into_iter().flatten())
+                .await;
+        }
+
+        // Simple FIFO Mode
+        let tenant = tenant_key(&ctx, self.configs()?.get_bool_or_default(CONFIG_PER_TENANT));
+        let Some(values) = self.queues.push_zip(tenant, self.n, idx, value) else {
+            return Ok(());
+        };
+        self.output_first(ctx, values).await
+    }
+}
+
+const CONSENSUS_EXACT: &str = "exact";
+const CONSENSUS_SIMILARITY: &str = "similarity";
+const CONSENSUS_SCORE: &str = "score";
+const THRESHOLD_DEFAULT: f64 = 0.6;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ConsensusMode {
+    Exact,
+    // minimum word overlap of similar answers
+    Similarity(f64),
+    Score,
+}
+
+impl ConsensusMode {
+    fn parse(mode: &str, threshold: f64) -> Result<Self, AgentError> {
+        match mode.trim() {
+            "" | CONSENSUS_EXACT => Ok(Self::Exact),
+            CONSENSUS_SIMILARITY => Ok(Self::Similarity(threshold)),
+            CONSENSUS_SCORE => Ok(Self::Score),
+            other => Err(AgentError::InvalidConfig(format!(
+                "Unknown mode: {}",
+                other
+            ))),
+        }
+    }
+}
+
+// The text of an answer to compare, ignoring case and surrounding spaces.
+fn answer_text(answer: Option<&AgentValue>) -> String {
+    match answer {
+        Some(AgentValue::String(s)) => s.trim().to_lowercase(),
+        Some(v) => v.to_json().to_string(),
+        None => String::new(),
+    }
+}
+
+// Jaccard similarity of the words of two texts.
+fn word_similarity(a: &str, b: &str) -> f64 {
+    let words = |s: &str| -> HashSet<String> {
+        s.split(|c: char| !c.is_alphanumeric())
+            .filter(|w| !w.is_empty())
+            .map(|w| w.to_string())
+            .collect()
+    };
+    let (a, b) = (words(a), words(b));
+    let union = a.union(&b).count();
+    if union == 0 {
+        return 1.0;
+    }
+    a.intersection(&b).count() as f64 / union as f64
+}
+
+/// Returns the index of the winning candidate and the number of candidates agreeing
+/// with it. Ties go to the earliest candidate.
+fn consensus(
+    candidates: &[AgentValue],
+    keys: &[String],
+    score_keys: &[String],
+    mode: ConsensusMode,
+) -> Result<Option<(usize, usize)>, AgentError> {
+    let texts: Vec<String> = candidates
+        .iter()
+        .map(|c| answer_text(get_nested_value(c, keys)))
+        .collect();
+    let votes = |i: usize| -> usize {
+        texts
+            .iter()
+            .filter(|t| match mode {
+                ConsensusMode::Similarity(threshold) => word_similarity(&texts[i], t) >= threshold,
+                _ => **t == texts[i],
+            })
+            .count()
+    };
+
+    if mode == ConsensusMode::Score {
+        let mut best: Option<(usize, f64)> = None;
+        for (i, c) in candidates.iter().enumerate() {
+            let Some(score) = get_nested_value(c, score_keys).and_then(|s| s.as_f64()) else {
+                continue;
+            };
+            if best.is_none_or(|(_, b)| score > b) {
+                best = Some((i, score));
+            }
+        }
+        return match best {
+            Some((i, _)) => Ok(Some((i, votes(i)))),
+            None if candidates.is_empty() => Ok(None),
+            None => Err(AgentError::InvalidValue(format!(
+                "No candidate has a score at '{}'",
+                score_keys.join(".")
+            ))),
+        };
+    }
+
+    let mut best: Option<(usize, usize)> = None;
+    for i in 0..candidates.len() {
+        let v = votes(i);
+        if best.is_none_or(|(_, b)| v > b) {
+            best = Some((i, v));
+        }
     }
+    Ok(best)
+}
 
-    async fn stop(&mut self) -> Result<(), AgentError> {
-        self.reset_state();
-        Ok(())
+/// Selects one answer out of candidates, such as several samples of the same prompt.
+///
+/// The input is an array of candidates, compared by the value at `key` (the whole
+/// candidate when blank). Depending on `mode`:
+///
+/// - `exact`: the most frequent answer wins. Strings are compared ignoring case and
+///   surrounding spaces
+/// - `similarity`: the answer similar to the most others wins. Answers are similar when
+///   the overlap of their words is at least `threshold` (0 to 1)
+/// - `score`: the candidate with the highest number at `score key` wins
+///
+/// The winning candidate is output on `value`, and its agreement on `agreement` as
+/// `{agreement, votes, total}`: the share and number of the candidates agreeing with it.
+/// Ties go to the earliest candidate. Nothing is output for an empty array.
+#[modular_agent(
+    title = "Consensus",
+    category = CATEGORY,
+    inputs = [PORT_ARRAY],
+    outputs = [PORT_VALUE, PORT_AGREEMENT],
+    string_config(name = CONFIG_MODE, default = CONSENSUS_EXACT, description = "exact, similarity, score"),
+    string_config(name = CONFIG_KEY),
+    number_config(name = CONFIG_THRESHOLD, default = THRESHOLD_DEFAULT, description = "similarity only"),
+    string_config(name = CONFIG_SCORE_KEY, title = "score key", description = "score only"),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
+)]
+struct ConsensusAgent {
+    data: AgentData,
+    contract: InputContract,
+}
+
+#[async_trait]
+impl AsAgent for ConsensusAgent {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
+        Ok(Self {
+            data: AgentData::new(ma, id, spec),
+            contract,
+        })
+    }
+
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
     }
 
     async fn process(
@@ -549,76 +3030,106 @@ impl AsAgent for ZipToObjectAgent {
         port: String,
         value: AgentValue,
     ) -> Result<(), AgentError> {
-        // Parse port number
-        let Some(idx) = port
-            .strip_prefix("in")
-            .and_then(|s| s.parse::<usize>().ok())
-            .filter(|&i| i >= 1 && i <= self.n)
-            .map(|i| i - 1)
-        else {
-            return Err(AgentError::InvalidValue(format!(
-                "Invalid input port: {}",
-                port
-            )));
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
         };
+        let config = self.configs()?;
+        let mode = ConsensusMode::parse(
+            &config.get_string_or(CONFIG_MODE, CONSENSUS_EXACT),
+            config.get_number_or(CONFIG_THRESHOLD, THRESHOLD_DEFAULT),
+        )?;
+        let key_path = |key: String| -> Vec<String> {
+            key.split('.')
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty())
+                .collect()
+        };
+        let keys = key_path(config.get_string_or_default(CONFIG_KEY));
+        let score_keys = key_path(config.get_string_or_default(CONFIG_SCORE_KEY));
 
-        // Context Mode
-        if self.use_ctx {
-            let ctx_key = ctx.ctx_key()?;
-
-            let mut entry = self
-                .ctx_buffers
-                .get(&ctx_key)
-                .unwrap_or_else(|| PendingZip {
-                    values: vec![None; self.n],
-                    count: 0,
-                });
+        let Some(candidates) = value.as_array() else {
+            return Err(AgentError::InvalidArrayValue("Expected array".into()));
+        };
+        let candidates: Vec<AgentValue> = candidates.iter().cloned().collect();
+        let Some((winner, votes)) = consensus(&candidates, &keys, &score_keys, mode)? else {
+            return Ok(());
+        };
 
-            if entry.values[idx].is_none() {
-                entry.count += 1;
-            }
-            entry.values[idx] = Some(value);
+        let total = candidates.len();
+        let mut agreement = HashMap::new();
+        agreement.insert(
+            "agreement".to_string(),
+            AgentValue::number(votes as f64 / total as f64),
+        );
+        agreement.insert("votes".to_string(), AgentValue::integer(votes as i64));
+        agreement.insert("total".to_string(), AgentValue::integer(total as i64));
+        self.output(
+            self.traced(ctx.clone()),
+            PORT_VALUE,
+            candidates[winner].clone(),
+        )
+        .await?;
+        self.output(
+            self.traced(ctx),
+            PORT_AGREEMENT,
+            AgentValue::object(agreement),
+        )
+        .await
+    }
+}
 
-            if entry.count == self.n {
-                self.ctx_buffers.invalidate(&ctx_key);
+const EMPTY_RULE_UNIT: &str = "unit";
+const EMPTY_RULE_EMPTY: &str = "empty";
+const EMPTY_RULE_FALSY: &str = "falsy";
 
-                // Zip keys and values, then collect
-                let map: HashMap<String, AgentValue> = self
-                    .keys
-                    .iter()
-                    .zip(entry.values.into_iter().map(|v| v.unwrap()))
-                    .map(|(k, v)| (k.clone(), v))
-                    .collect();
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum EmptyRule {
+    Unit,
+    Empty,
+    Falsy,
+}
 
-                return self.output(ctx, PORT_OBJECT, AgentValue::Object(map)).await;
-            } else {
-                self.ctx_buffers.insert(ctx_key, entry);
-            }
-            return Ok(());
+impl EmptyRule {
+    fn from_str(s: &str) -> Result<Self, AgentError> {
+        match s.trim() {
+            "" | EMPTY_RULE_UNIT => Ok(Self::Unit),
+            EMPTY_RULE_EMPTY => Ok(Self::Empty),
+            EMPTY_RULE_FALSY => Ok(Self::Falsy),
+            other => Err(AgentError::InvalidConfig(format!(
+                "Unknown empty rule: {}",
+                other
+            ))),
         }
+    }
 
-        // Simple FIFO Mode
-        self.queues[idx].push_back(value);
-
-        if self.queues.iter().all(|q| !q.is_empty()) {
-            // Take from head and combine with keys to create Map
-            let map: HashMap<String, AgentValue> = self
-                .keys
-                .iter()
-                .zip(self.queues.iter_mut())
-                .map(|(k, q)| (k.clone(), q.pop_front().unwrap()))
-                .collect();
-
-            self.output(ctx, PORT_OBJECT, AgentValue::Object(map)).await
-        } else {
-            Ok(())
+    fn is_empty(&self, value: &AgentValue) -> bool {
+        if value.is_unit() {
+            return true;
+        }
+        if *self == Self::Unit {
+            return false;
+        }
+        let empty = match value {
+            AgentValue::String(s) => s.is_empty(),
+            AgentValue::Array(arr) => arr.is_empty(),
+            AgentValue::Object(obj) => obj.is_empty(),
+            _ => false,
+        };
+        if empty || *self == Self::Empty {
+            return empty;
+        }
+        match value {
+            AgentValue::Boolean(b) => !b,
+            AgentValue::Integer(i) => *i == 0,
+            AgentValue::Number(n) => *n == 0.0,
+            _ => false,
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use im::hashmap;
+    use im::{hashmap, vector};
 
     use super::*;
 
@@ -742,4 +3253,677 @@ mod tests {
             })
         );
     }
+
+    #[test]
+    fn test_delta_value_numbers() {
+        assert_eq!(
+            delta_value(&AgentValue::integer(10), &AgentValue::integer(15)),
+            Some(AgentValue::integer(5))
+        );
+        assert_eq!(
+            delta_value(&AgentValue::integer(2), &AgentValue::number(3.5)),
+            Some(AgentValue::number(1.5))
+        );
+        assert_eq!(
+            delta_value(&AgentValue::integer(7), &AgentValue::integer(7)),
+            None
+        );
+        assert_eq!(
+            delta_value(&AgentValue::number(1.0), &AgentValue::integer(1)),
+            None
+        );
+    }
+
+    #[test]
+    fn test_delta_value_objects() {
+        let prev = AgentValue::object(hashmap! {
+            "a".to_string() => AgentValue::integer(1),
+            "b".to_string() => AgentValue::integer(2),
+            "c".to_string() => AgentValue::integer(3),
+        });
+        let curr = AgentValue::object(hashmap! {
+            "a".to_string() => AgentValue::integer(1),
+            "b".to_string() => AgentValue::integer(20),
+            "d".to_string() => AgentValue::integer(4),
+        });
+
+        // changed and added keys carry the new value, removed keys become unit
+        let expected = AgentValue::object(hashmap! {
+            "b".to_string() => AgentValue::integer(20),
+            "c".to_string() => AgentValue::unit(),
+            "d".to_string() => AgentValue::integer(4),
+        });
+        assert_eq!(delta_value(&prev, &curr), Some(expected));
+        assert_eq!(delta_value(&curr, &curr.clone()), None);
+    }
+
+    #[test]
+    fn test_delta_value_others() {
+        assert_eq!(
+            delta_value(&AgentValue::string("a"), &AgentValue::string("b")),
+            Some(AgentValue::string("b"))
+        );
+        assert_eq!(
+            delta_value(&AgentValue::string("a"), &AgentValue::string("a")),
+            None
+        );
+        assert_eq!(
+            delta_value(&AgentValue::integer(1), &AgentValue::string("1")),
+            Some(AgentValue::string("1"))
+        );
+    }
+
+    #[test]
+    fn test_resolve_zip_keys() {
+        let keys = vec![
+            "a".to_string(),
+            "".to_string(),
+            "".to_string(),
+            "".to_string(),
+            "".to_string(),
+        ];
+        let source_pins = vec![
+            Some("x".to_string()),
+            Some("name".to_string()),
+            Some("value".to_string()),
+            Some("value".to_string()),
+        ];
+
+        // explicit keys win, duplicated or missing pin names fall back to input names
+        assert_eq!(
+            resolve_zip_keys(&keys, &source_pins),
+            vec!["a", "name", "in3", "in4", "in5"]
+        );
+        assert_eq!(
+            resolve_zip_keys(&keys, &[]),
+            vec!["a", "in2", "in3", "in4", "in5"]
+        );
+    }
+
+    #[test]
+    fn test_zip_object() {
+        let keys = vec!["a".to_string(), "b".to_string()];
+        let values = || vec![AgentValue::integer(1), AgentValue::integer(2)];
+        assert_eq!(
+            zip_object(keys.clone(), values(), None).unwrap(),
+            AgentValue::object(hashmap! {
+                "a".into() => AgentValue::integer(1),
+                "b".into() => AgentValue::integer(2),
+            })
+        );
+
+        // a key used twice does not overwrite the first value
+        let keys_dup = vec!["a".to_string(), "a".to_string()];
+        assert!(zip_object(keys_dup, values(), None).is_err());
+
+        let mut reg = handlebars_new();
+        reg.register_template_string(KEY_TEMPLATE_NAME, "k{{this}}")
+            .unwrap();
+        assert_eq!(
+            zip_object(keys.clone(), values(), Some(&reg)).unwrap(),
+            AgentValue::object(hashmap! {
+                "k1".into() => AgentValue::integer(1),
+                "k2".into() => AgentValue::integer(2),
+            })
+        );
+        reg.register_template_string(KEY_TEMPLATE_NAME, "same")
+            .unwrap();
+        assert!(zip_object(keys, values(), Some(&reg)).is_err());
+    }
+
+    #[test]
+    fn test_flatten_value() {
+        let value = AgentValue::object(hashmap! {
+            "a".to_string() => AgentValue::object(hashmap! {
+                "b".to_string() => AgentValue::integer(1),
+                "c".to_string() => AgentValue::array(vector![
+                    AgentValue::integer(2),
+                    AgentValue::object(hashmap! {
+                        "d".to_string() => AgentValue::integer(3),
+                    }),
+                ]),
+            }),
+            "e".to_string() => AgentValue::object_default(),
+        });
+
+        let mut flat = HashMap::new();
+        flatten_value(&value, None, ".", true, &mut flat);
+        assert_eq!(
+            flat,
+            hashmap! {
+                "a.b".to_string() => AgentValue::integer(1),
+                "a.c.0".to_string() => AgentValue::integer(2),
+                "a.c.1.d".to_string() => AgentValue::integer(3),
+                "e".to_string() => AgentValue::object_default(),
+            }
+        );
+        assert_eq!(unflatten_value(&flat, ".", true), value);
+
+        // arrays are kept as values without array_index
+        let mut flat = HashMap::new();
+        flatten_value(&value, None, "/", false, &mut flat);
+        assert_eq!(flat.len(), 3);
+        assert_eq!(flat.get("a/c"), value.get("a").and_then(|a| a.get("c")));
+        assert_eq!(unflatten_value(&flat, "/", false), value);
+    }
+
+    #[test]
+    fn test_unflatten_value_indexed_keys() {
+        let flat = hashmap! {
+            "a.0".to_string() => AgentValue::integer(1),
+            "a.1".to_string() => AgentValue::integer(2),
+            "b.0".to_string() => AgentValue::integer(1),
+            "b.2".to_string() => AgentValue::integer(3),
+        };
+
+        // only complete 0..n keys become arrays
+        assert_eq!(
+            unflatten_value(&flat, ".", true),
+            AgentValue::object(hashmap! {
+                "a".to_string() => AgentValue::array(vector![
+                    AgentValue::integer(1),
+                    AgentValue::integer(2),
+                ]),
+                "b".to_string() => AgentValue::object(hashmap! {
+                    "0".to_string() => AgentValue::integer(1),
+                    "2".to_string() => AgentValue::integer(3),
+                }),
+            })
+        );
+        assert!(
+            unflatten_value(&flat, ".", false)
+                .get("a")
+                .unwrap()
+                .is_object()
+        );
+    }
+
+    #[test]
+    fn test_empty_rule() {
+        let values = [
+            AgentValue::unit(),
+            AgentValue::string(""),
+            AgentValue::array_default(),
+            AgentValue::object_default(),
+            AgentValue::boolean(false),
+            AgentValue::integer(0),
+            AgentValue::number(0.0),
+            AgentValue::string("a"),
+            AgentValue::integer(1),
+        ];
+        let count_empty = |rule: EmptyRule| values.iter().filter(|v| rule.is_empty(v)).count();
+
+        assert_eq!(count_empty(EmptyRule::from_str("unit").unwrap()), 1);
+        assert_eq!(count_empty(EmptyRule::from_str("empty").unwrap()), 4);
+        assert_eq!(count_empty(EmptyRule::from_str("falsy").unwrap()), 7);
+        assert!(EmptyRule::from_str("null").is_err());
+    }
+
+    fn record(pairs: &[(&str, AgentValue)]) -> AgentValue {
+        AgentValue::object(
+            pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.clone()))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_cells() {
+        assert_eq!(parse_cell("A1"), Some((0, 0)));
+        assert_eq!(parse_cell("$b$2"), Some((1, 1)));
+        assert_eq!(parse_cell("AA10"), Some((9, 26)));
+        assert_eq!(parse_cell("A0"), None);
+        assert_eq!(parse_cell("12"), None);
+        assert_eq!(parse_cell("A1B"), None);
+        assert_eq!(
+            parse_cell_range("C3:A2").unwrap(),
+            CellRange {
+                start: (1, 0),
+                end: (2, 2)
+            }
+        );
+        assert!(parse_cell_range("A1:").is_err());
+        assert!(parse_cell_range("XFD1048576").is_ok());
+        assert!(parse_cell_range("A1048577").is_err());
+        assert!(parse_cell_range("XFE1").is_err());
+
+        let row = |cells: &[i64]| {
+            AgentValue::array(cells.iter().map(|n| AgentValue::integer(*n)).collect())
+        };
+        let table = vector![row(&[1, 2, 3]), row(&[4, 5]), row(&[7, 8, 9])];
+
+        let get = |r: &str| get_cells(&table, &parse_cell_range(r).unwrap()).unwrap();
+        assert_eq!(get("B2"), AgentValue::integer(5));
+        assert_eq!(get("C2"), AgentValue::unit());
+        assert_eq!(get("A9"), AgentValue::unit());
+        assert_eq!(
+            get("B2:C3"),
+            AgentValue::array(vector![row(&[5]), row(&[8, 9])])
+        );
+        assert_eq!(get("A3:C9"), AgentValue::array(vector![row(&[7, 8, 9])]));
+
+        let set = set_cells(
+            table.clone(),
+            &parse_cell_range("C2").unwrap(),
+            AgentValue::integer(6),
+        )
+        .unwrap();
+        assert_eq!(set[1], row(&[4, 5, 6]));
+        assert!(
+            set_cells(
+                table.clone(),
+                &parse_cell_range("A1:XFD1048576").unwrap(),
+                AgentValue::integer(0),
+            )
+            .is_err()
+        );
+
+        let set = set_cells(
+            vector![row(&[1])],
+            &parse_cell_range("B2:B3").unwrap(),
+            AgentValue::integer(0),
+        )
+        .unwrap();
+        assert_eq!(
+            set,
+            vector![
+                row(&[1]),
+                AgentValue::array(vector![AgentValue::unit(), AgentValue::integer(0)]),
+                AgentValue::array(vector![AgentValue::unit(), AgentValue::integer(0)]),
+            ]
+        );
+
+        assert!(
+            get_cells(
+                &vector![AgentValue::integer(1)],
+                &parse_cell_range("A1").unwrap()
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_pivot_and_unpivot() {
+        let long = vector![
+            record(&[
+                ("day", AgentValue::string("mon")),
+                ("item", AgentValue::string("apple")),
+                ("qty", AgentValue::integer(1)),
+            ]),
+            record(&[
+                ("day", AgentValue::string("mon")),
+                ("item", AgentValue::string("pear")),
+                ("qty", AgentValue::integer(2)),
+            ]),
+            record(&[
+                ("day", AgentValue::string("tue")),
+                ("item", AgentValue::string("apple")),
+                ("qty", AgentValue::integer(3)),
+            ]),
+            record(&[
+                ("day", AgentValue::string("mon")),
+                ("item", AgentValue::string("apple")),
+                ("qty", AgentValue::integer(4)),
+            ]),
+        ];
+
+        let wide = pivot(&long, "day", "item", "qty", "sum").unwrap();
+        assert_eq!(
+            wide,
+            vector![
+                record(&[
+                    ("day", AgentValue::string("mon")),
+                    ("apple", AgentValue::integer(5)),
+                    ("pear", AgentValue::integer(2)),
+                ]),
+                record(&[
+                    ("day", AgentValue::string("tue")),
+                    ("apple", AgentValue::integer(3)),
+                ]),
+            ]
+        );
+
+        // a dotted row key is read from and written to a nested object
+        let nested: Vector<AgentValue> = long
+            .iter()
+            .map(|r| {
+                let mut r = r.clone();
+                let day = take_nested_value(&mut r, &["day"]).unwrap();
+                set_nested_value(&mut r, &["date", "day"], day);
+                r
+            })
+            .collect();
+        let wide_nested = pivot(&nested, "date.day", "item", "qty", "sum").unwrap();
+        assert_eq!(
+            wide_nested[1],
+            AgentValue::object(hashmap! {
+                "date".into() => record(&[("day", AgentValue::string("tue"))]),
+                "apple".into() => AgentValue::integer(3),
+            })
+        );
+
+        let back = unpivot(&wide, &["day"], "item", "qty");
+        assert_eq!(
+            back,
+            vector![
+                record(&[
+                    ("day", AgentValue::string("mon")),
+                    ("item", AgentValue::string("apple")),
+                    ("qty", AgentValue::integer(5)),
+                ]),
+                record(&[
+                    ("day", AgentValue::string("mon")),
+                    ("item", AgentValue::string("pear")),
+                    ("qty", AgentValue::integer(2)),
+                ]),
+                record(&[
+                    ("day", AgentValue::string("tue")),
+                    ("item", AgentValue::string("apple")),
+                    ("qty", AgentValue::integer(3)),
+                ]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_aggregate() {
+        let values = [
+            AgentValue::integer(3),
+            AgentValue::number(1.5),
+            AgentValue::string("x"),
+        ];
+        assert_eq!(aggregate(&values, "first").unwrap(), AgentValue::integer(3));
+        assert_eq!(aggregate(&values, "last").unwrap(), AgentValue::string("x"));
+        assert_eq!(aggregate(&values, "count").unwrap(), AgentValue::integer(3));
+        assert_eq!(aggregate(&values, "sum").unwrap(), AgentValue::number(4.5));
+        assert_eq!(aggregate(&values, "avg").unwrap(), AgentValue::number(2.25));
+        assert_eq!(aggregate(&values, "min").unwrap(), AgentValue::number(1.5));
+        assert_eq!(aggregate(&values, "max").unwrap(), AgentValue::integer(3));
+        assert_eq!(
+            aggregate(&values[..1], "sum").unwrap(),
+            AgentValue::integer(3)
+        );
+        assert_eq!(aggregate(&[], "avg").unwrap(), AgentValue::unit());
+        assert!(aggregate(&values, "median").is_err());
+    }
+
+    #[test]
+    fn test_aggregate_groups() {
+        let values = [
+            record(&[
+                ("host", AgentValue::string("a")),
+                ("ms", AgentValue::integer(10)),
+            ]),
+            record(&[
+                ("host", AgentValue::string("b")),
+                ("ms", AgentValue::integer(30)),
+            ]),
+            record(&[("host", AgentValue::string("a"))]),
+            record(&[
+                ("host", AgentValue::string("a")),
+                ("ms", AgentValue::integer(20)),
+            ]),
+            record(&[("ms", AgentValue::integer(40))]),
+        ];
+
+        let groups = aggregate_groups(&values, "host", "ms", "avg").unwrap();
+        assert_eq!(
+            groups,
+            vec![
+                record(&[
+                    ("group", AgentValue::string("a")),
+                    ("value", AgentValue::number(15.0)),
+                    ("count", AgentValue::integer(2)),
+                ]),
+                record(&[
+                    ("group", AgentValue::string("b")),
+                    ("value", AgentValue::number(30.0)),
+                    ("count", AgentValue::integer(1)),
+                ]),
+            ]
+        );
+
+        let groups = aggregate_groups(&values, "", "ms", "max").unwrap();
+        assert_eq!(
+            groups,
+            vec![record(&[
+                ("group", AgentValue::unit()),
+                ("value", AgentValue::integer(40)),
+                ("count", AgentValue::integer(4)),
+            ])]
+        );
+    }
+
+    #[test]
+    fn test_redactor() {
+        let value = AgentValue::object(hashmap! {
+            "user".to_string() => AgentValue::object(hashmap! {
+                "name".to_string() => AgentValue::string("Alice"),
+                "id".to_string() => AgentValue::integer(42),
+            }),
+            "note".to_string() => AgentValue::array(vector![AgentValue::string(
+                "mail alice@example.com or call +1 555-123-4567, card 4111 1111 1111 1111"
+            )]),
+        });
+
+        let redactor = Redactor::new(
+            "user.name, user.id",
+            PATTERNS_DEFAULT,
+            "",
+            "mask",
+            "***",
+            "",
+        )
+        .unwrap();
+        assert_eq!(
+            redactor.redact(&value, &[]),
+            AgentValue::object(hashmap! {
+                "user".to_string() => AgentValue::object(hashmap! {
+                    "name".to_string() => AgentValue::string("***"),
+                    "id".to_string() => AgentValue::string("***"),
+                }),
+                "note".to_string() => AgentValue::array(vector![AgentValue::string(
+                    "mail *** or call ***, card ***"
+                )]),
+            })
+        );
+
+        // dates, IP addresses and short numbers are not phone numbers
+        let redactor = Redactor::new("", "phone", "", "mask", "***", "").unwrap();
+        assert_eq!(
+            redactor.redact_str("on 2024-01-15 from 192.168.100.200, call (555) 123-4567"),
+            "on 2024-01-15 from 192.168.100.200, call ***"
+        );
+        assert_eq!(
+            redactor.redact_str("order 12345 on 15.01.2024"),
+            "order 12345 on 15.01.2024"
+        );
+
+        // a pattern does not match the replacement of another
+        let redactor =
+            Redactor::new("", "", "[0-9a-f]{8}\nsecret-\\d+", "hash", "", "salt").unwrap();
+        assert_eq!(
+            redactor.redact_str("secret-1"),
+            redactor.replacement("secret-1")
+        );
+
+        let redactor = Redactor::new("", "", r"secret-\d+", "hash", "", "salt").unwrap();
+        let a = redactor.redact_str("id secret-1");
+        assert_eq!(a.len(), "id ".len() + 64);
+        assert_eq!(redactor.redact_str("secret-1"), a["id ".len()..]);
+        assert_ne!(redactor.redact_str("secret-2"), a["id ".len()..]);
+
+        assert!(Redactor::new("", "ssn", "", "mask", "***", "").is_err());
+        assert!(Redactor::new("", "", "(", "mask", "***", "").is_err());
+        assert!(Redactor::new("", "", "", "encrypt", "***", "").is_err());
+    }
+
+    #[test]
+    fn test_wrap_envelope() {
+        let payload = AgentValue::object(hashmap! {"meta".into() => AgentValue::integer(1)});
+        let envelope = wrap_envelope(payload.clone(), "cam", &["a".to_string()], 1_000);
+        let (value, meta) = envelope_parts(&envelope).unwrap();
+        assert_eq!(value, &payload);
+        assert_eq!(meta.get_str(KEY_SOURCE), Some("cam"));
+        assert_eq!(meta.get_i64(KEY_ATTEMPT), Some(1));
+        assert_eq!(meta.get_i64(KEY_RECEIVED_AT), Some(1_000));
+        // a payload with its own meta field is not an envelope
+        assert!(envelope_parts(&payload).is_none());
+
+        // wrapping again keeps the payload and source, and merges the tags
+        let envelope = wrap_envelope(envelope, "", &["a".to_string(), "b".to_string()], 2_000);
+        let (value, meta) = envelope_parts(&envelope).unwrap();
+        assert_eq!(value, &payload);
+        assert_eq!(meta.get_str(KEY_SOURCE), Some("cam"));
+        assert_eq!(meta.get_i64(KEY_ATTEMPT), Some(2));
+        assert_eq!(meta.get_i64(KEY_RECEIVED_AT), Some(2_000));
+        assert_eq!(
+            meta.get(KEY_TAGS).unwrap(),
+            &AgentValue::array(vector![AgentValue::string("a"), AgentValue::string("b")])
+        );
+        assert!(render_meta(meta).starts_with("cam #2 [a, b] "));
+    }
+
+    #[test]
+    fn test_consensus() {
+        let answers = |xs: &[&str]| -> Vec<AgentValue> {
+            xs.iter()
+                .map(|x| {
+                    let mut obj = HashMap::new();
+                    obj.insert("answer".to_string(), AgentValue::string(*x));
+                    obj.insert("score".to_string(), AgentValue::number(x.len() as f64));
+                    AgentValue::object(obj)
+                })
+                .collect()
+        };
+        let keys = vec!["answer".to_string()];
+        let score_keys = vec!["score".to_string()];
+
+        let candidates = answers(&["Paris", "London", " paris ", "Rome"]);
+        assert_eq!(
+            consensus(&candidates, &keys, &score_keys, ConsensusMode::Exact).unwrap(),
+            Some((0, 2))
+        );
+        // the earliest of tied answers wins
+        let candidates = answers(&["a", "b", "b", "a"]);
+        assert_eq!(
+            consensus(&candidates, &keys, &score_keys, ConsensusMode::Exact).unwrap(),
+            Some((0, 2))
+        );
+
+        let candidates = answers(&[
+            "the answer is 42",
+            "it is 7",
+            "the answer is 42.",
+            "answer is 42",
+        ]);
+        assert_eq!(
+            consensus(
+                &candidates,
+                &keys,
+                &score_keys,
+                ConsensusMode::Similarity(0.7)
+            )
+            .unwrap(),
+            Some((0, 3))
+        );
+
+        let candidates = answers(&["ab", "abcd", "abc"]);
+        assert_eq!(
+            consensus(&candidates, &keys, &score_keys, ConsensusMode::Score).unwrap(),
+            Some((1, 1))
+        );
+        assert!(consensus(&candidates, &keys, &keys, ConsensusMode::Score).is_err());
+        assert_eq!(
+            consensus(&[], &keys, &score_keys, ConsensusMode::Exact).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_transform() {
+        let spec = r#"
+- filter: status != "skip"
+- rename: {user.name: name}
+- default: {qty: 1}
+- map: {status: {"1": ok, "0": failed}}
+- compute: {total: price * qty, label: upper(name) + ":" + status}
+- pick: [name, status, total, label]
+"#;
+        let steps = parse_transform(spec).unwrap();
+        let record = |status: AgentValue, qty: Option<i64>| {
+            let mut obj = hashmap! {
+                "user".to_string() => AgentValue::object(hashmap! {
+                    "name".to_string() => AgentValue::string("ada"),
+                }),
+                "status".to_string() => status,
+                "price".to_string() => AgentValue::integer(5),
+            };
+            if let Some(qty) = qty {
+                obj.insert("qty".to_string(), AgentValue::integer(qty));
+            }
+            AgentValue::object(obj)
+        };
+
+        let out = transform(&steps, record(AgentValue::integer(1), Some(3)))
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            out,
+            AgentValue::object(hashmap! {
+                "name".to_string() => AgentValue::string("ada"),
+                "status".to_string() => AgentValue::string("ok"),
+                "total".to_string() => AgentValue::integer(15),
+                "label".to_string() => AgentValue::string("ADA:ok"),
+            })
+        );
+        let out = transform(&steps, record(AgentValue::integer(0), None))
+            .unwrap()
+            .unwrap();
+        assert_eq!(out.get_i64("total"), Some(5));
+        assert_eq!(out.get_str("status"), Some("failed"));
+        assert!(
+            transform(&steps, record(AgentValue::string("skip"), None))
+                .unwrap()
+                .is_none()
+        );
+
+        // JSON specs work too
+        let steps = parse_transform(r#"[{"compute": {"a.b": "1 + 1"}}]"#).unwrap();
+        let out = transform(&steps, AgentValue::object_default())
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            get_nested_value(&out, &["a", "b"]),
+            Some(&AgentValue::integer(2))
+        );
+
+        // renames in a list are done in order, those of an object at once
+        let value = AgentValue::object(hashmap! {
+            "a".to_string() => AgentValue::integer(1),
+            "b".to_string() => AgentValue::integer(2),
+        });
+        let steps = parse_transform("- rename: [{a: x}, {x: y}, {b: z}]").unwrap();
+        assert_eq!(
+            transform(&steps, value.clone()).unwrap().unwrap(),
+            AgentValue::object(hashmap! {
+                "y".to_string() => AgentValue::integer(1),
+                "z".to_string() => AgentValue::integer(2),
+            })
+        );
+        let steps = parse_transform("- rename: {a: b, b: a}").unwrap();
+        assert_eq!(
+            transform(&steps, value).unwrap().unwrap(),
+            AgentValue::object(hashmap! {
+                "a".to_string() => AgentValue::integer(2),
+                "b".to_string() => AgentValue::integer(1),
+            })
+        );
+
+        assert!(parse_transform("{pick: a}").is_err());
+        assert!(parse_transform("- explode: a").is_err());
+        assert!(parse_transform("- filter: a +").is_err());
+        assert!(parse_transform("- {pick: a, rename: {a: b}}").is_err());
+    }
 }
@@ -1,18 +1,227 @@
+use std::fs;
+use std::path::Path;
 use std::vec;
 
-use im::hashmap;
+use im::{HashMap, Vector, hashmap};
 use modular_agent_core::{
     Agent, AgentContext, AgentData, AgentError, AgentOutput, AgentSpec, AgentValue, AsAgent, ModularAgent,
     async_trait, modular_agent,
 };
 
+use regex::Regex;
+use tokio::task::JoinHandle;
+use tokio::time::{Duration, Instant};
+
+use crate::audit::{ACTION_WRITE_FILE, audit};
+use crate::contract::{CONFIG_INPUT_CONTRACT, ContractAgent, InputContract, contract_agents};
+use crate::data::{envelope_parts, get_nested_value, render_meta};
+use crate::profile::ProfileConfigs;
+use crate::provenance::{Traced, render_path};
+use crate::string::handlebars_new;
+use crate::time::parse_duration_to_ms;
+
 const CATEGORY: &str = "Std/Display";
 
+const PORT_STRING: &str = "string";
 const PORT_VALUE: &str = "value";
 
+const CONFIG_COLORS: &str = "colors";
+const CONFIG_FILTER: &str = "filter";
+const CONFIG_FILTER_KEY: &str = "filter_key";
+const CONFIG_FORMAT: &str = "format";
+const CONFIG_INTERVAL: &str = "interval";
+const CONFIG_MAX_LENGTH: &str = "max_length";
+const CONFIG_PATH: &str = "path";
+const CONFIG_SEVERITY_KEY: &str = "severity_key";
+const CONFIG_TEMPLATE: &str = "template";
+
+const DISPLAY_COLOR: &str = "color";
+const DISPLAY_FULL_VALUE: &str = "full_value";
+const DISPLAY_META: &str = "meta";
+const DISPLAY_PATH: &str = "path";
 const DISPLAY_VALUE: &str = "value";
 
+// used when the colors config is empty
+const DEFAULT_COLORS: &[(&str, &str)] = &[
+    ("critical", "red"),
+    ("error", "red"),
+    ("fatal", "red"),
+    ("warn", "orange"),
+    ("warning", "orange"),
+];
+
+const FORMAT_HTML: &str = "html";
+const FORMAT_MARKDOWN: &str = "markdown";
+
+// Display throttling and truncation
+
+/// Display agents whose refreshes are rate-limited by the `interval` config.
+///
+/// Updates within the interval after the last refresh are held back, and only the
+/// latest of them is shown when the interval has passed.
+trait ThrottledDisplay: Agent + Sized + Send + 'static {
+    fn throttle(&mut self) -> &mut DisplayThrottle;
+
+    fn show(&mut self, updates: Vec<(&'static str, AgentValue)>) -> Result<(), AgentError> {
+        let interval = self.configs()?.get_string_or_default(CONFIG_INTERVAL);
+        let interval = if interval.trim().is_empty() {
+            None
+        } else {
+            Some(Duration::from_millis(parse_duration_to_ms(&interval)?))
+        };
+
+        let now = Instant::now();
+        let ma = self.ma().clone();
+        let agent_id = self.id().to_string();
+        let runtime = self.runtime().handle().clone();
+        let throttle = self.throttle();
+        if let (Some(interval), Some(last)) = (interval, throttle.last_shown)
+            && now < last + interval
+        {
+            throttle.pending = Some(updates);
+            if throttle
+                .flush_handle
+                .as_ref()
+                .is_none_or(|h| h.is_finished())
+            {
+                let deadline = last + interval;
+                throttle.flush_handle = Some(runtime.spawn(async move {
+                    tokio::time::sleep_until(deadline).await;
+                    let Some(agent) = ma.get_agent(&agent_id) else {
+                        return;
+                    };
+                    let mut agent = agent.lock().await;
+                    let Some(agent) = agent.as_agent_mut::<Self>() else {
+                        return;
+                    };
+                    let Some(updates) = agent.throttle().pending.take() else {
+                        return;
+                    };
+                    agent.throttle().last_shown = Some(Instant::now());
+                    if let Err(e) = agent.apply(updates) {
+                        log::error!("Failed to refresh display: {}", e);
+                    }
+                }));
+            }
+            return Ok(());
+        }
+
+        throttle.pending = None;
+        throttle.last_shown = Some(now);
+        self.apply(updates)
+    }
+
+    fn apply(&mut self, updates: Vec<(&'static str, AgentValue)>) -> Result<(), AgentError> {
+        for (key, value) in updates {
+            self.set_config(key.to_string(), value.clone())?;
+            // the editor reads the full value only when the truncated one is expanded
+            if key != DISPLAY_FULL_VALUE {
+                self.emit_config_updated(key, value);
+            }
+        }
+        Ok(())
+    }
+
+    fn stop_throttle(&mut self) {
+        let throttle = self.throttle();
+        if let Some(handle) = throttle.flush_handle.take() {
+            handle.abort();
+        }
+        throttle.pending = None;
+        throttle.last_shown = None;
+    }
+}
+
+#[derive(Default)]
+struct DisplayThrottle {
+    last_shown: Option<Instant>,
+    pending: Option<Vec<(&'static str, AgentValue)>>,
+    flush_handle: Option<JoinHandle<()>>,
+}
+
+/// Truncates strings longer than `max_length` characters and arrays longer than
+/// `max_length` items, recursively.
+///
+/// The removed part is replaced by an indicator such as `… (120 more items)`, which the
+/// editor shows in place of the rest of the value. A `max_length` of 0 keeps values as is.
+fn truncate_value(value: AgentValue, max_length: usize) -> AgentValue {
+    if max_length == 0 {
+        return value;
+    }
+    match value {
+        AgentValue::String(s) => {
+            let len = s.chars().count();
+            if len <= max_length {
+                return AgentValue::String(s);
+            }
+            let head: String = s.chars().take(max_length).collect();
+            AgentValue::string(format!("{}… ({} more chars)", head, len - max_length))
+        }
+        AgentValue::Array(arr) => {
+            let len = arr.len();
+            let mut items: Vector<AgentValue> = arr
+                .into_iter()
+                .take(max_length)
+                .map(|v| truncate_value(v, max_length))
+                .collect();
+            if len > max_length {
+                items.push_back(AgentValue::string(format!(
+                    "… ({} more items)",
+                    len - max_length
+                )));
+            }
+            AgentValue::array(items)
+        }
+        AgentValue::Object(obj) => AgentValue::object(
+            obj.into_iter()
+                .map(|(k, v)| (k, truncate_value(v, max_length)))
+                .collect(),
+        ),
+        other => other,
+    }
+}
+
+// Returns `value` truncated for display, and the full value to keep in `full_value`
+// (unit when nothing was truncated).
+fn truncate_for_display(value: AgentValue, max_length: usize) -> (AgentValue, AgentValue) {
+    let shown = truncate_value(value.clone(), max_length);
+    if shown == value {
+        (shown, AgentValue::unit())
+    } else {
+        (shown, value)
+    }
+}
+
+fn max_length(agent: &impl Agent) -> Result<usize, AgentError> {
+    Ok(agent
+        .configs()?
+        .get_integer_or_default(CONFIG_MAX_LENGTH)
+        .max(0) as usize)
+}
+
+contract_agents!(
+    DisplayValueAgent,
+    DebugValueAgent,
+    ProvenanceAgent,
+    ReportAgent
+);
+
 // Display Value
+/// Displays the latest input value.
+///
+/// If `filter` (a regex) is set, only values whose `filter_key` matches are displayed;
+/// a blank key matches against the whole value. Strings are matched as is and other
+/// values as JSON.
+///
+/// If `severity_key` is set, its value is looked up in `colors` (severity -> color,
+/// case-insensitive) and the result is shown in `color` for the editor to emphasize
+/// the value. With empty `colors`, errors are red and warnings orange.
+///
+/// Envelopes (see Wrap) are displayed as their payload, with their metadata in `meta`.
+///
+/// `interval` limits how often the display is refreshed, and `max_length` truncates
+/// long strings and arrays, so fast streams of large values stay viewable. The value
+/// is truncated only for display; the whole of it is kept in `full value`.
 #[modular_agent(
     kind = "Display",
     title = "Display Value",
@@ -24,36 +233,146 @@ const DISPLAY_VALUE: &str = "value";
         type_="*",
         default=AgentValue::unit(),
         hide_title,
-    )
+    ),
+    string_config(name = DISPLAY_META, readonly, hide_title),
+    string_config(name = DISPLAY_COLOR, readonly, detail),
+    string_config(name = CONFIG_FILTER_KEY, title = "filter key", detail),
+    string_config(name = CONFIG_FILTER, description = "regex", detail),
+    string_config(name = CONFIG_SEVERITY_KEY, title = "severity key", detail),
+    object_config(name = CONFIG_COLORS, detail),
+    custom_config(
+        name = DISPLAY_FULL_VALUE,
+        readonly,
+        type_="*",
+        default=AgentValue::unit(),
+        title = "full value",
+        detail,
+    ),
+    string_config(name = CONFIG_INTERVAL, description = "min refresh interval (ex. 500ms)", detail),
+    integer_config(name = CONFIG_MAX_LENGTH, title = "max length", description = "0: unlimited", detail),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
 )]
 struct DisplayValueAgent {
     data: AgentData,
+    contract: InputContract,
+    filter: Option<(String, Regex)>,
+    throttle: DisplayThrottle,
+}
+
+impl ThrottledDisplay for DisplayValueAgent {
+    fn throttle(&mut self) -> &mut DisplayThrottle {
+        &mut self.throttle
+    }
+}
+
+impl DisplayValueAgent {
+    fn filter_regex(&mut self, pattern: &str) -> Result<&Regex, AgentError> {
+        if self.filter.as_ref().is_none_or(|(p, _)| p != pattern) {
+            let re = Regex::new(pattern)
+                .map_err(|e| AgentError::InvalidConfig(format!("Invalid filter: {}", e)))?;
+            self.filter = Some((pattern.to_string(), re));
+        }
+        Ok(&self.filter.as_ref().unwrap().1)
+    }
 }
 
 #[async_trait]
 impl AsAgent for DisplayValueAgent {
-    fn new(ma: ModularAgent, id: String, spec: AgentSpec) -> Result<Self, AgentError> {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
         Ok(Self {
             data: AgentData::new(ma, id, spec),
+            contract,
+            filter: None,
+            throttle: DisplayThrottle::default(),
         })
     }
 
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
+    }
+
     async fn start(&mut self) -> Result<(), AgentError> {
         Ok(())
     }
 
+    async fn stop(&mut self) -> Result<(), AgentError> {
+        self.stop_throttle();
+        Ok(())
+    }
+
     async fn process(
         &mut self,
-        _ctx: AgentContext,
-        _port: String,
+        ctx: AgentContext,
+        port: String,
         value: AgentValue,
     ) -> Result<(), AgentError> {
-        self.set_config(DISPLAY_VALUE.to_string(), value.clone())?;
-        self.emit_config_updated(DISPLAY_VALUE, value);
-        Ok(())
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
+        let config = self.configs()?;
+        let pattern = config.get_string_or_default(CONFIG_FILTER);
+        let filter_key = config.get_string_or_default(CONFIG_FILTER_KEY);
+        let severity_key = config.get_string_or_default(CONFIG_SEVERITY_KEY);
+        let colors = config.get_object_or_default(CONFIG_COLORS);
+
+        // Envelopes show their payload, with the metadata on a line of its own
+        let (value, meta) = match envelope_parts(&value) {
+            Some((payload, meta)) => (payload.clone(), render_meta(meta)),
+            None => (value, String::new()),
+        };
+
+        if !pattern.is_empty() {
+            let Some(text) = value_text(&value, &filter_key) else {
+                return Ok(());
+            };
+            if !self.filter_regex(&pattern)?.is_match(&text) {
+                return Ok(());
+            }
+        }
+
+        let mut updates = vec![(DISPLAY_META, AgentValue::string(meta))];
+        if !severity_key.is_empty() {
+            let color = value_text(&value, &severity_key)
+                .and_then(|severity| severity_color(&severity, &colors))
+                .unwrap_or_default();
+            updates.push((DISPLAY_COLOR, AgentValue::string(color)));
+        }
+        let (value, full_value) = truncate_for_display(value, max_length(self)?);
+        updates.push((DISPLAY_VALUE, value));
+        updates.push((DISPLAY_FULL_VALUE, full_value));
+        self.show(updates)
     }
 }
 
+// Gets the value at the dot-separated key path as text. A blank key is the whole value.
+fn value_text(value: &AgentValue, key: &str) -> Option<String> {
+    let v = if key.is_empty() {
+        value
+    } else {
+        let keys: Vec<&str> = key.split('.').collect();
+        get_nested_value(value, &keys)?
+    };
+    match v {
+        AgentValue::String(s) => Some(s.to_string()),
+        v => serde_json::to_string(v).ok(),
+    }
+}
+
+fn severity_color(severity: &str, colors: &HashMap<String, AgentValue>) -> Option<String> {
+    let severity = severity.trim().to_lowercase();
+    if colors.is_empty() {
+        return DEFAULT_COLORS
+            .iter()
+            .find(|(s, _)| *s == severity)
+            .map(|(_, c)| c.to_string());
+    }
+    colors
+        .iter()
+        .find(|(s, _)| s.to_lowercase() == severity)
+        .and_then(|(_, c)| c.as_str().map(|c| c.to_string()))
+}
+
 // Debug Value
 #[modular_agent(
     kind = "Display",
@@ -64,33 +383,480 @@ impl AsAgent for DisplayValueAgent {
         name = DISPLAY_VALUE,
         readonly,
         hide_title,
-    )
+    ),
+    custom_config(
+        name = DISPLAY_FULL_VALUE,
+        readonly,
+        type_="*",
+        default=AgentValue::unit(),
+        title = "full value",
+        detail,
+    ),
+    string_config(name = CONFIG_INTERVAL, description = "min refresh interval (ex. 500ms)", detail),
+    integer_config(name = CONFIG_MAX_LENGTH, title = "max length", description = "0: unlimited", detail),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
 )]
 struct DebugValueAgent {
     data: AgentData,
+    contract: InputContract,
+    throttle: DisplayThrottle,
+}
+
+impl ThrottledDisplay for DebugValueAgent {
+    fn throttle(&mut self) -> &mut DisplayThrottle {
+        &mut self.throttle
+    }
 }
 
 #[async_trait]
 impl AsAgent for DebugValueAgent {
-    fn new(ma: ModularAgent, id: String, spec: AgentSpec) -> Result<Self, AgentError> {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
         Ok(Self {
             data: AgentData::new(ma, id, spec),
+            contract,
+            throttle: DisplayThrottle::default(),
         })
     }
 
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
+    }
+
+    async fn stop(&mut self) -> Result<(), AgentError> {
+        self.stop_throttle();
+        Ok(())
+    }
+
     async fn process(
         &mut self,
         ctx: AgentContext,
-        _port: String,
+        port: String,
         value: AgentValue,
     ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
         let ctx_json =
             serde_json::to_value(&ctx).map_err(|e| AgentError::InvalidValue(e.to_string()))?;
         let ctx = AgentValue::from_json(ctx_json)?;
-        let debug_value =
-            AgentValue::object(hashmap! { "ctx".into() => ctx, "value".into() => value });
-        self.set_config(DISPLAY_VALUE.to_string(), debug_value.clone())?;
-        self.emit_config_updated(DISPLAY_VALUE, debug_value);
+        let (payload, meta) = match envelope_parts(&value) {
+            Some((payload, meta)) => (payload.clone(), Some(meta.clone())),
+            None => (value, None),
+        };
+        let debug_value = |value: AgentValue| {
+            let mut obj = hashmap! {
+                "ctx".into() => ctx.clone(),
+                "value".into() => value,
+            };
+            if let Some(meta) = &meta {
+                obj.insert("meta".into(), meta.clone());
+            }
+            AgentValue::object(obj)
+        };
+        let (shown, full) = truncate_for_display(payload, max_length(self)?);
+        let full = if full.is_unit() {
+            full
+        } else {
+            debug_value(full)
+        };
+        self.show(vec![
+            (DISPLAY_VALUE, debug_value(shown)),
+            (DISPLAY_FULL_VALUE, full),
+        ])
+    }
+}
+
+// Provenance
+/// Displays the path the input value took, as recorded in its context in provenance mode.
+///
+/// Each line shows an agent that output the value, with the time since the first one.
+/// Provenance is enabled with the `MODULAR_AGENT_PROVENANCE` environment variable.
+#[modular_agent(
+    kind = "Display",
+    title = "Provenance",
+    category = CATEGORY,
+    inputs = [PORT_VALUE],
+    text_config(
+        name = DISPLAY_PATH,
+        readonly,
+        hide_title,
+    ),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
+)]
+struct ProvenanceAgent {
+    data: AgentData,
+    contract: InputContract,
+}
+
+#[async_trait]
+impl AsAgent for ProvenanceAgent {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
+        Ok(Self {
+            data: AgentData::new(ma, id, spec),
+            contract,
+        })
+    }
+
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
+    }
+
+    async fn process(
+        &mut self,
+        ctx: AgentContext,
+        port: String,
+        value: AgentValue,
+    ) -> Result<(), AgentError> {
+        if self.check_input(&ctx, &port, value).await?.is_none() {
+            return Ok(());
+        }
+        let path = AgentValue::string(render_path(&ctx));
+        self.set_config(DISPLAY_PATH.to_string(), path.clone())?;
+        self.emit_config_updated(DISPLAY_PATH, path);
         Ok(())
     }
 }
+
+// Report
+/// Assembles a Markdown or HTML report from the fields of the input object.
+///
+/// Each field is rendered as a section: strings as text, arrays as tables
+/// (columns from the object keys), images as embedded base64 PNGs, and other
+/// values as JSON. Combine several inputs into one object with Zip To Object.
+///
+/// The rendered sections are available by field name in the handlebars `template`.
+/// With a blank template, all sections are emitted in key order under their names.
+/// The document is emitted on `string`, and written to `path` if it is set.
+#[modular_agent(
+    title = "Report",
+    category = CATEGORY,
+    inputs = [PORT_VALUE],
+    outputs = [PORT_STRING],
+    string_config(name = CONFIG_FORMAT, default = FORMAT_MARKDOWN, description = "markdown, html"),
+    text_config(name = CONFIG_TEMPLATE),
+    string_config(name = CONFIG_PATH, description = "optional file to write"),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
+)]
+struct ReportAgent {
+    data: AgentData,
+    contract: InputContract,
+}
+
+#[async_trait]
+impl AsAgent for ReportAgent {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
+        Ok(Self {
+            data: AgentData::new(ma, id, spec),
+            contract,
+        })
+    }
+
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
+    }
+
+    async fn process(
+        &mut self,
+        ctx: AgentContext,
+        port: String,
+        value: AgentValue,
+    ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
+        let config = self.configs()?;
+        let html = match config.get_string_or(CONFIG_FORMAT, FORMAT_MARKDOWN).trim() {
+            "" | FORMAT_MARKDOWN => false,
+            FORMAT_HTML => true,
+            other => {
+                return Err(AgentError::InvalidConfig(format!(
+                    "Unknown format: {}",
+                    other
+                )));
+            }
+        };
+        let template = config.get_string_or_default(CONFIG_TEMPLATE);
+        let path = config.get_string_resolved(CONFIG_PATH)?;
+
+        let sections = value
+            .as_object()
+            .ok_or_else(|| AgentError::InvalidValue("Expected object of sections".into()))?;
+        let doc = build_report(sections, &template, html)?;
+
+        if !path.is_empty() {
+            let path = Path::new(&path);
+            if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+                fs::create_dir_all(parent).map_err(|e| {
+                    AgentError::IoError(format!("Failed to create parent directories: {}", e))
+                })?;
+            }
+            let target = path.display().to_string();
+            audit(
+                self,
+                &ctx,
+                ACTION_WRITE_FILE,
+                &target,
+                &AgentValue::string(&doc),
+            )
+            .await?;
+            fs::write(path, &doc).map_err(|e| {
+                AgentError::IoError(format!("Failed to write file {}: {}", path.display(), e))
+            })?;
+        }
+
+        self.output(self.traced(ctx), PORT_STRING, AgentValue::string(doc))
+            .await
+    }
+}
+
+fn build_report(
+    sections: &HashMap<String, AgentValue>,
+    template: &str,
+    html: bool,
+) -> Result<String, AgentError> {
+    let mut names: Vec<&String> = sections.keys().collect();
+    names.sort();
+
+    if template.trim().is_empty() {
+        let body: Vec<String> = names
+            .iter()
+            .map(|name| {
+                let section = render_section(&sections[*name], html);
+                if html {
+                    format!("<h2>{}</h2>\n{}\n", escape_html(name), section)
+                } else {
+                    format!("## {}\n\n{}\n", name, section)
+                }
+            })
+            .collect();
+        let body = body.join("\n");
+        if html {
+            return Ok(format!(
+                "<!DOCTYPE html>\n<html>\n<body>\n{}</body>\n</html>\n",
+                body
+            ));
+        }
+        return Ok(body);
+    }
+
+    let rendered: serde_json::Map<String, serde_json::Value> = names
+        .iter()
+        .map(|name| {
+            (
+                name.to_string(),
+                serde_json::Value::String(render_section(&sections[*name], html)),
+            )
+        })
+        .collect();
+    handlebars_new()
+        .render_template(template, &rendered)
+        .map_err(|e| AgentError::InvalidConfig(format!("Failed to render template: {}", e)))
+}
+
+fn render_section(value: &AgentValue, html: bool) -> String {
+    #[cfg(feature = "image")]
+    if value.is_image() {
+        let src = serde_json::to_value(value)
+            .ok()
+            .and_then(|v| v.as_str().map(|s| s.to_string()))
+            .unwrap_or_default();
+        return if html {
+            format!("<img src=\"{}\">", src)
+        } else {
+            format!("![image]({})", src)
+        };
+    }
+
+    match value {
+        AgentValue::String(s) if html => format!("<p>{}</p>", escape_html(s)),
+        AgentValue::String(s) => s.to_string(),
+        AgentValue::Array(rows) if !rows.is_empty() => render_table(rows, html),
+        _ => {
+            let json = serde_json::to_string_pretty(value).unwrap_or_default();
+            if html {
+                format!("<pre>{}</pre>", escape_html(&json))
+            } else {
+                format!("```json\n{}\n```", json)
+            }
+        }
+    }
+}
+
+// Renders rows of objects as a table with the union of their keys as columns.
+// Rows of other values are rendered in a single `value` column.
+fn render_table(rows: &Vector<AgentValue>, html: bool) -> String {
+    let mut columns: Vec<String> = Vec::new();
+    for row in rows {
+        if let Some(obj) = row.as_object() {
+            let mut keys: Vec<&String> = obj.keys().filter(|k| !columns.contains(k)).collect();
+            keys.sort();
+            columns.extend(keys.into_iter().cloned());
+        }
+    }
+    if columns.is_empty() || rows.iter().any(|r| !r.is_object()) {
+        columns = vec!["value".to_string()];
+    }
+    let single = columns.len() == 1 && columns[0] == "value";
+
+    let cell = |row: &AgentValue, column: &str| -> String {
+        let v = if single && !row.is_object() {
+            Some(row)
+        } else {
+            row.as_object().and_then(|o| o.get(column))
+        };
+        let text = match v {
+            None | Some(AgentValue::Unit) => String::new(),
+            Some(AgentValue::String(s)) => s.to_string(),
+            Some(v) => serde_json::to_string(v).unwrap_or_default(),
+        };
+        if html {
+            escape_html(&text)
+        } else {
+            text.replace('|', "\\|").replace('\n', " ")
+        }
+    };
+
+    if html {
+        let mut out = String::from("<table>\n<tr>");
+        for c in &columns {
+            out.push_str(&format!("<th>{}</th>", escape_html(c)));
+        }
+        out.push_str("</tr>\n");
+        for row in rows {
+            out.push_str("<tr>");
+            for c in &columns {
+                out.push_str(&format!("<td>{}</td>", cell(row, c)));
+            }
+            out.push_str("</tr>\n");
+        }
+        out.push_str("</table>");
+        out
+    } else {
+        let mut lines = vec![
+            format!("| {} |", columns.join(" | ")),
+            format!("|{}", " --- |".repeat(columns.len())),
+        ];
+        for row in rows {
+            let cells: Vec<String> = columns.iter().map(|c| cell(row, c)).collect();
+            lines.push(format!("| {} |", cells.join(" | ")));
+        }
+        lines.join("\n")
+    }
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use im::vector;
+
+    use super::*;
+
+    #[test]
+    fn test_value_text() {
+        let value = AgentValue::object(hashmap! {
+            "level".to_string() => AgentValue::string("WARN"),
+            "data".to_string() => AgentValue::object(hashmap! {
+                "code".to_string() => AgentValue::integer(500),
+            }),
+        });
+        assert_eq!(value_text(&value, "level").unwrap(), "WARN");
+        assert_eq!(value_text(&value, "data.code").unwrap(), "500");
+        assert_eq!(value_text(&value, "data").unwrap(), r#"{"code":500}"#);
+        assert_eq!(value_text(&value, "missing"), None);
+        assert_eq!(value_text(&AgentValue::string("x"), "").unwrap(), "x");
+    }
+
+    #[test]
+    fn test_severity_color() {
+        let empty = HashMap::new();
+        assert_eq!(severity_color("ERROR", &empty).unwrap(), "red");
+        assert_eq!(severity_color("warn", &empty).unwrap(), "orange");
+        assert_eq!(severity_color("info", &empty), None);
+
+        let colors = hashmap! {
+            "Info".to_string() => AgentValue::string("#2196f3"),
+        };
+        assert_eq!(severity_color("info", &colors).unwrap(), "#2196f3");
+        assert_eq!(severity_color("error", &colors), None);
+    }
+
+    #[test]
+    fn test_truncate_value() {
+        let value = AgentValue::object(hashmap! {
+            "text".to_string() => AgentValue::string("abcdef"),
+            "items".to_string() => AgentValue::array(vector![
+                AgentValue::integer(1),
+                AgentValue::string("xyz"),
+                AgentValue::integer(3),
+                AgentValue::integer(4),
+            ]),
+        });
+        assert_eq!(
+            truncate_value(value.clone(), 2),
+            AgentValue::object(hashmap! {
+                "text".to_string() => AgentValue::string("ab… (4 more chars)"),
+                "items".to_string() => AgentValue::array(vector![
+                    AgentValue::integer(1),
+                    AgentValue::string("xy… (1 more chars)"),
+                    AgentValue::string("… (2 more items)"),
+                ]),
+            })
+        );
+        assert_eq!(truncate_value(value.clone(), 0), value);
+
+        // the full value is kept aside only when something was truncated
+        let (shown, full) = truncate_for_display(value.clone(), 2);
+        assert_eq!(shown, truncate_value(value.clone(), 2));
+        assert_eq!(full, value);
+        assert_eq!(
+            truncate_for_display(value.clone(), 10),
+            (value, AgentValue::unit())
+        );
+    }
+
+    #[test]
+    fn test_build_report_markdown() {
+        let sections = hashmap! {
+            "summary".to_string() => AgentValue::string("All good"),
+            "rows".to_string() => AgentValue::array(vector![
+                AgentValue::object(hashmap! {
+                    "name".to_string() => AgentValue::string("a|b"),
+                    "n".to_string() => AgentValue::integer(1),
+                }),
+                AgentValue::object(hashmap! {
+                    "name".to_string() => AgentValue::string("c"),
+                }),
+            ]),
+        };
+
+        assert_eq!(
+            build_report(&sections, "", false).unwrap(),
+            "## rows\n\n| n | name |\n| --- | --- |\n| 1 | a\\|b |\n|  | c |\n\n## summary\n\nAll good\n"
+        );
+        assert_eq!(
+            build_report(&sections, "# Report\n{{summary}}", false).unwrap(),
+            "# Report\nAll good"
+        );
+    }
+
+    #[test]
+    fn test_build_report_html() {
+        let sections = hashmap! {
+            "note".to_string() => AgentValue::string("<b>&</b>"),
+            "values".to_string() => AgentValue::array(vector![AgentValue::integer(1)]),
+        };
+        assert_eq!(
+            build_report(&sections, "{{note}}\n{{values}}", true).unwrap(),
+            "<p>&lt;b&gt;&amp;&lt;/b&gt;</p>\n<table>\n<tr><th>value</th></tr>\n<tr><td>1</td></tr>\n</table>"
+        );
+    }
+}
@@ -0,0 +1,577 @@
+use std::cmp::Ordering;
+
+use modular_agent_core::{AgentError, AgentValue};
+
+use crate::condition::compare;
+use crate::data::get_nested_value;
+
+/// A simple expression over the fields of a value.
+///
+/// - literals: `1`, `2.5`, `"text"` or `'text'`, `true`, `false`, `null`
+/// - fields: `name`, `a.b.c` (`null` when missing), `_` for the value itself
+/// - operators: `+ - * / %`, `== != < <= > >=`, `&& || !`, parentheses
+/// - functions: `lower`, `upper`, `trim`, `len`, `abs`, `round`, `floor`, `ceil`, `str`,
+///   `num`, `coalesce`, `if`
+///
+/// `+` concatenates when either side is a string. Arithmetic with `null` gives `null`.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Expr {
+    Literal(AgentValue),
+    Field(Vec<String>),
+    Unary(char, Box<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+    Call(String, Vec<Expr>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    And,
+    Or,
+}
+
+const FUNCTIONS: &[&str] = &[
+    "lower", "upper", "trim", "len", "abs", "round", "floor", "ceil", "str", "num", "coalesce",
+    "if",
+];
+
+impl Expr {
+    pub(crate) fn parse(src: &str) -> Result<Self, AgentError> {
+        let tokens = tokenize(src)?;
+        let mut parser = Parser { tokens, pos: 0 };
+        let expr = parser.or()?;
+        if let Some(token) = parser.peek() {
+            return Err(AgentError::InvalidConfig(format!(
+                "Unexpected '{}' in expression: {}",
+                token, src
+            )));
+        }
+        Ok(expr)
+    }
+
+    pub(crate) fn eval(&self, value: &AgentValue) -> Result<AgentValue, AgentError> {
+        match self {
+            Expr::Literal(v) => Ok(v.clone()),
+            Expr::Field(keys) => Ok(get_nested_value(value, keys)
+                .cloned()
+                .unwrap_or(AgentValue::Unit)),
+            Expr::Unary('!', e) => Ok(AgentValue::boolean(!truthy(&e.eval(value)?))),
+            Expr::Unary(_, e) => match e.eval(value)? {
+                AgentValue::Integer(n) => n
+                    .checked_neg()
+                    .map(AgentValue::integer)
+                    .ok_or_else(|| AgentError::InvalidValue(format!("Integer overflow: -{}", n))),
+                AgentValue::Number(n) => Ok(AgentValue::number(-n)),
+                AgentValue::Unit => Ok(AgentValue::Unit),
+                v => Err(type_error("-", &v)),
+            },
+            Expr::Binary(BinOp::And, a, b) => {
+                let a = truthy(&a.eval(value)?);
+                Ok(AgentValue::boolean(a && truthy(&b.eval(value)?)))
+            }
+            Expr::Binary(BinOp::Or, a, b) => {
+                let a = truthy(&a.eval(value)?);
+                Ok(AgentValue::boolean(a || truthy(&b.eval(value)?)))
+            }
+            Expr::Binary(op, a, b) => binary(*op, a.eval(value)?, b.eval(value)?),
+            Expr::Call(name, args) if name == "if" => {
+                if truthy(&args[0].eval(value)?) {
+                    args[1].eval(value)
+                } else {
+                    args.get(2).map_or(Ok(AgentValue::Unit), |e| e.eval(value))
+                }
+            }
+            Expr::Call(name, args) => {
+                let args = args
+                    .iter()
+                    .map(|e| e.eval(value))
+                    .collect::<Result<Vec<_>, _>>()?;
+                call(name, args)
+            }
+        }
+    }
+}
+
+/// `null`, `false`, `0`, and empty strings, arrays and objects are false.
+pub(crate) fn truthy(value: &AgentValue) -> bool {
+    match value {
+        AgentValue::Unit => false,
+        AgentValue::Boolean(b) => *b,
+        AgentValue::Integer(n) => *n != 0,
+        AgentValue::Number(n) => *n != 0.0,
+        AgentValue::String(s) => !s.is_empty(),
+        AgentValue::Array(a) => !a.is_empty(),
+        AgentValue::Object(o) => !o.is_empty(),
+        _ => true,
+    }
+}
+
+fn type_error(op: &str, value: &AgentValue) -> AgentError {
+    AgentError::InvalidValue(format!("Invalid operand for '{}': {}", op, value.to_json()))
+}
+
+fn binary(op: BinOp, a: AgentValue, b: AgentValue) -> Result<AgentValue, AgentError> {
+    let ord = || compare(&a, &b);
+    let cmp = match op {
+        BinOp::Eq => Some(ord() == Some(Ordering::Equal)),
+        BinOp::Ne => Some(ord() != Some(Ordering::Equal)),
+        BinOp::Lt => Some(ord() == Some(Ordering::Less)),
+        BinOp::Le => Some(matches!(ord(), Some(Ordering::Less | Ordering::Equal))),
+        BinOp::Gt => Some(ord() == Some(Ordering::Greater)),
+        BinOp::Ge => Some(matches!(ord(), Some(Ordering::Greater | Ordering::Equal))),
+        _ => None,
+    };
+    if let Some(cmp) = cmp {
+        return Ok(AgentValue::boolean(cmp));
+    }
+
+    if op == BinOp::Add && (a.is_string() || b.is_string()) {
+        return Ok(AgentValue::string(format!(
+            "{}{}",
+            display(&a),
+            display(&b)
+        )));
+    }
+    if a.is_unit() || b.is_unit() {
+        return Ok(AgentValue::Unit);
+    }
+    let symbol = match op {
+        BinOp::Add => "+",
+        BinOp::Sub => "-",
+        BinOp::Mul => "*",
+        BinOp::Div => "/",
+        _ => "%",
+    };
+    match (&a, &b) {
+        (AgentValue::Integer(x), AgentValue::Integer(y)) => {
+            let (x, y) = (*x, *y);
+            let result = match op {
+                BinOp::Add => x.checked_add(y),
+                BinOp::Sub => x.checked_sub(y),
+                BinOp::Mul => x.checked_mul(y),
+                BinOp::Div if x.checked_rem(y).is_some_and(|r| r != 0) => {
+                    return Ok(AgentValue::number(x as f64 / y as f64));
+                }
+                BinOp::Div => x.checked_div(y),
+                _ => x.checked_rem(y),
+            };
+            result.map(AgentValue::integer).ok_or_else(|| {
+                AgentError::InvalidValue(format!(
+                    "Integer overflow or division by zero: {}",
+                    symbol
+                ))
+            })
+        }
+        (
+            AgentValue::Integer(_) | AgentValue::Number(_),
+            AgentValue::Integer(_) | AgentValue::Number(_),
+        ) => {
+            let (x, y) = (a.to_number().unwrap(), b.to_number().unwrap());
+            Ok(AgentValue::number(match op {
+                BinOp::Add => x + y,
+                BinOp::Sub => x - y,
+                BinOp::Mul => x * y,
+                BinOp::Div => x / y,
+                _ => x % y,
+            }))
+        }
+        (AgentValue::Integer(_) | AgentValue::Number(_), v) | (v, _) => Err(type_error(symbol, v)),
+    }
+}
+
+// Strings without quotes, other values as JSON.
+fn display(value: &AgentValue) -> String {
+    match value {
+        AgentValue::String(s) => s.to_string(),
+        AgentValue::Unit => String::new(),
+        v => v.to_json().to_string(),
+    }
+}
+
+fn call(name: &str, mut args: Vec<AgentValue>) -> Result<AgentValue, AgentError> {
+    if name == "coalesce" {
+        return Ok(args
+            .into_iter()
+            .find(|v| !v.is_unit())
+            .unwrap_or(AgentValue::Unit));
+    }
+    let arg = args.swap_remove(0);
+    let string = |f: fn(&str) -> String| match &arg {
+        AgentValue::String(s) => Ok(AgentValue::string(f(s))),
+        AgentValue::Unit => Ok(AgentValue::Unit),
+        v => Err(type_error(name, v)),
+    };
+    let number = |f: fn(f64) -> f64| match &arg {
+        AgentValue::Integer(n) if name == "abs" => Ok(AgentValue::integer(n.saturating_abs())),
+        AgentValue::Integer(_) => Ok(arg.clone()),
+        AgentValue::Number(n) => Ok(AgentValue::number(f(*n))),
+        AgentValue::Unit => Ok(AgentValue::Unit),
+        v => Err(type_error(name, v)),
+    };
+    match name {
+        "lower" => string(|s| s.to_lowercase()),
+        "upper" => string(|s| s.to_uppercase()),
+        "trim" => string(|s| s.trim().to_string()),
+        "abs" => number(f64::abs),
+        "round" => number(f64::round),
+        "floor" => number(f64::floor),
+        "ceil" => number(f64::ceil),
+        "len" => Ok(match &arg {
+            AgentValue::String(s) => AgentValue::integer(s.chars().count() as i64),
+            AgentValue::Array(a) => AgentValue::integer(a.len() as i64),
+            AgentValue::Object(o) => AgentValue::integer(o.len() as i64),
+            _ => AgentValue::integer(0),
+        }),
+        "str" => Ok(match arg {
+            AgentValue::Unit => AgentValue::Unit,
+            v => AgentValue::string(display(&v)),
+        }),
+        _ => Ok(match &arg {
+            AgentValue::Integer(_) | AgentValue::Number(_) => arg,
+            AgentValue::Boolean(b) => AgentValue::integer(*b as i64),
+            AgentValue::String(s) => {
+                let s = s.trim();
+                s.parse::<i64>()
+                    .map(AgentValue::integer)
+                    .or_else(|_| s.parse::<f64>().map(AgentValue::number))
+                    .unwrap_or(AgentValue::Unit)
+            }
+            _ => AgentValue::Unit,
+        }),
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Num(AgentValue),
+    Str(String),
+    Ident(String),
+    Op(&'static str),
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Num(v) => write!(f, "{}", v.to_json()),
+            Token::Str(s) => write!(f, "\"{}\"", s),
+            Token::Ident(s) => write!(f, "{}", s),
+            Token::Op(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+const OPS: &[&str] = &[
+    "==", "!=", "<=", ">=", "&&", "||", "<", ">", "+", "-", "*", "/", "%", "!", "(", ")", ",",
+];
+
+fn tokenize(src: &str) -> Result<Vec<Token>, AgentError> {
+    let invalid = |msg: &str| AgentError::InvalidConfig(format!("{} in expression: {}", msg, src));
+    let chars: Vec<char> = src.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c == '"' || c == '\'' {
+            let mut s = String::new();
+            i += 1;
+            loop {
+                match chars.get(i) {
+                    None => return Err(invalid("Unterminated string")),
+                    Some('\\') if i + 1 < chars.len() => {
+                        s.push(chars[i + 1]);
+                        i += 2;
+                    }
+                    Some(&q) if q == c => break,
+                    Some(&ch) => {
+                        s.push(ch);
+                        i += 1;
+                    }
+                }
+            }
+            i += 1;
+            tokens.push(Token::Str(s));
+        } else if c.is_ascii_digit() {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                i += 1;
+            }
+            let s: String = chars[start..i].iter().collect();
+            let num = s
+                .parse::<i64>()
+                .map(AgentValue::integer)
+                .or_else(|_| s.parse::<f64>().map(AgentValue::number))
+                .map_err(|_| invalid(&format!("Invalid number '{}'", s)))?;
+            tokens.push(Token::Num(num));
+        } else if c.is_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_alphanumeric() || "_.".contains(chars[i])) {
+                i += 1;
+            }
+            tokens.push(Token::Ident(chars[start..i].iter().collect()));
+        } else {
+            let rest: String = chars[i..chars.len().min(i + 2)].iter().collect();
+            let Some(op) = OPS.iter().find(|op| rest.starts_with(**op)) else {
+                return Err(invalid(&format!("Unexpected '{}'", c)));
+            };
+            i += op.len();
+            tokens.push(Token::Op(op));
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn eat(&mut self, op: &str) -> bool {
+        if matches!(self.peek(), Some(Token::Op(o)) if *o == op) {
+            self.pos += 1;
+            return true;
+        }
+        false
+    }
+
+    fn expect(&mut self, op: &str) -> Result<(), AgentError> {
+        if self.eat(op) {
+            return Ok(());
+        }
+        Err(AgentError::InvalidConfig(match self.peek() {
+            Some(token) => format!("Expected '{}' but found '{}'", op, token),
+            None => format!("Expected '{}' at the end of the expression", op),
+        }))
+    }
+
+    fn binary(
+        &mut self,
+        ops: &[(&str, BinOp)],
+        next: fn(&mut Self) -> Result<Expr, AgentError>,
+    ) -> Result<Expr, AgentError> {
+        let mut lhs = next(self)?;
+        'outer: loop {
+            for (symbol, op) in ops {
+                if self.eat(symbol) {
+                    let rhs = next(self)?;
+                    lhs = Expr::Binary(*op, Box::new(lhs), Box::new(rhs));
+                    continue 'outer;
+                }
+            }
+            return Ok(lhs);
+        }
+    }
+
+    fn or(&mut self) -> Result<Expr, AgentError> {
+        self.binary(&[("||", BinOp::Or)], Self::and)
+    }
+
+    fn and(&mut self) -> Result<Expr, AgentError> {
+        self.binary(&[("&&", BinOp::And)], Self::comparison)
+    }
+
+    fn comparison(&mut self) -> Result<Expr, AgentError> {
+        self.binary(
+            &[
+                ("==", BinOp::Eq),
+                ("!=", BinOp::Ne),
+                ("<=", BinOp::Le),
+                (">=", BinOp::Ge),
+                ("<", BinOp::Lt),
+                (">", BinOp::Gt),
+            ],
+            Self::additive,
+        )
+    }
+
+    fn additive(&mut self) -> Result<Expr, AgentError> {
+        self.binary(
+            &[("+", BinOp::Add), ("-", BinOp::Sub)],
+            Self::multiplicative,
+        )
+    }
+
+    fn multiplicative(&mut self) -> Result<Expr, AgentError> {
+        self.binary(
+            &[("*", BinOp::Mul), ("/", BinOp::Div), ("%", BinOp::Rem)],
+            Self::unary,
+        )
+    }
+
+    fn unary(&mut self) -> Result<Expr, AgentError> {
+        if self.eat("!") {
+            return Ok(Expr::Unary('!', Box::new(self.unary()?)));
+        }
+        if self.eat("-") {
+            return Ok(Expr::Unary('-', Box::new(self.unary()?)));
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Expr, AgentError> {
+        let Some(token) = self.tokens.get(self.pos).cloned() else {
+            return Err(AgentError::InvalidConfig(
+                "Unexpected end of the expression".into(),
+            ));
+        };
+        self.pos += 1;
+        match token {
+            Token::Num(v) => Ok(Expr::Literal(v)),
+            Token::Str(s) => Ok(Expr::Literal(AgentValue::string(s))),
+            Token::Op("(") => {
+                let expr = self.or()?;
+                self.expect(")")?;
+                Ok(expr)
+            }
+            Token::Ident(name) if self.eat("(") => {
+                if !FUNCTIONS.contains(&name.as_str()) {
+                    return Err(AgentError::InvalidConfig(format!(
+                        "Unknown function: {}",
+                        name
+                    )));
+                }
+                let mut args = Vec::new();
+                if !self.eat(")") {
+                    loop {
+                        args.push(self.or()?);
+                        if self.eat(")") {
+                            break;
+                        }
+                        self.expect(",")?;
+                    }
+                }
+                let arity_ok = match name.as_str() {
+                    "coalesce" => !args.is_empty(),
+                    "if" => args.len() == 2 || args.len() == 3,
+                    _ => args.len() == 1,
+                };
+                if !arity_ok {
+                    return Err(AgentError::InvalidConfig(format!(
+                        "Wrong number of arguments for {}()",
+                        name
+                    )));
+                }
+                Ok(Expr::Call(name, args))
+            }
+            Token::Ident(name) => Ok(match name.as_str() {
+                "true" => Expr::Literal(AgentValue::boolean(true)),
+                "false" => Expr::Literal(AgentValue::boolean(false)),
+                "null" => Expr::Literal(AgentValue::Unit),
+                "_" => Expr::Field(Vec::new()),
+                _ => Expr::Field(name.split('.').map(|s| s.to_string()).collect()),
+            }),
+            token => Err(AgentError::InvalidConfig(format!(
+                "Unexpected '{}' in expression",
+                token
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use im::hashmap;
+
+    use super::*;
+
+    fn eval(src: &str) -> AgentValue {
+        let value = AgentValue::object(hashmap! {
+            "price".to_string() => AgentValue::integer(120),
+            "qty".to_string() => AgentValue::integer(3),
+            "name".to_string() => AgentValue::string(" Widget "),
+            "user".to_string() => AgentValue::object(hashmap! {
+                "first".to_string() => AgentValue::string("Ada"),
+            }),
+        });
+        Expr::parse(src).unwrap().eval(&value).unwrap()
+    }
+
+    #[test]
+    fn test_expr_eval() {
+        assert_eq!(eval("price * qty - 10"), AgentValue::integer(350));
+        assert_eq!(eval("(price + 30) / 4"), AgentValue::number(37.5));
+        assert_eq!(eval("-qty % 2"), AgentValue::integer(-1));
+        assert_eq!(
+            eval("price >= 100 && !(qty > 5)"),
+            AgentValue::boolean(true)
+        );
+        assert_eq!(eval("missing > 1 || qty == 3.0"), AgentValue::boolean(true));
+        assert_eq!(eval("missing + 1"), AgentValue::Unit);
+        assert_eq!(
+            eval("upper(trim(name)) + '-' + qty"),
+            AgentValue::string("WIDGET-3")
+        );
+        assert_eq!(eval("user.first"), AgentValue::string("Ada"));
+        assert_eq!(eval("len(user)"), AgentValue::integer(1));
+        assert_eq!(
+            eval("coalesce(missing, \"none\")"),
+            AgentValue::string("none")
+        );
+        assert_eq!(
+            eval("if(qty > 2, 'bulk', 'single')"),
+            AgentValue::string("bulk")
+        );
+        assert_eq!(eval("round(num('2.6'))"), AgentValue::number(3.0));
+    }
+
+    #[test]
+    fn test_expr_errors() {
+        assert!(Expr::parse("1 +").is_err());
+        assert!(Expr::parse("(1").is_err());
+        assert!(Expr::parse("foo(1)").is_err());
+        assert!(Expr::parse("upper(1, 2)").is_err());
+        assert!(Expr::parse("'abc").is_err());
+        assert!(Expr::parse("a $ b").is_err());
+        let expr = Expr::parse("name * 2").unwrap();
+        assert!(
+            expr.eval(&AgentValue::object(hashmap! {
+                "name".to_string() => AgentValue::string("x"),
+            }))
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_expr_eval_errors() {
+        let eval = |src: &str, n: i64| {
+            Expr::parse(src)
+                .unwrap()
+                .eval(&AgentValue::object(hashmap! {
+                    "n".to_string() => AgentValue::integer(n),
+                }))
+        };
+        assert!(eval("n / 0", 1).is_err());
+        assert!(eval("n % 0", 1).is_err());
+        assert!(eval("n / -1", i64::MIN).is_err());
+        assert!(eval("n % -1", i64::MIN).is_err());
+        assert!(eval("-n", i64::MIN).is_err());
+        assert!(eval("n + 1", i64::MAX).is_err());
+        assert!(eval("n * 2", i64::MAX).is_err());
+        assert!(eval("-'x'", 1).is_err());
+        assert!(eval("n + true", 1).is_err());
+        assert_eq!(
+            eval("n / -1", i64::MAX).unwrap(),
+            AgentValue::integer(-i64::MAX)
+        );
+        assert_eq!(
+            eval("n / 0.0", 1).unwrap(),
+            AgentValue::number(f64::INFINITY)
+        );
+    }
+}
@@ -1,59 +1,106 @@
+use std::collections::{BTreeMap, HashSet};
 use std::fs;
 use std::io::Write;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
+use chrono::{DateTime, Local};
 use glob::glob;
-use im::hashmap;
+use im::{Vector, hashmap};
 use modular_agent_core::{
     Agent, AgentContext, AgentData, AgentError, AgentOutput, AgentSpec, AgentValue, AsAgent, ModularAgent,
     async_trait, modular_agent,
 };
+use serde_json::json;
+use sha2::{Digest, Sha256};
+
+use crate::audit::{ACTION_MOVE_FILE, ACTION_WRITE_FILE, audit};
+use crate::contract::{CONFIG_INPUT_CONTRACT, ContractAgent, InputContract, contract_agents};
+use crate::profile::{ProfileConfigs, resolve};
+use crate::provenance::{Traced, stamp};
+use crate::string::handlebars_new;
 
 const CATEGORY: &str = "Std/File";
 
+const CONFIG_DEST: &str = "dest";
+const CONFIG_MIN_SIZE: &str = "min_size";
 const CONFIG_PATH: &str = "path";
+const CONFIG_PROGRESS_EVERY: &str = "progress_every";
+const CONFIG_TEMPLATE: &str = "template";
+
+const ORGANIZE_TEMPLATE_DEFAULT: &str = "{{year}}/{{month}}/{{file_name}}";
+const PROGRESS_EVERY_DEFAULT: i64 = 1000;
 
+const PORT_APPLY: &str = "apply";
 const PORT_ARRAY: &str = "array";
 const PORT_DATA: &str = "data";
 const PORT_DOC: &str = "doc";
+const PORT_DUPLICATES: &str = "duplicates";
 const PORT_FILES: &str = "files";
 const PORT_PATH: &str = "path";
+const PORT_PLAN: &str = "plan";
+const PORT_PROGRESS: &str = "progress";
 const PORT_STRING: &str = "string";
 const PORT_UNIT: &str = "unit";
 const PORT_VALUE: &str = "value";
 
+contract_agents!(
+    GlobAgent,
+    ListFilesAgent,
+    ReadTextFileAgent,
+    WriteTextFileAgent,
+    ReadJsonFileAgent,
+    WriteJsonFileAgent,
+    ReadJsonlFileAgent,
+    WriteJsonlFileAgent,
+    AppendJsonlFileAgent,
+    OrganizeFilesAgent,
+    FindDuplicatesAgent,
+);
+
 // Glob Agent
 #[modular_agent(
     title = "Glob",
     category = CATEGORY,
     inputs = [PORT_PATH],
-    outputs = [PORT_FILES]
+    outputs = [PORT_FILES],
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
 )]
 struct GlobAgent {
     data: AgentData,
+    contract: InputContract,
 }
 
 #[async_trait]
 impl AsAgent for GlobAgent {
-    fn new(ma: ModularAgent, id: String, spec: AgentSpec) -> Result<Self, AgentError> {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
         Ok(Self {
             data: AgentData::new(ma, id, spec),
+            contract,
         })
     }
 
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
+    }
+
     async fn process(
         &mut self,
         ctx: AgentContext,
-        _port: String,
+        port: String,
         value: AgentValue,
     ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
         let pat = value
             .as_str()
             .ok_or_else(|| AgentError::InvalidValue("not a string".to_string()))?;
+        let pat = resolve(pat)?;
 
         let mut files = Vec::new();
 
-        for entry in glob(pat).map_err(|e| {
+        for entry in glob(&pat).map_err(|e| {
             AgentError::InvalidValue(format!("Failed to read glob pattern {}: {}", pat, e))
         })? {
             match entry {
@@ -70,7 +117,7 @@ impl AsAgent for GlobAgent {
         }
 
         let out_value = AgentValue::array(files.into());
-        self.output(ctx, PORT_FILES, out_value).await
+        self.output(self.traced(ctx), PORT_FILES, out_value).await
     }
 }
 
@@ -79,30 +126,42 @@ impl AsAgent for GlobAgent {
     title = "List Files",
     category = CATEGORY,
     inputs = [PORT_PATH],
-    outputs = [PORT_FILES]
+    outputs = [PORT_FILES],
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
 )]
 struct ListFilesAgent {
     data: AgentData,
+    contract: InputContract,
 }
 
 #[async_trait]
 impl AsAgent for ListFilesAgent {
-    fn new(ma: ModularAgent, id: String, spec: AgentSpec) -> Result<Self, AgentError> {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
         Ok(Self {
             data: AgentData::new(ma, id, spec),
+            contract,
         })
     }
 
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
+    }
+
     async fn process(
         &mut self,
         ctx: AgentContext,
-        _port: String,
+        port: String,
         value: AgentValue,
     ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
         let path = value
             .as_str()
             .ok_or_else(|| AgentError::InvalidValue("path is not a string".to_string()))?;
-        let path = Path::new(path);
+        let path = resolve(path)?;
+        let path = Path::new(&path);
 
         if !path.exists() {
             return Err(AgentError::InvalidValue(format!(
@@ -136,7 +195,7 @@ impl AsAgent for ListFilesAgent {
         }
 
         let out_value = AgentValue::array(files.into());
-        self.output(ctx, PORT_FILES, out_value).await
+        self.output(self.traced(ctx), PORT_FILES, out_value).await
     }
 }
 
@@ -145,30 +204,42 @@ impl AsAgent for ListFilesAgent {
     title = "Read Text File",
     category = CATEGORY,
     inputs = [PORT_PATH],
-    outputs = [PORT_STRING, PORT_DOC]
+    outputs = [PORT_STRING, PORT_DOC],
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
 )]
 struct ReadTextFileAgent {
     data: AgentData,
+    contract: InputContract,
 }
 
 #[async_trait]
 impl AsAgent for ReadTextFileAgent {
-    fn new(ma: ModularAgent, id: String, spec: AgentSpec) -> Result<Self, AgentError> {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
         Ok(Self {
             data: AgentData::new(ma, id, spec),
+            contract,
         })
     }
 
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
+    }
+
     async fn process(
         &mut self,
         ctx: AgentContext,
-        _port: String,
+        port: String,
         value: AgentValue,
     ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
         let path = value
             .as_str()
             .ok_or_else(|| AgentError::InvalidValue("path is not a string".into()))?;
-        let path = Path::new(path);
+        let path = resolve(path)?;
+        let path = Path::new(&path);
 
         if !path.exists() {
             return Err(AgentError::InvalidValue(format!(
@@ -189,13 +260,14 @@ impl AsAgent for ReadTextFileAgent {
         })?;
 
         let text = AgentValue::string(content);
-        self.output(ctx.clone(), PORT_STRING, text.clone()).await?;
+        self.output(self.traced(ctx.clone()), PORT_STRING, text.clone())
+            .await?;
 
         let out_doc = AgentValue::object(hashmap! {
             "path".into() => AgentValue::string(path.to_string_lossy().to_string()),
             "text".into() => text,
         });
-        self.output(ctx, PORT_DOC, out_doc).await
+        self.output(self.traced(ctx), PORT_DOC, out_doc).await
     }
 }
 
@@ -206,36 +278,56 @@ impl AsAgent for ReadTextFileAgent {
     inputs = [PORT_STRING, PORT_DOC],
     outputs = [PORT_UNIT],
     string_config(name = CONFIG_PATH),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
 )]
 struct WriteTextFileAgent {
     data: AgentData,
+    contract: InputContract,
+    // the path config, resolved
+    path: String,
 }
 
 #[async_trait]
 impl AsAgent for WriteTextFileAgent {
-    fn new(ma: ModularAgent, id: String, spec: AgentSpec) -> Result<Self, AgentError> {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
         Ok(Self {
             data: AgentData::new(ma, id, spec),
+            contract,
+            path: String::new(),
         })
     }
 
+    async fn start(&mut self) -> Result<(), AgentError> {
+        self.path = self.configs()?.get_string_resolved(CONFIG_PATH)?;
+        Ok(())
+    }
+
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()?;
+        self.path = self.configs()?.get_string_resolved(CONFIG_PATH)?;
+        Ok(())
+    }
+
     async fn process(
         &mut self,
         ctx: AgentContext,
         port: String,
         value: AgentValue,
     ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
         let (path, text) = if port == PORT_STRING {
-            let path = self.configs()?.get_string(CONFIG_PATH)?;
+            let path = self.path.clone();
             let text = value
                 .to_string()
                 .ok_or_else(|| AgentError::InvalidValue("Input value is not a string".into()))?;
             (path, text)
         } else if port == PORT_DOC {
-            let path = if let Some(path) = value.get_str("path") {
-                path.to_string()
-            } else {
-                self.configs()?.get_string(CONFIG_PATH)?
+            let path = match value.get_str("path") {
+                Some(path) => resolve(path)?,
+                None => self.path.clone(),
             };
             let text = value
                 .get_str("text")
@@ -257,11 +349,20 @@ impl AsAgent for WriteTextFileAgent {
             }
         }
 
+        let target = path.display().to_string();
+        audit(
+            self,
+            &ctx,
+            ACTION_WRITE_FILE,
+            &target,
+            &AgentValue::string(&text),
+        )
+        .await?;
         fs::write(path, text).map_err(|e| {
             AgentError::InvalidValue(format!("Failed to write file {}: {}", path.display(), e))
         })?;
 
-        self.output(ctx, PORT_DATA, value).await
+        self.output(self.traced(ctx), PORT_DATA, value).await
     }
 }
 
@@ -270,30 +371,42 @@ impl AsAgent for WriteTextFileAgent {
     title = "Read JSON File",
     category = CATEGORY,
     inputs = [PORT_PATH],
-    outputs = [PORT_VALUE, PORT_DOC]
+    outputs = [PORT_VALUE, PORT_DOC],
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
 )]
 struct ReadJsonFileAgent {
     data: AgentData,
+    contract: InputContract,
 }
 
 #[async_trait]
 impl AsAgent for ReadJsonFileAgent {
-    fn new(ma: ModularAgent, id: String, spec: AgentSpec) -> Result<Self, AgentError> {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
         Ok(Self {
             data: AgentData::new(ma, id, spec),
+            contract,
         })
     }
 
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
+    }
+
     async fn process(
         &mut self,
         ctx: AgentContext,
-        _port: String,
+        port: String,
         value: AgentValue,
     ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
         let path = value
             .as_str()
             .ok_or_else(|| AgentError::InvalidValue("path is not a string".into()))?;
-        let path = Path::new(path);
+        let path = resolve(path)?;
+        let path = Path::new(&path);
 
         if !path.exists() {
             return Err(AgentError::InvalidValue(format!(
@@ -322,13 +435,14 @@ impl AsAgent for ReadJsonFileAgent {
         })?;
 
         let value = AgentValue::from_json(json)?;
-        self.output(ctx.clone(), PORT_VALUE, value.clone()).await?;
+        self.output(self.traced(ctx.clone()), PORT_VALUE, value.clone())
+            .await?;
 
         let out_doc = AgentValue::object(hashmap! {
             "path".into() => AgentValue::string(path.to_string_lossy().to_string()),
             "value".into() => value,
         });
-        self.output(ctx, PORT_DOC, out_doc).await
+        self.output(self.traced(ctx), PORT_DOC, out_doc).await
     }
 }
 
@@ -339,33 +453,53 @@ impl AsAgent for ReadJsonFileAgent {
     inputs = [PORT_VALUE, PORT_DOC],
     outputs = [PORT_UNIT],
     string_config(name = CONFIG_PATH),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
 )]
 struct WriteJsonFileAgent {
     data: AgentData,
+    contract: InputContract,
+    // the path config, resolved
+    path: String,
 }
 
 #[async_trait]
 impl AsAgent for WriteJsonFileAgent {
-    fn new(ma: ModularAgent, id: String, spec: AgentSpec) -> Result<Self, AgentError> {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
         Ok(Self {
             data: AgentData::new(ma, id, spec),
+            contract,
+            path: String::new(),
         })
     }
 
+    async fn start(&mut self) -> Result<(), AgentError> {
+        self.path = self.configs()?.get_string_resolved(CONFIG_PATH)?;
+        Ok(())
+    }
+
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()?;
+        self.path = self.configs()?.get_string_resolved(CONFIG_PATH)?;
+        Ok(())
+    }
+
     async fn process(
         &mut self,
         ctx: AgentContext,
         port: String,
         value: AgentValue,
     ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
         let (path, value) = if port == PORT_VALUE {
-            let path = self.configs()?.get_string(CONFIG_PATH)?;
+            let path = self.path.clone();
             (path, value)
         } else if port == PORT_DOC {
-            let path = if let Some(path) = value.get_str("path") {
-                path.to_string()
-            } else {
-                self.configs()?.get_string(CONFIG_PATH)?
+            let path = match value.get_str("path") {
+                Some(path) => resolve(path)?,
+                None => self.path.clone(),
             };
             let value = value.get("value").ok_or_else(|| {
                 AgentError::InvalidValue("Input doc is missing 'value' field".into())
@@ -386,11 +520,14 @@ impl AsAgent for WriteJsonFileAgent {
             }
         }
 
+        let target = path.display().to_string();
+        audit(self, &ctx, ACTION_WRITE_FILE, &target, &value).await?;
         fs::write(path, value.to_json().to_string()).map_err(|e| {
             AgentError::InvalidValue(format!("Failed to write file {}: {}", path.display(), e))
         })?;
 
-        self.output(ctx, PORT_UNIT, AgentValue::unit()).await
+        self.output(self.traced(ctx), PORT_UNIT, AgentValue::unit())
+            .await
     }
 }
 
@@ -399,30 +536,42 @@ impl AsAgent for WriteJsonFileAgent {
     title = "Read JSONL File",
     category = CATEGORY,
     inputs = [PORT_PATH],
-    outputs = [PORT_ARRAY, PORT_DOC]
+    outputs = [PORT_ARRAY, PORT_DOC],
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
 )]
 struct ReadJsonlFileAgent {
     data: AgentData,
+    contract: InputContract,
 }
 
 #[async_trait]
 impl AsAgent for ReadJsonlFileAgent {
-    fn new(ma: ModularAgent, id: String, spec: AgentSpec) -> Result<Self, AgentError> {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
         Ok(Self {
             data: AgentData::new(ma, id, spec),
+            contract,
         })
     }
 
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
+    }
+
     async fn process(
         &mut self,
         ctx: AgentContext,
-        _port: String,
+        port: String,
         value: AgentValue,
     ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
         let path = value
             .as_str()
             .ok_or_else(|| AgentError::InvalidValue("path is not a string".into()))?;
-        let path = Path::new(path);
+        let path = resolve(path)?;
+        let path = Path::new(&path);
 
         if !path.exists() {
             return Err(AgentError::InvalidValue(format!(
@@ -452,14 +601,14 @@ impl AsAgent for ReadJsonlFileAgent {
         }
 
         let array_value = AgentValue::array(values.into());
-        self.output(ctx.clone(), PORT_ARRAY, array_value.clone())
+        self.output(self.traced(ctx.clone()), PORT_ARRAY, array_value.clone())
             .await?;
 
         let out_doc = AgentValue::object(hashmap! {
             "path".into() => AgentValue::string(path.to_string_lossy().to_string()),
             "value".into() => array_value,
         });
-        self.output(ctx, PORT_DOC, out_doc).await
+        self.output(self.traced(ctx), PORT_DOC, out_doc).await
     }
 }
 
@@ -470,33 +619,53 @@ impl AsAgent for ReadJsonlFileAgent {
     inputs = [PORT_VALUE, PORT_DOC],
     outputs = [PORT_UNIT],
     string_config(name = CONFIG_PATH),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
 )]
 struct WriteJsonlFileAgent {
     data: AgentData,
+    contract: InputContract,
+    // the path config, resolved
+    path: String,
 }
 
 #[async_trait]
 impl AsAgent for WriteJsonlFileAgent {
-    fn new(ma: ModularAgent, id: String, spec: AgentSpec) -> Result<Self, AgentError> {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
         Ok(Self {
             data: AgentData::new(ma, id, spec),
+            contract,
+            path: String::new(),
         })
     }
 
+    async fn start(&mut self) -> Result<(), AgentError> {
+        self.path = self.configs()?.get_string_resolved(CONFIG_PATH)?;
+        Ok(())
+    }
+
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()?;
+        self.path = self.configs()?.get_string_resolved(CONFIG_PATH)?;
+        Ok(())
+    }
+
     async fn process(
         &mut self,
         ctx: AgentContext,
         port: String,
         value: AgentValue,
     ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
         let (path, value) = if port == PORT_VALUE {
-            let path = self.configs()?.get_string(CONFIG_PATH)?;
+            let path = self.path.clone();
             (path, value)
         } else if port == PORT_DOC {
-            let path = if let Some(path) = value.get_str("path") {
-                path.to_string()
-            } else {
-                self.configs()?.get_string(CONFIG_PATH)?
+            let path = match value.get_str("path") {
+                Some(path) => resolve(path)?,
+                None => self.path.clone(),
             };
             let value = value.get("value").ok_or_else(|| {
                 AgentError::InvalidValue("Input doc is missing 'value' field".into())
@@ -526,6 +695,8 @@ impl AsAgent for WriteJsonlFileAgent {
             json_lines.push(value.to_json().to_string());
         }
 
+        let target = path.display().to_string();
+        audit(self, &ctx, ACTION_WRITE_FILE, &target, &value).await?;
         let mut f = fs::File::options()
             .write(true)
             .create(true)
@@ -543,7 +714,8 @@ impl AsAgent for WriteJsonlFileAgent {
             })?;
         }
 
-        self.output(ctx, PORT_UNIT, AgentValue::unit()).await
+        self.output(self.traced(ctx), PORT_UNIT, AgentValue::unit())
+            .await
     }
 }
 
@@ -554,33 +726,53 @@ impl AsAgent for WriteJsonlFileAgent {
     inputs = [PORT_VALUE, PORT_DOC],
     outputs = [PORT_UNIT],
     string_config(name = CONFIG_PATH),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
 )]
 struct AppendJsonlFileAgent {
     data: AgentData,
+    contract: InputContract,
+    // the path config, resolved
+    path: String,
 }
 
 #[async_trait]
 impl AsAgent for AppendJsonlFileAgent {
-    fn new(ma: ModularAgent, id: String, spec: AgentSpec) -> Result<Self, AgentError> {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
         Ok(Self {
             data: AgentData::new(ma, id, spec),
+            contract,
+            path: String::new(),
         })
     }
 
+    async fn start(&mut self) -> Result<(), AgentError> {
+        self.path = self.configs()?.get_string_resolved(CONFIG_PATH)?;
+        Ok(())
+    }
+
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.path = self.configs()?.get_string_resolved(CONFIG_PATH)?;
+        self.reload_contract()?;
+        Ok(())
+    }
+
     async fn process(
         &mut self,
         ctx: AgentContext,
         port: String,
         value: AgentValue,
     ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
         let (path, value) = if port == PORT_VALUE {
-            let path = self.configs()?.get_string(CONFIG_PATH)?;
+            let path = self.path.clone();
             (path, value)
         } else if port == PORT_DOC {
-            let path = if let Some(path) = value.get_str("path") {
-                path.to_string()
-            } else {
-                self.configs()?.get_string(CONFIG_PATH)?
+            let path = match value.get_str("path") {
+                Some(path) => resolve(path)?,
+                None => self.path.clone(),
             };
             let value = value.get("value").ok_or_else(|| {
                 AgentError::InvalidValue("Input doc is missing 'value' field".into())
@@ -610,6 +802,8 @@ impl AsAgent for AppendJsonlFileAgent {
             json_lines.push(value.to_json().to_string());
         }
 
+        let target = path.display().to_string();
+        audit(self, &ctx, ACTION_WRITE_FILE, &target, &value).await?;
         let mut f = fs::File::options()
             .append(true)
             .create(true)
@@ -627,6 +821,585 @@ impl AsAgent for AppendJsonlFileAgent {
             })?;
         }
 
-        self.output(ctx, PORT_UNIT, AgentValue::unit()).await
+        self.output(self.traced(ctx), PORT_UNIT, AgentValue::unit())
+            .await
+    }
+}
+
+/// Organize Files Agent
+///
+/// Plans moving the files given on `files` (an array of paths, or a directory whose files are
+/// organized) to the paths rendered from `template`, relative to `dest` (or to the directory
+/// of each file when empty), and outputs the plan as an array of `{from, to}` on `plan`.
+/// Nothing is moved until a value arrives on `apply`, which carries out the last plan and
+/// outputs the moves on `files`, with an `error` for those that failed.
+///
+/// The template is a Handlebars template with `name` (file name without extension), `ext`,
+/// `file_name`, `year`, `month`, `day` (of the modification time), `size` and `hash`
+/// (SHA-256, read only when the template uses it). Moves to paths that are taken get a
+/// numbered suffix (ex. `photo-1.jpg`), and a move whose target has appeared since the plan
+/// fails instead of overwriting it.
+#[modular_agent(
+    title = "Organize Files",
+    category = CATEGORY,
+    inputs = [PORT_FILES, PORT_APPLY],
+    outputs = [PORT_PLAN, PORT_FILES],
+    string_config(name = CONFIG_TEMPLATE, default = ORGANIZE_TEMPLATE_DEFAULT),
+    string_config(name = CONFIG_DEST, description = "base directory (empty: directory of each file)"),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
+)]
+struct OrganizeFilesAgent {
+    data: AgentData,
+    contract: InputContract,
+    plan: Vec<(PathBuf, PathBuf)>,
+    // the dest config, resolved
+    dest: String,
+}
+
+#[async_trait]
+impl AsAgent for OrganizeFilesAgent {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
+        Ok(Self {
+            data: AgentData::new(ma, id, spec),
+            contract,
+            plan: Vec::new(),
+            dest: String::new(),
+        })
+    }
+
+    async fn start(&mut self) -> Result<(), AgentError> {
+        self.dest = self.configs()?.get_string_resolved(CONFIG_DEST)?;
+        Ok(())
+    }
+
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()?;
+        self.dest = self.configs()?.get_string_resolved(CONFIG_DEST)?;
+        Ok(())
+    }
+
+    async fn process(
+        &mut self,
+        ctx: AgentContext,
+        port: String,
+        value: AgentValue,
+    ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
+        if port == PORT_FILES {
+            let config = self.configs()?;
+            let template = config.get_string_or(CONFIG_TEMPLATE, ORGANIZE_TEMPLATE_DEFAULT);
+            let dest = self.dest.clone();
+            // reading and hashing the files blocks
+            self.plan = tokio::task::spawn_blocking(move || {
+                let files = organize_sources(&value)?;
+                plan_organize(&files, &template, &dest)
+            })
+            .await
+            .map_err(|e| AgentError::InvalidValue(format!("Failed to plan moves: {}", e)))??;
+            let plan = self
+                .plan
+                .iter()
+                .map(|(from, to)| file_move_value(from, to, None))
+                .collect();
+            self.output(self.traced(ctx), PORT_PLAN, AgentValue::array(plan))
+                .await
+        } else if port == PORT_APPLY {
+            let plan = std::mem::take(&mut self.plan);
+            let mut moved = Vector::new();
+            for (from, to) in plan {
+                let target = from.display().to_string();
+                let payload = AgentValue::string(to.display().to_string());
+                let error = match audit(self, &ctx, ACTION_MOVE_FILE, &target, &payload).await {
+                    Ok(()) => {
+                        let (src, dst) = (from.clone(), to.clone());
+                        match tokio::task::spawn_blocking(move || move_file(&src, &dst)).await {
+                            Ok(Ok(())) => None,
+                            Ok(Err(e)) => {
+                                log::error!("Failed to move {}: {}", from.display(), e);
+                                Some(e.to_string())
+                            }
+                            Err(e) => Some(e.to_string()),
+                        }
+                    }
+                    Err(e) => Some(e.to_string()),
+                };
+                moved.push_back(file_move_value(&from, &to, error));
+            }
+            self.output(self.traced(ctx), PORT_FILES, AgentValue::array(moved))
+                .await
+        } else {
+            Err(AgentError::InvalidPin(port))
+        }
+    }
+}
+
+// The files to organize: an array of paths, or the files in a directory.
+fn organize_sources(value: &AgentValue) -> Result<Vec<PathBuf>, AgentError> {
+    if let Some(arr) = value.as_array() {
+        return arr
+            .iter()
+            .map(|v| {
+                v.as_str()
+                    .map(PathBuf::from)
+                    .ok_or_else(|| AgentError::InvalidValue("path is not a string".to_string()))
+            })
+            .collect();
+    }
+    let path = value
+        .as_str()
+        .ok_or_else(|| AgentError::InvalidValue("path is not a string".to_string()))?;
+    let path = Path::new(path);
+    if !path.is_dir() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+    let entries = fs::read_dir(path).map_err(|e| {
+        AgentError::InvalidValue(format!(
+            "Failed to read directory {}: {}",
+            path.display(),
+            e
+        ))
+    })?;
+    let mut files = Vec::new();
+    for entry in entries {
+        let entry = entry.map_err(|e| {
+            AgentError::InvalidValue(format!("Failed to read directory entry: {}", e))
+        })?;
+        if entry.path().is_file() {
+            files.push(entry.path());
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+fn plan_organize(
+    files: &[PathBuf],
+    template: &str,
+    dest: &str,
+) -> Result<Vec<(PathBuf, PathBuf)>, AgentError> {
+    let mut reg = handlebars_new();
+    let mut taken = HashSet::new();
+    let mut plan = Vec::new();
+    for from in files {
+        // `{{hash}}` is a helper, so that the file is read only when the template uses it
+        let path = from.clone();
+        reg.register_helper(
+            "hash",
+            Box::new(
+                move |_: &handlebars::Helper<'_>,
+                      _: &handlebars::Handlebars<'_>,
+                      _: &handlebars::Context,
+                      _: &mut handlebars::RenderContext<'_, '_>,
+                      out: &mut dyn handlebars::Output|
+                      -> handlebars::HelperResult {
+                    let hash = hash_file(&path).map_err(|e| {
+                        handlebars::RenderErrorReason::Other(format!(
+                            "Failed to read file {}: {}",
+                            path.display(),
+                            e
+                        ))
+                    })?;
+                    let hex: String = hash.iter().map(|b| format!("{:02x}", b)).collect();
+                    out.write(&hex)?;
+                    Ok(())
+                },
+            ),
+        );
+        let meta = fs::metadata(from).map_err(|e| {
+            AgentError::InvalidValue(format!("Failed to read file {}: {}", from.display(), e))
+        })?;
+        let modified: DateTime<Local> = meta
+            .modified()
+            .map(DateTime::from)
+            .unwrap_or_else(|_| Local::now());
+        let data = json!({
+            "name": from.file_stem().map(|s| s.to_string_lossy()).unwrap_or_default(),
+            "ext": from.extension().map(|s| s.to_string_lossy()).unwrap_or_default(),
+            "file_name": from.file_name().map(|s| s.to_string_lossy()).unwrap_or_default(),
+            "year": modified.format("%Y").to_string(),
+            "month": modified.format("%m").to_string(),
+            "day": modified.format("%d").to_string(),
+            "size": meta.len(),
+        });
+        let rendered = reg
+            .render_template(template, &data)
+            .map_err(|e| AgentError::InvalidValue(format!("Failed to render template: {}", e)))?;
+        let rendered = rendered.trim();
+        if rendered.is_empty() {
+            continue;
+        }
+
+        let base = if dest.is_empty() {
+            from.parent().map(Path::to_path_buf).unwrap_or_default()
+        } else {
+            PathBuf::from(dest)
+        };
+        let to = base.join(rendered);
+        if to == *from {
+            continue;
+        }
+        let to = unique_path(to, &taken);
+        taken.insert(to.clone());
+        plan.push((from.clone(), to));
+    }
+    Ok(plan)
+}
+
+// Adds a numbered suffix to `path` while it exists or is taken by another move.
+fn unique_path(path: PathBuf, taken: &HashSet<PathBuf>) -> PathBuf {
+    if !path.exists() && !taken.contains(&path) {
+        return path;
+    }
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let ext = path
+        .extension()
+        .map(|s| format!(".{}", s.to_string_lossy()))
+        .unwrap_or_default();
+    (1..)
+        .map(|i| path.with_file_name(format!("{}-{}{}", stem, i, ext)))
+        .find(|p| !p.exists() && !taken.contains(p))
+        .unwrap()
+}
+
+// Moves the file without ever overwriting `to`: fails if it exists.
+fn move_file(from: &Path, to: &Path) -> std::io::Result<()> {
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    // unlike rename, hard_link fails when the target exists
+    match fs::hard_link(from, to) {
+        Ok(()) => return fs::remove_file(from),
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => return Err(e),
+        // not supported, or across file systems
+        Err(_) => {}
+    }
+    let mut src = fs::File::open(from)?;
+    let mut dst = fs::File::create_new(to)?;
+    if let Err(e) = std::io::copy(&mut src, &mut dst) {
+        drop(dst);
+        let _ = fs::remove_file(to);
+        return Err(e);
+    }
+    fs::remove_file(from)
+}
+
+fn file_move_value(from: &Path, to: &Path, error: Option<String>) -> AgentValue {
+    let mut obj = hashmap! {
+        "from".to_string() => AgentValue::string(from.to_string_lossy()),
+        "to".to_string() => AgentValue::string(to.to_string_lossy()),
+    };
+    if let Some(error) = error {
+        obj.insert("error".to_string(), AgentValue::string(error));
+    }
+    AgentValue::object(obj)
+}
+
+/// Find Duplicates Agent
+///
+/// Scans the directory tree at the input path and outputs the groups of files with the same
+/// content on `duplicates`, as an array of arrays of paths. Files are grouped by size first,
+/// and only files sharing a size are hashed (SHA-256). Symbolic links are not followed.
+///
+/// While scanning, `{phase, done, total}` is output on `progress` every `progress every`
+/// files (0: never), with phase `scan` (no total) and then `hash`.
+#[modular_agent(
+    title = "Find Duplicates",
+    category = CATEGORY,
+    inputs = [PORT_PATH],
+    outputs = [PORT_DUPLICATES, PORT_PROGRESS],
+    integer_config(name = CONFIG_MIN_SIZE, default = 1, title = "min size", description = "bytes"),
+    integer_config(name = CONFIG_PROGRESS_EVERY, default = PROGRESS_EVERY_DEFAULT, title = "progress every"),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
+)]
+struct FindDuplicatesAgent {
+    data: AgentData,
+    contract: InputContract,
+}
+
+#[async_trait]
+impl AsAgent for FindDuplicatesAgent {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
+        Ok(Self {
+            data: AgentData::new(ma, id, spec),
+            contract,
+        })
+    }
+
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
+    }
+
+    async fn process(
+        &mut self,
+        ctx: AgentContext,
+        port: String,
+        value: AgentValue,
+    ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
+        let root = value
+            .as_str()
+            .ok_or_else(|| AgentError::InvalidValue("path is not a string".to_string()))?;
+        let root = PathBuf::from(resolve(root)?);
+        if !root.is_dir() {
+            return Err(AgentError::InvalidValue(format!(
+                "Path is not a directory: {}",
+                root.display()
+            )));
+        }
+
+        let config = self.configs()?;
+        let min_size = config.get_integer_or(CONFIG_MIN_SIZE, 1).max(0) as u64;
+        let progress_every = config
+            .get_integer_or(CONFIG_PROGRESS_EVERY, PROGRESS_EVERY_DEFAULT)
+            .max(0) as usize;
+
+        // Progress is sent from the scanning thread
+        let ma = self.ma().clone();
+        let agent_id = self.id().to_string();
+        let def_name = self.def_name().to_string();
+        let progress_ctx = ctx.clone();
+        let report = move |phase: &str, done: usize, total: Option<usize>| {
+            if progress_every == 0 || !done.is_multiple_of(progress_every) {
+                return;
+            }
+            let mut progress = hashmap! {
+                "phase".to_string() => AgentValue::string(phase),
+                "done".to_string() => AgentValue::integer(done as i64),
+            };
+            if let Some(total) = total {
+                progress.insert("total".to_string(), AgentValue::integer(total as i64));
+            }
+            if let Err(e) = ma.try_send_agent_out(
+                agent_id.clone(),
+                stamp(progress_ctx.clone(), &agent_id, &def_name),
+                PORT_PROGRESS.to_string(),
+                AgentValue::object(progress),
+            ) {
+                log::error!("Failed to send progress: {}", e);
+            }
+        };
+
+        let groups = tokio::task::spawn_blocking(move || find_duplicates(&root, min_size, report))
+            .await
+            .map_err(|e| AgentError::InvalidValue(format!("Failed to scan files: {}", e)))??;
+
+        let groups = groups
+            .into_iter()
+            .map(|group| {
+                AgentValue::array(
+                    group
+                        .iter()
+                        .map(|path| AgentValue::string(path.to_string_lossy()))
+                        .collect(),
+                )
+            })
+            .collect();
+        self.output(self.traced(ctx), PORT_DUPLICATES, AgentValue::array(groups))
+            .await
+    }
+}
+
+// Groups of files with the same content under `root`, each sorted by path.
+fn find_duplicates(
+    root: &Path,
+    min_size: u64,
+    mut report: impl FnMut(&str, usize, Option<usize>),
+) -> Result<Vec<Vec<PathBuf>>, AgentError> {
+    // scan
+    let mut by_size: BTreeMap<u64, Vec<PathBuf>> = BTreeMap::new();
+    let mut dirs = vec![root.to_path_buf()];
+    let mut scanned = 0;
+    while let Some(dir) = dirs.pop() {
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(e) if dir == root => {
+                return Err(AgentError::InvalidValue(format!(
+                    "Failed to read directory {}: {}",
+                    dir.display(),
+                    e
+                )));
+            }
+            // an unreadable subdirectory does not stop the scan
+            Err(e) => {
+                log::warn!("Failed to read directory {}: {}", dir.display(), e);
+                continue;
+            }
+        };
+        for entry in entries {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    log::warn!("Failed to read directory entry in {}: {}", dir.display(), e);
+                    continue;
+                }
+            };
+            let file_type = match entry.file_type() {
+                Ok(file_type) => file_type,
+                Err(e) => {
+                    log::warn!(
+                        "Failed to read file type of {}: {}",
+                        entry.path().display(),
+                        e
+                    );
+                    continue;
+                }
+            };
+            if file_type.is_dir() {
+                dirs.push(entry.path());
+            } else if file_type.is_file() {
+                let size = entry.metadata().map(|m| m.len()).unwrap_or(0);
+                if size >= min_size {
+                    by_size.entry(size).or_default().push(entry.path());
+                }
+                scanned += 1;
+                report("scan", scanned, None);
+            }
+        }
+    }
+
+    // hash the files sharing a size
+    let candidates: Vec<Vec<PathBuf>> = by_size
+        .into_values()
+        .filter(|paths| paths.len() > 1)
+        .collect();
+    let total = candidates.iter().map(|paths| paths.len()).sum();
+    let mut hashed = 0;
+    let mut groups = Vec::new();
+    for paths in candidates {
+        let mut by_hash: BTreeMap<Vec<u8>, Vec<PathBuf>> = BTreeMap::new();
+        for path in paths {
+            match hash_file(&path) {
+                Ok(hash) => by_hash.entry(hash).or_default().push(path),
+                // files may be removed or unreadable while scanning
+                Err(e) => log::warn!("Failed to hash {}: {}", path.display(), e),
+            }
+            hashed += 1;
+            report("hash", hashed, Some(total));
+        }
+        groups.extend(by_hash.into_values().filter(|paths| paths.len() > 1));
+    }
+    for group in groups.iter_mut() {
+        group.sort();
+    }
+    groups.sort();
+    Ok(groups)
+}
+
+fn hash_file(path: &Path) -> std::io::Result<Vec<u8>> {
+    let mut file = fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut file, &mut hasher)?;
+    Ok(hasher.finalize().to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("modular_agent_std_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_plan_organize() {
+        let dir = temp_dir("organize");
+        fs::write(dir.join("a.txt"), "a").unwrap();
+        fs::write(dir.join("b.jpg"), "b").unwrap();
+        fs::create_dir_all(dir.join("txt")).unwrap();
+        fs::write(dir.join("txt").join("a.txt"), "taken").unwrap();
+
+        let files = organize_sources(&AgentValue::string(dir.to_string_lossy())).unwrap();
+        assert_eq!(files, vec![dir.join("a.txt"), dir.join("b.jpg")]);
+
+        let plan = plan_organize(&files, "{{ext}}/{{file_name}}", "").unwrap();
+        assert_eq!(
+            plan,
+            vec![
+                (dir.join("a.txt"), dir.join("txt").join("a-1.txt")),
+                (dir.join("b.jpg"), dir.join("jpg").join("b.jpg")),
+            ]
+        );
+        // nothing is moved by planning
+        assert!(dir.join("a.txt").exists());
+
+        for (from, to) in &plan {
+            move_file(from, to).unwrap();
+        }
+        assert_eq!(
+            fs::read_to_string(dir.join("txt").join("a-1.txt")).unwrap(),
+            "a"
+        );
+        assert!(!dir.join("b.jpg").exists());
+
+        // a target that appeared after the plan is not overwritten
+        fs::write(dir.join("c.txt"), "c").unwrap();
+        let plan = plan_organize(&[dir.join("c.txt")], "{{ext}}/c.txt", "").unwrap();
+        fs::write(dir.join("txt").join("c.txt"), "new").unwrap();
+        let err = move_file(&plan[0].0, &plan[0].1).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::AlreadyExists);
+        assert_eq!(
+            fs::read_to_string(dir.join("txt").join("c.txt")).unwrap(),
+            "new"
+        );
+        assert!(dir.join("c.txt").exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_plan_organize_by_hash() {
+        let dir = temp_dir("organize_hash");
+        fs::write(dir.join("a.txt"), "same").unwrap();
+        fs::write(dir.join("b.txt"), "same").unwrap();
+        let files = vec![dir.join("a.txt"), dir.join("b.txt")];
+
+        let dest = dir.join("out");
+        let plan = plan_organize(&files, "{{hash}}.{{ext}}", &dest.to_string_lossy()).unwrap();
+        let hash = format!("{:x}", Sha256::digest(b"same"));
+        assert_eq!(plan[0].1, dest.join(format!("{}.txt", hash)));
+        assert_eq!(plan[1].1, dest.join(format!("{}-1.txt", hash)));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_find_duplicates() {
+        let dir = temp_dir("duplicates");
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::write(dir.join("a.txt"), "same").unwrap();
+        fs::write(dir.join("sub").join("b.txt"), "same").unwrap();
+        fs::write(dir.join("c.txt"), "diff").unwrap();
+        fs::write(dir.join("d.txt"), "other content").unwrap();
+        fs::write(dir.join("e.txt"), "").unwrap();
+        fs::write(dir.join("f.txt"), "").unwrap();
+
+        let mut progress = Vec::new();
+        let groups = find_duplicates(&dir, 1, |phase, done, total| {
+            progress.push((phase.to_string(), done, total))
+        })
+        .unwrap();
+        assert_eq!(
+            groups,
+            vec![vec![dir.join("a.txt"), dir.join("sub").join("b.txt")]]
+        );
+        // all 6 files are scanned, and only the 3 files of size 4 are hashed, as the
+        // empty ones are below min_size
+        assert_eq!(progress.iter().filter(|(p, _, _)| p == "scan").count(), 6);
+        assert_eq!(progress.last(), Some(&("hash".to_string(), 3, Some(3))));
+
+        fs::remove_dir_all(&dir).unwrap();
     }
 }
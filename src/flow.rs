@@ -0,0 +1,1281 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering as AtomicOrdering};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+use im::hashmap;
+use modular_agent_core::{
+    Agent, AgentContext, AgentData, AgentError, AgentOutput, AgentSpec, AgentStatus, AgentValue,
+    AsAgent, ModularAgent, async_trait, modular_agent,
+};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::tcp::OwnedReadHalf;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast;
+use tokio::task::{JoinHandle, JoinSet};
+
+use crate::contract::{CONFIG_INPUT_CONTRACT, ContractAgent, InputContract, contract_agents};
+use crate::provenance::{Traced, stamp};
+use crate::scheduler::{Timer, schedule};
+use crate::string::{handlebars_new, json_escape};
+use crate::time::parse_duration_to_ms;
+
+const CATEGORY: &str = "Std/Flow";
+
+const PORT_ABORTED: &str = "aborted";
+const PORT_COMMIT: &str = "commit";
+const PORT_COMMITTED: &str = "committed";
+const PORT_COMPENSATION: &str = "compensation";
+const PORT_FAILURE: &str = "failure";
+const PORT_IN1: &str = "in1";
+const PORT_IN2: &str = "in2";
+const PORT_NO_QUORUM: &str = "no_quorum";
+const PORT_STATUS: &str = "status";
+const PORT_TIMEOUT: &str = "timeout";
+const PORT_VALUE: &str = "value";
+
+const CONFIG_ADDRESS: &str = "address";
+const CONFIG_COMPENSATION: &str = "compensation";
+const CONFIG_MODE: &str = "mode";
+const CONFIG_N: &str = "n";
+const CONFIG_RECONNECT_SEC: &str = "reconnect_sec";
+const CONFIG_STEP: &str = "step";
+const CONFIG_TIMEOUT: &str = "timeout";
+const CONFIG_TOPIC: &str = "topic";
+const CONFIG_TOPICS: &str = "topics";
+const CONFIG_TTL: &str = "ttl";
+const CONFIG_WITH_TOPIC: &str = "with_topic";
+
+const ADDRESS_DEFAULT: &str = "127.0.0.1:7878";
+const MODE_ALL: &str = "all";
+const MODE_CONNECT: &str = "connect";
+const MODE_FIRST: &str = "first";
+const MODE_LISTEN: &str = "listen";
+const MODE_MAJORITY: &str = "majority";
+const QUORUM_TIMEOUT_DEFAULT: &str = "30s";
+const RECONNECT_SEC_DEFAULT: i64 = 5;
+const TTL_DEFAULT: &str = "1h";
+const BRIDGE_CHANNEL_CAPACITY: usize = 1024;
+const BRIDGE_MAX_LINE_BYTES: u64 = 1024 * 1024;
+
+struct Subscriber {
+    // id of the Subscribe or Bridge agent
+    agent_id: String,
+    pattern: String,
+    target: SubscriberTarget,
+}
+
+enum SubscriberTarget {
+    Agent { ma: ModularAgent, with_topic: bool },
+    Bridge(broadcast::Sender<BridgeMessage>),
+}
+
+// A message to the peers of a Bridge: topic, value, and the id of the peer it came from
+type BridgeMessage = (String, AgentValue, Option<u64>);
+
+// Subscribers of all running Subscribe and Bridge agents in the process
+static SUBSCRIBERS: LazyLock<Mutex<Vec<Subscriber>>> = LazyLock::new(Default::default);
+
+fn unsubscribe(agent_id: &str) {
+    SUBSCRIBERS
+        .lock()
+        .unwrap()
+        .retain(|s| s.agent_id != agent_id);
+}
+
+/// Delivers a value to the subscribers whose pattern matches `topic`.
+///
+/// The subscriber `origin` (a Bridge the value came from) is skipped to avoid echoing
+/// values back to where they came from.
+async fn publish(
+    topic: &str,
+    ctx: AgentContext,
+    value: AgentValue,
+    origin: Option<&str>,
+) -> Result<(), AgentError> {
+    let mut targets = Vec::new();
+    {
+        let subscribers = SUBSCRIBERS.lock().unwrap();
+        for s in subscribers.iter() {
+            if Some(s.agent_id.as_str()) == origin || !topic_matches(&s.pattern, topic) {
+                continue;
+            }
+            match &s.target {
+                SubscriberTarget::Agent { ma, with_topic } => {
+                    let value = if *with_topic {
+                        AgentValue::object(im::hashmap! {
+                            "topic".to_string() => AgentValue::string(topic),
+                            "value".to_string() => value.clone(),
+                        })
+                    } else {
+                        value.clone()
+                    };
+                    targets.push((ma.clone(), s.agent_id.clone(), value));
+                }
+                SubscriberTarget::Bridge(tx) => {
+                    // no connected peer is not an error
+                    let _ = tx.send((topic.to_string(), value.clone(), None));
+                }
+            }
+        }
+    }
+
+    // a failing subscriber does not keep the message from the others
+    for (ma, agent_id, value) in targets {
+        if let Err(e) = ma
+            .send_agent_out(agent_id.clone(), ctx.clone(), PORT_VALUE.to_string(), value)
+            .await
+        {
+            log::error!(
+                "Failed to deliver {} to subscriber {}: {}",
+                topic,
+                agent_id,
+                e
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Returns true if `topic` matches the MQTT-style `pattern`.
+///
+/// Levels are separated by `/`. `+` matches exactly one level and
+/// `#` (only as the last level) matches any number of remaining levels, including none.
+fn topic_matches(pattern: &str, topic: &str) -> bool {
+    let mut topic_levels = topic.split('/');
+    for p in pattern.split('/') {
+        if p == "#" {
+            return true;
+        }
+        match topic_levels.next() {
+            Some(t) if p == "+" || p == t => {}
+            _ => return false,
+        }
+    }
+    topic_levels.next().is_none()
+}
+
+contract_agents!(
+    PublishAgent,
+    QuorumAgent,
+    SagaBeginAgent,
+    SagaStepAgent,
+    SagaCompensateAgent
+);
+
+/// Publishes input values to a topic.
+///
+/// Every running Subscribe agent whose topic pattern matches receives the value,
+/// in any preset of the same process, and so does every Bridge forwarding the topic.
+/// The context is passed along.
+#[modular_agent(
+    title = "Publish",
+    category = CATEGORY,
+    inputs = [PORT_VALUE],
+    string_config(name = CONFIG_TOPIC),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
+    hint(color=4),
+)]
+struct PublishAgent {
+    data: AgentData,
+    contract: InputContract,
+}
+
+#[async_trait]
+impl AsAgent for PublishAgent {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
+        Ok(Self {
+            data: AgentData::new(ma, id, spec),
+            contract,
+        })
+    }
+
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
+    }
+
+    async fn process(
+        &mut self,
+        ctx: AgentContext,
+        port: String,
+        value: AgentValue,
+    ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
+        let topic = self.configs()?.get_string_or_default(CONFIG_TOPIC);
+        if topic.is_empty() || topic.contains(['+', '#']) {
+            return Err(AgentError::InvalidConfig(format!(
+                "Invalid topic to publish: '{}'",
+                topic
+            )));
+        }
+
+        publish(&topic, self.traced(ctx), value, None).await
+    }
+}
+
+/// Emits the values published to topics matching its topic pattern.
+///
+/// The pattern may use MQTT-style wildcards: `+` for one level and `#` for the rest
+/// (e.g. `sensors/+/temp`, `sensors/#`). When `with_topic` is true,
+/// `{topic, value}` is emitted instead of the bare value.
+#[modular_agent(
+    title = "Subscribe",
+    category = CATEGORY,
+    outputs = [PORT_VALUE],
+    string_config(name = CONFIG_TOPIC, description = "+: one level, #: remaining levels"),
+    boolean_config(name = CONFIG_WITH_TOPIC, title = "with topic"),
+    hint(color=4),
+)]
+struct SubscribeAgent {
+    data: AgentData,
+}
+
+impl SubscribeAgent {
+    fn subscribe(&self) -> Result<(), AgentError> {
+        let config = self.configs()?;
+        let pattern = config.get_string_or_default(CONFIG_TOPIC);
+        let with_topic = config.get_bool_or_default(CONFIG_WITH_TOPIC);
+
+        let mut subscribers = SUBSCRIBERS.lock().unwrap();
+        subscribers.retain(|s| s.agent_id != self.id());
+        if !pattern.is_empty() {
+            subscribers.push(Subscriber {
+                agent_id: self.id().to_string(),
+                pattern,
+                target: SubscriberTarget::Agent {
+                    ma: self.ma().clone(),
+                    with_topic,
+                },
+            });
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl AsAgent for SubscribeAgent {
+    fn new(ma: ModularAgent, id: String, spec: AgentSpec) -> Result<Self, AgentError> {
+        Ok(Self {
+            data: AgentData::new(ma, id, spec),
+        })
+    }
+
+    async fn start(&mut self) -> Result<(), AgentError> {
+        self.subscribe()
+    }
+
+    async fn stop(&mut self) -> Result<(), AgentError> {
+        unsubscribe(self.id());
+        Ok(())
+    }
+
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        if *self.status() == AgentStatus::Start {
+            self.subscribe()?;
+        }
+        Ok(())
+    }
+}
+
+/// Connects the bus to another process over TCP.
+///
+/// Values published locally to topics matching `topics` (comma-separated patterns)
+/// are sent to the peer, and values received from the peer are published locally,
+/// so a Publish on one machine reaches the Subscribe agents on another.
+/// In `listen` mode the agent accepts any number of peers on `address`, and relays
+/// the values of forwarded topics from each peer to the others; failing to listen
+/// fails the start, and stopping disconnects the peers. In `connect` mode it connects
+/// to `address` and reconnects after `reconnect_sec` when the connection fails or is
+/// closed.
+///
+/// Messages are newline-delimited JSON objects `{"topic": ..., "value": ...}`, of up
+/// to 1 MiB; a peer sending a longer line is disconnected.
+/// `connected` and `disconnected` are emitted on `status`.
+#[modular_agent(
+    title = "Bridge",
+    category = CATEGORY,
+    outputs = [PORT_STATUS],
+    string_config(name = CONFIG_MODE, default = MODE_CONNECT, description = "listen, connect"),
+    string_config(name = CONFIG_ADDRESS, default = ADDRESS_DEFAULT),
+    string_config(name = CONFIG_TOPICS, default = "#", description = "topic patterns to forward (comma separated)"),
+    integer_config(name = CONFIG_RECONNECT_SEC, default = RECONNECT_SEC_DEFAULT, title = "reconnect (sec)"),
+    hint(color=4),
+)]
+struct BridgeAgent {
+    data: AgentData,
+    handle: Option<JoinHandle<()>>,
+    // in listen mode, the address and its listener, kept while the address is unchanged
+    listener: Option<(String, Arc<TcpListener>)>,
+    // in listen mode, the tasks of the connected peers; taken out to stop them
+    peers: Arc<Mutex<Option<JoinSet<()>>>>,
+}
+
+impl BridgeAgent {
+    // Returns the listener on `address`, binding it if needed, so that a bad or busy
+    // address fails the start.
+    fn listener(&mut self, address: &str) -> Result<Arc<TcpListener>, AgentError> {
+        if let Some((bound, listener)) = &self.listener
+            && bound == address
+        {
+            return Ok(listener.clone());
+        }
+        self.listener = None;
+        let _guard = self.runtime().enter();
+        let listener = std::net::TcpListener::bind(address)
+            .and_then(|listener| {
+                listener.set_nonblocking(true)?;
+                TcpListener::from_std(listener)
+            })
+            .map_err(|e| {
+                AgentError::InvalidConfig(format!("Failed to listen on {}: {}", address, e))
+            })?;
+        let listener = Arc::new(listener);
+        self.listener = Some((address.to_string(), listener.clone()));
+        Ok(listener)
+    }
+
+    fn start_bridge(&mut self) -> Result<(), AgentError> {
+        let config = self.configs()?;
+        let mode = config.get_string_or(CONFIG_MODE, MODE_CONNECT);
+        let address = config.get_string_or(CONFIG_ADDRESS, ADDRESS_DEFAULT);
+        let topics = config.get_string_or(CONFIG_TOPICS, "#");
+        let reconnect = Duration::from_secs(
+            config
+                .get_integer_or(CONFIG_RECONNECT_SEC, RECONNECT_SEC_DEFAULT)
+                .max(1) as u64,
+        );
+        let listen = match mode.trim() {
+            MODE_LISTEN => true,
+            "" | MODE_CONNECT => false,
+            other => {
+                return Err(AgentError::InvalidConfig(format!(
+                    "Unknown mode: {}",
+                    other
+                )));
+            }
+        };
+
+        let listener = if listen {
+            Some(self.listener(&address)?)
+        } else {
+            self.listener = None;
+            None
+        };
+
+        let patterns: Arc<Vec<String>> = Arc::new(
+            topics
+                .split(',')
+                .map(|s| s.trim())
+                .filter(|s| !s.is_empty())
+                .map(|s| s.to_string())
+                .collect(),
+        );
+        let (tx, _) = broadcast::channel(BRIDGE_CHANNEL_CAPACITY);
+        {
+            let mut subscribers = SUBSCRIBERS.lock().unwrap();
+            subscribers.retain(|s| s.agent_id != self.id());
+            for pattern in patterns.iter() {
+                subscribers.push(Subscriber {
+                    agent_id: self.id().to_string(),
+                    pattern: pattern.clone(),
+                    target: SubscriberTarget::Bridge(tx.clone()),
+                });
+            }
+        }
+
+        let peers = Arc::new(Mutex::new(Some(JoinSet::new())));
+        self.peers = peers.clone();
+
+        let ma = self.ma().clone();
+        let agent_id = self.id().to_string();
+        let handle = self.runtime().spawn(async move {
+            if let Some(listener) = listener {
+                let mut next_id = 0;
+                loop {
+                    match listener.accept().await {
+                        Ok((stream, _)) => {
+                            let mut peers = peers.lock().unwrap();
+                            // the bridge is stopping
+                            let Some(peers) = peers.as_mut() else {
+                                break;
+                            };
+                            // forget the peers that have disconnected
+                            while peers.try_join_next().is_some() {}
+                            next_id += 1;
+                            let peer = Peer {
+                                ma: ma.clone(),
+                                agent_id: agent_id.clone(),
+                                id: next_id,
+                                relay: Some((tx.clone(), patterns.clone())),
+                            };
+                            let rx = tx.subscribe();
+                            peers.spawn(async move { peer.run(stream, rx).await });
+                        }
+                        Err(e) => {
+                            log::error!("Bridge '{}' failed to accept: {}", agent_id, e);
+                        }
+                    }
+                }
+            } else {
+                let peer = Peer {
+                    ma: ma.clone(),
+                    agent_id: agent_id.clone(),
+                    id: 0,
+                    relay: None,
+                };
+                loop {
+                    match TcpStream::connect(&address).await {
+                        Ok(stream) => peer.run(stream, tx.subscribe()).await,
+                        Err(e) => {
+                            log::warn!(
+                                "Bridge '{}' failed to connect to {}: {}",
+                                agent_id,
+                                address,
+                                e
+                            );
+                        }
+                    }
+                    tokio::time::sleep(reconnect).await;
+                }
+            }
+        });
+        self.handle = Some(handle);
+        Ok(())
+    }
+
+    fn stop_bridge(&mut self) {
+        unsubscribe(self.id());
+        if let Some(handle) = self.handle.take() {
+            handle.abort();
+        }
+        if let Some(mut peers) = self.peers.lock().unwrap().take() {
+            peers.abort_all();
+        }
+    }
+}
+
+#[async_trait]
+impl AsAgent for BridgeAgent {
+    fn new(ma: ModularAgent, id: String, spec: AgentSpec) -> Result<Self, AgentError> {
+        Ok(Self {
+            data: AgentData::new(ma, id, spec),
+            handle: None,
+            listener: None,
+            peers: Default::default(),
+        })
+    }
+
+    async fn start(&mut self) -> Result<(), AgentError> {
+        self.start_bridge()
+    }
+
+    async fn stop(&mut self) -> Result<(), AgentError> {
+        unsubscribe(self.id());
+        self.listener = None;
+        if let Some(handle) = self.handle.take() {
+            handle.abort();
+            // the task owns a listener too; wait for it to close the port
+            let _ = handle.await;
+        }
+        let peers = self.peers.lock().unwrap().take();
+        if let Some(mut peers) = peers {
+            peers.shutdown().await;
+        }
+        Ok(())
+    }
+
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        if *self.status() == AgentStatus::Start {
+            self.stop_bridge();
+            self.start_bridge()?;
+        }
+        Ok(())
+    }
+}
+
+// A connection of a Bridge agent
+struct Peer {
+    ma: ModularAgent,
+    agent_id: String,
+    // id of the peer among those of a listening Bridge
+    id: u64,
+    // in listen mode, the channel to the other peers and the forwarded topic patterns
+    relay: Option<(broadcast::Sender<BridgeMessage>, Arc<Vec<String>>)>,
+}
+
+impl Peer {
+    async fn run(&self, stream: TcpStream, mut rx: broadcast::Receiver<BridgeMessage>) {
+        self.send_status("connected");
+
+        let (reader, mut writer) = stream.into_split();
+        let mut reader = BufReader::new(reader);
+        let mut buf = Vec::new();
+        loop {
+            tokio::select! {
+                msg = rx.recv() => {
+                    let (topic, value) = match msg {
+                        Ok((_, _, Some(from))) if from == self.id => continue,
+                        Ok((topic, value, _)) => (topic, value),
+                        Err(broadcast::error::RecvError::Lagged(n)) => {
+                            log::warn!("Bridge '{}' dropped {} messages", self.agent_id, n);
+                            continue;
+                        }
+                        Err(broadcast::error::RecvError::Closed) => break,
+                    };
+                    let mut line = match encode_message(&topic, &value) {
+                        Ok(line) => line,
+                        Err(e) => {
+                            log::error!("Bridge '{}' failed to encode message: {}", self.agent_id, e);
+                            continue;
+                        }
+                    };
+                    line.push('\n');
+                    if let Err(e) = writer.write_all(line.as_bytes()).await {
+                        log::warn!("Bridge '{}' failed to send: {}", self.agent_id, e);
+                        break;
+                    }
+                }
+                read = read_line(&mut reader, &mut buf) => {
+                    match read {
+                        Ok(0) if buf.is_empty() => break,
+                        Ok(_) => {}
+                        Err(e) => {
+                            log::warn!("Bridge '{}' failed to receive: {}", self.agent_id, e);
+                            break;
+                        }
+                    }
+                    if buf.len() as u64 > BRIDGE_MAX_LINE_BYTES && !buf.ends_with(b"\n") {
+                        log::warn!("Bridge '{}' received a too long message", self.agent_id);
+                        break;
+                    }
+                    let line = String::from_utf8_lossy(&buf).into_owned();
+                    buf.clear();
+                    if line.trim().is_empty() {
+                        continue;
+                    }
+                    let result = match decode_message(&line) {
+                        Ok((topic, value)) => {
+                            self.relay(&topic, &value);
+                            publish(&topic, AgentContext::new(), value, Some(&self.agent_id)).await
+                        }
+                        Err(e) => Err(e),
+                    };
+                    if let Err(e) = result {
+                        log::error!("Bridge '{}' failed to publish message: {}", self.agent_id, e);
+                    }
+                }
+            }
+        }
+
+        self.send_status("disconnected");
+    }
+
+    // Sends a message from this peer to the other peers of a listening Bridge.
+    fn relay(&self, topic: &str, value: &AgentValue) {
+        let Some((tx, patterns)) = &self.relay else {
+            return;
+        };
+        if patterns.iter().any(|p| topic_matches(p, topic)) {
+            // no other peer is not an error
+            let _ = tx.send((topic.to_string(), value.clone(), Some(self.id)));
+        }
+    }
+
+    fn send_status(&self, status: &str) {
+        if let Err(e) = self.ma.try_send_agent_out(
+            self.agent_id.clone(),
+            AgentContext::new(),
+            PORT_STATUS.to_string(),
+            AgentValue::string(status),
+        ) {
+            log::error!("Failed to send bridge status: {}", e);
+        }
+    }
+}
+
+// Reads a line into `buf`, stopping after BRIDGE_MAX_LINE_BYTES. Cancel safe, as the
+// bytes read so far stay in `buf`.
+async fn read_line(
+    reader: &mut BufReader<OwnedReadHalf>,
+    buf: &mut Vec<u8>,
+) -> std::io::Result<usize> {
+    let limit = (BRIDGE_MAX_LINE_BYTES + 1).saturating_sub(buf.len() as u64);
+    (&mut *reader).take(limit).read_until(b'\n', buf).await
+}
+
+fn encode_message(topic: &str, value: &AgentValue) -> Result<String, AgentError> {
+    let msg = serde_json::json!({ "topic": topic, "value": value });
+    serde_json::to_string(&msg)
+        .map_err(|e| AgentError::InvalidValue(format!("Failed to serialize message: {}", e)))
+}
+
+fn decode_message(line: &str) -> Result<(String, AgentValue), AgentError> {
+    let mut msg: serde_json::Value = serde_json::from_str(line)
+        .map_err(|e| AgentError::InvalidValue(format!("Invalid message: {}", e)))?;
+    let topic = msg
+        .get("topic")
+        .and_then(|t| t.as_str())
+        .filter(|t| !t.is_empty() && !t.contains(['+', '#']))
+        .ok_or_else(|| AgentError::InvalidValue("Invalid message topic".into()))?
+        .to_string();
+    let value = AgentValue::from_json(msg["value"].take())?;
+    Ok((topic, value))
+}
+
+// Quorum
+
+// Rounds without a timeout are forgotten after this, even if some branches never responded
+const QUORUM_ROUND_TTL_DEFAULT: Duration = Duration::from_secs(600);
+
+// Returns how long a round is kept. Rounds outlive their timeout, so that late responses
+// of a decided round are still ignored rather than starting a new round.
+fn quorum_round_ttl(timeout: Option<Duration>) -> Duration {
+    timeout.map_or(QUORUM_ROUND_TTL_DEFAULT, |timeout| {
+        timeout.saturating_mul(2)
+    })
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum QuorumMode {
+    First,
+    Majority,
+    All,
+}
+
+impl QuorumMode {
+    fn parse(mode: &str) -> Result<Self, AgentError> {
+        match mode.trim() {
+            "" | MODE_FIRST => Ok(Self::First),
+            MODE_MAJORITY => Ok(Self::Majority),
+            MODE_ALL => Ok(Self::All),
+            other => Err(AgentError::InvalidConfig(format!(
+                "Unknown mode: {}",
+                other
+            ))),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum QuorumDecision {
+    Value(AgentValue),
+    // the responses, when the branches cannot agree
+    NoQuorum(AgentValue),
+}
+
+// The responses of the branches to one context.
+struct QuorumRound {
+    started: Instant,
+    responses: Vec<Option<AgentValue>>,
+    decided: bool,
+}
+
+// The responses in branch order, with unit for the branches that have not responded.
+fn quorum_responses(responses: &[Option<AgentValue>]) -> AgentValue {
+    AgentValue::array(
+        responses
+            .iter()
+            .map(|r| r.clone().unwrap_or_default())
+            .collect(),
+    )
+}
+
+// Decides a round from the responses so far, or returns None to keep waiting.
+fn quorum_decide(mode: QuorumMode, responses: &[Option<AgentValue>]) -> Option<QuorumDecision> {
+    let n = responses.len();
+    let received: Vec<&AgentValue> = responses.iter().flatten().collect();
+    match mode {
+        QuorumMode::First => received
+            .first()
+            .map(|v| QuorumDecision::Value((*v).clone())),
+        QuorumMode::Majority => {
+            let mut best: Option<(&AgentValue, usize)> = None;
+            for v in &received {
+                let count = received.iter().filter(|w| *w == v).count();
+                if best.is_none_or(|(_, c)| count > c) {
+                    best = Some((v, count));
+                }
+            }
+            let (value, count) = best?;
+            if count * 2 > n {
+                Some(QuorumDecision::Value(value.clone()))
+            } else if (count + n - received.len()) * 2 <= n {
+                // no value can reach a majority with the responses left
+                Some(QuorumDecision::NoQuorum(quorum_responses(responses)))
+            } else {
+                None
+            }
+        }
+        QuorumMode::All => {
+            (received.len() == n).then(|| QuorumDecision::Value(quorum_responses(responses)))
+        }
+    }
+}
+
+/// Fans in redundant branches, and decides as soon as enough of them respond.
+///
+/// Responses on `in1` to `inN` are matched by context key, so the branches must pass the
+/// context of the request on. Depending on `mode`:
+///
+/// - `first`: the first response is output on `value`
+/// - `majority`: the response equal in more than half of the branches is output on
+///   `value`. When no response can reach a majority, the responses are output on
+///   `no_quorum`
+/// - `all`: the responses of all branches are output as an array on `value`
+///
+/// When a round is not decided within `timeout` (empty: no timeout), the responses so far
+/// are output on `timeout`, with unit for the missing ones. Responses after the decision
+/// are ignored while the round is kept: twice `timeout`, or 10 minutes without one.
+#[modular_agent(
+    title = "Quorum",
+    category = CATEGORY,
+    inputs = [PORT_IN1, PORT_IN2],
+    outputs = [PORT_VALUE, PORT_NO_QUORUM, PORT_TIMEOUT],
+    integer_config(name = CONFIG_N, default = 2),
+    string_config(name = CONFIG_MODE, default = MODE_FIRST, description = "first, majority, all"),
+    string_config(name = CONFIG_TIMEOUT, default = QUORUM_TIMEOUT_DEFAULT, description = "(ex. 500ms, 30s) empty: no timeout"),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
+    hint(color=4),
+)]
+struct QuorumAgent {
+    data: AgentData,
+    contract: InputContract,
+    n: usize,
+    // rounds by context key
+    rounds: Arc<Mutex<HashMap<String, QuorumRound>>>,
+    // timeouts of the rounds by context key
+    timers: HashMap<String, Timer>,
+}
+
+impl QuorumAgent {
+    fn update_spec(spec: &mut AgentSpec) -> usize {
+        let n = spec
+            .configs
+            .as_ref()
+            .map(|cfg| cfg.get_integer_or(CONFIG_N, 2))
+            .unwrap_or(2)
+            .max(1) as usize;
+        spec.inputs = Some((1..=n).map(|i| format!("in{}", i)).collect());
+        n
+    }
+
+    fn start_timeout(&mut self, ctx: &AgentContext, key: String, timeout: Duration) {
+        let rounds = self.rounds.clone();
+        let ma = self.ma().clone();
+        let agent_id = self.id().to_string();
+        let def_name = self.def_name().to_string();
+        let round_key = key.clone();
+        let mut ctx = Some(ctx.clone());
+        let deadline = tokio::time::Instant::now() + timeout;
+        let timer = schedule(self, deadline, move |_| {
+            let responses = {
+                let mut rounds = rounds.lock().unwrap();
+                let round = rounds.get_mut(&round_key).filter(|r| !r.decided)?;
+                round.decided = true;
+                quorum_responses(&round.responses)
+            };
+            let ctx = ctx.take()?;
+            if let Err(e) = ma.try_send_agent_out(
+                agent_id.clone(),
+                stamp(ctx, &agent_id, &def_name),
+                PORT_TIMEOUT.to_string(),
+                responses,
+            ) {
+                log::error!("Failed to send quorum timeout: {}", e);
+            }
+            None
+        });
+        self.timers.retain(|_, timer| timer.is_active());
+        self.timers.insert(key, timer);
+    }
+
+    fn reset(&mut self) {
+        self.timers.clear();
+        self.rounds.lock().unwrap().clear();
+    }
+}
+
+#[async_trait]
+impl AsAgent for QuorumAgent {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let n = Self::update_spec(&mut spec);
+        let contract = InputContract::update_spec(&mut spec)?;
+        Ok(Self {
+            data: AgentData::new(ma, id, spec),
+            contract,
+            n,
+            rounds: Default::default(),
+            timers: HashMap::new(),
+        })
+    }
+
+    async fn stop(&mut self) -> Result<(), AgentError> {
+        // Dropping the timers cancels them
+        self.reset();
+        Ok(())
+    }
+
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        let n = Self::update_spec(&mut self.data.spec);
+        self.reload_contract()?;
+        if n != self.n {
+            self.n = n;
+            self.reset();
+            self.emit_agent_spec_updated();
+        }
+        Ok(())
+    }
+
+    async fn process(
+        &mut self,
+        ctx: AgentContext,
+        port: String,
+        value: AgentValue,
+    ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
+        let Some(idx) = port
+            .strip_prefix("in")
+            .and_then(|s| s.parse::<usize>().ok())
+            .filter(|&i| i >= 1 && i <= self.n)
+            .map(|i| i - 1)
+        else {
+            return Err(AgentError::InvalidPin(port));
+        };
+        let configs = self.configs()?;
+        let mode = QuorumMode::parse(&configs.get_string_or(CONFIG_MODE, MODE_FIRST))?;
+        let timeout = configs.get_string_or(CONFIG_TIMEOUT, QUORUM_TIMEOUT_DEFAULT);
+        let timeout = if timeout.trim().is_empty() {
+            None
+        } else {
+            Some(Duration::from_millis(parse_duration_to_ms(&timeout)?))
+        };
+        let key = ctx.ctx_key()?;
+
+        let (new_round, decision) = {
+            let mut rounds = self.rounds.lock().unwrap();
+            let ttl = quorum_round_ttl(timeout);
+            rounds.retain(|_, round| round.started.elapsed() < ttl);
+            let new_round = !rounds.contains_key(&key);
+            let round = rounds.entry(key.clone()).or_insert_with(|| QuorumRound {
+                started: Instant::now(),
+                responses: vec![None; self.n],
+                decided: false,
+            });
+            // The first response of each branch counts
+            round.responses[idx].get_or_insert(value);
+            let decision = if round.decided {
+                None
+            } else {
+                quorum_decide(mode, &round.responses)
+            };
+            if decision.is_some() {
+                round.decided = true;
+            }
+            // Forget the round when all branches have responded
+            if round.decided && round.responses.iter().all(|r| r.is_some()) {
+                rounds.remove(&key);
+            }
+            (new_round, decision)
+        };
+
+        match decision {
+            Some(decision) => {
+                self.timers.remove(&key);
+                match decision {
+                    QuorumDecision::Value(value) => {
+                        self.output(self.traced(ctx), PORT_VALUE, value).await
+                    }
+                    QuorumDecision::NoQuorum(responses) => {
+                        self.output(self.traced(ctx), PORT_NO_QUORUM, responses)
+                            .await
+                    }
+                }
+            }
+            None => {
+                if new_round && let Some(timeout) = timeout {
+                    self.start_timeout(&ctx, key, timeout);
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+// Saga
+//
+// A saga is started by Saga Begin, which puts its id in the `saga` variable of the
+// context. Saga Step agents downstream record a compensation for each completed step,
+// and Saga Compensate either forgets them (commit) or emits them in reverse order
+// (failure) so cleanup branches can undo the partial work.
+
+const VAR_SAGA: &str = "saga";
+
+struct Saga {
+    expires_at: Instant,
+    // (step, compensation payload) in order of completion
+    steps: Vec<(String, AgentValue)>,
+}
+
+// Sagas in progress by id
+static SAGAS: LazyLock<Mutex<HashMap<String, Saga>>> = LazyLock::new(Default::default);
+
+static NEXT_SAGA: AtomicU64 = AtomicU64::new(1);
+
+// Starts a saga that expires after `ttl`, and forgets the expired ones.
+fn saga_begin(agent_id: &str, ttl: Duration) -> String {
+    let id = format!(
+        "{}-{}",
+        agent_id,
+        NEXT_SAGA.fetch_add(1, AtomicOrdering::Relaxed)
+    );
+    let now = Instant::now();
+    let mut sagas = SAGAS.lock().unwrap();
+    sagas.retain(|_, saga| saga.expires_at > now);
+    sagas.insert(
+        id.clone(),
+        Saga {
+            expires_at: now + ttl,
+            steps: Vec::new(),
+        },
+    );
+    id
+}
+
+fn saga_record(id: &str, step: &str, compensation: AgentValue) -> Result<(), AgentError> {
+    let mut sagas = SAGAS.lock().unwrap();
+    let saga = sagas
+        .get_mut(id)
+        .ok_or_else(|| AgentError::InvalidValue(format!("Saga '{}' is not in progress", id)))?;
+    saga.steps.push((step.to_string(), compensation));
+    Ok(())
+}
+
+// Ends a saga and returns its steps.
+fn saga_end(id: &str) -> Option<Vec<(String, AgentValue)>> {
+    SAGAS.lock().unwrap().remove(id).map(|saga| saga.steps)
+}
+
+fn saga_id(ctx: &AgentContext) -> Result<String, AgentError> {
+    ctx.get_var(VAR_SAGA)
+        .and_then(|v| v.as_str())
+        .map(|s| s.to_string())
+        .ok_or_else(|| AgentError::InvalidValue("No saga in the context".into()))
+}
+
+/// Starts a saga for each input value, and passes the value on with the saga in its
+/// context.
+///
+/// Sagas not ended by Saga Compensate within `ttl` are forgotten.
+#[modular_agent(
+    title = "Saga Begin",
+    category = CATEGORY,
+    inputs = [PORT_VALUE],
+    outputs = [PORT_VALUE],
+    string_config(name = CONFIG_TTL, default = TTL_DEFAULT, description = "(ex. 10m, 1h)"),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
+    hint(color=4),
+)]
+struct SagaBeginAgent {
+    data: AgentData,
+    contract: InputContract,
+}
+
+#[async_trait]
+impl AsAgent for SagaBeginAgent {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
+        Ok(Self {
+            data: AgentData::new(ma, id, spec),
+            contract,
+        })
+    }
+
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
+    }
+
+    async fn process(
+        &mut self,
+        ctx: AgentContext,
+        port: String,
+        value: AgentValue,
+    ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
+        let ttl = parse_duration_to_ms(&self.configs()?.get_string_or(CONFIG_TTL, TTL_DEFAULT))?;
+        let id = saga_begin(self.id(), Duration::from_millis(ttl));
+        let ctx = ctx.with_var(VAR_SAGA.to_string(), AgentValue::string(id));
+        self.output(self.traced(ctx), PORT_VALUE, value).await
+    }
+}
+
+/// Records a completed step of the saga in the context, and passes the value on.
+///
+/// The compensation is `compensation` rendered with the input as `value` (parsed as JSON
+/// when possible, ex. `{"delete": "{{value.id}}"}`), or the input itself when empty.
+/// Strings interpolated into a JSON template are JSON-escaped.
+#[modular_agent(
+    title = "Saga Step",
+    category = CATEGORY,
+    inputs = [PORT_VALUE],
+    outputs = [PORT_VALUE],
+    string_config(name = CONFIG_STEP),
+    text_config(name = CONFIG_COMPENSATION),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
+    hint(color=4),
+)]
+struct SagaStepAgent {
+    data: AgentData,
+    contract: InputContract,
+}
+
+#[async_trait]
+impl AsAgent for SagaStepAgent {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
+        Ok(Self {
+            data: AgentData::new(ma, id, spec),
+            contract,
+        })
+    }
+
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
+    }
+
+    async fn process(
+        &mut self,
+        ctx: AgentContext,
+        port: String,
+        value: AgentValue,
+    ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
+        let configs = self.configs()?;
+        let step = configs.get_string_or_default(CONFIG_STEP);
+        let template = configs.get_string_or_default(CONFIG_COMPENSATION);
+        let compensation = if template.trim().is_empty() {
+            value.clone()
+        } else {
+            render_compensation(&template, &value)?
+        };
+
+        saga_record(&saga_id(&ctx)?, &step, compensation)?;
+        self.output(self.traced(ctx), PORT_VALUE, value).await
+    }
+}
+
+// Renders a compensation template. Interpolated strings are JSON-escaped so that a JSON
+// template stays valid whatever the input holds; a template that does not render to
+// JSON is rendered again as plain text.
+fn render_compensation(template: &str, value: &AgentValue) -> Result<AgentValue, AgentError> {
+    let data = serde_json::json!({ "value": value });
+    let render = |reg: &handlebars::Handlebars| {
+        reg.render_template(template, &data)
+            .map_err(|e| AgentError::InvalidValue(format!("Failed to render template: {}", e)))
+    };
+
+    let mut reg = handlebars_new();
+    reg.register_escape_fn(json_escape);
+    if let Some(json) = serde_json::from_str::<serde_json::Value>(&render(&reg)?)
+        .ok()
+        .and_then(|json| AgentValue::from_json(json).ok())
+    {
+        return Ok(json);
+    }
+    Ok(AgentValue::string(render(&handlebars_new())?))
+}
+
+/// Ends the saga in the context.
+///
+/// On `commit`, the recorded steps are forgotten and the value is output on `committed`.
+/// On `failure`, `{step, compensation}` of each recorded step is output on
+/// `compensation`, from the last step to the first, then the value on `aborted`.
+#[modular_agent(
+    title = "Saga Compensate",
+    category = CATEGORY,
+    inputs = [PORT_COMMIT, PORT_FAILURE],
+    outputs = [PORT_COMPENSATION, PORT_COMMITTED, PORT_ABORTED],
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
+    hint(color=4),
+)]
+struct SagaCompensateAgent {
+    data: AgentData,
+    contract: InputContract,
+}
+
+#[async_trait]
+impl AsAgent for SagaCompensateAgent {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
+        Ok(Self {
+            data: AgentData::new(ma, id, spec),
+            contract,
+        })
+    }
+
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
+    }
+
+    async fn process(
+        &mut self,
+        ctx: AgentContext,
+        port: String,
+        value: AgentValue,
+    ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
+        let id = saga_id(&ctx)?;
+        let steps = saga_end(&id)
+            .ok_or_else(|| AgentError::InvalidValue(format!("Saga '{}' is not in progress", id)))?;
+        match port.as_str() {
+            PORT_COMMIT => self.output(self.traced(ctx), PORT_COMMITTED, value).await,
+            PORT_FAILURE => {
+                for (step, compensation) in steps.into_iter().rev() {
+                    let out = AgentValue::object(hashmap! {
+                        CONFIG_STEP.to_string() => AgentValue::string(step),
+                        CONFIG_COMPENSATION.to_string() => compensation,
+                    });
+                    self.output(self.traced(ctx.clone()), PORT_COMPENSATION, out)
+                        .await?;
+                }
+                self.output(self.traced(ctx), PORT_ABORTED, value).await
+            }
+            _ => Err(AgentError::InvalidPin(port)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_topic_matches() {
+        assert!(topic_matches("a/b/c", "a/b/c"));
+        assert!(!topic_matches("a/b/c", "a/b"));
+        assert!(!topic_matches("a/b", "a/b/c"));
+        assert!(topic_matches("a/+/c", "a/x/c"));
+        assert!(!topic_matches("a/+/c", "a/x/y/c"));
+        assert!(topic_matches("a/#", "a/x/y/c"));
+        assert!(topic_matches("a/#", "a"));
+        assert!(topic_matches("#", "anything/at/all"));
+        assert!(topic_matches("+/+", "a/b"));
+        assert!(!topic_matches("+", "a/b"));
+    }
+
+    #[test]
+    fn test_bridge_message() {
+        let value = AgentValue::object(im::hashmap! {
+            "n".to_string() => AgentValue::integer(1),
+            "s".to_string() => AgentValue::string("x"),
+        });
+        let line = encode_message("a/b", &value).unwrap();
+        assert_eq!(decode_message(&line).unwrap(), ("a/b".to_string(), value));
+
+        assert!(decode_message(r#"{"topic": "a/+", "value": 1}"#).is_err());
+        assert!(decode_message(r#"{"value": 1}"#).is_err());
+        assert!(decode_message("not json").is_err());
+    }
+
+    #[test]
+    fn test_quorum_round_ttl() {
+        assert_eq!(
+            quorum_round_ttl(Some(Duration::from_secs(30))),
+            Duration::from_secs(60)
+        );
+        assert_eq!(quorum_round_ttl(None), QUORUM_ROUND_TTL_DEFAULT);
+    }
+
+    #[test]
+    fn test_quorum_decide() {
+        let v = |i: i64| Some(AgentValue::integer(i));
+
+        assert_eq!(quorum_decide(QuorumMode::First, &[None, None, None]), None);
+        assert_eq!(
+            quorum_decide(QuorumMode::First, &[None, v(2), None]),
+            Some(QuorumDecision::Value(AgentValue::integer(2)))
+        );
+
+        assert_eq!(
+            quorum_decide(QuorumMode::Majority, &[v(1), None, None]),
+            None
+        );
+        assert_eq!(
+            quorum_decide(QuorumMode::Majority, &[v(1), None, v(1)]),
+            Some(QuorumDecision::Value(AgentValue::integer(1)))
+        );
+        assert_eq!(
+            quorum_decide(QuorumMode::Majority, &[v(1), v(2), None]),
+            None
+        );
+        // no majority is possible any more
+        assert_eq!(
+            quorum_decide(QuorumMode::Majority, &[v(1), v(2), v(3), None]),
+            Some(QuorumDecision::NoQuorum(AgentValue::array(im::vector![
+                AgentValue::integer(1),
+                AgentValue::integer(2),
+                AgentValue::integer(3),
+                AgentValue::Unit,
+            ])))
+        );
+
+        assert_eq!(quorum_decide(QuorumMode::All, &[v(1), None]), None);
+        assert_eq!(
+            quorum_decide(QuorumMode::All, &[v(1), v(2)]),
+            Some(QuorumDecision::Value(AgentValue::array(im::vector![
+                AgentValue::integer(1),
+                AgentValue::integer(2),
+            ])))
+        );
+    }
+
+    #[test]
+    fn test_saga_registry() {
+        let id = saga_begin("saga_test", Duration::from_secs(60));
+        saga_record(&id, "a", AgentValue::integer(1)).unwrap();
+        saga_record(&id, "b", AgentValue::integer(2)).unwrap();
+        assert_eq!(
+            saga_end(&id).unwrap(),
+            vec![
+                ("a".to_string(), AgentValue::integer(1)),
+                ("b".to_string(), AgentValue::integer(2)),
+            ]
+        );
+        assert!(saga_end(&id).is_none());
+        assert!(saga_record(&id, "c", AgentValue::Unit).is_err());
+
+        // a short ttl does not expire the sagas begun with a longer one
+        let long = saga_begin("saga_test", Duration::from_secs(60));
+        let short = saga_begin("saga_test", Duration::ZERO);
+        saga_begin("saga_test", Duration::ZERO);
+        assert!(saga_end(&short).is_none());
+        assert!(saga_end(&long).is_some());
+    }
+
+    #[test]
+    fn test_render_compensation() {
+        let value = AgentValue::object(im::hashmap! {
+            "id".to_string() => AgentValue::string("a\"b\\c"),
+        });
+        assert_eq!(
+            render_compensation(r#"{"delete": "{{value.id}}"}"#, &value).unwrap(),
+            AgentValue::object(im::hashmap! {
+                "delete".to_string() => AgentValue::string("a\"b\\c"),
+            })
+        );
+        assert_eq!(
+            render_compensation("delete {{value.id}}", &value).unwrap(),
+            AgentValue::string("delete a\"b\\c")
+        );
+    }
+}
@@ -1,15 +1,34 @@
 #![cfg(feature = "image")]
 
-use std::sync::Arc;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, UNIX_EPOCH};
 
+use im::{Vector, hashmap};
 use modular_agent_core::photon_rs::{self, PhotonImage};
 use modular_agent_core::{
     Agent, AgentContext, AgentData, AgentError, AgentOutput, AgentSpec, AgentValue, AsAgent,
     ModularAgent, async_trait, modular_agent,
 };
+use serde::{Deserialize, Serialize};
+use tokio::task::JoinHandle;
+
+use crate::audit::{ACTION_WRITE_FILE, audit_by};
+use crate::contract::{CONFIG_INPUT_CONTRACT, ContractAgent, InputContract, contract_agents};
+use crate::control::{PORT_PAUSE, PORT_RESUME, PauseState};
+use crate::profile::ProfileConfigs;
+use crate::provenance::{Traced, stamp};
+use crate::quota::admit;
+use crate::supervisor::{
+    CONFIG_MAX_RESTARTS, CONFIG_TASK_RESTARTS, MAX_RESTARTS_DEFAULT, reset_task_restarts,
+    spawn_supervised,
+};
+use crate::time::parse_duration_to_ms;
 
 const CATEGORY: &str = "Std/Image";
 
+const PORT_ARRAY: &str = "array";
 const PORT_FILENAME: &str = "filename";
 const PORT_IMAGE: &str = "image";
 const PORT_IMAGE_FILENAME: &str = "image_filename";
@@ -18,13 +37,45 @@ const PORT_NON_BLANK: &str = "non_blank";
 const PORT_CHANGED: &str = "changed";
 const PORT_UNCHANGED: &str = "unchanged";
 const PORT_RESULT: &str = "result";
+const PORT_THRESHOLD: &str = "threshold";
 
 const CONFIG_ALMOST_BLACK_THRESHOLD: &str = "almost_black_threshold";
 const CONFIG_BLANK_THRESHOLD: &str = "blank_threshold";
+const CONFIG_BRIGHTNESS: &str = "brightness";
+const CONFIG_CONTRAST: &str = "contrast";
+const CONFIG_CHANNEL: &str = "channel";
+const CONFIG_EXTENSIONS: &str = "extensions";
+const CONFIG_FROM: &str = "from";
+const CONFIG_METHOD: &str = "method";
 const CONFIG_SCALE: &str = "scale";
 const CONFIG_HEIGHT: &str = "height";
+const CONFIG_INTERVAL: &str = "interval";
+const CONFIG_PATH: &str = "path";
+const CONFIG_STATE_FILE: &str = "state_file";
 const CONFIG_WIDTH: &str = "width";
 const CONFIG_THRESHOLD: &str = "threshold";
+const CONFIG_TO: &str = "to";
+
+const THRESHOLD_FIXED: &str = "fixed";
+const THRESHOLD_OTSU: &str = "otsu";
+
+const IMAGE_EXTENSIONS_DEFAULT: &str = "jpg, jpeg, png, gif, bmp, webp, tif, tiff";
+const WATCH_INTERVAL_DEFAULT: &str = "5s";
+
+contract_agents!(
+    IsBlankImageAgent,
+    ResampleImageAgent,
+    ResizeImageAgent,
+    ScaleImageAgent,
+    AdjustImageAgent,
+    ExtractChannelImageAgent,
+    MergeChannelsImageAgent,
+    ConvertColorSpaceImageAgent,
+    ThresholdImageAgent,
+    IsChangedImageAgent,
+    OpenImageAgent,
+    SaveImageAgent,
+);
 
 // IsBlankImageAgent
 #[modular_agent(
@@ -33,48 +84,96 @@ const CONFIG_THRESHOLD: &str = "threshold";
     inputs = [PORT_IMAGE],
     outputs = [PORT_BLANK, PORT_NON_BLANK],
     integer_config(name = CONFIG_ALMOST_BLACK_THRESHOLD, default = 20),
-    integer_config(name = CONFIG_BLANK_THRESHOLD, default = 400)
+    integer_config(name = CONFIG_BLANK_THRESHOLD, default = 400),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
 )]
 struct IsBlankImageAgent {
     data: AgentData,
+    contract: InputContract,
 }
 
-impl IsBlankImageAgent {
-    fn is_blank(
-        &self,
-        image: &PhotonImage,
-        almost_black_threshold: u8,
-        blank_threshold: u32,
-    ) -> bool {
-        let mut count = 0;
-        for pixel in image.get_raw_pixels() {
-            if pixel >= almost_black_threshold {
-                count += 1;
-            }
-            if count >= blank_threshold {
-                return false;
-            }
+fn is_blank(image: &PhotonImage, almost_black_threshold: u8, blank_threshold: u32) -> bool {
+    let mut count = 0;
+    for pixel in image.get_raw_pixels() {
+        if pixel >= almost_black_threshold {
+            count += 1;
+        }
+        if count >= blank_threshold {
+            return false;
         }
-        true
     }
+    true
 }
 
 #[async_trait]
 impl AsAgent for IsBlankImageAgent {
-    fn new(ma: ModularAgent, id: String, spec: AgentSpec) -> Result<Self, AgentError> {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
         Ok(Self {
             data: AgentData::new(ma, id, spec),
+            contract,
         })
     }
 
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
+    }
+
     async fn process(
         &mut self,
         ctx: AgentContext,
-        _port: String,
+        port: String,
         value: AgentValue,
     ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
         let config = self.configs()?;
 
+        if let AgentValue::Array(arr) = &value {
+            let almost_black_threshold =
+                config.get_integer_or_default(CONFIG_ALMOST_BLACK_THRESHOLD) as u8;
+            let blank_threshold = config.get_integer_or_default(CONFIG_BLANK_THRESHOLD) as u32;
+
+            let items: Vec<AgentValue> = arr.iter().cloned().collect();
+            let (items, blanks) = par_map_blocking(items, move |item| {
+                item.as_image()
+                    .map(|image| is_blank(image, almost_black_threshold, blank_threshold))
+            })
+            .await?;
+
+            let mut blank = Vector::new();
+            let mut non_blank = Vector::new();
+            for (item, is_blank) in items.into_iter().zip(blanks) {
+                match is_blank {
+                    Some(true) => blank.push_back(item),
+                    Some(false) => non_blank.push_back(item),
+                    None => {
+                        return Err(AgentError::InvalidValue(
+                            "Array item is not an image".into(),
+                        ));
+                    }
+                }
+            }
+            if !blank.is_empty() {
+                self.output(
+                    self.traced(ctx.clone()),
+                    PORT_BLANK,
+                    AgentValue::array(blank),
+                )
+                .await?;
+            }
+            if !non_blank.is_empty() {
+                self.output(
+                    self.traced(ctx),
+                    PORT_NON_BLANK,
+                    AgentValue::array(non_blank),
+                )
+                .await?;
+            }
+            return Ok(());
+        }
+
         if value.is_image() {
             let image = value
                 .as_image()
@@ -84,11 +183,10 @@ impl AsAgent for IsBlankImageAgent {
                 config.get_integer_or_default(CONFIG_ALMOST_BLACK_THRESHOLD) as u8;
             let blank_threshold = config.get_integer_or_default(CONFIG_BLANK_THRESHOLD) as u32;
 
-            let is_blank = self.is_blank(&image, almost_black_threshold, blank_threshold);
-            if is_blank {
-                self.output(ctx, PORT_BLANK, value).await
+            if is_blank(image, almost_black_threshold, blank_threshold) {
+                self.output(self.traced(ctx), PORT_BLANK, value).await
             } else {
-                self.output(ctx, PORT_NON_BLANK, value).await
+                self.output(self.traced(ctx), PORT_NON_BLANK, value).await
             }
         } else {
             Err(AgentError::InvalidValue(
@@ -106,28 +204,49 @@ impl AsAgent for IsBlankImageAgent {
     inputs = [PORT_IMAGE],
     outputs = [PORT_IMAGE],
     integer_config(name = CONFIG_WIDTH, default = 512),
-    integer_config(name = CONFIG_HEIGHT, default = 512)
+    integer_config(name = CONFIG_HEIGHT, default = 512),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
 )]
 struct ResampleImageAgent {
     data: AgentData,
+    contract: InputContract,
 }
 
 #[async_trait]
 impl AsAgent for ResampleImageAgent {
-    fn new(ma: ModularAgent, id: String, spec: AgentSpec) -> Result<Self, AgentError> {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
         Ok(Self {
             data: AgentData::new(ma, id, spec),
+            contract,
         })
     }
 
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
+    }
+
     async fn process(
         &mut self,
         ctx: AgentContext,
-        _port: String,
+        port: String,
         value: AgentValue,
     ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
         let config = self.configs()?;
 
+        if let AgentValue::Array(arr) = &value {
+            let width = config.get_integer_or_default(CONFIG_WIDTH) as usize;
+            let height = config.get_integer_or_default(CONFIG_HEIGHT) as usize;
+            let images = map_images(arr, move |image| {
+                Some(photon_rs::transform::resample(image, width, height))
+            })
+            .await?;
+            return self.output(self.traced(ctx), PORT_IMAGE, images).await;
+        }
+
         if value.is_image() {
             let image = value
                 .as_image()
@@ -138,11 +257,15 @@ impl AsAgent for ResampleImageAgent {
 
             let resampled_image = photon_rs::transform::resample(&*image, width, height);
 
-            self.output(ctx, PORT_IMAGE, AgentValue::image(resampled_image))
-                .await
+            self.output(
+                self.traced(ctx),
+                PORT_IMAGE,
+                AgentValue::image(resampled_image),
+            )
+            .await
         } else {
             // Pass through non-image value
-            self.output(ctx, PORT_IMAGE, value).await
+            self.output(self.traced(ctx), PORT_IMAGE, value).await
         }
     }
 }
@@ -155,28 +278,54 @@ impl AsAgent for ResampleImageAgent {
     inputs = [PORT_IMAGE],
     outputs = [PORT_IMAGE],
     integer_config(name = CONFIG_WIDTH, default = 512),
-    integer_config(name = CONFIG_HEIGHT, default = 512)
+    integer_config(name = CONFIG_HEIGHT, default = 512),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
 )]
 struct ResizeImageAgent {
     data: AgentData,
+    contract: InputContract,
 }
 
 #[async_trait]
 impl AsAgent for ResizeImageAgent {
-    fn new(ma: ModularAgent, id: String, spec: AgentSpec) -> Result<Self, AgentError> {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
         Ok(Self {
             data: AgentData::new(ma, id, spec),
+            contract,
         })
     }
 
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
+    }
+
     async fn process(
         &mut self,
         ctx: AgentContext,
-        _port: String,
+        port: String,
         value: AgentValue,
     ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
         let config = self.configs()?;
 
+        if let AgentValue::Array(arr) = &value {
+            let width = config.get_integer_or_default(CONFIG_WIDTH) as u32;
+            let height = config.get_integer_or_default(CONFIG_HEIGHT) as u32;
+            let images = map_images(arr, move |image| {
+                Some(photon_rs::transform::resize(
+                    image,
+                    width,
+                    height,
+                    photon_rs::transform::SamplingFilter::Nearest,
+                ))
+            })
+            .await?;
+            return self.output(self.traced(ctx), PORT_IMAGE, images).await;
+        }
+
         if value.is_image() {
             let image = value
                 .as_image()
@@ -192,11 +341,15 @@ impl AsAgent for ResizeImageAgent {
                 photon_rs::transform::SamplingFilter::Nearest,
             );
 
-            self.output(ctx, PORT_IMAGE, AgentValue::image(resized_image))
-                .await
+            self.output(
+                self.traced(ctx),
+                PORT_IMAGE,
+                AgentValue::image(resized_image),
+            )
+            .await
         } else {
             // Pass through non-image value
-            self.output(ctx, PORT_IMAGE, value).await
+            self.output(self.traced(ctx), PORT_IMAGE, value).await
         }
     }
 }
@@ -208,33 +361,40 @@ impl AsAgent for ResizeImageAgent {
     category = CATEGORY,
     inputs = [PORT_IMAGE],
     outputs = [PORT_IMAGE],
-    number_config(name = CONFIG_SCALE, default = 1.0)
+    number_config(name = CONFIG_SCALE, default = 1.0),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
 )]
 struct ScaleImageAgent {
     data: AgentData,
+    contract: InputContract,
 }
 
 #[async_trait]
 impl AsAgent for ScaleImageAgent {
-    fn new(ma: ModularAgent, id: String, spec: AgentSpec) -> Result<Self, AgentError> {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
         Ok(Self {
             data: AgentData::new(ma, id, spec),
+            contract,
         })
     }
 
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
+    }
+
     async fn process(
         &mut self,
         ctx: AgentContext,
-        _port: String,
+        port: String,
         value: AgentValue,
     ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
         let config = self.configs()?;
 
-        if value.is_image() {
-            let image = value
-                .as_image()
-                .ok_or_else(|| AgentError::InvalidValue("Expected image value".into()))?;
-
+        if value.is_image() || value.is_array() {
             let scale = config.get_number_or_default(CONFIG_SCALE);
 
             if scale <= 0.0 {
@@ -243,36 +403,769 @@ impl AsAgent for ScaleImageAgent {
                 ));
             }
 
-            if scale == 1.0 {
+            if let AgentValue::Array(arr) = &value {
+                let images = map_images(arr, move |image| scale_image(image, scale)).await?;
+                return self.output(self.traced(ctx), PORT_IMAGE, images).await;
+            }
+
+            let image = value
+                .as_image()
+                .ok_or_else(|| AgentError::InvalidValue("Expected image value".into()))?;
+
+            match scale_image(image, scale) {
+                Some(scaled_image) => {
+                    self.output(
+                        self.traced(ctx),
+                        PORT_IMAGE,
+                        AgentValue::image(scaled_image),
+                    )
+                    .await
+                }
                 // No scaling needed, pass through the original image
-                return self.output(ctx, PORT_IMAGE, value).await;
+                None => self.output(self.traced(ctx), PORT_IMAGE, value).await,
             }
+        } else {
+            // Pass through non-image value
+            self.output(self.traced(ctx), PORT_IMAGE, value).await
+        }
+    }
+}
 
-            if scale < 1.0 {
-                let width = ((image.get_width() as f64) * scale) as u32;
-                let height = ((image.get_height() as f64) * scale) as u32;
+// Scales the image by the factor, or returns None if the factor is 1.0
+fn scale_image(image: &PhotonImage, scale: f64) -> Option<PhotonImage> {
+    if scale == 1.0 {
+        return None;
+    }
 
-                let resized_image = photon_rs::transform::resize(
-                    &*image,
-                    width,
-                    height,
-                    photon_rs::transform::SamplingFilter::Nearest,
-                );
-                self.output(ctx, PORT_IMAGE, AgentValue::image(resized_image))
+    if scale < 1.0 {
+        let width = ((image.get_width() as f64) * scale) as u32;
+        let height = ((image.get_height() as f64) * scale) as u32;
+        Some(photon_rs::transform::resize(
+            image,
+            width,
+            height,
+            photon_rs::transform::SamplingFilter::Nearest,
+        ))
+    } else {
+        // scale > 1.0
+        let width = ((image.get_width() as f64) * scale) as usize;
+        let height = ((image.get_height() as f64) * scale) as usize;
+        Some(photon_rs::transform::resample(image, width, height))
+    }
+}
+
+// AdjustImageAgent
+
+/// Adjusts the brightness and contrast of an image, or of each image of an array.
+///
+/// `brightness` (-255 to 255) is added to the color channels, and `contrast` (-255 to
+/// 255) spreads them from the middle gray when positive, or pulls them towards it when
+/// negative. Alpha is kept.
+#[modular_agent(
+    title = "Adjust Image",
+    category = CATEGORY,
+    inputs = [PORT_IMAGE],
+    outputs = [PORT_IMAGE],
+    integer_config(name = CONFIG_BRIGHTNESS, description = "-255 to 255"),
+    number_config(name = CONFIG_CONTRAST, description = "-255 to 255"),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
+)]
+struct AdjustImageAgent {
+    data: AgentData,
+    contract: InputContract,
+}
+
+#[async_trait]
+impl AsAgent for AdjustImageAgent {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
+        Ok(Self {
+            data: AgentData::new(ma, id, spec),
+            contract,
+        })
+    }
+
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
+    }
+
+    async fn process(
+        &mut self,
+        ctx: AgentContext,
+        port: String,
+        value: AgentValue,
+    ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
+        let config = self.configs()?;
+        let brightness = config.get_integer_or_default(CONFIG_BRIGHTNESS);
+        let contrast = config.get_number_or_default(CONFIG_CONTRAST);
+        if !(-255..=255).contains(&brightness) || !(-255.0..=255.0).contains(&contrast) {
+            return Err(AgentError::InvalidConfig(
+                "brightness and contrast must be from -255 to 255".into(),
+            ));
+        }
+
+        if let AgentValue::Array(arr) = &value {
+            let images =
+                map_images(arr, move |image| adjust_image(image, brightness, contrast)).await?;
+            return self.output(self.traced(ctx), PORT_IMAGE, images).await;
+        }
+
+        let image = value
+            .as_image()
+            .ok_or_else(|| AgentError::InvalidValue("Input value is not an image".into()))?;
+        match adjust_image(image, brightness, contrast) {
+            Some(adjusted) => {
+                self.output(self.traced(ctx), PORT_IMAGE, AgentValue::image(adjusted))
                     .await
-            } else {
-                // scale > 1.0
-                let width = ((image.get_width() as f64) * scale) as usize;
-                let height = ((image.get_height() as f64) * scale) as usize;
-                let resampled_image = photon_rs::transform::resample(&*image, width, height);
-                self.output(ctx, PORT_IMAGE, AgentValue::image(resampled_image))
+            }
+            None => self.output(self.traced(ctx), PORT_IMAGE, value).await,
+        }
+    }
+}
+
+// Returns None when there is nothing to adjust
+fn adjust_image(image: &PhotonImage, brightness: i64, contrast: f64) -> Option<PhotonImage> {
+    if brightness == 0 && contrast == 0.0 {
+        return None;
+    }
+    let factor = (259.0 * (contrast + 255.0)) / (255.0 * (259.0 - contrast));
+    let table: Vec<u8> = (0..=255i64)
+        .map(|v| {
+            let v = (v + brightness).clamp(0, 255) as f64;
+            ((v - 128.0) * factor + 128.0).round().clamp(0.0, 255.0) as u8
+        })
+        .collect();
+    let pixels: Vec<u8> = image
+        .get_raw_pixels()
+        .chunks_exact(4)
+        .flat_map(|p| {
+            [
+                table[p[0] as usize],
+                table[p[1] as usize],
+                table[p[2] as usize],
+                p[3],
+            ]
+        })
+        .collect();
+    Some(PhotonImage::new(
+        pixels,
+        image.get_width(),
+        image.get_height(),
+    ))
+}
+
+// ExtractChannelImageAgent
+
+#[modular_agent(
+    title = "Extract Channel",
+    category = CATEGORY,
+    inputs = [PORT_IMAGE],
+    outputs = [PORT_IMAGE],
+    string_config(name = CONFIG_CHANNEL, default = "r", description = "r, g, b, a"),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
+)]
+struct ExtractChannelImageAgent {
+    data: AgentData,
+    contract: InputContract,
+}
+
+#[async_trait]
+impl AsAgent for ExtractChannelImageAgent {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
+        Ok(Self {
+            data: AgentData::new(ma, id, spec),
+            contract,
+        })
+    }
+
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
+    }
+
+    async fn process(
+        &mut self,
+        ctx: AgentContext,
+        port: String,
+        value: AgentValue,
+    ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
+        let channel = channel_index(&self.configs()?.get_string_or(CONFIG_CHANNEL, "r"))?;
+
+        if let AgentValue::Array(arr) = &value {
+            let images =
+                map_images(arr, move |image| Some(extract_channel(image, channel))).await?;
+            return self.output(self.traced(ctx), PORT_IMAGE, images).await;
+        }
+
+        let image = value
+            .as_image()
+            .ok_or_else(|| AgentError::InvalidValue("Input value is not an image".into()))?;
+        self.output(
+            self.traced(ctx),
+            PORT_IMAGE,
+            AgentValue::image(extract_channel(image, channel)),
+        )
+        .await
+    }
+}
+
+// MergeChannelsImageAgent
+
+#[modular_agent(
+    title = "Merge Channels",
+    category = CATEGORY,
+    inputs = [PORT_ARRAY],
+    outputs = [PORT_IMAGE],
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
+)]
+struct MergeChannelsImageAgent {
+    data: AgentData,
+    contract: InputContract,
+}
+
+#[async_trait]
+impl AsAgent for MergeChannelsImageAgent {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
+        Ok(Self {
+            data: AgentData::new(ma, id, spec),
+            contract,
+        })
+    }
+
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
+    }
+
+    async fn process(
+        &mut self,
+        ctx: AgentContext,
+        port: String,
+        value: AgentValue,
+    ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
+        let arr = value
+            .as_array()
+            .ok_or_else(|| AgentError::InvalidArrayValue("Expected array of images".into()))?;
+        let channels = arr
+            .iter()
+            .map(|v| v.as_image())
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(|| AgentError::InvalidValue("Array item is not an image".into()))?;
+        let image = merge_channels(&channels)?;
+        self.output(self.traced(ctx), PORT_IMAGE, AgentValue::image(image))
+            .await
+    }
+}
+
+// ConvertColorSpaceImageAgent
+
+#[modular_agent(
+    title = "Convert Color Space",
+    category = CATEGORY,
+    inputs = [PORT_IMAGE],
+    outputs = [PORT_IMAGE],
+    string_config(name = CONFIG_FROM, default = COLOR_SPACE_RGB, description = "rgb, hsv, lab"),
+    string_config(name = CONFIG_TO, default = COLOR_SPACE_HSV, description = "rgb, hsv, lab"),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
+)]
+struct ConvertColorSpaceImageAgent {
+    data: AgentData,
+    contract: InputContract,
+}
+
+#[async_trait]
+impl AsAgent for ConvertColorSpaceImageAgent {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
+        Ok(Self {
+            data: AgentData::new(ma, id, spec),
+            contract,
+        })
+    }
+
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
+    }
+
+    async fn process(
+        &mut self,
+        ctx: AgentContext,
+        port: String,
+        value: AgentValue,
+    ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
+        let config = self.configs()?;
+        let from = ColorSpace::parse(&config.get_string_or(CONFIG_FROM, COLOR_SPACE_RGB))?;
+        let to = ColorSpace::parse(&config.get_string_or(CONFIG_TO, COLOR_SPACE_HSV))?;
+
+        if let AgentValue::Array(arr) = &value {
+            let images = map_images(arr, move |image| convert_color_space(image, from, to)).await?;
+            return self.output(self.traced(ctx), PORT_IMAGE, images).await;
+        }
+
+        let image = value
+            .as_image()
+            .ok_or_else(|| AgentError::InvalidValue("Input value is not an image".into()))?;
+        match convert_color_space(image, from, to) {
+            Some(converted) => {
+                self.output(self.traced(ctx), PORT_IMAGE, AgentValue::image(converted))
                     .await
             }
+            None => self.output(self.traced(ctx), PORT_IMAGE, value).await,
+        }
+    }
+}
+
+// ThresholdImageAgent
+
+#[modular_agent(
+    title = "Threshold Image",
+    category = CATEGORY,
+    inputs = [PORT_IMAGE],
+    outputs = [PORT_IMAGE, PORT_THRESHOLD],
+    string_config(name = CONFIG_METHOD, default = THRESHOLD_FIXED, description = "fixed, otsu"),
+    integer_config(name = CONFIG_THRESHOLD, default = 128, description = "0-255, for fixed"),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
+)]
+struct ThresholdImageAgent {
+    data: AgentData,
+    contract: InputContract,
+}
+
+#[async_trait]
+impl AsAgent for ThresholdImageAgent {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
+        Ok(Self {
+            data: AgentData::new(ma, id, spec),
+            contract,
+        })
+    }
+
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
+    }
+
+    async fn process(
+        &mut self,
+        ctx: AgentContext,
+        port: String,
+        value: AgentValue,
+    ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
+        let config = self.configs()?;
+        let otsu = match config.get_string_or(CONFIG_METHOD, THRESHOLD_FIXED).trim() {
+            "" | THRESHOLD_FIXED => false,
+            THRESHOLD_OTSU => true,
+            other => {
+                return Err(AgentError::InvalidConfig(format!(
+                    "Unknown threshold method: {}",
+                    other
+                )));
+            }
+        };
+        let fixed = config.get_integer_or(CONFIG_THRESHOLD, 128).clamp(0, 255) as u8;
+        let threshold_of = move |image: &PhotonImage| {
+            if otsu { otsu_threshold(image) } else { fixed }
+        };
+
+        if let AgentValue::Array(arr) = &value {
+            let images =
+                map_images(arr, move |image| Some(binarize(image, threshold_of(image)))).await?;
+            return self.output(self.traced(ctx), PORT_IMAGE, images).await;
+        }
+
+        let image = value
+            .as_image()
+            .ok_or_else(|| AgentError::InvalidValue("Input value is not an image".into()))?;
+        let threshold = threshold_of(image);
+        let binary = binarize(image, threshold);
+        self.output(
+            self.traced(ctx.clone()),
+            PORT_IMAGE,
+            AgentValue::image(binary),
+        )
+        .await?;
+        self.output(
+            self.traced(ctx),
+            PORT_THRESHOLD,
+            AgentValue::integer(threshold as i64),
+        )
+        .await
+    }
+}
+
+// Channel and color space operations
+
+fn channel_index(channel: &str) -> Result<usize, AgentError> {
+    match channel.trim().to_lowercase().as_str() {
+        "r" | "red" => Ok(0),
+        "g" | "green" => Ok(1),
+        "b" | "blue" => Ok(2),
+        "a" | "alpha" => Ok(3),
+        other => Err(AgentError::InvalidConfig(format!(
+            "Unknown channel: {}",
+            other
+        ))),
+    }
+}
+
+// Returns a grayscale image of the channel
+fn extract_channel(image: &PhotonImage, channel: usize) -> PhotonImage {
+    let pixels: Vec<u8> = image
+        .get_raw_pixels()
+        .chunks_exact(4)
+        .flat_map(|p| [p[channel], p[channel], p[channel], 255])
+        .collect();
+    PhotonImage::new(pixels, image.get_width(), image.get_height())
+}
+
+// Merges grayscale images (their red channel) into R, G, B and optional A channels
+fn merge_channels(channels: &[&PhotonImage]) -> Result<PhotonImage, AgentError> {
+    if channels.len() != 3 && channels.len() != 4 {
+        return Err(AgentError::InvalidValue(
+            "Expected 3 (RGB) or 4 (RGBA) channel images".into(),
+        ));
+    }
+    let width = channels[0].get_width();
+    let height = channels[0].get_height();
+    if channels
+        .iter()
+        .any(|c| c.get_width() != width || c.get_height() != height)
+    {
+        return Err(AgentError::InvalidValue(
+            "Channel images must have the same size".into(),
+        ));
+    }
+
+    let raws: Vec<Vec<u8>> = channels.iter().map(|c| c.get_raw_pixels()).collect();
+    let n = (width * height) as usize;
+    let mut pixels = Vec::with_capacity(n * 4);
+    for i in 0..n {
+        for c in 0..4 {
+            pixels.push(raws.get(c).map_or(255, |raw| raw[i * 4]));
+        }
+    }
+    Ok(PhotonImage::new(pixels, width, height))
+}
+
+const COLOR_SPACE_RGB: &str = "rgb";
+const COLOR_SPACE_HSV: &str = "hsv";
+const COLOR_SPACE_LAB: &str = "lab";
+
+/// Color spaces of RGBA images.
+///
+/// HSV and LAB are stored in the 8-bit channels as H (0-360) -> 0-255, S, V (0-1) -> 0-255,
+/// and L (0-100) -> 0-255, a, b (-128-127) -> 0-255. Alpha is kept.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ColorSpace {
+    Rgb,
+    Hsv,
+    Lab,
+}
+
+impl ColorSpace {
+    fn parse(s: &str) -> Result<Self, AgentError> {
+        match s.trim().to_lowercase().as_str() {
+            COLOR_SPACE_RGB => Ok(Self::Rgb),
+            COLOR_SPACE_HSV => Ok(Self::Hsv),
+            COLOR_SPACE_LAB => Ok(Self::Lab),
+            other => Err(AgentError::InvalidConfig(format!(
+                "Unknown color space: {}",
+                other
+            ))),
+        }
+    }
+
+    // Converts a pixel of this color space to RGB
+    fn decode(self, p: [u8; 3]) -> [u8; 3] {
+        match self {
+            Self::Rgb => p,
+            Self::Hsv => hsv_to_rgb(p),
+            Self::Lab => lab_to_rgb(p),
+        }
+    }
+
+    // Converts an RGB pixel to this color space
+    fn encode(self, p: [u8; 3]) -> [u8; 3] {
+        match self {
+            Self::Rgb => p,
+            Self::Hsv => rgb_to_hsv(p),
+            Self::Lab => rgb_to_lab(p),
+        }
+    }
+}
+
+// Converts the pixels, or returns None if the color spaces are the same
+fn convert_color_space(
+    image: &PhotonImage,
+    from: ColorSpace,
+    to: ColorSpace,
+) -> Option<PhotonImage> {
+    if from == to {
+        return None;
+    }
+    let pixels: Vec<u8> = image
+        .get_raw_pixels()
+        .chunks_exact(4)
+        .flat_map(|p| {
+            let [x, y, z] = to.encode(from.decode([p[0], p[1], p[2]]));
+            [x, y, z, p[3]]
+        })
+        .collect();
+    Some(PhotonImage::new(
+        pixels,
+        image.get_width(),
+        image.get_height(),
+    ))
+}
+
+fn to_u8(v: f64) -> u8 {
+    v.round().clamp(0.0, 255.0) as u8
+}
+
+fn rgb_to_hsv([r, g, b]: [u8; 3]) -> [u8; 3] {
+    let (r, g, b) = (r as f64 / 255.0, g as f64 / 255.0, b as f64 / 255.0);
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let d = max - min;
+    let h = if d == 0.0 {
+        0.0
+    } else if max == r {
+        60.0 * ((g - b) / d).rem_euclid(6.0)
+    } else if max == g {
+        60.0 * ((b - r) / d + 2.0)
+    } else {
+        60.0 * ((r - g) / d + 4.0)
+    };
+    let s = if max == 0.0 { 0.0 } else { d / max };
+    [
+        to_u8(h / 360.0 * 255.0),
+        to_u8(s * 255.0),
+        to_u8(max * 255.0),
+    ]
+}
+
+fn hsv_to_rgb([h, s, v]: [u8; 3]) -> [u8; 3] {
+    let h = h as f64 / 255.0 * 360.0;
+    let s = s as f64 / 255.0;
+    let v = v as f64 / 255.0;
+    let c = v * s;
+    let x = c * (1.0 - ((h / 60.0).rem_euclid(2.0) - 1.0).abs());
+    let m = v - c;
+    let (r, g, b) = match (h / 60.0) as u32 {
+        0 => (c, x, 0.0),
+        1 => (x, c, 0.0),
+        2 => (0.0, c, x),
+        3 => (0.0, x, c),
+        4 => (x, 0.0, c),
+        _ => (c, 0.0, x),
+    };
+    [
+        to_u8((r + m) * 255.0),
+        to_u8((g + m) * 255.0),
+        to_u8((b + m) * 255.0),
+    ]
+}
+
+// sRGB (D65) <-> CIELAB
+
+const WHITE_X: f64 = 0.95047;
+const WHITE_Y: f64 = 1.0;
+const WHITE_Z: f64 = 1.08883;
+
+fn rgb_to_lab([r, g, b]: [u8; 3]) -> [u8; 3] {
+    let lin = |c: u8| {
+        let c = c as f64 / 255.0;
+        if c <= 0.04045 {
+            c / 12.92
         } else {
-            // Pass through non-image value
-            self.output(ctx, PORT_IMAGE, value).await
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    };
+    let (r, g, b) = (lin(r), lin(g), lin(b));
+    let x = 0.4124 * r + 0.3576 * g + 0.1805 * b;
+    let y = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+    let z = 0.0193 * r + 0.1192 * g + 0.9505 * b;
+
+    let f = |t: f64| {
+        if t > 0.008856 {
+            t.cbrt()
+        } else {
+            7.787 * t + 16.0 / 116.0
+        }
+    };
+    let (fx, fy, fz) = (f(x / WHITE_X), f(y / WHITE_Y), f(z / WHITE_Z));
+    let l = 116.0 * fy - 16.0;
+    let a = 500.0 * (fx - fy);
+    let b = 200.0 * (fy - fz);
+    [to_u8(l / 100.0 * 255.0), to_u8(a + 128.0), to_u8(b + 128.0)]
+}
+
+fn lab_to_rgb([l, a, b]: [u8; 3]) -> [u8; 3] {
+    let l = l as f64 / 255.0 * 100.0;
+    let a = a as f64 - 128.0;
+    let b = b as f64 - 128.0;
+
+    let fy = (l + 16.0) / 116.0;
+    let fx = fy + a / 500.0;
+    let fz = fy - b / 200.0;
+    let finv = |t: f64| {
+        if t.powi(3) > 0.008856 {
+            t.powi(3)
+        } else {
+            (t - 16.0 / 116.0) / 7.787
         }
+    };
+    let (x, y, z) = (finv(fx) * WHITE_X, finv(fy) * WHITE_Y, finv(fz) * WHITE_Z);
+
+    let r = 3.2406 * x - 1.5372 * y - 0.4986 * z;
+    let g = -0.9689 * x + 1.8758 * y + 0.0415 * z;
+    let b = 0.0557 * x - 0.2040 * y + 1.0570 * z;
+    let gamma = |c: f64| {
+        let c = if c <= 0.0031308 {
+            12.92 * c
+        } else {
+            1.055 * c.powf(1.0 / 2.4) - 0.055
+        };
+        to_u8(c * 255.0)
+    };
+    [gamma(r), gamma(g), gamma(b)]
+}
+
+// Thresholding
+
+fn luma(p: &[u8]) -> u8 {
+    to_u8(0.299 * p[0] as f64 + 0.587 * p[1] as f64 + 0.114 * p[2] as f64)
+}
+
+// Returns a black and white image: white where the luma is above the threshold
+fn binarize(image: &PhotonImage, threshold: u8) -> PhotonImage {
+    let pixels: Vec<u8> = image
+        .get_raw_pixels()
+        .chunks_exact(4)
+        .flat_map(|p| {
+            let v = if luma(p) > threshold { 255 } else { 0 };
+            [v, v, v, p[3]]
+        })
+        .collect();
+    PhotonImage::new(pixels, image.get_width(), image.get_height())
+}
+
+// Otsu's method: the threshold maximizing the between-class variance of the luma histogram
+fn otsu_threshold(image: &PhotonImage) -> u8 {
+    let mut histogram = [0u64; 256];
+    for p in image.get_raw_pixels().chunks_exact(4) {
+        histogram[luma(p) as usize] += 1;
     }
+    let total: u64 = histogram.iter().sum();
+    if total == 0 {
+        return 0;
+    }
+    let sum_all: f64 = histogram
+        .iter()
+        .enumerate()
+        .map(|(i, &n)| i as f64 * n as f64)
+        .sum();
+
+    let mut best = (0u8, -1.0);
+    let mut weight_bg = 0u64;
+    let mut sum_bg = 0.0;
+    for (t, &n) in histogram.iter().enumerate() {
+        weight_bg += n;
+        if weight_bg == 0 {
+            continue;
+        }
+        let weight_fg = total - weight_bg;
+        if weight_fg == 0 {
+            break;
+        }
+        sum_bg += t as f64 * n as f64;
+        let mean_bg = sum_bg / weight_bg as f64;
+        let mean_fg = (sum_all - sum_bg) / weight_fg as f64;
+        let variance = weight_bg as f64 * weight_fg as f64 * (mean_bg - mean_fg).powi(2);
+        if variance > best.1 {
+            best = (t as u8, variance);
+        }
+    }
+    best.0
+}
+
+// Batch processing
+
+/// Applies `f` to the images of an array in parallel, preserving the order.
+///
+/// Non-image items and images for which `f` returns None are passed through as is.
+async fn map_images<F>(arr: &Vector<AgentValue>, f: F) -> Result<AgentValue, AgentError>
+where
+    F: Fn(&PhotonImage) -> Option<PhotonImage> + Send + Sync + 'static,
+{
+    let items: Vec<AgentValue> = arr.iter().cloned().collect();
+    let (items, images) = par_map_blocking(items, move |item| item.as_image().and_then(&f)).await?;
+    Ok(AgentValue::array(
+        items
+            .into_iter()
+            .zip(images)
+            .map(|(item, image)| image.map(AgentValue::image).unwrap_or(item))
+            .collect(),
+    ))
+}
+
+/// Runs [`par_map`] on the blocking thread pool, off the async runtime, and gives the
+/// items back with the results.
+async fn par_map_blocking<T, R, F>(items: Vec<T>, f: F) -> Result<(Vec<T>, Vec<R>), AgentError>
+where
+    T: Send + Sync + 'static,
+    R: Send + 'static,
+    F: Fn(&T) -> R + Send + Sync + 'static,
+{
+    tokio::task::spawn_blocking(move || {
+        let results = par_map(&items, f);
+        (items, results)
+    })
+    .await
+    .map_err(|e| AgentError::InvalidValue(format!("Failed to process images: {}", e)))
+}
+
+/// Applies `f` to each item on multiple threads, preserving the order.
+fn par_map<T, R, F>(items: &[T], f: F) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> R + Sync,
+{
+    let threads = std::thread::available_parallelism()
+        .map_or(1, |n| n.get())
+        .min(items.len());
+    if threads <= 1 {
+        return items.iter().map(f).collect();
+    }
+
+    let chunk_size = items.len().div_ceil(threads);
+    let f = &f;
+    std::thread::scope(|s| {
+        let handles: Vec<_> = items
+            .chunks(chunk_size)
+            .map(|chunk| s.spawn(move || chunk.iter().map(f).collect::<Vec<R>>()))
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|h| h.join().unwrap_or_else(|e| std::panic::resume_unwind(e)))
+            .collect()
+    })
 }
 
 // IsChangedImageAgent
@@ -281,10 +1174,12 @@ impl AsAgent for ScaleImageAgent {
     category = CATEGORY,
     inputs = [PORT_IMAGE],
     outputs = [PORT_CHANGED, PORT_UNCHANGED],
-    number_config(name = CONFIG_THRESHOLD, default = 0.01)
+    number_config(name = CONFIG_THRESHOLD, default = 0.01),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
 )]
 struct IsChangedImageAgent {
     data: AgentData,
+    contract: InputContract,
     last_image: Option<Arc<PhotonImage>>,
 }
 
@@ -314,19 +1209,28 @@ impl IsChangedImageAgent {
 
 #[async_trait]
 impl AsAgent for IsChangedImageAgent {
-    fn new(ma: ModularAgent, id: String, spec: AgentSpec) -> Result<Self, AgentError> {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
         Ok(Self {
             data: AgentData::new(ma, id, spec),
+            contract,
             last_image: None,
         })
     }
 
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
+    }
+
     async fn process(
         &mut self,
         ctx: AgentContext,
-        _port: String,
+        port: String,
         value: AgentValue,
     ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
         let config = self.configs()?;
 
         if value.is_image() {
@@ -344,9 +1248,9 @@ impl AsAgent for IsChangedImageAgent {
 
             if is_changed {
                 self.last_image = value.clone().into_image();
-                self.output(ctx, PORT_CHANGED, value).await
+                self.output(self.traced(ctx), PORT_CHANGED, value).await
             } else {
-                self.output(ctx, PORT_UNCHANGED, value).await
+                self.output(self.traced(ctx), PORT_UNCHANGED, value).await
             }
         } else {
             Err(AgentError::InvalidValue(
@@ -362,26 +1266,37 @@ impl AsAgent for IsChangedImageAgent {
     title = "Open Image",
     category = CATEGORY,
     inputs = [PORT_FILENAME],
-    outputs = [PORT_IMAGE]
+    outputs = [PORT_IMAGE],
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
 )]
 struct OpenImageAgent {
     data: AgentData,
+    contract: InputContract,
 }
 
 #[async_trait]
 impl AsAgent for OpenImageAgent {
-    fn new(ma: ModularAgent, id: String, spec: AgentSpec) -> Result<Self, AgentError> {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
         Ok(Self {
             data: AgentData::new(ma, id, spec),
+            contract,
         })
     }
 
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
+    }
+
     async fn process(
         &mut self,
         ctx: AgentContext,
-        _port: String,
+        port: String,
         value: AgentValue,
     ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
         let filename = value
             .as_str()
             .ok_or_else(|| AgentError::InvalidValue("Expected filename string".into()))?;
@@ -391,7 +1306,8 @@ impl AsAgent for OpenImageAgent {
             AgentError::InvalidValue(format!("Failed to open image {}: {}", filename, e))
         })?;
 
-        self.output(ctx, PORT_IMAGE, AgentValue::image(image)).await
+        self.output(self.traced(ctx), PORT_IMAGE, AgentValue::image(image))
+            .await
     }
 }
 
@@ -399,26 +1315,37 @@ impl AsAgent for OpenImageAgent {
     title = "Save Image",
     category = CATEGORY,
     inputs = [PORT_IMAGE_FILENAME],
-    outputs = [PORT_RESULT]
+    outputs = [PORT_RESULT],
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
 )]
 struct SaveImageAgent {
     data: AgentData,
+    contract: InputContract,
 }
 
 #[async_trait]
 impl AsAgent for SaveImageAgent {
-    fn new(ma: ModularAgent, id: String, spec: AgentSpec) -> Result<Self, AgentError> {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
         Ok(Self {
             data: AgentData::new(ma, id, spec),
+            contract,
         })
     }
 
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
+    }
+
     async fn process(
         &mut self,
         ctx: AgentContext,
-        _port: String,
+        port: String,
         value: AgentValue,
     ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
         let Some(image) = value.get_image("image") else {
             return Err(AgentError::InvalidValue(
                 "Expected image value under 'image' key".into(),
@@ -435,6 +1362,482 @@ impl AsAgent for SaveImageAgent {
             |e| AgentError::InvalidValue(format!("Failed to save image {}: {}", filename, e)),
         )?;
 
-        self.output(ctx, PORT_RESULT, AgentValue::unit()).await
+        self.output(self.traced(ctx), PORT_RESULT, AgentValue::unit())
+            .await
+    }
+}
+
+/// Watch Images Agent
+///
+/// Polls the directory at `path` every `interval` and outputs each new or modified image
+/// file, as `{image, filename, size, modified}` on `image_filename` (the `modified` time is
+/// in milliseconds). Files are matched by `extensions` and output in order of modification.
+///
+/// Processed files are remembered by their modification time, in `state file` when set, so
+/// they are not output again after a restart. While paused, the directory is not polled.
+/// Files that fail to load (ex. while still being written) are retried when they change,
+/// and files dropped by a Quota are output on a later poll.
+#[modular_agent(
+    title = "Watch Images",
+    category = CATEGORY,
+    inputs = [PORT_PAUSE, PORT_RESUME],
+    outputs = [PORT_IMAGE_FILENAME],
+    string_config(name = CONFIG_PATH, description = "directory to watch"),
+    string_config(name = CONFIG_INTERVAL, default = WATCH_INTERVAL_DEFAULT, description = "(ex. 10s, 5m, 100ms, 1h, 1d)"),
+    string_config(name = CONFIG_EXTENSIONS, default = IMAGE_EXTENSIONS_DEFAULT),
+    string_config(name = CONFIG_STATE_FILE, title = "state file", description = "file to remember processed files (empty: in memory)"),
+    integer_config(name = CONFIG_MAX_RESTARTS, default = MAX_RESTARTS_DEFAULT, title = "max restarts", description = "-1: unlimited", detail),
+    integer_config(name = CONFIG_TASK_RESTARTS, title = "task restarts", readonly, detail),
+)]
+struct WatchImagesAgent {
+    data: AgentData,
+    handle: Option<JoinHandle<()>>,
+    paused: PauseState,
+    state: Arc<Mutex<WatchState>>,
+}
+
+impl WatchImagesAgent {
+    fn start_watch(&mut self) -> Result<(), AgentError> {
+        let config = self.configs()?;
+        let dir = PathBuf::from(config.get_string_resolved(CONFIG_PATH)?);
+        if !dir.is_dir() {
+            return Err(AgentError::InvalidConfig(format!(
+                "Path is not a directory: {}",
+                dir.display()
+            )));
+        }
+        let interval = Duration::from_millis(parse_duration_to_ms(
+            &config.get_string_or(CONFIG_INTERVAL, WATCH_INTERVAL_DEFAULT),
+        )?);
+        let extensions =
+            parse_extensions(&config.get_string_or(CONFIG_EXTENSIONS, IMAGE_EXTENSIONS_DEFAULT));
+        let state_file = config.get_string_resolved(CONFIG_STATE_FILE)?;
+        let state_file = (!state_file.is_empty()).then(|| PathBuf::from(state_file));
+        let max_restarts = config.get_integer_or(CONFIG_MAX_RESTARTS, MAX_RESTARTS_DEFAULT);
+
+        if let Some(state_file) = &state_file {
+            *self.state.lock().unwrap() = WatchState::load(state_file)?;
+        }
+
+        let state = self.state.clone();
+        let paused = self.paused.clone();
+        let ma = self.ma().clone();
+        let agent_id = self.id().to_string();
+        let def_name = self.def_name().to_string();
+        let preset_id = self.preset_id().to_string();
+        let handle = spawn_supervised(self, max_restarts, move || {
+            let dir = dir.clone();
+            let extensions = extensions.clone();
+            let state_file = state_file.clone();
+            let state = state.clone();
+            let paused = paused.clone();
+            let ma = ma.clone();
+            let agent_id = agent_id.clone();
+            let def_name = def_name.clone();
+            let preset_id = preset_id.clone();
+            async move {
+                let mut interval = tokio::time::interval(interval);
+                loop {
+                    interval.tick().await;
+                    if paused.is_paused() {
+                        continue;
+                    }
+
+                    let dir = dir.clone();
+                    let extensions = extensions.clone();
+                    let save = state_file.is_some();
+                    let state = state.clone();
+                    let ma = ma.clone();
+                    let poll_agent_id = agent_id.clone();
+                    let poll_def_name = def_name.clone();
+                    let preset_id = preset_id.clone();
+                    // listing and decoding files blocks
+                    let polled = tokio::task::spawn_blocking(move || {
+                        let (agent_id, def_name) = (poll_agent_id, poll_def_name);
+                        let mut state = state.lock().unwrap();
+                        let changed = poll_images(&dir, &extensions, &mut state, |value| {
+                            if !admit(&preset_id, &agent_id, &value) {
+                                return false;
+                            }
+                            match ma.try_send_agent_out(
+                                agent_id.clone(),
+                                stamp(AgentContext::new(), &agent_id, &def_name),
+                                PORT_IMAGE_FILENAME.to_string(),
+                                value,
+                            ) {
+                                Ok(()) => true,
+                                Err(e) => {
+                                    log::error!("Failed to send watched image: {}", e);
+                                    false
+                                }
+                            }
+                        })?;
+                        // the state to save
+                        (changed && save).then(|| state.to_text()).transpose()
+                    })
+                    .await;
+                    match polled {
+                        Ok(Ok(Some(text))) => {
+                            let Some(state_file) = &state_file else {
+                                continue;
+                            };
+                            let target = state_file.display().to_string();
+                            let payload = AgentValue::string(&text);
+                            let ctx = AgentContext::new();
+                            let saved = audit_by(
+                                &agent_id,
+                                &def_name,
+                                &ctx,
+                                ACTION_WRITE_FILE,
+                                &target,
+                                &payload,
+                            )
+                            .await
+                            .and_then(|_| WatchState::write(state_file, &text));
+                            if let Err(e) = saved {
+                                log::error!("Failed to save the watch state: {}", e);
+                            }
+                        }
+                        Ok(Ok(None)) => {}
+                        Ok(Err(e)) => log::error!("Failed to watch images: {}", e),
+                        // propagate panics to the supervisor
+                        Err(e) => std::panic::resume_unwind(e.into_panic()),
+                    }
+                }
+            }
+        });
+        self.handle = Some(handle);
+        Ok(())
+    }
+
+    fn stop_watch(&mut self) {
+        if let Some(handle) = self.handle.take() {
+            handle.abort();
+        }
+    }
+}
+
+#[async_trait]
+impl AsAgent for WatchImagesAgent {
+    fn new(ma: ModularAgent, id: String, spec: AgentSpec) -> Result<Self, AgentError> {
+        Ok(Self {
+            data: AgentData::new(ma, id, spec),
+            handle: None,
+            paused: Default::default(),
+            state: Default::default(),
+        })
+    }
+
+    async fn start(&mut self) -> Result<(), AgentError> {
+        reset_task_restarts(self)?;
+        self.paused.set_paused(false);
+        self.start_watch()
+    }
+
+    async fn stop(&mut self) -> Result<(), AgentError> {
+        self.stop_watch();
+        Ok(())
+    }
+
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        if self.handle.is_some() {
+            self.stop_watch();
+            self.start_watch()?;
+        }
+        Ok(())
+    }
+
+    async fn process(
+        &mut self,
+        _ctx: AgentContext,
+        port: String,
+        _value: AgentValue,
+    ) -> Result<(), AgentError> {
+        if self.paused.handle_port(&port) {
+            return Ok(());
+        }
+        Err(AgentError::InvalidPin(port))
+    }
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct WatchState {
+    // modification times (ms) of the processed files by path
+    processed: HashMap<String, i64>,
+    // modification times of the files that failed to load, to retry only when they change
+    #[serde(skip)]
+    failed: HashMap<String, i64>,
+}
+
+impl WatchState {
+    fn load(path: &Path) -> Result<Self, AgentError> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let text = std::fs::read_to_string(path).map_err(|e| {
+            AgentError::InvalidValue(format!("Failed to read {}: {}", path.display(), e))
+        })?;
+        serde_json::from_str(&text).map_err(|e| {
+            AgentError::InvalidValue(format!("Failed to parse {}: {}", path.display(), e))
+        })
+    }
+
+    fn to_text(&self) -> Result<String, AgentError> {
+        serde_json::to_string(self)
+            .map_err(|e| AgentError::InvalidValue(format!("Failed to serialize state: {}", e)))
+    }
+
+    fn write(path: &Path, text: &str) -> Result<(), AgentError> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| {
+                AgentError::InvalidValue(format!("Failed to create parent directories: {}", e))
+            })?;
+        }
+        std::fs::write(path, text).map_err(|e| {
+            AgentError::InvalidValue(format!("Failed to write {}: {}", path.display(), e))
+        })
+    }
+}
+
+fn parse_extensions(extensions: &str) -> Vec<String> {
+    extensions
+        .split(',')
+        .map(|ext| ext.trim().trim_start_matches('.').to_lowercase())
+        .filter(|ext| !ext.is_empty())
+        .collect()
+}
+
+// Loads the new or modified images in `dir`, and passes them to `emit`.
+//
+// A file is processed only when `emit` returns true; otherwise it and the rest are
+// left for the next poll. The files that are gone are forgotten.
+// Returns whether the state changed.
+fn poll_images(
+    dir: &Path,
+    extensions: &[String],
+    state: &mut WatchState,
+    mut emit: impl FnMut(AgentValue) -> bool,
+) -> Result<bool, AgentError> {
+    let entries = std::fs::read_dir(dir).map_err(|e| {
+        AgentError::InvalidValue(format!("Failed to read directory {}: {}", dir.display(), e))
+    })?;
+
+    let mut files = Vec::new();
+    let mut present = HashSet::new();
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let matched = path
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase())
+            .is_some_and(|ext| extensions.contains(&ext));
+        if !matched {
+            continue;
+        }
+        let Ok(meta) = entry.metadata() else {
+            continue;
+        };
+        if !meta.is_file() {
+            continue;
+        }
+        let modified = meta
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_millis() as i64)
+            .unwrap_or_default();
+        let key = path.to_string_lossy().to_string();
+        present.insert(key.clone());
+        if state.processed.get(&key) == Some(&modified) || state.failed.get(&key) == Some(&modified)
+        {
+            continue;
+        }
+        files.push((modified, key, meta.len()));
+    }
+    files.sort();
+
+    let known = state.processed.len();
+    state.processed.retain(|key, _| present.contains(key));
+    state.failed.retain(|key, _| present.contains(key));
+    let mut changed = state.processed.len() != known;
+
+    for (modified, key, size) in files {
+        let image = match photon_rs::native::open_image(Path::new(&key)) {
+            Ok(image) => image,
+            Err(e) => {
+                log::warn!("Failed to open image {}: {}", key, e);
+                state.failed.insert(key, modified);
+                continue;
+            }
+        };
+        state.failed.remove(&key);
+        let emitted = emit(AgentValue::object(hashmap! {
+            "image".to_string() => AgentValue::image(image),
+            "filename".to_string() => AgentValue::string(key.clone()),
+            "size".to_string() => AgentValue::integer(size as i64),
+            "modified".to_string() => AgentValue::integer(modified),
+        }));
+        if !emitted {
+            break;
+        }
+        state.processed.insert(key, modified);
+        changed = true;
+    }
+    Ok(changed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_map_images_preserves_order() {
+        let images: Vector<AgentValue> = (1..=9)
+            .map(|w| AgentValue::image(PhotonImage::new(vec![0; w * 4], w as u32, 1)))
+            .chain(std::iter::once(AgentValue::string("not an image")))
+            .collect();
+
+        let scaled = map_images(&images, |image| scale_image(image, 2.0))
+            .await
+            .unwrap();
+        let arr = scaled.as_array().unwrap();
+        assert_eq!(arr.len(), 10);
+        for (i, item) in arr.iter().take(9).enumerate() {
+            let image = item.as_image().unwrap();
+            assert_eq!(image.get_width(), (i as u32 + 1) * 2);
+            assert_eq!(image.get_height(), 2);
+        }
+        assert_eq!(arr[9], AgentValue::string("not an image"));
+
+        // factor 1.0 passes the images through
+        let same = map_images(&images, |image| scale_image(image, 1.0))
+            .await
+            .unwrap();
+        assert_eq!(same.as_array().unwrap(), &images);
+    }
+
+    #[test]
+    fn test_adjust_image() {
+        let image = pixel_image(&[[0, 100, 200, 50], [128, 128, 255, 255]]);
+        assert!(adjust_image(&image, 0, 0.0).is_none());
+
+        let brighter = adjust_image(&image, 100, 0.0).unwrap();
+        assert_eq!(
+            brighter.get_raw_pixels(),
+            [[100, 200, 255, 50], [228, 228, 255, 255]].concat()
+        );
+        let flat = adjust_image(&image, 0, -255.0).unwrap();
+        assert_eq!(
+            flat.get_raw_pixels(),
+            [[128, 128, 128, 50], [128, 128, 128, 255]].concat()
+        );
+        let sharp = adjust_image(&image, 0, 255.0).unwrap();
+        assert_eq!(
+            sharp.get_raw_pixels(),
+            [[0, 0, 255, 50], [128, 128, 255, 255]].concat()
+        );
+    }
+
+    fn pixel_image(pixels: &[[u8; 4]]) -> PhotonImage {
+        PhotonImage::new(pixels.concat(), pixels.len() as u32, 1)
+    }
+
+    #[test]
+    fn test_channels() {
+        let image = pixel_image(&[[10, 20, 30, 40], [50, 60, 70, 80]]);
+        let r = extract_channel(&image, channel_index("r").unwrap());
+        assert_eq!(r.get_raw_pixels(), vec![10, 10, 10, 255, 50, 50, 50, 255]);
+
+        let channels: Vec<PhotonImage> = (0..4).map(|c| extract_channel(&image, c)).collect();
+        let refs: Vec<&PhotonImage> = channels.iter().collect();
+        assert_eq!(
+            merge_channels(&refs).unwrap().get_raw_pixels(),
+            image.get_raw_pixels()
+        );
+        assert_eq!(
+            merge_channels(&refs[..3]).unwrap().get_raw_pixels(),
+            vec![10, 20, 30, 255, 50, 60, 70, 255]
+        );
+        assert!(merge_channels(&refs[..2]).is_err());
+        assert!(channel_index("x").is_err());
+    }
+
+    #[test]
+    fn test_color_space_round_trip() {
+        assert_eq!(rgb_to_hsv([255, 0, 0]), [0, 255, 255]);
+        assert_eq!(rgb_to_lab([255, 255, 255]), [255, 128, 128]);
+        for p in [[255, 0, 0], [0, 128, 255], [200, 180, 20], [30, 30, 30]] {
+            for (a, b) in [hsv_to_rgb(rgb_to_hsv(p)), lab_to_rgb(rgb_to_lab(p))]
+                .into_iter()
+                .flat_map(|q| q.into_iter().zip(p))
+            {
+                // 8-bit LAB loses precision near black
+                assert!(a.abs_diff(b) <= 8, "{:?}", p);
+            }
+        }
+    }
+
+    #[test]
+    fn test_threshold() {
+        let image = pixel_image(&[[10, 10, 10, 255], [20, 20, 20, 255], [200, 200, 200, 255]]);
+        let t = otsu_threshold(&image);
+        assert!((20..200).contains(&t));
+        assert_eq!(
+            binarize(&image, t).get_raw_pixels(),
+            vec![0, 0, 0, 255, 0, 0, 0, 255, 255, 255, 255, 255]
+        );
+    }
+
+    #[test]
+    fn test_poll_images() {
+        let dir = std::env::temp_dir().join(format!(
+            "modular_agent_std_watch_images_{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let image = pixel_image(&[[10, 20, 30, 255], [40, 50, 60, 255]]);
+        photon_rs::native::save_image(image, dir.join("a.png")).unwrap();
+        std::fs::write(dir.join("b.jpg"), "not an image").unwrap();
+        std::fs::write(dir.join("c.txt"), "ignored").unwrap();
+
+        let extensions = parse_extensions(IMAGE_EXTENSIONS_DEFAULT);
+        let mut state = WatchState::default();
+        // a file that is not sent is left for the next poll
+        let changed = poll_images(&dir, &extensions, &mut state, |_| false).unwrap();
+        assert!(!changed);
+        assert!(state.processed.is_empty());
+
+        let mut values = Vec::new();
+        let changed = poll_images(&dir, &extensions, &mut state, |v| {
+            values.push(v);
+            true
+        })
+        .unwrap();
+        assert!(changed);
+        assert_eq!(values.len(), 1);
+        assert_eq!(
+            values[0].get_str("filename"),
+            Some(dir.join("a.png").to_string_lossy().as_ref())
+        );
+        assert_eq!(values[0].get_image("image").unwrap().get_width(), 2);
+
+        // processed and failed files are skipped until they change
+        let changed = poll_images(&dir, &extensions, &mut state, |_| panic!()).unwrap();
+        assert!(!changed);
+
+        // the processed files are remembered in the state file
+        let state_file = dir.join("state").join("watch.json");
+        WatchState::write(&state_file, &state.to_text().unwrap()).unwrap();
+        let mut state = WatchState::load(&state_file).unwrap();
+        assert_eq!(state.processed.len(), 1);
+        poll_images(&dir, &extensions, &mut state, |_| panic!()).unwrap();
+
+        // the files that are gone are forgotten
+        std::fs::remove_file(dir.join("a.png")).unwrap();
+        let changed = poll_images(&dir, &extensions, &mut state, |_| panic!()).unwrap();
+        assert!(changed);
+        assert!(state.processed.is_empty());
+
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 }
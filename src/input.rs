@@ -5,6 +5,8 @@ use modular_agent_core::{
     AsAgent, ModularAgent, async_trait, modular_agent,
 };
 
+use crate::provenance::Traced;
+
 const CATEGORY: &str = "Std/Input";
 
 const UNIT: &str = "unit";
@@ -40,7 +42,7 @@ impl AsAgent for UnitInputAgent {
         // Since set_config is called even when the agent is not running,
         // we need to check the status before outputting the value.
         if *self.status() == AgentStatus::Start {
-            self.try_output(AgentContext::new(), UNIT, AgentValue::unit())?;
+            self.try_output(self.traced(AgentContext::new()), UNIT, AgentValue::unit())?;
         }
 
         Ok(())
@@ -72,7 +74,7 @@ impl AsAgent for BooleanInputAgent {
     fn configs_changed(&mut self) -> Result<(), AgentError> {
         if *self.status() == AgentStatus::Start {
             let value = self.configs()?.get(BOOLEAN)?;
-            self.try_output(AgentContext::new(), BOOLEAN, value.clone())?;
+            self.try_output(self.traced(AgentContext::new()), BOOLEAN, value.clone())?;
         }
         Ok(())
     }
@@ -84,7 +86,7 @@ impl AsAgent for BooleanInputAgent {
         _value: AgentValue,
     ) -> Result<(), AgentError> {
         let value = self.configs()?.get(BOOLEAN)?;
-        self.output(ctx, BOOLEAN, value.clone()).await
+        self.output(self.traced(ctx), BOOLEAN, value.clone()).await
     }
 }
 
@@ -113,7 +115,7 @@ impl AsAgent for IntegerInputAgent {
     fn configs_changed(&mut self) -> Result<(), AgentError> {
         if *self.status() == AgentStatus::Start {
             let value = self.configs()?.get(INTEGER)?;
-            self.try_output(AgentContext::new(), INTEGER, value.clone())?;
+            self.try_output(self.traced(AgentContext::new()), INTEGER, value.clone())?;
         }
         Ok(())
     }
@@ -125,7 +127,7 @@ impl AsAgent for IntegerInputAgent {
         _value: AgentValue,
     ) -> Result<(), AgentError> {
         let value = self.configs()?.get(INTEGER)?;
-        self.output(ctx, INTEGER, value.clone()).await
+        self.output(self.traced(ctx), INTEGER, value.clone()).await
     }
 }
 
@@ -154,7 +156,11 @@ impl AsAgent for NumberInputAgent {
     fn configs_changed(&mut self) -> Result<(), AgentError> {
         if *self.status() == AgentStatus::Start {
             let value = self.configs()?.get_number(NUMBER)?; // Should we use to_number here?
-            self.try_output(AgentContext::new(), NUMBER, AgentValue::number(value))?;
+            self.try_output(
+                self.traced(AgentContext::new()),
+                NUMBER,
+                AgentValue::number(value),
+            )?;
         }
         Ok(())
     }
@@ -166,7 +172,8 @@ impl AsAgent for NumberInputAgent {
         _value: AgentValue,
     ) -> Result<(), AgentError> {
         let value = self.configs()?.get_number(NUMBER)?;
-        self.output(ctx, NUMBER, AgentValue::number(value)).await
+        self.output(self.traced(ctx), NUMBER, AgentValue::number(value))
+            .await
     }
 }
 
@@ -195,7 +202,7 @@ impl AsAgent for StringInputAgent {
     fn configs_changed(&mut self) -> Result<(), AgentError> {
         if *self.status() == AgentStatus::Start {
             let value = self.configs()?.get(STRING)?;
-            self.try_output(AgentContext::new(), STRING, value.clone())?;
+            self.try_output(self.traced(AgentContext::new()), STRING, value.clone())?;
         }
         Ok(())
     }
@@ -207,7 +214,7 @@ impl AsAgent for StringInputAgent {
         _value: AgentValue,
     ) -> Result<(), AgentError> {
         let value = self.configs()?.get(STRING)?;
-        self.output(ctx, STRING, value.clone()).await
+        self.output(self.traced(ctx), STRING, value.clone()).await
     }
 }
 
@@ -236,7 +243,7 @@ impl AsAgent for TextInputAgent {
     fn configs_changed(&mut self) -> Result<(), AgentError> {
         if *self.status() == AgentStatus::Start {
             let value = self.configs()?.get(TEXT)?;
-            self.try_output(AgentContext::new(), TEXT, value.clone())?;
+            self.try_output(self.traced(AgentContext::new()), TEXT, value.clone())?;
         }
         Ok(())
     }
@@ -248,7 +255,7 @@ impl AsAgent for TextInputAgent {
         _value: AgentValue,
     ) -> Result<(), AgentError> {
         let value = self.configs()?.get(TEXT)?;
-        self.output(ctx, TEXT, value.clone()).await
+        self.output(self.traced(ctx), TEXT, value.clone()).await
     }
 }
 
@@ -277,7 +284,7 @@ impl AsAgent for ObjectInputAgent {
     fn configs_changed(&mut self) -> Result<(), AgentError> {
         if *self.status() == AgentStatus::Start {
             let value = self.configs()?.get(OBJECT)?;
-            self.try_output(AgentContext::new(), OBJECT, value.clone())?;
+            self.try_output(self.traced(AgentContext::new()), OBJECT, value.clone())?;
         }
         Ok(())
     }
@@ -289,6 +296,6 @@ impl AsAgent for ObjectInputAgent {
         _value: AgentValue,
     ) -> Result<(), AgentError> {
         let value = self.configs()?.get(OBJECT)?;
-        self.output(ctx, OBJECT, value.clone()).await
+        self.output(self.traced(ctx), OBJECT, value.clone()).await
     }
 }
@@ -0,0 +1,3594 @@
+//! Sensor payload decoders and equipment polling.
+//!
+//! Binary payloads are taken as arrays of bytes (see `bytes`), or as hex strings like
+//! `"0a1b2c"` (whitespace, `:` and `-` between bytes are ignored), since BLE scanners and
+//! serial tools usually report them so.
+//!
+//! Equipment is polled with SNMP GET (v1, v2c, and v3 with USM authentication and
+//! privacy), or OPC UA Read over the binary TCP protocol without security.
+
+use std::net::Ipv6Addr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use aes::cipher::block_padding::NoPadding;
+use aes::cipher::{AsyncStreamCipher, BlockDecryptMut, BlockEncryptMut, KeyInit, KeyIvInit};
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use im::{HashMap, hashmap};
+use md5::Md5;
+use modular_agent_core::{
+    Agent, AgentContext, AgentData, AgentError, AgentOutput, AgentSpec, AgentValue, AsAgent,
+    ModularAgent, async_trait, modular_agent,
+};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket, lookup_host};
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+use crate::bytes::{bytes_to_value, value_to_bytes};
+use crate::contract::{CONFIG_INPUT_CONTRACT, ContractAgent, InputContract, contract_agents};
+use crate::control::{PORT_PAUSE, PORT_RESUME, PauseState};
+use crate::profile::{ProfileConfigs, resolve};
+use crate::provenance::{Traced, stamp};
+use crate::quota::admit;
+use crate::supervisor::{
+    CONFIG_MAX_RESTARTS, CONFIG_TASK_RESTARTS, MAX_RESTARTS_DEFAULT, reset_task_restarts,
+    spawn_supervised,
+};
+use crate::time::parse_duration_to_ms;
+
+const CATEGORY: &str = "Std/Iot";
+
+const PORT_BYTES: &str = "bytes";
+const PORT_CALIB: &str = "calib";
+const PORT_DATA: &str = "data";
+const PORT_ERROR: &str = "error";
+const PORT_SENTENCE: &str = "sentence";
+const PORT_VALUE: &str = "value";
+
+const CONFIG_AUTH_PASSWORD: &str = "auth_password";
+const CONFIG_AUTH_PROTOCOL: &str = "auth_protocol";
+const CONFIG_CHECKSUM: &str = "checksum";
+const CONFIG_COMMUNITY: &str = "community";
+const CONFIG_CONTEXT: &str = "context";
+const CONFIG_ENDPOINT: &str = "endpoint";
+const CONFIG_FORMAT: &str = "format";
+const CONFIG_HOST: &str = "host";
+const CONFIG_INTERVAL: &str = "interval";
+const CONFIG_NODES: &str = "nodes";
+const CONFIG_OIDS: &str = "oids";
+const CONFIG_PRIV_PASSWORD: &str = "priv_password";
+const CONFIG_PRIV_PROTOCOL: &str = "priv_protocol";
+const CONFIG_TIMEOUT: &str = "timeout";
+const CONFIG_USER: &str = "user";
+const CONFIG_VERSION: &str = "version";
+
+const COMMUNITY_DEFAULT: &str = "public";
+const INTERVAL_DEFAULT: &str = "10s";
+const OPCUA_TIMEOUT_DEFAULT: &str = "5s";
+const SNMP_PORT: u16 = 161;
+const TIMEOUT_DEFAULT: &str = "2s";
+const VERSION_DEFAULT: &str = "2c";
+
+const FORMAT_ATC: &str = "atc";
+const FORMAT_AUTO: &str = "auto";
+const FORMAT_MIBEACON: &str = "mibeacon";
+const FORMAT_PVVX: &str = "pvvx";
+
+/// Returns the bytes of a payload: a hex string, or a binary value.
+fn payload_bytes(value: &AgentValue) -> Result<Vec<u8>, AgentError> {
+    let Some(s) = value.as_str() else {
+        return value_to_bytes(value);
+    };
+    let digits: Vec<u8> = s
+        .bytes()
+        .filter(|b| !b.is_ascii_whitespace() && *b != b':' && *b != b'-')
+        .collect();
+    let invalid = || AgentError::InvalidValue(format!("Invalid hex payload: {}", s));
+    if !digits.len().is_multiple_of(2) {
+        return Err(invalid());
+    }
+    digits
+        .chunks(2)
+        .map(|pair| {
+            std::str::from_utf8(pair)
+                .ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                .ok_or_else(invalid)
+        })
+        .collect()
+}
+
+fn u16_le(b: &[u8], i: usize) -> u16 {
+    u16::from_le_bytes([b[i], b[i + 1]])
+}
+
+fn i16_le(b: &[u8], i: usize) -> i16 {
+    i16::from_le_bytes([b[i], b[i + 1]])
+}
+
+fn number(x: f64) -> AgentValue {
+    AgentValue::number(x)
+}
+
+// BME280
+
+/// Calibration of a BME280, from registers 0x88..0xA1 and 0xE1..0xE7.
+#[derive(Clone, Debug, Default)]
+struct Bme280Calib {
+    t: [f64; 3],
+    p: [f64; 9],
+    // None for a BMP280, which has no humidity sensor
+    h: Option<[f64; 6]>,
+}
+
+impl Bme280Calib {
+    fn parse(b: &[u8]) -> Result<Self, AgentError> {
+        if b.len() != 26 && b.len() != 33 {
+            return Err(AgentError::InvalidValue(format!(
+                "BME280 calibration must be 26 or 33 bytes, got {}",
+                b.len()
+            )));
+        }
+        let t = [
+            u16_le(b, 0) as f64,
+            i16_le(b, 2) as f64,
+            i16_le(b, 4) as f64,
+        ];
+        let mut p = [u16_le(b, 6) as f64; 9];
+        for (i, p) in p.iter_mut().enumerate().skip(1) {
+            *p = i16_le(b, 6 + i * 2) as f64;
+        }
+        let h = (b.len() == 33).then(|| {
+            // 0xA1, then 0xE1.. from index 26
+            let e = &b[26..];
+            [
+                b[25] as f64,
+                i16_le(e, 0) as f64,
+                e[2] as f64,
+                (((e[3] as i8 as i16) << 4) | (e[4] & 0x0f) as i16) as f64,
+                (((e[5] as i8 as i16) << 4) | (e[4] >> 4) as i16) as f64,
+                e[6] as i8 as f64,
+            ]
+        });
+        Ok(Self { t, p, h })
+    }
+
+    /// Compensates the raw registers 0xF7..0xFE (0xF7..0xFC for a BMP280), with the
+    /// floating point formulas of the datasheet.
+    fn compensate(&self, d: &[u8]) -> Result<AgentValue, AgentError> {
+        if d.len() != 6 && d.len() != 8 {
+            return Err(AgentError::InvalidValue(format!(
+                "BME280 data must be 6 or 8 bytes, got {}",
+                d.len()
+            )));
+        }
+        let adc_p = ((d[0] as u32) << 12 | (d[1] as u32) << 4 | (d[2] as u32) >> 4) as f64;
+        let adc_t = ((d[3] as u32) << 12 | (d[4] as u32) << 4 | (d[5] as u32) >> 4) as f64;
+
+        let [t1, t2, t3] = self.t;
+        let var1 = (adc_t / 16384.0 - t1 / 1024.0) * t2;
+        let var2 = (adc_t / 131072.0 - t1 / 8192.0).powi(2) * t3;
+        let t_fine = var1 + var2;
+        let mut result = hashmap! {
+            "temperature".to_string() => number(t_fine / 5120.0),
+        };
+
+        let [p1, p2, p3, p4, p5, p6, p7, p8, p9] = self.p;
+        let mut var1 = t_fine / 2.0 - 64000.0;
+        let mut var2 = var1 * var1 * p6 / 32768.0;
+        var2 += var1 * p5 * 2.0;
+        var2 = var2 / 4.0 + p4 * 65536.0;
+        var1 = (p3 * var1 * var1 / 524288.0 + p2 * var1) / 524288.0;
+        var1 = (1.0 + var1 / 32768.0) * p1;
+        if var1 != 0.0 {
+            let mut p = 1048576.0 - adc_p;
+            p = (p - var2 / 4096.0) * 6250.0 / var1;
+            let var1 = p9 * p * p / 2147483648.0;
+            let var2 = p * p8 / 32768.0;
+            p += (var1 + var2 + p7) / 16.0;
+            result.insert("pressure".to_string(), number(p / 100.0));
+        }
+
+        // 0x8000 is the reset value when humidity measurement is skipped
+        if let (Some([h1, h2, h3, h4, h5, h6]), 8) = (self.h, d.len())
+            && !(d[6] == 0x80 && d[7] == 0x00)
+        {
+            let adc_h = ((d[6] as u32) << 8 | d[7] as u32) as f64;
+            let mut h = t_fine - 76800.0;
+            h = (adc_h - (h4 * 64.0 + h5 / 16384.0 * h))
+                * (h2 / 65536.0 * (1.0 + h6 / 67108864.0 * h * (1.0 + h3 / 67108864.0 * h)));
+            h *= 1.0 - h1 * h / 524288.0;
+            result.insert("humidity".to_string(), number(h.clamp(0.0, 100.0)));
+        }
+        Ok(AgentValue::object(result))
+    }
+}
+
+contract_agents!(Bme280DecodeAgent, XiaomiBleDecodeAgent, NmeaDecodeAgent);
+
+/// Decodes the raw registers of a Bosch BME280 (or BMP280) into
+/// `{temperature, pressure, humidity}` in °C, hPa and %.
+///
+/// The calibration registers (0x88..0xA1, then 0xE1..0xE7 for a BME280) are given once on
+/// `calib` and kept. Each value on `data` (the registers 0xF7..0xFE) is then decoded.
+#[modular_agent(
+    title = "BME280 Decode",
+    category = CATEGORY,
+    inputs = [PORT_CALIB, PORT_DATA],
+    outputs = [PORT_VALUE],
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
+)]
+struct Bme280DecodeAgent {
+    data: AgentData,
+    contract: InputContract,
+    calib: Option<Bme280Calib>,
+}
+
+#[async_trait]
+impl AsAgent for Bme280DecodeAgent {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
+        Ok(Self {
+            data: AgentData::new(ma, id, spec),
+            contract,
+            calib: None,
+        })
+    }
+
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
+    }
+
+    async fn process(
+        &mut self,
+        ctx: AgentContext,
+        port: String,
+        value: AgentValue,
+    ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
+        match port.as_str() {
+            PORT_CALIB => {
+                self.calib = Some(Bme280Calib::parse(&payload_bytes(&value)?)?);
+                Ok(())
+            }
+            PORT_DATA => {
+                let Some(calib) = &self.calib else {
+                    return Err(AgentError::InvalidValue(
+                        "No calibration received on calib yet".into(),
+                    ));
+                };
+                let decoded = calib.compensate(&payload_bytes(&value)?)?;
+                self.output(self.traced(ctx), PORT_VALUE, decoded).await
+            }
+            _ => Err(AgentError::InvalidPin(port)),
+        }
+    }
+}
+
+// Xiaomi BLE
+
+fn mac_string(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|b| format!("{:02X}", b))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+/// Decodes the service data of a Xiaomi BLE advertisement.
+///
+/// `atc` and `pvvx` are the custom firmware formats of the LYWSD03MMC thermometers;
+/// `mibeacon` is the stock format (service UUID 0xFE95), unencrypted only.
+fn decode_xiaomi(b: &[u8], format: &str) -> Result<AgentValue, AgentError> {
+    let format = match format {
+        FORMAT_AUTO => match b.len() {
+            13 => FORMAT_ATC,
+            15 => FORMAT_PVVX,
+            _ => FORMAT_MIBEACON,
+        },
+        format => format,
+    };
+    let short = |n: usize| {
+        AgentError::InvalidValue(format!(
+            "{} payload must be {} bytes, got {}",
+            format,
+            n,
+            b.len()
+        ))
+    };
+    let mut out = HashMap::new();
+    match format {
+        FORMAT_ATC => {
+            if b.len() < 13 {
+                return Err(short(13));
+            }
+            out.insert("mac".to_string(), AgentValue::string(mac_string(&b[0..6])));
+            let temperature = i16::from_be_bytes([b[6], b[7]]) as f64 / 10.0;
+            out.insert("temperature".to_string(), number(temperature));
+            out.insert("humidity".to_string(), number(b[8] as f64));
+            out.insert("battery".to_string(), AgentValue::integer(b[9] as i64));
+            let voltage = u16::from_be_bytes([b[10], b[11]]) as f64 / 1000.0;
+            out.insert("voltage".to_string(), number(voltage));
+            out.insert("counter".to_string(), AgentValue::integer(b[12] as i64));
+        }
+        FORMAT_PVVX => {
+            if b.len() < 15 {
+                return Err(short(15));
+            }
+            let mac: Vec<u8> = b[0..6].iter().rev().copied().collect();
+            out.insert("mac".to_string(), AgentValue::string(mac_string(&mac)));
+            out.insert(
+                "temperature".to_string(),
+                number(i16_le(b, 6) as f64 / 100.0),
+            );
+            out.insert("humidity".to_string(), number(u16_le(b, 8) as f64 / 100.0));
+            out.insert("voltage".to_string(), number(u16_le(b, 10) as f64 / 1000.0));
+            out.insert("battery".to_string(), AgentValue::integer(b[12] as i64));
+            out.insert("counter".to_string(), AgentValue::integer(b[13] as i64));
+        }
+        FORMAT_MIBEACON => decode_mibeacon(b, &mut out)?,
+        other => {
+            return Err(AgentError::InvalidConfig(format!(
+                "Unknown format: {}",
+                other
+            )));
+        }
+    }
+    Ok(AgentValue::object(out))
+}
+
+fn decode_mibeacon(b: &[u8], out: &mut HashMap<String, AgentValue>) -> Result<(), AgentError> {
+    let truncated = || AgentError::InvalidValue("Truncated MiBeacon payload".into());
+    if b.len() < 5 {
+        return Err(truncated());
+    }
+    let frame_control = u16_le(b, 0);
+    if frame_control & 0x0008 != 0 {
+        return Err(AgentError::InvalidValue(
+            "Encrypted MiBeacon payloads are not supported".into(),
+        ));
+    }
+    out.insert(
+        "product_id".to_string(),
+        AgentValue::integer(u16_le(b, 2) as i64),
+    );
+    out.insert("counter".to_string(), AgentValue::integer(b[4] as i64));
+    let mut i = 5;
+    if frame_control & 0x0010 != 0 {
+        let mac: Vec<u8> = b
+            .get(i..i + 6)
+            .ok_or_else(truncated)?
+            .iter()
+            .rev()
+            .copied()
+            .collect();
+        out.insert("mac".to_string(), AgentValue::string(mac_string(&mac)));
+        i += 6;
+    }
+    if frame_control & 0x0020 != 0 {
+        let capability = *b.get(i).ok_or_else(truncated)?;
+        i += 1;
+        if capability & 0x20 != 0 {
+            i += 2;
+        }
+    }
+    if frame_control & 0x0040 == 0 {
+        return Ok(());
+    }
+    while i + 3 <= b.len() {
+        let object_type = u16_le(b, i);
+        let len = b[i + 2] as usize;
+        let data = b.get(i + 3..i + 3 + len).ok_or_else(truncated)?;
+        i += 3 + len;
+        match (object_type, len) {
+            (0x1004, 2) => {
+                out.insert(
+                    "temperature".to_string(),
+                    number(i16_le(data, 0) as f64 / 10.0),
+                );
+            }
+            (0x1006, 2) => {
+                out.insert(
+                    "humidity".to_string(),
+                    number(u16_le(data, 0) as f64 / 10.0),
+                );
+            }
+            (0x1007, 3) => {
+                let lux = u32::from_le_bytes([data[0], data[1], data[2], 0]);
+                out.insert("illuminance".to_string(), AgentValue::integer(lux as i64));
+            }
+            (0x1008, 1) => {
+                out.insert("moisture".to_string(), AgentValue::integer(data[0] as i64));
+            }
+            (0x1009, 2) => {
+                let conductivity = u16_le(data, 0) as i64;
+                out.insert(
+                    "conductivity".to_string(),
+                    AgentValue::integer(conductivity),
+                );
+            }
+            (0x100A, 1) => {
+                out.insert("battery".to_string(), AgentValue::integer(data[0] as i64));
+            }
+            (0x100D, 4) => {
+                out.insert(
+                    "temperature".to_string(),
+                    number(i16_le(data, 0) as f64 / 10.0),
+                );
+                out.insert(
+                    "humidity".to_string(),
+                    number(u16_le(data, 2) as f64 / 10.0),
+                );
+            }
+            _ => log::debug!("Skipping MiBeacon object {:#06x}", object_type),
+        }
+    }
+    Ok(())
+}
+
+/// Decodes the service data of a Xiaomi BLE thermometer or plant sensor advertisement
+/// into `{temperature, humidity, battery, ...}`.
+///
+/// `format` is `atc` or `pvvx` (the custom firmware formats of the LYWSD03MMC), or
+/// `mibeacon` (the stock format, unencrypted only). `auto` picks by payload length.
+#[modular_agent(
+    title = "Xiaomi BLE Decode",
+    category = CATEGORY,
+    inputs = [PORT_BYTES],
+    outputs = [PORT_VALUE],
+    string_config(name = CONFIG_FORMAT, default = FORMAT_AUTO, description = "auto, atc, pvvx, mibeacon"),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
+)]
+struct XiaomiBleDecodeAgent {
+    data: AgentData,
+    contract: InputContract,
+}
+
+#[async_trait]
+impl AsAgent for XiaomiBleDecodeAgent {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
+        Ok(Self {
+            data: AgentData::new(ma, id, spec),
+            contract,
+        })
+    }
+
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
+    }
+
+    async fn process(
+        &mut self,
+        ctx: AgentContext,
+        port: String,
+        value: AgentValue,
+    ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
+        let format = self.configs()?.get_string_or(CONFIG_FORMAT, FORMAT_AUTO);
+        let format = match format.trim() {
+            "" => FORMAT_AUTO,
+            format => format,
+        };
+        let decoded = decode_xiaomi(&payload_bytes(&value)?, format)?;
+        self.output(self.traced(ctx), PORT_VALUE, decoded).await
+    }
+}
+
+// NMEA
+
+// Converts `ddmm.mmmm` (or `dddmm.mmmm`) and a hemisphere to signed decimal degrees.
+fn nmea_degrees(value: &str, hemisphere: &str) -> Option<f64> {
+    let value: f64 = value.parse().ok()?;
+    let degrees = (value / 100.0).trunc();
+    let decimal = degrees + (value - degrees * 100.0) / 60.0;
+    match hemisphere {
+        "N" | "E" => Some(decimal),
+        "S" | "W" => Some(-decimal),
+        _ => None,
+    }
+}
+
+/// Parses an NMEA 0183 sentence into `{talker, type, fields}`, with the fields of GGA
+/// and RMC sentences also decoded by name.
+fn parse_nmea(sentence: &str, verify_checksum: bool) -> Result<AgentValue, AgentError> {
+    let invalid = |msg: &str| AgentError::InvalidValue(format!("{}: {}", msg, sentence));
+    let s = sentence.trim();
+    let Some(body) = s.strip_prefix('$').or_else(|| s.strip_prefix('!')) else {
+        return Err(invalid("NMEA sentence must start with $"));
+    };
+    let body = match body.split_once('*') {
+        Some((body, checksum)) => {
+            let expected = u8::from_str_radix(checksum.trim(), 16)
+                .map_err(|_| invalid("Invalid NMEA checksum"))?;
+            let actual = body.bytes().fold(0u8, |acc, b| acc ^ b);
+            if verify_checksum && actual != expected {
+                return Err(invalid("NMEA checksum mismatch"));
+            }
+            body
+        }
+        None if verify_checksum => return Err(invalid("NMEA sentence has no checksum")),
+        None => body,
+    };
+
+    let mut fields = body.split(',');
+    let address = fields.next().unwrap_or_default();
+    if address.len() < 3 || !address.is_ascii() {
+        return Err(invalid("Invalid NMEA address"));
+    }
+    // proprietary sentences ($P...) have no talker
+    let (talker, kind) = match address.strip_prefix('P') {
+        Some(kind) => ("P", kind),
+        None => address.split_at(2),
+    };
+    let fields: Vec<&str> = fields.collect();
+
+    let mut out = HashMap::new();
+    out.insert("talker".to_string(), AgentValue::string(talker));
+    out.insert("type".to_string(), AgentValue::string(kind));
+    out.insert(
+        "fields".to_string(),
+        AgentValue::array(fields.iter().map(|f| AgentValue::string(*f)).collect()),
+    );
+
+    let field = |i: usize| fields.get(i).copied().unwrap_or_default();
+    let mut set_number = |key: &str, s: &str| {
+        if let Ok(n) = s.parse::<f64>() {
+            out.insert(key.to_string(), number(n));
+        }
+    };
+    match kind {
+        "GGA" => {
+            set_number("quality", field(5));
+            set_number("satellites", field(6));
+            set_number("hdop", field(7));
+            set_number("altitude", field(8));
+        }
+        "RMC" => {
+            set_number("speed_knots", field(6));
+            set_number("course", field(7));
+        }
+        _ => {}
+    }
+    match kind {
+        "GGA" => {
+            out.insert("time".to_string(), AgentValue::string(field(0)));
+            if let (Some(lat), Some(lon)) = (
+                nmea_degrees(field(1), field(2)),
+                nmea_degrees(field(3), field(4)),
+            ) {
+                out.insert("latitude".to_string(), number(lat));
+                out.insert("longitude".to_string(), number(lon));
+            }
+        }
+        "RMC" => {
+            out.insert("time".to_string(), AgentValue::string(field(0)));
+            out.insert("valid".to_string(), AgentValue::boolean(field(1) == "A"));
+            if let (Some(lat), Some(lon)) = (
+                nmea_degrees(field(2), field(3)),
+                nmea_degrees(field(4), field(5)),
+            ) {
+                out.insert("latitude".to_string(), number(lat));
+                out.insert("longitude".to_string(), number(lon));
+            }
+            out.insert("date".to_string(), AgentValue::string(field(8)));
+        }
+        _ => {}
+    }
+    Ok(AgentValue::object(out))
+}
+
+/// Parses an NMEA 0183 sentence (ex. from a GPS receiver) into
+/// `{talker, type, fields}`.
+///
+/// GGA sentences also get `time`, `latitude`, `longitude` (decimal degrees), `quality`,
+/// `satellites`, `hdop` and `altitude`; RMC sentences `time`, `valid`, `latitude`,
+/// `longitude`, `speed_knots`, `course` and `date`. With `checksum`, sentences without a
+/// valid checksum are errors.
+#[modular_agent(
+    title = "NMEA Decode",
+    category = CATEGORY,
+    inputs = [PORT_SENTENCE],
+    outputs = [PORT_VALUE],
+    boolean_config(name = CONFIG_CHECKSUM, default = true),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
+)]
+struct NmeaDecodeAgent {
+    data: AgentData,
+    contract: InputContract,
+}
+
+#[async_trait]
+impl AsAgent for NmeaDecodeAgent {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
+        Ok(Self {
+            data: AgentData::new(ma, id, spec),
+            contract,
+        })
+    }
+
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
+    }
+
+    async fn process(
+        &mut self,
+        ctx: AgentContext,
+        port: String,
+        value: AgentValue,
+    ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
+        let verify_checksum = self.configs()?.get_bool_or(CONFIG_CHECKSUM, true);
+        let Some(sentence) = value.as_str() else {
+            return Err(AgentError::InvalidValue(
+                "NMEA sentence must be a string".into(),
+            ));
+        };
+        let decoded = parse_nmea(sentence, verify_checksum)?;
+        self.output(self.traced(ctx), PORT_VALUE, decoded).await
+    }
+}
+
+// SNMP
+
+const BER_INTEGER: u8 = 0x02;
+const BER_OCTET_STRING: u8 = 0x04;
+const BER_NULL: u8 = 0x05;
+const BER_OID: u8 = 0x06;
+const BER_SEQUENCE: u8 = 0x30;
+const SNMP_IP_ADDRESS: u8 = 0x40;
+const SNMP_COUNTER32: u8 = 0x41;
+const SNMP_GAUGE32: u8 = 0x42;
+const SNMP_TIMETICKS: u8 = 0x43;
+const SNMP_COUNTER64: u8 = 0x46;
+const SNMP_NO_SUCH_OBJECT: u8 = 0x80;
+const SNMP_NO_SUCH_INSTANCE: u8 = 0x81;
+const SNMP_END_OF_MIB_VIEW: u8 = 0x82;
+const SNMP_GET_REQUEST: u8 = 0xa0;
+const SNMP_RESPONSE: u8 = 0xa2;
+const SNMP_REPORT: u8 = 0xa8;
+
+fn ber_tlv(tag: u8, content: &[u8]) -> Vec<u8> {
+    let mut out = vec![tag];
+    let len = content.len();
+    if len < 0x80 {
+        out.push(len as u8);
+    } else {
+        let bytes: Vec<u8> = len
+            .to_be_bytes()
+            .into_iter()
+            .skip_while(|b| *b == 0)
+            .collect();
+        out.push(0x80 | bytes.len() as u8);
+        out.extend(bytes);
+    }
+    out.extend_from_slice(content);
+    out
+}
+
+fn ber_integer(n: i64) -> Vec<u8> {
+    let bytes = n.to_be_bytes();
+    // drop leading bytes that only repeat the sign
+    let mut start = 0;
+    while start < 7
+        && ((bytes[start] == 0x00 && bytes[start + 1] & 0x80 == 0)
+            || (bytes[start] == 0xff && bytes[start + 1] & 0x80 != 0))
+    {
+        start += 1;
+    }
+    ber_tlv(BER_INTEGER, &bytes[start..])
+}
+
+fn parse_oid(oid: &str) -> Result<Vec<u64>, AgentError> {
+    let arcs: Vec<u64> = oid
+        .trim()
+        .trim_start_matches('.')
+        .split('.')
+        .map(|arc| arc.parse::<u64>())
+        .collect::<Result<_, _>>()
+        .map_err(|_| AgentError::InvalidConfig(format!("Invalid OID: {}", oid)))?;
+    if arcs.len() < 2 || arcs[0] > 2 || (arcs[0] < 2 && arcs[1] >= 40) {
+        return Err(AgentError::InvalidConfig(format!("Invalid OID: {}", oid)));
+    }
+    Ok(arcs)
+}
+
+fn ber_oid(arcs: &[u64]) -> Vec<u8> {
+    let mut content = Vec::new();
+    let first = arcs[0] * 40 + arcs[1];
+    for arc in std::iter::once(first).chain(arcs[2..].iter().copied()) {
+        let mut base128 = vec![(arc & 0x7f) as u8];
+        let mut rest = arc >> 7;
+        while rest > 0 {
+            base128.push(0x80 | (rest & 0x7f) as u8);
+            rest >>= 7;
+        }
+        content.extend(base128.into_iter().rev());
+    }
+    ber_tlv(BER_OID, &content)
+}
+
+/// Encodes an SNMP GetRequest. `version` is 0 for v1 and 1 for v2c.
+fn encode_get_request(
+    version: i64,
+    community: &str,
+    request_id: i64,
+    oids: &[Vec<u64>],
+) -> Vec<u8> {
+    let mut message = ber_integer(version);
+    message.extend(ber_tlv(BER_OCTET_STRING, community.as_bytes()));
+    message.extend(encode_get_pdu(request_id, oids));
+    ber_tlv(BER_SEQUENCE, &message)
+}
+
+fn encode_get_pdu(request_id: i64, oids: &[Vec<u64>]) -> Vec<u8> {
+    let mut varbinds = Vec::new();
+    for oid in oids {
+        let mut varbind = ber_oid(oid);
+        varbind.extend(ber_tlv(BER_NULL, &[]));
+        varbinds.extend(ber_tlv(BER_SEQUENCE, &varbind));
+    }
+    let mut pdu = ber_integer(request_id);
+    pdu.extend(ber_integer(0));
+    pdu.extend(ber_integer(0));
+    pdu.extend(ber_tlv(BER_SEQUENCE, &varbinds));
+    ber_tlv(SNMP_GET_REQUEST, &pdu)
+}
+
+/// Reads BER TLVs from a buffer.
+struct BerReader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> BerReader<'a> {
+    fn truncated() -> AgentError {
+        AgentError::InvalidValue("Truncated SNMP message".into())
+    }
+
+    fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    fn read(&mut self) -> Result<(u8, &'a [u8]), AgentError> {
+        let [tag, first, rest @ ..] = self.buf else {
+            return Err(Self::truncated());
+        };
+        let (len, rest) = if first & 0x80 == 0 {
+            (*first as usize, rest)
+        } else {
+            let n = (first & 0x7f) as usize;
+            if n == 0 || n > 4 || rest.len() < n {
+                return Err(Self::truncated());
+            }
+            let len = rest[..n]
+                .iter()
+                .fold(0usize, |acc, b| acc << 8 | *b as usize);
+            (len, &rest[n..])
+        };
+        if rest.len() < len {
+            return Err(Self::truncated());
+        }
+        self.buf = &rest[len..];
+        Ok((*tag, &rest[..len]))
+    }
+
+    fn expect(&mut self, tag: u8) -> Result<&'a [u8], AgentError> {
+        let (actual, content) = self.read()?;
+        if actual != tag {
+            return Err(AgentError::InvalidValue(format!(
+                "Unexpected SNMP tag {:#04x}, expected {:#04x}",
+                actual, tag
+            )));
+        }
+        Ok(content)
+    }
+
+    fn integer(&mut self) -> Result<i64, AgentError> {
+        Ok(decode_integer(self.expect(BER_INTEGER)?))
+    }
+}
+
+fn decode_integer(content: &[u8]) -> i64 {
+    let init = if content.first().is_some_and(|b| b & 0x80 != 0) {
+        -1
+    } else {
+        0
+    };
+    content.iter().fold(init, |acc, b| acc << 8 | *b as i64)
+}
+
+fn decode_unsigned(content: &[u8]) -> AgentValue {
+    let n = content.iter().fold(0u64, |acc, b| acc << 8 | *b as u64);
+    match i64::try_from(n) {
+        Ok(n) => AgentValue::integer(n),
+        Err(_) => AgentValue::number(n as f64),
+    }
+}
+
+fn decode_oid(content: &[u8]) -> String {
+    let mut arcs = Vec::new();
+    let mut arc = 0u64;
+    for b in content {
+        arc = arc << 7 | (b & 0x7f) as u64;
+        if b & 0x80 == 0 {
+            if arcs.is_empty() {
+                let first = (arc / 40).min(2);
+                arcs.push(first);
+                arcs.push(arc - first * 40);
+            } else {
+                arcs.push(arc);
+            }
+            arc = 0;
+        }
+    }
+    arcs.iter()
+        .map(|a| a.to_string())
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+fn decode_snmp_value(tag: u8, content: &[u8]) -> AgentValue {
+    match tag {
+        BER_INTEGER => AgentValue::integer(decode_integer(content)),
+        BER_OCTET_STRING => match std::str::from_utf8(content) {
+            Ok(s) if !s.chars().any(|c| c.is_control() && !c.is_whitespace()) => {
+                AgentValue::string(s)
+            }
+            // binary strings, like MAC addresses
+            _ => AgentValue::string(
+                content
+                    .iter()
+                    .map(|b| format!("{:02x}", b))
+                    .collect::<Vec<_>>()
+                    .join(":"),
+            ),
+        },
+        BER_OID => AgentValue::string(decode_oid(content)),
+        SNMP_IP_ADDRESS if content.len() == 4 => AgentValue::string(format!(
+            "{}.{}.{}.{}",
+            content[0], content[1], content[2], content[3]
+        )),
+        SNMP_COUNTER32 | SNMP_GAUGE32 | SNMP_TIMETICKS | SNMP_COUNTER64 => decode_unsigned(content),
+        BER_NULL | SNMP_NO_SUCH_OBJECT | SNMP_NO_SUCH_INSTANCE | SNMP_END_OF_MIB_VIEW => {
+            AgentValue::unit()
+        }
+        tag => {
+            log::debug!("Unknown SNMP value type {:#04x}", tag);
+            AgentValue::unit()
+        }
+    }
+}
+
+struct SnmpResponse {
+    // SNMP_RESPONSE, or SNMP_REPORT from a v3 engine
+    pdu: u8,
+    request_id: i64,
+    error_status: i64,
+    error_index: i64,
+    varbinds: Vec<(String, AgentValue)>,
+}
+
+fn decode_response(buf: &[u8]) -> Result<SnmpResponse, AgentError> {
+    let mut message = BerReader {
+        buf: BerReader { buf }.expect(BER_SEQUENCE)?,
+    };
+    message.integer()?;
+    message.expect(BER_OCTET_STRING)?;
+    decode_pdu(SNMP_RESPONSE, message.expect(SNMP_RESPONSE)?)
+}
+
+fn decode_pdu(tag: u8, content: &[u8]) -> Result<SnmpResponse, AgentError> {
+    let mut pdu = BerReader { buf: content };
+    let request_id = pdu.integer()?;
+    let error_status = pdu.integer()?;
+    let error_index = pdu.integer()?;
+    let mut list = BerReader {
+        buf: pdu.expect(BER_SEQUENCE)?,
+    };
+    let mut varbinds = Vec::new();
+    while !list.is_empty() {
+        let mut varbind = BerReader {
+            buf: list.expect(BER_SEQUENCE)?,
+        };
+        let oid = decode_oid(varbind.expect(BER_OID)?);
+        let (tag, content) = varbind.read()?;
+        varbinds.push((oid, decode_snmp_value(tag, content)));
+    }
+    Ok(SnmpResponse {
+        pdu: tag,
+        request_id,
+        error_status,
+        error_index,
+        varbinds,
+    })
+}
+
+// SNMP v3 (RFC 3412), with the user-based security model (RFC 3414, RFC 3826, RFC 7860)
+
+const SNMP_V3: i64 = 3;
+const SNMP_MAX_SIZE: i64 = 65507;
+const SNMP_USM: i64 = 3;
+const SNMP_FLAG_AUTH: u8 = 0x01;
+const SNMP_FLAG_PRIV: u8 = 0x02;
+const SNMP_FLAG_REPORTABLE: u8 = 0x04;
+
+// usmStats counters reported for failed requests
+const USM_STATS: &str = "1.3.6.1.6.3.15.1.1";
+const USM_NOT_IN_TIME_WINDOWS: &str = "1.3.6.1.6.3.15.1.1.2.0";
+const USM_UNKNOWN_ENGINE_IDS: &str = "1.3.6.1.6.3.15.1.1.4.0";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum SnmpAuth {
+    None,
+    Md5,
+    Sha1,
+    Sha256,
+}
+
+impl SnmpAuth {
+    fn parse(protocol: &str) -> Result<Self, AgentError> {
+        match protocol.trim().to_ascii_lowercase().as_str() {
+            "" | "none" => Ok(Self::None),
+            "md5" => Ok(Self::Md5),
+            "sha" | "sha1" => Ok(Self::Sha1),
+            "sha256" => Ok(Self::Sha256),
+            other => Err(AgentError::InvalidConfig(format!(
+                "Unsupported SNMP auth protocol: {}",
+                other
+            ))),
+        }
+    }
+
+    // Length of msgAuthenticationParameters, the truncated HMAC.
+    fn mac_len(self) -> usize {
+        match self {
+            Self::None => 0,
+            Self::Md5 | Self::Sha1 => 12,
+            Self::Sha256 => 24,
+        }
+    }
+
+    /// Derives the key of `password` localized to an engine (RFC 3414 A.2).
+    fn localize_key(self, password: &str, engine_id: &[u8]) -> Vec<u8> {
+        fn localize<D: Digest>(password: &[u8], engine_id: &[u8]) -> Vec<u8> {
+            let mut hasher = D::new();
+            let mut block = [0u8; 64];
+            let mut index = 0;
+            for _ in 0..(1_048_576 / 64) {
+                for b in block.iter_mut() {
+                    *b = password[index % password.len()];
+                    index += 1;
+                }
+                hasher.update(block);
+            }
+            let key = hasher.finalize();
+            let mut hasher = D::new();
+            hasher.update(&key);
+            hasher.update(engine_id);
+            hasher.update(&key);
+            hasher.finalize().to_vec()
+        }
+        match self {
+            Self::None => Vec::new(),
+            Self::Md5 => localize::<Md5>(password.as_bytes(), engine_id),
+            Self::Sha1 => localize::<Sha1>(password.as_bytes(), engine_id),
+            Self::Sha256 => localize::<Sha256>(password.as_bytes(), engine_id),
+        }
+    }
+
+    fn mac(self, key: &[u8], message: &[u8]) -> Vec<u8> {
+        fn hmac<M: Mac + KeyInit>(key: &[u8], message: &[u8]) -> Vec<u8> {
+            let mut mac =
+                <M as KeyInit>::new_from_slice(key).expect("HMAC takes keys of any length");
+            mac.update(message);
+            mac.finalize().into_bytes().to_vec()
+        }
+        let mut mac = match self {
+            Self::None => return Vec::new(),
+            Self::Md5 => hmac::<Hmac<Md5>>(key, message),
+            Self::Sha1 => hmac::<Hmac<Sha1>>(key, message),
+            Self::Sha256 => hmac::<Hmac<Sha256>>(key, message),
+        };
+        mac.truncate(self.mac_len());
+        mac
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum SnmpPriv {
+    None,
+    Des,
+    Aes,
+}
+
+impl SnmpPriv {
+    fn parse(protocol: &str) -> Result<Self, AgentError> {
+        match protocol.trim().to_ascii_lowercase().as_str() {
+            "" | "none" => Ok(Self::None),
+            "des" => Ok(Self::Des),
+            "aes" | "aes128" => Ok(Self::Aes),
+            other => Err(AgentError::InvalidConfig(format!(
+                "Unsupported SNMP privacy protocol: {}",
+                other
+            ))),
+        }
+    }
+
+    /// Encrypts a scoped PDU, returning it and msgPrivacyParameters.
+    fn encrypt(
+        self,
+        key: &[u8],
+        boots: i64,
+        time: i64,
+        salt: u64,
+        data: &[u8],
+    ) -> (Vec<u8>, Vec<u8>) {
+        match self {
+            Self::None => (data.to_vec(), Vec::new()),
+            Self::Des => {
+                // the salt is the engine boots and a local counter (RFC 3414 8.1.1.1)
+                let salt = ((boots as u64) << 32 | (salt & 0xffff_ffff)).to_be_bytes();
+                let iv = des_iv(key, &salt);
+                let mut buf = data.to_vec();
+                buf.resize(data.len().div_ceil(8) * 8, 0);
+                let len = buf.len();
+                cbc::Encryptor::<des::Des>::new(key[..8].into(), (&iv).into())
+                    .encrypt_padded_mut::<NoPadding>(&mut buf, len)
+                    .expect("padded to the block size");
+                (buf, salt.to_vec())
+            }
+            Self::Aes => {
+                let salt = salt.to_be_bytes();
+                let mut buf = data.to_vec();
+                cfb_mode::Encryptor::<aes::Aes128>::new(
+                    key[..16].into(),
+                    (&aes_iv(boots, time, &salt)).into(),
+                )
+                .encrypt(&mut buf);
+                (buf, salt.to_vec())
+            }
+        }
+    }
+
+    fn decrypt(
+        self,
+        key: &[u8],
+        boots: i64,
+        time: i64,
+        salt: &[u8],
+        data: &[u8],
+    ) -> Result<Vec<u8>, AgentError> {
+        let invalid = || AgentError::InvalidValue("Invalid SNMP privacy parameters".into());
+        let salt: [u8; 8] = salt.try_into().map_err(|_| invalid())?;
+        let mut buf = data.to_vec();
+        match self {
+            Self::None => {}
+            Self::Des => {
+                let iv = des_iv(key, &salt);
+                cbc::Decryptor::<des::Des>::new(key[..8].into(), (&iv).into())
+                    .decrypt_padded_mut::<NoPadding>(&mut buf)
+                    .map_err(|_| invalid())?;
+            }
+            Self::Aes => {
+                cfb_mode::Decryptor::<aes::Aes128>::new(
+                    key[..16].into(),
+                    (&aes_iv(boots, time, &salt)).into(),
+                )
+                .decrypt(&mut buf);
+            }
+        }
+        Ok(buf)
+    }
+}
+
+// The DES IV is the pre-IV (the second half of the key) XOR the salt.
+fn des_iv(key: &[u8], salt: &[u8; 8]) -> [u8; 8] {
+    std::array::from_fn(|i| key[8 + i] ^ salt[i])
+}
+
+// The AES IV is the engine boots and time, followed by the salt (RFC 3826 3.1.2.1).
+fn aes_iv(boots: i64, time: i64, salt: &[u8; 8]) -> [u8; 16] {
+    let mut iv = [0u8; 16];
+    iv[..4].copy_from_slice(&(boots as u32).to_be_bytes());
+    iv[4..8].copy_from_slice(&(time as u32).to_be_bytes());
+    iv[8..].copy_from_slice(salt);
+    iv
+}
+
+/// msgSecurityParameters of the user-based security model.
+#[derive(Clone, Debug, Default, PartialEq)]
+struct UsmParams {
+    engine_id: Vec<u8>,
+    boots: i64,
+    time: i64,
+    user: Vec<u8>,
+    auth: Vec<u8>,
+    privacy: Vec<u8>,
+}
+
+/// Encodes an SNMP v3 message. `data` is the scoped PDU, or its encrypted octet string.
+fn encode_v3_message(msg_id: i64, flags: u8, usm: &UsmParams, data: &[u8]) -> Vec<u8> {
+    let mut header = ber_integer(msg_id);
+    header.extend(ber_integer(SNMP_MAX_SIZE));
+    header.extend(ber_tlv(BER_OCTET_STRING, &[flags]));
+    header.extend(ber_integer(SNMP_USM));
+
+    let mut params = ber_tlv(BER_OCTET_STRING, &usm.engine_id);
+    params.extend(ber_integer(usm.boots));
+    params.extend(ber_integer(usm.time));
+    params.extend(ber_tlv(BER_OCTET_STRING, &usm.user));
+    params.extend(ber_tlv(BER_OCTET_STRING, &usm.auth));
+    params.extend(ber_tlv(BER_OCTET_STRING, &usm.privacy));
+
+    let mut message = ber_integer(SNMP_V3);
+    message.extend(ber_tlv(BER_SEQUENCE, &header));
+    message.extend(ber_tlv(BER_OCTET_STRING, &ber_tlv(BER_SEQUENCE, &params)));
+    message.extend_from_slice(data);
+    ber_tlv(BER_SEQUENCE, &message)
+}
+
+fn encode_scoped_pdu(engine_id: &[u8], context: &str, pdu: &[u8]) -> Vec<u8> {
+    let mut scoped = ber_tlv(BER_OCTET_STRING, engine_id);
+    scoped.extend(ber_tlv(BER_OCTET_STRING, context.as_bytes()));
+    scoped.extend_from_slice(pdu);
+    ber_tlv(BER_SEQUENCE, &scoped)
+}
+
+struct SnmpV3Message<'a> {
+    msg_id: i64,
+    flags: u8,
+    usm: UsmParams,
+    // where msgAuthenticationParameters lies in the message, to zero it for the HMAC
+    auth_range: std::ops::Range<usize>,
+    // the scoped PDU, or its encrypted octet string
+    data_tag: u8,
+    data: &'a [u8],
+}
+
+fn decode_v3_message(buf: &[u8]) -> Result<SnmpV3Message<'_>, AgentError> {
+    let mut message = BerReader {
+        buf: BerReader { buf }.expect(BER_SEQUENCE)?,
+    };
+    let version = message.integer()?;
+    if version != SNMP_V3 {
+        return Err(AgentError::InvalidValue(format!(
+            "Unexpected SNMP version {}",
+            version
+        )));
+    }
+    let mut header = BerReader {
+        buf: message.expect(BER_SEQUENCE)?,
+    };
+    let msg_id = header.integer()?;
+    header.integer()?;
+    let flags = *header
+        .expect(BER_OCTET_STRING)?
+        .first()
+        .ok_or_else(BerReader::truncated)?;
+    let mut params = BerReader {
+        buf: BerReader {
+            buf: message.expect(BER_OCTET_STRING)?,
+        }
+        .expect(BER_SEQUENCE)?,
+    };
+    let engine_id = params.expect(BER_OCTET_STRING)?.to_vec();
+    let boots = params.integer()?;
+    let time = params.integer()?;
+    let user = params.expect(BER_OCTET_STRING)?.to_vec();
+    let auth = params.expect(BER_OCTET_STRING)?;
+    let auth_start = auth.as_ptr() as usize - buf.as_ptr() as usize;
+    let privacy = params.expect(BER_OCTET_STRING)?.to_vec();
+    let (data_tag, data) = message.read()?;
+    Ok(SnmpV3Message {
+        msg_id,
+        flags,
+        usm: UsmParams {
+            engine_id,
+            boots,
+            time,
+            user,
+            auth: auth.to_vec(),
+            privacy,
+        },
+        auth_range: auth_start..auth_start + auth.len(),
+        data_tag,
+        data,
+    })
+}
+
+fn decode_scoped_pdu(buf: &[u8]) -> Result<SnmpResponse, AgentError> {
+    let mut scoped = BerReader {
+        buf: BerReader { buf }.expect(BER_SEQUENCE)?,
+    };
+    scoped.expect(BER_OCTET_STRING)?;
+    scoped.expect(BER_OCTET_STRING)?;
+    let (tag, content) = scoped.read()?;
+    if tag != SNMP_RESPONSE && tag != SNMP_REPORT {
+        return Err(AgentError::InvalidValue(format!(
+            "Unexpected SNMP PDU {:#04x}",
+            tag
+        )));
+    }
+    decode_pdu(tag, content)
+}
+
+/// The SNMP v3 user and its security level.
+#[derive(Clone)]
+struct SnmpUsm {
+    user: String,
+    auth: SnmpAuth,
+    auth_password: String,
+    privacy: SnmpPriv,
+    priv_password: String,
+    context: String,
+}
+
+/// The authoritative engine of the device, as discovered, with the user's keys
+/// localized to it.
+#[derive(Clone)]
+struct SnmpEngine {
+    id: Vec<u8>,
+    boots: i64,
+    time: i64,
+    // when `time` was learned, to estimate the time of the engine later
+    learned_at: Instant,
+    auth_key: Vec<u8>,
+    priv_key: Vec<u8>,
+}
+
+impl SnmpEngine {
+    fn time(&self) -> i64 {
+        self.time + self.learned_at.elapsed().as_secs() as i64
+    }
+}
+
+impl SnmpUsm {
+    fn flags(&self) -> u8 {
+        let mut flags = SNMP_FLAG_REPORTABLE;
+        if self.auth != SnmpAuth::None {
+            flags |= SNMP_FLAG_AUTH;
+        }
+        if self.privacy != SnmpPriv::None {
+            flags |= SNMP_FLAG_PRIV;
+        }
+        flags
+    }
+
+    fn engine(&self, usm: &UsmParams) -> SnmpEngine {
+        SnmpEngine {
+            id: usm.engine_id.clone(),
+            boots: usm.boots,
+            time: usm.time,
+            learned_at: Instant::now(),
+            auth_key: self.auth.localize_key(&self.auth_password, &usm.engine_id),
+            priv_key: self.auth.localize_key(&self.priv_password, &usm.engine_id),
+        }
+    }
+
+    /// Encodes a request of `pdu` to the engine, encrypted and authenticated by the
+    /// security level of the user.
+    fn encode_request(
+        &self,
+        engine: &SnmpEngine,
+        msg_id: i64,
+        salt: u64,
+        pdu: &[u8],
+    ) -> Result<Vec<u8>, AgentError> {
+        let flags = self.flags();
+        let time = engine.time();
+        let scoped = encode_scoped_pdu(&engine.id, &self.context, pdu);
+        let (data, privacy) = if flags & SNMP_FLAG_PRIV != 0 {
+            let (encrypted, privacy) =
+                self.privacy
+                    .encrypt(&engine.priv_key, engine.boots, time, salt, &scoped);
+            (ber_tlv(BER_OCTET_STRING, &encrypted), privacy)
+        } else {
+            (scoped, Vec::new())
+        };
+        let usm = UsmParams {
+            engine_id: engine.id.clone(),
+            boots: engine.boots,
+            time,
+            user: self.user.as_bytes().to_vec(),
+            auth: vec![0; self.auth.mac_len()],
+            privacy,
+        };
+        let mut message = encode_v3_message(msg_id, flags, &usm, &data);
+        if flags & SNMP_FLAG_AUTH != 0 {
+            // the HMAC is of the message with zeros in its place
+            let mac = self.auth.mac(&engine.auth_key, &message);
+            let range = decode_v3_message(&message)?.auth_range;
+            message[range].copy_from_slice(&mac);
+        }
+        Ok(message)
+    }
+
+    /// Decodes a response, checking its HMAC and decrypting it. Unauthenticated
+    /// responses are only taken as reports, which tell why a request failed.
+    fn decode_response(
+        &self,
+        engine: &SnmpEngine,
+        buf: &[u8],
+    ) -> Result<(i64, UsmParams, SnmpResponse), AgentError> {
+        let message = decode_v3_message(buf)?;
+        if message.flags & SNMP_FLAG_AUTH != 0 {
+            if self.auth == SnmpAuth::None || message.usm.engine_id != engine.id {
+                return Err(AgentError::InvalidValue(
+                    "Unexpected authenticated SNMP message".into(),
+                ));
+            }
+            let mut zeroed = buf.to_vec();
+            zeroed[message.auth_range.clone()].fill(0);
+            if self.auth.mac(&engine.auth_key, &zeroed) != message.usm.auth {
+                return Err(AgentError::InvalidValue(
+                    "SNMP message authentication failed".into(),
+                ));
+            }
+        }
+        let scoped = if message.flags & SNMP_FLAG_PRIV != 0 {
+            if message.flags & SNMP_FLAG_AUTH == 0 || message.data_tag != BER_OCTET_STRING {
+                return Err(AgentError::InvalidValue(
+                    "Invalid encrypted SNMP message".into(),
+                ));
+            }
+            self.privacy.decrypt(
+                &engine.priv_key,
+                message.usm.boots,
+                message.usm.time,
+                &message.usm.privacy,
+                message.data,
+            )?
+        } else {
+            ber_tlv(message.data_tag, message.data)
+        };
+        let response = decode_scoped_pdu(&scoped)?;
+        if message.flags & SNMP_FLAG_AUTH == 0
+            && self.auth != SnmpAuth::None
+            && response.pdu != SNMP_REPORT
+        {
+            return Err(AgentError::InvalidValue(
+                "Unauthenticated SNMP response".into(),
+            ));
+        }
+        Ok((message.msg_id, message.usm, response))
+    }
+}
+
+// Describes a report of a failed SNMP v3 request by its usmStats counter.
+fn snmp_report_error(response: &SnmpResponse) -> String {
+    let oid = response
+        .varbinds
+        .first()
+        .map(|(oid, _)| oid.as_str())
+        .unwrap_or_default();
+    let reason = match oid.strip_prefix(USM_STATS) {
+        Some(".1.0") => "unsupported security level",
+        Some(".2.0") => "not in time window",
+        Some(".3.0") => "unknown user name",
+        Some(".4.0") => "unknown engine ID",
+        Some(".5.0") => "wrong digest (check the auth password)",
+        Some(".6.0") => "decryption error (check the privacy password)",
+        _ => "unexpected report",
+    };
+    format!("{} ({})", reason, oid)
+}
+
+/// Adds the default `port` to a bare `host`, bracketing a bare IPv6 address.
+fn host_port(host: &str, port: u16) -> String {
+    if host.parse::<Ipv6Addr>().is_ok() {
+        format!("[{}]:{}", host, port)
+    } else if !host.contains(':') || host.ends_with(']') {
+        format!("{}:{}", host, port)
+    } else {
+        host.to_string()
+    }
+}
+
+#[derive(Clone)]
+struct SnmpTarget {
+    host: String,
+    version: i64,
+    community: String,
+    // the user of v3
+    usm: Option<SnmpUsm>,
+    oids: Vec<Vec<u64>>,
+    timeout: Duration,
+    // the v3 engine of the device, once discovered
+    engine: Option<SnmpEngine>,
+    request_id: i64,
+    // the salt of the next encrypted v3 request
+    salt: u64,
+}
+
+impl SnmpTarget {
+    fn next_request_id(&mut self) -> i64 {
+        self.request_id = self.request_id % i32::MAX as i64 + 1;
+        self.request_id
+    }
+
+    /// Gets the OIDs, and returns `{oid: value}`.
+    async fn get(&mut self) -> Result<AgentValue, AgentError> {
+        let socket = self.connect().await?;
+        let response = match self.usm.clone() {
+            Some(usm) => self.get_v3(&socket, &usm).await?,
+            None => {
+                let request_id = self.next_request_id();
+                let request =
+                    encode_get_request(self.version, &self.community, request_id, &self.oids);
+                self.exchange(&socket, &request, |buf| {
+                    decode_response(buf).map(|r| (r.request_id == request_id).then_some(r))
+                })
+                .await?
+            }
+        };
+        if response.error_status != 0 {
+            return Err(AgentError::InvalidValue(format!(
+                "SNMP error status {} at varbind {} from {}",
+                response.error_status, response.error_index, self.host
+            )));
+        }
+        Ok(AgentValue::object(response.varbinds.into_iter().collect()))
+    }
+
+    fn io_error(&self, e: std::io::Error) -> AgentError {
+        AgentError::IoError(format!("SNMP request to {} failed: {}", self.host, e))
+    }
+
+    async fn connect(&self) -> Result<UdpSocket, AgentError> {
+        let addr = lookup_host(&self.host)
+            .await
+            .map_err(|e| self.io_error(e))?
+            .next()
+            .ok_or_else(|| {
+                AgentError::IoError(format!("SNMP host {} has no address", self.host))
+            })?;
+        // bind by the address family of the host, so IPv6 hosts are reachable
+        let local = if addr.is_ipv6() {
+            "[::]:0"
+        } else {
+            "0.0.0.0:0"
+        };
+        let socket = UdpSocket::bind(local).await.map_err(|e| self.io_error(e))?;
+        socket.connect(addr).await.map_err(|e| self.io_error(e))?;
+        Ok(socket)
+    }
+
+    /// Sends `request`, and returns the first datagram `decode` takes as its response.
+    async fn exchange<T>(
+        &self,
+        socket: &UdpSocket,
+        request: &[u8],
+        mut decode: impl FnMut(&[u8]) -> Result<Option<T>, AgentError>,
+    ) -> Result<T, AgentError> {
+        socket.send(request).await.map_err(|e| self.io_error(e))?;
+        let mut buf = vec![0u8; 65535];
+        tokio::time::timeout(self.timeout, async {
+            loop {
+                let n = socket.recv(&mut buf).await.map_err(|e| self.io_error(e))?;
+                match decode(&buf[..n]) {
+                    Ok(Some(response)) => return Ok(response),
+                    // stale or foreign datagrams
+                    Ok(None) => continue,
+                    Err(e) => log::debug!("Skipping SNMP datagram from {}: {}", self.host, e),
+                }
+            }
+        })
+        .await
+        .map_err(|_| {
+            AgentError::IoError(format!(
+                "SNMP request to {} timed out after {:?}",
+                self.host, self.timeout
+            ))
+        })?
+    }
+
+    /// Learns the engine ID, boots and time of the device from the report to an
+    /// empty request.
+    async fn discover(
+        &mut self,
+        socket: &UdpSocket,
+        usm: &SnmpUsm,
+    ) -> Result<SnmpEngine, AgentError> {
+        let msg_id = self.next_request_id();
+        let pdu = encode_get_pdu(msg_id, &[]);
+        let request = encode_v3_message(
+            msg_id,
+            SNMP_FLAG_REPORTABLE,
+            &UsmParams::default(),
+            &encode_scoped_pdu(&[], "", &pdu),
+        );
+        let params = self
+            .exchange(socket, &request, |buf| {
+                let message = decode_v3_message(buf)?;
+                Ok(
+                    (message.msg_id == msg_id && !message.usm.engine_id.is_empty())
+                        .then_some(message.usm),
+                )
+            })
+            .await?;
+        Ok(usm.engine(&params))
+    }
+
+    async fn get_v3(
+        &mut self,
+        socket: &UdpSocket,
+        usm: &SnmpUsm,
+    ) -> Result<SnmpResponse, AgentError> {
+        let mut engine = match self.engine.take() {
+            Some(engine) => engine,
+            None => self.discover(socket, usm).await?,
+        };
+        let mut retried = false;
+        loop {
+            let msg_id = self.next_request_id();
+            let pdu = encode_get_pdu(msg_id, &self.oids);
+            let request = usm.encode_request(&engine, msg_id, self.salt, &pdu)?;
+            self.salt = self.salt.wrapping_add(1);
+            let (params, response) = self
+                .exchange(socket, &request, |buf| {
+                    usm.decode_response(&engine, buf)
+                        .map(|(id, params, r)| (id == msg_id).then_some((params, r)))
+                })
+                .await?;
+            if response.pdu != SNMP_REPORT {
+                self.engine = Some(engine);
+                return Ok(response);
+            }
+            // A rebooted device, or one whose clock has drifted, reports its engine
+            // again; learn it and retry once.
+            let oid = response.varbinds.first().map(|(oid, _)| oid.as_str());
+            match oid {
+                Some(USM_UNKNOWN_ENGINE_IDS) if !retried => engine = usm.engine(&params),
+                Some(USM_NOT_IN_TIME_WINDOWS) if !retried && params.engine_id == engine.id => {
+                    engine.boots = params.boots;
+                    engine.time = params.time;
+                    engine.learned_at = Instant::now();
+                }
+                _ => {
+                    self.engine = Some(engine);
+                    return Err(AgentError::InvalidValue(format!(
+                        "SNMP request to {} failed: {}",
+                        self.host,
+                        snmp_report_error(&response)
+                    )));
+                }
+            }
+            retried = true;
+        }
+    }
+}
+
+/// Polls SNMP OIDs of a device every `interval`.
+///
+/// `host` is `address[:port]` (port 161 by default, IPv6 addresses in brackets when a
+/// port is given), `oids` a comma-separated list of numeric OIDs (ex.
+/// `1.3.6.1.2.1.1.3.0`), and `version` `1`, `2c` or `3`. Each poll
+/// outputs `{time, host, values}` on `value`, where `time` is in milliseconds and
+/// `values` maps each OID to its value: integers for INTEGER, counters,
+/// gauges and time ticks, strings for octet strings (binary ones in hex), OIDs and IP
+/// addresses, and null for missing objects. Failed polls are output on `error`.
+/// While paused, the device is not polled.
+///
+/// With v3, `community` is unused and `user` is authenticated with `auth protocol`
+/// (HMAC-MD5, HMAC-SHA or HMAC-SHA-256) when set, and its requests encrypted with
+/// `privacy protocol` (DES or AES-128) when set too. Passwords are at least 8 characters.
+/// The engine ID, boots and time of the device are discovered on the first poll, and
+/// again when it reports them unknown or out of its time window.
+#[modular_agent(
+    title = "SNMP Poll",
+    category = CATEGORY,
+    inputs = [PORT_PAUSE, PORT_RESUME],
+    outputs = [PORT_VALUE, PORT_ERROR],
+    string_config(name = CONFIG_HOST),
+    string_config(name = CONFIG_COMMUNITY, default = COMMUNITY_DEFAULT),
+    string_config(name = CONFIG_OIDS, description = "comma-separated"),
+    string_config(name = CONFIG_VERSION, default = VERSION_DEFAULT, description = "1, 2c, 3"),
+    string_config(name = CONFIG_USER, description = "v3"),
+    string_config(name = CONFIG_AUTH_PROTOCOL, title = "auth protocol", description = "v3: none, md5, sha, sha256"),
+    string_config(name = CONFIG_AUTH_PASSWORD, title = "auth password", description = "v3"),
+    string_config(name = CONFIG_PRIV_PROTOCOL, title = "privacy protocol", description = "v3: none, des, aes"),
+    string_config(name = CONFIG_PRIV_PASSWORD, title = "privacy password", description = "v3"),
+    string_config(name = CONFIG_CONTEXT, description = "v3 context name", detail),
+    string_config(name = CONFIG_INTERVAL, default = INTERVAL_DEFAULT, description = "(ex. 10s, 5m, 100ms, 1h, 1d)"),
+    string_config(name = CONFIG_TIMEOUT, default = TIMEOUT_DEFAULT),
+    integer_config(name = CONFIG_MAX_RESTARTS, default = MAX_RESTARTS_DEFAULT, title = "max restarts", description = "-1: unlimited", detail),
+    integer_config(name = CONFIG_TASK_RESTARTS, title = "task restarts", readonly, detail),
+    hint(color=2),
+)]
+struct SnmpPollAgent {
+    data: AgentData,
+    handle: Option<JoinHandle<()>>,
+    paused: PauseState,
+}
+
+impl SnmpPollAgent {
+    fn target(&self) -> Result<SnmpTarget, AgentError> {
+        let config = self.configs()?;
+        let host = config.get_string_resolved(CONFIG_HOST)?;
+        let host = host.trim();
+        if host.is_empty() {
+            return Err(AgentError::InvalidConfig("host is not set".into()));
+        }
+        let host = host_port(host, SNMP_PORT);
+        let version = match config.get_string_or(CONFIG_VERSION, VERSION_DEFAULT).trim() {
+            "1" => 0,
+            "" | "2c" | "2" => 1,
+            "3" | "v3" => SNMP_V3,
+            other => {
+                return Err(AgentError::InvalidConfig(format!(
+                    "Unsupported SNMP version: {}",
+                    other
+                )));
+            }
+        };
+        let oids = config
+            .get_string_or_default(CONFIG_OIDS)
+            .split(',')
+            .filter(|oid| !oid.trim().is_empty())
+            .map(parse_oid)
+            .collect::<Result<Vec<_>, _>>()?;
+        if oids.is_empty() {
+            return Err(AgentError::InvalidConfig("oids is not set".into()));
+        }
+        let usm = if version == SNMP_V3 {
+            Some(self.usm()?)
+        } else {
+            None
+        };
+        Ok(SnmpTarget {
+            host,
+            version,
+            community: config.get_string_resolved(CONFIG_COMMUNITY)?,
+            usm,
+            oids,
+            timeout: Duration::from_millis(parse_duration_to_ms(
+                &config.get_string_or(CONFIG_TIMEOUT, TIMEOUT_DEFAULT),
+            )?),
+            engine: None,
+            request_id: 0,
+            salt: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_nanos() as u64),
+        })
+    }
+
+    fn usm(&self) -> Result<SnmpUsm, AgentError> {
+        let config = self.configs()?;
+        let user = resolve(&config.get_string_or_default(CONFIG_USER))?;
+        if user.trim().is_empty() {
+            return Err(AgentError::InvalidConfig(
+                "user is not set for SNMP v3".into(),
+            ));
+        }
+        let auth = SnmpAuth::parse(&config.get_string_or_default(CONFIG_AUTH_PROTOCOL))?;
+        let privacy = SnmpPriv::parse(&config.get_string_or_default(CONFIG_PRIV_PROTOCOL))?;
+        if auth == SnmpAuth::None && privacy != SnmpPriv::None {
+            return Err(AgentError::InvalidConfig(
+                "SNMP v3 privacy needs an auth protocol".into(),
+            ));
+        }
+        // RFC 3414 requires passwords of at least 8 characters
+        let password = |key: &str, used: bool| -> Result<String, AgentError> {
+            if !used {
+                return Ok(String::new());
+            }
+            let password = resolve(&config.get_string_or_default(key))?;
+            if password.chars().count() < 8 {
+                return Err(AgentError::InvalidConfig(format!(
+                    "{} must be at least 8 characters",
+                    key
+                )));
+            }
+            Ok(password)
+        };
+        Ok(SnmpUsm {
+            user: user.trim().to_string(),
+            auth,
+            auth_password: password(CONFIG_AUTH_PASSWORD, auth != SnmpAuth::None)?,
+            privacy,
+            priv_password: password(CONFIG_PRIV_PASSWORD, privacy != SnmpPriv::None)?,
+            context: config.get_string_or_default(CONFIG_CONTEXT),
+        })
+    }
+
+    fn start_poll(&mut self) -> Result<(), AgentError> {
+        let target = self.target()?;
+        let config = self.configs()?;
+        let interval = Duration::from_millis(parse_duration_to_ms(
+            &config.get_string_or(CONFIG_INTERVAL, INTERVAL_DEFAULT),
+        )?);
+        let max_restarts = config.get_integer_or(CONFIG_MAX_RESTARTS, MAX_RESTARTS_DEFAULT);
+
+        let paused = self.paused.clone();
+        let ma = self.ma().clone();
+        let agent_id = self.id().to_string();
+        let def_name = self.def_name().to_string();
+        let preset_id = self.preset_id().to_string();
+        let handle = spawn_supervised(self, max_restarts, move || {
+            let target = target.clone();
+            let paused = paused.clone();
+            let ma = ma.clone();
+            let agent_id = agent_id.clone();
+            let def_name = def_name.clone();
+            let preset_id = preset_id.clone();
+            async move {
+                let mut target = target;
+                let mut interval = tokio::time::interval(interval);
+                loop {
+                    interval.tick().await;
+                    if paused.is_paused() {
+                        continue;
+                    }
+                    let (port, value) = match target.get().await {
+                        Ok(values) => (
+                            PORT_VALUE,
+                            AgentValue::object(hashmap! {
+                                "time".to_string() => AgentValue::integer(Utc::now().timestamp_millis()),
+                                "host".to_string() => AgentValue::string(&target.host),
+                                "values".to_string() => values,
+                            }),
+                        ),
+                        Err(e) => (PORT_ERROR, AgentValue::string(e.to_string())),
+                    };
+                    if !admit(&preset_id, &agent_id, &value) {
+                        continue;
+                    }
+                    if let Err(e) = ma.try_send_agent_out(
+                        agent_id.clone(),
+                        stamp(AgentContext::new(), &agent_id, &def_name),
+                        port.to_string(),
+                        value,
+                    ) {
+                        log::error!("Failed to send SNMP poll output: {}", e);
+                    }
+                }
+            }
+        });
+        self.handle = Some(handle);
+        Ok(())
+    }
+
+    fn stop_poll(&mut self) {
+        if let Some(handle) = self.handle.take() {
+            handle.abort();
+        }
+    }
+}
+
+#[async_trait]
+impl AsAgent for SnmpPollAgent {
+    fn new(ma: ModularAgent, id: String, spec: AgentSpec) -> Result<Self, AgentError> {
+        Ok(Self {
+            data: AgentData::new(ma, id, spec),
+            handle: None,
+            paused: Default::default(),
+        })
+    }
+
+    async fn start(&mut self) -> Result<(), AgentError> {
+        reset_task_restarts(self)?;
+        self.paused.set_paused(false);
+        self.start_poll()
+    }
+
+    async fn stop(&mut self) -> Result<(), AgentError> {
+        self.stop_poll();
+        Ok(())
+    }
+
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        if self.handle.is_some() {
+            self.stop_poll();
+            self.start_poll()?;
+        }
+        Ok(())
+    }
+
+    async fn process(
+        &mut self,
+        _ctx: AgentContext,
+        port: String,
+        _value: AgentValue,
+    ) -> Result<(), AgentError> {
+        if self.paused.handle_port(&port) {
+            return Ok(());
+        }
+        Err(AgentError::InvalidPin(port))
+    }
+}
+
+// OPC UA (binary protocol over TCP, without security)
+
+const OPCUA_PORT: u16 = 4840;
+const OPCUA_SECURITY_POLICY_NONE: &str = "http://opcfoundation.org/UA/SecurityPolicy#None";
+const OPCUA_SECURITY_MODE_NONE: u32 = 1;
+const OPCUA_TOKEN_ANONYMOUS: u32 = 0;
+const OPCUA_MAX_CHUNK: usize = 1 << 24;
+
+// Binary encoding IDs of the requests; each response is its request + 3.
+const OPCUA_OPEN_SECURE_CHANNEL: u32 = 446;
+const OPCUA_CLOSE_SECURE_CHANNEL: u32 = 452;
+const OPCUA_CREATE_SESSION: u32 = 461;
+const OPCUA_ACTIVATE_SESSION: u32 = 467;
+const OPCUA_CLOSE_SESSION: u32 = 473;
+const OPCUA_READ: u32 = 631;
+const OPCUA_SERVICE_FAULT: u32 = 397;
+const OPCUA_ANONYMOUS_IDENTITY_TOKEN: u32 = 321;
+
+const OPCUA_ATTRIBUTE_VALUE: u32 = 13;
+const OPCUA_TIMESTAMPS_BOTH: u32 = 2;
+
+// milliseconds from 1601-01-01, the epoch of OPC UA date times, to the Unix epoch
+const OPCUA_EPOCH_OFFSET_MS: i64 = 11_644_473_600_000;
+
+#[derive(Clone, Debug, PartialEq)]
+enum NodeIdentifier {
+    Numeric(u32),
+    String(String),
+    // as encoded: the first three fields little-endian
+    Guid([u8; 16]),
+    Opaque(Vec<u8>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct NodeId {
+    namespace: u16,
+    identifier: NodeIdentifier,
+}
+
+impl NodeId {
+    fn numeric(namespace: u16, id: u32) -> Self {
+        Self {
+            namespace,
+            identifier: NodeIdentifier::Numeric(id),
+        }
+    }
+
+    /// Parses the string form of a node ID, like `ns=2;s=Temperature` or `i=2258`.
+    fn parse(s: &str) -> Result<Self, AgentError> {
+        let invalid = || AgentError::InvalidConfig(format!("Invalid node ID: {}", s));
+        let s = s.trim();
+        let (namespace, id) = match s.strip_prefix("ns=") {
+            Some(rest) => {
+                let (ns, id) = rest.split_once(';').ok_or_else(invalid)?;
+                (ns.parse::<u16>().map_err(|_| invalid())?, id)
+            }
+            None => (0, s),
+        };
+        let identifier = if let Some(n) = id.strip_prefix("i=") {
+            NodeIdentifier::Numeric(n.parse().map_err(|_| invalid())?)
+        } else if let Some(name) = id.strip_prefix("s=") {
+            NodeIdentifier::String(name.to_string())
+        } else if let Some(guid) = id.strip_prefix("g=") {
+            NodeIdentifier::Guid(parse_guid(guid).ok_or_else(invalid)?)
+        } else {
+            // opaque (b=) node IDs are not taken, as they need base64
+            return Err(invalid());
+        };
+        Ok(Self {
+            namespace,
+            identifier,
+        })
+    }
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        let ns = self.namespace;
+        match &self.identifier {
+            NodeIdentifier::Numeric(id) if ns == 0 && *id < 0x100 => {
+                out.extend([0x00, *id as u8]);
+            }
+            NodeIdentifier::Numeric(id) if ns < 0x100 && *id < 0x10000 => {
+                out.extend([0x01, ns as u8]);
+                out.extend((*id as u16).to_le_bytes());
+            }
+            NodeIdentifier::Numeric(id) => {
+                out.push(0x02);
+                out.extend(ns.to_le_bytes());
+                out.extend(id.to_le_bytes());
+            }
+            NodeIdentifier::String(name) => {
+                out.push(0x03);
+                out.extend(ns.to_le_bytes());
+                ua_string(out, Some(name));
+            }
+            NodeIdentifier::Guid(guid) => {
+                out.push(0x04);
+                out.extend(ns.to_le_bytes());
+                out.extend(guid);
+            }
+            NodeIdentifier::Opaque(bytes) => {
+                out.push(0x05);
+                out.extend(ns.to_le_bytes());
+                ua_byte_string(out, Some(bytes));
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for NodeId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.namespace != 0 {
+            write!(f, "ns={};", self.namespace)?;
+        }
+        match &self.identifier {
+            NodeIdentifier::Numeric(id) => write!(f, "i={}", id),
+            NodeIdentifier::String(name) => write!(f, "s={}", name),
+            NodeIdentifier::Guid(guid) => write!(f, "g={}", format_guid(guid)),
+            NodeIdentifier::Opaque(bytes) => write!(
+                f,
+                "b={}",
+                bytes
+                    .iter()
+                    .map(|b| format!("{:02x}", b))
+                    .collect::<String>()
+            ),
+        }
+    }
+}
+
+// Parses `09087e75-8e5e-499b-954f-f2a9603db28a` into its encoded form.
+fn parse_guid(s: &str) -> Option<[u8; 16]> {
+    let parts: Vec<&str> = s.split('-').collect();
+    let [data1, data2, data3, data4, data5] = parts[..] else {
+        return None;
+    };
+    if data1.len() != 8 || data2.len() != 4 || data3.len() != 4 || data4.len() != 4 {
+        return None;
+    }
+    let mut guid = [0u8; 16];
+    guid[..4].copy_from_slice(&u32::from_str_radix(data1, 16).ok()?.to_le_bytes());
+    guid[4..6].copy_from_slice(&u16::from_str_radix(data2, 16).ok()?.to_le_bytes());
+    guid[6..8].copy_from_slice(&u16::from_str_radix(data3, 16).ok()?.to_le_bytes());
+    let rest = format!("{}{}", data4, data5);
+    if rest.len() != 16 {
+        return None;
+    }
+    for (i, b) in guid[8..].iter_mut().enumerate() {
+        *b = u8::from_str_radix(rest.get(i * 2..i * 2 + 2)?, 16).ok()?;
+    }
+    Some(guid)
+}
+
+fn format_guid(guid: &[u8; 16]) -> String {
+    let data1 = u32::from_le_bytes([guid[0], guid[1], guid[2], guid[3]]);
+    let data2 = u16::from_le_bytes([guid[4], guid[5]]);
+    let data3 = u16::from_le_bytes([guid[6], guid[7]]);
+    let hex = |bytes: &[u8]| {
+        bytes
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<String>()
+    };
+    format!(
+        "{:08x}-{:04x}-{:04x}-{}-{}",
+        data1,
+        data2,
+        data3,
+        hex(&guid[8..10]),
+        hex(&guid[10..])
+    )
+}
+
+fn ua_string(out: &mut Vec<u8>, s: Option<&str>) {
+    ua_byte_string(out, s.map(str::as_bytes));
+}
+
+fn ua_byte_string(out: &mut Vec<u8>, bytes: Option<&[u8]>) {
+    match bytes {
+        Some(bytes) => {
+            out.extend((bytes.len() as i32).to_le_bytes());
+            out.extend_from_slice(bytes);
+        }
+        None => out.extend((-1i32).to_le_bytes()),
+    }
+}
+
+fn ua_now() -> i64 {
+    (Utc::now().timestamp_millis() + OPCUA_EPOCH_OFFSET_MS) * 10_000
+}
+
+// A date time in milliseconds since the Unix epoch, or null for the minimum and
+// maximum values, which mean "not set".
+fn ua_date_time(ticks: i64) -> AgentValue {
+    if ticks <= 0 || ticks == i64::MAX {
+        return AgentValue::unit();
+    }
+    AgentValue::integer(ticks / 10_000 - OPCUA_EPOCH_OFFSET_MS)
+}
+
+fn ua_status_is_bad(status: u32) -> bool {
+    status & 0x8000_0000 != 0
+}
+
+/// Reads OPC UA binary encoded values from a buffer.
+struct UaReader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> UaReader<'a> {
+    fn truncated() -> AgentError {
+        AgentError::InvalidValue("Truncated OPC UA message".into())
+    }
+
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8], AgentError> {
+        if self.buf.len() < n {
+            return Err(Self::truncated());
+        }
+        let (bytes, rest) = self.buf.split_at(n);
+        self.buf = rest;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], AgentError> {
+        Ok(self.bytes(N)?.try_into().expect("N bytes"))
+    }
+
+    fn u8(&mut self) -> Result<u8, AgentError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, AgentError> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    fn u32(&mut self) -> Result<u32, AgentError> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    fn i32(&mut self) -> Result<i32, AgentError> {
+        Ok(i32::from_le_bytes(self.array()?))
+    }
+
+    fn i64(&mut self) -> Result<i64, AgentError> {
+        Ok(i64::from_le_bytes(self.array()?))
+    }
+
+    fn f64(&mut self) -> Result<f64, AgentError> {
+        Ok(f64::from_le_bytes(self.array()?))
+    }
+
+    // The length of an array, or of a string; negative lengths are null.
+    fn len(&mut self) -> Result<Option<usize>, AgentError> {
+        let len = self.i32()?;
+        if len < 0 {
+            return Ok(None);
+        }
+        // every element takes at least a byte
+        if len as usize > self.buf.len() {
+            return Err(Self::truncated());
+        }
+        Ok(Some(len as usize))
+    }
+
+    fn array_len(&mut self) -> Result<usize, AgentError> {
+        Ok(self.len()?.unwrap_or(0))
+    }
+
+    fn byte_string(&mut self) -> Result<Option<&'a [u8]>, AgentError> {
+        match self.len()? {
+            Some(len) => Ok(Some(self.bytes(len)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn string(&mut self) -> Result<Option<String>, AgentError> {
+        Ok(self
+            .byte_string()?
+            .map(|bytes| String::from_utf8_lossy(bytes).into_owned()))
+    }
+
+    fn node_id(&mut self) -> Result<NodeId, AgentError> {
+        let encoding = self.u8()?;
+        let node = match encoding & 0x3f {
+            0x00 => NodeId::numeric(0, self.u8()? as u32),
+            0x01 => {
+                let namespace = self.u8()? as u16;
+                NodeId::numeric(namespace, self.u16()? as u32)
+            }
+            0x02 => {
+                let namespace = self.u16()?;
+                NodeId::numeric(namespace, self.u32()?)
+            }
+            0x03 => NodeId {
+                namespace: self.u16()?,
+                identifier: NodeIdentifier::String(self.string()?.unwrap_or_default()),
+            },
+            0x04 => NodeId {
+                namespace: self.u16()?,
+                identifier: NodeIdentifier::Guid(self.array()?),
+            },
+            0x05 => NodeId {
+                namespace: self.u16()?,
+                identifier: NodeIdentifier::Opaque(
+                    self.byte_string()?.unwrap_or_default().to_vec(),
+                ),
+            },
+            other => {
+                return Err(AgentError::InvalidValue(format!(
+                    "Invalid OPC UA node ID encoding {:#04x}",
+                    other
+                )));
+            }
+        };
+        // the namespace URI and server index of an expanded node ID
+        if encoding & 0x80 != 0 {
+            self.string()?;
+        }
+        if encoding & 0x40 != 0 {
+            self.u32()?;
+        }
+        Ok(node)
+    }
+
+    fn localized_text(&mut self) -> Result<Option<String>, AgentError> {
+        let mask = self.u8()?;
+        if mask & 0x01 != 0 {
+            self.string()?;
+        }
+        if mask & 0x02 != 0 {
+            return self.string();
+        }
+        Ok(None)
+    }
+
+    fn skip_diagnostic_info(&mut self) -> Result<(), AgentError> {
+        let mask = self.u8()?;
+        // symbolic ID, namespace URI, localized text and locale are indexes
+        for bit in [0x01, 0x02, 0x04, 0x08] {
+            if mask & bit != 0 {
+                self.i32()?;
+            }
+        }
+        if mask & 0x10 != 0 {
+            self.string()?;
+        }
+        if mask & 0x20 != 0 {
+            self.u32()?;
+        }
+        if mask & 0x40 != 0 {
+            self.skip_diagnostic_info()?;
+        }
+        Ok(())
+    }
+
+    fn extension_object(&mut self) -> Result<(NodeId, Option<&'a [u8]>), AgentError> {
+        let type_id = self.node_id()?;
+        let body = match self.u8()? {
+            0x00 => None,
+            0x01 | 0x02 => self.byte_string()?,
+            other => {
+                return Err(AgentError::InvalidValue(format!(
+                    "Invalid OPC UA extension object encoding {:#04x}",
+                    other
+                )));
+            }
+        };
+        Ok((type_id, body))
+    }
+
+    // Returns the service result of a response header.
+    fn response_header(&mut self) -> Result<u32, AgentError> {
+        self.i64()?; // timestamp
+        self.u32()?; // request handle
+        let service_result = self.u32()?;
+        self.skip_diagnostic_info()?;
+        for _ in 0..self.array_len()? {
+            self.string()?;
+        }
+        self.extension_object()?;
+        Ok(service_result)
+    }
+
+    fn skip_application_description(&mut self) -> Result<(), AgentError> {
+        self.string()?; // application URI
+        self.string()?; // product URI
+        self.localized_text()?;
+        self.u32()?; // application type
+        self.string()?; // gateway server URI
+        self.string()?; // discovery profile URI
+        for _ in 0..self.array_len()? {
+            self.string()?;
+        }
+        Ok(())
+    }
+
+    fn variant(&mut self) -> Result<AgentValue, AgentError> {
+        let mask = self.u8()?;
+        let type_id = mask & 0x3f;
+        if mask & 0x80 == 0 {
+            return self.scalar(type_id);
+        }
+        let mut values = Vec::new();
+        for _ in 0..self.array_len()? {
+            values.push(self.scalar(type_id)?);
+        }
+        // multi-dimensional arrays are flattened
+        if mask & 0x40 != 0 {
+            for _ in 0..self.array_len()? {
+                self.i32()?;
+            }
+        }
+        Ok(AgentValue::array(values.into_iter().collect()))
+    }
+
+    fn scalar(&mut self, type_id: u8) -> Result<AgentValue, AgentError> {
+        let string = |s: Option<String>| s.map(AgentValue::string).unwrap_or_else(AgentValue::unit);
+        Ok(match type_id {
+            0 => AgentValue::unit(),
+            1 => AgentValue::boolean(self.u8()? != 0),
+            2 => AgentValue::integer(self.u8()? as i8 as i64),
+            3 => AgentValue::integer(self.u8()? as i64),
+            4 => AgentValue::integer(self.u16()? as i16 as i64),
+            5 => AgentValue::integer(self.u16()? as i64),
+            6 => AgentValue::integer(self.i32()? as i64),
+            7 => AgentValue::integer(self.u32()? as i64),
+            8 => AgentValue::integer(self.i64()?),
+            9 => decode_unsigned(&self.bytes(8)?.iter().rev().copied().collect::<Vec<_>>()),
+            10 => AgentValue::number(f32::from_le_bytes(self.array()?) as f64),
+            11 => AgentValue::number(self.f64()?),
+            // String, XmlElement
+            12 | 16 => string(self.string()?),
+            13 => ua_date_time(self.i64()?),
+            14 => AgentValue::string(format_guid(&self.array()?)),
+            15 => self
+                .byte_string()?
+                .map(bytes_to_value)
+                .unwrap_or_else(AgentValue::unit),
+            // NodeId, ExpandedNodeId
+            17 | 18 => AgentValue::string(self.node_id()?.to_string()),
+            19 => AgentValue::integer(self.u32()? as i64),
+            20 => {
+                let namespace = self.u16()?;
+                let name = self.string()?.unwrap_or_default();
+                if namespace == 0 {
+                    AgentValue::string(name)
+                } else {
+                    AgentValue::string(format!("{}:{}", namespace, name))
+                }
+            }
+            21 => string(self.localized_text()?),
+            22 => {
+                let (type_id, body) = self.extension_object()?;
+                AgentValue::object(hashmap! {
+                    "type_id".to_string() => AgentValue::string(type_id.to_string()),
+                    "body".to_string() => body.map(bytes_to_value).unwrap_or_else(AgentValue::unit),
+                })
+            }
+            other => {
+                return Err(AgentError::InvalidValue(format!(
+                    "Unsupported OPC UA variant type {}",
+                    other
+                )));
+            }
+        })
+    }
+
+    // Returns `{value, status, source_time, server_time}`.
+    fn data_value(&mut self) -> Result<AgentValue, AgentError> {
+        let mask = self.u8()?;
+        let value = if mask & 0x01 != 0 {
+            self.variant()?
+        } else {
+            AgentValue::unit()
+        };
+        let status = if mask & 0x02 != 0 { self.u32()? } else { 0 };
+        let source_time = if mask & 0x04 != 0 {
+            ua_date_time(self.i64()?)
+        } else {
+            AgentValue::unit()
+        };
+        if mask & 0x10 != 0 {
+            self.u16()?; // source picoseconds
+        }
+        let server_time = if mask & 0x08 != 0 {
+            ua_date_time(self.i64()?)
+        } else {
+            AgentValue::unit()
+        };
+        if mask & 0x20 != 0 {
+            self.u16()?; // server picoseconds
+        }
+        Ok(AgentValue::object(hashmap! {
+            "value".to_string() => value,
+            "status".to_string() => AgentValue::integer(status as i64),
+            "source_time".to_string() => source_time,
+            "server_time".to_string() => server_time,
+        }))
+    }
+}
+
+/// Checks the response to `service` and returns its body after the response header.
+fn ua_service_response(service: u32, payload: &[u8]) -> Result<&[u8], AgentError> {
+    let mut reader = UaReader { buf: payload };
+    let type_id = reader.node_id()?;
+    let fault = type_id == NodeId::numeric(0, OPCUA_SERVICE_FAULT);
+    if !fault && type_id != NodeId::numeric(0, service + 3) {
+        return Err(AgentError::InvalidValue(format!(
+            "Unexpected OPC UA response {}",
+            type_id
+        )));
+    }
+    let service_result = reader.response_header()?;
+    if fault || ua_status_is_bad(service_result) {
+        return Err(AgentError::InvalidValue(format!(
+            "OPC UA service {} failed with status {:#010x}",
+            service, service_result
+        )));
+    }
+    Ok(reader.buf)
+}
+
+/// A session with an OPC UA server, over a secure channel without security.
+struct OpcUaSession {
+    stream: TcpStream,
+    channel_id: u32,
+    token_id: u32,
+    sequence_number: u32,
+    request_id: u32,
+    auth_token: NodeId,
+    // the secure channel is reopened before its token expires
+    renew_at: Instant,
+}
+
+impl OpcUaSession {
+    async fn send(&mut self, kind: &[u8; 3], body: &[u8]) -> Result<(), AgentError> {
+        let mut chunk = Vec::with_capacity(8 + body.len());
+        chunk.extend(kind);
+        chunk.push(b'F');
+        chunk.extend(((8 + body.len()) as u32).to_le_bytes());
+        chunk.extend_from_slice(body);
+        self.stream
+            .write_all(&chunk)
+            .await
+            .map_err(|e| AgentError::IoError(format!("Failed to send OPC UA message: {}", e)))
+    }
+
+    // Receives a chunk, and returns its kind, chunk type and body.
+    async fn recv(&mut self) -> Result<([u8; 3], u8, Vec<u8>), AgentError> {
+        let io = |e: std::io::Error| {
+            AgentError::IoError(format!("Failed to receive OPC UA message: {}", e))
+        };
+        let mut header = [0u8; 8];
+        self.stream.read_exact(&mut header).await.map_err(io)?;
+        let size = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as usize;
+        if !(8..=OPCUA_MAX_CHUNK).contains(&size) {
+            return Err(AgentError::InvalidValue(format!(
+                "Invalid OPC UA message size {}",
+                size
+            )));
+        }
+        let mut body = vec![0u8; size - 8];
+        self.stream.read_exact(&mut body).await.map_err(io)?;
+        let kind = [header[0], header[1], header[2]];
+        if &kind == b"ERR" {
+            let mut reader = UaReader { buf: &body };
+            let status = reader.u32()?;
+            let reason = reader.string()?.unwrap_or_default();
+            return Err(AgentError::IoError(format!(
+                "OPC UA server error {:#010x}: {}",
+                status, reason
+            )));
+        }
+        Ok((kind, header[3], body))
+    }
+
+    // Receives a secure channel message, joining its chunks, and returns its payload.
+    async fn recv_message(&mut self, kind: &[u8; 3]) -> Result<Vec<u8>, AgentError> {
+        let mut payload = Vec::new();
+        loop {
+            let (actual, chunk_type, body) = self.recv().await?;
+            if &actual != kind {
+                return Err(AgentError::InvalidValue(format!(
+                    "Unexpected OPC UA message {}",
+                    String::from_utf8_lossy(&actual)
+                )));
+            }
+            let mut reader = UaReader { buf: &body };
+            reader.u32()?; // secure channel ID
+            if kind == b"OPN" {
+                reader.string()?; // security policy URI
+                reader.byte_string()?; // sender certificate
+                reader.byte_string()?; // receiver certificate thumbprint
+            } else {
+                reader.u32()?; // token ID
+            }
+            reader.u32()?; // sequence number
+            reader.u32()?; // request ID
+            if payload.len() + reader.buf.len() > OPCUA_MAX_CHUNK {
+                return Err(AgentError::InvalidValue("OPC UA message too large".into()));
+            }
+            payload.extend_from_slice(reader.buf);
+            match chunk_type {
+                b'F' => return Ok(payload),
+                b'C' => continue,
+                _ => {
+                    return Err(AgentError::IoError(
+                        "OPC UA server aborted the message".into(),
+                    ));
+                }
+            }
+        }
+    }
+
+    fn next_sequence(&mut self, out: &mut Vec<u8>) {
+        self.sequence_number = self.sequence_number.wrapping_add(1);
+        self.request_id = self.request_id.wrapping_add(1);
+        out.extend(self.sequence_number.to_le_bytes());
+        out.extend(self.request_id.to_le_bytes());
+    }
+
+    fn request_header(&self, out: &mut Vec<u8>) {
+        self.auth_token.encode(out);
+        out.extend(ua_now().to_le_bytes());
+        out.extend(self.request_id.to_le_bytes()); // request handle
+        out.extend(0u32.to_le_bytes()); // return diagnostics
+        ua_string(out, None); // audit entry ID
+        out.extend(0u32.to_le_bytes()); // timeout hint
+        out.extend([0, 0, 0]); // no additional header
+    }
+
+    /// Calls `service` with `body` (after the request header), and returns the body of
+    /// its response.
+    async fn call(&mut self, service: u32, body: &[u8]) -> Result<Vec<u8>, AgentError> {
+        let mut message = Vec::new();
+        message.extend(self.channel_id.to_le_bytes());
+        message.extend(self.token_id.to_le_bytes());
+        self.next_sequence(&mut message);
+        NodeId::numeric(0, service).encode(&mut message);
+        self.request_header(&mut message);
+        message.extend_from_slice(body);
+        self.send(b"MSG", &message).await?;
+        let payload = self.recv_message(b"MSG").await?;
+        Ok(ua_service_response(service, &payload)?.to_vec())
+    }
+
+    async fn hello(&mut self, endpoint: &str) -> Result<(), AgentError> {
+        let mut body = Vec::new();
+        // protocol version, receive and send buffer sizes, no limits on messages
+        for n in [0u32, 65536, 65536, 0, 0] {
+            body.extend(n.to_le_bytes());
+        }
+        ua_string(&mut body, Some(endpoint));
+        self.send(b"HEL", &body).await?;
+        match self.recv().await? {
+            (kind, _, _) if &kind == b"ACK" => Ok(()),
+            (kind, _, _) => Err(AgentError::InvalidValue(format!(
+                "Unexpected OPC UA message {}",
+                String::from_utf8_lossy(&kind)
+            ))),
+        }
+    }
+
+    async fn open_channel(&mut self) -> Result<(), AgentError> {
+        let mut message = Vec::new();
+        message.extend(0u32.to_le_bytes()); // secure channel ID
+        ua_string(&mut message, Some(OPCUA_SECURITY_POLICY_NONE));
+        ua_byte_string(&mut message, None); // sender certificate
+        ua_byte_string(&mut message, None); // receiver certificate thumbprint
+        self.next_sequence(&mut message);
+        NodeId::numeric(0, OPCUA_OPEN_SECURE_CHANNEL).encode(&mut message);
+        self.request_header(&mut message);
+        message.extend(0u32.to_le_bytes()); // client protocol version
+        message.extend(0u32.to_le_bytes()); // request type: issue
+        message.extend(OPCUA_SECURITY_MODE_NONE.to_le_bytes());
+        ua_byte_string(&mut message, Some(&[])); // client nonce
+        message.extend(3_600_000u32.to_le_bytes()); // requested lifetime
+        self.send(b"OPN", &message).await?;
+
+        let payload = self.recv_message(b"OPN").await?;
+        let mut reader = UaReader {
+            buf: ua_service_response(OPCUA_OPEN_SECURE_CHANNEL, &payload)?,
+        };
+        reader.u32()?; // server protocol version
+        self.channel_id = reader.u32()?;
+        self.token_id = reader.u32()?;
+        reader.i64()?; // created at
+        let lifetime = reader.u32()?;
+        self.renew_at = Instant::now() + Duration::from_millis(lifetime as u64 * 3 / 4);
+        Ok(())
+    }
+
+    // Creates a session, and returns the policy ID of anonymous users.
+    async fn create_session(
+        &mut self,
+        endpoint: &str,
+        session_timeout: Duration,
+    ) -> Result<String, AgentError> {
+        let mut body = Vec::new();
+        // client description
+        ua_string(&mut body, Some("urn:modular-agent-std"));
+        ua_string(&mut body, Some("urn:modular-agent-std"));
+        body.push(0x02);
+        ua_string(&mut body, Some("Modular Agent"));
+        body.extend(1u32.to_le_bytes()); // application type: client
+        ua_string(&mut body, None); // gateway server URI
+        ua_string(&mut body, None); // discovery profile URI
+        body.extend((-1i32).to_le_bytes()); // discovery URLs
+
+        ua_string(&mut body, None); // server URI
+        ua_string(&mut body, Some(endpoint));
+        ua_string(&mut body, Some("modular-agent-std"));
+        ua_byte_string(&mut body, None); // client nonce
+        ua_byte_string(&mut body, None); // client certificate
+        body.extend((session_timeout.as_millis() as f64).to_le_bytes());
+        body.extend(0u32.to_le_bytes()); // max response message size
+        let response = self.call(OPCUA_CREATE_SESSION, &body).await?;
+
+        let mut reader = UaReader { buf: &response };
+        reader.node_id()?; // session ID
+        self.auth_token = reader.node_id()?;
+        reader.f64()?; // revised session timeout
+        reader.byte_string()?; // server nonce
+        reader.byte_string()?; // server certificate
+        let mut policy_id = None;
+        for _ in 0..reader.array_len()? {
+            reader.string()?; // endpoint URL
+            reader.skip_application_description()?;
+            reader.byte_string()?; // server certificate
+            let mode = reader.u32()?;
+            reader.string()?; // security policy URI
+            for _ in 0..reader.array_len()? {
+                let id = reader.string()?.unwrap_or_default();
+                let token_type = reader.u32()?;
+                reader.string()?; // issued token type
+                reader.string()?; // issuer endpoint URL
+                reader.string()?; // security policy URI
+                if mode == OPCUA_SECURITY_MODE_NONE && token_type == OPCUA_TOKEN_ANONYMOUS {
+                    policy_id.get_or_insert(id);
+                }
+            }
+            reader.string()?; // transport profile URI
+            reader.u8()?; // security level
+        }
+        policy_id.ok_or_else(|| {
+            AgentError::InvalidValue(
+                "OPC UA server has no endpoint for anonymous users without security".into(),
+            )
+        })
+    }
+
+    async fn activate_session(&mut self, policy_id: &str) -> Result<(), AgentError> {
+        let mut body = Vec::new();
+        ua_string(&mut body, None); // client signature algorithm
+        ua_byte_string(&mut body, None); // client signature
+        body.extend(0i32.to_le_bytes()); // client software certificates
+        body.extend(0i32.to_le_bytes()); // locale IDs
+        NodeId::numeric(0, OPCUA_ANONYMOUS_IDENTITY_TOKEN).encode(&mut body);
+        body.push(0x01);
+        let mut token = Vec::new();
+        ua_string(&mut token, Some(policy_id));
+        ua_byte_string(&mut body, Some(&token));
+        ua_string(&mut body, None); // user token signature algorithm
+        ua_byte_string(&mut body, None); // user token signature
+        self.call(OPCUA_ACTIVATE_SESSION, &body).await?;
+        Ok(())
+    }
+
+    /// Reads the values of `nodes`, and returns `{node: data value}`.
+    async fn read(&mut self, nodes: &[(String, NodeId)]) -> Result<AgentValue, AgentError> {
+        let mut body = Vec::new();
+        body.extend(0f64.to_le_bytes()); // max age
+        body.extend(OPCUA_TIMESTAMPS_BOTH.to_le_bytes());
+        body.extend((nodes.len() as i32).to_le_bytes());
+        for (_, node) in nodes {
+            node.encode(&mut body);
+            body.extend(OPCUA_ATTRIBUTE_VALUE.to_le_bytes());
+            ua_string(&mut body, None); // index range
+            body.extend(0u16.to_le_bytes()); // data encoding
+            ua_string(&mut body, None);
+        }
+        let response = self.call(OPCUA_READ, &body).await?;
+
+        let mut reader = UaReader { buf: &response };
+        let n = reader.array_len()?;
+        if n != nodes.len() {
+            return Err(AgentError::InvalidValue(format!(
+                "OPC UA server returned {} values for {} nodes",
+                n,
+                nodes.len()
+            )));
+        }
+        let mut values = HashMap::new();
+        for (name, _) in nodes {
+            values.insert(name.clone(), reader.data_value()?);
+        }
+        Ok(AgentValue::object(values))
+    }
+
+    async fn close(mut self) {
+        // deletes the subscriptions
+        if let Err(e) = self.call(OPCUA_CLOSE_SESSION, &[1]).await {
+            log::debug!("Failed to close OPC UA session: {}", e);
+        }
+        let mut message = Vec::new();
+        message.extend(self.channel_id.to_le_bytes());
+        message.extend(self.token_id.to_le_bytes());
+        self.next_sequence(&mut message);
+        NodeId::numeric(0, OPCUA_CLOSE_SECURE_CHANNEL).encode(&mut message);
+        self.request_header(&mut message);
+        if let Err(e) = self.send(b"CLO", &message).await {
+            log::debug!("Failed to close OPC UA secure channel: {}", e);
+        }
+    }
+}
+
+/// Returns the `host:port` of an `opc.tcp://host[:port][/path]` endpoint.
+fn opcua_address(endpoint: &str) -> Result<String, AgentError> {
+    let authority = endpoint
+        .strip_prefix("opc.tcp://")
+        .and_then(|rest| rest.split('/').next())
+        .filter(|authority| !authority.is_empty())
+        .ok_or_else(|| {
+            AgentError::InvalidConfig(format!(
+                "Invalid OPC UA endpoint {}: expected opc.tcp://host[:port][/path]",
+                endpoint
+            ))
+        })?;
+    Ok(host_port(authority, OPCUA_PORT))
+}
+
+#[derive(Clone)]
+struct OpcUaTarget {
+    endpoint: String,
+    address: String,
+    // the node IDs as configured, and parsed
+    nodes: Vec<(String, NodeId)>,
+    timeout: Duration,
+    session_timeout: Duration,
+}
+
+impl OpcUaTarget {
+    fn timed_out(&self) -> AgentError {
+        AgentError::IoError(format!(
+            "OPC UA request to {} timed out after {:?}",
+            self.endpoint, self.timeout
+        ))
+    }
+
+    async fn connect(&self) -> Result<OpcUaSession, AgentError> {
+        let stream = TcpStream::connect(&self.address).await.map_err(|e| {
+            AgentError::IoError(format!("Failed to connect to {}: {}", self.endpoint, e))
+        })?;
+        let mut session = OpcUaSession {
+            stream,
+            channel_id: 0,
+            token_id: 0,
+            sequence_number: 0,
+            request_id: 0,
+            auth_token: NodeId::numeric(0, 0),
+            renew_at: Instant::now(),
+        };
+        session.hello(&self.endpoint).await?;
+        session.open_channel().await?;
+        let policy_id = session
+            .create_session(&self.endpoint, self.session_timeout)
+            .await?;
+        session.activate_session(&policy_id).await?;
+        Ok(session)
+    }
+
+    /// Reads the nodes in `session`, opening it first if needed. A session that
+    /// fails is dropped, to be opened again on the next poll.
+    async fn poll(&self, session: &mut Option<OpcUaSession>) -> Result<AgentValue, AgentError> {
+        if let Some(expiring) = session.take_if(|s| Instant::now() >= s.renew_at) {
+            let _ = tokio::time::timeout(self.timeout, expiring.close()).await;
+        }
+        let current = match session {
+            Some(current) => current,
+            None => session.insert(
+                tokio::time::timeout(self.timeout, self.connect())
+                    .await
+                    .map_err(|_| self.timed_out())??,
+            ),
+        };
+        let values = match tokio::time::timeout(self.timeout, current.read(&self.nodes)).await {
+            Ok(values) => values,
+            Err(_) => Err(self.timed_out()),
+        };
+        if values.is_err() {
+            *session = None;
+        }
+        values
+    }
+}
+
+/// Polls the values of OPC UA nodes every `interval`.
+///
+/// `endpoint` is the server URL, `opc.tcp://host[:port][/path]` (port 4840 by default),
+/// and `nodes` a comma-separated list of node IDs in their string form (ex.
+/// `ns=2;s=Line1.Temperature`, `i=2258`). Each poll outputs `{time, endpoint, values}`
+/// on `value`, where `time` is in milliseconds and `values` maps each node to
+/// `{value, status, source_time, server_time}`: the value by its OPC UA type (numbers,
+/// booleans, strings, date times in milliseconds, byte strings as binary values, and
+/// arrays of them), its status code (0 when good), and its timestamps in milliseconds,
+/// null when not given. Failed polls are output on `error`. While paused, the server is
+/// not polled.
+///
+/// The session is kept between polls, and opened again after a failure. It is opened
+/// without security (security policy None) as an anonymous user, so the server must
+/// offer such an endpoint.
+#[modular_agent(
+    title = "OPC UA Poll",
+    category = CATEGORY,
+    inputs = [PORT_PAUSE, PORT_RESUME],
+    outputs = [PORT_VALUE, PORT_ERROR],
+    string_config(name = CONFIG_ENDPOINT, description = "opc.tcp://host[:port][/path]"),
+    string_config(name = CONFIG_NODES, description = "comma-separated (ex. ns=2;s=Temperature)"),
+    string_config(name = CONFIG_INTERVAL, default = INTERVAL_DEFAULT, description = "(ex. 10s, 5m, 100ms, 1h, 1d)"),
+    string_config(name = CONFIG_TIMEOUT, default = OPCUA_TIMEOUT_DEFAULT),
+    integer_config(name = CONFIG_MAX_RESTARTS, default = MAX_RESTARTS_DEFAULT, title = "max restarts", description = "-1: unlimited", detail),
+    integer_config(name = CONFIG_TASK_RESTARTS, title = "task restarts", readonly, detail),
+    hint(color=2),
+)]
+struct OpcUaPollAgent {
+    data: AgentData,
+    handle: Option<JoinHandle<()>>,
+    paused: PauseState,
+}
+
+impl OpcUaPollAgent {
+    fn target(&self) -> Result<OpcUaTarget, AgentError> {
+        let config = self.configs()?;
+        let endpoint = config.get_string_resolved(CONFIG_ENDPOINT)?;
+        let endpoint = endpoint.trim();
+        if endpoint.is_empty() {
+            return Err(AgentError::InvalidConfig("endpoint is not set".into()));
+        }
+        let nodes = config
+            .get_string_or_default(CONFIG_NODES)
+            .split(',')
+            .filter(|node| !node.trim().is_empty())
+            .map(|node| Ok((node.trim().to_string(), NodeId::parse(node)?)))
+            .collect::<Result<Vec<_>, AgentError>>()?;
+        if nodes.is_empty() {
+            return Err(AgentError::InvalidConfig("nodes is not set".into()));
+        }
+        let interval = Duration::from_millis(parse_duration_to_ms(
+            &config.get_string_or(CONFIG_INTERVAL, INTERVAL_DEFAULT),
+        )?);
+        Ok(OpcUaTarget {
+            endpoint: endpoint.to_string(),
+            address: opcua_address(endpoint)?,
+            nodes,
+            timeout: Duration::from_millis(parse_duration_to_ms(
+                &config.get_string_or(CONFIG_TIMEOUT, OPCUA_TIMEOUT_DEFAULT),
+            )?),
+            // outlives the wait between polls
+            session_timeout: (interval * 3).max(Duration::from_secs(60)),
+        })
+    }
+
+    fn start_poll(&mut self) -> Result<(), AgentError> {
+        let target = self.target()?;
+        let config = self.configs()?;
+        let interval = Duration::from_millis(parse_duration_to_ms(
+            &config.get_string_or(CONFIG_INTERVAL, INTERVAL_DEFAULT),
+        )?);
+        let max_restarts = config.get_integer_or(CONFIG_MAX_RESTARTS, MAX_RESTARTS_DEFAULT);
+
+        let paused = self.paused.clone();
+        let ma = self.ma().clone();
+        let agent_id = self.id().to_string();
+        let def_name = self.def_name().to_string();
+        let preset_id = self.preset_id().to_string();
+        let handle = spawn_supervised(self, max_restarts, move || {
+            let target = target.clone();
+            let paused = paused.clone();
+            let ma = ma.clone();
+            let agent_id = agent_id.clone();
+            let def_name = def_name.clone();
+            let preset_id = preset_id.clone();
+            async move {
+                let mut session = None;
+                let mut interval = tokio::time::interval(interval);
+                loop {
+                    interval.tick().await;
+                    if paused.is_paused() {
+                        continue;
+                    }
+                    let (port, value) = match target.poll(&mut session).await {
+                        Ok(values) => (
+                            PORT_VALUE,
+                            AgentValue::object(hashmap! {
+                                "time".to_string() => AgentValue::integer(Utc::now().timestamp_millis()),
+                                "endpoint".to_string() => AgentValue::string(&target.endpoint),
+                                "values".to_string() => values,
+                            }),
+                        ),
+                        Err(e) => (PORT_ERROR, AgentValue::string(e.to_string())),
+                    };
+                    if !admit(&preset_id, &agent_id, &value) {
+                        continue;
+                    }
+                    if let Err(e) = ma.try_send_agent_out(
+                        agent_id.clone(),
+                        stamp(AgentContext::new(), &agent_id, &def_name),
+                        port.to_string(),
+                        value,
+                    ) {
+                        log::error!("Failed to send OPC UA poll output: {}", e);
+                    }
+                }
+            }
+        });
+        self.handle = Some(handle);
+        Ok(())
+    }
+
+    fn stop_poll(&mut self) {
+        if let Some(handle) = self.handle.take() {
+            handle.abort();
+        }
+    }
+}
+
+#[async_trait]
+impl AsAgent for OpcUaPollAgent {
+    fn new(ma: ModularAgent, id: String, spec: AgentSpec) -> Result<Self, AgentError> {
+        Ok(Self {
+            data: AgentData::new(ma, id, spec),
+            handle: None,
+            paused: Default::default(),
+        })
+    }
+
+    async fn start(&mut self) -> Result<(), AgentError> {
+        reset_task_restarts(self)?;
+        self.paused.set_paused(false);
+        self.start_poll()
+    }
+
+    async fn stop(&mut self) -> Result<(), AgentError> {
+        self.stop_poll();
+        Ok(())
+    }
+
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        if self.handle.is_some() {
+            self.stop_poll();
+            self.start_poll()?;
+        }
+        Ok(())
+    }
+
+    async fn process(
+        &mut self,
+        _ctx: AgentContext,
+        port: String,
+        _value: AgentValue,
+    ) -> Result<(), AgentError> {
+        if self.paused.handle_port(&port) {
+            return Ok(());
+        }
+        Err(AgentError::InvalidPin(port))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_f64(value: &AgentValue, key: &str) -> f64 {
+        value.get(key).and_then(|v| v.as_f64()).unwrap()
+    }
+
+    #[test]
+    fn test_payload_bytes() {
+        assert_eq!(
+            payload_bytes(&AgentValue::string("0a:1B 2c")).unwrap(),
+            vec![0x0a, 0x1b, 0x2c]
+        );
+        assert!(payload_bytes(&AgentValue::string("abc")).is_err());
+        assert!(payload_bytes(&AgentValue::string("zz")).is_err());
+    }
+
+    #[test]
+    fn test_bme280() {
+        // the calibration and readings of the example in the BMP280 datasheet
+        let mut calib = Vec::new();
+        calib.extend(27504u16.to_le_bytes());
+        for t in [26435i16, -1000] {
+            calib.extend(t.to_le_bytes());
+        }
+        calib.extend(36477u16.to_le_bytes());
+        for p in [-10685i16, 3024, 2855, 140, -7, 15500, -14600, 6000] {
+            calib.extend(p.to_le_bytes());
+        }
+        calib.extend([0, 75]);
+        let calib26 = Bme280Calib::parse(&calib).unwrap();
+        assert!(calib26.h.is_none());
+
+        // adc_P = 415148, adc_T = 519888
+        let data = [0x65, 0x5a, 0xc0, 0x7e, 0xed, 0x00];
+        let value = calib26.compensate(&data).unwrap();
+        assert!((get_f64(&value, "temperature") - 25.08).abs() < 0.01);
+        assert!((get_f64(&value, "pressure") - 1006.53).abs() < 0.01);
+        assert!(value.get("humidity").is_none());
+
+        // H2 = 362, H3 = 0, H4 = 313, H5 = 50, H6 = 30
+        calib.extend([0x6a, 0x01, 0x00, 0x13, 0x29, 0x03, 0x1e]);
+        let calib33 = Bme280Calib::parse(&calib).unwrap();
+        assert_eq!(calib33.h, Some([75.0, 362.0, 0.0, 313.0, 50.0, 30.0]));
+        let mut data = data.to_vec();
+        data.extend([0x6c, 0x00]);
+        let humidity = get_f64(&calib33.compensate(&data).unwrap(), "humidity");
+        assert!((0.0..=100.0).contains(&humidity));
+        data[6..].copy_from_slice(&[0x80, 0x00]);
+        assert!(calib33.compensate(&data).unwrap().get("humidity").is_none());
+
+        assert!(Bme280Calib::parse(&[0; 10]).is_err());
+        assert!(calib33.compensate(&[0; 7]).is_err());
+    }
+
+    #[test]
+    fn test_xiaomi() {
+        let atc = payload_bytes(&AgentValue::string("a4c138aabbcc00ea2c5a0b8a07")).unwrap();
+        let value = decode_xiaomi(&atc, FORMAT_AUTO).unwrap();
+        assert_eq!(value.get_str("mac"), Some("A4:C1:38:AA:BB:CC"));
+        assert_eq!(get_f64(&value, "temperature"), 23.4);
+        assert_eq!(get_f64(&value, "humidity"), 44.0);
+        assert_eq!(value.get("battery"), Some(&AgentValue::integer(90)));
+        assert_eq!(get_f64(&value, "voltage"), 2.954);
+
+        let pvvx = payload_bytes(&AgentValue::string("ccbbaa38c1a42609a81180 0b5a0704")).unwrap();
+        let value = decode_xiaomi(&pvvx, FORMAT_AUTO).unwrap();
+        assert_eq!(value.get_str("mac"), Some("A4:C1:38:AA:BB:CC"));
+        assert_eq!(get_f64(&value, "temperature"), 23.42);
+        assert_eq!(get_f64(&value, "humidity"), 45.2);
+        assert_eq!(value.get("battery"), Some(&AgentValue::integer(90)));
+
+        // frame control 0x2050: MAC and object included; temperature and humidity object
+        let mibeacon =
+            payload_bytes(&AgentValue::string("5020aa0112ccbbaa38c1a40d1004ea00c201")).unwrap();
+        let value = decode_xiaomi(&mibeacon, FORMAT_AUTO).unwrap();
+        assert_eq!(value.get_str("mac"), Some("A4:C1:38:AA:BB:CC"));
+        assert_eq!(value.get("product_id"), Some(&AgentValue::integer(0x01aa)));
+        assert_eq!(get_f64(&value, "temperature"), 23.4);
+        assert_eq!(get_f64(&value, "humidity"), 45.0);
+
+        let encrypted = payload_bytes(&AgentValue::string("5820aa0112")).unwrap();
+        assert!(decode_xiaomi(&encrypted, FORMAT_MIBEACON).is_err());
+        assert!(decode_xiaomi(&atc[..10], FORMAT_ATC).is_err());
+        assert!(decode_xiaomi(&atc, "other").is_err());
+    }
+
+    #[test]
+    fn test_nmea() {
+        let gga = "$GPGGA,123519,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,*47";
+        let value = parse_nmea(gga, true).unwrap();
+        assert_eq!(value.get_str("talker"), Some("GP"));
+        assert_eq!(value.get_str("type"), Some("GGA"));
+        assert_eq!(value.get_str("time"), Some("123519"));
+        assert!((get_f64(&value, "latitude") - 48.1173).abs() < 1e-4);
+        assert!((get_f64(&value, "longitude") - 11.516_667).abs() < 1e-4);
+        assert_eq!(get_f64(&value, "satellites"), 8.0);
+        assert_eq!(get_f64(&value, "altitude"), 545.4);
+        assert_eq!(
+            value
+                .get("fields")
+                .and_then(|f| f.as_array())
+                .unwrap()
+                .len(),
+            14
+        );
+
+        let rmc = "$GPRMC,225446,A,4916.45,N,12311.12,W,000.5,054.7,191194,020.3,E*68";
+        let value = parse_nmea(rmc, true).unwrap();
+        assert_eq!(value.get("valid"), Some(&AgentValue::boolean(true)));
+        assert!((get_f64(&value, "longitude") + 123.1853).abs() < 1e-4);
+        assert_eq!(get_f64(&value, "speed_knots"), 0.5);
+        assert_eq!(value.get_str("date"), Some("191194"));
+
+        assert!(parse_nmea(&gga.replace("*47", "*48"), true).is_err());
+        assert!(parse_nmea(&gga.replace("*47", "*48"), false).is_ok());
+        assert!(parse_nmea("$GPGGA,1,2", true).is_err());
+        assert!(parse_nmea("$GPGGA,1,2", false).is_ok());
+        assert!(parse_nmea("GPGGA,1,2", false).is_err());
+    }
+
+    #[test]
+    fn test_host_port() {
+        assert_eq!(host_port("10.0.0.1", SNMP_PORT), "10.0.0.1:161");
+        assert_eq!(
+            host_port("switch.local:1161", SNMP_PORT),
+            "switch.local:1161"
+        );
+        assert_eq!(host_port("::1", SNMP_PORT), "[::1]:161");
+        assert_eq!(host_port("[fe80::1]", SNMP_PORT), "[fe80::1]:161");
+        assert_eq!(host_port("[fe80::1]:1161", SNMP_PORT), "[fe80::1]:1161");
+    }
+
+    #[test]
+    fn test_snmp_codec() {
+        let sys_descr = parse_oid("1.3.6.1.2.1.1.1.0").unwrap();
+        let request = encode_get_request(1, "public", 1, &[sys_descr]);
+        let expected = "30 26 02 01 01 04 06 70 75 62 6c 69 63 a0 19 02 01 01 02 01 00 02 01 00 \
+                        30 0e 30 0c 06 08 2b 06 01 02 01 01 01 00 05 00";
+        assert_eq!(
+            request,
+            payload_bytes(&AgentValue::string(expected)).unwrap()
+        );
+
+        assert_eq!(ber_integer(0), vec![0x02, 0x01, 0x00]);
+        assert_eq!(ber_integer(128), vec![0x02, 0x02, 0x00, 0x80]);
+        assert_eq!(ber_integer(-129), vec![0x02, 0x02, 0xff, 0x7f]);
+        assert_eq!(decode_integer(&[0xff, 0x7f]), -129);
+        assert_eq!(
+            ber_oid(&[1, 3, 6, 1, 4, 1, 2021]),
+            vec![0x06, 0x07, 0x2b, 0x06, 0x01, 0x04, 0x01, 0x8f, 0x65]
+        );
+        assert_eq!(
+            decode_oid(&[0x2b, 0x06, 0x01, 0x04, 0x01, 0x8f, 0x65]),
+            "1.3.6.1.4.1.2021"
+        );
+        assert!(parse_oid("1.3.x").is_err());
+        assert!(parse_oid("1").is_err());
+
+        let long = ber_tlv(BER_OCTET_STRING, &[b'a'; 200]);
+        assert_eq!(&long[..3], &[0x04, 0x81, 200]);
+        assert_eq!(
+            BerReader { buf: &long }
+                .expect(BER_OCTET_STRING)
+                .unwrap()
+                .len(),
+            200
+        );
+
+        // response: sysDescr.0 = "Linux", sysUpTime.0 = 1234 ticks, ifPhysAddress, missing
+        let mut varbinds = Vec::new();
+        for (oid, tag, content) in [
+            ("1.3.6.1.2.1.1.1.0", BER_OCTET_STRING, b"Linux".to_vec()),
+            ("1.3.6.1.2.1.1.3.0", SNMP_TIMETICKS, vec![0x04, 0xd2]),
+            (
+                "1.3.6.1.2.1.2.2.1.6.1",
+                BER_OCTET_STRING,
+                vec![0x00, 0x1a, 0x2b, 0x3c, 0x4d, 0x5e],
+            ),
+            ("1.3.6.1.2.1.1.9.0", SNMP_NO_SUCH_INSTANCE, vec![]),
+        ] {
+            let mut varbind = ber_oid(&parse_oid(oid).unwrap());
+            varbind.extend(ber_tlv(tag, &content));
+            varbinds.extend(ber_tlv(BER_SEQUENCE, &varbind));
+        }
+        let mut pdu = ber_integer(7);
+        pdu.extend(ber_integer(0));
+        pdu.extend(ber_integer(0));
+        pdu.extend(ber_tlv(BER_SEQUENCE, &varbinds));
+        let mut message = ber_integer(1);
+        message.extend(ber_tlv(BER_OCTET_STRING, b"public"));
+        message.extend(ber_tlv(SNMP_RESPONSE, &pdu));
+        let response = decode_response(&ber_tlv(BER_SEQUENCE, &message)).unwrap();
+        assert_eq!(response.request_id, 7);
+        assert_eq!(response.error_status, 0);
+        assert_eq!(
+            response.varbinds,
+            vec![
+                ("1.3.6.1.2.1.1.1.0".to_string(), AgentValue::string("Linux")),
+                ("1.3.6.1.2.1.1.3.0".to_string(), AgentValue::integer(1234)),
+                (
+                    "1.3.6.1.2.1.2.2.1.6.1".to_string(),
+                    AgentValue::string("00:1a:2b:3c:4d:5e")
+                ),
+                ("1.3.6.1.2.1.1.9.0".to_string(), AgentValue::unit()),
+            ]
+        );
+        assert!(decode_response(&request).is_err());
+        assert!(decode_response(&[0x30, 0x05, 0x02]).is_err());
+    }
+
+    fn test_usm(auth: SnmpAuth, privacy: SnmpPriv) -> SnmpUsm {
+        SnmpUsm {
+            user: "admin".into(),
+            auth,
+            auth_password: "maplesyrup".into(),
+            privacy,
+            priv_password: "maplesyrup2".into(),
+            context: String::new(),
+        }
+    }
+
+    fn test_response_pdu(request_id: i64) -> Vec<u8> {
+        let mut varbind = ber_oid(&parse_oid("1.3.6.1.2.1.1.5.0").unwrap());
+        varbind.extend(ber_tlv(BER_OCTET_STRING, b"router"));
+        let mut pdu = ber_integer(request_id);
+        pdu.extend(ber_integer(0));
+        pdu.extend(ber_integer(0));
+        pdu.extend(ber_tlv(BER_SEQUENCE, &ber_tlv(BER_SEQUENCE, &varbind)));
+        ber_tlv(SNMP_RESPONSE, &pdu)
+    }
+
+    #[test]
+    fn test_snmp_v3_keys() {
+        // RFC 3414 A.3.1 and A.3.2
+        let engine_id = [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2];
+        assert_eq!(
+            SnmpAuth::Md5.localize_key("maplesyrup", &engine_id),
+            payload_bytes(&AgentValue::string("526f5eed9fcce26f8964c2930787d82b")).unwrap()
+        );
+        assert_eq!(
+            SnmpAuth::Sha1.localize_key("maplesyrup", &engine_id),
+            payload_bytes(&AgentValue::string(
+                "6695febc9288e36282235fc7151f128497b38f3f"
+            ))
+            .unwrap()
+        );
+        assert_eq!(
+            SnmpAuth::Sha256
+                .localize_key("maplesyrup", &engine_id)
+                .len(),
+            32
+        );
+        assert_eq!(SnmpAuth::Md5.mac(&[0; 16], b"message").len(), 12);
+        assert_eq!(SnmpAuth::Sha256.mac(&[0; 32], b"message").len(), 24);
+    }
+
+    #[test]
+    fn test_snmp_v3_codec() {
+        let params = UsmParams {
+            engine_id: vec![0x80, 0, 0x1f, 0x88, 4, 1, 2, 3],
+            boots: 3,
+            time: 1200,
+            ..Default::default()
+        };
+        for (auth, privacy) in [
+            (SnmpAuth::None, SnmpPriv::None),
+            (SnmpAuth::Md5, SnmpPriv::None),
+            (SnmpAuth::Sha1, SnmpPriv::Des),
+            (SnmpAuth::Sha256, SnmpPriv::Aes),
+        ] {
+            let usm = test_usm(auth, privacy);
+            let engine = usm.engine(&params);
+            // the device answers in kind, with its own salt
+            let message = usm
+                .encode_request(&engine, 42, 7, &test_response_pdu(42))
+                .unwrap();
+            let decoded = decode_v3_message(&message).unwrap();
+            assert_eq!(decoded.msg_id, 42);
+            assert_eq!(decoded.flags & 0x03, usm.flags() & 0x03);
+            assert_eq!(decoded.usm.user, b"admin");
+            assert_eq!(decoded.usm.auth.len(), auth.mac_len());
+            let encrypted = privacy != SnmpPriv::None;
+            assert_eq!(decoded.data_tag == BER_OCTET_STRING, encrypted);
+
+            let (msg_id, usm_params, response) = usm.decode_response(&engine, &message).unwrap();
+            assert_eq!(msg_id, 42);
+            assert_eq!(usm_params.boots, 3);
+            assert_eq!(response.pdu, SNMP_RESPONSE);
+            assert_eq!(
+                response.varbinds,
+                vec![(
+                    "1.3.6.1.2.1.1.5.0".to_string(),
+                    AgentValue::string("router")
+                )]
+            );
+
+            if auth != SnmpAuth::None {
+                // a tampered message fails its HMAC
+                let mut tampered = message.clone();
+                let last = tampered.len() - 1;
+                tampered[last] ^= 1;
+                assert!(usm.decode_response(&engine, &tampered).is_err());
+
+                // an unauthenticated response is only taken as a report
+                let plain = test_usm(SnmpAuth::None, SnmpPriv::None);
+                let unauthenticated = plain
+                    .encode_request(&plain.engine(&params), 43, 0, &test_response_pdu(43))
+                    .unwrap();
+                assert!(usm.decode_response(&engine, &unauthenticated).is_err());
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_snmp_v3_get() {
+        let device = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let usm = test_usm(SnmpAuth::Sha1, SnmpPriv::Aes);
+        let mut target = SnmpTarget {
+            host: device.local_addr().unwrap().to_string(),
+            version: SNMP_V3,
+            community: String::new(),
+            usm: Some(usm.clone()),
+            oids: vec![parse_oid("1.3.6.1.2.1.1.5.0").unwrap()],
+            timeout: Duration::from_secs(5),
+            engine: None,
+            request_id: 0,
+            salt: 0,
+        };
+
+        let device_task = tokio::spawn(async move {
+            let engine = usm.engine(&UsmParams {
+                engine_id: vec![0x80, 0, 0x1f, 0x88, 4, 1, 2, 3],
+                boots: 5,
+                time: 1000,
+                ..Default::default()
+            });
+            let mut buf = vec![0u8; 65535];
+            let report = |msg_id: i64, oid: &str, params: &UsmParams| {
+                let mut varbind = ber_oid(&parse_oid(oid).unwrap());
+                varbind.extend(ber_tlv(SNMP_COUNTER32, &[1]));
+                let mut pdu = ber_integer(msg_id);
+                pdu.extend(ber_integer(0));
+                pdu.extend(ber_integer(0));
+                pdu.extend(ber_tlv(BER_SEQUENCE, &ber_tlv(BER_SEQUENCE, &varbind)));
+                let scoped = encode_scoped_pdu(&params.engine_id, "", &ber_tlv(SNMP_REPORT, &pdu));
+                encode_v3_message(msg_id, 0, params, &scoped)
+            };
+
+            // discovery: reports the engine ID, but not its boots and time
+            let (n, peer) = device.recv_from(&mut buf).await.unwrap();
+            let request = decode_v3_message(&buf[..n]).unwrap();
+            assert!(request.usm.engine_id.is_empty());
+            let params = UsmParams {
+                engine_id: engine.id.clone(),
+                ..Default::default()
+            };
+            let response = report(request.msg_id, USM_UNKNOWN_ENGINE_IDS, &params);
+            device.send_to(&response, peer).await.unwrap();
+
+            // not in the time window yet
+            let (n, peer) = device.recv_from(&mut buf).await.unwrap();
+            let request = decode_v3_message(&buf[..n]).unwrap();
+            assert_eq!(request.flags, 0x07);
+            assert_eq!(request.usm.boots, 0);
+            let params = UsmParams {
+                engine_id: engine.id.clone(),
+                boots: engine.boots,
+                time: engine.time,
+                ..Default::default()
+            };
+            let response = report(request.msg_id, USM_NOT_IN_TIME_WINDOWS, &params);
+            device.send_to(&response, peer).await.unwrap();
+
+            // the retry is in time
+            let (n, peer) = device.recv_from(&mut buf).await.unwrap();
+            let request = decode_v3_message(&buf[..n]).unwrap();
+            assert_eq!(request.usm.boots, 5);
+            assert!(request.usm.time >= 1000);
+            let response = usm
+                .encode_request(
+                    &engine,
+                    request.msg_id,
+                    9,
+                    &test_response_pdu(request.msg_id),
+                )
+                .unwrap();
+            device.send_to(&response, peer).await.unwrap();
+        });
+
+        let values = target.get().await.unwrap();
+        assert_eq!(values.get_str("1.3.6.1.2.1.1.5.0"), Some("router"));
+        device_task.await.unwrap();
+        assert_eq!(target.engine.as_ref().map(|e| e.boots), Some(5));
+    }
+
+    #[test]
+    fn test_opcua_node_id() {
+        let encoded = |s: &str| {
+            let mut out = Vec::new();
+            NodeId::parse(s).unwrap().encode(&mut out);
+            out
+        };
+        assert_eq!(encoded("i=13"), vec![0x00, 13]);
+        assert_eq!(encoded("ns=2;i=1001"), vec![0x01, 2, 0xe9, 0x03]);
+        assert_eq!(encoded("i=70000"), vec![0x02, 0, 0, 0x70, 0x11, 0x01, 0]);
+        assert_eq!(
+            encoded("ns=3;s=Tank"),
+            vec![0x03, 3, 0, 4, 0, 0, 0, b'T', b'a', b'n', b'k']
+        );
+        assert_eq!(
+            encoded("ns=1;g=09087e75-8e5e-499b-954f-f2a9603db28a")[3..],
+            [
+                0x75, 0x7e, 0x08, 0x09, 0x5e, 0x8e, 0x9b, 0x49, 0x95, 0x4f, 0xf2, 0xa9, 0x60, 0x3d,
+                0xb2, 0x8a
+            ]
+        );
+        for s in [
+            "i=2258",
+            "ns=2;s=Line1.Temperature",
+            "ns=1;g=09087e75-8e5e-499b-954f-f2a9603db28a",
+        ] {
+            let node = NodeId::parse(s).unwrap();
+            assert_eq!(node.to_string(), s);
+            let mut out = Vec::new();
+            node.encode(&mut out);
+            assert_eq!(UaReader { buf: &out }.node_id().unwrap(), node);
+        }
+        assert!(NodeId::parse("ns=2").is_err());
+        assert!(NodeId::parse("ns=x;i=1").is_err());
+        assert!(NodeId::parse("i=abc").is_err());
+        assert!(NodeId::parse("b=AAEC").is_err());
+        assert!(NodeId::parse("g=1234").is_err());
+
+        assert_eq!(opcua_address("opc.tcp://plc").unwrap(), "plc:4840");
+        assert_eq!(
+            opcua_address("opc.tcp://10.0.0.5:4841/OPCUA/Server").unwrap(),
+            "10.0.0.5:4841"
+        );
+        assert!(opcua_address("http://plc").is_err());
+        assert!(opcua_address("opc.tcp:///path").is_err());
+    }
+
+    // The OPC UA date time of `ms` since the Unix epoch.
+    fn ua_ticks(ms: i64) -> i64 {
+        (ms + OPCUA_EPOCH_OFFSET_MS) * 10_000
+    }
+
+    #[test]
+    fn test_opcua_data_value() {
+        // a Double with its status, source time and picoseconds, and server time
+        let mut buf = vec![0x1f, 11];
+        buf.extend(21.5f64.to_le_bytes());
+        buf.extend(0x0040_0000u32.to_le_bytes());
+        buf.extend(ua_ticks(1_700_000_000_000).to_le_bytes());
+        buf.extend(7u16.to_le_bytes());
+        buf.extend(ua_ticks(1_700_000_000_250).to_le_bytes());
+        // an array of Int16 with its dimensions
+        buf.extend([0x01, 0xc4]);
+        buf.extend(2i32.to_le_bytes());
+        buf.extend((-2i16).to_le_bytes());
+        buf.extend(300i16.to_le_bytes());
+        buf.extend(1i32.to_le_bytes());
+        buf.extend(2i32.to_le_bytes());
+        // a ByteString, a LocalizedText and a QualifiedName
+        buf.extend([0x01, 15, 2, 0, 0, 0, 0xab, 0xcd]);
+        buf.extend([
+            0x01, 21, 0x03, 2, 0, 0, 0, b'e', b'n', 2, 0, 0, 0, b'o', b'k',
+        ]);
+        buf.extend([0x01, 20, 2, 0, 1, 0, 0, 0, b'x']);
+        // no value at all
+        buf.push(0x00);
+
+        let mut reader = UaReader { buf: &buf };
+        let value = reader.data_value().unwrap();
+        assert_eq!(get_f64(&value, "value"), 21.5);
+        assert_eq!(
+            value.get("status").and_then(|v| v.as_i64()),
+            Some(0x0040_0000)
+        );
+        assert_eq!(
+            value.get("source_time").and_then(|v| v.as_i64()),
+            Some(1_700_000_000_000)
+        );
+        assert_eq!(
+            value.get("server_time").and_then(|v| v.as_i64()),
+            Some(1_700_000_000_250)
+        );
+
+        let value = reader.data_value().unwrap();
+        assert_eq!(
+            value.get("value"),
+            Some(&AgentValue::array(
+                vec![AgentValue::integer(-2), AgentValue::integer(300)].into()
+            ))
+        );
+        assert_eq!(value.get("source_time"), Some(&AgentValue::unit()));
+
+        let value = reader.data_value().unwrap();
+        assert_eq!(value.get("value"), Some(&bytes_to_value(&[0xab, 0xcd])));
+        let value = reader.data_value().unwrap();
+        assert_eq!(value.get_str("value"), Some("ok"));
+        let value = reader.data_value().unwrap();
+        assert_eq!(value.get_str("value"), Some("2:x"));
+        let value = reader.data_value().unwrap();
+        assert_eq!(value.get("value"), Some(&AgentValue::unit()));
+        assert!(reader.buf.is_empty());
+
+        // truncated
+        assert!(
+            UaReader {
+                buf: &[0x01, 11, 0, 0]
+            }
+            .data_value()
+            .is_err()
+        );
+        assert!(
+            UaReader {
+                buf: &[0x01, 12, 0xff, 0xff, 0, 0]
+            }
+            .data_value()
+            .is_err()
+        );
+    }
+
+    async fn read_chunk(stream: &mut TcpStream) -> ([u8; 3], Vec<u8>) {
+        let mut header = [0u8; 8];
+        stream.read_exact(&mut header).await.unwrap();
+        let size = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as usize;
+        let mut body = vec![0u8; size - 8];
+        stream.read_exact(&mut body).await.unwrap();
+        ([header[0], header[1], header[2]], body)
+    }
+
+    async fn write_chunk(stream: &mut TcpStream, kind: &[u8; 3], chunk: u8, body: &[u8]) {
+        let mut message = kind.to_vec();
+        message.push(chunk);
+        message.extend(((8 + body.len()) as u32).to_le_bytes());
+        message.extend_from_slice(body);
+        stream.write_all(&message).await.unwrap();
+    }
+
+    // Returns the service of a MSG request, and its auth token.
+    fn test_request(body: &[u8]) -> (NodeId, NodeId) {
+        let mut reader = UaReader { buf: &body[16..] };
+        (reader.node_id().unwrap(), reader.node_id().unwrap())
+    }
+
+    fn test_response(service: u32, body: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        NodeId::numeric(0, service + 3).encode(&mut out);
+        out.extend(0i64.to_le_bytes());
+        out.extend(1u32.to_le_bytes());
+        out.extend(0u32.to_le_bytes()); // good
+        out.push(0);
+        out.extend((-1i32).to_le_bytes());
+        out.extend([0, 0, 0]);
+        out.extend_from_slice(body);
+        out
+    }
+
+    fn test_sequence(channel: &[u8], payload: &[u8]) -> Vec<u8> {
+        let mut out = channel.to_vec();
+        out.extend(1u32.to_le_bytes());
+        out.extend(1u32.to_le_bytes());
+        out.extend_from_slice(payload);
+        out
+    }
+
+    #[tokio::test]
+    async fn test_opcua_poll() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let endpoint = format!("opc.tcp://{}/server", address);
+        let target = OpcUaTarget {
+            endpoint: endpoint.clone(),
+            address: opcua_address(&endpoint).unwrap(),
+            nodes: vec![
+                (
+                    "ns=2;s=Temperature".to_string(),
+                    NodeId::parse("ns=2;s=Temperature").unwrap(),
+                ),
+                ("i=2258".to_string(), NodeId::parse("i=2258").unwrap()),
+            ],
+            timeout: Duration::from_secs(5),
+            session_timeout: Duration::from_secs(60),
+        };
+        let auth_token = NodeId::parse("ns=1;i=4242").unwrap();
+
+        let server_task = tokio::spawn({
+            let auth_token = auth_token.clone();
+            async move {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let channel = [7u8, 0, 0, 0, 1, 0, 0, 0]; // channel and token IDs
+
+                let (kind, body) = read_chunk(&mut stream).await;
+                assert_eq!(&kind, b"HEL");
+                assert_eq!(
+                    UaReader { buf: &body[20..] }.string().unwrap().as_deref(),
+                    Some(endpoint.as_str())
+                );
+                write_chunk(&mut stream, b"ACK", b'F', &body[..20]).await;
+
+                let (kind, _) = read_chunk(&mut stream).await;
+                assert_eq!(&kind, b"OPN");
+                let mut response = vec![];
+                response.extend(0u32.to_le_bytes());
+                response.extend_from_slice(&channel);
+                response.extend(ua_now().to_le_bytes());
+                response.extend(3_600_000u32.to_le_bytes());
+                response.extend(0i32.to_le_bytes()); // server nonce
+                let mut opn = channel[..4].to_vec();
+                ua_string(&mut opn, Some(OPCUA_SECURITY_POLICY_NONE));
+                ua_byte_string(&mut opn, None);
+                ua_byte_string(&mut opn, None);
+                let opn = test_sequence(&opn, &test_response(OPCUA_OPEN_SECURE_CHANNEL, &response));
+                write_chunk(&mut stream, b"OPN", b'F', &opn).await;
+
+                let (kind, body) = read_chunk(&mut stream).await;
+                assert_eq!(&kind, b"MSG");
+                assert_eq!(&body[..8], &channel);
+                assert_eq!(
+                    test_request(&body).0,
+                    NodeId::numeric(0, OPCUA_CREATE_SESSION)
+                );
+                let mut response = Vec::new();
+                NodeId::numeric(1, 1).encode(&mut response); // session ID
+                auth_token.encode(&mut response);
+                response.extend(60_000f64.to_le_bytes());
+                response.extend(0i32.to_le_bytes()); // server nonce
+                response.extend((-1i32).to_le_bytes()); // server certificate
+                response.extend(2i32.to_le_bytes());
+                // a signed endpoint, then one without security
+                for (mode, policy_id) in [(2u32, "signed"), (OPCUA_SECURITY_MODE_NONE, "anon")] {
+                    ua_string(&mut response, Some(&endpoint));
+                    ua_string(&mut response, Some("urn:server"));
+                    ua_string(&mut response, None);
+                    response.extend([0x02, 1, 0, 0, 0, b'S']);
+                    response.extend(0u32.to_le_bytes());
+                    ua_string(&mut response, None);
+                    ua_string(&mut response, None);
+                    response.extend((-1i32).to_le_bytes());
+                    ua_byte_string(&mut response, None);
+                    response.extend(mode.to_le_bytes());
+                    ua_string(&mut response, Some(OPCUA_SECURITY_POLICY_NONE));
+                    response.extend(1i32.to_le_bytes());
+                    ua_string(&mut response, Some(policy_id));
+                    response.extend(OPCUA_TOKEN_ANONYMOUS.to_le_bytes());
+                    for _ in 0..3 {
+                        ua_string(&mut response, None);
+                    }
+                    ua_string(&mut response, None);
+                    response.push(0);
+                }
+                let msg = test_sequence(&channel, &test_response(OPCUA_CREATE_SESSION, &response));
+                write_chunk(&mut stream, b"MSG", b'F', &msg).await;
+
+                let (_, body) = read_chunk(&mut stream).await;
+                let (service, token) = test_request(&body);
+                assert_eq!(service, NodeId::numeric(0, OPCUA_ACTIVATE_SESSION));
+                assert_eq!(token, auth_token);
+                let policy_id = b"anon";
+                assert!(body.windows(policy_id.len()).any(|w| w == policy_id));
+                let msg = test_sequence(&channel, &test_response(OPCUA_ACTIVATE_SESSION, &[]));
+                write_chunk(&mut stream, b"MSG", b'F', &msg).await;
+
+                // two reads in the same session, answered in two chunks
+                for temperature in [21.5f64, 22.0] {
+                    let (_, body) = read_chunk(&mut stream).await;
+                    let (service, token) = test_request(&body);
+                    assert_eq!(service, NodeId::numeric(0, OPCUA_READ));
+                    assert_eq!(token, auth_token);
+                    let mut response = 2i32.to_le_bytes().to_vec();
+                    response.extend([0x01, 11]);
+                    response.extend(temperature.to_le_bytes());
+                    response.extend([0x01, 13]);
+                    response.extend(ua_ticks(1_700_000_000_000).to_le_bytes());
+                    response.extend((-1i32).to_le_bytes());
+                    let response = test_response(OPCUA_READ, &response);
+                    let (first, last) = response.split_at(response.len() / 2);
+                    write_chunk(&mut stream, b"MSG", b'C', &test_sequence(&channel, first)).await;
+                    write_chunk(&mut stream, b"MSG", b'F', &test_sequence(&channel, last)).await;
+                }
+
+                // a bad read drops the session
+                let (_, _) = read_chunk(&mut stream).await;
+                let mut fault = Vec::new();
+                NodeId::numeric(0, OPCUA_SERVICE_FAULT).encode(&mut fault);
+                fault.extend(0i64.to_le_bytes());
+                fault.extend(1u32.to_le_bytes());
+                fault.extend(0x800e_0000u32.to_le_bytes());
+                fault.push(0);
+                fault.extend((-1i32).to_le_bytes());
+                fault.extend([0, 0, 0]);
+                write_chunk(&mut stream, b"MSG", b'F', &test_sequence(&channel, &fault)).await;
+            }
+        });
+
+        let mut session = None;
+        for temperature in [21.5, 22.0] {
+            let values = target.poll(&mut session).await.unwrap();
+            let value = values.get("ns=2;s=Temperature").unwrap();
+            assert_eq!(get_f64(value, "value"), temperature);
+            assert_eq!(value.get("status").and_then(|v| v.as_i64()), Some(0));
+            let value = values.get("i=2258").unwrap();
+            assert_eq!(
+                value.get("value").and_then(|v| v.as_i64()),
+                Some(1_700_000_000_000)
+            );
+            assert!(session.is_some());
+        }
+        let e = target.poll(&mut session).await.unwrap_err();
+        assert!(e.to_string().contains("0x800e0000"), "{}", e);
+        assert!(session.is_none());
+        server_task.await.unwrap();
+    }
+}
@@ -0,0 +1,743 @@
+#![cfg(feature = "http")]
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use chrono::Utc;
+use handlebars::Handlebars;
+use modular_agent_core::{
+    Agent, AgentConfigs, AgentContext, AgentData, AgentError, AgentOutput, AgentSpec, AgentValue,
+    AsAgent, ModularAgent, async_trait, modular_agent,
+};
+use tokio::task::JoinHandle;
+
+use crate::audit::{ACTION_HTTP_SUBMIT, audit};
+use crate::contract::{CONFIG_INPUT_CONTRACT, ContractAgent, InputContract, contract_agents};
+use crate::data::get_nested_value;
+use crate::profile::{placeholder_template, resolve};
+use crate::provenance::{Traced, stamp};
+use crate::quota::admit_in;
+use crate::string::handlebars_new;
+use crate::time::{PORT_EXPIRED, deadline_instant, is_expired, parse_duration_to_ms};
+
+const CATEGORY: &str = "Std/Flow";
+
+const PORT_DONE: &str = "done";
+const PORT_FAILED: &str = "failed";
+const PORT_HANDLE: &str = "handle";
+const PORT_RESULT: &str = "result";
+const PORT_STATUS: &str = "status";
+const PORT_VALUE: &str = "value";
+
+const CONFIG_BODY: &str = "body";
+const CONFIG_DONE: &str = "done";
+const CONFIG_FAILED: &str = "failed";
+const CONFIG_HEADERS: &str = "headers";
+const CONFIG_ID_KEY: &str = "id_key";
+const CONFIG_INTERVAL: &str = "interval";
+const CONFIG_METHOD: &str = "method";
+const CONFIG_RESULT_KEY: &str = "result_key";
+const CONFIG_STATUS_KEY: &str = "status_key";
+const CONFIG_TIMEOUT: &str = "timeout";
+const CONFIG_URL: &str = "url";
+
+const DONE_DEFAULT: &str = "done, completed, succeeded, success";
+const FAILED_DEFAULT: &str = "failed, error, cancelled, canceled";
+const ID_KEY_DEFAULT: &str = "id";
+const INTERVAL_DEFAULT: &str = "5s";
+const METHOD_GET: &str = "GET";
+const METHOD_POST: &str = "POST";
+const STATUS_KEY_DEFAULT: &str = "status";
+const TIMEOUT_DEFAULT: &str = "10m";
+
+// Keys of a job handle
+const KEY_ERROR: &str = "error";
+const KEY_ID: &str = "id";
+const KEY_POLLS: &str = "polls";
+const KEY_RESPONSE: &str = "response";
+const KEY_STATUS: &str = "status";
+const KEY_SUBMITTED_AT: &str = "submitted_at";
+
+const HTTP_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Consecutive failed polls before a job is given up
+const MAX_POLL_ERRORS: u32 = 3;
+
+contract_agents!(SubmitJobAgent, PollJobStatusAgent, FetchJobResultAgent);
+
+// Submit Job
+/// Submits a job to an HTTP API and outputs its handle.
+///
+/// The request is sent to `url` with `method`, `headers` and `body`. They are templates
+/// rendered with the input as `value` (ex. `{{value.file}}`); an empty body sends the
+/// input as JSON. Values rendered into `url` are percent-encoded (`{{{value.path}}}` keeps
+/// them as they are). Configs may also contain `${env:NAME}` and `${profile:key}`, which
+/// are resolved after rendering, and are kept unresolved in the audit and logs.
+///
+/// The handle is `{id, submitted_at, response}`, where `id` is taken from the response at
+/// `id key`. Poll Job Status and Fetch Job Result take it as input.
+///
+/// An input whose context deadline has passed is output on `expired` without a request.
+#[modular_agent(
+    title = "Submit Job",
+    category = CATEGORY,
+    inputs = [PORT_VALUE],
+    outputs = [PORT_HANDLE, PORT_EXPIRED],
+    string_config(name = CONFIG_URL),
+    string_config(name = CONFIG_METHOD, default = METHOD_POST),
+    object_config(name = CONFIG_HEADERS),
+    text_config(name = CONFIG_BODY),
+    string_config(name = CONFIG_ID_KEY, default = ID_KEY_DEFAULT, title = "id key"),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
+)]
+struct SubmitJobAgent {
+    data: AgentData,
+    contract: InputContract,
+}
+
+#[async_trait]
+impl AsAgent for SubmitJobAgent {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
+        Ok(Self {
+            data: AgentData::new(ma, id, spec),
+            contract,
+        })
+    }
+
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
+    }
+
+    async fn process(
+        &mut self,
+        ctx: AgentContext,
+        port: String,
+        value: AgentValue,
+    ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
+        if is_expired(&ctx) {
+            return self.output(self.traced(ctx), PORT_EXPIRED, value).await;
+        }
+        let configs = self.configs()?;
+        let template = HttpRequest::from_configs(configs, METHOD_POST)?;
+        let request = template.render(&value, true, true)?;
+        let id_keys = key_path(&configs.get_string_or(CONFIG_ID_KEY, ID_KEY_DEFAULT));
+
+        let logged = template.render(&value, true, false)?;
+        let payload = AgentValue::string(&logged.body);
+        audit(self, &ctx, ACTION_HTTP_SUBMIT, &logged.url, &payload).await?;
+        let response = send_blocking(request).await?;
+        let id = get_nested_value(&response, &id_keys)
+            .filter(|id| !id.is_unit())
+            .cloned()
+            .ok_or_else(|| {
+                AgentError::InvalidValue(format!(
+                    "No job id at '{}' in the response",
+                    id_keys.join(".")
+                ))
+            })?;
+
+        let mut handle = im::HashMap::new();
+        handle.insert(KEY_ID.to_string(), id);
+        handle.insert(
+            KEY_SUBMITTED_AT.to_string(),
+            AgentValue::integer(Utc::now().timestamp_millis()),
+        );
+        handle.insert(KEY_RESPONSE.to_string(), response);
+        self.output(self.traced(ctx), PORT_HANDLE, AgentValue::object(handle))
+            .await
+    }
+}
+
+// Poll Job Status
+/// Polls the status of submitted jobs until they finish.
+///
+/// For each job handle, `url` (a template rendered with the handle as `value`, ex.
+/// `https://api.example.com/jobs/{{value.id}}`) is requested every `interval`.
+/// The status is read from the response at `status key`:
+///
+/// - in `done`: the handle is output on `done`
+/// - in `failed`: the handle is output on `failed`
+/// - otherwise: the handle is output on `status`, and polling continues
+///
+/// The handle is output with the latest `status`, `response` and number of `polls`.
+/// A job that has not finished within `timeout`, or fails to poll 3 times in a row, is
+/// output on `failed` with an `error`. Polling stops when the context deadline passes,
+/// and the handle is output on `expired`.
+#[modular_agent(
+    title = "Poll Job Status",
+    category = CATEGORY,
+    inputs = [PORT_HANDLE],
+    outputs = [PORT_DONE, PORT_FAILED, PORT_STATUS, PORT_EXPIRED],
+    string_config(name = CONFIG_URL),
+    string_config(name = CONFIG_METHOD, default = METHOD_GET),
+    object_config(name = CONFIG_HEADERS),
+    string_config(name = CONFIG_STATUS_KEY, default = STATUS_KEY_DEFAULT, title = "status key"),
+    string_config(name = CONFIG_DONE, default = DONE_DEFAULT),
+    string_config(name = CONFIG_FAILED, default = FAILED_DEFAULT),
+    string_config(name = CONFIG_INTERVAL, default = INTERVAL_DEFAULT, description = "(ex. 500ms, 5s)"),
+    string_config(name = CONFIG_TIMEOUT, default = TIMEOUT_DEFAULT, description = "(ex. 30s, 10m)"),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
+)]
+struct PollJobStatusAgent {
+    data: AgentData,
+    contract: InputContract,
+    // polling tasks by job id
+    tasks: HashMap<String, JoinHandle<()>>,
+    // number of handles without an id
+    next_anonymous: u64,
+}
+
+impl PollJobStatusAgent {
+    fn stop_tasks(&mut self) {
+        for (_, task) in self.tasks.drain() {
+            task.abort();
+        }
+    }
+}
+
+#[async_trait]
+impl AsAgent for PollJobStatusAgent {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
+        Ok(Self {
+            data: AgentData::new(ma, id, spec),
+            contract,
+            tasks: HashMap::new(),
+            next_anonymous: 0,
+        })
+    }
+
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
+    }
+
+    async fn stop(&mut self) -> Result<(), AgentError> {
+        self.stop_tasks();
+        Ok(())
+    }
+
+    async fn process(
+        &mut self,
+        ctx: AgentContext,
+        port: String,
+        value: AgentValue,
+    ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
+        let configs = self.configs()?;
+        let request =
+            HttpRequest::from_configs(configs, METHOD_GET)?.render(&value, false, true)?;
+        let poll = JobPoll {
+            status_keys: key_path(&configs.get_string_or(CONFIG_STATUS_KEY, STATUS_KEY_DEFAULT)),
+            done: parse_list(&configs.get_string_or(CONFIG_DONE, DONE_DEFAULT)),
+            failed: parse_list(&configs.get_string_or(CONFIG_FAILED, FAILED_DEFAULT)),
+        };
+        let interval = Duration::from_millis(parse_duration_to_ms(
+            &configs.get_string_or(CONFIG_INTERVAL, INTERVAL_DEFAULT),
+        )?);
+        let timeout = Duration::from_millis(parse_duration_to_ms(
+            &configs.get_string_or(CONFIG_TIMEOUT, TIMEOUT_DEFAULT),
+        )?);
+        let Some(mut handle) = value.as_object().cloned() else {
+            return Err(AgentError::InvalidValue(
+                "Job handle must be an object".into(),
+            ));
+        };
+        // handles without an id are polled apart from each other
+        let job_id = match handle.get(KEY_ID) {
+            Some(AgentValue::String(s)) => s.to_string(),
+            Some(id) if !id.is_unit() => id.to_json().to_string(),
+            _ => {
+                self.next_anonymous += 1;
+                format!("#{}", self.next_anonymous)
+            }
+        };
+
+        let ma = self.ma().clone();
+        let agent_id = self.id().to_string();
+        let def_name = self.def_name().to_string();
+        let preset_id = self.preset_id().to_string();
+        let task_job_id = job_id.clone();
+        let task = tokio::spawn(async move {
+            let start = tokio::time::Instant::now();
+            let mut polls = 0;
+            let mut errors = 0;
+            let (port, error) = loop {
+                if is_expired(&ctx) {
+                    break (PORT_EXPIRED, None);
+                }
+                polls += 1;
+                match send_blocking(request.clone()).await {
+                    Ok(response) => {
+                        errors = 0;
+                        let state = poll.state(&response);
+                        handle.insert(KEY_STATUS.to_string(), poll.status(&response));
+                        handle.insert(KEY_RESPONSE.to_string(), response);
+                        handle.insert(KEY_POLLS.to_string(), AgentValue::integer(polls));
+                        match state {
+                            JobState::Done => break (PORT_DONE, None),
+                            JobState::Failed => break (PORT_FAILED, None),
+                            JobState::Running => {}
+                        }
+                    }
+                    Err(e) => {
+                        errors += 1;
+                        log::warn!("Failed to poll job {}: {}", task_job_id, e);
+                        if errors >= MAX_POLL_ERRORS {
+                            break (PORT_FAILED, Some(e.to_string()));
+                        }
+                    }
+                }
+                if start.elapsed() + interval > timeout {
+                    break (
+                        PORT_FAILED,
+                        Some(format!("Job did not finish within {:?}", timeout)),
+                    );
+                }
+                let status = AgentValue::object(handle.clone());
+                if errors == 0 && admit_in(&preset_id, &agent_id, &ctx, &status) {
+                    let _ = ma.try_send_agent_out(
+                        agent_id.clone(),
+                        stamp(ctx.clone(), &agent_id, &def_name),
+                        PORT_STATUS.to_string(),
+                        status,
+                    );
+                }
+                let mut next_poll = tokio::time::Instant::now() + interval;
+                if let Some(expires) = deadline_instant(&ctx) {
+                    next_poll = next_poll.min(expires);
+                }
+                tokio::time::sleep_until(next_poll).await;
+            };
+
+            if let Some(error) = error {
+                handle.insert(KEY_ERROR.to_string(), AgentValue::string(error));
+            }
+            if let Err(e) = ma.try_send_agent_out(
+                agent_id.clone(),
+                stamp(ctx, &agent_id, &def_name),
+                port.to_string(),
+                AgentValue::object(handle),
+            ) {
+                log::error!("Failed to send job status: {}", e);
+            }
+        });
+
+        self.tasks.retain(|_, task| !task.is_finished());
+        // A handle submitted again restarts polling
+        if let Some(old) = self.tasks.insert(job_id, task) {
+            old.abort();
+        }
+        Ok(())
+    }
+}
+
+// Fetch Job Result
+/// Fetches the result of a finished job.
+///
+/// `url` is a template rendered with the job handle as `value` (ex.
+/// `https://api.example.com/jobs/{{value.id}}/result`). The response, or the part of it at
+/// `result key`, is output on `result`.
+///
+/// A handle whose context deadline has passed is output on `expired` without a request.
+#[modular_agent(
+    title = "Fetch Job Result",
+    category = CATEGORY,
+    inputs = [PORT_HANDLE],
+    outputs = [PORT_RESULT, PORT_EXPIRED],
+    string_config(name = CONFIG_URL),
+    string_config(name = CONFIG_METHOD, default = METHOD_GET),
+    object_config(name = CONFIG_HEADERS),
+    string_config(name = CONFIG_RESULT_KEY, title = "result key"),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
+)]
+struct FetchJobResultAgent {
+    data: AgentData,
+    contract: InputContract,
+}
+
+#[async_trait]
+impl AsAgent for FetchJobResultAgent {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
+        Ok(Self {
+            data: AgentData::new(ma, id, spec),
+            contract,
+        })
+    }
+
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
+    }
+
+    async fn process(
+        &mut self,
+        ctx: AgentContext,
+        port: String,
+        value: AgentValue,
+    ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
+        if is_expired(&ctx) {
+            return self.output(self.traced(ctx), PORT_EXPIRED, value).await;
+        }
+        let configs = self.configs()?;
+        let request =
+            HttpRequest::from_configs(configs, METHOD_GET)?.render(&value, false, true)?;
+        let result_keys = key_path(&configs.get_string_or_default(CONFIG_RESULT_KEY));
+
+        let response = send_blocking(request).await?;
+        let result = get_nested_value(&response, &result_keys)
+            .cloned()
+            .ok_or_else(|| {
+                AgentError::InvalidValue(format!(
+                    "No result at '{}' in the response",
+                    result_keys.join(".")
+                ))
+            })?;
+        self.output(self.traced(ctx), PORT_RESULT, result).await
+    }
+}
+
+#[derive(Clone, Debug)]
+struct HttpRequest {
+    method: String,
+    url: String,
+    headers: Vec<(String, String)>,
+    body: String,
+    // the url with its placeholders unresolved, for errors
+    logged_url: String,
+}
+
+impl HttpRequest {
+    fn from_configs(configs: &AgentConfigs, default_method: &str) -> Result<Self, AgentError> {
+        let url = configs.get_string_or_default(CONFIG_URL);
+        if url.trim().is_empty() {
+            return Err(AgentError::InvalidConfig("url is not set".into()));
+        }
+        let method = configs
+            .get_string_or(CONFIG_METHOD, default_method)
+            .trim()
+            .to_uppercase();
+        let headers = configs
+            .get_object_or_default(CONFIG_HEADERS)
+            .iter()
+            .map(|(k, v)| {
+                let v = match v {
+                    AgentValue::String(s) => s.to_string(),
+                    v => v.to_json().to_string(),
+                };
+                (k.clone(), v)
+            })
+            .collect();
+        let body = configs.get_string_or_default(CONFIG_BODY);
+        Ok(Self {
+            method: if method.is_empty() {
+                default_method.to_string()
+            } else {
+                method
+            },
+            logged_url: url.clone(),
+            url,
+            headers,
+            body,
+        })
+    }
+
+    // Renders the templates with `value`, then resolves their placeholders when `resolved`.
+    // With `json_body`, an empty body is the value as JSON.
+    fn render(
+        &self,
+        value: &AgentValue,
+        json_body: bool,
+        resolved: bool,
+    ) -> Result<Self, AgentError> {
+        let reg = handlebars_new();
+        let mut url_reg = handlebars_new();
+        url_reg.register_escape_fn(percent_encode);
+        let render = |reg: &Handlebars, template: &str, resolved: bool| {
+            let (template, mut placeholders) = placeholder_template(template);
+            if resolved {
+                placeholders = placeholders
+                    .iter()
+                    .map(|p| resolve(p))
+                    .collect::<Result<_, _>>()?;
+            }
+            let data = serde_json::json!({ "value": value, "placeholders": placeholders });
+            reg.render_template(&template, &data)
+                .map_err(|e| AgentError::InvalidValue(format!("Failed to render template: {}", e)))
+        };
+        let body = if self.body.trim().is_empty() {
+            if json_body {
+                value.to_json().to_string()
+            } else {
+                String::new()
+            }
+        } else {
+            render(&reg, &self.body, resolved)?
+        };
+        Ok(Self {
+            method: self.method.clone(),
+            url: render(&url_reg, &self.url, resolved)?,
+            headers: self
+                .headers
+                .iter()
+                .map(|(k, v)| Ok((k.clone(), render(&reg, v, resolved)?)))
+                .collect::<Result<_, AgentError>>()?,
+            body,
+            logged_url: render(&url_reg, &self.url, false)?,
+        })
+    }
+
+    // Sends the request and returns the response, parsed as JSON when possible.
+    fn send(&self) -> Result<AgentValue, AgentError> {
+        let agent: ureq::Agent = ureq::Agent::config_builder()
+            .timeout_global(Some(HTTP_TIMEOUT))
+            .build()
+            .into();
+        let mut builder = ureq::http::Request::builder()
+            .method(self.method.as_str())
+            .uri(&self.url);
+        for (k, v) in &self.headers {
+            builder = builder.header(k, v);
+        }
+        if !self.body.is_empty()
+            && !self
+                .headers
+                .iter()
+                .any(|(k, _)| k.eq_ignore_ascii_case("content-type"))
+        {
+            builder = builder.header("content-type", "application/json");
+        }
+        let request = builder
+            .body(self.body.clone().into_bytes())
+            .map_err(|e| AgentError::InvalidConfig(format!("Invalid request: {}", e)))?;
+
+        let error = |e: ureq::Error| {
+            AgentError::InvalidValue(format!("{} {} failed: {}", self.method, self.logged_url, e))
+        };
+        let mut response = agent.run(request).map_err(error)?;
+        let text = response.body_mut().read_to_string().map_err(error)?;
+        Ok(serde_json::from_str::<serde_json::Value>(&text)
+            .ok()
+            .and_then(|json| AgentValue::from_json(json).ok())
+            .unwrap_or_else(|| AgentValue::string(text)))
+    }
+}
+
+// Percent-encodes all but the unreserved characters of RFC 3986.
+fn percent_encode(s: &str) -> String {
+    let mut encoded = String::with_capacity(s.len());
+    for b in s.bytes() {
+        if b.is_ascii_alphanumeric() || matches!(b, b'-' | b'.' | b'_' | b'~') {
+            encoded.push(b as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", b));
+        }
+    }
+    encoded
+}
+
+async fn send_blocking(request: HttpRequest) -> Result<AgentValue, AgentError> {
+    tokio::task::spawn_blocking(move || request.send())
+        .await
+        .map_err(|e| AgentError::InvalidValue(format!("Failed to send request: {}", e)))?
+}
+
+#[derive(Debug, PartialEq)]
+enum JobState {
+    Running,
+    Done,
+    Failed,
+}
+
+struct JobPoll {
+    status_keys: Vec<String>,
+    done: Vec<String>,
+    failed: Vec<String>,
+}
+
+impl JobPoll {
+    fn status(&self, response: &AgentValue) -> AgentValue {
+        get_nested_value(response, &self.status_keys)
+            .cloned()
+            .unwrap_or(AgentValue::Unit)
+    }
+
+    fn state(&self, response: &AgentValue) -> JobState {
+        let status = match self.status(response) {
+            AgentValue::String(s) => s.trim().to_lowercase(),
+            AgentValue::Unit => String::new(),
+            v => v.to_json().to_string(),
+        };
+        if self.done.contains(&status) {
+            JobState::Done
+        } else if self.failed.contains(&status) {
+            JobState::Failed
+        } else {
+            JobState::Running
+        }
+    }
+}
+
+fn key_path(key: &str) -> Vec<String> {
+    let key = key.trim();
+    if key.is_empty() {
+        return Vec::new();
+    }
+    key.split('.').map(|s| s.to_string()).collect()
+}
+
+fn parse_list(s: &str) -> Vec<String> {
+    s.split(',')
+        .map(|s| s.trim().to_lowercase())
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+
+    use im::hashmap;
+
+    use super::*;
+
+    // Serves `responses` in order, and returns the received requests.
+    fn serve(responses: Vec<&'static str>) -> (String, std::thread::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let mut requests = Vec::new();
+            for body in responses {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+                let mut request = String::new();
+                let mut content_length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if let Some(len) = line.to_lowercase().strip_prefix("content-length:") {
+                        content_length = len.trim().parse().unwrap();
+                    }
+                    if line == "\r\n" {
+                        break;
+                    }
+                    request.push_str(&line);
+                }
+                let mut content = vec![0; content_length];
+                reader.read_exact(&mut content).unwrap();
+                request.push_str(&String::from_utf8(content).unwrap());
+                requests.push(request);
+                write!(
+                    reader.get_mut(),
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                )
+                .unwrap();
+            }
+            requests
+        });
+        (url, server)
+    }
+
+    #[test]
+    fn test_http_request() {
+        let (url, server) = serve(vec![r#"{"job": {"id": "j1"}}"#, "plain"]);
+        let request = HttpRequest {
+            method: METHOD_POST.to_string(),
+            url: format!("{}/jobs?name={{{{value.name}}}}", url),
+            headers: vec![("x-token".to_string(), "t-{{value.name}}".to_string())],
+            body: String::new(),
+            logged_url: String::new(),
+        };
+        let value = AgentValue::object(hashmap! {"name".to_string() => AgentValue::string("a")});
+        let response = request.render(&value, true, true).unwrap().send().unwrap();
+        assert_eq!(
+            get_nested_value(&response, &["job", "id"]),
+            Some(&AgentValue::string("j1"))
+        );
+
+        let request = HttpRequest {
+            method: METHOD_GET.to_string(),
+            url: format!("{}/jobs/{{{{value.id}}}}", url),
+            headers: Vec::new(),
+            body: String::new(),
+            logged_url: String::new(),
+        };
+        let handle = AgentValue::object(
+            hashmap! {"id".to_string() => AgentValue::string("j/1 ${env:HOME}")},
+        );
+        let response = request
+            .render(&handle, false, true)
+            .unwrap()
+            .send()
+            .unwrap();
+        assert_eq!(response, AgentValue::string("plain"));
+
+        let requests = server.join().unwrap();
+        assert!(
+            requests[0].starts_with("POST /jobs?name=a "),
+            "{}",
+            requests[0]
+        );
+        assert!(requests[0].contains("x-token: t-a"));
+        assert!(requests[0].ends_with(r#"{"name":"a"}"#));
+        assert!(
+            requests[1].starts_with("GET /jobs/j%2F1%20%24%7Benv%3AHOME%7D "),
+            "{}",
+            requests[1]
+        );
+    }
+
+    #[test]
+    fn test_http_request_placeholders() {
+        let request = HttpRequest {
+            method: METHOD_GET.to_string(),
+            url: "http://${env:JOB_TEST_UNSET}/{{value.id}}/{{{value.id}}}".to_string(),
+            headers: Vec::new(),
+            body: "{{value.id}} ${env:JOB_TEST_UNSET}".to_string(),
+            logged_url: String::new(),
+        };
+        let handle =
+            AgentValue::object(hashmap! {"id".to_string() => AgentValue::string("a/{{b}}")});
+        // placeholders are resolved after rendering
+        assert!(request.render(&handle, false, true).is_err());
+
+        let logged = request.render(&handle, false, false).unwrap();
+        assert_eq!(
+            logged.url,
+            "http://${env:JOB_TEST_UNSET}/a%2F%7B%7Bb%7D%7D/a/{{b}}"
+        );
+        assert_eq!(logged.logged_url, logged.url);
+        assert_eq!(logged.body, "a/{{b}} ${env:JOB_TEST_UNSET}");
+    }
+
+    #[test]
+    fn test_job_state() {
+        let poll = JobPoll {
+            status_keys: key_path("job.state"),
+            done: parse_list(DONE_DEFAULT),
+            failed: parse_list(FAILED_DEFAULT),
+        };
+        let response = |state: &str| {
+            AgentValue::object(hashmap! {
+                "job".to_string() => AgentValue::object(hashmap! {
+                    "state".to_string() => AgentValue::string(state),
+                }),
+            })
+        };
+        assert_eq!(poll.state(&response("Succeeded")), JobState::Done);
+        assert_eq!(poll.state(&response("cancelled")), JobState::Failed);
+        assert_eq!(poll.state(&response("running")), JobState::Running);
+        assert_eq!(poll.state(&AgentValue::Unit), JobState::Running);
+    }
+}
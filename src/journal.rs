@@ -0,0 +1,778 @@
+//! Run journal.
+//!
+//! An Interval Timer or a Schedule Timer with a `journal` file records each run it
+//! triggers as a JSONL entry before sending it:
+//!
+//! `{time, run, job, event, payload_hash, payload}`
+//!
+//! The run is carried in the context of the flow, and a Run Done agent at the end of the
+//! pipeline records whether it `succeeded` or `failed`. Journal Query lists the recent
+//! runs of a journal with their status, so "did last night's job run?" can be answered
+//! from within the flow. A timer with `resume` set triggers again, when it first starts
+//! in the process, the runs of its job that started but never completed, with their
+//! original payload.
+//!
+//! Entries are appended by a single writer thread, so that timers never wait for the
+//! disk. A journal keeps every unfinished run and the last 10000 completed ones; older
+//! entries are compacted away as it grows.
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{LazyLock, Mutex, mpsc};
+
+use chrono::Utc;
+use im::hashmap;
+use modular_agent_core::{
+    Agent, AgentConfigs, AgentContext, AgentData, AgentError, AgentOutput, AgentSpec, AgentValue,
+    AgentValueMap, AsAgent, ModularAgent, async_trait, modular_agent,
+};
+
+use crate::audit::payload_hash;
+use crate::contract::{CONFIG_INPUT_CONTRACT, ContractAgent, InputContract, contract_agents};
+use crate::profile::ProfileConfigs;
+use crate::provenance::{Traced, stamp};
+use crate::time::parse_duration_to_ms;
+
+const CATEGORY: &str = "Std/Time";
+
+const PORT_FAILED: &str = "failed";
+const PORT_RUNS: &str = "runs";
+const PORT_SUCCEEDED: &str = "succeeded";
+const PORT_TRIGGER: &str = "trigger";
+const PORT_VALUE: &str = "value";
+
+pub(crate) const CONFIG_JOB: &str = "job";
+pub(crate) const CONFIG_JOURNAL: &str = "journal";
+pub(crate) const CONFIG_RESUME: &str = "resume";
+const CONFIG_LIMIT: &str = "limit";
+const CONFIG_SINCE: &str = "since";
+const CONFIG_STATUS: &str = "status";
+
+const EVENT_FAILED: &str = "failed";
+const EVENT_RESUMED: &str = "resumed";
+const EVENT_STARTED: &str = "started";
+const EVENT_SUCCEEDED: &str = "succeeded";
+
+const KEY_ENDED: &str = "ended";
+const KEY_ERROR: &str = "error";
+const KEY_EVENT: &str = "event";
+const KEY_JOB: &str = "job";
+const KEY_JOURNAL: &str = "journal";
+const KEY_PAYLOAD: &str = "payload";
+const KEY_PAYLOAD_HASH: &str = "payload_hash";
+const KEY_RESUMED: &str = "resumed";
+const KEY_RUN: &str = "run";
+const KEY_STARTED: &str = "started";
+const KEY_STATUS: &str = "status";
+const KEY_TIME: &str = "time";
+
+const VAR_RUN: &str = "run";
+
+const LIMIT_DEFAULT: i64 = 100;
+
+// completed runs kept by a compaction
+const KEEP_RUNS: usize = 10000;
+// appends to a journal between compactions
+const COMPACT_EVERY: usize = 1000;
+
+enum JournalWrite {
+    Append(PathBuf, AgentValue),
+    Flush(mpsc::Sender<()>),
+}
+
+// The writer thread appends the entries in the order they are sent, so that lines are
+// never interleaved, and compacts the journals.
+static WRITER: LazyLock<mpsc::Sender<JournalWrite>> = LazyLock::new(|| {
+    let (tx, rx) = mpsc::channel();
+    std::thread::spawn(move || {
+        let mut appends: HashMap<PathBuf, usize> = HashMap::new();
+        for write in rx {
+            match write {
+                JournalWrite::Append(path, entry) => {
+                    if let Err(e) = append_entry(&path, &entry) {
+                        log::error!("Failed to journal an entry: {}", e);
+                    }
+                    let count = appends.entry(path.clone()).or_default();
+                    *count += 1;
+                    if *count >= COMPACT_EVERY {
+                        *count = 0;
+                        if let Err(e) = compact(&path, KEEP_RUNS) {
+                            log::error!("Failed to compact journal {}: {}", path.display(), e);
+                        }
+                    }
+                }
+                JournalWrite::Flush(done) => {
+                    let _ = done.send(());
+                }
+            }
+        }
+    });
+    tx
+});
+
+// Sends the entry to the writer thread.
+fn send_entry(path: &Path, entry: AgentValue) {
+    if WRITER
+        .send(JournalWrite::Append(path.to_path_buf(), entry))
+        .is_err()
+    {
+        log::error!("Journal writer is gone");
+    }
+}
+
+// Waits until the entries sent so far are written. Blocks.
+fn flush() {
+    let (tx, rx) = mpsc::channel();
+    if WRITER.send(JournalWrite::Flush(tx)).is_ok() {
+        let _ = rx.recv();
+    }
+}
+
+fn append_entry(path: &Path, entry: &AgentValue) -> Result<(), AgentError> {
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent).map_err(|e| {
+            AgentError::IoError(format!("Failed to create journal directory: {}", e))
+        })?;
+    }
+    let mut f = fs::File::options()
+        .append(true)
+        .create(true)
+        .open(path)
+        .map_err(|e| {
+            AgentError::IoError(format!("Failed to open journal {}: {}", path.display(), e))
+        })?;
+    writeln!(f, "{}", entry.to_json()).map_err(|e| {
+        AgentError::IoError(format!("Failed to write journal {}: {}", path.display(), e))
+    })
+}
+
+fn read_journal(path: &Path) -> Result<String, AgentError> {
+    match fs::read_to_string(path) {
+        Ok(text) => Ok(text),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(String::new()),
+        Err(e) => Err(AgentError::IoError(format!(
+            "Failed to read journal {}: {}",
+            path.display(),
+            e
+        ))),
+    }
+}
+
+// The runs folded so far from each journal, to fold only the entries appended since.
+static FOLDED: LazyLock<Mutex<HashMap<PathBuf, FoldedRuns>>> = LazyLock::new(Default::default);
+
+// Calls `f` with the runs of the journal at `path`, folding the new entries first. Blocks.
+fn with_runs<T>(path: &Path, f: impl FnOnce(&[Run]) -> T) -> Result<T, AgentError> {
+    flush();
+    let io_error = |e: std::io::Error| {
+        AgentError::IoError(format!("Failed to read journal {}: {}", path.display(), e))
+    };
+    let mut folded = FOLDED.lock().unwrap_or_else(|e| e.into_inner());
+    let runs = folded.entry(path.to_path_buf()).or_default();
+    let mut file = match fs::File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            *runs = FoldedRuns::default();
+            return Ok(f(&runs.runs));
+        }
+        Err(e) => return Err(io_error(e)),
+    };
+    if file.metadata().map_err(io_error)?.len() < runs.offset {
+        // replaced by another process
+        *runs = FoldedRuns::default();
+    }
+    file.seek(SeekFrom::Start(runs.offset)).map_err(io_error)?;
+    let mut text = String::new();
+    file.read_to_string(&mut text).map_err(io_error)?;
+    // a line being written is folded the next time
+    let complete = text.rfind('\n').map_or(0, |i| i + 1);
+    runs.fold(&text[..complete]);
+    runs.offset += complete as u64;
+    Ok(f(&runs.runs))
+}
+
+// Rewrites the journal at `path` with the entries of the unfinished runs and of the last
+// `keep` completed runs, if there are more. Runs on the writer thread.
+fn compact(path: &Path, keep: usize) -> Result<(), AgentError> {
+    let text = read_journal(path)?;
+    let runs = fold_runs(&text);
+    let completed = runs
+        .iter()
+        .filter(|run| run.status != EVENT_STARTED)
+        .count();
+    if completed <= keep {
+        return Ok(());
+    }
+    let mut skip = completed - keep;
+    let mut dropped = HashSet::new();
+    for run in &runs {
+        if skip == 0 {
+            break;
+        }
+        if run.status != EVENT_STARTED {
+            dropped.insert(run.run.as_str());
+            skip -= 1;
+        }
+    }
+    let mut kept = String::new();
+    for line in text.lines() {
+        let run = serde_json::from_str::<serde_json::Value>(line)
+            .ok()
+            .and_then(|json| {
+                json.get(KEY_RUN)
+                    .and_then(|r| r.as_str())
+                    .map(|r| r.to_string())
+            });
+        if run.is_some_and(|run| !dropped.contains(run.as_str())) {
+            kept.push_str(line);
+            kept.push('\n');
+        }
+    }
+    let tmp = path.with_extension("compacting");
+    fs::write(&tmp, kept)
+        .and_then(|_| fs::rename(&tmp, path))
+        .map_err(|e| {
+            AgentError::IoError(format!(
+                "Failed to compact journal {}: {}",
+                path.display(),
+                e
+            ))
+        })?;
+    FOLDED
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .remove(path);
+    Ok(())
+}
+
+// The journals and jobs whose runs have been resumed in this process.
+static RESUMED_JOBS: LazyLock<Mutex<HashSet<(PathBuf, String)>>> = LazyLock::new(Default::default);
+
+/// The journal of the runs of a timer.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Journal {
+    path: PathBuf,
+    job: String,
+    resume: bool,
+}
+
+impl Journal {
+    /// Returns the journal set in the `journal`, `job` and `resume` configs, or None when
+    /// `journal` is blank.
+    pub(crate) fn from_configs(configs: &AgentConfigs) -> Result<Option<Self>, AgentError> {
+        let path = configs.get_string_resolved(CONFIG_JOURNAL)?;
+        if path.trim().is_empty() {
+            return Ok(None);
+        }
+        // Agent ids change each time the preset is loaded, so runs are kept by job name.
+        let job = configs.get_string_or_default(CONFIG_JOB).trim().to_string();
+        if job.is_empty() {
+            return Err(AgentError::InvalidConfig(
+                "job must be set to keep a journal".into(),
+            ));
+        }
+        Ok(Some(Self {
+            path: PathBuf::from(path.trim()),
+            job,
+            resume: configs.get_bool_or_default(CONFIG_RESUME),
+        }))
+    }
+
+    fn entry(
+        &self,
+        run: &str,
+        event: &str,
+        time: i64,
+        payload: &AgentValue,
+    ) -> AgentValueMap<String, AgentValue> {
+        hashmap! {
+            KEY_TIME.to_string() => AgentValue::integer(time),
+            KEY_RUN.to_string() => AgentValue::string(run),
+            KEY_JOB.to_string() => AgentValue::string(&self.job),
+            KEY_EVENT.to_string() => AgentValue::string(event),
+            KEY_PAYLOAD_HASH.to_string() => AgentValue::string(payload_hash(payload)),
+        }
+    }
+
+    fn run_ctx(&self, ctx: AgentContext, run: &str) -> AgentContext {
+        ctx.with_var(
+            VAR_RUN.to_string(),
+            AgentValue::object(hashmap! {
+                KEY_RUN.to_string() => AgentValue::string(run),
+                KEY_JOB.to_string() => AgentValue::string(&self.job),
+                KEY_JOURNAL.to_string() => AgentValue::string(self.path.to_string_lossy()),
+            }),
+        )
+    }
+
+    /// Records the start of a new run of `payload`, and returns the context carrying it.
+    ///
+    /// The payload is kept in the entry, so that the run can be triggered again. A run
+    /// that cannot be recorded is still triggered; missing a job is worse than missing
+    /// its entry.
+    pub(crate) fn start_run(&self, ctx: AgentContext, payload: &AgentValue) -> AgentContext {
+        let time = Utc::now().timestamp_millis();
+        let run = format!("{}-{}", time, ctx.id());
+        let mut entry = self.entry(&run, EVENT_STARTED, time, payload);
+        entry.insert(KEY_PAYLOAD.to_string(), payload.clone());
+        send_entry(&self.path, AgentValue::object(entry));
+        self.run_ctx(ctx, &run)
+    }
+
+    /// Records that the unfinished runs of the job are triggered again, and returns their
+    /// contexts and payloads, oldest first. Blocks.
+    fn resume_runs(&self) -> Result<Vec<(AgentContext, AgentValue)>, AgentError> {
+        let unfinished: Vec<(String, AgentValue)> = with_runs(&self.path, |runs| {
+            runs.iter()
+                .filter(|run| run.job == self.job && run.status == EVENT_STARTED)
+                .map(|run| {
+                    let payload = run.payload.clone().unwrap_or_else(AgentValue::unit);
+                    (run.run.clone(), payload)
+                })
+                .collect()
+        })?;
+        let time = Utc::now().timestamp_millis();
+        let mut resumed = Vec::new();
+        for (run, payload) in unfinished {
+            let entry = self.entry(&run, EVENT_RESUMED, time, &payload);
+            send_entry(&self.path, AgentValue::object(entry));
+            resumed.push((self.run_ctx(AgentContext::new(), &run), payload));
+        }
+        Ok(resumed)
+    }
+
+    /// Triggers again on `port` of the agent the runs that started but never completed,
+    /// if `resume` is set.
+    ///
+    /// Only the first start of the job in the process resumes: the runs it starts after
+    /// that are in flight, not lost.
+    pub(crate) fn spawn_resume(
+        &self,
+        ma: &ModularAgent,
+        agent_id: &str,
+        def_name: &str,
+        port: &str,
+    ) {
+        let first = RESUMED_JOBS
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert((self.path.clone(), self.job.clone()));
+        if !self.resume || !first {
+            return;
+        }
+        let journal = self.clone();
+        let ma = ma.clone();
+        let agent_id = agent_id.to_string();
+        let def_name = def_name.to_string();
+        let port = port.to_string();
+        tokio::spawn(async move {
+            let runs = match tokio::task::spawn_blocking(move || journal.resume_runs()).await {
+                Ok(Ok(runs)) => runs,
+                Ok(Err(e)) => {
+                    log::error!("Failed to resume runs: {}", e);
+                    return;
+                }
+                Err(e) => {
+                    log::error!("Failed to resume runs: {}", e);
+                    return;
+                }
+            };
+            for (ctx, payload) in runs {
+                if let Err(e) = ma
+                    .send_agent_out(
+                        agent_id.clone(),
+                        stamp(ctx, &agent_id, &def_name),
+                        port.clone(),
+                        payload,
+                    )
+                    .await
+                {
+                    log::error!("Failed to send resumed run: {}", e);
+                }
+            }
+        });
+    }
+}
+
+/// A run folded from the entries of a journal.
+#[derive(Debug)]
+struct Run {
+    run: String,
+    job: String,
+    started: Option<i64>,
+    ended: Option<i64>,
+    status: String,
+    payload_hash: String,
+    payload: Option<AgentValue>,
+    resumed: i64,
+    error: Option<String>,
+}
+
+impl Run {
+    fn to_value(&self) -> AgentValue {
+        let mut value = hashmap! {
+            KEY_RUN.to_string() => AgentValue::string(&self.run),
+            KEY_JOB.to_string() => AgentValue::string(&self.job),
+            KEY_STATUS.to_string() => AgentValue::string(&self.status),
+            KEY_PAYLOAD_HASH.to_string() => AgentValue::string(&self.payload_hash),
+            KEY_RESUMED.to_string() => AgentValue::integer(self.resumed),
+        };
+        if let Some(started) = self.started {
+            value.insert(KEY_STARTED.to_string(), AgentValue::integer(started));
+        }
+        if let Some(ended) = self.ended {
+            value.insert(KEY_ENDED.to_string(), AgentValue::integer(ended));
+        }
+        if let Some(error) = &self.error {
+            value.insert(KEY_ERROR.to_string(), AgentValue::string(error));
+        }
+        AgentValue::object(value)
+    }
+}
+
+/// Runs folded from the first `offset` bytes of a journal.
+#[derive(Default)]
+struct FoldedRuns {
+    offset: u64,
+    runs: Vec<Run>,
+    // position of each run in `runs`
+    index: HashMap<String, usize>,
+}
+
+impl FoldedRuns {
+    /// Folds the entries of the JSONL `text` into the runs, in the order they started.
+    /// The first end of a run counts; broken lines are skipped.
+    fn fold(&mut self, text: &str) {
+        let Self { runs, index, .. } = self;
+        let entries = text
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .filter_map(|line| serde_json::from_str::<serde_json::Value>(line).ok())
+            .filter_map(|json| AgentValue::from_json(json).ok());
+        for entry in entries {
+            let (Some(id), Some(event)) = (entry.get_str(KEY_RUN), entry.get_str(KEY_EVENT)) else {
+                continue;
+            };
+            let time = entry.get(KEY_TIME).and_then(|t| t.as_i64());
+            let i = *index.entry(id.to_string()).or_insert_with(|| {
+                runs.push(Run {
+                    run: id.to_string(),
+                    job: entry.get_str(KEY_JOB).unwrap_or_default().to_string(),
+                    started: None,
+                    ended: None,
+                    status: EVENT_STARTED.to_string(),
+                    payload_hash: String::new(),
+                    payload: None,
+                    resumed: 0,
+                    error: None,
+                });
+                runs.len() - 1
+            });
+            let run = &mut runs[i];
+            match event {
+                EVENT_STARTED => {
+                    run.started = time;
+                    run.payload_hash = entry
+                        .get_str(KEY_PAYLOAD_HASH)
+                        .unwrap_or_default()
+                        .to_string();
+                    run.payload = entry.get(KEY_PAYLOAD).cloned();
+                }
+                EVENT_RESUMED => run.resumed += 1,
+                EVENT_SUCCEEDED | EVENT_FAILED if run.status == EVENT_STARTED => {
+                    run.ended = time;
+                    run.status = event.to_string();
+                    run.error = entry.get_str(KEY_ERROR).map(|s| s.to_string());
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+// Folds the whole JSONL `text` into runs.
+fn fold_runs(text: &str) -> Vec<Run> {
+    let mut folded = FoldedRuns::default();
+    folded.fold(text);
+    folded.runs
+}
+
+/// Returns the error message of a failure value: the value itself if it is a string, or
+/// its `error` field.
+fn error_message(value: &AgentValue) -> Option<String> {
+    match value {
+        AgentValue::String(s) => Some(s.to_string()),
+        _ => value.get_str(KEY_ERROR).map(|s| s.to_string()),
+    }
+}
+
+contract_agents!(RunDoneAgent);
+
+/// Records the end of the journaled run of the flow, and passes the value on.
+///
+/// Put it at the end of a pipeline triggered by a timer with a `journal`: a value on
+/// `succeeded` or `failed` completes the run carried in its context. The first end of a
+/// run counts. Values outside of a journaled run are passed on unrecorded.
+#[modular_agent(
+    title = "Run Done",
+    category = CATEGORY,
+    inputs = [PORT_SUCCEEDED, PORT_FAILED],
+    outputs = [PORT_VALUE],
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
+    hint(color=2),
+)]
+struct RunDoneAgent {
+    data: AgentData,
+    contract: InputContract,
+}
+
+#[async_trait]
+impl AsAgent for RunDoneAgent {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
+        Ok(Self {
+            data: AgentData::new(ma, id, spec),
+            contract,
+        })
+    }
+
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
+    }
+
+    async fn process(
+        &mut self,
+        ctx: AgentContext,
+        port: String,
+        value: AgentValue,
+    ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
+        let event = match port.as_str() {
+            PORT_SUCCEEDED => EVENT_SUCCEEDED,
+            PORT_FAILED => EVENT_FAILED,
+            _ => return Err(AgentError::InvalidPin(port)),
+        };
+        let run = ctx.get_var(VAR_RUN);
+        let field = |key: &str| run.and_then(|r| r.get_str(key)).unwrap_or_default();
+        let (id, job, path) = (field(KEY_RUN), field(KEY_JOB), field(KEY_JOURNAL));
+        if id.is_empty() || path.is_empty() {
+            log::warn!("Run Done got a value outside of a journaled run");
+        } else {
+            let journal = Journal {
+                path: PathBuf::from(path),
+                job: job.to_string(),
+                resume: false,
+            };
+            let mut entry = journal.entry(id, event, Utc::now().timestamp_millis(), &value);
+            if event == EVENT_FAILED
+                && let Some(error) = error_message(&value)
+            {
+                entry.insert(KEY_ERROR.to_string(), AgentValue::string(error));
+            }
+            send_entry(&journal.path, AgentValue::object(entry));
+        }
+        self.output(self.traced(ctx), PORT_VALUE, value).await
+    }
+}
+
+/// Lists the recent runs of a journal.
+///
+/// On any value on `trigger`, outputs the last `limit` (0: all) runs of the journal at
+/// `path` as an array on `runs`, in the order they started:
+/// `{run, job, status, started, ended, payload_hash, resumed, error}`. `status` is
+/// `started` for the runs that have not completed (still running, or lost in a crash),
+/// `succeeded` or `failed`. Blank filters match any run; `since` (ex. `1d`) keeps the
+/// runs started recently.
+#[modular_agent(
+    title = "Journal Query",
+    category = CATEGORY,
+    inputs = [PORT_TRIGGER],
+    outputs = [PORT_RUNS],
+    string_config(name = CONFIG_JOURNAL),
+    string_config(name = CONFIG_JOB),
+    string_config(name = CONFIG_STATUS, description = "started, succeeded, failed; empty: all"),
+    string_config(name = CONFIG_SINCE, description = "(ex. 1h, 1d) empty: all"),
+    integer_config(name = CONFIG_LIMIT, default = LIMIT_DEFAULT, description = "0: all"),
+    hint(color=2),
+)]
+struct JournalQueryAgent {
+    data: AgentData,
+}
+
+#[async_trait]
+impl AsAgent for JournalQueryAgent {
+    fn new(ma: ModularAgent, id: String, spec: AgentSpec) -> Result<Self, AgentError> {
+        Ok(Self {
+            data: AgentData::new(ma, id, spec),
+        })
+    }
+
+    async fn process(
+        &mut self,
+        ctx: AgentContext,
+        _port: String,
+        _value: AgentValue,
+    ) -> Result<(), AgentError> {
+        let configs = self.configs()?;
+        let path = configs.get_string_resolved(CONFIG_JOURNAL)?;
+        if path.trim().is_empty() {
+            return Err(AgentError::InvalidConfig("journal is not set".into()));
+        }
+        let job = configs.get_string_or_default(CONFIG_JOB);
+        let status = configs.get_string_or_default(CONFIG_STATUS);
+        let since = configs.get_string_or_default(CONFIG_SINCE);
+        let since = if since.trim().is_empty() {
+            None
+        } else {
+            Some(Utc::now().timestamp_millis() - parse_duration_to_ms(&since)? as i64)
+        };
+        let limit = configs.get_integer_or(CONFIG_LIMIT, LIMIT_DEFAULT).max(0) as usize;
+
+        let path = PathBuf::from(path.trim());
+        let runs = tokio::task::spawn_blocking(move || {
+            with_runs(&path, |runs| {
+                let mut runs: Vec<AgentValue> = runs
+                    .iter()
+                    .filter(|run| job.trim().is_empty() || run.job == job.trim())
+                    .filter(|run| status.trim().is_empty() || run.status == status.trim())
+                    .filter(|run| since.is_none_or(|since| run.started.is_some_and(|t| t >= since)))
+                    .map(|run| run.to_value())
+                    .collect();
+                if limit > 0 && runs.len() > limit {
+                    runs.drain(..runs.len() - limit);
+                }
+                runs
+            })
+        })
+        .await
+        .map_err(|e| AgentError::IoError(format!("Failed to query journal: {}", e)))??;
+        self.output(self.traced(ctx), PORT_RUNS, AgentValue::array(runs.into()))
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_journal_runs() {
+        let path = std::env::temp_dir().join(format!(
+            "modular_agent_std_journal_{}.jsonl",
+            std::process::id()
+        ));
+        let _ = fs::remove_file(&path);
+
+        let journal = Journal {
+            path: path.clone(),
+            job: "nightly".to_string(),
+            resume: true,
+        };
+        let other = Journal {
+            job: "hourly".to_string(),
+            ..journal.clone()
+        };
+        let run_id = |ctx: &AgentContext| {
+            ctx.get_var(VAR_RUN)
+                .and_then(|r| r.get_str(KEY_RUN))
+                .unwrap()
+                .to_string()
+        };
+        let done = run_id(&journal.start_run(AgentContext::new(), &AgentValue::integer(1)));
+        let crashed = run_id(&journal.start_run(AgentContext::new(), &AgentValue::integer(2)));
+        let _ = other.start_run(AgentContext::new(), &AgentValue::unit());
+        flush();
+        let end = |run: &str, event: &str, time: i64| {
+            let mut entry = journal.entry(run, event, time, &AgentValue::unit());
+            entry.insert(KEY_ERROR.to_string(), AgentValue::string("boom"));
+            append_entry(&path, &AgentValue::object(entry)).unwrap();
+        };
+        end(&done, EVENT_FAILED, 5);
+        end(&done, EVENT_SUCCEEDED, 6);
+
+        let mut text = fs::read_to_string(&path).unwrap();
+        text.push_str("not json\n");
+        let runs = fold_runs(&text);
+        assert_eq!(runs.len(), 3);
+        assert_eq!(runs[0].status, EVENT_FAILED);
+        assert_eq!(runs[0].ended, Some(5));
+        assert_eq!(runs[0].error.as_deref(), Some("boom"));
+        assert_eq!(runs[0].payload_hash, payload_hash(&AgentValue::integer(1)));
+        assert_eq!(runs[1].status, EVENT_STARTED);
+        assert_eq!(runs[2].job, "hourly");
+
+        // Only the unfinished run of the job is resumed, with its run id and payload.
+        let resumed = journal.resume_runs().unwrap();
+        assert_eq!(resumed.len(), 1);
+        assert_eq!(run_id(&resumed[0].0), crashed);
+        assert_eq!(resumed[0].1, AgentValue::integer(2));
+        let (resumed, status) =
+            with_runs(&path, |runs| (runs[1].resumed, runs[1].status.clone())).unwrap();
+        assert_eq!(resumed, 1);
+        assert_eq!(status, EVENT_STARTED);
+
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_journal_compact() {
+        let path = std::env::temp_dir().join(format!(
+            "modular_agent_std_journal_compact_{}.jsonl",
+            std::process::id()
+        ));
+        let _ = fs::remove_file(&path);
+
+        let journal = Journal {
+            path: path.clone(),
+            job: "nightly".to_string(),
+            resume: false,
+        };
+        let unfinished = journal.start_run(AgentContext::new(), &AgentValue::integer(0));
+        let unfinished = unfinished
+            .get_var(VAR_RUN)
+            .unwrap()
+            .get_str(KEY_RUN)
+            .unwrap();
+        for i in 1..=3 {
+            let run = format!("run{}", i);
+            let entry = journal.entry(&run, EVENT_STARTED, i, &AgentValue::unit());
+            send_entry(&path, AgentValue::object(entry));
+            let entry = journal.entry(&run, EVENT_SUCCEEDED, i, &AgentValue::unit());
+            send_entry(&path, AgentValue::object(entry));
+        }
+
+        // the new entries are folded onto the cached runs
+        assert_eq!(with_runs(&path, |runs| runs.len()).unwrap(), 4);
+        let entry = journal.entry("run4", EVENT_STARTED, 4, &AgentValue::unit());
+        send_entry(&path, AgentValue::object(entry));
+        assert_eq!(with_runs(&path, |runs| runs.len()).unwrap(), 5);
+
+        // the unfinished runs and the last completed ones are kept
+        flush();
+        compact(&path, 2).unwrap();
+        let runs: Vec<String> = with_runs(&path, |runs| {
+            runs.iter().map(|run| run.run.clone()).collect()
+        })
+        .unwrap();
+        assert_eq!(runs, vec![unfinished, "run2", "run3", "run4"]);
+
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_journal_configs() {
+        let mut configs = AgentConfigs::new();
+        configs.set(CONFIG_JOURNAL.into(), AgentValue::string(""));
+        assert_eq!(Journal::from_configs(&configs).unwrap(), None);
+        configs.set(CONFIG_JOURNAL.into(), AgentValue::string("runs.jsonl"));
+        assert!(Journal::from_configs(&configs).is_err());
+        configs.set(CONFIG_JOB.into(), AgentValue::string(" nightly "));
+        let journal = Journal::from_configs(&configs).unwrap().unwrap();
+        assert_eq!(journal.job, "nightly");
+        assert!(!journal.resume);
+    }
+}
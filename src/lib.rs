@@ -1,18 +1,56 @@
 #![recursion_limit = "256"]
 
 pub mod array;
+pub mod audit;
+pub mod bytes;
+pub mod checkpoint;
 pub mod data;
 pub mod display;
 pub mod file;
+pub mod flow;
 pub mod input;
+pub mod iot;
+pub mod journal;
+pub mod math;
+pub mod meta;
+pub mod notify;
+pub mod quota;
 pub mod sequence;
+pub mod state;
 pub mod string;
+pub mod system;
+pub mod tenant;
 pub mod time;
 pub mod ui;
 pub mod utils;
+pub mod vars;
+
+mod condition;
+mod contract;
+mod control;
+mod expr;
+mod profile;
+mod provenance;
+mod scheduler;
+mod supervisor;
+
+#[cfg(feature = "capture")]
+pub mod capture;
 
 #[cfg(feature = "image")]
 pub mod image;
 
+#[cfg(feature = "http")]
+pub mod job;
+
+#[cfg(feature = "python")]
+pub mod python;
+
+#[cfg(feature = "script")]
+pub mod script;
+
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
 #[cfg(feature = "yaml")]
 pub mod yaml;
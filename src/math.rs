@@ -0,0 +1,607 @@
+use modular_agent_core::{
+    Agent, AgentContext, AgentData, AgentError, AgentOutput, AgentSpec, AgentValue, AsAgent,
+    ModularAgent, async_trait, modular_agent,
+};
+
+use std::sync::{Arc, Mutex};
+
+use tokio::time::{Duration, Instant};
+
+use crate::bytes::value_to_bytes;
+use crate::contract::{CONFIG_INPUT_CONTRACT, ContractAgent, InputContract, contract_agents};
+use crate::provenance::{Traced, stamp};
+use crate::scheduler::{Timer, schedule};
+use crate::time::parse_duration_to_ms;
+
+const CATEGORY: &str = "Std/Math";
+
+const PORT_BYTES: &str = "bytes";
+const PORT_CLEAR: &str = "clear";
+const PORT_RAISE: &str = "raise";
+const PORT_RESET: &str = "reset";
+const PORT_VALUE: &str = "value";
+
+const CONFIG_DIRECTION: &str = "direction";
+const CONFIG_ENDIAN: &str = "endian";
+const CONFIG_HYSTERESIS: &str = "hysteresis";
+const CONFIG_MIN_DURATION: &str = "min_duration";
+const CONFIG_OFFSET: &str = "offset";
+const CONFIG_OP: &str = "op";
+const CONFIG_OPERAND: &str = "operand";
+const CONFIG_RATE: &str = "rate";
+const CONFIG_THRESHOLD: &str = "threshold";
+const CONFIG_TYPE: &str = "type";
+
+const DIRECTION_ABOVE: &str = "above";
+const DIRECTION_BELOW: &str = "below";
+const ENDIAN_BIG: &str = "big";
+const ENDIAN_LITTLE: &str = "little";
+const OP_AND: &str = "and";
+const OP_NOT: &str = "not";
+const OP_OR: &str = "or";
+const OP_SHL: &str = "shl";
+const OP_SHR: &str = "shr";
+const OP_XOR: &str = "xor";
+const TYPE_DEFAULT: &str = "u16";
+
+fn bitwise(op: &str, value: i64, operand: i64) -> Result<i64, AgentError> {
+    let shift = || {
+        u32::try_from(operand)
+            .ok()
+            .filter(|n| *n < 64)
+            .ok_or_else(|| AgentError::InvalidConfig(format!("Invalid shift: {}", operand)))
+    };
+    match op.trim() {
+        OP_AND => Ok(value & operand),
+        OP_OR => Ok(value | operand),
+        OP_XOR => Ok(value ^ operand),
+        OP_NOT => Ok(!value),
+        OP_SHL => Ok(value << shift()?),
+        // logical, so the sign bit is not extended
+        OP_SHR => Ok(((value as u64) >> shift()?) as i64),
+        other => Err(AgentError::InvalidConfig(format!("Unknown op: {}", other))),
+    }
+}
+
+contract_agents!(BitwiseAgent, DecodeBytesAgent, AlarmAgent);
+
+/// Applies a bitwise operation to an integer.
+///
+/// `op` is one of `and`, `or`, `xor` (with `operand`), `not`, `shl` and `shr` (shifted by
+/// `operand` bits). `shr` shifts in zeros. `operand` may be written in hex (`0xff`) or
+/// binary (`0b1010`).
+#[modular_agent(
+    title = "Bitwise",
+    category = CATEGORY,
+    inputs = [PORT_VALUE],
+    outputs = [PORT_VALUE],
+    string_config(name = CONFIG_OP, default = OP_AND, description = "and, or, xor, not, shl, shr"),
+    string_config(name = CONFIG_OPERAND, default = "0"),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
+)]
+struct BitwiseAgent {
+    data: AgentData,
+    contract: InputContract,
+}
+
+fn parse_integer(s: &str) -> Result<i64, AgentError> {
+    let s = s.trim().replace('_', "");
+    let parsed = if let Some(hex) = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        u64::from_str_radix(hex, 16).map(|n| n as i64)
+    } else if let Some(bin) = s.strip_prefix("0b").or_else(|| s.strip_prefix("0B")) {
+        u64::from_str_radix(bin, 2).map(|n| n as i64)
+    } else {
+        s.parse::<i64>()
+    };
+    parsed.map_err(|_| AgentError::InvalidConfig(format!("Invalid integer: {}", s)))
+}
+
+#[async_trait]
+impl AsAgent for BitwiseAgent {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
+        Ok(Self {
+            data: AgentData::new(ma, id, spec),
+            contract,
+        })
+    }
+
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
+    }
+
+    async fn process(
+        &mut self,
+        ctx: AgentContext,
+        port: String,
+        value: AgentValue,
+    ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
+        let config = self.configs()?;
+        let op = config.get_string_or(CONFIG_OP, OP_AND);
+        let operand = parse_integer(&config.get_string_or(CONFIG_OPERAND, "0"))?;
+        let Some(n) = value.as_i64() else {
+            return Err(AgentError::InvalidValue(
+                "Input value must be an integer".into(),
+            ));
+        };
+        let result = bitwise(&op, n, operand)?;
+        self.output(self.traced(ctx), PORT_VALUE, AgentValue::integer(result))
+            .await
+    }
+}
+
+/// Decodes a number of `type` from `bytes` at `offset`, in `endian` byte order.
+fn decode_number(
+    bytes: &[u8],
+    offset: usize,
+    ty: &str,
+    little: bool,
+) -> Result<AgentValue, AgentError> {
+    let size = match ty {
+        "u8" | "i8" => 1,
+        "u16" | "i16" => 2,
+        "u32" | "i32" | "f32" => 4,
+        "u64" | "i64" | "f64" => 8,
+        other => {
+            return Err(AgentError::InvalidConfig(format!(
+                "Unknown type: {}",
+                other
+            )));
+        }
+    };
+    let Some(field) = offset
+        .checked_add(size)
+        .and_then(|end| bytes.get(offset..end))
+    else {
+        return Err(AgentError::InvalidValue(format!(
+            "{} bytes at offset {} are out of {} bytes",
+            size,
+            offset,
+            bytes.len()
+        )));
+    };
+    let mut buf = [0u8; 8];
+    buf[..size].copy_from_slice(field);
+    if !little {
+        buf[..size].reverse();
+    }
+    // buf now holds the bytes in little endian order
+    let value = match ty {
+        "u8" => AgentValue::integer(buf[0] as i64),
+        "i8" => AgentValue::integer(buf[0] as i8 as i64),
+        "u16" => AgentValue::integer(u16::from_le_bytes([buf[0], buf[1]]) as i64),
+        "i16" => AgentValue::integer(i16::from_le_bytes([buf[0], buf[1]]) as i64),
+        "u32" => AgentValue::integer(u32::from_le_bytes(buf[..4].try_into().unwrap()) as i64),
+        "i32" => AgentValue::integer(i32::from_le_bytes(buf[..4].try_into().unwrap()) as i64),
+        "f32" => AgentValue::number(f32::from_le_bytes(buf[..4].try_into().unwrap()) as f64),
+        "u64" => {
+            let n = u64::from_le_bytes(buf);
+            let n = i64::try_from(n).map_err(|_| {
+                AgentError::InvalidValue(format!("u64 value {} does not fit in an integer", n))
+            })?;
+            AgentValue::integer(n)
+        }
+        "i64" => AgentValue::integer(i64::from_le_bytes(buf)),
+        _ => AgentValue::number(f64::from_le_bytes(buf)),
+    };
+    Ok(value)
+}
+
+/// Decodes a number from a binary value.
+///
+/// Reads `type` (`u8`, `i8`, `u16`, `i16`, `u32`, `i32`, `u64`, `i64`, `f32`, `f64`) at
+/// `offset` bytes, in `endian` (`big` or `little`) byte order. Integers are output as
+/// integers, and floats as numbers.
+#[modular_agent(
+    title = "Decode Bytes",
+    category = CATEGORY,
+    inputs = [PORT_BYTES],
+    outputs = [PORT_VALUE],
+    string_config(name = CONFIG_TYPE, default = TYPE_DEFAULT, description = "u8, i8, u16, i16, u32, i32, u64, i64, f32, f64"),
+    integer_config(name = CONFIG_OFFSET),
+    string_config(name = CONFIG_ENDIAN, default = ENDIAN_BIG, description = "big, little"),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
+)]
+struct DecodeBytesAgent {
+    data: AgentData,
+    contract: InputContract,
+}
+
+#[async_trait]
+impl AsAgent for DecodeBytesAgent {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
+        Ok(Self {
+            data: AgentData::new(ma, id, spec),
+            contract,
+        })
+    }
+
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
+    }
+
+    async fn process(
+        &mut self,
+        ctx: AgentContext,
+        port: String,
+        value: AgentValue,
+    ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
+        let config = self.configs()?;
+        let ty = config.get_string_or(CONFIG_TYPE, TYPE_DEFAULT);
+        let offset = config.get_integer_or_default(CONFIG_OFFSET);
+        let little = match config.get_string_or(CONFIG_ENDIAN, ENDIAN_BIG).trim() {
+            "" | ENDIAN_BIG => false,
+            ENDIAN_LITTLE => true,
+            other => {
+                return Err(AgentError::InvalidConfig(format!(
+                    "Unknown endian: {}",
+                    other
+                )));
+            }
+        };
+        let offset = usize::try_from(offset)
+            .map_err(|_| AgentError::InvalidConfig("offset must not be negative".into()))?;
+
+        let bytes = value_to_bytes(&value)?;
+        let number = decode_number(&bytes, offset, ty.trim(), little)?;
+        self.output(self.traced(ctx), PORT_VALUE, number).await
+    }
+}
+
+#[derive(Clone)]
+struct AlarmConfig {
+    threshold: f64,
+    hysteresis: f64,
+    below: bool,
+    min_duration: Duration,
+    rate: bool,
+}
+
+#[derive(Default)]
+struct AlarmState {
+    active: bool,
+    // when the value started to ask for the other state
+    pending_since: Option<Instant>,
+    // the previous value and its time, for the rate
+    prev: Option<(f64, Instant)>,
+    // the last compared value (the rate when `rate` is set)
+    last: f64,
+}
+
+impl AlarmState {
+    /// Feeds a value at `now`. Returns `Some(true)` when the alarm is raised, and
+    /// `Some(false)` when it is cleared.
+    fn update(&mut self, config: &AlarmConfig, value: f64, now: Instant) -> Option<bool> {
+        let x = if config.rate {
+            let prev = self.prev.replace((value, now));
+            let (prev_value, prev_time) = prev?;
+            let secs = now.duration_since(prev_time).as_secs_f64();
+            if secs <= 0.0 {
+                return None;
+            }
+            (value - prev_value) / secs
+        } else {
+            value
+        };
+        self.last = x;
+
+        // the alarm is raised beyond the threshold, and cleared only after the value is
+        // back past the threshold by the hysteresis
+        let (beyond, back) = if config.below {
+            (
+                x < config.threshold,
+                x > config.threshold + config.hysteresis,
+            )
+        } else {
+            (
+                x > config.threshold,
+                x < config.threshold - config.hysteresis,
+            )
+        };
+        let wants_change = if self.active { back } else { beyond };
+        if !wants_change {
+            self.pending_since = None;
+            return None;
+        }
+        self.pending_since.get_or_insert(now);
+        self.expire(config, now)
+    }
+
+    /// Changes the state when a pending change has lasted `min_duration` at `now`.
+    fn expire(&mut self, config: &AlarmConfig, now: Instant) -> Option<bool> {
+        if now < self.pending_deadline(config)? {
+            return None;
+        }
+        self.pending_since = None;
+        self.active = !self.active;
+        Some(self.active)
+    }
+
+    /// When a pending change is due.
+    fn pending_deadline(&self, config: &AlarmConfig) -> Option<Instant> {
+        self.pending_since.map(|since| since + config.min_duration)
+    }
+}
+
+/// Raises an alarm when a number crosses a threshold, and clears it when the number is
+/// back.
+///
+/// With `direction` `above`, the alarm is raised when the value is above `threshold`,
+/// and cleared when it is below `threshold - hysteresis` (`below` mirrors it), so noise
+/// around the threshold does not make it flap. The value has to stay past the threshold
+/// for `min duration` (e.g. `5s`, blank: none) before the state changes, even when no
+/// further value arrives. When `rate` is set, the rate of change per second is compared
+/// instead of the value.
+///
+/// The triggering value (or its rate, with `rate`) is output on `raise` or `clear`. A
+/// value on `reset` clears the state without output.
+#[modular_agent(
+    title = "Alarm",
+    category = CATEGORY,
+    inputs = [PORT_VALUE, PORT_RESET],
+    outputs = [PORT_RAISE, PORT_CLEAR],
+    number_config(name = CONFIG_THRESHOLD),
+    number_config(name = CONFIG_HYSTERESIS),
+    string_config(name = CONFIG_DIRECTION, default = DIRECTION_ABOVE, description = "above, below"),
+    string_config(name = CONFIG_MIN_DURATION, title = "min duration"),
+    boolean_config(name = CONFIG_RATE, description = "compare the rate of change per second"),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
+    hint(color=2),
+)]
+struct AlarmAgent {
+    data: AgentData,
+    state: Arc<Mutex<AlarmState>>,
+    // changes the state when a pending change lasts `min duration`
+    timer: Option<Timer>,
+    contract: InputContract,
+}
+
+impl AlarmAgent {
+    fn alarm_config(&self) -> Result<AlarmConfig, AgentError> {
+        let config = self.configs()?;
+        let below = match config
+            .get_string_or(CONFIG_DIRECTION, DIRECTION_ABOVE)
+            .trim()
+        {
+            "" | DIRECTION_ABOVE => false,
+            DIRECTION_BELOW => true,
+            other => {
+                return Err(AgentError::InvalidConfig(format!(
+                    "Unknown direction: {}",
+                    other
+                )));
+            }
+        };
+        let min_duration = config.get_string_or_default(CONFIG_MIN_DURATION);
+        let min_duration = if min_duration.trim().is_empty() {
+            Duration::ZERO
+        } else {
+            Duration::from_millis(parse_duration_to_ms(&min_duration)?)
+        };
+        let hysteresis = config.get_number_or(CONFIG_HYSTERESIS, 0.0);
+        if hysteresis < 0.0 {
+            return Err(AgentError::InvalidConfig(
+                "hysteresis must not be negative".into(),
+            ));
+        }
+        Ok(AlarmConfig {
+            threshold: config.get_number_or(CONFIG_THRESHOLD, 0.0),
+            hysteresis,
+            below,
+            min_duration,
+            rate: config.get_bool_or_default(CONFIG_RATE),
+        })
+    }
+
+    // Arms the timer of a pending change, to output `value` when it is due.
+    fn arm_timer(&mut self, config: AlarmConfig, ctx: AgentContext, value: AgentValue) {
+        let Some(deadline) = self.state.lock().unwrap().pending_deadline(&config) else {
+            self.timer = None;
+            return;
+        };
+        let state = self.state.clone();
+        let ma = self.ma().clone();
+        let agent_id = self.id().to_string();
+        let def_name = self.def_name().to_string();
+        let mut data = Some((ctx, value));
+        self.timer = Some(schedule(self, deadline, move |now| {
+            let port = match state.lock().unwrap().expire(&config, now)? {
+                true => PORT_RAISE,
+                false => PORT_CLEAR,
+            };
+            let (ctx, value) = data.take()?;
+            if let Err(e) = ma.try_send_agent_out(
+                agent_id.clone(),
+                stamp(ctx, &agent_id, &def_name),
+                port.to_string(),
+                value,
+            ) {
+                log::error!("Failed to send alarm: {}", e);
+            }
+            None
+        }));
+    }
+
+    fn reset(&mut self) {
+        self.timer = None;
+        *self.state.lock().unwrap() = AlarmState::default();
+    }
+}
+
+#[async_trait]
+impl AsAgent for AlarmAgent {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
+        Ok(Self {
+            data: AgentData::new(ma, id, spec),
+            state: Default::default(),
+            timer: None,
+            contract,
+        })
+    }
+
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
+    }
+
+    async fn stop(&mut self) -> Result<(), AgentError> {
+        // Dropping the timer cancels it
+        self.reset();
+        Ok(())
+    }
+
+    async fn process(
+        &mut self,
+        ctx: AgentContext,
+        port: String,
+        value: AgentValue,
+    ) -> Result<(), AgentError> {
+        match port.as_str() {
+            PORT_VALUE => {
+                let Some(value) = self.check_input(&ctx, &port, value).await? else {
+                    return Ok(());
+                };
+                let config = self.alarm_config()?;
+                let Some(x) = value.as_f64() else {
+                    return Err(AgentError::InvalidValue(
+                        "Input value must be a number".into(),
+                    ));
+                };
+                let (change, compared) = {
+                    let mut state = self.state.lock().unwrap();
+                    (state.update(&config, x, Instant::now()), state.last)
+                };
+                let value = if config.rate {
+                    AgentValue::number(compared)
+                } else {
+                    value
+                };
+                let port = match change {
+                    Some(true) => PORT_RAISE,
+                    Some(false) => PORT_CLEAR,
+                    None => {
+                        self.arm_timer(config, ctx, value);
+                        return Ok(());
+                    }
+                };
+                self.timer = None;
+                self.output(self.traced(ctx), port, value).await
+            }
+            PORT_RESET => {
+                self.reset();
+                Ok(())
+            }
+            _ => Err(AgentError::InvalidPin(port)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bitwise() {
+        assert_eq!(bitwise("and", 0b1100, 0b1010).unwrap(), 0b1000);
+        assert_eq!(bitwise("or", 0b1100, 0b1010).unwrap(), 0b1110);
+        assert_eq!(bitwise("xor", 0b1100, 0b1010).unwrap(), 0b0110);
+        assert_eq!(bitwise("not", 0, 0).unwrap(), -1);
+        assert_eq!(bitwise("shl", 1, 4).unwrap(), 16);
+        assert_eq!(bitwise("shr", -1, 60).unwrap(), 0xf);
+        assert!(bitwise("shl", 1, 64).is_err());
+        assert!(bitwise("nand", 1, 1).is_err());
+
+        assert_eq!(parse_integer("0xff").unwrap(), 255);
+        assert_eq!(parse_integer("0b1010").unwrap(), 10);
+        assert_eq!(parse_integer("-3").unwrap(), -3);
+        assert!(parse_integer("x").is_err());
+    }
+
+    #[test]
+    fn test_decode_number() {
+        let bytes = [0x12, 0x34, 0xff, 0xfe, 0x3f, 0x80, 0x00, 0x00];
+        let int = |v: i64| AgentValue::integer(v);
+        assert_eq!(decode_number(&bytes, 0, "u16", false).unwrap(), int(0x1234));
+        assert_eq!(decode_number(&bytes, 0, "u16", true).unwrap(), int(0x3412));
+        assert_eq!(decode_number(&bytes, 2, "i16", false).unwrap(), int(-2));
+        assert_eq!(decode_number(&bytes, 2, "u8", false).unwrap(), int(255));
+        assert_eq!(decode_number(&bytes, 2, "i8", false).unwrap(), int(-1));
+        assert_eq!(
+            decode_number(&bytes, 4, "f32", false).unwrap(),
+            AgentValue::number(1.0)
+        );
+        assert_eq!(
+            decode_number(&bytes, 0, "u32", false).unwrap(),
+            int(0x1234fffe)
+        );
+        assert!(decode_number(&bytes, 6, "u32", false).is_err());
+        assert!(decode_number(&bytes, 0, "u128", false).is_err());
+        assert!(decode_number(&[0xff; 8], 0, "u64", false).is_err());
+        assert_eq!(decode_number(&[0xff; 8], 0, "i64", false).unwrap(), int(-1));
+    }
+
+    #[test]
+    fn test_alarm_state() {
+        let t0 = Instant::now();
+        let at = |ms: u64| t0 + Duration::from_millis(ms);
+        let mut config = AlarmConfig {
+            threshold: 10.0,
+            hysteresis: 2.0,
+            below: false,
+            min_duration: Duration::ZERO,
+            rate: false,
+        };
+
+        let mut state = AlarmState::default();
+        let events: Vec<_> = [9.0, 11.0, 9.5, 10.5, 7.9, 9.0, 10.1]
+            .iter()
+            .map(|x| state.update(&config, *x, t0))
+            .collect();
+        assert_eq!(
+            events,
+            vec![None, Some(true), None, None, Some(false), None, Some(true)]
+        );
+
+        config.below = true;
+        let mut state = AlarmState::default();
+        assert_eq!(state.update(&config, 9.0, t0), Some(true));
+        assert_eq!(state.update(&config, 11.0, t0), None);
+        assert_eq!(state.update(&config, 12.5, t0), Some(false));
+
+        config.below = false;
+        config.min_duration = Duration::from_millis(100);
+        let mut state = AlarmState::default();
+        assert_eq!(state.update(&config, 11.0, at(0)), None);
+        assert_eq!(state.update(&config, 9.0, at(50)), None);
+        assert_eq!(state.update(&config, 11.0, at(60)), None);
+        assert_eq!(state.update(&config, 11.0, at(120)), None);
+        assert_eq!(state.update(&config, 11.0, at(160)), Some(true));
+
+        // the pending change is due without a further value
+        let mut state = AlarmState::default();
+        assert_eq!(state.update(&config, 11.0, at(0)), None);
+        assert_eq!(state.pending_deadline(&config), Some(at(100)));
+        assert_eq!(state.expire(&config, at(99)), None);
+        assert_eq!(state.expire(&config, at(100)), Some(true));
+        assert_eq!(state.pending_deadline(&config), None);
+        assert_eq!(state.expire(&config, at(200)), None);
+
+        config.min_duration = Duration::ZERO;
+        config.rate = true;
+        config.hysteresis = 0.0;
+        let mut state = AlarmState::default();
+        assert_eq!(state.update(&config, 0.0, at(0)), None);
+        assert_eq!(state.update(&config, 5.0, at(1000)), None);
+        assert_eq!(state.update(&config, 20.0, at(2000)), Some(true));
+        assert_eq!(state.last, 15.0);
+        assert_eq!(state.update(&config, 21.0, at(3000)), Some(false));
+        assert_eq!(state.last, 1.0);
+    }
+}
@@ -0,0 +1,1713 @@
+//! Graph introspection and control.
+//!
+//! List Agents outputs the agents of the running presets, and Get Config and Set Config
+//! read and update the configs of another agent of the same preset, so that a graph can
+//! adjust itself (ex. raise a Throttle interval when a probe sees overload). Start Preset
+//! and Stop Preset control other presets, and Reload Preset reloads the configs of its
+//! own preset from its file, so that a controller graph can manage worker graphs. Export
+//! Configs and Import Configs move the tuned parameters of a preset between environments
+//! as a YAML document. Health Server serves the liveness and readiness of its preset over
+//! HTTP for container probes.
+//!
+//! Agents and presets are locked while they are read. Since an agent may be busy, or a
+//! preset being stopped, these reads give up after a while instead of waiting forever.
+//! Presets are started, stopped and reloaded in tasks, since stopping a preset waits for
+//! its agents, which may include the agent asking for it.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use im::hashmap;
+use modular_agent_core::{
+    Agent, AgentConfigSpecs, AgentConfigs, AgentContext, AgentData, AgentError, AgentOutput,
+    AgentSpec, AgentStatus, AgentValue, AsAgent, ModularAgent, PresetSpec, async_trait,
+    modular_agent,
+};
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+use crate::audit::{ACTION_SET_CONFIG, audit, audit_by};
+use crate::contract::{CONFIG_INPUT_CONTRACT, ContractAgent, InputContract, contract_agents};
+use crate::profile::ProfileConfigs;
+use crate::provenance::{Traced, VAR_PROVENANCE, stamp};
+use crate::time::parse_duration_to_ms;
+
+const CATEGORY: &str = "Std/Meta";
+
+const PORT_AGENTS: &str = "agents";
+const PORT_DIFF: &str = "diff";
+const PORT_FAILURE: &str = "failure";
+const PORT_OK: &str = "ok";
+const PORT_PROBLEM: &str = "problem";
+const PORT_SUCCESS: &str = "success";
+const PORT_TRIGGER: &str = "trigger";
+const PORT_VALUE: &str = "value";
+const PORT_YAML: &str = "yaml";
+
+const CONFIG_AGENT: &str = "agent";
+const CONFIG_ALL_PRESETS: &str = "all_presets";
+const CONFIG_BIND: &str = "bind";
+const CONFIG_CONFIRM: &str = "confirm";
+const CONFIG_DRY_RUN: &str = "dry_run";
+const CONFIG_KEY: &str = "key";
+const CONFIG_PATH: &str = "path";
+const CONFIG_PORT: &str = "port";
+const CONFIG_PRESET: &str = "preset";
+const CONFIG_PROBLEM_TTL: &str = "problem_ttl";
+const CONFIG_SELECT: &str = "select";
+
+const KEY_DEF_NAME: &str = "def_name";
+const KEY_DISABLED: &str = "disabled";
+const KEY_ERROR: &str = "error";
+const KEY_FROM: &str = "from";
+const KEY_KEY: &str = "key";
+const KEY_AGENT: &str = "agent";
+const KEY_AGENTS: &str = "agents";
+const KEY_PROBLEM: &str = "problem";
+const KEY_PROBLEMS: &str = "problems";
+const KEY_SOURCE: &str = "source";
+const KEY_STARTED: &str = "started";
+const KEY_TOTAL: &str = "total";
+const KEY_ID: &str = "id";
+const KEY_PRESET: &str = "preset";
+const KEY_PRESET_NAME: &str = "preset_name";
+const KEY_STATUS: &str = "status";
+const KEY_TITLE: &str = "title";
+const KEY_TO: &str = "to";
+const KEY_UPDATED: &str = "updated";
+
+const INPUT_DEF_PREFIX: &str = "modular_agent_std::input::";
+
+const LOCK_TIMEOUT: Duration = Duration::from_secs(5);
+
+const BIND_DEFAULT: &str = "0.0.0.0";
+const HEALTH_PORT_DEFAULT: i64 = 8080;
+// requests are small; anything larger is not a probe
+const MAX_REQUEST_SIZE: usize = 8192;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+// how long a probe waits in all for the agents busy processing to report their status
+const STATUS_TIMEOUT: Duration = Duration::from_millis(500);
+
+async fn locked<T>(what: &str, fut: impl Future<Output = T>) -> Result<T, AgentError> {
+    tokio::time::timeout(LOCK_TIMEOUT, fut)
+        .await
+        .map_err(|_| AgentError::IoError(format!("Timed out waiting for {}", what)))
+}
+
+fn status_name(status: &AgentStatus) -> &'static str {
+    match status {
+        AgentStatus::Init => "init",
+        AgentStatus::Start => "start",
+        AgentStatus::Stop => "stop",
+    }
+}
+
+/// Returns the agent specs of the preset.
+async fn preset_agents(ma: &ModularAgent, preset_id: &str) -> Result<Vec<AgentSpec>, AgentError> {
+    let preset = ma
+        .get_preset(preset_id)
+        .ok_or_else(|| AgentError::PresetNotFound(preset_id.to_string()))?;
+    let preset = locked("the preset", preset.lock()).await?;
+    Ok(preset.spec().agents.clone())
+}
+
+/// Finds the id of the agent `target` among the agents `(id, def_name, title)` of a
+/// preset: `target` is an id, or the title or definition name of a single agent. Ids are
+/// renewed whenever a preset is loaded, so titles are the stable way to refer to agents.
+fn find_agent(agents: &[(String, String, String)], target: &str) -> Result<String, AgentError> {
+    if target.is_empty() {
+        return Err(AgentError::InvalidConfig("agent is not set".into()));
+    }
+    if agents.iter().any(|(id, _, _)| id == target) {
+        return Ok(target.to_string());
+    }
+    let found: Vec<&String> = agents
+        .iter()
+        .filter(|(_, def_name, title)| title == target || def_name == target)
+        .map(|(id, _, _)| id)
+        .collect();
+    match found.as_slice() {
+        [id] => Ok(id.to_string()),
+        [] => Err(AgentError::AgentNotFound(target.to_string())),
+        ids => Err(AgentError::InvalidConfig(format!(
+            "agent {} is ambiguous; use one of the ids {}",
+            target,
+            ids.iter()
+                .map(|id| id.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ))),
+    }
+}
+
+fn agent_title(ma: &ModularAgent, def_name: &str) -> String {
+    ma.get_agent_definition(def_name)
+        .and_then(|def| def.title)
+        .unwrap_or_else(|| def_name.to_string())
+}
+
+/// Returns the id and the current spec of the agent `target` of the same preset as
+/// `agent`.
+async fn sibling_spec(agent: &impl Agent, target: &str) -> Result<(String, AgentSpec), AgentError> {
+    let ma = agent.ma();
+    let agents: Vec<(String, String, String)> = preset_agents(ma, agent.preset_id())
+        .await?
+        .into_iter()
+        .map(|spec| {
+            let title = agent_title(ma, &spec.def_name);
+            (spec.id, spec.def_name, title)
+        })
+        .collect();
+    let id = find_agent(&agents, target)?;
+    if id == agent.id() {
+        return Ok((id, agent.spec().clone()));
+    }
+    let spec = locked("the agent", ma.get_agent_spec(&id))
+        .await?
+        .ok_or_else(|| AgentError::AgentNotFound(id.clone()))?;
+    Ok((id, spec))
+}
+
+fn configs_to_value(configs: &AgentConfigs) -> AgentValue {
+    AgentValue::object(
+        configs
+            .keys()
+            .filter_map(|key| Some((key.clone(), configs.get(key).ok()?.clone())))
+            .collect(),
+    )
+}
+
+/// Checks the updates against the config specs of the target: the keys must exist, not
+/// be readonly, and the values must have the type of the config.
+fn check_config_updates(
+    specs: &AgentConfigSpecs,
+    updates: &[(String, AgentValue)],
+) -> Result<(), AgentError> {
+    for (key, value) in updates {
+        let Some(spec) = specs.get(key) else {
+            return Err(AgentError::InvalidConfig(format!(
+                "Unknown config: {}",
+                key
+            )));
+        };
+        if spec.readonly {
+            return Err(AgentError::InvalidConfig(format!(
+                "Config {} is readonly",
+                key
+            )));
+        }
+        let ok = match spec.type_.as_deref() {
+            Some("boolean") => value.is_boolean(),
+            Some("integer") => value.is_integer(),
+            Some("number") => value.is_number() || value.is_integer(),
+            Some("string") | Some("text") => value.is_string(),
+            Some("array") => value.is_array(),
+            _ => true,
+        };
+        if !ok {
+            return Err(AgentError::InvalidValue(format!(
+                "Config {} expects {}, got {}",
+                key,
+                spec.type_.as_deref().unwrap_or_default(),
+                value.to_json()
+            )));
+        }
+    }
+    Ok(())
+}
+
+contract_agents!(SetConfigAgent, ImportConfigsAgent, HealthServerAgent);
+
+/// Lists the agents of this preset, or of all the presets.
+///
+/// On any value on `trigger`, outputs `{id, def_name, title, preset, preset_name, status,
+/// disabled}` for each agent as an array on `agents`. `status` is `init`, `start` or
+/// `stop`.
+#[modular_agent(
+    title = "List Agents",
+    category = CATEGORY,
+    inputs = [PORT_TRIGGER],
+    outputs = [PORT_AGENTS],
+    boolean_config(name = CONFIG_ALL_PRESETS, title = "all presets"),
+)]
+struct ListAgentsAgent {
+    data: AgentData,
+}
+
+#[async_trait]
+impl AsAgent for ListAgentsAgent {
+    fn new(ma: ModularAgent, id: String, spec: AgentSpec) -> Result<Self, AgentError> {
+        Ok(Self {
+            data: AgentData::new(ma, id, spec),
+        })
+    }
+
+    async fn process(
+        &mut self,
+        ctx: AgentContext,
+        _port: String,
+        _value: AgentValue,
+    ) -> Result<(), AgentError> {
+        let ma = self.ma().clone();
+        let presets = if self.configs()?.get_bool_or_default(CONFIG_ALL_PRESETS) {
+            locked("the presets", ma.get_preset_infos()).await?
+        } else {
+            locked("the preset", ma.get_preset_info(self.preset_id()))
+                .await?
+                .into_iter()
+                .collect()
+        };
+
+        let mut agents = Vec::new();
+        for preset in presets {
+            for spec in preset_agents(&ma, &preset.id).await? {
+                let status = if spec.id == self.id() {
+                    status_name(self.status())
+                } else {
+                    match ma.get_agent(&spec.id) {
+                        // a locked agent is processing, so it is running
+                        Some(agent) => agent
+                            .try_lock()
+                            .map_or("start", |agent| status_name(agent.status())),
+                        None => continue,
+                    }
+                };
+                let title = agent_title(&ma, &spec.def_name);
+                agents.push(AgentValue::object(hashmap! {
+                    KEY_ID.to_string() => AgentValue::string(&spec.id),
+                    KEY_DEF_NAME.to_string() => AgentValue::string(&spec.def_name),
+                    KEY_TITLE.to_string() => AgentValue::string(title),
+                    KEY_PRESET.to_string() => AgentValue::string(&preset.id),
+                    KEY_PRESET_NAME.to_string() => preset.name.clone().map(AgentValue::string).unwrap_or_default(),
+                    KEY_STATUS.to_string() => AgentValue::string(status),
+                    KEY_DISABLED.to_string() => AgentValue::boolean(spec.disabled),
+                }));
+            }
+        }
+        self.output(
+            self.traced(ctx),
+            PORT_AGENTS,
+            AgentValue::array(agents.into()),
+        )
+        .await
+    }
+}
+
+/// Reads the configs of another agent of the same preset.
+///
+/// On any value on `trigger`, outputs the config `key` of the agent `agent`, or all its
+/// configs as an object when `key` is blank. `agent` is the title of an agent of the
+/// preset (ex. `Throttle`), or its id when several have the same title.
+#[modular_agent(
+    title = "Get Config",
+    category = CATEGORY,
+    inputs = [PORT_TRIGGER],
+    outputs = [PORT_VALUE],
+    string_config(name = CONFIG_AGENT, description = "title or id"),
+    string_config(name = CONFIG_KEY, description = "empty: all"),
+)]
+struct GetConfigAgent {
+    data: AgentData,
+}
+
+#[async_trait]
+impl AsAgent for GetConfigAgent {
+    fn new(ma: ModularAgent, id: String, spec: AgentSpec) -> Result<Self, AgentError> {
+        Ok(Self {
+            data: AgentData::new(ma, id, spec),
+        })
+    }
+
+    async fn process(
+        &mut self,
+        ctx: AgentContext,
+        _port: String,
+        _value: AgentValue,
+    ) -> Result<(), AgentError> {
+        let configs = self.configs()?;
+        let target = configs.get_string_or_default(CONFIG_AGENT);
+        let key = configs.get_string_or_default(CONFIG_KEY);
+        let (_, spec) = sibling_spec(self, target.trim()).await?;
+        let target_configs = spec.configs.unwrap_or_default();
+        let value = match key.trim() {
+            "" => configs_to_value(&target_configs),
+            key => target_configs.get(key).cloned()?,
+        };
+        self.output(self.traced(ctx), PORT_VALUE, value).await
+    }
+}
+
+/// Updates the configs of another agent of the same preset.
+///
+/// The value on `value` becomes the config `key` of the agent `agent` (a title or an id,
+/// as in Get Config); when `key` is blank, the value is an object of the configs to
+/// update. The configs must exist, not be readonly, and the values must have their types.
+/// Nothing is updated unless `confirm` is on. The updated configs are output as an object.
+#[modular_agent(
+    title = "Set Config",
+    category = CATEGORY,
+    inputs = [PORT_VALUE],
+    outputs = [PORT_VALUE],
+    string_config(name = CONFIG_AGENT, description = "title or id"),
+    string_config(name = CONFIG_KEY, description = "empty: an object of configs"),
+    boolean_config(name = CONFIG_CONFIRM, description = "must be on to update"),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
+    hint(color=4),
+)]
+struct SetConfigAgent {
+    data: AgentData,
+    contract: InputContract,
+}
+
+#[async_trait]
+impl AsAgent for SetConfigAgent {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
+        Ok(Self {
+            data: AgentData::new(ma, id, spec),
+            contract,
+        })
+    }
+
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
+    }
+
+    async fn process(
+        &mut self,
+        ctx: AgentContext,
+        port: String,
+        value: AgentValue,
+    ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
+        let configs = self.configs()?;
+        if !configs.get_bool_or_default(CONFIG_CONFIRM) {
+            return Err(AgentError::InvalidConfig(
+                "confirm is off; configs are not updated".into(),
+            ));
+        }
+        let target = configs.get_string_or_default(CONFIG_AGENT);
+        let target = target.trim();
+        let key = configs.get_string_or_default(CONFIG_KEY);
+        let updates: Vec<(String, AgentValue)> = match key.trim() {
+            "" => value
+                .as_object()
+                .ok_or_else(|| AgentError::InvalidValue("Expected an object of configs".into()))?
+                .iter()
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect(),
+            key => vec![(key.to_string(), value)],
+        };
+
+        let (target, spec) = sibling_spec(self, target).await?;
+        let specs = spec
+            .config_specs
+            .clone()
+            .or_else(|| self.ma().get_agent_config_specs(&spec.def_name))
+            .unwrap_or_default();
+        check_config_updates(&specs, &updates)?;
+
+        let mut target_configs = spec.configs.unwrap_or_default();
+        for (key, value) in &updates {
+            target_configs.set(key.clone(), value.clone());
+        }
+        let payload = AgentValue::object(updates.iter().cloned().collect());
+        audit(self, &ctx, ACTION_SET_CONFIG, &target, &payload).await?;
+        self.ma()
+            .set_agent_configs(target.clone(), target_configs)
+            .await?;
+        log::info!("Agent '{}' updated configs of '{}'", self.id(), target);
+
+        let updated = AgentValue::object(updates.into_iter().collect());
+        self.output(self.traced(ctx), PORT_VALUE, updated).await
+    }
+}
+
+/// Finds the id of a loaded preset by its id or name. When `open` is set and none is
+/// loaded, `preset` is taken as the path of a preset file to open, named after the path.
+async fn find_preset(ma: &ModularAgent, preset: &str, open: bool) -> Result<String, AgentError> {
+    if preset.is_empty() {
+        return Err(AgentError::InvalidConfig("preset is not set".into()));
+    }
+    let infos = locked("the presets", ma.get_preset_infos()).await?;
+    if let Some(info) = infos.iter().find(|info| info.id == preset) {
+        return Ok(info.id.clone());
+    }
+    let found: Vec<&str> = infos
+        .iter()
+        .filter(|info| info.name.as_deref() == Some(preset))
+        .map(|info| info.id.as_str())
+        .collect();
+    match found.as_slice() {
+        [id] => Ok(id.to_string()),
+        [] if open && Path::new(preset).is_file() => {
+            ma.open_preset_from_file(preset, Some(preset.to_string()))
+                .await
+        }
+        [] => Err(AgentError::PresetNotFound(preset.to_string())),
+        ids => Err(AgentError::InvalidConfig(format!(
+            "preset {} is ambiguous; use one of the ids {}",
+            preset,
+            ids.join(", ")
+        ))),
+    }
+}
+
+/// Runs a preset operation in a task, and outputs its result on `success`, or
+/// `{preset, error}` on `failure`.
+fn spawn_preset_task<F>(agent: &impl Agent, ctx: AgentContext, preset: String, task: F)
+where
+    F: Future<Output = Result<AgentValue, AgentError>> + Send + 'static,
+{
+    let ma = agent.ma().clone();
+    let agent_id = agent.id().to_string();
+    let ctx = stamp(ctx, &agent_id, agent.def_name());
+    tokio::spawn(async move {
+        let (port, value) = match task.await {
+            Ok(value) => (PORT_SUCCESS, value),
+            Err(e) => {
+                log::error!("Preset operation on '{}' failed: {}", preset, e);
+                (
+                    PORT_FAILURE,
+                    AgentValue::object(hashmap! {
+                        KEY_PRESET.to_string() => AgentValue::string(preset),
+                        KEY_ERROR.to_string() => AgentValue::string(e.to_string()),
+                    }),
+                )
+            }
+        };
+        if let Err(e) = ma.try_send_agent_out(agent_id, ctx, port.to_string(), value) {
+            log::error!("Failed to send preset operation result: {}", e);
+        }
+    });
+}
+
+fn preset_value(id: &str, name: &str) -> AgentValue {
+    AgentValue::object(hashmap! {
+        KEY_PRESET.to_string() => AgentValue::string(id),
+        KEY_PRESET_NAME.to_string() => AgentValue::string(name),
+    })
+}
+
+/// Starts another preset.
+///
+/// On any value on `trigger`, starts the preset `preset`: the id or name of a loaded
+/// preset, or the path of a preset file, which is opened first. Outputs `{preset,
+/// preset_name}` on `success`, or `{preset, error}` on `failure`.
+#[modular_agent(
+    title = "Start Preset",
+    category = CATEGORY,
+    inputs = [PORT_TRIGGER],
+    outputs = [PORT_SUCCESS, PORT_FAILURE],
+    string_config(name = CONFIG_PRESET, description = "name, id or file path"),
+    hint(color=4),
+)]
+struct StartPresetAgent {
+    data: AgentData,
+}
+
+#[async_trait]
+impl AsAgent for StartPresetAgent {
+    fn new(ma: ModularAgent, id: String, spec: AgentSpec) -> Result<Self, AgentError> {
+        Ok(Self {
+            data: AgentData::new(ma, id, spec),
+        })
+    }
+
+    async fn process(
+        &mut self,
+        ctx: AgentContext,
+        _port: String,
+        _value: AgentValue,
+    ) -> Result<(), AgentError> {
+        let preset = self.configs()?.get_string_resolved(CONFIG_PRESET)?;
+        let preset = preset.trim().to_string();
+        let ma = self.ma().clone();
+        spawn_preset_task(self, ctx, preset.clone(), async move {
+            let id = find_preset(&ma, &preset, true).await?;
+            ma.start_preset(&id).await?;
+            log::info!("Started preset '{}' ({})", preset, id);
+            Ok(preset_value(&id, &preset))
+        });
+        Ok(())
+    }
+}
+
+/// Stops another preset.
+///
+/// On any value on `trigger`, stops the loaded preset `preset` (its id or name). Outputs
+/// `{preset, preset_name}` on `success`, or `{preset, error}` on `failure`.
+#[modular_agent(
+    title = "Stop Preset",
+    category = CATEGORY,
+    inputs = [PORT_TRIGGER],
+    outputs = [PORT_SUCCESS, PORT_FAILURE],
+    string_config(name = CONFIG_PRESET, description = "name or id"),
+    hint(color=4),
+)]
+struct StopPresetAgent {
+    data: AgentData,
+}
+
+#[async_trait]
+impl AsAgent for StopPresetAgent {
+    fn new(ma: ModularAgent, id: String, spec: AgentSpec) -> Result<Self, AgentError> {
+        Ok(Self {
+            data: AgentData::new(ma, id, spec),
+        })
+    }
+
+    async fn process(
+        &mut self,
+        ctx: AgentContext,
+        _port: String,
+        _value: AgentValue,
+    ) -> Result<(), AgentError> {
+        let preset = self.configs()?.get_string_resolved(CONFIG_PRESET)?;
+        let preset = preset.trim().to_string();
+        let ma = self.ma().clone();
+        spawn_preset_task(self, ctx, preset.clone(), async move {
+            let id = find_preset(&ma, &preset, false).await?;
+            ma.stop_preset(&id).await?;
+            log::info!("Stopped preset '{}' ({})", preset, id);
+            Ok(preset_value(&id, &preset))
+        });
+        Ok(())
+    }
+}
+
+/// Returns the agents whose configs differ in the preset file, with their new configs.
+///
+/// The agents of the file are matched in order to the agents of the preset, which must
+/// have the same definitions. Configs missing in the file are kept.
+fn reloaded_configs(
+    current: &[AgentSpec],
+    file: &PresetSpec,
+) -> Result<Vec<(String, AgentConfigs)>, AgentError> {
+    if current.len() != file.agents.len()
+        || current
+            .iter()
+            .zip(&file.agents)
+            .any(|(a, b)| a.def_name != b.def_name)
+    {
+        return Err(AgentError::InvalidValue(
+            "The agents of the preset file differ; reopen the preset instead".into(),
+        ));
+    }
+    let mut changes = Vec::new();
+    for (agent, saved) in current.iter().zip(&file.agents) {
+        let Some(saved) = &saved.configs else {
+            continue;
+        };
+        let mut configs = agent.configs.clone().unwrap_or_default();
+        let mut changed = false;
+        for key in saved.keys() {
+            let value = saved.get(key)?;
+            if configs.get(key).ok() != Some(value) {
+                configs.set(key.clone(), value.clone());
+                changed = true;
+            }
+        }
+        if changed {
+            changes.push((agent.id.clone(), configs));
+        }
+    }
+    Ok(changes)
+}
+
+/// Reloads the configs of this preset from its file.
+///
+/// On any value on `trigger`, reads the preset file at `path` and updates the configs of
+/// the agents of this preset that differ. The agents and connections of the file must be
+/// those of the preset; to change them, reopen the preset. Outputs `{preset, updated}`
+/// (the ids of the updated agents) on `success`, or `{preset, error}` on `failure`.
+#[modular_agent(
+    title = "Reload Preset",
+    category = CATEGORY,
+    inputs = [PORT_TRIGGER],
+    outputs = [PORT_SUCCESS, PORT_FAILURE],
+    string_config(name = CONFIG_PATH, description = "preset file"),
+    hint(color=4),
+)]
+struct ReloadPresetAgent {
+    data: AgentData,
+}
+
+#[async_trait]
+impl AsAgent for ReloadPresetAgent {
+    fn new(ma: ModularAgent, id: String, spec: AgentSpec) -> Result<Self, AgentError> {
+        Ok(Self {
+            data: AgentData::new(ma, id, spec),
+        })
+    }
+
+    async fn process(
+        &mut self,
+        ctx: AgentContext,
+        _port: String,
+        _value: AgentValue,
+    ) -> Result<(), AgentError> {
+        let path = self.configs()?.get_string_resolved(CONFIG_PATH)?;
+        let path = path.trim().to_string();
+        if path.is_empty() {
+            return Err(AgentError::InvalidConfig("path is not set".into()));
+        }
+        let ma = self.ma().clone();
+        let preset_id = self.preset_id().to_string();
+        let (agent_id, def_name) = (self.id().to_string(), self.def_name().to_string());
+        let audit_ctx = ctx.clone();
+        spawn_preset_task(self, ctx, preset_id.clone(), async move {
+            let text = std::fs::read_to_string(&path).map_err(|e| {
+                AgentError::IoError(format!("Failed to read preset {}: {}", path, e))
+            })?;
+            let file: PresetSpec = serde_json::from_str(&text).map_err(|e| {
+                AgentError::JsonParseError(format!("Invalid preset {}: {}", path, e))
+            })?;
+
+            let mut current = Vec::new();
+            for spec in preset_agents(&ma, &preset_id).await? {
+                let spec = locked("the agent", ma.get_agent_spec(&spec.id))
+                    .await?
+                    .ok_or_else(|| AgentError::AgentNotFound(spec.id.clone()))?;
+                current.push(spec);
+            }
+            let changes = reloaded_configs(&current, &file)?;
+
+            let mut updated = Vec::new();
+            for (id, configs) in changes {
+                let payload = AgentValue::from_serialize(&configs)?;
+                audit_by(
+                    &agent_id,
+                    &def_name,
+                    &audit_ctx,
+                    ACTION_SET_CONFIG,
+                    &id,
+                    &payload,
+                )
+                .await?;
+                ma.set_agent_configs(id.clone(), configs).await?;
+                updated.push(AgentValue::string(id));
+            }
+            log::info!(
+                "Reloaded preset {} from {}: {} agents updated",
+                preset_id,
+                path,
+                updated.len()
+            );
+            Ok(AgentValue::object(hashmap! {
+                KEY_PRESET.to_string() => AgentValue::string(&preset_id),
+                KEY_UPDATED.to_string() => AgentValue::array(updated.into()),
+            }))
+        });
+        Ok(())
+    }
+}
+
+// Config documents
+
+/// Returns the keys of agents with `titles` in config documents: the title, numbered in
+/// order (`Title#2`) when several agents have it.
+fn agent_keys(titles: &[String]) -> Vec<String> {
+    let mut seen: std::collections::HashMap<&str, usize> = Default::default();
+    titles
+        .iter()
+        .map(|title| {
+            let n = seen.entry(title.as_str()).or_default();
+            *n += 1;
+            if titles.iter().filter(|t| *t == title).count() > 1 {
+                format!("{}#{}", title, n)
+            } else {
+                title.clone()
+            }
+        })
+        .collect()
+}
+
+/// An agent of the preset, with its key in config documents and its config specs.
+struct DocumentAgent {
+    key: String,
+    spec: AgentSpec,
+    config_specs: AgentConfigSpecs,
+}
+
+impl DocumentAgent {
+    fn is_input(&self) -> bool {
+        self.spec.def_name.starts_with(INPUT_DEF_PREFIX)
+    }
+
+    fn writable_keys(&self) -> Vec<String> {
+        let configs = self.spec.configs.clone().unwrap_or_default();
+        configs
+            .keys()
+            .filter(|key| {
+                self.config_specs
+                    .get(key.as_str())
+                    .is_some_and(|spec| !spec.readonly)
+            })
+            .cloned()
+            .collect()
+    }
+}
+
+/// Returns the agents of the preset of `agent` with their current specs.
+async fn document_agents(agent: &impl Agent) -> Result<Vec<DocumentAgent>, AgentError> {
+    let ma = agent.ma();
+    let mut specs = Vec::new();
+    for spec in preset_agents(ma, agent.preset_id()).await? {
+        let spec = if spec.id == agent.id() {
+            agent.spec().clone()
+        } else {
+            locked("the agent", ma.get_agent_spec(&spec.id))
+                .await?
+                .ok_or_else(|| AgentError::AgentNotFound(spec.id.clone()))?
+        };
+        specs.push(spec);
+    }
+    let titles: Vec<String> = specs
+        .iter()
+        .map(|spec| agent_title(ma, &spec.def_name))
+        .collect();
+    Ok(agent_keys(&titles)
+        .into_iter()
+        .zip(specs)
+        .map(|(key, spec)| {
+            let config_specs = spec
+                .config_specs
+                .clone()
+                .or_else(|| ma.get_agent_config_specs(&spec.def_name))
+                .unwrap_or_default();
+            DocumentAgent {
+                key,
+                spec,
+                config_specs,
+            }
+        })
+        .collect())
+}
+
+/// Finds the agent referred to by `key` in a document: its key, or its id.
+fn document_agent(agents: &[DocumentAgent], key: &str) -> Option<usize> {
+    agents
+        .iter()
+        .position(|agent| agent.key == key)
+        .or_else(|| agents.iter().position(|agent| agent.spec.id == key))
+}
+
+/// Builds the config document of `agents`: the values of the Input agents, and the
+/// configs picked by `select` (`agent` for all its configs, or `agent.key`).
+fn export_document(
+    agents: &[DocumentAgent],
+    select: &[&str],
+    preset_name: &str,
+) -> Result<serde_json::Value, AgentError> {
+    let mut picked: Vec<Option<Vec<String>>> = agents
+        .iter()
+        .map(|agent| agent.is_input().then(|| agent.writable_keys()))
+        .collect();
+    for entry in select {
+        let (index, keys) = match document_agent(agents, entry) {
+            Some(index) => (index, agents[index].writable_keys()),
+            None => {
+                let (index, key) = entry
+                    .rsplit_once('.')
+                    .and_then(|(agent, key)| Some((document_agent(agents, agent)?, key)))
+                    .ok_or_else(|| AgentError::AgentNotFound(entry.to_string()))?;
+                if !agents[index].writable_keys().iter().any(|k| k == key) {
+                    return Err(AgentError::InvalidConfig(format!(
+                        "Unknown config: {}",
+                        entry
+                    )));
+                }
+                (index, vec![key.to_string()])
+            }
+        };
+        picked[index].get_or_insert_with(Vec::new).extend(keys);
+    }
+
+    let mut doc_agents = serde_json::Map::new();
+    for (agent, keys) in agents.iter().zip(picked) {
+        let Some(keys) = keys else {
+            continue;
+        };
+        let configs = agent.spec.configs.clone().unwrap_or_default();
+        let values: serde_json::Map<String, serde_json::Value> = keys
+            .into_iter()
+            .filter_map(|key| {
+                let value = configs.get(&key).ok()?.to_json();
+                Some((key, value))
+            })
+            .collect();
+        doc_agents.insert(agent.key.clone(), serde_json::Value::Object(values));
+    }
+    Ok(serde_json::json!({
+        KEY_PRESET: preset_name,
+        KEY_AGENTS: doc_agents,
+    }))
+}
+
+/// The changes of the configs of a preset by a config document.
+struct ImportChanges {
+    // `{agent, key, from, to}` for each config that differs
+    diff: Vec<AgentValue>,
+    // the ids and new configs of the agents that differ
+    configs: Vec<(String, AgentConfigs)>,
+}
+
+/// Compares the config document `doc` with `agents`.
+///
+/// The whole document is checked first, so that it is applied entirely or not at all.
+fn import_changes(
+    agents: &[DocumentAgent],
+    doc: &serde_json::Value,
+) -> Result<ImportChanges, AgentError> {
+    let doc_agents = doc
+        .get(KEY_AGENTS)
+        .and_then(|agents| agents.as_object())
+        .ok_or_else(|| AgentError::InvalidValue("Document has no agents".into()))?;
+    let mut changes = ImportChanges {
+        diff: Vec::new(),
+        configs: Vec::new(),
+    };
+    for (key, values) in doc_agents {
+        let agent = document_agent(agents, key)
+            .map(|index| &agents[index])
+            .ok_or_else(|| AgentError::AgentNotFound(key.to_string()))?;
+        let values = values.as_object().ok_or_else(|| {
+            AgentError::InvalidValue(format!("Configs of {} are not an object", key))
+        })?;
+        let updates = values
+            .iter()
+            .map(|(k, v)| Ok((k.clone(), AgentValue::from_json(v.clone())?)))
+            .collect::<Result<Vec<_>, AgentError>>()?;
+        check_config_updates(&agent.config_specs, &updates)?;
+
+        let mut configs = agent.spec.configs.clone().unwrap_or_default();
+        let mut changed = false;
+        for (k, to) in updates {
+            let from = configs.get(&k).ok().cloned().unwrap_or_default();
+            if from == to {
+                continue;
+            }
+            changes.diff.push(AgentValue::object(hashmap! {
+                KEY_AGENT.to_string() => AgentValue::string(&agent.key),
+                KEY_KEY.to_string() => AgentValue::string(&k),
+                KEY_FROM.to_string() => from,
+                KEY_TO.to_string() => to.clone(),
+            }));
+            configs.set(k, to);
+            changed = true;
+        }
+        if changed {
+            changes.configs.push((agent.spec.id.clone(), configs));
+        }
+    }
+    Ok(changes)
+}
+
+fn document_to_string(doc: &serde_json::Value) -> Result<String, AgentError> {
+    #[cfg(feature = "yaml")]
+    return serde_yaml_ng::to_string(doc)
+        .map_err(|e| AgentError::SerializationError(e.to_string()));
+    #[cfg(not(feature = "yaml"))]
+    serde_json::to_string_pretty(doc).map_err(|e| AgentError::SerializationError(e.to_string()))
+}
+
+fn document_from_str(s: &str) -> Result<serde_json::Value, AgentError> {
+    match serde_json::from_str(s) {
+        Ok(doc) => Ok(doc),
+        #[cfg(feature = "yaml")]
+        Err(_) => serde_yaml_ng::from_str(s)
+            .map_err(|e| AgentError::InvalidValue(format!("Invalid document: {}", e))),
+        #[cfg(not(feature = "yaml"))]
+        Err(e) => Err(AgentError::InvalidValue(format!("Invalid document: {}", e))),
+    }
+}
+
+/// Exports the parameters of this preset as a YAML document.
+///
+/// On any value on `trigger`, outputs on `yaml` the values of the Input agents of the
+/// preset, and the configs picked by `select` (comma separated: `agent` for all its
+/// configs, or `agent.key`), as `{preset, agents: {agent: {key: value}}}`. Agents are
+/// named by their titles, numbered in order (`Integer Input#2`) when several share one,
+/// so the document applies to the same preset opened elsewhere. Without the `yaml`
+/// feature, the document is JSON.
+#[modular_agent(
+    title = "Export Configs",
+    category = CATEGORY,
+    inputs = [PORT_TRIGGER],
+    outputs = [PORT_YAML],
+    string_config(name = CONFIG_SELECT, description = "agent or agent.key, comma separated"),
+)]
+struct ExportConfigsAgent {
+    data: AgentData,
+}
+
+#[async_trait]
+impl AsAgent for ExportConfigsAgent {
+    fn new(ma: ModularAgent, id: String, spec: AgentSpec) -> Result<Self, AgentError> {
+        Ok(Self {
+            data: AgentData::new(ma, id, spec),
+        })
+    }
+
+    async fn process(
+        &mut self,
+        ctx: AgentContext,
+        _port: String,
+        _value: AgentValue,
+    ) -> Result<(), AgentError> {
+        let select = self.configs()?.get_string_or_default(CONFIG_SELECT);
+        let select: Vec<&str> = select
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .collect();
+        let agents = document_agents(self).await?;
+        let preset_name = locked("the preset", self.ma().get_preset_info(self.preset_id()))
+            .await?
+            .and_then(|info| info.name)
+            .unwrap_or_default();
+        let doc = export_document(&agents, &select, &preset_name)?;
+        self.output(
+            self.traced(ctx),
+            PORT_YAML,
+            AgentValue::string(document_to_string(&doc)?),
+        )
+        .await
+    }
+}
+
+/// Imports a document of Export Configs into this preset.
+///
+/// The document on `yaml` (YAML or JSON text, or an object) is checked as a whole
+/// against the agents of the preset: the agents and configs must exist, not be readonly,
+/// and the values must have their types. The differences are output on `diff` as an
+/// array of `{agent, key, from, to}`; unless `dry run` is on, they are applied too.
+/// If updating an agent fails, the agents already updated get their previous configs
+/// back and the error is returned.
+#[modular_agent(
+    title = "Import Configs",
+    category = CATEGORY,
+    inputs = [PORT_YAML],
+    outputs = [PORT_DIFF],
+    boolean_config(name = CONFIG_DRY_RUN, default = true, title = "dry run", description = "output the diff only"),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
+    hint(color=4),
+)]
+struct ImportConfigsAgent {
+    data: AgentData,
+    contract: InputContract,
+}
+
+#[async_trait]
+impl AsAgent for ImportConfigsAgent {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
+        Ok(Self {
+            data: AgentData::new(ma, id, spec),
+            contract,
+        })
+    }
+
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
+    }
+
+    async fn process(
+        &mut self,
+        ctx: AgentContext,
+        port: String,
+        value: AgentValue,
+    ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
+        let doc = match value.as_str() {
+            Some(s) => document_from_str(s)?,
+            None => value.to_json(),
+        };
+        let dry_run = self.configs()?.get_bool_or(CONFIG_DRY_RUN, true);
+        let agents = document_agents(self).await?;
+        let changes = import_changes(&agents, &doc)?;
+        if changes.configs.iter().any(|(id, _)| id == self.id()) {
+            return Err(AgentError::InvalidValue(
+                "Import Configs cannot update its own configs".into(),
+            ));
+        }
+
+        if !dry_run {
+            // the configs before the import, to roll back to if an update fails
+            let mut applied: Vec<(String, AgentConfigs)> = Vec::new();
+            for (id, configs) in changes.configs {
+                let previous = agents
+                    .iter()
+                    .find(|agent| agent.spec.id == id)
+                    .and_then(|agent| agent.spec.configs.clone())
+                    .unwrap_or_default();
+                let payload = AgentValue::from_serialize(&configs)?;
+                let result = match audit(self, &ctx, ACTION_SET_CONFIG, &id, &payload).await {
+                    Ok(()) => self.ma().set_agent_configs(id.clone(), configs).await,
+                    Err(e) => Err(e),
+                };
+                if let Err(e) = result {
+                    for (id, configs) in applied.into_iter().rev() {
+                        if let Err(e) = self.ma().set_agent_configs(id.clone(), configs).await {
+                            log::error!("Failed to roll back the configs of '{}': {}", id, e);
+                        }
+                    }
+                    return Err(e);
+                }
+                applied.push((id, previous));
+            }
+            log::info!(
+                "Agent '{}' imported {} configs into preset {}",
+                self.id(),
+                changes.diff.len(),
+                self.preset_id()
+            );
+        }
+        self.output(
+            self.traced(ctx),
+            PORT_DIFF,
+            AgentValue::array(changes.diff.into()),
+        )
+        .await
+    }
+}
+
+// Health Server
+
+#[derive(Default)]
+struct HealthState {
+    // the last problem reported by each source, and when
+    problems: HashMap<String, (AgentValue, Instant)>,
+    problem_ttl: Option<Duration>,
+}
+
+impl HealthState {
+    // The problems not expired, by source.
+    fn problems(&self, now: Instant) -> HashMap<String, AgentValue> {
+        self.problems
+            .iter()
+            .filter(|(_, (_, at))| self.problem_ttl.is_none_or(|ttl| now < *at + ttl))
+            .map(|(source, (problem, _))| (source.clone(), problem.clone()))
+            .collect()
+    }
+
+    // The latest problem not expired.
+    fn problem(&self, now: Instant) -> Option<&AgentValue> {
+        self.problems
+            .values()
+            .filter(|(_, at)| self.problem_ttl.is_none_or(|ttl| now < *at + ttl))
+            .max_by_key(|(_, at)| *at)
+            .map(|(problem, _)| problem)
+    }
+}
+
+/// The source of a value on `problem` or `ok`: its `source` key, or else the agent that
+/// output it when the context carries provenance.
+fn problem_source(ctx: &AgentContext, value: &AgentValue) -> String {
+    if let Some(source) = value.get_str(KEY_SOURCE) {
+        return source.to_string();
+    }
+    ctx.get_var(VAR_PROVENANCE)
+        .and_then(|path| path.as_array())
+        .and_then(|path| path.back())
+        .and_then(|step| step.get_str(KEY_AGENT))
+        .unwrap_or_default()
+        .to_string()
+}
+
+/// Returns the status code and the JSON body for a probe of `path`.
+///
+/// Problems degrade the status, but only fail readiness: restarting the process would not
+/// fix what the checks report.
+fn health_response(
+    path: &str,
+    started: usize,
+    total: usize,
+    problem: Option<&AgentValue>,
+    problems: HashMap<String, AgentValue>,
+) -> (u16, String) {
+    let ready = started == total;
+    let (code, status) = match path {
+        "/healthz" | "/livez" if problem.is_some() => (200, "degraded"),
+        "/healthz" | "/livez" => (200, "ok"),
+        "/readyz" if problem.is_some() => (503, "degraded"),
+        "/readyz" if !ready => (503, "starting"),
+        "/readyz" => (200, "ok"),
+        _ => return (404, "{\"status\":\"not found\"}".to_string()),
+    };
+    let body = AgentValue::object(hashmap! {
+        KEY_STATUS.to_string() => AgentValue::string(status),
+        KEY_AGENTS.to_string() => AgentValue::object(hashmap! {
+            KEY_STARTED.to_string() => AgentValue::integer(started as i64),
+            KEY_TOTAL.to_string() => AgentValue::integer(total as i64),
+        }),
+        KEY_PROBLEM.to_string() => problem.cloned().unwrap_or_default(),
+        KEY_PROBLEMS.to_string() => AgentValue::object(problems.into_iter().collect()),
+    });
+    (code, body.to_json().to_string())
+}
+
+/// Counts the started agents of the preset, out of the enabled ones.
+///
+/// Agents still busy processing when `STATUS_TIMEOUT` has passed are not counted.
+async fn started_agents(ma: &ModularAgent, preset_id: &str) -> Result<(usize, usize), AgentError> {
+    let mut started = 0;
+    let mut total = 0;
+    let deadline = Instant::now() + STATUS_TIMEOUT;
+    for spec in preset_agents(ma, preset_id).await? {
+        if spec.disabled {
+            continue;
+        }
+        total += 1;
+        let Some(agent) = ma.get_agent(&spec.id) else {
+            continue;
+        };
+        if tokio::time::timeout_at(deadline, agent.lock())
+            .await
+            .is_ok_and(|agent| *agent.status() == AgentStatus::Start)
+        {
+            started += 1;
+        }
+    }
+    Ok((started, total))
+}
+
+/// Reads the request line of an HTTP request, and returns its method and path.
+async fn read_request<S: AsyncRead + Unpin>(stream: &mut S) -> Option<(String, String)> {
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 1024];
+    while !buf.windows(4).any(|w| w == b"\r\n\r\n") {
+        if buf.len() > MAX_REQUEST_SIZE {
+            return None;
+        }
+        let n = stream.read(&mut chunk).await.ok()?;
+        if n == 0 {
+            break;
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    }
+    let text = String::from_utf8_lossy(&buf);
+    let mut parts = text.lines().next()?.split_whitespace();
+    let method = parts.next()?.to_string();
+    let target = parts.next()?;
+    let path = target.split('?').next().unwrap_or_default().to_string();
+    Some((method, path))
+}
+
+async fn serve_probe<S: AsyncRead + AsyncWrite + Unpin>(
+    mut stream: S,
+    ma: &ModularAgent,
+    preset_id: &str,
+    state: &Mutex<HealthState>,
+) -> std::io::Result<()> {
+    let Ok(Some((method, path))) =
+        tokio::time::timeout(REQUEST_TIMEOUT, read_request(&mut stream)).await
+    else {
+        return Ok(());
+    };
+    let (code, body) = if method != "GET" && method != "HEAD" {
+        (405, "{\"status\":\"method not allowed\"}".to_string())
+    } else {
+        let (started, total) = started_agents(ma, preset_id).await.unwrap_or_else(|e| {
+            log::warn!("Failed to read the agents of preset {}: {}", preset_id, e);
+            (0, 1)
+        });
+        let (problem, problems) = {
+            let state = state.lock().unwrap();
+            let now = Instant::now();
+            (state.problem(now).cloned(), state.problems(now))
+        };
+        health_response(&path, started, total, problem.as_ref(), problems)
+    };
+    let reason = match code {
+        200 => "OK",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        _ => "Service Unavailable",
+    };
+    let mut response = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        code,
+        reason,
+        body.len()
+    );
+    if method != "HEAD" {
+        response.push_str(&body);
+    }
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+/// Serves the health of this preset over HTTP, for probes of container orchestrators.
+///
+/// `GET /healthz` (or `/livez`) answers 200 while the server runs, and `GET /readyz`
+/// answers 200 once every enabled agent of the preset is started and no problem is
+/// reported, 503 otherwise. The body is `{status, agents: {started, total}, problem,
+/// problems}`, where `status` is `ok`, `starting` or `degraded`, `problem` is the latest
+/// problem and `problems` the problems by source.
+///
+/// A value on `problem` (ex. from an Alarm) reports a problem of its source until a value
+/// on `ok` from the same source, or until `problem ttl` passes (blank: until `ok`), so
+/// that checks which repeat their reports while failing can be connected directly. The
+/// source is the `source` key of the value, or else the agent that output it when the
+/// context carries provenance.
+#[modular_agent(
+    title = "Health Server",
+    category = CATEGORY,
+    inputs = [PORT_PROBLEM, PORT_OK],
+    string_config(name = CONFIG_BIND, default = BIND_DEFAULT),
+    integer_config(name = CONFIG_PORT, default = HEALTH_PORT_DEFAULT),
+    string_config(name = CONFIG_PROBLEM_TTL, title = "problem ttl", description = "(ex. 30s) empty: until ok"),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
+    hint(color=4),
+)]
+struct HealthServerAgent {
+    data: AgentData,
+    contract: InputContract,
+    state: Arc<Mutex<HealthState>>,
+    handle: Option<JoinHandle<()>>,
+    // the address listened on
+    addr: Option<String>,
+}
+
+impl HealthServerAgent {
+    fn update_ttl(&mut self) -> Result<(), AgentError> {
+        let ttl = self.configs()?.get_string_or_default(CONFIG_PROBLEM_TTL);
+        let ttl = if ttl.trim().is_empty() {
+            None
+        } else {
+            Some(Duration::from_millis(parse_duration_to_ms(&ttl)?))
+        };
+        self.state.lock().unwrap().problem_ttl = ttl;
+        Ok(())
+    }
+
+    fn addr(&self) -> Result<String, AgentError> {
+        let configs = self.configs()?;
+        let bind = configs.get_string_or(CONFIG_BIND, BIND_DEFAULT);
+        let port = configs.get_integer_or(CONFIG_PORT, HEALTH_PORT_DEFAULT);
+        let port = u16::try_from(port)
+            .map_err(|_| AgentError::InvalidConfig(format!("Invalid port: {}", port)))?;
+        Ok(format!("{}:{}", bind.trim(), port))
+    }
+
+    fn start_server(&mut self) -> Result<(), AgentError> {
+        let addr = self.addr()?;
+        // bound here, so that a port in use fails the agent
+        let listener = std::net::TcpListener::bind(&addr)
+            .and_then(|listener| listener.set_nonblocking(true).map(|_| listener))
+            .map_err(|e| AgentError::IoError(format!("Failed to listen on {}: {}", addr, e)))?;
+        log::info!("Health server listening on {}", addr);
+
+        let ma = self.ma().clone();
+        let preset_id = self.preset_id().to_string();
+        let state = self.state.clone();
+        self.addr = Some(addr.clone());
+        self.handle = Some(self.runtime().spawn(async move {
+            let listener = match TcpListener::from_std(listener) {
+                Ok(listener) => listener,
+                Err(e) => {
+                    log::error!("Health server failed to listen on {}: {}", addr, e);
+                    return;
+                }
+            };
+            loop {
+                let stream = match listener.accept().await {
+                    Ok((stream, _)) => stream,
+                    Err(e) => {
+                        log::warn!("Health server failed to accept: {}", e);
+                        continue;
+                    }
+                };
+                let ma = ma.clone();
+                let preset_id = preset_id.clone();
+                let state = state.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = serve_probe(stream, &ma, &preset_id, &state).await {
+                        log::debug!("Health probe failed: {}", e);
+                    }
+                });
+            }
+        }));
+        Ok(())
+    }
+
+    // Aborts the server task, and returns it to wait for.
+    fn abort_server(&mut self) -> Option<JoinHandle<()>> {
+        self.addr = None;
+        let handle = self.handle.take()?;
+        handle.abort();
+        Some(handle)
+    }
+
+    async fn stop_server(&mut self) {
+        if let Some(handle) = self.abort_server() {
+            // the task owns the listener; wait for it to close the port
+            let _ = handle.await;
+        }
+    }
+}
+
+#[async_trait]
+impl AsAgent for HealthServerAgent {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
+        Ok(Self {
+            data: AgentData::new(ma, id, spec),
+            contract,
+            state: Default::default(),
+            handle: None,
+            addr: None,
+        })
+    }
+
+    async fn start(&mut self) -> Result<(), AgentError> {
+        self.state.lock().unwrap().problems.clear();
+        self.update_ttl()?;
+        self.start_server()
+    }
+
+    async fn stop(&mut self) -> Result<(), AgentError> {
+        self.stop_server().await;
+        Ok(())
+    }
+
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()?;
+        self.update_ttl()?;
+        if self.handle.is_some() && self.addr.as_deref() != Some(self.addr()?.as_str()) {
+            // the new address is another one, so the old listener may close after it binds
+            self.abort_server();
+            self.start_server()?;
+        }
+        Ok(())
+    }
+
+    async fn process(
+        &mut self,
+        ctx: AgentContext,
+        port: String,
+        value: AgentValue,
+    ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
+        let source = problem_source(&ctx, &value);
+        let mut state = self.state.lock().unwrap();
+        match port.as_str() {
+            PORT_PROBLEM => {
+                if !state.problems(Instant::now()).contains_key(&source) {
+                    log::warn!(
+                        "Health of preset {} degraded: {}",
+                        self.preset_id(),
+                        value.to_json()
+                    );
+                }
+                state.problems.insert(source, (value, Instant::now()));
+            }
+            PORT_OK => {
+                if state.problems.remove(&source).is_some() {
+                    log::info!(
+                        "Health of preset {} recovered from {}",
+                        self.preset_id(),
+                        if source.is_empty() {
+                            "its problem"
+                        } else {
+                            &source
+                        }
+                    );
+                }
+            }
+            _ => return Err(AgentError::InvalidPin(port)),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use modular_agent_core::AgentConfigSpec;
+
+    fn config_spec(type_: &str, readonly: bool) -> AgentConfigSpec {
+        AgentConfigSpec {
+            type_: Some(type_.to_string()),
+            readonly,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_check_config_updates() {
+        let mut specs = AgentConfigSpecs::default();
+        specs.insert("interval".to_string(), config_spec("integer", false));
+        specs.insert("rate".to_string(), config_spec("number", false));
+        specs.insert("name".to_string(), config_spec("string", false));
+        specs.insert("restarts".to_string(), config_spec("integer", true));
+
+        let update = |key: &str, value: AgentValue| vec![(key.to_string(), value)];
+        assert!(check_config_updates(&specs, &update("interval", AgentValue::integer(5))).is_ok());
+        assert!(check_config_updates(&specs, &update("rate", AgentValue::integer(5))).is_ok());
+        assert!(check_config_updates(&specs, &update("rate", AgentValue::number(0.5))).is_ok());
+        assert!(
+            check_config_updates(&specs, &update("interval", AgentValue::string("5"))).is_err()
+        );
+        assert!(check_config_updates(&specs, &update("name", AgentValue::integer(5))).is_err());
+        assert!(check_config_updates(&specs, &update("restarts", AgentValue::integer(0))).is_err());
+        assert!(check_config_updates(&specs, &update("unknown", AgentValue::unit())).is_err());
+
+        let agents: Vec<(String, String, String)> = [
+            ("1", "std::ThrottleAgent", "Throttle"),
+            ("2", "std::SetValueAgent", "Set Value"),
+            ("3", "std::SetValueAgent", "Set Value"),
+        ]
+        .iter()
+        .map(|(id, def_name, title)| (id.to_string(), def_name.to_string(), title.to_string()))
+        .collect();
+        assert_eq!(find_agent(&agents, "Throttle").unwrap(), "1");
+        assert_eq!(find_agent(&agents, "std::ThrottleAgent").unwrap(), "1");
+        assert_eq!(find_agent(&agents, "3").unwrap(), "3");
+        assert!(matches!(
+            find_agent(&agents, "Set Value"),
+            Err(AgentError::InvalidConfig(_))
+        ));
+        assert!(matches!(
+            find_agent(&agents, "Delay"),
+            Err(AgentError::AgentNotFound(_))
+        ));
+        assert!(find_agent(&agents, "").is_err());
+
+        let mut configs = AgentConfigs::new();
+        configs.set("interval".to_string(), AgentValue::integer(5));
+        assert_eq!(
+            configs_to_value(&configs),
+            AgentValue::object(hashmap! {"interval".to_string() => AgentValue::integer(5)})
+        );
+    }
+
+    #[test]
+    fn test_config_documents() {
+        let titles: Vec<String> = ["Integer Input", "Throttle", "Integer Input"]
+            .iter()
+            .map(|t| t.to_string())
+            .collect();
+        assert_eq!(
+            agent_keys(&titles),
+            vec!["Integer Input#1", "Throttle", "Integer Input#2"]
+        );
+
+        let agent = |key: &str, id: &str, def_name: &str, configs: &[(&str, AgentValue, bool)]| {
+            let mut agent_configs = AgentConfigs::new();
+            let mut config_specs = AgentConfigSpecs::default();
+            for (key, value, readonly) in configs {
+                agent_configs.set(key.to_string(), value.clone());
+                let type_ = if value.is_integer() {
+                    "integer"
+                } else {
+                    "string"
+                };
+                config_specs.insert(key.to_string(), config_spec(type_, *readonly));
+            }
+            DocumentAgent {
+                key: key.to_string(),
+                spec: AgentSpec {
+                    id: id.to_string(),
+                    def_name: def_name.to_string(),
+                    configs: Some(agent_configs),
+                    ..Default::default()
+                },
+                config_specs,
+            }
+        };
+        let input = format!("{}IntegerInputAgent", INPUT_DEF_PREFIX);
+        let agents = vec![
+            agent(
+                "Integer Input",
+                "1",
+                &input,
+                &[("integer", AgentValue::integer(3), false)],
+            ),
+            agent(
+                "Throttle",
+                "2",
+                "std::ThrottleAgent",
+                &[
+                    ("time", AgentValue::string("1s"), false),
+                    ("max_num_data", AgentValue::integer(10), false),
+                    ("task_restarts", AgentValue::integer(0), true),
+                ],
+            ),
+        ];
+
+        let doc = export_document(&agents, &[], "p").unwrap();
+        assert_eq!(
+            doc,
+            serde_json::json!({"preset": "p", "agents": {"Integer Input": {"integer": 3}}})
+        );
+        let doc = export_document(&agents, &["Throttle.time"], "p").unwrap();
+        assert_eq!(doc["agents"]["Throttle"], serde_json::json!({"time": "1s"}));
+        let doc = export_document(&agents, &["2"], "p").unwrap();
+        assert_eq!(
+            doc["agents"]["Throttle"],
+            serde_json::json!({"time": "1s", "max_num_data": 10})
+        );
+        assert!(export_document(&agents, &["Throttle.task_restarts"], "p").is_err());
+        assert!(export_document(&agents, &["Delay"], "p").is_err());
+
+        let text = document_to_string(&doc).unwrap();
+        assert_eq!(document_from_str(&text).unwrap(), doc);
+
+        let doc = serde_json::json!({"agents": {
+            "Integer Input": {"integer": 5},
+            "Throttle": {"time": "1s", "max_num_data": 20},
+        }});
+        let changes = import_changes(&agents, &doc).unwrap();
+        assert_eq!(changes.diff.len(), 2);
+        assert!(changes.diff.contains(&AgentValue::object(hashmap! {
+            "agent".to_string() => AgentValue::string("Throttle"),
+            "key".to_string() => AgentValue::string("max_num_data"),
+            "from".to_string() => AgentValue::integer(10),
+            "to".to_string() => AgentValue::integer(20),
+        })));
+        assert_eq!(changes.configs.len(), 2);
+        let (id, configs) = changes.configs.iter().find(|(id, _)| id == "2").unwrap();
+        assert_eq!(id, "2");
+        assert_eq!(configs.get("time").unwrap(), &AgentValue::string("1s"));
+        assert_eq!(
+            configs.get("max_num_data").unwrap(),
+            &AgentValue::integer(20)
+        );
+
+        // an invalid entry fails the whole document
+        let doc = serde_json::json!({"agents": {
+            "Integer Input": {"integer": 5},
+            "Throttle": {"task_restarts": 1},
+        }});
+        assert!(import_changes(&agents, &doc).is_err());
+        let doc = serde_json::json!({"agents": {"Delay": {"time": "1s"}}});
+        assert!(import_changes(&agents, &doc).is_err());
+        assert!(import_changes(&agents, &serde_json::json!({})).is_err());
+    }
+
+    #[test]
+    fn test_reloaded_configs() {
+        let spec = |id: &str, def_name: &str, configs: &[(&str, AgentValue)]| {
+            let mut agent_configs = AgentConfigs::new();
+            for (key, value) in configs {
+                agent_configs.set(key.to_string(), value.clone());
+            }
+            AgentSpec {
+                id: id.to_string(),
+                def_name: def_name.to_string(),
+                configs: Some(agent_configs),
+                ..Default::default()
+            }
+        };
+        let current = vec![
+            spec(
+                "1",
+                "Throttle",
+                &[
+                    ("interval", AgentValue::integer(1)),
+                    ("restarts", AgentValue::integer(2)),
+                ],
+            ),
+            spec("2", "Delay", &[("delay", AgentValue::integer(10))]),
+        ];
+        let file = PresetSpec {
+            agents: vec![
+                spec("10", "Throttle", &[("interval", AgentValue::integer(5))]),
+                spec("11", "Delay", &[("delay", AgentValue::integer(10))]),
+            ],
+            ..Default::default()
+        };
+        let changes = reloaded_configs(&current, &file).unwrap();
+        assert_eq!(changes.len(), 1);
+        let (id, configs) = &changes[0];
+        assert_eq!(id, "1");
+        assert_eq!(configs.get_integer_or_default("interval"), 5);
+        assert_eq!(configs.get_integer_or_default("restarts"), 2);
+
+        let file = PresetSpec {
+            agents: vec![spec("10", "Throttle", &[])],
+            ..Default::default()
+        };
+        assert!(reloaded_configs(&current, &file).is_err());
+    }
+
+    #[test]
+    fn test_health_response() {
+        let problem = AgentValue::string("too hot");
+        let status = |path: &str, started: usize, problem: Option<&AgentValue>| {
+            let (code, body) = health_response(path, started, 3, problem, HashMap::new());
+            let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+            (code, body["status"].as_str().unwrap().to_string())
+        };
+        assert_eq!(status("/healthz", 1, None), (200, "ok".to_string()));
+        assert_eq!(status("/readyz", 1, None), (503, "starting".to_string()));
+        assert_eq!(status("/readyz", 3, None), (200, "ok".to_string()));
+        assert_eq!(
+            status("/readyz", 3, Some(&problem)),
+            (503, "degraded".to_string())
+        );
+        assert_eq!(
+            status("/livez", 3, Some(&problem)),
+            (200, "degraded".to_string())
+        );
+        assert_eq!(health_response("/", 3, 3, None, HashMap::new()).0, 404);
+
+        let now = Instant::now();
+        let other = AgentValue::string("too cold");
+        let mut state = HealthState {
+            problems: HashMap::from([
+                ("a".to_string(), (problem.clone(), now)),
+                (
+                    "b".to_string(),
+                    (other.clone(), now + Duration::from_secs(20)),
+                ),
+            ]),
+            problem_ttl: Some(Duration::from_secs(30)),
+        };
+        assert_eq!(state.problem(now + Duration::from_secs(10)), Some(&other));
+        assert_eq!(state.problems(now + Duration::from_secs(10)).len(), 2);
+        assert_eq!(
+            state.problems(now + Duration::from_secs(30)),
+            HashMap::from([("b".to_string(), other.clone())])
+        );
+        state.problems.remove("b");
+        assert_eq!(state.problem(now + Duration::from_secs(30)), None);
+        state.problem_ttl = None;
+        assert_eq!(
+            state.problem(now + Duration::from_secs(300)),
+            Some(&problem)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_read_request() {
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        client
+            .write_all(b"GET /readyz?verbose=1 HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        assert_eq!(
+            read_request(&mut server).await,
+            Some(("GET".to_string(), "/readyz".to_string()))
+        );
+
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        client.write_all(b"\r\n\r\n").await.unwrap();
+        assert_eq!(read_request(&mut server).await, None);
+    }
+}
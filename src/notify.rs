@@ -0,0 +1,575 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Local, NaiveTime, Utc};
+use cron::Schedule;
+use im::{Vector, hashmap};
+use modular_agent_core::{
+    Agent, AgentContext, AgentData, AgentError, AgentOutput, AgentSpec, AgentStatus, AgentValue,
+    AsAgent, ModularAgent, async_trait, modular_agent,
+};
+
+use crate::contract::{CONFIG_INPUT_CONTRACT, ContractAgent, InputContract, contract_agents};
+use crate::data::get_nested_value;
+use crate::provenance::{Traced, stamp};
+use crate::scheduler::{Timer, schedule};
+use crate::time::{next_schedule_deadline, parse_duration_to_ms};
+
+const CATEGORY: &str = "Std/Notify";
+
+const PORT_ALERT: &str = "alert";
+const PORT_DIGEST: &str = "digest";
+const PORT_FLUSH: &str = "flush";
+const PORT_SUPPRESSED: &str = "suppressed";
+
+const CONFIG_DEDUPE_KEY: &str = "dedupe_key";
+const CONFIG_DEDUPE_WINDOW: &str = "dedupe_window";
+const CONFIG_DEFAULT_CHANNEL: &str = "default_channel";
+const CONFIG_DIGEST_SCHEDULE: &str = "digest_schedule";
+const CONFIG_QUIET_BYPASS: &str = "quiet_bypass";
+const CONFIG_QUIET_HOURS: &str = "quiet_hours";
+const CONFIG_ROUTES: &str = "routes";
+const CONFIG_SEVERITY_KEY: &str = "severity_key";
+
+const DEFAULT_CHANNEL_DEFAULT: &str = "default";
+const DEDUPE_WINDOW_DEFAULT: &str = "5m";
+const QUIET_BYPASS_DEFAULT: &str = "critical";
+const SEVERITY_KEY_DEFAULT: &str = "severity";
+
+const REASON_DUPLICATE: &str = "duplicate";
+const REASON_QUIET_HOURS: &str = "quiet_hours";
+
+// max alerts suppressed by quiet hours kept in a digest
+const DIGEST_MAX_HELD: usize = 100;
+
+// Severities from the lowest. Unknown severities rank as info.
+const SEVERITIES: &[&str] = &["debug", "info", "warning", "error", "critical"];
+
+contract_agents!(NotifyRouterAgent);
+
+/// Notify Router
+///
+/// Routes alert objects to channels by their severity (at `severity key`). `routes` maps
+/// severities to channels, and each channel is an output pin; alerts with other
+/// severities go to `default channel`. `suppressed` and `digest` are not valid channels.
+///
+/// Alerts are suppressed, and output on `suppressed` as `{alert, reason, channel}`, when:
+///
+/// - they arrive in `quiet hours` (ex. `22:00-07:00`) and are below `quiet bypass`
+///   (severities: debug, info, warning, error, critical)
+/// - an alert with the same `dedupe key` (blank: the whole alert) was routed within
+///   `dedupe window` (empty: no dedupe)
+///
+/// A digest of the alerts since the last one is output on `digest` by `digest schedule`
+/// (cron, empty: none) or on `flush`, with the counts by severity and channel, the counts
+/// of suppressed alerts, and the alerts held during quiet hours.
+#[modular_agent(
+    title = "Notify Router",
+    category = CATEGORY,
+    inputs = [PORT_ALERT, PORT_FLUSH],
+    outputs = [DEFAULT_CHANNEL_DEFAULT, PORT_SUPPRESSED, PORT_DIGEST],
+    object_config(name = CONFIG_ROUTES, description = "severity -> channel"),
+    string_config(name = CONFIG_DEFAULT_CHANNEL, default = DEFAULT_CHANNEL_DEFAULT, title = "default channel"),
+    string_config(name = CONFIG_SEVERITY_KEY, default = SEVERITY_KEY_DEFAULT, title = "severity key"),
+    string_config(name = CONFIG_QUIET_HOURS, title = "quiet hours", description = "(ex. 22:00-07:00)"),
+    string_config(name = CONFIG_QUIET_BYPASS, default = QUIET_BYPASS_DEFAULT, title = "quiet bypass", description = "min severity sent in quiet hours"),
+    string_config(name = CONFIG_DEDUPE_KEY, title = "dedupe key"),
+    string_config(name = CONFIG_DEDUPE_WINDOW, default = DEDUPE_WINDOW_DEFAULT, title = "dedupe window", description = "(ex. 10s, 5m, 1h) empty: no dedupe"),
+    string_config(name = CONFIG_DIGEST_SCHEDULE, title = "digest schedule", description = "sec min hour day month week year"),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
+    hint(color=5),
+)]
+struct NotifyRouterAgent {
+    data: AgentData,
+    contract: InputContract,
+    rules: NotifyRules,
+    state: Arc<Mutex<NotifyState>>,
+    timer: Option<Timer>,
+}
+
+impl NotifyRouterAgent {
+    fn update_spec(spec: &mut AgentSpec) -> Result<NotifyRules, AgentError> {
+        let rules = match spec.configs.as_ref() {
+            Some(configs) => NotifyRules::new(
+                &configs.get_object_or_default(CONFIG_ROUTES),
+                &configs.get_string_or(CONFIG_DEFAULT_CHANNEL, DEFAULT_CHANNEL_DEFAULT),
+                &configs.get_string_or(CONFIG_SEVERITY_KEY, SEVERITY_KEY_DEFAULT),
+                &configs.get_string_or_default(CONFIG_QUIET_HOURS),
+                &configs.get_string_or(CONFIG_QUIET_BYPASS, QUIET_BYPASS_DEFAULT),
+                &configs.get_string_or_default(CONFIG_DEDUPE_KEY),
+                &configs.get_string_or(CONFIG_DEDUPE_WINDOW, DEDUPE_WINDOW_DEFAULT),
+            )?,
+            None => NotifyRules::default(),
+        };
+
+        let mut outputs: Vec<String> = rules.channels().into_iter().collect();
+        outputs.push(PORT_SUPPRESSED.to_string());
+        outputs.push(PORT_DIGEST.to_string());
+        spec.outputs = Some(outputs);
+
+        Ok(rules)
+    }
+
+    fn start_digest(&mut self) -> Result<(), AgentError> {
+        self.timer = None;
+        let schedule_str = self
+            .configs()?
+            .get_string_or_default(CONFIG_DIGEST_SCHEDULE);
+        if schedule_str.trim().is_empty() {
+            return Ok(());
+        }
+        let cron_schedule = Schedule::from_str(&schedule_str).map_err(|e| {
+            AgentError::InvalidConfig(format!("Invalid cron schedule '{}': {}", schedule_str, e))
+        })?;
+
+        let state = self.state.clone();
+        let ma = self.ma().clone();
+        let agent_id = self.id().to_string();
+        let def_name = self.def_name().to_string();
+        let Some(deadline) = next_schedule_deadline(&cron_schedule, &agent_id) else {
+            return Ok(());
+        };
+        let timer = schedule(self, deadline, move |_| {
+            let digest = state.lock().unwrap().take_digest(now_ms());
+            if let Err(e) = ma.try_send_agent_out(
+                agent_id.clone(),
+                stamp(AgentContext::new(), &agent_id, &def_name),
+                PORT_DIGEST.to_string(),
+                digest,
+            ) {
+                log::error!("Failed to send notify digest: {}", e);
+            }
+            next_schedule_deadline(&cron_schedule, &agent_id)
+        });
+        self.timer = Some(timer);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl AsAgent for NotifyRouterAgent {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let rules = Self::update_spec(&mut spec)?;
+        let contract = InputContract::update_spec(&mut spec)?;
+        Ok(Self {
+            data: AgentData::new(ma, id, spec),
+            contract,
+            rules,
+            state: Arc::new(Mutex::new(NotifyState::new(now_ms()))),
+            timer: None,
+        })
+    }
+
+    async fn start(&mut self) -> Result<(), AgentError> {
+        *self.state.lock().unwrap() = NotifyState::new(now_ms());
+        self.start_digest()
+    }
+
+    async fn stop(&mut self) -> Result<(), AgentError> {
+        // Dropping the timer cancels it
+        self.timer = None;
+        Ok(())
+    }
+
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        let rules = Self::update_spec(&mut self.data.spec)?;
+        self.reload_contract()?;
+        let channels_changed = rules.channels() != self.rules.channels();
+        self.rules = rules;
+        if channels_changed {
+            self.emit_agent_spec_updated();
+        }
+        if *self.status() == AgentStatus::Start {
+            self.start_digest()?;
+        }
+        Ok(())
+    }
+
+    async fn process(
+        &mut self,
+        ctx: AgentContext,
+        port: String,
+        value: AgentValue,
+    ) -> Result<(), AgentError> {
+        if port == PORT_FLUSH {
+            let digest = self.state.lock().unwrap().take_digest(now_ms());
+            return self.output(self.traced(ctx), PORT_DIGEST, digest).await;
+        }
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
+        if port != PORT_ALERT {
+            return Err(AgentError::InvalidPin(port));
+        }
+
+        let routing = {
+            let mut state = self.state.lock().unwrap();
+            self.rules.route(&mut state, &value, Local::now())
+        };
+        match routing {
+            Routing::Send(channel) => self.output(self.traced(ctx), channel, value).await,
+            Routing::Suppress(channel, reason) => {
+                let suppressed = AgentValue::object(hashmap! {
+                    "alert".to_string() => value,
+                    "reason".to_string() => AgentValue::string(reason),
+                    "channel".to_string() => AgentValue::string(channel),
+                });
+                self.output(self.traced(ctx), PORT_SUPPRESSED, suppressed)
+                    .await
+            }
+        }
+    }
+}
+
+fn now_ms() -> i64 {
+    Utc::now().timestamp_millis()
+}
+
+#[derive(Debug, PartialEq)]
+enum Routing {
+    Send(String),
+    Suppress(String, &'static str),
+}
+
+#[derive(Default)]
+struct NotifyRules {
+    routes: HashMap<String, String>,
+    default_channel: String,
+    severity_keys: Vec<String>,
+    quiet_hours: Option<(NaiveTime, NaiveTime)>,
+    quiet_bypass: usize,
+    dedupe_keys: Vec<String>,
+    dedupe_window_ms: Option<i64>,
+}
+
+impl NotifyRules {
+    fn new(
+        routes: &im::HashMap<String, AgentValue>,
+        default_channel: &str,
+        severity_key: &str,
+        quiet_hours: &str,
+        quiet_bypass: &str,
+        dedupe_key: &str,
+        dedupe_window: &str,
+    ) -> Result<Self, AgentError> {
+        let routes = routes
+            .iter()
+            .map(|(severity, channel)| {
+                let channel = channel.as_str().map(|c| c.trim()).unwrap_or_default();
+                if channel.is_empty() {
+                    return Err(AgentError::InvalidConfig(format!(
+                        "Invalid channel for severity '{}'",
+                        severity
+                    )));
+                }
+                Ok((severity.trim().to_lowercase(), channel.to_string()))
+            })
+            .collect::<Result<_, _>>()?;
+
+        let default_channel = default_channel.trim();
+        let default_channel = if default_channel.is_empty() {
+            DEFAULT_CHANNEL_DEFAULT
+        } else {
+            default_channel
+        };
+
+        let dedupe_window = dedupe_window.trim();
+        let dedupe_window_ms = if dedupe_window.is_empty() {
+            None
+        } else {
+            Some(parse_duration_to_ms(dedupe_window)? as i64)
+        };
+
+        let rules = Self {
+            routes,
+            default_channel: default_channel.to_string(),
+            severity_keys: key_path(severity_key),
+            quiet_hours: parse_quiet_hours(quiet_hours)?,
+            quiet_bypass: severity_rank(quiet_bypass),
+            dedupe_keys: key_path(dedupe_key),
+            dedupe_window_ms,
+        };
+        // channels are output pins next to these
+        if let Some(channel) = rules
+            .channels()
+            .into_iter()
+            .find(|c| c == PORT_SUPPRESSED || c == PORT_DIGEST)
+        {
+            return Err(AgentError::InvalidConfig(format!(
+                "Channel name '{}' is reserved",
+                channel
+            )));
+        }
+        Ok(rules)
+    }
+
+    fn channels(&self) -> BTreeSet<String> {
+        let mut channels: BTreeSet<String> = self.routes.values().cloned().collect();
+        channels.insert(self.default_channel.clone());
+        channels
+    }
+
+    fn route(&self, state: &mut NotifyState, alert: &AgentValue, now: DateTime<Local>) -> Routing {
+        let severity = get_nested_value(alert, &self.severity_keys)
+            .and_then(|v| v.as_str())
+            .unwrap_or_default()
+            .trim()
+            .to_lowercase();
+        let channel = self
+            .routes
+            .get(&severity)
+            .cloned()
+            .unwrap_or_else(|| self.default_channel.clone());
+        let now_ms = now.timestamp_millis();
+
+        state.total += 1;
+        *state.by_severity.entry(severity.clone()).or_default() += 1;
+
+        if let Some((from, to)) = self.quiet_hours
+            && in_quiet_hours(now.time(), from, to)
+            && severity_rank(&severity) < self.quiet_bypass
+        {
+            state.quiet += 1;
+            if state.held.len() < DIGEST_MAX_HELD {
+                state.held.push_back(alert.clone());
+            }
+            return Routing::Suppress(channel, REASON_QUIET_HOURS);
+        }
+
+        if let Some(window_ms) = self.dedupe_window_ms {
+            let key = if self.dedupe_keys.is_empty() {
+                Some(alert)
+            } else {
+                get_nested_value(alert, &self.dedupe_keys)
+            };
+            let key = key
+                .and_then(|v| serde_json::to_string(v).ok())
+                .unwrap_or_default();
+            state
+                .last_routed
+                .retain(|_, routed_at| now_ms - *routed_at < window_ms);
+            if state.last_routed.contains_key(&key) {
+                state.duplicates += 1;
+                return Routing::Suppress(channel, REASON_DUPLICATE);
+            }
+            state.last_routed.insert(key, now_ms);
+        }
+
+        *state.by_channel.entry(channel.clone()).or_default() += 1;
+        Routing::Send(channel)
+    }
+}
+
+// Counts for the digest, and the dedupe window.
+struct NotifyState {
+    since: i64,
+    total: i64,
+    by_severity: BTreeMap<String, i64>,
+    by_channel: BTreeMap<String, i64>,
+    quiet: i64,
+    duplicates: i64,
+    held: Vector<AgentValue>,
+    // when each dedupe key was last routed
+    last_routed: HashMap<String, i64>,
+}
+
+impl NotifyState {
+    fn new(now_ms: i64) -> Self {
+        Self {
+            since: now_ms,
+            total: 0,
+            by_severity: BTreeMap::new(),
+            by_channel: BTreeMap::new(),
+            quiet: 0,
+            duplicates: 0,
+            held: Vector::new(),
+            last_routed: HashMap::new(),
+        }
+    }
+
+    // Returns the digest since the last one, and starts a new one.
+    // The dedupe window is kept.
+    fn take_digest(&mut self, now_ms: i64) -> AgentValue {
+        let counts = |counts: &BTreeMap<String, i64>| {
+            AgentValue::object(
+                counts
+                    .iter()
+                    .map(|(k, n)| (k.clone(), AgentValue::integer(*n)))
+                    .collect(),
+            )
+        };
+        let digest = AgentValue::object(hashmap! {
+            "since".to_string() => AgentValue::integer(self.since),
+            "until".to_string() => AgentValue::integer(now_ms),
+            "total".to_string() => AgentValue::integer(self.total),
+            "severity".to_string() => counts(&self.by_severity),
+            "channel".to_string() => counts(&self.by_channel),
+            "quiet".to_string() => AgentValue::integer(self.quiet),
+            "duplicates".to_string() => AgentValue::integer(self.duplicates),
+            "held".to_string() => AgentValue::array(std::mem::take(&mut self.held)),
+        });
+        let last_routed = std::mem::take(&mut self.last_routed);
+        *self = Self::new(now_ms);
+        self.last_routed = last_routed;
+        digest
+    }
+}
+
+fn key_path(key: &str) -> Vec<String> {
+    let key = key.trim();
+    if key.is_empty() {
+        return Vec::new();
+    }
+    key.split('.').map(|s| s.to_string()).collect()
+}
+
+fn severity_rank(severity: &str) -> usize {
+    let severity = severity.trim().to_lowercase();
+    let severity = match severity.as_str() {
+        "warn" => "warning",
+        "fatal" => "critical",
+        s => s,
+    };
+    SEVERITIES.iter().position(|s| *s == severity).unwrap_or(1)
+}
+
+fn parse_quiet_hours(quiet_hours: &str) -> Result<Option<(NaiveTime, NaiveTime)>, AgentError> {
+    let quiet_hours = quiet_hours.trim();
+    if quiet_hours.is_empty() {
+        return Ok(None);
+    }
+    let invalid = || AgentError::InvalidConfig(format!("Invalid quiet hours: {}", quiet_hours));
+    let (from, to) = quiet_hours.split_once('-').ok_or_else(invalid)?;
+    let from = NaiveTime::parse_from_str(from.trim(), "%H:%M").map_err(|_| invalid())?;
+    let to = NaiveTime::parse_from_str(to.trim(), "%H:%M").map_err(|_| invalid())?;
+    Ok(Some((from, to)))
+}
+
+// The range wraps around midnight when `from` is after `to`.
+fn in_quiet_hours(time: NaiveTime, from: NaiveTime, to: NaiveTime) -> bool {
+    if from <= to {
+        from <= time && time < to
+    } else {
+        time >= from || time < to
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn alert(severity: &str, id: i64) -> AgentValue {
+        AgentValue::object(hashmap! {
+            "severity".to_string() => AgentValue::string(severity),
+            "id".to_string() => AgentValue::integer(id),
+        })
+    }
+
+    fn rules(quiet_hours: &str, dedupe_window: &str) -> NotifyRules {
+        let routes = hashmap! {
+            "critical".to_string() => AgentValue::string("pager"),
+            "error".to_string() => AgentValue::string("chat"),
+        };
+        NotifyRules::new(
+            &routes,
+            "email",
+            "severity",
+            quiet_hours,
+            "critical",
+            "id",
+            dedupe_window,
+        )
+        .unwrap()
+    }
+
+    fn at(hour: u32, sec: u32) -> DateTime<Local> {
+        Local.with_ymd_and_hms(2026, 1, 1, hour, 0, sec).unwrap()
+    }
+
+    #[test]
+    fn test_route_by_severity() {
+        let rules = rules("", "");
+        let mut state = NotifyState::new(0);
+        assert_eq!(
+            rules.channels().into_iter().collect::<Vec<_>>(),
+            vec!["chat", "email", "pager"]
+        );
+        assert_eq!(
+            rules.route(&mut state, &alert("CRITICAL", 1), at(12, 0)),
+            Routing::Send("pager".into())
+        );
+        assert_eq!(
+            rules.route(&mut state, &alert("info", 2), at(12, 0)),
+            Routing::Send("email".into())
+        );
+    }
+
+    #[test]
+    fn test_reserved_channels() {
+        let routes = hashmap! {"error".to_string() => AgentValue::string("digest")};
+        assert!(NotifyRules::new(&routes, "email", "severity", "", "critical", "", "").is_err());
+        assert!(
+            NotifyRules::new(
+                &hashmap! {},
+                "suppressed",
+                "severity",
+                "",
+                "critical",
+                "",
+                ""
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_quiet_hours_and_dedupe() {
+        let rules = rules("22:00-07:00", "10s");
+        let mut state = NotifyState::new(0);
+
+        // critical alerts bypass quiet hours
+        assert_eq!(
+            rules.route(&mut state, &alert("error", 1), at(23, 0)),
+            Routing::Suppress("chat".into(), REASON_QUIET_HOURS)
+        );
+        assert_eq!(
+            rules.route(&mut state, &alert("critical", 2), at(6, 0)),
+            Routing::Send("pager".into())
+        );
+
+        // the same id is collapsed within the window
+        assert_eq!(
+            rules.route(&mut state, &alert("error", 3), at(12, 0)),
+            Routing::Send("chat".into())
+        );
+        assert_eq!(
+            rules.route(&mut state, &alert("error", 3), at(12, 5)),
+            Routing::Suppress("chat".into(), REASON_DUPLICATE)
+        );
+        assert_eq!(
+            rules.route(&mut state, &alert("error", 3), at(12, 10)),
+            Routing::Send("chat".into())
+        );
+
+        let digest = state.take_digest(1_000);
+        assert_eq!(digest.get_i64("total"), Some(5));
+        assert_eq!(digest.get_i64("quiet"), Some(1));
+        assert_eq!(digest.get_i64("duplicates"), Some(1));
+        assert_eq!(digest.get("channel").unwrap().get_i64("chat"), Some(2));
+        assert_eq!(digest.get("held").unwrap().as_array().unwrap().len(), 1);
+        assert_eq!(state.take_digest(2_000).get_i64("total"), Some(0));
+    }
+
+    #[test]
+    fn test_parse_quiet_hours() {
+        assert!(parse_quiet_hours("").unwrap().is_none());
+        assert!(parse_quiet_hours("22:00").is_err());
+        let (from, to) = parse_quiet_hours("09:00-17:30").unwrap().unwrap();
+        let time = |h, m| NaiveTime::from_hms_opt(h, m, 0).unwrap();
+        assert!(in_quiet_hours(time(9, 0), from, to));
+        assert!(!in_quiet_hours(time(17, 30), from, to));
+    }
+}
@@ -0,0 +1,183 @@
+//! Environment profiles for configs.
+//!
+//! String configs may contain `${env:NAME}` and `${profile:key}` placeholders.
+//! `env` is looked up in the environment variables, `profile` in the profile file
+//! selected by the `MODULAR_AGENT_PROFILE` environment variable (a JSON file, or YAML
+//! with the `yaml` feature). Profile keys are dot-separated paths into the file.
+//! Unresolvable placeholders are config errors, so a missing variable fails fast
+//! instead of running with a literal `${...}`.
+
+use std::sync::LazyLock;
+
+use modular_agent_core::{AgentConfigs, AgentError, AgentValue};
+use regex::{Captures, Regex};
+
+use crate::data::get_nested_value;
+
+pub(crate) const PROFILE_ENV: &str = "MODULAR_AGENT_PROFILE";
+
+static PLACEHOLDER: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\$\{(env|profile):([^}]+)\}").unwrap());
+
+pub(crate) trait ProfileConfigs {
+    /// Gets a string config with its placeholders resolved.
+    fn get_string_resolved(&self, key: &str) -> Result<String, AgentError>;
+}
+
+impl ProfileConfigs for AgentConfigs {
+    fn get_string_resolved(&self, key: &str) -> Result<String, AgentError> {
+        resolve(&self.get_string(key)?)
+    }
+}
+
+/// Resolves the placeholders in `s`.
+pub(crate) fn resolve(s: &str) -> Result<String, AgentError> {
+    if !s.contains("${") {
+        return Ok(s.to_string());
+    }
+    let profile = if s.contains("${profile:") {
+        Some(load_profile()?)
+    } else {
+        None
+    };
+    resolve_with(
+        s,
+        |name| std::env::var(name).ok(),
+        |keys| {
+            profile
+                .as_ref()
+                .and_then(|p| get_nested_value(p, keys).cloned())
+        },
+    )
+}
+
+/// Replaces the placeholders of a Handlebars `template` by references to
+/// `placeholders.[i]`, and returns it with the placeholders as written.
+///
+/// The template is then rendered with the placeholders, resolved or not, as
+/// `placeholders`, so that the resolved values are not parsed as templates and the
+/// rendered data is not resolved.
+#[cfg(feature = "http")]
+pub(crate) fn placeholder_template(template: &str) -> (String, Vec<String>) {
+    let mut placeholders = Vec::new();
+    let template = PLACEHOLDER.replace_all(template, |caps: &Captures| {
+        placeholders.push(caps[0].to_string());
+        format!("{{{{{{placeholders.[{}]}}}}}}", placeholders.len() - 1)
+    });
+    (template.into_owned(), placeholders)
+}
+
+/// Resolves the placeholders in the strings of `value`, recursively.
+pub(crate) fn resolve_value(value: &AgentValue) -> Result<AgentValue, AgentError> {
+    match value {
+        AgentValue::String(s) => Ok(AgentValue::string(resolve(s)?)),
+        AgentValue::Array(arr) => Ok(AgentValue::array(
+            arr.iter().map(resolve_value).collect::<Result<_, _>>()?,
+        )),
+        AgentValue::Object(obj) => Ok(AgentValue::object(
+            obj.iter()
+                .map(|(k, v)| Ok((k.clone(), resolve_value(v)?)))
+                .collect::<Result<_, AgentError>>()?,
+        )),
+        other => Ok(other.clone()),
+    }
+}
+
+fn resolve_with(
+    s: &str,
+    env: impl Fn(&str) -> Option<String>,
+    profile: impl Fn(&[&str]) -> Option<AgentValue>,
+) -> Result<String, AgentError> {
+    let mut err = None;
+    let resolved = PLACEHOLDER.replace_all(s, |caps: &Captures| {
+        let name = caps[2].trim();
+        let value = if &caps[1] == "env" {
+            env(name)
+        } else {
+            let keys: Vec<&str> = name.split('.').collect();
+            profile(&keys).map(|v| match v {
+                AgentValue::String(s) => s.to_string(),
+                v => serde_json::to_string(&v).unwrap_or_default(),
+            })
+        };
+        value.unwrap_or_else(|| {
+            err.get_or_insert_with(|| {
+                AgentError::InvalidConfig(format!("Unresolved placeholder: {}", &caps[0]))
+            });
+            String::new()
+        })
+    });
+    match err {
+        Some(e) => Err(e),
+        None => Ok(resolved.into_owned()),
+    }
+}
+
+fn load_profile() -> Result<AgentValue, AgentError> {
+    let path = std::env::var(PROFILE_ENV).map_err(|_| {
+        AgentError::InvalidConfig(format!(
+            "{} is not set for ${{profile:}} configs",
+            PROFILE_ENV
+        ))
+    })?;
+    let content = std::fs::read_to_string(&path).map_err(|e| {
+        AgentError::InvalidConfig(format!("Failed to read profile {}: {}", path, e))
+    })?;
+
+    #[cfg(feature = "yaml")]
+    if path.ends_with(".yaml") || path.ends_with(".yml") {
+        let value: serde_json::Value = serde_yaml_ng::from_str(&content).map_err(|e| {
+            AgentError::InvalidConfig(format!("Failed to parse profile {}: {}", path, e))
+        })?;
+        return AgentValue::from_json(value);
+    }
+
+    let value: serde_json::Value = serde_json::from_str(&content).map_err(|e| {
+        AgentError::InvalidConfig(format!("Failed to parse profile {}: {}", path, e))
+    })?;
+    AgentValue::from_json(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use im::hashmap;
+
+    use super::*;
+
+    #[cfg(feature = "http")]
+    #[test]
+    fn test_placeholder_template() {
+        assert_eq!(
+            placeholder_template("${env:HOST}/{{value.id}}?k=${profile:api.key}"),
+            (
+                "{{{placeholders.[0]}}}/{{value.id}}?k={{{placeholders.[1]}}}".to_string(),
+                vec!["${env:HOST}".to_string(), "${profile:api.key}".to_string()]
+            )
+        );
+    }
+
+    #[test]
+    fn test_resolve_with() {
+        let profile = AgentValue::object(hashmap! {
+            "api".to_string() => AgentValue::object(hashmap! {
+                "url".to_string() => AgentValue::string("https://staging"),
+                "port".to_string() => AgentValue::integer(8080),
+            }),
+        });
+        let env = |name: &str| (name == "HOME").then(|| "/home/me".to_string());
+        let prof = |keys: &[&str]| get_nested_value(&profile, keys).cloned();
+
+        assert_eq!(
+            resolve_with("${env:HOME}/data.json", env, prof).unwrap(),
+            "/home/me/data.json"
+        );
+        assert_eq!(
+            resolve_with("${profile:api.url}:${profile:api.port}/v1", env, prof).unwrap(),
+            "https://staging:8080/v1"
+        );
+        // other placeholders are left as is
+        assert_eq!(resolve_with("${name}", env, prof).unwrap(), "${name}");
+        assert!(resolve_with("${env:MISSING}", env, prof).is_err());
+        assert!(resolve_with("${profile:api.key}", env, prof).is_err());
+    }
+}
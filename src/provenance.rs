@@ -0,0 +1,130 @@
+//! Value provenance.
+//!
+//! When the `MODULAR_AGENT_PROVENANCE` environment variable is set (to anything but
+//! empty, `0` or `false`), the agents of this crate append an entry
+//! `{agent, def_name, time}` to the `provenance` variable of the context of every value
+//! they output, so the context records the path the value took.
+//! A context that already carries provenance is traced even when the variable is not set,
+//! so a single flow can opt in by feeding a context with an empty `provenance` array.
+//! The Provenance display agent renders the recorded path.
+
+use std::sync::LazyLock;
+
+use chrono::{DateTime, Local, Utc};
+use im::hashmap;
+use modular_agent_core::{Agent, AgentContext, AgentValue};
+
+pub(crate) const PROVENANCE_ENV: &str = "MODULAR_AGENT_PROVENANCE";
+
+pub(crate) const VAR_PROVENANCE: &str = "provenance";
+
+const KEY_AGENT: &str = "agent";
+const KEY_DEF_NAME: &str = "def_name";
+const KEY_TIME: &str = "time";
+
+static ENABLED: LazyLock<bool> = LazyLock::new(|| {
+    std::env::var(PROVENANCE_ENV)
+        .map(|v| !matches!(v.trim(), "" | "0" | "false"))
+        .unwrap_or(false)
+});
+
+pub(crate) trait Traced: Agent {
+    /// Appends this agent to the provenance of `ctx`, if provenance is enabled.
+    fn traced(&self, ctx: AgentContext) -> AgentContext {
+        stamp(ctx, self.id(), self.def_name())
+    }
+}
+
+impl<T: Agent> Traced for T {}
+
+/// Appends the agent to the provenance of `ctx`, if provenance is enabled.
+///
+/// For values output from tasks, where the agent itself is not available.
+pub(crate) fn stamp(ctx: AgentContext, agent_id: &str, def_name: &str) -> AgentContext {
+    if !*ENABLED && ctx.get_var(VAR_PROVENANCE).is_none() {
+        return ctx;
+    }
+    stamp_at(ctx, agent_id, def_name, Utc::now().timestamp_millis())
+}
+
+fn stamp_at(ctx: AgentContext, agent_id: &str, def_name: &str, time: i64) -> AgentContext {
+    let mut path = ctx
+        .get_var(VAR_PROVENANCE)
+        .and_then(|v| v.as_array())
+        .cloned()
+        .unwrap_or_default();
+    path.push_back(AgentValue::object(hashmap! {
+        KEY_AGENT.to_string() => AgentValue::string(agent_id),
+        KEY_DEF_NAME.to_string() => AgentValue::string(def_name),
+        KEY_TIME.to_string() => AgentValue::integer(time),
+    }));
+    ctx.with_var(VAR_PROVENANCE.to_string(), AgentValue::array(path))
+}
+
+/// Renders the provenance of `ctx`, one step per line, with the time since the first step.
+pub(crate) fn render_path(ctx: &AgentContext) -> String {
+    let Some(path) = ctx.get_var(VAR_PROVENANCE).and_then(|v| v.as_array()) else {
+        return String::new();
+    };
+    let start = path
+        .front()
+        .and_then(|step| step.get_i64(KEY_TIME))
+        .unwrap_or_default();
+    path.iter()
+        .enumerate()
+        .map(|(i, step)| {
+            let def_name = step.get_str(KEY_DEF_NAME).unwrap_or_default();
+            let agent = step.get_str(KEY_AGENT).unwrap_or_default();
+            let time = step.get_i64(KEY_TIME).unwrap_or_default();
+            if i == 0 {
+                let time = DateTime::from_timestamp_millis(time)
+                    .map(|t| {
+                        t.with_timezone(&Local)
+                            .format("%Y-%m-%d %H:%M:%S%.3f")
+                            .to_string()
+                    })
+                    .unwrap_or_default();
+                format!("{}. {} ({}) {}", i + 1, def_name, agent, time)
+            } else {
+                format!("{}. {} ({}) +{}ms", i + 1, def_name, agent, time - start)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stamp_at() {
+        let ctx = stamp_at(AgentContext::new(), "a1", "std::Source", 1_000);
+        let ctx = stamp_at(ctx, "a2", "std::Sink", 1_250);
+
+        let path = ctx.get_var(VAR_PROVENANCE).unwrap().as_array().unwrap();
+        assert_eq!(path.len(), 2);
+        assert_eq!(path[1].get_str(KEY_AGENT), Some("a2"));
+        assert_eq!(path[1].get_i64(KEY_TIME), Some(1_250));
+
+        let rendered = render_path(&ctx);
+        let lines: Vec<&str> = rendered.lines().collect();
+        assert!(lines[0].starts_with("1. std::Source (a1) "));
+        assert_eq!(lines[1], "2. std::Sink (a2) +250ms");
+    }
+
+    #[test]
+    fn test_stamp_keeps_existing_provenance() {
+        let ctx =
+            AgentContext::new().with_var(VAR_PROVENANCE.to_string(), AgentValue::array_default());
+        let ctx = stamp(ctx, "a1", "std::Source");
+        assert_eq!(
+            ctx.get_var(VAR_PROVENANCE)
+                .unwrap()
+                .as_array()
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+}
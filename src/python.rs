@@ -0,0 +1,362 @@
+#![cfg(feature = "python")]
+
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::time::Duration;
+
+use im::hashmap;
+use modular_agent_core::{
+    Agent, AgentContext, AgentData, AgentError, AgentOutput, AgentSpec, AgentValue, AsAgent,
+    ModularAgent, async_trait, modular_agent,
+};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::process::{Child, ChildStdin, ChildStdout, Command};
+
+use crate::contract::{CONFIG_INPUT_CONTRACT, ContractAgent, InputContract, contract_agents};
+use crate::profile::ProfileConfigs;
+use crate::provenance::Traced;
+use crate::time::parse_duration_to_ms;
+
+const CATEGORY: &str = "Std/Script";
+
+const PORT_VALUE: &str = "value";
+
+const CONFIG_CODE: &str = "code";
+const CONFIG_ENV: &str = "env";
+const CONFIG_FUNCTION: &str = "function";
+const CONFIG_PYTHON: &str = "python";
+const CONFIG_TIMEOUT: &str = "timeout";
+const CONFIG_VENV: &str = "venv";
+
+const FUNCTION_DEFAULT: &str = "transform";
+const PYTHON_DEFAULT: &str = "python3";
+const TIMEOUT_DEFAULT: &str = "10s";
+
+// Runs in the sidecar. Reads the code and the function name on the first line, then
+// answers each `{"value": ...}` line with `{"value": ...}` or `{"error": ...}`.
+// The user code prints to stderr, as stdout carries the responses.
+const SIDECAR: &str = r#"
+import json, sys, traceback
+_out = sys.stdout
+sys.stdout = sys.stderr
+def _send(msg):
+    _out.write(json.dumps(msg) + "\n")
+    _out.flush()
+_init = json.loads(sys.stdin.readline())
+try:
+    _globals = {"__name__": "__agent__"}
+    exec(compile(_init["code"], "<agent>", "exec"), _globals)
+    _fn = _globals[_init["function"]]
+    _send({"value": None})
+except BaseException:
+    _send({"error": traceback.format_exc()})
+    sys.exit(1)
+for _line in sys.stdin:
+    try:
+        _send({"value": _fn(json.loads(_line)["value"])})
+    except BaseException:
+        _send({"error": traceback.format_exc()})
+"#;
+
+contract_agents!(PythonAgent);
+
+/// Python
+///
+/// Calls `function` (default `transform(value)`) defined in `code` for each input, and
+/// outputs its return value. Nothing is output when it returns `None`.
+///
+/// The code runs in a Python process started with `python` (default `python3`), or with
+/// the interpreter of the virtual environment at `venv`, with the extra environment
+/// variables in `env`. Values are passed as JSON. Output of `print` goes to the log.
+///
+/// The process is kept between inputs, and restarted when it exits or a call takes
+/// longer than `timeout`.
+#[modular_agent(
+    title = "Python",
+    category = CATEGORY,
+    inputs = [PORT_VALUE],
+    outputs = [PORT_VALUE],
+    text_config(name = CONFIG_CODE),
+    string_config(name = CONFIG_FUNCTION, default = FUNCTION_DEFAULT),
+    string_config(name = CONFIG_PYTHON, default = PYTHON_DEFAULT, description = "interpreter"),
+    string_config(name = CONFIG_VENV, description = "virtual environment directory"),
+    object_config(name = CONFIG_ENV, description = "environment variables"),
+    string_config(name = CONFIG_TIMEOUT, default = TIMEOUT_DEFAULT, description = "(ex. 500ms, 10s)"),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
+    hint(color=3),
+)]
+struct PythonAgent {
+    data: AgentData,
+    contract: InputContract,
+    sidecar: Option<PythonSidecar>,
+}
+
+impl PythonAgent {
+    fn sidecar_config(&self) -> Result<SidecarConfig, AgentError> {
+        let configs = self.configs()?;
+        let venv = configs.get_string_resolved(CONFIG_VENV)?;
+        let python = if venv.trim().is_empty() {
+            PathBuf::from(configs.get_string_resolved(CONFIG_PYTHON)?)
+        } else if cfg!(windows) {
+            PathBuf::from(&venv).join("Scripts").join("python.exe")
+        } else {
+            PathBuf::from(&venv).join("bin").join("python")
+        };
+        let mut env: Vec<(String, String)> = configs
+            .get_object_or_default(CONFIG_ENV)
+            .iter()
+            .map(|(k, v)| {
+                let v = match v {
+                    AgentValue::String(s) => s.to_string(),
+                    v => v.to_json().to_string(),
+                };
+                (k.clone(), v)
+            })
+            .collect();
+        if !venv.trim().is_empty() {
+            env.push(("VIRTUAL_ENV".to_string(), venv));
+        }
+        Ok(SidecarConfig {
+            python,
+            env,
+            code: configs.get_string_or_default(CONFIG_CODE),
+            function: configs.get_string_or(CONFIG_FUNCTION, FUNCTION_DEFAULT),
+            timeout: Duration::from_millis(parse_duration_to_ms(
+                &configs.get_string_or(CONFIG_TIMEOUT, TIMEOUT_DEFAULT),
+            )?),
+        })
+    }
+}
+
+#[async_trait]
+impl AsAgent for PythonAgent {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
+        Ok(Self {
+            data: AgentData::new(ma, id, spec),
+            contract,
+            sidecar: None,
+        })
+    }
+
+    async fn stop(&mut self) -> Result<(), AgentError> {
+        // The process is killed on drop
+        self.sidecar = None;
+        Ok(())
+    }
+
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()?;
+        // Started again with the new code on the next input
+        self.sidecar = None;
+        Ok(())
+    }
+
+    async fn process(
+        &mut self,
+        ctx: AgentContext,
+        port: String,
+        value: AgentValue,
+    ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
+        let config = self.sidecar_config()?;
+        if config.code.trim().is_empty() {
+            return Ok(());
+        }
+        if self.sidecar.is_none() {
+            self.sidecar = Some(PythonSidecar::spawn(&config).await?);
+        }
+        let sidecar = self.sidecar.as_mut().unwrap();
+        let result = match tokio::time::timeout(config.timeout, sidecar.call(&value)).await {
+            // an exception in Python, the process keeps running
+            Ok(Ok(result)) => result?,
+            Ok(Err(e)) => {
+                self.sidecar = None;
+                return Err(e);
+            }
+            Err(_) => {
+                self.sidecar = None;
+                return Err(AgentError::InvalidValue(format!(
+                    "Python call timed out after {:?}",
+                    config.timeout
+                )));
+            }
+        };
+        if result.is_unit() {
+            return Ok(());
+        }
+        self.output(self.traced(ctx), PORT_VALUE, result).await
+    }
+}
+
+struct SidecarConfig {
+    python: PathBuf,
+    env: Vec<(String, String)>,
+    code: String,
+    function: String,
+    timeout: Duration,
+}
+
+struct PythonSidecar {
+    _child: Child,
+    stdin: ChildStdin,
+    stdout: Lines<BufReader<ChildStdout>>,
+}
+
+impl PythonSidecar {
+    async fn spawn(config: &SidecarConfig) -> Result<Self, AgentError> {
+        let mut child = Command::new(&config.python)
+            .arg("-u")
+            .arg("-c")
+            .arg(SIDECAR)
+            .envs(config.env.iter().map(|(k, v)| (k, v)))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| {
+                AgentError::InvalidConfig(format!(
+                    "Failed to start {}: {}",
+                    config.python.display(),
+                    e
+                ))
+            })?;
+
+        let stdin = child.stdin.take().unwrap();
+        let stdout = BufReader::new(child.stdout.take().unwrap()).lines();
+        let mut stderr = BufReader::new(child.stderr.take().unwrap()).lines();
+        tokio::spawn(async move {
+            while let Ok(Some(line)) = stderr.next_line().await {
+                log::info!("[python] {}", line);
+            }
+        });
+
+        let mut sidecar = Self {
+            _child: child,
+            stdin,
+            stdout,
+        };
+        let init = AgentValue::object(hashmap! {
+            CONFIG_CODE.to_string() => AgentValue::string(config.code.as_str()),
+            CONFIG_FUNCTION.to_string() => AgentValue::string(config.function.as_str()),
+        });
+        tokio::time::timeout(config.timeout, sidecar.request(&init))
+            .await
+            .map_err(|_| AgentError::InvalidValue("Python did not start in time".into()))??
+            .map_err(|e| AgentError::InvalidConfig(e.to_string()))?;
+        Ok(sidecar)
+    }
+
+    // The outer error is a failure of the process, the inner one an exception in Python.
+    async fn call(
+        &mut self,
+        value: &AgentValue,
+    ) -> Result<Result<AgentValue, AgentError>, AgentError> {
+        let request = AgentValue::object(hashmap! {
+            PORT_VALUE.to_string() => value.clone(),
+        });
+        self.request(&request).await
+    }
+
+    async fn request(
+        &mut self,
+        request: &AgentValue,
+    ) -> Result<Result<AgentValue, AgentError>, AgentError> {
+        let io_error = |e: std::io::Error| {
+            AgentError::InvalidValue(format!("Failed to talk to Python: {}", e))
+        };
+        let mut line = request.to_json().to_string();
+        line.push('\n');
+        self.stdin
+            .write_all(line.as_bytes())
+            .await
+            .map_err(io_error)?;
+        self.stdin.flush().await.map_err(io_error)?;
+
+        let Some(line) = self.stdout.next_line().await.map_err(io_error)? else {
+            return Err(AgentError::InvalidValue("Python process exited".into()));
+        };
+        let response: serde_json::Value = serde_json::from_str(&line).map_err(|e| {
+            AgentError::InvalidValue(format!("Invalid response from Python: {}", e))
+        })?;
+        if let Some(error) = response.get("error").and_then(|e| e.as_str()) {
+            return Ok(Err(AgentError::InvalidValue(format!(
+                "Python error: {}",
+                error.trim_end()
+            ))));
+        }
+        Ok(AgentValue::from_json(
+            response.get(PORT_VALUE).cloned().unwrap_or_default(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(code: &str) -> SidecarConfig {
+        SidecarConfig {
+            python: PathBuf::from(PYTHON_DEFAULT),
+            env: vec![("AGENT_SCALE".to_string(), "3".to_string())],
+            code: code.to_string(),
+            function: FUNCTION_DEFAULT.to_string(),
+            timeout: Duration::from_secs(10),
+        }
+    }
+
+    fn python_available() -> bool {
+        std::process::Command::new(PYTHON_DEFAULT)
+            .arg("--version")
+            .output()
+            .is_ok()
+    }
+
+    #[tokio::test]
+    async fn test_python_sidecar() {
+        if !python_available() {
+            return;
+        }
+
+        let code = r#"
+import os
+def transform(value):
+    print("called", value)
+    if value is None:
+        return None
+    return {"scaled": value["n"] * int(os.environ["AGENT_SCALE"])}
+"#;
+        let mut sidecar = PythonSidecar::spawn(&config(code)).await.unwrap();
+        let value = AgentValue::object(hashmap! {"n".to_string() => AgentValue::integer(2)});
+        let result = sidecar.call(&value).await.unwrap().unwrap();
+        assert_eq!(result.get_i64("scaled"), Some(6));
+        assert!(
+            sidecar
+                .call(&AgentValue::Unit)
+                .await
+                .unwrap()
+                .unwrap()
+                .is_unit()
+        );
+
+        // exceptions are reported, and the process keeps running
+        let err = sidecar
+            .call(&AgentValue::integer(1))
+            .await
+            .unwrap()
+            .unwrap_err();
+        assert!(err.to_string().contains("TypeError"), "{}", err);
+        assert!(sidecar.call(&value).await.unwrap().is_ok());
+
+        // invalid code fails to start
+        assert!(
+            PythonSidecar::spawn(&config("def transform(:"))
+                .await
+                .is_err()
+        );
+        assert!(PythonSidecar::spawn(&config("x = 1")).await.is_err());
+    }
+}
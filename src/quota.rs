@@ -0,0 +1,355 @@
+//! Graph-level quotas for source-like agents.
+//!
+//! A Quota agent sets limits on the values emitted per second by the agents of its
+//! preset that emit on their own (Interval Timer, Schedule Timer, Watch Images,
+//! Stdin Lines, Poll Job Status, SNMP Poll). They ask for admission before each emit; values over
+//! the quota are dropped, and the Quota agent outputs `quota_exceeded` once per second
+//! while values are dropped. Values are measured by the estimated length of their JSON,
+//! with images counted as their raw pixel bytes. With `per tenant`, each tenant id in the
+//! context of the values has its own quota.
+
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+use im::hashmap;
+use modular_agent_core::{
+    Agent, AgentContext, AgentData, AgentError, AgentSpec, AgentValue, AsAgent, ModularAgent,
+    async_trait, modular_agent,
+};
+
+use crate::provenance::stamp;
+use crate::tenant::{CONFIG_PER_TENANT, TenantMap, tenant_key};
+
+const CATEGORY: &str = "Std/Flow";
+
+const PORT_QUOTA_EXCEEDED: &str = "quota_exceeded";
+
+const CONFIG_MAX_BYTES: &str = "max_bytes";
+const CONFIG_MAX_MESSAGES: &str = "max_messages";
+
+const KEY_AGENT: &str = "agent";
+const KEY_BYTES: &str = "bytes";
+const KEY_MAX_BYTES: &str = "max_bytes";
+const KEY_MAX_MESSAGES: &str = "max_messages";
+const KEY_MESSAGES: &str = "messages";
+const KEY_TENANT: &str = "tenant";
+
+const QUOTA_WINDOW: Duration = Duration::from_secs(1);
+
+#[derive(Debug, PartialEq)]
+enum Admission {
+    Admitted,
+    // dropped; `signal` is true for the first drop in the window
+    Dropped { signal: bool },
+}
+
+/// Counts the values of the current window against the limits (0: unlimited).
+#[derive(Default)]
+struct QuotaWindow {
+    max_messages: u64,
+    max_bytes: u64,
+    start: Option<Instant>,
+    messages: u64,
+    bytes: u64,
+    signaled: bool,
+}
+
+impl QuotaWindow {
+    fn admit(&mut self, bytes: u64, now: Instant) -> Admission {
+        if self
+            .start
+            .is_none_or(|start| now.duration_since(start) >= QUOTA_WINDOW)
+        {
+            self.start = Some(now);
+            self.messages = 0;
+            self.bytes = 0;
+            self.signaled = false;
+        }
+        let over_messages = self.max_messages > 0 && self.messages + 1 > self.max_messages;
+        let over_bytes = self.max_bytes > 0 && self.bytes + bytes > self.max_bytes;
+        if over_messages || over_bytes {
+            let signal = !self.signaled;
+            self.signaled = true;
+            return Admission::Dropped { signal };
+        }
+        self.messages += 1;
+        self.bytes += bytes;
+        Admission::Admitted
+    }
+}
+
+struct PresetQuota {
+    ma: ModularAgent,
+    // the Quota agent
+    agent_id: String,
+    def_name: String,
+    max_messages: u64,
+    max_bytes: u64,
+    per_tenant: bool,
+    // windows by tenant id
+    windows: TenantMap<QuotaWindow>,
+}
+
+static QUOTAS: LazyLock<Mutex<HashMap<String, PresetQuota>>> = LazyLock::new(Default::default);
+
+/// Asks the quota of the preset whether `agent_id` may emit `value`.
+///
+/// Always true when the preset has no Quota agent running.
+pub(crate) fn admit(preset_id: &str, agent_id: &str, value: &AgentValue) -> bool {
+    admit_in(preset_id, agent_id, &AgentContext::new(), value)
+}
+
+/// Like [`admit`], for a value emitted in `ctx`, whose tenant id is used by a Quota
+/// agent with `per tenant`.
+pub(crate) fn admit_in(
+    preset_id: &str,
+    agent_id: &str,
+    ctx: &AgentContext,
+    value: &AgentValue,
+) -> bool {
+    let signal = {
+        let mut quotas = QUOTAS.lock().unwrap();
+        let Some(quota) = quotas.get_mut(preset_id) else {
+            return true;
+        };
+        let bytes = if quota.max_bytes > 0 {
+            estimated_bytes(value)
+        } else {
+            0
+        };
+        let tenant = tenant_key(ctx, quota.per_tenant);
+        let (max_messages, max_bytes) = (quota.max_messages, quota.max_bytes);
+        let window = quota
+            .windows
+            .get_or_insert_with(tenant.clone(), || QuotaWindow {
+                max_messages,
+                max_bytes,
+                ..Default::default()
+            });
+        match window.admit(bytes, Instant::now()) {
+            Admission::Admitted => return true,
+            Admission::Dropped { signal: false } => None,
+            Admission::Dropped { signal: true } => {
+                let mut exceeded = hashmap! {
+                    KEY_AGENT.to_string() => AgentValue::string(agent_id),
+                    KEY_MESSAGES.to_string() => AgentValue::integer(window.messages as i64),
+                    KEY_BYTES.to_string() => AgentValue::integer(window.bytes as i64),
+                    KEY_MAX_MESSAGES.to_string() => AgentValue::integer(window.max_messages as i64),
+                    KEY_MAX_BYTES.to_string() => AgentValue::integer(window.max_bytes as i64),
+                };
+                if quota.per_tenant {
+                    exceeded.insert(KEY_TENANT.to_string(), AgentValue::string(tenant));
+                }
+                let exceeded = AgentValue::object(exceeded);
+                Some((
+                    quota.ma.clone(),
+                    quota.agent_id.clone(),
+                    quota.def_name.clone(),
+                    exceeded,
+                ))
+            }
+        }
+    };
+    if let Some((ma, quota_id, def_name, exceeded)) = signal {
+        log::warn!("Quota exceeded by '{}'; dropping its values", agent_id);
+        if let Err(e) = ma.try_send_agent_out(
+            quota_id.clone(),
+            stamp(AgentContext::new(), &quota_id, &def_name),
+            PORT_QUOTA_EXCEEDED.to_string(),
+            exceeded,
+        ) {
+            log::error!("Failed to send quota exceeded: {}", e);
+        }
+    }
+    false
+}
+
+// Approximates the length of the JSON of the value without serializing it.
+// Images count as their raw pixel bytes.
+fn estimated_bytes(value: &AgentValue) -> u64 {
+    match value {
+        AgentValue::Unit => 4,
+        AgentValue::Boolean(b) => {
+            if *b {
+                4
+            } else {
+                5
+            }
+        }
+        AgentValue::Integer(i) => i.to_string().len() as u64,
+        AgentValue::Number(n) => n.to_string().len() as u64,
+        AgentValue::String(s) => s.len() as u64 + 2,
+        AgentValue::Image(image) => image.get_raw_pixels().len() as u64,
+        AgentValue::Array(arr) => arr.iter().map(|v| estimated_bytes(v) + 1).sum::<u64>() + 1,
+        AgentValue::Object(obj) => {
+            obj.iter()
+                .map(|(k, v)| k.len() as u64 + 4 + estimated_bytes(v))
+                .sum::<u64>()
+                + 1
+        }
+        AgentValue::Tensor(t) => t.len() as u64 * 8,
+        AgentValue::Message(_) | AgentValue::Error(_) => value.to_json().to_string().len() as u64,
+    }
+}
+
+/// Limits what the source-like agents of this preset emit per second.
+///
+/// `max messages` is the number of values, and `max bytes` their total size as JSON;
+/// 0 is unlimited. Values over the quota are dropped, and `{agent, messages, bytes,
+/// max_messages, max_bytes}` is output on `quota exceeded` once per second while they
+/// are. Use one Quota agent per preset.
+///
+/// With `per tenant`, the limits apply to each tenant id in the context separately (see
+/// Tenant), and `tenant` is added to `quota exceeded`. Only the sources that emit in an
+/// incoming context (Poll Job Status) carry a tenant; the others count as the empty one.
+#[modular_agent(
+    title = "Quota",
+    category = CATEGORY,
+    outputs = [PORT_QUOTA_EXCEEDED],
+    integer_config(name = CONFIG_MAX_MESSAGES, title = "max messages", description = "per second. 0: unlimited"),
+    integer_config(name = CONFIG_MAX_BYTES, title = "max bytes", description = "per second. 0: unlimited"),
+    boolean_config(name = CONFIG_PER_TENANT, title = "per tenant"),
+    hint(color=4),
+)]
+struct QuotaAgent {
+    data: AgentData,
+}
+
+impl QuotaAgent {
+    fn limits(&self) -> Result<(u64, u64, bool), AgentError> {
+        let configs = self.configs()?;
+        Ok((
+            configs.get_integer_or_default(CONFIG_MAX_MESSAGES).max(0) as u64,
+            configs.get_integer_or_default(CONFIG_MAX_BYTES).max(0) as u64,
+            configs.get_bool_or_default(CONFIG_PER_TENANT),
+        ))
+    }
+}
+
+#[async_trait]
+impl AsAgent for QuotaAgent {
+    fn new(ma: ModularAgent, id: String, spec: AgentSpec) -> Result<Self, AgentError> {
+        Ok(Self {
+            data: AgentData::new(ma, id, spec),
+        })
+    }
+
+    async fn start(&mut self) -> Result<(), AgentError> {
+        let (max_messages, max_bytes, per_tenant) = self.limits()?;
+        let quota = PresetQuota {
+            ma: self.ma().clone(),
+            agent_id: self.id().to_string(),
+            def_name: self.def_name().to_string(),
+            max_messages,
+            max_bytes,
+            per_tenant,
+            windows: TenantMap::default(),
+        };
+        let mut quotas = QUOTAS.lock().unwrap();
+        if let Some(old) = quotas.insert(self.preset_id().to_string(), quota)
+            && old.agent_id != self.id()
+        {
+            log::warn!(
+                "Quota '{}' replaces '{}' in the same preset",
+                self.id(),
+                old.agent_id
+            );
+        }
+        Ok(())
+    }
+
+    async fn stop(&mut self) -> Result<(), AgentError> {
+        let mut quotas = QUOTAS.lock().unwrap();
+        if quotas
+            .get(self.preset_id())
+            .is_some_and(|quota| quota.agent_id == self.id())
+        {
+            quotas.remove(self.preset_id());
+        }
+        Ok(())
+    }
+
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        let (max_messages, max_bytes, per_tenant) = self.limits()?;
+        let mut quotas = QUOTAS.lock().unwrap();
+        if let Some(quota) = quotas
+            .get_mut(self.preset_id())
+            .filter(|quota| quota.agent_id == self.id())
+        {
+            quota.max_messages = max_messages;
+            quota.max_bytes = max_bytes;
+            if per_tenant != quota.per_tenant {
+                quota.per_tenant = per_tenant;
+                quota.windows.clear();
+            }
+            for window in quota.windows.values_mut() {
+                window.max_messages = max_messages;
+                window.max_bytes = max_bytes;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quota_window() {
+        let t0 = Instant::now();
+        let at = |ms: u64| t0 + Duration::from_millis(ms);
+
+        let mut window = QuotaWindow {
+            max_messages: 2,
+            ..Default::default()
+        };
+        assert_eq!(window.admit(0, at(0)), Admission::Admitted);
+        assert_eq!(window.admit(0, at(100)), Admission::Admitted);
+        assert_eq!(
+            window.admit(0, at(200)),
+            Admission::Dropped { signal: true }
+        );
+        assert_eq!(
+            window.admit(0, at(300)),
+            Admission::Dropped { signal: false }
+        );
+        assert_eq!(window.admit(0, at(1000)), Admission::Admitted);
+
+        let mut window = QuotaWindow {
+            max_bytes: 10,
+            ..Default::default()
+        };
+        assert_eq!(window.admit(6, at(0)), Admission::Admitted);
+        assert_eq!(window.admit(6, at(10)), Admission::Dropped { signal: true });
+        assert_eq!(window.admit(4, at(20)), Admission::Admitted);
+        assert_eq!(
+            window.admit(1, at(30)),
+            Admission::Dropped { signal: false }
+        );
+
+        let mut window = QuotaWindow::default();
+        for i in 0..100 {
+            assert_eq!(window.admit(1000, at(i)), Admission::Admitted);
+        }
+
+        assert!(admit("no quota", "agent", &AgentValue::unit()));
+    }
+
+    #[test]
+    fn test_estimated_bytes() {
+        let value = AgentValue::object(hashmap! {
+            "a".to_string() => AgentValue::array(vec![
+                AgentValue::integer(12),
+                AgentValue::string("xyz"),
+                AgentValue::boolean(false),
+                AgentValue::unit(),
+            ].into()),
+        });
+        assert_eq!(
+            estimated_bytes(&value),
+            value.to_json().to_string().len() as u64
+        );
+    }
+}
@@ -0,0 +1,401 @@
+//! Shared timer service for time-based agents.
+//!
+//! Timers of all agents are kept in one queue served by a single task, instead of
+//! each agent spawning its own sleeping task. A timer runs its callback at its deadline;
+//! the callback returns the next deadline to run again, or `None` when it is done.
+//!
+//! Callbacks run on the scheduler task, so they must not block or await. They typically
+//! send outputs with `try_send_agent_out`. A panicking callback is logged and, for
+//! supervised timers, run again after a growing delay up to `max_restarts` times (see
+//! [`crate::supervisor`]).
+
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::panic::{AssertUnwindSafe, catch_unwind};
+use std::sync::{Arc, LazyLock, Mutex, OnceLock};
+
+use modular_agent_core::Agent;
+use tokio::sync::Notify;
+use tokio::time::Instant;
+
+use crate::supervisor::{panic_message, restart_delay, update_task_restarts};
+
+type Callback = Box<dyn FnMut(Instant) -> Option<Instant> + Send>;
+
+static SCHEDULER: LazyLock<Arc<Scheduler>> = LazyLock::new(|| Arc::new(Scheduler::default()));
+
+/// Runs `callback` at `deadline`, and again at each deadline it returns.
+///
+/// The callback gets the deadline it was scheduled for.
+/// The timer is cancelled when the returned [`Timer`] is dropped.
+pub(crate) fn schedule<F>(agent: &impl Agent, deadline: Instant, callback: F) -> Timer
+where
+    F: FnMut(Instant) -> Option<Instant> + Send + 'static,
+{
+    SCHEDULER.ensure_running(agent);
+    SCHEDULER.add(deadline, Box::new(callback), None)
+}
+
+/// Like [`schedule`], but a panicking callback is run again after a delay.
+///
+/// Restarts are counted in the agent's `task_restarts` config. After `max_restarts`
+/// restarts (-1: unlimited) the timer is left stopped.
+pub(crate) fn schedule_supervised<T, F>(
+    agent: &T,
+    max_restarts: i64,
+    deadline: Instant,
+    callback: F,
+) -> Timer
+where
+    T: Agent,
+    F: FnMut(Instant) -> Option<Instant> + Send + 'static,
+{
+    SCHEDULER.ensure_running(agent);
+    let ma = agent.ma().clone();
+    let agent_id = agent.id().to_string();
+    let supervision = Supervision {
+        agent_id: agent_id.clone(),
+        max_restarts,
+        restarts: 0,
+        on_restart: Box::new(move |restarts| {
+            let ma = ma.clone();
+            let agent_id = agent_id.clone();
+            tokio::spawn(async move {
+                update_task_restarts::<T>(&ma, &agent_id, restarts).await;
+            });
+        }),
+    };
+    SCHEDULER.add(deadline, Box::new(callback), Some(supervision))
+}
+
+/// Handle of a scheduled timer. Dropping it cancels the timer.
+pub(crate) struct Timer {
+    id: u64,
+    scheduler: Arc<Scheduler>,
+}
+
+impl Timer {
+    /// Returns true until the timer is done or cancelled.
+    pub(crate) fn is_active(&self) -> bool {
+        self.scheduler
+            .state
+            .lock()
+            .unwrap()
+            .entries
+            .contains_key(&self.id)
+    }
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        // dropped after unlocking, as the callback may own timers
+        let _entry = self
+            .scheduler
+            .state
+            .lock()
+            .unwrap()
+            .entries
+            .remove(&self.id);
+    }
+}
+
+struct Supervision {
+    agent_id: String,
+    max_restarts: i64,
+    restarts: i64,
+    on_restart: Box<dyn Fn(i64) + Send>,
+}
+
+struct Entry {
+    deadline: Instant,
+    // taken out while the callback runs
+    callback: Option<Callback>,
+    supervision: Option<Supervision>,
+}
+
+#[derive(Default)]
+struct State {
+    next_id: u64,
+    entries: HashMap<u64, Entry>,
+    // may contain stale deadlines of cancelled or rescheduled entries
+    queue: BinaryHeap<Reverse<(Instant, u64)>>,
+}
+
+impl State {
+    // Removes stale queue heads and returns the next deadline.
+    fn next_deadline(&mut self) -> Option<Instant> {
+        while let Some(Reverse((deadline, id))) = self.queue.peek() {
+            match self.entries.get(id) {
+                Some(entry) if entry.deadline == *deadline && entry.callback.is_some() => {
+                    return Some(*deadline);
+                }
+                _ => {
+                    self.queue.pop();
+                }
+            }
+        }
+        None
+    }
+}
+
+#[derive(Default)]
+struct Scheduler {
+    state: Mutex<State>,
+    notify: Notify,
+    running: OnceLock<()>,
+}
+
+impl Scheduler {
+    fn ensure_running(self: &Arc<Self>, agent: &impl Agent) {
+        self.running.get_or_init(|| {
+            agent.runtime().spawn(self.clone().run());
+        });
+    }
+
+    fn add(
+        self: &Arc<Self>,
+        deadline: Instant,
+        callback: Callback,
+        supervision: Option<Supervision>,
+    ) -> Timer {
+        let id = {
+            let mut state = self.state.lock().unwrap();
+            state.next_id += 1;
+            let id = state.next_id;
+            state.entries.insert(
+                id,
+                Entry {
+                    deadline,
+                    callback: Some(callback),
+                    supervision,
+                },
+            );
+            state.queue.push(Reverse((deadline, id)));
+            id
+        };
+        self.notify.notify_one();
+        Timer {
+            id,
+            scheduler: self.clone(),
+        }
+    }
+
+    async fn run(self: Arc<Self>) {
+        loop {
+            let next = self.state.lock().unwrap().next_deadline();
+            match next {
+                Some(deadline) => {
+                    tokio::select! {
+                        _ = tokio::time::sleep_until(deadline) => {}
+                        _ = self.notify.notified() => continue,
+                    }
+                }
+                None => {
+                    self.notify.notified().await;
+                    continue;
+                }
+            }
+            self.fire_due(Instant::now());
+        }
+    }
+
+    fn fire_due(&self, now: Instant) {
+        loop {
+            let (id, deadline, mut callback) = {
+                let mut state = self.state.lock().unwrap();
+                match state.next_deadline() {
+                    Some(deadline) if deadline <= now => {}
+                    _ => return,
+                }
+                let Reverse((deadline, id)) = state.queue.pop().unwrap();
+                let callback = state.entries.get_mut(&id).unwrap().callback.take();
+                (id, deadline, callback.unwrap())
+            };
+
+            let result = catch_unwind(AssertUnwindSafe(|| callback(deadline)));
+
+            // Removed entries are dropped after unlocking, as their callbacks may own timers.
+            let mut state = self.state.lock().unwrap();
+            let removed = Self::reschedule(&mut state, id, callback, result);
+            drop(state);
+            drop(removed);
+        }
+    }
+
+    fn reschedule(
+        state: &mut State,
+        id: u64,
+        callback: Callback,
+        result: std::thread::Result<Option<Instant>>,
+    ) -> Option<(Callback, Option<Entry>)> {
+        // cancelled while running
+        let Some(entry) = state.entries.get_mut(&id) else {
+            return Some((callback, None));
+        };
+        let next = match result {
+            Ok(next) => next,
+            Err(payload) => match entry.supervision.as_mut() {
+                None => {
+                    log::error!("Timer callback panicked: {}", panic_message(payload));
+                    None
+                }
+                Some(supervision) => {
+                    log::error!(
+                        "Timer of agent '{}' panicked: {}",
+                        supervision.agent_id,
+                        panic_message(payload)
+                    );
+                    if supervision.max_restarts >= 0
+                        && supervision.restarts >= supervision.max_restarts
+                    {
+                        log::error!(
+                            "Timer of agent '{}' is not restarted: max restarts ({}) reached",
+                            supervision.agent_id,
+                            supervision.max_restarts
+                        );
+                        None
+                    } else {
+                        supervision.restarts += 1;
+                        (supervision.on_restart)(supervision.restarts);
+                        Some(Instant::now() + restart_delay(supervision.restarts))
+                    }
+                }
+            },
+        };
+        match next {
+            Some(next) => {
+                entry.deadline = next;
+                entry.callback = Some(callback);
+                state.queue.push(Reverse((next, id)));
+                None
+            }
+            None => Some((callback, state.entries.remove(&id))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    use super::*;
+
+    fn start() -> Arc<Scheduler> {
+        let scheduler = Arc::new(Scheduler::default());
+        tokio::spawn(scheduler.clone().run());
+        scheduler
+    }
+
+    #[tokio::test]
+    async fn test_timers_fire_in_order() {
+        let scheduler = start();
+        let fired = Arc::new(Mutex::new(Vec::new()));
+        let now = Instant::now();
+
+        let mut timers = Vec::new();
+        for (name, ms) in [("b", 40), ("a", 20), ("c", 60)] {
+            let fired = fired.clone();
+            timers.push(scheduler.add(
+                now + Duration::from_millis(ms),
+                Box::new(move |_| {
+                    fired.lock().unwrap().push(name);
+                    None
+                }),
+                None,
+            ));
+        }
+        tokio::time::sleep(Duration::from_millis(150)).await;
+
+        assert_eq!(*fired.lock().unwrap(), vec!["a", "b", "c"]);
+        assert!(timers.iter().all(|t| !t.is_active()));
+    }
+
+    #[tokio::test]
+    async fn test_repeating_timer_and_cancel() {
+        let scheduler = start();
+        let count = Arc::new(AtomicUsize::new(0));
+
+        let c = count.clone();
+        let timer = scheduler.add(
+            Instant::now() + Duration::from_millis(10),
+            Box::new(move |deadline| {
+                c.fetch_add(1, Ordering::Relaxed);
+                Some(deadline + Duration::from_millis(10))
+            }),
+            None,
+        );
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(timer.is_active());
+        drop(timer);
+
+        let fired = count.load(Ordering::Relaxed);
+        assert!(fired >= 3, "fired {} times", fired);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(count.load(Ordering::Relaxed), fired);
+    }
+
+    #[tokio::test]
+    async fn test_panicking_callback_is_dropped() {
+        let scheduler = start();
+        let timer = scheduler.add(
+            Instant::now() + Duration::from_millis(10),
+            Box::new(|_| panic!("boom")),
+            None,
+        );
+        let count = Arc::new(AtomicUsize::new(0));
+        let c = count.clone();
+        let _other = scheduler.add(
+            Instant::now() + Duration::from_millis(30),
+            Box::new(move |_| {
+                c.fetch_add(1, Ordering::Relaxed);
+                None
+            }),
+            None,
+        );
+        // the panic hook may take a while (ex. printing a backtrace), so wait for the other timer
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while count.load(Ordering::Relaxed) == 0 && Instant::now() < deadline {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        assert!(!timer.is_active());
+        assert_eq!(count.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn test_supervised_callback_backs_off() {
+        let mut state = State::default();
+        state.entries.insert(
+            1,
+            Entry {
+                deadline: Instant::now(),
+                callback: None,
+                supervision: Some(Supervision {
+                    agent_id: "a".into(),
+                    max_restarts: 3,
+                    restarts: 0,
+                    on_restart: Box::new(|_| {}),
+                }),
+            },
+        );
+
+        let mut delays = Vec::new();
+        for _ in 0..4 {
+            let now = Instant::now();
+            let removed =
+                Scheduler::reschedule(&mut state, 1, Box::new(|_| None), Err(Box::new("boom")));
+            match state.entries.get_mut(&1) {
+                Some(entry) => {
+                    assert!(removed.is_none());
+                    delays.push((entry.deadline - now).as_secs());
+                    entry.callback = None;
+                }
+                None => assert!(removed.is_some()),
+            }
+        }
+        // restarted with growing delays, then left stopped after max_restarts
+        assert_eq!(delays, vec![1, 2, 4]);
+        assert!(state.entries.is_empty());
+    }
+}
@@ -0,0 +1,287 @@
+#![cfg(feature = "script")]
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use modular_agent_core::{
+    Agent, AgentContext, AgentData, AgentError, AgentOutput, AgentSpec, AgentValue, AsAgent,
+    ModularAgent, async_trait, modular_agent,
+};
+use rhai::module_resolvers::DummyModuleResolver;
+use rhai::{AST, Dynamic, Engine, EvalAltResult, Map, Scope};
+
+use crate::contract::{CONFIG_INPUT_CONTRACT, ContractAgent, InputContract, contract_agents};
+use crate::provenance::Traced;
+use crate::time::parse_duration_to_ms;
+
+const CATEGORY: &str = "Std/Script";
+
+const PORT_VALUE: &str = "value";
+
+const CONFIG_ALLOW_FILES: &str = "allow_files";
+const CONFIG_MAX_OPERATIONS: &str = "max_operations";
+const CONFIG_SCRIPT: &str = "script";
+const CONFIG_TIMEOUT: &str = "timeout";
+
+const MAX_OPERATIONS_DEFAULT: i64 = 1_000_000;
+const TIMEOUT_DEFAULT: &str = "1s";
+
+// Limits on the sizes of the values a script can build
+const MAX_STRING_SIZE: usize = 1 << 20;
+const MAX_ARRAY_SIZE: usize = 100_000;
+const MAX_MAP_SIZE: usize = 100_000;
+const MAX_CALL_LEVELS: usize = 64;
+
+contract_agents!(ScriptAgent);
+
+/// Script
+///
+/// Runs a [Rhai](https://rhai.rs) script for each input and outputs the value of its last
+/// expression. Nothing is output when the script returns `()`.
+///
+/// The script sees:
+///
+/// - `value`: the input value
+/// - `ctx`: `#{id, port}` of the input
+/// - `log(message)`: writes to the log
+/// - `now()`: the current time in milliseconds
+///
+/// A script is stopped after `max operations` or `timeout`. It cannot import other script
+/// files unless `allow files` is set.
+#[modular_agent(
+    title = "Script",
+    category = CATEGORY,
+    inputs = [PORT_VALUE],
+    outputs = [PORT_VALUE],
+    text_config(name = CONFIG_SCRIPT),
+    string_config(name = CONFIG_TIMEOUT, default = TIMEOUT_DEFAULT, description = "(ex. 100ms, 1s)"),
+    integer_config(name = CONFIG_MAX_OPERATIONS, default = MAX_OPERATIONS_DEFAULT, title = "max operations"),
+    boolean_config(name = CONFIG_ALLOW_FILES, title = "allow files", description = "allow importing script files"),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
+    hint(color=3),
+)]
+struct ScriptAgent {
+    data: AgentData,
+    contract: InputContract,
+    script: Option<Arc<AST>>,
+    limits: ScriptLimits,
+}
+
+impl ScriptAgent {
+    fn update_script(&mut self) -> Result<(), AgentError> {
+        let configs = self.configs()?;
+        let limits = ScriptLimits::from_configs(
+            configs.get_integer_or(CONFIG_MAX_OPERATIONS, MAX_OPERATIONS_DEFAULT),
+            &configs.get_string_or(CONFIG_TIMEOUT, TIMEOUT_DEFAULT),
+            configs.get_bool_or_default(CONFIG_ALLOW_FILES),
+        )?;
+        let script = configs.get_string_or_default(CONFIG_SCRIPT);
+        self.script = if script.trim().is_empty() {
+            None
+        } else {
+            Some(Arc::new(limits.engine().compile(&script).map_err(|e| {
+                AgentError::InvalidConfig(format!("Invalid script: {}", e))
+            })?))
+        };
+        self.limits = limits;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl AsAgent for ScriptAgent {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
+        let mut agent = Self {
+            data: AgentData::new(ma, id, spec),
+            contract,
+            script: None,
+            limits: ScriptLimits::default(),
+        };
+        agent.update_script()?;
+        Ok(agent)
+    }
+
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()?;
+        self.update_script()
+    }
+
+    async fn process(
+        &mut self,
+        ctx: AgentContext,
+        port: String,
+        value: AgentValue,
+    ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
+        let Some(script) = self.script.clone() else {
+            return Ok(());
+        };
+        let limits = self.limits.clone();
+        let ctx_id = ctx.id();
+        let result =
+            tokio::task::spawn_blocking(move || run_script(&limits, &script, value, ctx_id, &port))
+                .await
+                .map_err(|e| AgentError::InvalidValue(format!("Failed to run script: {}", e)))??;
+        if result.is_unit() {
+            return Ok(());
+        }
+        self.output(self.traced(ctx), PORT_VALUE, result).await
+    }
+}
+
+#[derive(Clone)]
+struct ScriptLimits {
+    max_operations: u64,
+    timeout: Duration,
+    allow_files: bool,
+}
+
+impl Default for ScriptLimits {
+    fn default() -> Self {
+        Self {
+            max_operations: MAX_OPERATIONS_DEFAULT as u64,
+            timeout: Duration::from_secs(1),
+            allow_files: false,
+        }
+    }
+}
+
+impl ScriptLimits {
+    fn from_configs(
+        max_operations: i64,
+        timeout: &str,
+        allow_files: bool,
+    ) -> Result<Self, AgentError> {
+        if max_operations <= 0 {
+            return Err(AgentError::InvalidConfig(
+                "max_operations must be greater than 0".into(),
+            ));
+        }
+        Ok(Self {
+            max_operations: max_operations as u64,
+            timeout: Duration::from_millis(parse_duration_to_ms(timeout)?),
+            allow_files,
+        })
+    }
+
+    // The engine with the limits and the API for scripts.
+    fn engine(&self) -> Engine {
+        let mut engine = Engine::new();
+        engine
+            .set_max_operations(self.max_operations)
+            .set_max_string_size(MAX_STRING_SIZE)
+            .set_max_array_size(MAX_ARRAY_SIZE)
+            .set_max_map_size(MAX_MAP_SIZE)
+            .set_max_call_levels(MAX_CALL_LEVELS);
+        if !self.allow_files {
+            engine.set_module_resolver(DummyModuleResolver::new());
+        }
+
+        let start = Instant::now();
+        let timeout = self.timeout;
+        engine.on_progress(move |_| (start.elapsed() > timeout).then(|| Dynamic::from("timeout")));
+
+        engine
+            .on_print(|s| log::info!("{}", s))
+            .on_debug(|s, _, pos| log::debug!("{} {}", pos, s));
+        engine
+            .register_fn("log", |s: &str| log::info!("{}", s))
+            .register_fn("now", || chrono::Utc::now().timestamp_millis());
+        engine
+    }
+}
+
+fn run_script(
+    limits: &ScriptLimits,
+    script: &AST,
+    value: AgentValue,
+    ctx_id: usize,
+    port: &str,
+) -> Result<AgentValue, AgentError> {
+    let value = rhai::serde::to_dynamic(value.to_json())
+        .map_err(|e| AgentError::InvalidValue(format!("Failed to pass value to script: {}", e)))?;
+    let mut ctx = Map::new();
+    ctx.insert("id".into(), Dynamic::from(ctx_id as i64));
+    ctx.insert("port".into(), Dynamic::from(port.to_string()));
+
+    let mut scope = Scope::new();
+    scope.push_dynamic("value", value);
+    scope.push_constant("ctx", ctx);
+
+    let result = limits
+        .engine()
+        .eval_ast_with_scope::<Dynamic>(&mut scope, script)
+        .map_err(|e| match *e {
+            EvalAltResult::ErrorTerminated(..) => {
+                AgentError::InvalidValue(format!("Script timed out after {:?}", limits.timeout))
+            }
+            e => AgentError::InvalidValue(format!("Script error: {}", e)),
+        })?;
+    if result.is_unit() {
+        return Ok(AgentValue::Unit);
+    }
+    let json: serde_json::Value = rhai::serde::from_dynamic(&result)
+        .map_err(|e| AgentError::InvalidValue(format!("Invalid script result: {}", e)))?;
+    AgentValue::from_json(json)
+}
+
+#[cfg(test)]
+mod tests {
+    use im::hashmap;
+
+    use super::*;
+
+    fn run(script: &str, value: AgentValue) -> Result<AgentValue, AgentError> {
+        let limits = ScriptLimits::from_configs(10_000, "1s", false).unwrap();
+        let ast = limits.engine().compile(script).unwrap();
+        run_script(&limits, &ast, value, 1, "value")
+    }
+
+    #[test]
+    fn test_run_script() {
+        let value = AgentValue::object(hashmap! {
+            "price".to_string() => AgentValue::integer(3),
+            "tags".to_string() => AgentValue::array(im::vector![AgentValue::string("a")]),
+        });
+        let result = run(
+            r#"#{total: value.price * 2, tags: value.tags.len(), port: ctx.port}"#,
+            value,
+        )
+        .unwrap();
+        assert_eq!(
+            result,
+            AgentValue::object(hashmap! {
+                "total".to_string() => AgentValue::integer(6),
+                "tags".to_string() => AgentValue::integer(1),
+                "port".to_string() => AgentValue::string("value"),
+            })
+        );
+
+        // () outputs nothing
+        assert!(
+            run("if value > 1 { value }", AgentValue::integer(0))
+                .unwrap()
+                .is_unit()
+        );
+    }
+
+    #[test]
+    fn test_script_limits() {
+        // stopped by the operation limit
+        assert!(run("loop {}", AgentValue::Unit).is_err());
+        // imports are disabled
+        assert!(run(r#"import "other" as o; 1"#, AgentValue::Unit).is_err());
+        assert!(run("throw \"failed\"", AgentValue::Unit).is_err());
+
+        // stopped by the timeout
+        let limits = ScriptLimits::from_configs(i64::MAX, "50ms", false).unwrap();
+        let ast = limits.engine().compile("loop {}").unwrap();
+        let start = Instant::now();
+        let err = run_script(&limits, &ast, AgentValue::Unit, 1, "value").unwrap_err();
+        assert!(err.to_string().contains("timed out"), "{}", err);
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+}
@@ -1,11 +1,21 @@
 use std::collections::VecDeque;
+use std::hash::{BuildHasher, RandomState};
 use std::time::Duration;
 
+use chrono::Utc;
+
 use modular_agent_core::{
-    ModularAgent, AgentContext, AgentData, AgentError, AgentOutput, AgentSpec, AgentValue, AsAgent,
-    modular_agent, async_trait,
+    ModularAgent, Agent, AgentContext, AgentData, AgentError, AgentOutput, AgentSpec, AgentValue,
+    AsAgent, modular_agent, async_trait,
 };
+use im::{hashmap, vector};
 use mini_moka::sync::Cache;
+use sha2::{Digest, Sha256};
+
+use crate::contract::{CONFIG_INPUT_CONTRACT, ContractAgent, InputContract, contract_agents};
+use crate::data::get_nested_value;
+use crate::provenance::Traced;
+use crate::tenant::{CONFIG_PER_TENANT, TenantMap, tenant_key};
 
 const CONFIG_TTL_SEC: &str = "ttl_sec";
 const CONFIG_CAPACITY: &str = "capacity";
@@ -15,11 +25,28 @@ const CATEGORY: &str = "Std/Sequence";
 const PORT_IN: &str = "in";
 const PORT_IN1: &str = "in1";
 const PORT_IN2: &str = "in2";
+const PORT_N: &str = "n";
 const PORT_OUT1: &str = "out1";
 const PORT_OUT2: &str = "out2";
+const PORT_RESET: &str = "reset";
+const PORT_VALUE: &str = "value";
 
+const CONFIG_AS_ARRAY: &str = "as_array";
+const CONFIG_FIRST: &str = "first";
+const CONFIG_KEY: &str = "key";
+const CONFIG_MAP_FRAME: &str = "map_frame";
 const CONFIG_N: &str = "n";
 const CONFIG_USE_CTX: &str = "use_ctx";
+const CONFIG_WEIGHTS: &str = "weights";
+
+contract_agents!(
+    SequenceAgent,
+    SplitTrafficAgent,
+    SyncAgent,
+    SampleEveryAgent,
+    PairwiseAgent,
+    RepeatAgent,
+);
 
 /// Receives an input and emits it sequentially to n outputs.
 #[modular_agent(
@@ -28,10 +55,12 @@ const CONFIG_USE_CTX: &str = "use_ctx";
     inputs = [PORT_IN],
     outputs = [PORT_OUT1, PORT_OUT2],
     integer_config(name = CONFIG_N, default = 2),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
     hint(color=2),
 )]
 struct SequenceAgent {
     data: AgentData,
+    contract: InputContract,
     n: usize,
 }
 
@@ -56,12 +85,14 @@ impl SequenceAgent {
 impl AsAgent for SequenceAgent {
     fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
         let n = Self::update_spec(&mut spec)?;
+        let contract = InputContract::update_spec(&mut spec)?;
         let data = AgentData::new(ma, id, spec);
-        Ok(Self { data, n })
+        Ok(Self { data, contract, n })
     }
 
     fn configs_changed(&mut self) -> Result<(), AgentError> {
         let n = Self::update_spec(&mut self.data.spec)?;
+        self.reload_contract()?;
         let mut changed = false;
         if n != self.n {
             self.n = n;
@@ -76,18 +107,165 @@ impl AsAgent for SequenceAgent {
     async fn process(
         &mut self,
         ctx: AgentContext,
-        _port: String,
+        port: String,
         value: AgentValue,
     ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
         for i in 0..self.n {
             let out_port = format!("out{}", i + 1);
-            self.output(ctx.clone(), out_port, value.clone()).await?;
+            self.output(self.traced(ctx.clone()), out_port, value.clone())
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+/// Routes each input to one of n outputs, chosen at random by weight.
+///
+/// `weights` lists a weight per output, separated by commas ("90, 10");
+/// blank means equal weights. When `key` names a path in the value
+/// (e.g. "user.id"), the choice is made from a hash of the value at that
+/// path, so the same key always goes to the same output. Values without
+/// the key are routed at random.
+#[modular_agent(
+    title = "Split Traffic",
+    category = CATEGORY,
+    inputs = [PORT_VALUE],
+    outputs = [PORT_OUT1, PORT_OUT2],
+    integer_config(name = CONFIG_N, default = 2),
+    string_config(name = CONFIG_WEIGHTS, description = "comma separated weights per output"),
+    string_config(name = CONFIG_KEY, title = "sticky key"),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
+    hint(color=2),
+)]
+struct SplitTrafficAgent {
+    data: AgentData,
+    contract: InputContract,
+    weights: Vec<f64>,
+}
+
+impl SplitTrafficAgent {
+    fn update_spec(spec: &mut AgentSpec) -> Result<Vec<f64>, AgentError> {
+        let n = SequenceAgent::update_spec(spec)?;
+        let weights = spec
+            .configs
+            .as_ref()
+            .map(|cfg| cfg.get_string_or_default(CONFIG_WEIGHTS))
+            .unwrap_or_default();
+        parse_weights(&weights, n)
+    }
+}
+
+fn parse_weights(s: &str, n: usize) -> Result<Vec<f64>, AgentError> {
+    if s.trim().is_empty() {
+        return Ok(vec![1.0; n]);
+    }
+    let weights = s
+        .split(',')
+        .map(|w| {
+            w.trim()
+                .parse::<f64>()
+                .ok()
+                .filter(|w| w.is_finite() && *w >= 0.0)
+                .ok_or_else(|| AgentError::InvalidConfig(format!("invalid weight: {}", w.trim())))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if weights.len() != n {
+        return Err(AgentError::InvalidConfig(format!(
+            "{} weights for {} outputs",
+            weights.len(),
+            n
+        )));
+    }
+    if weights.iter().sum::<f64>() <= 0.0 {
+        return Err(AgentError::InvalidConfig(
+            "weights must not all be zero".into(),
+        ));
+    }
+    Ok(weights)
+}
+
+// Picks the output index whose share of the total weight contains r (0 <= r < 1).
+fn pick_weighted(weights: &[f64], r: f64) -> usize {
+    let mut point = r * weights.iter().sum::<f64>();
+    for (i, w) in weights.iter().enumerate() {
+        if point < *w {
+            return i;
+        }
+        point -= w;
+    }
+    // Rounding may leave the point at the very end; use the last weighted output.
+    weights.iter().rposition(|w| *w > 0.0).unwrap_or(0)
+}
+
+fn unit_from_u64(x: u64) -> f64 {
+    (x >> 11) as f64 / (1u64 << 53) as f64
+}
+
+fn sticky_unit(key: &AgentValue) -> f64 {
+    let digest = Sha256::digest(key.to_json().to_string());
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&digest[..8]);
+    unit_from_u64(u64::from_be_bytes(bytes))
+}
+
+fn random_unit() -> f64 {
+    // Every RandomState is seeded differently, which is enough for traffic splitting.
+    unit_from_u64(RandomState::new().hash_one(Utc::now().timestamp_nanos_opt()))
+}
+
+#[async_trait]
+impl AsAgent for SplitTrafficAgent {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let weights = Self::update_spec(&mut spec)?;
+        let contract = InputContract::update_spec(&mut spec)?;
+        let data = AgentData::new(ma, id, spec);
+        Ok(Self {
+            data,
+            contract,
+            weights,
+        })
+    }
+
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        let weights = Self::update_spec(&mut self.data.spec)?;
+        self.reload_contract()?;
+        let changed = weights.len() != self.weights.len();
+        self.weights = weights;
+        if changed {
+            self.emit_agent_spec_updated();
         }
         Ok(())
     }
+
+    async fn process(
+        &mut self,
+        ctx: AgentContext,
+        port: String,
+        value: AgentValue,
+    ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
+        let key = self.configs()?.get_string_or_default(CONFIG_KEY);
+        let sticky = if key.is_empty() {
+            None
+        } else {
+            let keys: Vec<&str> = key.split('.').collect();
+            get_nested_value(&value, &keys).map(sticky_unit)
+        };
+        let i = pick_weighted(&self.weights, sticky.unwrap_or_else(random_unit));
+        self.output(self.traced(ctx), format!("out{}", i + 1), value)
+            .await
+    }
 }
 
 /// Receives inputs in any order and, once all are present, emits them sequentially.
+///
+/// Without `use_ctx`, inputs are queued in arrival order; with `per tenant`,
+/// each tenant id in the context has its own queues.
 #[modular_agent(
     title = "Sync",
     category = CATEGORY,
@@ -97,10 +275,13 @@ impl AsAgent for SequenceAgent {
     boolean_config(name = CONFIG_USE_CTX),
     integer_config(name = CONFIG_TTL_SEC, default = 60), 
     integer_config(name = CONFIG_CAPACITY, default = 1000),
+    boolean_config(name = CONFIG_PER_TENANT, title = "per tenant"),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
     hint(color=2),
 )]
 struct SyncAgent {
     data: AgentData,
+    contract: InputContract,
     n: usize,
     use_ctx: bool,
         ttl_sec: u64,
@@ -109,8 +290,8 @@ struct SyncAgent {
     // Optimization: Pre-generate and store output port names ("out1", "out2"...)
     output_ports: Vec<String>,
 
-    // For simple mode
-    queues: Vec<VecDeque<AgentValue>>,
+    // For simple mode, by tenant id
+    queues: TenantMap<Vec<VecDeque<AgentValue>>>,
 
     // For use_ctx mode: Cache with TTL
     ctx_buffers: Cache<String, PendingSync>,
@@ -156,7 +337,7 @@ impl SyncAgent {
     }
 
     fn reset_state(&mut self) {
-        self.queues = vec![VecDeque::new(); self.n];
+        self.queues.clear();
         self.ctx_buffers.invalidate_all();
     }
 }
@@ -165,6 +346,7 @@ impl SyncAgent {
 impl AsAgent for SyncAgent {
     fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
         let (n, use_ctx, ttl_sec, capacity, output_ports) = Self::update_spec(&mut spec)?;
+        let contract = InputContract::update_spec(&mut spec)?;
 
         let cache = Cache::builder()
             .max_capacity(capacity)
@@ -174,18 +356,20 @@ impl AsAgent for SyncAgent {
         let data = AgentData::new(ma, id, spec);
         Ok(Self {
             data,
+            contract,
             n,
             use_ctx,
             ttl_sec,
             capacity,
             output_ports,
-            queues: vec![VecDeque::new(); n],
+            queues: TenantMap::default(),
             ctx_buffers: cache,
         })
     }
 
     fn configs_changed(&mut self) -> Result<(), AgentError> {
         let (n, use_ctx, ttl_sec, capacity, output_ports) = Self::update_spec(&mut self.data.spec)?;
+        self.reload_contract()?;
         let mut changed = false;
         if n != self.n {
             self.n = n;
@@ -227,6 +411,9 @@ impl AsAgent for SyncAgent {
         port: String,
         value: AgentValue,
     ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
         // Parse port number
         let Some(idx) = port
             .strip_prefix("in")
@@ -259,7 +446,8 @@ impl AsAgent for SyncAgent {
                 // Output sequentially
                 for (i, val_opt) in entry.values.into_iter().enumerate() {
                     if let Some(val) = val_opt {
-                        self.output(ctx.clone(), &self.output_ports[i], val).await?;
+                        self.output(self.traced(ctx.clone()), &self.output_ports[i], val)
+                            .await?;
                     }
                 }
             }
@@ -267,20 +455,264 @@ impl AsAgent for SyncAgent {
         }
 
         // Simple FIFO Mode
-        self.queues[idx].push_back(value);
-
-        // Check if all queues have data
-        if self.queues.iter().all(|q| !q.is_empty()) {
-            let ready_values: Vec<AgentValue> = self.queues
-                .iter_mut()
-                .map(|q| q.pop_front().unwrap())
-                .collect();
+        let tenant = tenant_key(&ctx, self.configs()?.get_bool_or_default(CONFIG_PER_TENANT));
 
+        // Once all queues have data
+        if let Some(ready_values) = self.queues.push_zip(tenant, self.n, idx, value) {
             for (i, val) in ready_values.into_iter().enumerate() {
-                self.output(ctx.clone(), &self.output_ports[i], val).await?;
+                self.output(self.traced(ctx.clone()), &self.output_ports[i], val)
+                    .await?;
             }
         }
 
         Ok(())
     }
 }
+
+/// Passes through only every nth value.
+///
+/// When `first` is true, the 1st, (n+1)th, (2n+1)th... values are emitted.
+/// Otherwise, the nth, 2nth, 3nth... values are emitted.
+/// A value on the `reset` pin restarts the count.
+#[modular_agent(
+    title = "Sample Every",
+    category = CATEGORY,
+    inputs = [PORT_VALUE, PORT_RESET],
+    outputs = [PORT_VALUE],
+    integer_config(name = CONFIG_N, default = 2),
+    boolean_config(name = CONFIG_FIRST),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
+    hint(color=2),
+)]
+struct SampleEveryAgent {
+    data: AgentData,
+    contract: InputContract,
+    count: u64,
+}
+
+#[async_trait]
+impl AsAgent for SampleEveryAgent {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
+        Ok(Self {
+            data: AgentData::new(ma, id, spec),
+            contract,
+            count: 0,
+        })
+    }
+
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
+    }
+
+    async fn stop(&mut self) -> Result<(), AgentError> {
+        self.count = 0;
+        Ok(())
+    }
+
+    async fn process(
+        &mut self,
+        ctx: AgentContext,
+        port: String,
+        value: AgentValue,
+    ) -> Result<(), AgentError> {
+        if port == PORT_RESET {
+            self.count = 0;
+            return Ok(());
+        }
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
+
+        let config = self.configs()?;
+        let n = config.get_integer_or(CONFIG_N, 2).max(1) as u64;
+        let first = config.get_bool_or_default(CONFIG_FIRST);
+
+        self.count += 1;
+        let emit = if first {
+            (self.count - 1).is_multiple_of(n)
+        } else {
+            self.count.is_multiple_of(n)
+        };
+        if emit {
+            self.output(self.traced(ctx), PORT_VALUE, value).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Emits each value paired with the previous one.
+///
+/// Outputs `{previous, current}` for every input after the first, or
+/// `[previous, current]` when `as_array` is true.
+/// A value on the `reset` pin forgets the previous value.
+#[modular_agent(
+    title = "Pairwise",
+    category = CATEGORY,
+    inputs = [PORT_VALUE, PORT_RESET],
+    outputs = [PORT_VALUE],
+    boolean_config(name = CONFIG_AS_ARRAY, title = "as array"),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
+    hint(color=2),
+)]
+struct PairwiseAgent {
+    data: AgentData,
+    contract: InputContract,
+    previous: Option<AgentValue>,
+}
+
+#[async_trait]
+impl AsAgent for PairwiseAgent {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
+        Ok(Self {
+            data: AgentData::new(ma, id, spec),
+            contract,
+            previous: None,
+        })
+    }
+
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
+    }
+
+    async fn stop(&mut self) -> Result<(), AgentError> {
+        self.previous = None;
+        Ok(())
+    }
+
+    async fn process(
+        &mut self,
+        ctx: AgentContext,
+        port: String,
+        value: AgentValue,
+    ) -> Result<(), AgentError> {
+        if port == PORT_RESET {
+            self.previous = None;
+            return Ok(());
+        }
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
+
+        let Some(previous) = self.previous.replace(value.clone()) else {
+            return Ok(());
+        };
+
+        let pair = if self.configs()?.get_bool_or_default(CONFIG_AS_ARRAY) {
+            AgentValue::array(vector![previous, value])
+        } else {
+            AgentValue::object(hashmap! {
+                "previous".to_string() => previous,
+                "current".to_string() => value,
+            })
+        };
+        self.output(self.traced(ctx), PORT_VALUE, pair).await
+    }
+}
+
+/// Emits each input value n times.
+///
+/// The count comes from the `n` config, or from the last integer received on the
+/// `n` pin, which takes precedence until the agent is stopped.
+/// When `map_frame` is true, each copy carries a `map` frame (like Map), so that
+/// Collect can reassemble the copies into an array.
+#[modular_agent(
+    title = "Repeat",
+    category = CATEGORY,
+    inputs = [PORT_VALUE, PORT_N],
+    outputs = [PORT_VALUE],
+    integer_config(name = CONFIG_N, default = 2),
+    boolean_config(name = CONFIG_MAP_FRAME, title = "map frame"),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
+    hint(color=2),
+)]
+struct RepeatAgent {
+    data: AgentData,
+    contract: InputContract,
+    n: Option<i64>,
+}
+
+#[async_trait]
+impl AsAgent for RepeatAgent {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
+        Ok(Self {
+            data: AgentData::new(ma, id, spec),
+            contract,
+            n: None,
+        })
+    }
+
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
+    }
+
+    async fn stop(&mut self) -> Result<(), AgentError> {
+        self.n = None;
+        Ok(())
+    }
+
+    async fn process(
+        &mut self,
+        ctx: AgentContext,
+        port: String,
+        value: AgentValue,
+    ) -> Result<(), AgentError> {
+        if port == PORT_N {
+            let n = value
+                .as_i64()
+                .ok_or_else(|| AgentError::InvalidValue("n must be an integer".into()))?;
+            self.n = Some(n);
+            return Ok(());
+        }
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
+
+        let config = self.configs()?;
+        let n = self
+            .n
+            .unwrap_or_else(|| config.get_integer_or(CONFIG_N, 2))
+            .max(0) as usize;
+        let map_frame = config.get_bool_or_default(CONFIG_MAP_FRAME);
+
+        for i in 0..n {
+            let c = if map_frame {
+                ctx.push_map_frame(i, n)?
+            } else {
+                ctx.clone()
+            };
+            self.output(self.traced(c), PORT_VALUE, value.clone())
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_weights() {
+        assert_eq!(parse_weights("", 3).unwrap(), vec![1.0, 1.0, 1.0]);
+        assert_eq!(parse_weights("90, 10", 2).unwrap(), vec![90.0, 10.0]);
+        assert!(parse_weights("1, 2", 3).is_err());
+        assert!(parse_weights("1, -1", 2).is_err());
+        assert!(parse_weights("0, 0", 2).is_err());
+
+        let weights = [1.0, 0.0, 3.0];
+        assert_eq!(pick_weighted(&weights, 0.0), 0);
+        assert_eq!(pick_weighted(&weights, 0.24), 0);
+        assert_eq!(pick_weighted(&weights, 0.25), 2);
+        assert_eq!(pick_weighted(&weights, 0.999), 2);
+        assert_eq!(pick_weighted(&[1.0, 0.0], 1.0), 0);
+
+        let user = AgentValue::string("alice");
+        assert_eq!(sticky_unit(&user), sticky_unit(&user));
+        assert_ne!(sticky_unit(&user), sticky_unit(&AgentValue::string("bob")));
+        assert!((0.0..1.0).contains(&sticky_unit(&user)));
+        assert!((0.0..1.0).contains(&random_unit()));
+    }
+}
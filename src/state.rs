@@ -0,0 +1,583 @@
+//! Value history.
+//!
+//! Time Series Append records values as points `{t, value}` (t in milliseconds) of a
+//! named series, kept in memory and in the JSONL file `<dir>/<series>.jsonl`, so that the
+//! history survives restarts. Time Series Query reads the points of a series back for
+//! charts and aggregation. Points older than the retention, or over the maximum number
+//! of points, are dropped; the file is compacted once it holds as many dropped points as
+//! kept ones. With `per tenant`, each tenant id in the context has its own series in
+//! `<dir>/<tenant>/<series>.jsonl`. At most `MAX_LOADED_SERIES` series are kept in
+//! memory; the series appended to least recently is dropped and read again when needed.
+
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, LazyLock, Mutex};
+
+use chrono::{DateTime, Utc};
+use im::hashmap;
+use modular_agent_core::{
+    Agent, AgentConfigs, AgentContext, AgentData, AgentError, AgentOutput, AgentSpec, AgentValue,
+    AsAgent, ModularAgent, async_trait, modular_agent,
+};
+
+use crate::audit::{ACTION_WRITE_FILE, audit};
+use crate::contract::{CONFIG_INPUT_CONTRACT, ContractAgent, InputContract, contract_agents};
+use crate::profile::ProfileConfigs;
+use crate::provenance::Traced;
+use crate::tenant::{CONFIG_PER_TENANT, tenant_key};
+use crate::time::parse_duration_to_ms;
+
+const CATEGORY: &str = "Std/State";
+
+const PORT_POINTS: &str = "points";
+const PORT_TRIGGER: &str = "trigger";
+const PORT_VALUE: &str = "value";
+
+const CONFIG_DIR: &str = "dir";
+const CONFIG_FROM: &str = "from";
+const CONFIG_LAST: &str = "last";
+const CONFIG_MAX_POINTS: &str = "max_points";
+const CONFIG_RETENTION: &str = "retention";
+const CONFIG_SERIES: &str = "series";
+const CONFIG_TO: &str = "to";
+
+const KEY_T: &str = "t";
+const KEY_VALUE: &str = "value";
+
+const MAX_LOADED_SERIES: usize = 1024;
+const MAX_POINTS_DEFAULT: i64 = 100_000;
+const SERIES_EXT: &str = "jsonl";
+
+// the least number of dropped points that triggers a compaction
+const COMPACT_MIN: usize = 64;
+
+#[derive(Clone, Debug, PartialEq)]
+struct Point {
+    t: i64,
+    value: AgentValue,
+}
+
+impl Point {
+    fn from_value(value: &AgentValue) -> Option<Self> {
+        let obj = value.as_object()?;
+        if obj.len() != 2 {
+            return None;
+        }
+        Some(Self {
+            t: obj.get(KEY_T)?.as_i64()?,
+            value: obj.get(KEY_VALUE)?.clone(),
+        })
+    }
+
+    fn to_value(&self) -> AgentValue {
+        AgentValue::object(hashmap! {
+            KEY_T.to_string() => AgentValue::integer(self.t),
+            KEY_VALUE.to_string() => self.value.clone(),
+        })
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct Retention {
+    max_age_ms: Option<i64>,
+    // 0: unlimited
+    max_points: usize,
+}
+
+#[derive(Default)]
+struct Series {
+    // in order of t
+    points: VecDeque<Point>,
+    // lines of the file which are not kept anymore
+    stale: usize,
+}
+
+impl Series {
+    /// Reads a series file. Broken lines are skipped.
+    fn load(path: &Path) -> Result<Self, AgentError> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => {
+                return Err(AgentError::IoError(format!(
+                    "Failed to read series {}: {}",
+                    path.display(),
+                    e
+                )));
+            }
+        };
+        let mut series = Series::default();
+        for line in text.lines().filter(|line| !line.trim().is_empty()) {
+            let point = serde_json::from_str::<serde_json::Value>(line)
+                .ok()
+                .and_then(|json| AgentValue::from_json(json).ok())
+                .and_then(|value| Point::from_value(&value));
+            match point {
+                Some(point) => series.points.push_back(point),
+                None => series.stale += 1,
+            }
+        }
+        series.points.make_contiguous().sort_by_key(|p| p.t);
+        Ok(series)
+    }
+
+    fn insert(&mut self, point: Point) {
+        let i = self.points.partition_point(|p| p.t <= point.t);
+        self.points.insert(i, point);
+    }
+
+    /// Drops the points out of the retention, and returns how many.
+    fn prune(&mut self, retention: Retention, now: i64) -> usize {
+        let len = self.points.len();
+        if let Some(max_age_ms) = retention.max_age_ms {
+            let i = self.points.partition_point(|p| p.t < now - max_age_ms);
+            self.points.drain(..i);
+        }
+        if retention.max_points > 0 && self.points.len() > retention.max_points {
+            self.points
+                .drain(..self.points.len() - retention.max_points);
+        }
+        len - self.points.len()
+    }
+
+    /// Returns the points from `from` to `to` (inclusive), limited to the last `last`
+    /// (0: all).
+    fn query(&self, from: Option<i64>, to: Option<i64>, last: usize) -> Vec<AgentValue> {
+        let start = from.map_or(0, |from| self.points.partition_point(|p| p.t < from));
+        let end = to.map_or(self.points.len(), |to| {
+            self.points.partition_point(|p| p.t <= to)
+        });
+        let start = if last > 0 && end > start + last {
+            end - last
+        } else {
+            start
+        };
+        self.points
+            .range(start..end.max(start))
+            .map(Point::to_value)
+            .collect()
+    }
+}
+
+// A series loaded by Time Series Append, locked on its own so that a slow series does not
+// hold up the others. None until it is read from its file.
+#[derive(Default)]
+struct LoadedSeries {
+    series: Arc<Mutex<Option<Series>>>,
+    // when it was last appended to, in milliseconds
+    appended: i64,
+}
+
+// series file path -> series
+static SERIES: LazyLock<Mutex<HashMap<PathBuf, LoadedSeries>>> = LazyLock::new(Default::default);
+
+/// Returns the file of the series of `configs`, in the directory of `tenant` if any.
+fn series_path(configs: &AgentConfigs, tenant: &str) -> Result<PathBuf, AgentError> {
+    let dir = configs.get_string_resolved(CONFIG_DIR)?;
+    if dir.trim().is_empty() {
+        return Err(AgentError::InvalidConfig("dir is not set".into()));
+    }
+    let name = configs.get_string_resolved(CONFIG_SERIES)?;
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(AgentError::InvalidConfig("series is not set".into()));
+    }
+    if !is_file_name(name) {
+        return Err(AgentError::InvalidConfig(format!(
+            "Invalid series name: {}",
+            name
+        )));
+    }
+    let mut path = PathBuf::from(dir.trim());
+    if !tenant.is_empty() {
+        if !is_file_name(tenant) {
+            return Err(AgentError::InvalidValue(format!(
+                "Invalid tenant id for a series: {}",
+                tenant
+            )));
+        }
+        path.push(tenant);
+    }
+    Ok(path.join(format!("{}.{}", name, SERIES_EXT)))
+}
+
+// Series names and tenant ids become file names in dir.
+fn is_file_name(name: &str) -> bool {
+    !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_alphanumeric() || matches!(c, '_' | '-' | '.'))
+}
+
+fn write_err(path: &Path, e: std::io::Error) -> AgentError {
+    AgentError::IoError(format!("Failed to write series {}: {}", path.display(), e))
+}
+
+/// Rewrites the file with the kept points only, atomically.
+fn compact(path: &Path, series: &Series) -> Result<(), AgentError> {
+    let mut text = String::new();
+    for point in &series.points {
+        text.push_str(&point.to_value().to_json().to_string());
+        text.push('\n');
+    }
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, text)
+        .and_then(|_| fs::rename(&tmp, path))
+        .map_err(|e| write_err(path, e))
+}
+
+fn append_point(
+    path: &Path,
+    point: Point,
+    retention: Retention,
+    now: i64,
+) -> Result<(), AgentError> {
+    let series = {
+        let mut all = SERIES.lock().unwrap_or_else(|e| e.into_inner());
+        if !all.contains_key(path) && all.len() >= MAX_LOADED_SERIES {
+            let oldest = all
+                .iter()
+                .min_by_key(|(_, loaded)| loaded.appended)
+                .map(|(path, _)| path.clone());
+            if let Some(oldest) = oldest {
+                all.remove(&oldest);
+            }
+        }
+        let loaded = all.entry(path.to_path_buf()).or_default();
+        loaded.appended = now;
+        loaded.series.clone()
+    };
+    let mut series = series.lock().unwrap_or_else(|e| e.into_inner());
+    let series = match &mut *series {
+        Some(series) => series,
+        None => series.insert(Series::load(path)?),
+    };
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent).map_err(|e| write_err(path, e))?;
+    }
+    let line = point.to_value().to_json().to_string();
+    series.insert(point);
+    series.stale += series.prune(retention, now);
+    if series.stale >= series.points.len().max(COMPACT_MIN) {
+        compact(path, series)?;
+        series.stale = 0;
+        return Ok(());
+    }
+    let mut f = fs::File::options()
+        .append(true)
+        .create(true)
+        .open(path)
+        .map_err(|e| write_err(path, e))?;
+    writeln!(f, "{}", line).map_err(|e| write_err(path, e))
+}
+
+fn query_points(
+    path: &Path,
+    from: Option<i64>,
+    to: Option<i64>,
+    last: usize,
+) -> Result<Vec<AgentValue>, AgentError> {
+    let series = SERIES
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(path)
+        .map(|loaded| loaded.series.clone());
+    if let Some(series) = series
+        && let Some(series) = &*series.lock().unwrap_or_else(|e| e.into_inner())
+    {
+        return Ok(series.query(from, to, last));
+    }
+    // not appended to in this process
+    Ok(Series::load(path)?.query(from, to, last))
+}
+
+/// Parses a time bound: a duration ago (ex. `1h`), milliseconds since the epoch, or an
+/// RFC 3339 date time. Blank is no bound.
+fn parse_time_bound(value: &AgentValue, now: i64) -> Result<Option<i64>, AgentError> {
+    if let Some(t) = value.as_i64() {
+        return Ok(Some(t));
+    }
+    let s = value.as_str().unwrap_or_default().trim();
+    if s.is_empty() {
+        return Ok(None);
+    }
+    if let Ok(t) = s.parse::<i64>() {
+        return Ok(Some(t));
+    }
+    if let Ok(dt) = DateTime::parse_from_rfc3339(s) {
+        return Ok(Some(dt.timestamp_millis()));
+    }
+    Ok(Some(now - parse_duration_to_ms(s)? as i64))
+}
+
+contract_agents!(TimeSeriesAppendAgent, TimeSeriesQueryAgent);
+
+/// Appends each value to a series.
+///
+/// The point is `{t, value}` with the current time, or the value itself when it is already
+/// a `{t, value}` object. Points older than `retention` (ex. `7d`; blank: forever) and the
+/// oldest points over `max points` (0: unlimited) are dropped. The value is passed through.
+/// With `per tenant`, the point goes to the series of the tenant id in the context.
+#[modular_agent(
+    title = "Time Series Append",
+    category = CATEGORY,
+    inputs = [PORT_VALUE],
+    outputs = [PORT_VALUE],
+    string_config(name = CONFIG_DIR),
+    string_config(name = CONFIG_SERIES),
+    string_config(name = CONFIG_RETENTION, description = "(ex. 1h, 7d) empty: forever"),
+    integer_config(name = CONFIG_MAX_POINTS, default = MAX_POINTS_DEFAULT, title = "max points", description = "0: unlimited"),
+    boolean_config(name = CONFIG_PER_TENANT, title = "per tenant"),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
+)]
+struct TimeSeriesAppendAgent {
+    data: AgentData,
+    contract: InputContract,
+}
+
+#[async_trait]
+impl AsAgent for TimeSeriesAppendAgent {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
+        Ok(Self {
+            data: AgentData::new(ma, id, spec),
+            contract,
+        })
+    }
+
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
+    }
+
+    async fn process(
+        &mut self,
+        ctx: AgentContext,
+        port: String,
+        value: AgentValue,
+    ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
+        let configs = self.configs()?;
+        let tenant = tenant_key(&ctx, configs.get_bool_or_default(CONFIG_PER_TENANT));
+        let path = series_path(configs, &tenant)?;
+        let retention = configs.get_string_or_default(CONFIG_RETENTION);
+        let retention = Retention {
+            max_age_ms: if retention.trim().is_empty() {
+                None
+            } else {
+                Some(parse_duration_to_ms(&retention)? as i64)
+            },
+            max_points: configs
+                .get_integer_or(CONFIG_MAX_POINTS, MAX_POINTS_DEFAULT)
+                .max(0) as usize,
+        };
+        let now = Utc::now().timestamp_millis();
+        let point = Point::from_value(&value).unwrap_or_else(|| Point {
+            t: now,
+            value: value.clone(),
+        });
+        let target = path.display().to_string();
+        audit(self, &ctx, ACTION_WRITE_FILE, &target, &point.value).await?;
+        tokio::task::spawn_blocking(move || append_point(&path, point, retention, now))
+            .await
+            .map_err(|e| AgentError::IoError(format!("Failed to append to series: {}", e)))??;
+        self.output(self.traced(ctx), PORT_VALUE, value).await
+    }
+}
+
+/// Reads points of a series.
+///
+/// On any value on `trigger`, outputs the points `{t, value}` from `from` to `to` as an
+/// array on `points`, limited to the last `last` (0: all). `from` and `to` are a duration
+/// ago (ex. `1h`), milliseconds since the epoch, or an RFC 3339 date time; blank is
+/// unbounded. An object on `trigger` may override them with its `from`, `to` and `last`.
+/// With `per tenant`, the series of the tenant id in the context is read.
+#[modular_agent(
+    title = "Time Series Query",
+    category = CATEGORY,
+    inputs = [PORT_TRIGGER],
+    outputs = [PORT_POINTS],
+    string_config(name = CONFIG_DIR),
+    string_config(name = CONFIG_SERIES),
+    string_config(name = CONFIG_FROM, description = "(ex. 1h, 2024-01-01T00:00:00Z) empty: all"),
+    string_config(name = CONFIG_TO, description = "empty: now"),
+    integer_config(name = CONFIG_LAST, description = "0: all"),
+    boolean_config(name = CONFIG_PER_TENANT, title = "per tenant"),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
+)]
+struct TimeSeriesQueryAgent {
+    data: AgentData,
+    contract: InputContract,
+}
+
+#[async_trait]
+impl AsAgent for TimeSeriesQueryAgent {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
+        Ok(Self {
+            data: AgentData::new(ma, id, spec),
+            contract,
+        })
+    }
+
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
+    }
+
+    async fn process(
+        &mut self,
+        ctx: AgentContext,
+        port: String,
+        value: AgentValue,
+    ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
+        let configs = self.configs()?;
+        let tenant = tenant_key(&ctx, configs.get_bool_or_default(CONFIG_PER_TENANT));
+        let path = series_path(configs, &tenant)?;
+        let now = Utc::now().timestamp_millis();
+        let bound = |key: &str| match value.get(key) {
+            Some(v) => parse_time_bound(v, now),
+            None => parse_time_bound(&AgentValue::string(configs.get_string_or_default(key)), now),
+        };
+        let from = bound(CONFIG_FROM)?;
+        let to = bound(CONFIG_TO)?;
+        let last = value
+            .get(CONFIG_LAST)
+            .and_then(|v| v.as_i64())
+            .unwrap_or_else(|| configs.get_integer_or_default(CONFIG_LAST))
+            .max(0) as usize;
+
+        let points = tokio::task::spawn_blocking(move || query_points(&path, from, to, last))
+            .await
+            .map_err(|e| AgentError::IoError(format!("Failed to query series: {}", e)))??;
+        self.output(
+            self.traced(ctx),
+            PORT_POINTS,
+            AgentValue::array(points.into()),
+        )
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(t: i64) -> Point {
+        Point {
+            t,
+            value: AgentValue::integer(t * 10),
+        }
+    }
+
+    fn times(points: &[AgentValue]) -> Vec<i64> {
+        points
+            .iter()
+            .map(|p| p.get(KEY_T).and_then(|t| t.as_i64()).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_series() {
+        let mut series = Series::default();
+        for t in [1, 3, 2, 5, 4] {
+            series.insert(point(t));
+        }
+        assert_eq!(times(&series.query(None, None, 0)), vec![1, 2, 3, 4, 5]);
+        assert_eq!(times(&series.query(Some(2), Some(4), 0)), vec![2, 3, 4]);
+        assert_eq!(times(&series.query(None, Some(4), 2)), vec![3, 4]);
+        assert_eq!(times(&series.query(Some(6), None, 0)), Vec::<i64>::new());
+        assert_eq!(times(&series.query(Some(4), Some(2), 0)), Vec::<i64>::new());
+
+        let max_points = Retention {
+            max_age_ms: None,
+            max_points: 4,
+        };
+        assert_eq!(series.prune(max_points, 5), 1);
+        let max_age = Retention {
+            max_age_ms: Some(2),
+            max_points: 0,
+        };
+        assert_eq!(series.prune(max_age, 5), 1);
+        assert_eq!(times(&series.query(None, None, 0)), vec![3, 4, 5]);
+
+        assert_eq!(Point::from_value(&point(7).to_value()), Some(point(7)));
+        assert_eq!(Point::from_value(&AgentValue::integer(7)), None);
+
+        assert_eq!(
+            parse_time_bound(&AgentValue::string(""), 10_000).unwrap(),
+            None
+        );
+        assert_eq!(
+            parse_time_bound(&AgentValue::string("1s"), 10_000).unwrap(),
+            Some(9_000)
+        );
+        assert_eq!(
+            parse_time_bound(&AgentValue::integer(42), 10_000).unwrap(),
+            Some(42)
+        );
+        assert_eq!(
+            parse_time_bound(&AgentValue::string("1970-01-01T00:00:01Z"), 0).unwrap(),
+            Some(1_000)
+        );
+        assert!(parse_time_bound(&AgentValue::string("soon"), 0).is_err());
+    }
+
+    #[test]
+    fn test_series_file() {
+        let dir =
+            std::env::temp_dir().join(format!("modular_agent_std_series_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let path = dir.join("temp.jsonl");
+
+        let retention = Retention {
+            max_age_ms: None,
+            max_points: 10,
+        };
+        for t in 0..100 {
+            append_point(&path, point(t), retention, t).unwrap();
+        }
+        assert_eq!(
+            times(&query_points(&path, None, None, 3).unwrap()),
+            vec![97, 98, 99]
+        );
+        // compacted whenever the dropped points reach the slack
+        let lines = fs::read_to_string(&path).unwrap().lines().count();
+        assert!(lines <= 10 + COMPACT_MIN);
+
+        fs::write(
+            &path,
+            "{\"t\":2,\"value\":20}\nbroken\n{\"t\":1,\"value\":10}\n",
+        )
+        .unwrap();
+        let series = Series::load(&path).unwrap();
+        assert_eq!(series.stale, 1);
+        assert_eq!(times(&series.query(None, None, 0)), vec![1, 2]);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_series_path() {
+        let mut configs = AgentConfigs::new();
+        configs.set(CONFIG_DIR.to_string(), AgentValue::string("data"));
+        configs.set(CONFIG_SERIES.to_string(), AgentValue::string("temp"));
+        assert_eq!(
+            series_path(&configs, "").unwrap(),
+            PathBuf::from("data/temp.jsonl")
+        );
+        assert_eq!(
+            series_path(&configs, "acme").unwrap(),
+            PathBuf::from("data/acme/temp.jsonl")
+        );
+        assert!(series_path(&configs, "../acme").is_err());
+
+        configs.set(CONFIG_SERIES.to_string(), AgentValue::string(".temp"));
+        assert!(series_path(&configs, "").is_err());
+    }
+}
@@ -1,23 +1,66 @@
+use std::collections::HashMap;
+
 use handlebars::Handlebars;
-use im::vector;
+use im::{hashmap, vector};
 use modular_agent_core::{
     Agent, AgentContext, AgentData, AgentError, AgentOutput, AgentSpec, AgentValue, AsAgent,
     ModularAgent, async_trait, modular_agent,
 };
 use serde_json::json;
 
+use crate::contract::{CONFIG_INPUT_CONTRACT, ContractAgent, InputContract, contract_agents};
+use crate::provenance::Traced;
+
 const CATEGORY: &str = "Std/String";
 
+const PORT_DIFF: &str = "diff";
+const PORT_HUNKS: &str = "hunks";
+const PORT_KEYWORDS: &str = "keywords";
+const PORT_NEGATIVE: &str = "negative";
+const PORT_NEUTRAL: &str = "neutral";
+const PORT_NEW: &str = "new";
+const PORT_OLD: &str = "old";
+const PORT_POSITIVE: &str = "positive";
+const PORT_SCORE: &str = "score";
 const PORT_STRING: &str = "string";
 const PORT_STRINGS: &str = "strings";
 const PORT_VALUE: &str = "value";
 const PORT_T: &str = "t";
 const PORT_F: &str = "f";
 
+const CONFIG_CONTEXT: &str = "context";
+const CONFIG_FORMAT: &str = "format";
 const CONFIG_LEN: &str = "len";
+const CONFIG_LEXICON: &str = "lexicon";
+const CONFIG_MIN_LENGTH: &str = "min_length";
+const CONFIG_MODE: &str = "mode";
 const CONFIG_OVERLAP: &str = "overlap";
 const CONFIG_SEP: &str = "sep";
+const CONFIG_STOPWORDS: &str = "stopwords";
 const CONFIG_TEMPLATE: &str = "template";
+const CONFIG_THRESHOLD: &str = "threshold";
+const CONFIG_TOP_K: &str = "top_k";
+
+const CONTEXT_DEFAULT: i64 = 3;
+const DIFF_MODE_LINE: &str = "line";
+const DIFF_MODE_WORD: &str = "word";
+const MIN_LENGTH_DEFAULT: i64 = 3;
+const SENTIMENT_THRESHOLD_DEFAULT: f64 = 0.05;
+const TOP_K_DEFAULT: i64 = 5;
+
+contract_agents!(
+    IsStringAgent,
+    IsEmptyStringAgent,
+    StringJoinAgent,
+    StringLengthSplitAgent,
+    TemplateStringAgent,
+    TemplateTextAgent,
+    TemplateArrayAgent,
+    TemplateValueAgent,
+    SentimentAgent,
+    ExtractKeywordsAgent,
+    TextDiffAgent,
+);
 
 /// Check if the input is a string.
 #[modular_agent(
@@ -25,30 +68,41 @@ const CONFIG_TEMPLATE: &str = "template";
     category = CATEGORY,
     inputs = [PORT_VALUE],
     outputs = [PORT_T, PORT_F],
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
     hint(color=5),
 )]
 struct IsStringAgent {
     data: AgentData,
+    contract: InputContract,
 }
 
 #[async_trait]
 impl AsAgent for IsStringAgent {
-    fn new(ma: ModularAgent, id: String, spec: AgentSpec) -> Result<Self, AgentError> {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
         Ok(Self {
             data: AgentData::new(ma, id, spec),
+            contract,
         })
     }
 
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
+    }
+
     async fn process(
         &mut self,
         ctx: AgentContext,
-        _port: String,
+        port: String,
         value: AgentValue,
     ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
         if value.is_string() {
-            self.output(ctx, PORT_T, value).await
+            self.output(self.traced(ctx), PORT_T, value).await
         } else {
-            self.output(ctx, PORT_F, value).await
+            self.output(self.traced(ctx), PORT_F, value).await
         }
     }
 }
@@ -59,35 +113,46 @@ impl AsAgent for IsStringAgent {
     category = CATEGORY,
     inputs = [PORT_STRING],
     outputs = [PORT_T, PORT_F],
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
     hint(color=5),
 )]
 struct IsEmptyStringAgent {
     data: AgentData,
+    contract: InputContract,
 }
 
 #[async_trait]
 impl AsAgent for IsEmptyStringAgent {
-    fn new(ma: ModularAgent, id: String, spec: AgentSpec) -> Result<Self, AgentError> {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
         Ok(Self {
             data: AgentData::new(ma, id, spec),
+            contract,
         })
     }
 
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
+    }
+
     async fn process(
         &mut self,
         ctx: AgentContext,
-        _port: String,
+        port: String,
         value: AgentValue,
     ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
         let is_empty = if let Some(s) = value.as_str() {
             s.is_empty()
         } else {
             false
         };
         if is_empty {
-            self.output(ctx, PORT_T, value).await
+            self.output(self.traced(ctx), PORT_T, value).await
         } else {
-            self.output(ctx, PORT_F, value).await
+            self.output(self.traced(ctx), PORT_F, value).await
         }
     }
 }
@@ -113,26 +178,37 @@ impl AsAgent for IsEmptyStringAgent {
     inputs = [PORT_STRINGS],
     outputs = [PORT_STRING],
     string_config(name = CONFIG_SEP, default = "\\n"),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
     hint(color=5),
 )]
 struct StringJoinAgent {
     data: AgentData,
+    contract: InputContract,
 }
 
 #[async_trait]
 impl AsAgent for StringJoinAgent {
-    fn new(ma: ModularAgent, id: String, spec: AgentSpec) -> Result<Self, AgentError> {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
         Ok(Self {
             data: AgentData::new(ma, id, spec),
+            contract,
         })
     }
 
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
+    }
+
     async fn process(
         &mut self,
         ctx: AgentContext,
-        _port: String,
+        port: String,
         value: AgentValue,
     ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
         let config = self.configs()?;
 
         let sep = config.get_string_or_default(CONFIG_SEP);
@@ -151,9 +227,9 @@ impl AsAgent for StringJoinAgent {
             out = out.replace("\\r", "\r");
             out = out.replace("\\\\", "\\");
             let out_value = AgentValue::string(out);
-            self.output(ctx, PORT_STRING, out_value).await
+            self.output(self.traced(ctx), PORT_STRING, out_value).await
         } else {
-            self.output(ctx, PORT_STRING, value).await
+            self.output(self.traced(ctx), PORT_STRING, value).await
         }
     }
 }
@@ -165,26 +241,37 @@ impl AsAgent for StringJoinAgent {
     outputs = [PORT_STRINGS],
     integer_config(name = CONFIG_LEN, default = 65536),
     integer_config(name = CONFIG_OVERLAP, default = 1024),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
     hint(color=5),
 )]
 struct StringLengthSplitAgent {
     data: AgentData,
+    contract: InputContract,
 }
 
 #[async_trait]
 impl AsAgent for StringLengthSplitAgent {
-    fn new(ma: ModularAgent, id: String, spec: AgentSpec) -> Result<Self, AgentError> {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
         Ok(Self {
             data: AgentData::new(ma, id, spec),
+            contract,
         })
     }
 
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
+    }
+
     async fn process(
         &mut self,
         ctx: AgentContext,
-        _port: String,
+        port: String,
         value: AgentValue,
     ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
         let config = self.configs()?;
 
         let n = config.get_integer_or_default(CONFIG_LEN) as usize;
@@ -227,8 +314,12 @@ impl AsAgent for StringLengthSplitAgent {
             }
             start = next_start;
         }
-        self.output(ctx, PORT_STRINGS, AgentValue::array(out.into()))
-            .await
+        self.output(
+            self.traced(ctx),
+            PORT_STRINGS,
+            AgentValue::array(out.into()),
+        )
+        .await
     }
 }
 
@@ -239,26 +330,37 @@ impl AsAgent for StringLengthSplitAgent {
     inputs = [PORT_VALUE],
     outputs = [PORT_STRING],
     string_config(name = CONFIG_TEMPLATE, default = "{{value}}"),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
     hint(color=5),
 )]
 struct TemplateStringAgent {
     data: AgentData,
+    contract: InputContract,
 }
 
 #[async_trait]
 impl AsAgent for TemplateStringAgent {
-    fn new(ma: ModularAgent, id: String, spec: AgentSpec) -> Result<Self, AgentError> {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
         Ok(Self {
             data: AgentData::new(ma, id, spec),
+            contract,
         })
     }
 
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
+    }
+
     async fn process(
         &mut self,
         ctx: AgentContext,
-        _port: String,
+        port: String,
         value: AgentValue,
     ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
         let config = self.configs()?;
 
         let template = config.get_string_or_default(CONFIG_TEMPLATE);
@@ -280,15 +382,19 @@ impl AsAgent for TemplateStringAgent {
                 })?;
                 out_arr.push(rendered_string.into());
             }
-            self.output(ctx, PORT_STRING, AgentValue::array(out_arr.into()))
-                .await
+            self.output(
+                self.traced(ctx),
+                PORT_STRING,
+                AgentValue::array(out_arr.into()),
+            )
+            .await
         } else {
             let data = json!({"value": value});
             let rendered_string = reg.render_template(&template, &data).map_err(|e| {
                 AgentError::InvalidValue(format!("Failed to render template: {}", e))
             })?;
             let out_value = AgentValue::string(rendered_string);
-            self.output(ctx, PORT_STRING, out_value).await
+            self.output(self.traced(ctx), PORT_STRING, out_value).await
         }
     }
 }
@@ -300,26 +406,37 @@ impl AsAgent for TemplateStringAgent {
     inputs = [PORT_VALUE],
     outputs = [PORT_STRING],
     text_config(name = CONFIG_TEMPLATE, default = "{{value}}"),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
     hint(color=5),
 )]
 struct TemplateTextAgent {
     data: AgentData,
+    contract: InputContract,
 }
 
 #[async_trait]
 impl AsAgent for TemplateTextAgent {
-    fn new(ma: ModularAgent, id: String, spec: AgentSpec) -> Result<Self, AgentError> {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
         Ok(Self {
             data: AgentData::new(ma, id, spec),
+            contract,
         })
     }
 
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
+    }
+
     async fn process(
         &mut self,
         ctx: AgentContext,
-        _port: String,
+        port: String,
         value: AgentValue,
     ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
         let config = self.configs()?;
 
         let template = config.get_string_or_default(CONFIG_TEMPLATE);
@@ -341,15 +458,19 @@ impl AsAgent for TemplateTextAgent {
                 })?;
                 out_arr.push(rendered_string.into());
             }
-            self.output(ctx, PORT_STRING, AgentValue::array(out_arr.into()))
-                .await
+            self.output(
+                self.traced(ctx),
+                PORT_STRING,
+                AgentValue::array(out_arr.into()),
+            )
+            .await
         } else {
             let data = json!({"value": value});
             let rendered_string = reg.render_template(&template, &data).map_err(|e| {
                 AgentError::InvalidValue(format!("Failed to render template: {}", e))
             })?;
             let out_value = AgentValue::string(rendered_string);
-            self.output(ctx, PORT_STRING, out_value).await
+            self.output(self.traced(ctx), PORT_STRING, out_value).await
         }
     }
 }
@@ -360,26 +481,37 @@ impl AsAgent for TemplateTextAgent {
     category = CATEGORY,
     inputs = [PORT_VALUE],
     outputs = [PORT_STRING],
-    text_config(name = CONFIG_TEMPLATE, default = "{{value}}")
+    text_config(name = CONFIG_TEMPLATE, default = "{{value}}"),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
 )]
 struct TemplateArrayAgent {
     data: AgentData,
+    contract: InputContract,
 }
 
 #[async_trait]
 impl AsAgent for TemplateArrayAgent {
-    fn new(ma: ModularAgent, id: String, spec: AgentSpec) -> Result<Self, AgentError> {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
         Ok(Self {
             data: AgentData::new(ma, id, spec),
+            contract,
         })
     }
 
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
+    }
+
     async fn process(
         &mut self,
         ctx: AgentContext,
-        _port: String,
+        port: String,
         value: AgentValue,
     ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
         let config = self.configs()?;
 
         let template = config.get_string_or_default(CONFIG_TEMPLATE);
@@ -393,20 +525,807 @@ impl AsAgent for TemplateArrayAgent {
             let rendered_string = reg.render_template(&template, &value).map_err(|e| {
                 AgentError::InvalidValue(format!("Failed to render template: {}", e))
             })?;
-            self.output(ctx, PORT_STRING, AgentValue::string(rendered_string))
-                .await
+            self.output(
+                self.traced(ctx),
+                PORT_STRING,
+                AgentValue::string(rendered_string),
+            )
+            .await
         } else {
             let d = AgentValue::array(vector![value.clone()]);
             let rendered_string = reg.render_template(&template, &d).map_err(|e| {
                 AgentError::InvalidValue(format!("Failed to render template: {}", e))
             })?;
             let out_value = AgentValue::string(rendered_string);
-            self.output(ctx, PORT_STRING, out_value).await
+            self.output(self.traced(ctx), PORT_STRING, out_value).await
+        }
+    }
+}
+
+// Template Value Agent
+//
+// With the json format, strings interpolated by `{{...}}` are JSON-escaped, so that
+// `{"name": "{{value}}"}` stays valid whatever the value holds.
+#[modular_agent(
+    title = "Template Value",
+    description = "Renders a template and parses the result as JSON or YAML",
+    category = CATEGORY,
+    inputs = [PORT_VALUE],
+    outputs = [PORT_VALUE],
+    text_config(name = CONFIG_TEMPLATE, default = "{{to_json value}}"),
+    string_config(name = CONFIG_FORMAT, default = FORMAT_JSON, description = "json, yaml"),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
+    hint(color=5),
+)]
+struct TemplateValueAgent {
+    data: AgentData,
+    contract: InputContract,
+}
+
+#[async_trait]
+impl AsAgent for TemplateValueAgent {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
+        Ok(Self {
+            data: AgentData::new(ma, id, spec),
+            contract,
+        })
+    }
+
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
+    }
+
+    async fn process(
+        &mut self,
+        ctx: AgentContext,
+        port: String,
+        value: AgentValue,
+    ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
+        let config = self.configs()?;
+
+        let template = config.get_string_or_default(CONFIG_TEMPLATE);
+        if template.is_empty() {
+            return Err(AgentError::InvalidConfig("template is not set".into()));
+        }
+        let format = config.get_string_or(CONFIG_FORMAT, FORMAT_JSON);
+
+        let mut reg = handlebars_new();
+        if matches!(format.trim(), "" | FORMAT_JSON) {
+            // interpolated strings stay inside their JSON string literals
+            reg.register_escape_fn(json_escape);
+        }
+
+        if value.is_array() {
+            let mut out_arr = Vec::new();
+            for v in value
+                .as_array()
+                .ok_or_else(|| AgentError::InvalidArrayValue("Expected array".into()))?
+            {
+                let data = json!({"value": v});
+                let rendered_string = reg.render_template(&template, &data).map_err(|e| {
+                    AgentError::InvalidValue(format!("Failed to render template: {}", e))
+                })?;
+                out_arr.push(parse_rendered(&rendered_string, &format)?);
+            }
+            self.output(
+                self.traced(ctx),
+                PORT_VALUE,
+                AgentValue::array(out_arr.into()),
+            )
+            .await
+        } else {
+            let data = json!({"value": value});
+            let rendered_string = reg.render_template(&template, &data).map_err(|e| {
+                AgentError::InvalidValue(format!("Failed to render template: {}", e))
+            })?;
+            let out_value = parse_rendered(&rendered_string, &format)?;
+            self.output(self.traced(ctx), PORT_VALUE, out_value).await
+        }
+    }
+}
+
+// Word weights for Sentiment, from -3 (very negative) to 3 (very positive).
+const SENTIMENT_LEXICON: &[(&str, f64)] = &[
+    ("abysmal", -3.0),
+    ("amazing", 3.0),
+    ("angry", -2.0),
+    ("annoying", -2.0),
+    ("awesome", 3.0),
+    ("awful", -3.0),
+    ("bad", -2.0),
+    ("beautiful", 3.0),
+    ("best", 3.0),
+    ("boring", -2.0),
+    ("broken", -2.0),
+    ("bug", -1.0),
+    ("calm", 1.0),
+    ("cancel", -1.0),
+    ("crash", -2.0),
+    ("delay", -1.0),
+    ("delighted", 3.0),
+    ("disappointed", -2.0),
+    ("disappointing", -2.0),
+    ("easy", 1.0),
+    ("error", -2.0),
+    ("excellent", 3.0),
+    ("fail", -2.0),
+    ("failed", -2.0),
+    ("failure", -2.0),
+    ("fantastic", 3.0),
+    ("fast", 1.0),
+    ("fine", 1.0),
+    ("fix", 1.0),
+    ("fixed", 2.0),
+    ("frustrated", -2.0),
+    ("frustrating", -2.0),
+    ("glad", 2.0),
+    ("good", 2.0),
+    ("great", 3.0),
+    ("happy", 3.0),
+    ("hate", -3.0),
+    ("helpful", 2.0),
+    ("horrible", -3.0),
+    ("issue", -1.0),
+    ("love", 3.0),
+    ("nice", 2.0),
+    ("outage", -3.0),
+    ("perfect", 3.0),
+    ("pleased", 2.0),
+    ("poor", -2.0),
+    ("problem", -2.0),
+    ("recommend", 2.0),
+    ("refund", -1.0),
+    ("reliable", 2.0),
+    ("sad", -2.0),
+    ("slow", -1.0),
+    ("smooth", 2.0),
+    ("stable", 1.0),
+    ("terrible", -3.0),
+    ("thank", 2.0),
+    ("thanks", 2.0),
+    ("unhappy", -2.0),
+    ("unusable", -3.0),
+    ("upset", -2.0),
+    ("useful", 2.0),
+    ("useless", -2.0),
+    ("waste", -2.0),
+    ("wonderful", 3.0),
+    ("worse", -2.0),
+    ("worst", -3.0),
+    ("wrong", -2.0),
+];
+
+const SENTIMENT_NEGATIONS: &[&str] = &[
+    "no", "not", "never", "nothing", "neither", "nor", "cannot", "dont", "doesnt", "didnt", "isnt",
+    "wasnt", "arent", "wont", "cant",
+];
+
+const SENTIMENT_INTENSIFIERS: &[&str] = &[
+    "very",
+    "really",
+    "extremely",
+    "so",
+    "totally",
+    "absolutely",
+    "completely",
+];
+
+const KEYWORD_STOPWORDS: &[&str] = &[
+    "a", "about", "after", "all", "also", "am", "an", "and", "any", "are", "as", "at", "be",
+    "been", "before", "but", "by", "can", "could", "did", "do", "does", "for", "from", "had",
+    "has", "have", "he", "her", "here", "him", "his", "how", "i", "if", "in", "into", "is", "it",
+    "its", "just", "me", "more", "my", "no", "not", "of", "on", "one", "only", "or", "our", "out",
+    "she", "should", "so", "some", "than", "that", "the", "their", "them", "then", "there",
+    "these", "they", "this", "to", "too", "up", "us", "very", "was", "we", "were", "what", "when",
+    "where", "which", "while", "who", "why", "will", "with", "would", "you", "your",
+];
+
+// Lowercase words of a text, with apostrophes removed (ex. "don't" -> "dont").
+fn text_words(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric() && c != '\'' && c != '’')
+        .map(|w| w.replace(['\'', '’'], "").to_lowercase())
+        .filter(|w| !w.is_empty())
+        .collect()
+}
+
+/// Scores the sentiment of a text from -1 (negative) to 1 (positive).
+///
+/// The weights of the words in the lexicon are summed, with the weight doubled after an
+/// intensifier (ex. "very") and flipped within three words after a negation in the same
+/// clause (ex. "not good"). The sum is normalized to -1..1.
+fn sentiment_score(text: &str, lexicon: &HashMap<String, f64>) -> f64 {
+    let mut total = 0.0;
+    for clause in text.split(['.', ',', ';', ':', '!', '?']) {
+        total += clause_sentiment(clause, lexicon);
+    }
+    total / (total * total + 15.0).sqrt()
+}
+
+fn clause_sentiment(clause: &str, lexicon: &HashMap<String, f64>) -> f64 {
+    let mut total = 0.0;
+    let mut negated = 0;
+    let mut intensified = false;
+    for word in text_words(clause) {
+        if SENTIMENT_NEGATIONS.contains(&word.as_str()) {
+            negated = 3;
+            continue;
+        }
+        if SENTIMENT_INTENSIFIERS.contains(&word.as_str()) {
+            intensified = true;
+            continue;
+        }
+        if let Some(weight) = lexicon.get(&word) {
+            let mut weight = *weight;
+            if intensified {
+                weight *= 2.0;
+            }
+            if negated > 0 {
+                weight = -weight;
+            }
+            total += weight;
+        }
+        negated = (negated - 1).max(0);
+        intensified = false;
+    }
+    total
+}
+
+/// Scores the sentiment of text.
+///
+/// The score (-1: negative to 1: positive) is output on `score`. The input is also
+/// routed to `positive`, `negative` or `neutral`, by whether the score is above
+/// `threshold`, below `-threshold` or in between.
+///
+/// The score comes from a small embedded English lexicon. `lexicon` adds or overrides
+/// word weights, from -3 to 3 (ex. `{"latency": -1}`).
+#[modular_agent(
+    title = "Sentiment",
+    category = CATEGORY,
+    inputs = [PORT_STRING],
+    outputs = [PORT_SCORE, PORT_POSITIVE, PORT_NEGATIVE, PORT_NEUTRAL],
+    number_config(name = CONFIG_THRESHOLD, default = SENTIMENT_THRESHOLD_DEFAULT),
+    object_config(name = CONFIG_LEXICON),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
+    hint(color=5),
+)]
+struct SentimentAgent {
+    data: AgentData,
+    contract: InputContract,
+    lexicon: HashMap<String, f64>,
+}
+
+impl SentimentAgent {
+    fn update_lexicon(&mut self) -> Result<(), AgentError> {
+        let mut lexicon: HashMap<String, f64> = SENTIMENT_LEXICON
+            .iter()
+            .map(|(w, s)| (w.to_string(), *s))
+            .collect();
+        for (word, weight) in self.configs()?.get_object_or_default(CONFIG_LEXICON).iter() {
+            let weight = weight.as_f64().ok_or_else(|| {
+                AgentError::InvalidConfig(format!("Weight of '{}' must be a number", word))
+            })?;
+            lexicon.insert(word.to_lowercase(), weight.clamp(-3.0, 3.0));
+        }
+        self.lexicon = lexicon;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl AsAgent for SentimentAgent {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
+        let mut agent = Self {
+            data: AgentData::new(ma, id, spec),
+            contract,
+            lexicon: Default::default(),
+        };
+        agent.update_lexicon()?;
+        Ok(agent)
+    }
+
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()?;
+        self.update_lexicon()
+    }
+
+    async fn process(
+        &mut self,
+        ctx: AgentContext,
+        port: String,
+        value: AgentValue,
+    ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
+        let text = value
+            .as_str()
+            .ok_or_else(|| AgentError::InvalidValue("Input value must be a string".into()))?;
+        let threshold = self
+            .configs()?
+            .get_number_or(CONFIG_THRESHOLD, SENTIMENT_THRESHOLD_DEFAULT);
+        let score = sentiment_score(text, &self.lexicon);
+        let port = if score > threshold {
+            PORT_POSITIVE
+        } else if score < -threshold {
+            PORT_NEGATIVE
+        } else {
+            PORT_NEUTRAL
+        };
+        self.output(
+            self.traced(ctx.clone()),
+            PORT_SCORE,
+            AgentValue::number(score),
+        )
+        .await?;
+        self.output(self.traced(ctx), port, value).await
+    }
+}
+
+/// Returns the `top_k` terms of a text by frequency, with their share of the counted
+/// words, most frequent first. Ties keep the order of first appearance.
+fn extract_keywords(
+    text: &str,
+    top_k: usize,
+    min_length: usize,
+    stopwords: &[String],
+) -> Vec<(String, f64)> {
+    let mut counts: Vec<(String, usize)> = Vec::new();
+    // word -> index in counts
+    let mut index: HashMap<String, usize> = HashMap::new();
+    let mut total = 0;
+    for word in text_words(text) {
+        if word.chars().count() < min_length
+            || word.chars().all(|c| c.is_numeric())
+            || KEYWORD_STOPWORDS.contains(&word.as_str())
+            || stopwords.contains(&word)
+        {
+            continue;
+        }
+        total += 1;
+        match index.get(&word) {
+            Some(&i) => counts[i].1 += 1,
+            None => {
+                index.insert(word.clone(), counts.len());
+                counts.push((word, 1));
+            }
+        }
+    }
+    // stable, so ties keep the order of first appearance
+    counts.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+    counts
+        .into_iter()
+        .take(top_k)
+        .map(|(word, count)| (word, count as f64 / total as f64))
+        .collect()
+}
+
+/// Extracts the most frequent terms of text.
+///
+/// Outputs an array of the `top k` terms as `{term, score}`, where `score` is the share
+/// of the term in the counted words. Words shorter than `min length`, numbers, common
+/// English words and the comma separated `stopwords` are not counted.
+#[modular_agent(
+    title = "Extract Keywords",
+    category = CATEGORY,
+    inputs = [PORT_STRING],
+    outputs = [PORT_KEYWORDS],
+    integer_config(name = CONFIG_TOP_K, default = TOP_K_DEFAULT, title = "top k"),
+    integer_config(name = CONFIG_MIN_LENGTH, default = MIN_LENGTH_DEFAULT, title = "min length"),
+    string_config(name = CONFIG_STOPWORDS),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
+    hint(color=5),
+)]
+struct ExtractKeywordsAgent {
+    data: AgentData,
+    contract: InputContract,
+}
+
+#[async_trait]
+impl AsAgent for ExtractKeywordsAgent {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
+        Ok(Self {
+            data: AgentData::new(ma, id, spec),
+            contract,
+        })
+    }
+
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
+    }
+
+    async fn process(
+        &mut self,
+        ctx: AgentContext,
+        port: String,
+        value: AgentValue,
+    ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
+        let text = value
+            .as_str()
+            .ok_or_else(|| AgentError::InvalidValue("Input value must be a string".into()))?;
+        let config = self.configs()?;
+        let top_k = config.get_integer_or(CONFIG_TOP_K, TOP_K_DEFAULT).max(0) as usize;
+        let min_length = config
+            .get_integer_or(CONFIG_MIN_LENGTH, MIN_LENGTH_DEFAULT)
+            .max(0) as usize;
+        let stopwords: Vec<String> = config
+            .get_string_or_default(CONFIG_STOPWORDS)
+            .split(',')
+            .map(|s| s.trim().to_lowercase())
+            .filter(|s| !s.is_empty())
+            .collect();
+
+        let keywords = extract_keywords(text, top_k, min_length, &stopwords)
+            .into_iter()
+            .map(|(term, score)| {
+                AgentValue::object(hashmap! {
+                    "term".to_string() => AgentValue::string(term),
+                    "score".to_string() => AgentValue::number(score),
+                })
+            })
+            .collect();
+        self.output(self.traced(ctx), PORT_KEYWORDS, AgentValue::array(keywords))
+            .await
+    }
+}
+
+// Limit on the size of the table to diff two texts, after their common ends are removed
+const DIFF_MAX_CELLS: usize = 16 * 1024 * 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum DiffOp {
+    Equal,
+    Delete,
+    Insert,
+}
+
+impl DiffOp {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Equal => "equal",
+            Self::Delete => "delete",
+            Self::Insert => "insert",
+        }
+    }
+}
+
+// A range of changes with the unchanged tokens around them. Starts are 1-based.
+struct DiffHunk<'a> {
+    old_start: usize,
+    old_count: usize,
+    new_start: usize,
+    new_count: usize,
+    changes: Vec<(DiffOp, &'a str)>,
+}
+
+// Splits a text into lines, or words.
+fn diff_split(text: &str, words: bool) -> Vec<&str> {
+    if words {
+        text.split_whitespace().collect()
+    } else {
+        text.lines().collect()
+    }
+}
+
+/// Diffs two token sequences by their longest common subsequence.
+fn diff_tokens<'a>(old: &[&'a str], new: &[&'a str]) -> Result<Vec<(DiffOp, &'a str)>, AgentError> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let (a, b) = (
+        &old[prefix..old.len() - suffix],
+        &new[prefix..new.len() - suffix],
+    );
+    if (a.len() + 1) * (b.len() + 1) > DIFF_MAX_CELLS {
+        return Err(AgentError::InvalidValue(
+            "Texts are too large to diff".into(),
+        ));
+    }
+
+    // lcs[i][j]: length of the LCS of a[i..] and b[j..]
+    let width = b.len() + 1;
+    let mut lcs = vec![0u32; (a.len() + 1) * width];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i * width + j] = if a[i] == b[j] {
+                lcs[(i + 1) * width + j + 1] + 1
+            } else {
+                lcs[(i + 1) * width + j].max(lcs[i * width + j + 1])
+            };
+        }
+    }
+
+    let mut ops: Vec<(DiffOp, &str)> = old[..prefix].iter().map(|t| (DiffOp::Equal, *t)).collect();
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            ops.push((DiffOp::Equal, a[i]));
+            i += 1;
+            j += 1;
+        } else if j == b.len()
+            || (i < a.len() && lcs[(i + 1) * width + j] >= lcs[i * width + j + 1])
+        {
+            ops.push((DiffOp::Delete, a[i]));
+            i += 1;
+        } else {
+            ops.push((DiffOp::Insert, b[j]));
+            j += 1;
+        }
+    }
+    ops.extend(
+        old[old.len() - suffix..]
+            .iter()
+            .map(|t| (DiffOp::Equal, *t)),
+    );
+    Ok(ops)
+}
+
+/// Groups the changes into hunks with `context` unchanged tokens around them.
+fn diff_hunks<'a>(ops: &[(DiffOp, &'a str)], context: usize) -> Vec<DiffHunk<'a>> {
+    // positions of each op in the old and new tokens
+    let mut positions = Vec::with_capacity(ops.len() + 1);
+    let (mut old_pos, mut new_pos) = (0, 0);
+    for (op, _) in ops {
+        positions.push((old_pos, new_pos));
+        match op {
+            DiffOp::Equal => {
+                old_pos += 1;
+                new_pos += 1;
+            }
+            DiffOp::Delete => old_pos += 1,
+            DiffOp::Insert => new_pos += 1,
         }
     }
+    positions.push((old_pos, new_pos));
+
+    // ranges of ops, merged when their context overlaps
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+    for (k, (op, _)) in ops.iter().enumerate() {
+        if *op == DiffOp::Equal {
+            continue;
+        }
+        let start = k.saturating_sub(context);
+        let end = (k + 1 + context).min(ops.len());
+        match ranges.last_mut() {
+            Some(last) if start <= last.1 => last.1 = end,
+            _ => ranges.push((start, end)),
+        }
+    }
+
+    ranges
+        .into_iter()
+        .map(|(start, end)| {
+            let (old_from, new_from) = positions[start];
+            let (old_to, new_to) = positions[end];
+            let (old_count, new_count) = (old_to - old_from, new_to - new_from);
+            DiffHunk {
+                // an empty side starts at the token before it, as in unified diffs
+                old_start: if old_count == 0 {
+                    old_from
+                } else {
+                    old_from + 1
+                },
+                old_count,
+                new_start: if new_count == 0 {
+                    new_from
+                } else {
+                    new_from + 1
+                },
+                new_count,
+                changes: ops[start..end].to_vec(),
+            }
+        })
+        .collect()
+}
+
+/// Renders hunks as a unified diff. Words are rendered inline, as `[-old-]{+new+}`.
+fn unified_diff(hunks: &[DiffHunk], words: bool) -> String {
+    if hunks.is_empty() {
+        return String::new();
+    }
+    let mut out = String::from("--- old\n+++ new\n");
+    for hunk in hunks {
+        out.push_str(&format!(
+            "@@ -{},{} +{},{} @@\n",
+            hunk.old_start, hunk.old_count, hunk.new_start, hunk.new_count
+        ));
+        if words {
+            let tokens: Vec<String> = hunk
+                .changes
+                .iter()
+                .map(|(op, token)| match op {
+                    DiffOp::Equal => token.to_string(),
+                    DiffOp::Delete => format!("[-{}-]", token),
+                    DiffOp::Insert => format!("{{+{}+}}", token),
+                })
+                .collect();
+            out.push_str(&tokens.join(" "));
+            out.push('\n');
+        } else {
+            for (op, line) in &hunk.changes {
+                let prefix = match op {
+                    DiffOp::Equal => ' ',
+                    DiffOp::Delete => '-',
+                    DiffOp::Insert => '+',
+                };
+                out.push(prefix);
+                out.push_str(line);
+                out.push('\n');
+            }
+        }
+    }
+    out
+}
+
+/// Diffs two texts.
+///
+/// Once both `old` and `new` have arrived, the diff of the latest of each is output, and
+/// both are cleared. Texts are compared by lines, or by words when `mode` is `word`.
+///
+/// The diff is output as a unified diff string on `diff` (empty when the texts are equal),
+/// and on `hunks` as an array of `{old_start, old_count, new_start, new_count, changes}`,
+/// where `changes` are `{op, text}` with `op` one of `equal`, `delete` and `insert`.
+/// Each hunk keeps `context` unchanged lines or words around its changes.
+#[modular_agent(
+    title = "Text Diff",
+    category = CATEGORY,
+    inputs = [PORT_OLD, PORT_NEW],
+    outputs = [PORT_DIFF, PORT_HUNKS],
+    string_config(name = CONFIG_MODE, default = DIFF_MODE_LINE, description = "line, word"),
+    integer_config(name = CONFIG_CONTEXT, default = CONTEXT_DEFAULT),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
+    hint(color=5),
+)]
+struct TextDiffAgent {
+    data: AgentData,
+    contract: InputContract,
+    old: Option<String>,
+    new: Option<String>,
+}
+
+#[async_trait]
+impl AsAgent for TextDiffAgent {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
+        Ok(Self {
+            data: AgentData::new(ma, id, spec),
+            contract,
+            old: None,
+            new: None,
+        })
+    }
+
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
+    }
+
+    async fn stop(&mut self) -> Result<(), AgentError> {
+        self.old = None;
+        self.new = None;
+        Ok(())
+    }
+
+    async fn process(
+        &mut self,
+        ctx: AgentContext,
+        port: String,
+        value: AgentValue,
+    ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
+        let text = value
+            .as_str()
+            .ok_or_else(|| AgentError::InvalidValue("Input value must be a string".into()))?
+            .to_string();
+        match port.as_str() {
+            PORT_OLD => self.old = Some(text),
+            PORT_NEW => self.new = Some(text),
+            _ => return Err(AgentError::InvalidPin(port)),
+        }
+        if self.old.is_none() || self.new.is_none() {
+            return Ok(());
+        }
+        let (old, new) = (self.old.take().unwrap(), self.new.take().unwrap());
+
+        let config = self.configs()?;
+        let words = match config.get_string_or(CONFIG_MODE, DIFF_MODE_LINE).trim() {
+            "" | DIFF_MODE_LINE => false,
+            DIFF_MODE_WORD => true,
+            other => {
+                return Err(AgentError::InvalidConfig(format!(
+                    "Unknown mode: {}",
+                    other
+                )));
+            }
+        };
+        let context = config
+            .get_integer_or(CONFIG_CONTEXT, CONTEXT_DEFAULT)
+            .max(0) as usize;
+
+        let (old_tokens, new_tokens) = (diff_split(&old, words), diff_split(&new, words));
+        let ops = diff_tokens(&old_tokens, &new_tokens)?;
+        let hunks = diff_hunks(&ops, context);
+
+        let diff = unified_diff(&hunks, words);
+        let hunks = hunks
+            .iter()
+            .map(|hunk| {
+                let changes = hunk
+                    .changes
+                    .iter()
+                    .map(|(op, text)| {
+                        AgentValue::object(hashmap! {
+                            "op".to_string() => AgentValue::string(op.name()),
+                            "text".to_string() => AgentValue::string(*text),
+                        })
+                    })
+                    .collect();
+                AgentValue::object(hashmap! {
+                    "old_start".to_string() => AgentValue::integer(hunk.old_start as i64),
+                    "old_count".to_string() => AgentValue::integer(hunk.old_count as i64),
+                    "new_start".to_string() => AgentValue::integer(hunk.new_start as i64),
+                    "new_count".to_string() => AgentValue::integer(hunk.new_count as i64),
+                    "changes".to_string() => AgentValue::array(changes),
+                })
+            })
+            .collect();
+        self.output(
+            self.traced(ctx.clone()),
+            PORT_DIFF,
+            AgentValue::string(diff),
+        )
+        .await?;
+        self.output(self.traced(ctx), PORT_HUNKS, AgentValue::array(hunks))
+            .await
+    }
+}
+
+const FORMAT_JSON: &str = "json";
+const FORMAT_YAML: &str = "yaml";
+
+fn parse_rendered(s: &str, format: &str) -> Result<AgentValue, AgentError> {
+    let value: serde_json::Value = match format.trim() {
+        "" | FORMAT_JSON => serde_json::from_str(s)
+            .map_err(|e| AgentError::InvalidValue(format!("Failed to parse JSON: {}", e)))?,
+        #[cfg(feature = "yaml")]
+        FORMAT_YAML => serde_yaml_ng::from_str(s)
+            .map_err(|e| AgentError::InvalidValue(format!("Failed to parse YAML: {}", e)))?,
+        #[cfg(not(feature = "yaml"))]
+        FORMAT_YAML => {
+            return Err(AgentError::InvalidConfig(
+                "yaml format requires the yaml feature".into(),
+            ));
+        }
+        other => {
+            return Err(AgentError::InvalidConfig(format!(
+                "Unknown format: {}",
+                other
+            )));
+        }
+    };
+    AgentValue::from_json(value)
+}
+
+/// Escapes `s` as the inside of a JSON string, for templates rendering JSON.
+pub(crate) fn json_escape(s: &str) -> String {
+    let quoted = serde_json::to_string(s).unwrap_or_default();
+    quoted[1..quoted.len() - 1].to_string()
 }
 
-fn handlebars_new<'a>() -> Handlebars<'a> {
+pub(crate) fn handlebars_new<'a>() -> Handlebars<'a> {
     let mut reg = Handlebars::new();
     reg.register_escape_fn(handlebars::no_escape);
     reg.register_helper("to_json", Box::new(to_json_helper));
@@ -0,0 +1,141 @@
+use std::any::Any;
+use std::future::Future;
+use std::time::Duration;
+
+use modular_agent_core::{Agent, AgentError, AgentOutput, AgentValue, ModularAgent};
+use tokio::task::JoinHandle;
+
+pub(crate) const CONFIG_MAX_RESTARTS: &str = "max_restarts";
+pub(crate) const CONFIG_TASK_RESTARTS: &str = "task_restarts";
+
+pub(crate) const MAX_RESTARTS_DEFAULT: i64 = -1;
+
+const RESTART_DELAY: Duration = Duration::from_secs(1);
+
+const MAX_RESTART_DELAY: Duration = Duration::from_secs(60);
+
+// Aborts the task when dropped, so that aborting the supervisor also aborts the task.
+struct AbortOnDrop(JoinHandle<()>);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// Spawns a task that is restarted when it panics.
+///
+/// `make_task` creates the task future, first on spawn and again after every panic.
+/// Panics are logged with the agent id and counted in the agent's `task_restarts` config.
+/// Restarts are delayed by [`restart_delay`]. After `max_restarts` restarts (-1: unlimited)
+/// the task is left stopped.
+///
+/// Aborting the returned handle also aborts the running task.
+pub(crate) fn spawn_supervised<T, F, Fut>(
+    agent: &T,
+    max_restarts: i64,
+    make_task: F,
+) -> JoinHandle<()>
+where
+    T: Agent,
+    F: Fn() -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    let ma = agent.ma().clone();
+    let agent_id = agent.id().to_string();
+
+    agent.runtime().spawn(async move {
+        let mut restarts = 0;
+        loop {
+            let mut task = AbortOnDrop(tokio::spawn(make_task()));
+            let err = match (&mut task.0).await {
+                Ok(()) => break,
+                Err(e) if e.is_panic() => e,
+                Err(_) => break,
+            };
+
+            log::error!(
+                "Task of agent '{}' panicked: {}",
+                agent_id,
+                panic_message(err.into_panic())
+            );
+            if max_restarts >= 0 && restarts >= max_restarts {
+                log::error!(
+                    "Task of agent '{}' is not restarted: max restarts ({}) reached",
+                    agent_id,
+                    max_restarts
+                );
+                break;
+            }
+
+            restarts += 1;
+            update_task_restarts::<T>(&ma, &agent_id, restarts).await;
+
+            tokio::time::sleep(restart_delay(restarts)).await;
+            log::info!("Restarting task of agent '{}' ({})", agent_id, restarts);
+        }
+    })
+}
+
+/// Returns the delay before the `restarts`th restart: one second, doubled on each
+/// restart up to a minute, so that a task panicking right away does not spin.
+pub(crate) fn restart_delay(restarts: i64) -> Duration {
+    let doublings = restarts.saturating_sub(1).clamp(0, 6) as u32;
+    (RESTART_DELAY * 2u32.pow(doublings)).min(MAX_RESTART_DELAY)
+}
+
+/// Resets the `task_restarts` config of the agent to 0.
+pub(crate) fn reset_task_restarts<T: Agent>(agent: &mut T) -> Result<(), AgentError> {
+    let value = AgentValue::integer(0);
+    agent.set_config(CONFIG_TASK_RESTARTS.to_string(), value.clone())?;
+    agent.emit_config_updated(CONFIG_TASK_RESTARTS, value);
+    Ok(())
+}
+
+pub(crate) async fn update_task_restarts<T: Agent>(
+    ma: &ModularAgent,
+    agent_id: &str,
+    restarts: i64,
+) {
+    let Some(agent) = ma.get_agent(agent_id) else {
+        return;
+    };
+    let mut agent = agent.lock().await;
+    let Some(agent) = agent.as_agent_mut::<T>() else {
+        return;
+    };
+    let value = AgentValue::integer(restarts);
+    if let Err(e) = agent.set_config(CONFIG_TASK_RESTARTS.to_string(), value.clone()) {
+        log::error!(
+            "Failed to update task restarts of agent '{}': {}",
+            agent_id,
+            e
+        );
+        return;
+    }
+    agent.emit_config_updated(CONFIG_TASK_RESTARTS, value);
+}
+
+pub(crate) fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        s.to_string()
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_restart_delay() {
+        assert_eq!(restart_delay(1), Duration::from_secs(1));
+        assert_eq!(restart_delay(2), Duration::from_secs(2));
+        assert_eq!(restart_delay(5), Duration::from_secs(16));
+        assert_eq!(restart_delay(7), Duration::from_secs(60));
+        assert_eq!(restart_delay(i64::MAX), Duration::from_secs(60));
+    }
+}
@@ -0,0 +1,332 @@
+use modular_agent_core::{
+    Agent, AgentContext, AgentData, AgentError, AgentOutput, AgentSpec, AgentValue, AsAgent,
+    ModularAgent, async_trait, modular_agent,
+};
+use std::time::Duration;
+
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+
+use crate::contract::{CONFIG_INPUT_CONTRACT, ContractAgent, InputContract, contract_agents};
+use crate::control::{PORT_PAUSE, PORT_RESUME, PauseState};
+use crate::data::{envelope_parts, render_meta};
+use crate::provenance::{Traced, stamp};
+use crate::quota::admit;
+
+const CATEGORY: &str = "Std/System";
+
+const PORT_EOF: &str = "eof";
+const PORT_STRING: &str = "string";
+const PORT_VALUE: &str = "value";
+
+const CONFIG_FORMAT: &str = "format";
+const CONFIG_SKIP_EMPTY: &str = "skip_empty";
+
+const FORMAT_RAW: &str = "raw";
+const FORMAT_JSON: &str = "json";
+const FORMAT_JSON_PRETTY: &str = "json_pretty";
+
+// how long stop waits for the lines read so far to be output
+const STOP_FLUSH_TIMEOUT: Duration = Duration::from_secs(1);
+
+contract_agents!(StdoutWriteAgent);
+
+/// Emits each line read from the process stdin while the agent runs.
+///
+/// Line endings are stripped. Unit is emitted on `eof` when stdin is closed.
+/// Stdin is shared by the whole process, so only one Stdin Lines agent should run at a time.
+/// While paused, stdin is still read and its lines are dropped; `eof` is emitted anyway.
+/// On stop, the lines already read from stdin are output before the agent stops.
+#[modular_agent(
+    title = "Stdin Lines",
+    category = CATEGORY,
+    inputs = [PORT_PAUSE, PORT_RESUME],
+    outputs = [PORT_STRING, PORT_EOF],
+    boolean_config(name = CONFIG_SKIP_EMPTY, title = "skip empty"),
+    hint(color=2),
+)]
+struct StdinLinesAgent {
+    data: AgentData,
+    // the reader task, and the signal to stop it
+    reader: Option<(oneshot::Sender<()>, JoinHandle<()>)>,
+    paused: PauseState,
+}
+
+// Outputs of the reader task of a Stdin Lines agent
+struct StdinOutput {
+    ma: ModularAgent,
+    agent_id: String,
+    def_name: String,
+    preset_id: String,
+    skip_empty: bool,
+    paused: PauseState,
+}
+
+impl StdinOutput {
+    async fn send_line(&self, line: &str) {
+        if self.paused.is_paused() || (self.skip_empty && line.trim().is_empty()) {
+            return;
+        }
+        let value = AgentValue::string(line);
+        if admit(&self.preset_id, &self.agent_id, &value) {
+            self.send(PORT_STRING, value).await;
+        }
+    }
+
+    async fn send(&self, port: &str, value: AgentValue) {
+        if let Err(e) = self
+            .ma
+            .send_agent_out(
+                self.agent_id.clone(),
+                stamp(AgentContext::new(), &self.agent_id, &self.def_name),
+                port.to_string(),
+                value,
+            )
+            .await
+        {
+            log::error!("Failed to send stdin line: {}", e);
+        }
+    }
+}
+
+#[async_trait]
+impl AsAgent for StdinLinesAgent {
+    fn new(ma: ModularAgent, id: String, spec: AgentSpec) -> Result<Self, AgentError> {
+        Ok(Self {
+            data: AgentData::new(ma, id, spec),
+            reader: None,
+            paused: Default::default(),
+        })
+    }
+
+    async fn start(&mut self) -> Result<(), AgentError> {
+        self.paused.set_paused(false);
+        let output = StdinOutput {
+            ma: self.ma().clone(),
+            agent_id: self.id().to_string(),
+            def_name: self.def_name().to_string(),
+            preset_id: self.preset_id().to_string(),
+            skip_empty: self.configs()?.get_bool_or_default(CONFIG_SKIP_EMPTY),
+            paused: self.paused.clone(),
+        };
+        let (stop_tx, mut stop_rx) = oneshot::channel();
+
+        let handle = self.runtime().spawn(async move {
+            let mut reader = BufReader::new(tokio::io::stdin());
+            // the line being read, kept across the select so no byte is lost
+            let mut buf = Vec::new();
+            loop {
+                tokio::select! {
+                    biased;
+                    _ = &mut stop_rx => {
+                        for line in complete_lines(&buf, reader.buffer()) {
+                            output.send_line(&line).await;
+                        }
+                        break;
+                    }
+                    read = reader.read_until(b'\n', &mut buf) => {
+                        match read {
+                            Ok(0) if buf.is_empty() => {
+                                output.send(PORT_EOF, AgentValue::unit()).await;
+                                break;
+                            }
+                            Ok(_) => {}
+                            Err(e) => {
+                                log::error!("Failed to read stdin: {}", e);
+                                break;
+                            }
+                        }
+                        let line = line_text(&buf);
+                        buf.clear();
+                        output.send_line(&line).await;
+                    }
+                }
+            }
+        });
+        self.reader = Some((stop_tx, handle));
+        Ok(())
+    }
+
+    async fn stop(&mut self) -> Result<(), AgentError> {
+        if let Some((stop_tx, mut handle)) = self.reader.take() {
+            // let the task output the lines it has read, then stop waiting for stdin
+            let _ = stop_tx.send(());
+            if tokio::time::timeout(STOP_FLUSH_TIMEOUT, &mut handle)
+                .await
+                .is_err()
+            {
+                handle.abort();
+            }
+        }
+        Ok(())
+    }
+
+    async fn process(
+        &mut self,
+        _ctx: AgentContext,
+        port: String,
+        _value: AgentValue,
+    ) -> Result<(), AgentError> {
+        self.paused.handle_port(&port);
+        Ok(())
+    }
+}
+
+/// Writes each input value to stdout, one per line.
+///
+/// In `raw` format strings are written as is, envelopes (see Wrap) as their metadata in
+/// brackets followed by their payload, and other values as JSON.
+/// `json` and `json_pretty` write every value as JSON.
+/// The value is passed through on `value`.
+#[modular_agent(
+    title = "Stdout Write",
+    category = CATEGORY,
+    inputs = [PORT_VALUE],
+    outputs = [PORT_VALUE],
+    string_config(name = CONFIG_FORMAT, default = FORMAT_RAW, description = "raw, json, json_pretty"),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
+    hint(color=2),
+)]
+struct StdoutWriteAgent {
+    data: AgentData,
+    contract: InputContract,
+}
+
+#[async_trait]
+impl AsAgent for StdoutWriteAgent {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
+        Ok(Self {
+            data: AgentData::new(ma, id, spec),
+            contract,
+        })
+    }
+
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
+    }
+
+    async fn process(
+        &mut self,
+        ctx: AgentContext,
+        port: String,
+        value: AgentValue,
+    ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
+        let format = self.configs()?.get_string_or(CONFIG_FORMAT, FORMAT_RAW);
+        let mut line = format_line(&value, &format)?;
+        line.push('\n');
+
+        let mut stdout = tokio::io::stdout();
+        stdout
+            .write_all(line.as_bytes())
+            .await
+            .map_err(|e| AgentError::IoError(format!("Failed to write stdout: {}", e)))?;
+        stdout
+            .flush()
+            .await
+            .map_err(|e| AgentError::IoError(format!("Failed to flush stdout: {}", e)))?;
+
+        self.output(self.traced(ctx), PORT_VALUE, value).await
+    }
+}
+
+// Returns a line read from stdin without its line ending.
+fn line_text(bytes: &[u8]) -> String {
+    let line = String::from_utf8_lossy(bytes);
+    let line = line.strip_suffix('\n').unwrap_or(&line);
+    line.strip_suffix('\r').unwrap_or(line).to_string()
+}
+
+// Returns the complete lines of the bytes read from stdin but not output yet: the
+// start of the current line, then the rest of the reader's buffer. A last line
+// without its line ending is left unread.
+fn complete_lines(line: &[u8], buffered: &[u8]) -> Vec<String> {
+    let bytes = [line, buffered].concat();
+    let Some(end) = bytes.iter().rposition(|b| *b == b'\n') else {
+        return Vec::new();
+    };
+    bytes[..=end]
+        .split_inclusive(|b| *b == b'\n')
+        .map(line_text)
+        .collect()
+}
+
+fn format_line(value: &AgentValue, format: &str) -> Result<String, AgentError> {
+    let json = |pretty: bool| {
+        let result = if pretty {
+            serde_json::to_string_pretty(value)
+        } else {
+            serde_json::to_string(value)
+        };
+        result.map_err(|e| AgentError::InvalidValue(format!("Failed to serialize value: {}", e)))
+    };
+    match format.trim() {
+        "" | FORMAT_RAW => match value {
+            AgentValue::String(s) => Ok(s.to_string()),
+            _ => match envelope_parts(value) {
+                Some((payload, meta)) => Ok(format!(
+                    "[{}] {}",
+                    render_meta(meta),
+                    format_line(payload, FORMAT_RAW)?
+                )),
+                None => json(false),
+            },
+        },
+        FORMAT_JSON => json(false),
+        FORMAT_JSON_PRETTY => json(true),
+        other => Err(AgentError::InvalidConfig(format!(
+            "Unknown format: {}",
+            other
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use im::hashmap;
+
+    use super::*;
+
+    #[test]
+    fn test_format_line() {
+        let s = AgentValue::string("a b");
+        let obj = AgentValue::object(hashmap! {"n".to_string() => AgentValue::integer(1)});
+
+        assert_eq!(format_line(&s, "raw").unwrap(), "a b");
+        assert_eq!(format_line(&obj, "raw").unwrap(), r#"{"n":1}"#);
+        assert_eq!(format_line(&s, "json").unwrap(), r#""a b""#);
+        assert_eq!(
+            format_line(&obj, "json_pretty").unwrap(),
+            "{\n  \"n\": 1\n}"
+        );
+        assert!(format_line(&s, "xml").is_err());
+
+        let envelope = AgentValue::object(hashmap! {
+            "value".into() => s.clone(),
+            "meta".into() => AgentValue::object(hashmap! {
+                "source".into() => AgentValue::string("cam"),
+                "attempt".into() => AgentValue::integer(2),
+            }),
+        });
+        assert_eq!(format_line(&envelope, "raw").unwrap(), "[cam #2] a b");
+    }
+
+    #[test]
+    fn test_complete_lines() {
+        assert_eq!(line_text(b"a b\r\n"), "a b");
+        assert_eq!(line_text(b"\n"), "");
+        assert_eq!(line_text(b"tail"), "tail");
+
+        // the partial line continues in the buffer, and the unfinished last line is left
+        assert_eq!(
+            complete_lines(b"fir", b"st\n\nthird\r\nfour"),
+            vec!["first", "", "third"]
+        );
+        assert!(complete_lines(b"", b"").is_empty());
+        assert!(complete_lines(b"no end", b"").is_empty());
+    }
+}
@@ -0,0 +1,297 @@
+//! Tenants.
+//!
+//! The Tenant agent puts a tenant id in the `tenant` variable of the context. Stateful
+//! agents with the `per tenant` config keep separate state for each tenant id found in
+//! the context, so one graph can serve many customers without mixing their state.
+//! Values without a tenant share the state of the empty tenant id. An agent keeps the
+//! state of at most `MAX_TENANTS` tenants; beyond that, the tenant seen least recently
+//! loses its state.
+
+use std::collections::{HashMap, VecDeque};
+
+use modular_agent_core::{
+    Agent, AgentContext, AgentData, AgentError, AgentOutput, AgentSpec, AgentValue, AsAgent,
+    ModularAgent, async_trait, modular_agent,
+};
+
+use crate::contract::{CONFIG_INPUT_CONTRACT, ContractAgent, InputContract, contract_agents};
+use crate::data::get_nested_value;
+use crate::profile::ProfileConfigs;
+use crate::provenance::Traced;
+
+const CATEGORY: &str = "Std/Flow";
+
+const PORT_VALUE: &str = "value";
+
+const CONFIG_TENANT: &str = "tenant";
+const CONFIG_TENANT_KEY: &str = "tenant_key";
+
+pub(crate) const CONFIG_PER_TENANT: &str = "per_tenant";
+
+pub(crate) const VAR_TENANT: &str = "tenant";
+
+const MAX_TENANTS: usize = 10_000;
+
+/// Returns the tenant id in the context, or an empty string.
+pub(crate) fn tenant_id(ctx: &AgentContext) -> String {
+    match ctx.get_var(VAR_TENANT) {
+        Some(AgentValue::String(s)) => s.to_string(),
+        Some(AgentValue::Integer(i)) => i.to_string(),
+        _ => String::new(),
+    }
+}
+
+/// Returns the key of the state for `ctx`: its tenant id when `per_tenant`, otherwise
+/// an empty string shared by all values.
+pub(crate) fn tenant_key(ctx: &AgentContext, per_tenant: bool) -> String {
+    if per_tenant {
+        tenant_id(ctx)
+    } else {
+        String::new()
+    }
+}
+
+/// State of an agent by tenant key, holding at most `MAX_TENANTS` tenants.
+///
+/// Adding a tenant over the limit evicts the tenant used least recently.
+pub(crate) struct TenantMap<V> {
+    // the state and the tick it was last used at
+    entries: HashMap<String, (V, u64)>,
+    tick: u64,
+    capacity: usize,
+}
+
+impl<V> Default for TenantMap<V> {
+    fn default() -> Self {
+        Self::with_capacity(MAX_TENANTS)
+    }
+}
+
+impl<V> TenantMap<V> {
+    fn with_capacity(capacity: usize) -> Self {
+        Self {
+            entries: HashMap::new(),
+            tick: 0,
+            capacity: capacity.max(1),
+        }
+    }
+
+    /// Returns the state of `tenant`, created by `f` if missing.
+    pub(crate) fn get_or_insert_with(&mut self, tenant: String, f: impl FnOnce() -> V) -> &mut V {
+        self.tick += 1;
+        if !self.entries.contains_key(&tenant) && self.entries.len() >= self.capacity {
+            self.evict();
+        }
+        let entry = self.entries.entry(tenant).or_insert_with(|| (f(), 0));
+        entry.1 = self.tick;
+        &mut entry.0
+    }
+
+    /// Sets the state of `tenant`, and returns the previous one.
+    pub(crate) fn insert(&mut self, tenant: String, value: V) -> Option<V> {
+        let prev = self.remove(&tenant);
+        self.get_or_insert_with(tenant, || value);
+        prev
+    }
+
+    pub(crate) fn get(&self, tenant: &str) -> Option<&V> {
+        self.entries.get(tenant).map(|(value, _)| value)
+    }
+
+    pub(crate) fn remove(&mut self, tenant: &str) -> Option<V> {
+        self.entries.remove(tenant).map(|(value, _)| value)
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    pub(crate) fn values(&self) -> impl Iterator<Item = &V> {
+        self.entries.values().map(|(value, _)| value)
+    }
+
+    pub(crate) fn values_mut(&mut self) -> impl Iterator<Item = &mut V> {
+        self.entries.values_mut().map(|(value, _)| value)
+    }
+
+    fn evict(&mut self) {
+        let oldest = self
+            .entries
+            .iter()
+            .min_by_key(|(_, (_, tick))| *tick)
+            .map(|(tenant, _)| tenant.clone());
+        if let Some(tenant) = oldest {
+            log::warn!(
+                "Over {} tenants; dropping the state of tenant '{}'",
+                self.capacity,
+                tenant
+            );
+            self.entries.remove(&tenant);
+        }
+    }
+}
+
+impl<V: Default> TenantMap<V> {
+    /// Returns the state of `tenant`, created empty if missing.
+    pub(crate) fn entry(&mut self, tenant: String) -> &mut V {
+        self.get_or_insert_with(tenant, V::default)
+    }
+}
+
+impl<T: Clone> TenantMap<Vec<VecDeque<T>>> {
+    /// Queues `value` on input `idx` of the `n` FIFO queues of `tenant`. Once every
+    /// queue has a value, pops and returns their heads.
+    pub(crate) fn push_zip(
+        &mut self,
+        tenant: String,
+        n: usize,
+        idx: usize,
+        value: T,
+    ) -> Option<Vec<T>> {
+        let queues = self.get_or_insert_with(tenant, || vec![VecDeque::new(); n]);
+        queues.get_mut(idx)?.push_back(value);
+        if queues.iter().any(|q| q.is_empty()) {
+            return None;
+        }
+        queues.iter_mut().map(|q| q.pop_front()).collect()
+    }
+}
+
+fn tenant_of(value: &AgentValue, tenant_keys: &[String], tenant: &str) -> Option<String> {
+    if !tenant_keys.is_empty() {
+        return match get_nested_value(value, tenant_keys) {
+            Some(AgentValue::String(s)) if !s.trim().is_empty() => Some(s.trim().to_string()),
+            Some(AgentValue::Integer(i)) => Some(i.to_string()),
+            _ => None,
+        };
+    }
+    let tenant = tenant.trim();
+    (!tenant.is_empty()).then(|| tenant.to_string())
+}
+
+contract_agents!(TenantAgent);
+
+/// Stamps a tenant id into the context, and passes the value on.
+///
+/// The tenant id is the string or integer at `tenant key` of the value, or `tenant`
+/// when the key is blank (`${env:NAME}` and `${profile:key}` are resolved).
+/// A value without a tenant id is an error.
+///
+/// Counter, Delta, Aggregate, Throttle Time, the Time Series agents, preset quotas and
+/// the queues of Sync, ZipToArray, ZipToObject, Coalesce and Set Ops keep their state
+/// per tenant downstream when their `per tenant` config is set.
+#[modular_agent(
+    title = "Tenant",
+    category = CATEGORY,
+    inputs = [PORT_VALUE],
+    outputs = [PORT_VALUE],
+    string_config(name = CONFIG_TENANT),
+    string_config(name = CONFIG_TENANT_KEY, title = "tenant key"),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
+    hint(color=4),
+)]
+struct TenantAgent {
+    data: AgentData,
+    contract: InputContract,
+    // the tenant config, resolved
+    tenant: String,
+}
+
+#[async_trait]
+impl AsAgent for TenantAgent {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
+        Ok(Self {
+            data: AgentData::new(ma, id, spec),
+            contract,
+            tenant: String::new(),
+        })
+    }
+
+    async fn start(&mut self) -> Result<(), AgentError> {
+        self.tenant = self.configs()?.get_string_resolved(CONFIG_TENANT)?;
+        Ok(())
+    }
+
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()?;
+        self.tenant = self.configs()?.get_string_resolved(CONFIG_TENANT)?;
+        Ok(())
+    }
+
+    async fn process(
+        &mut self,
+        ctx: AgentContext,
+        port: String,
+        value: AgentValue,
+    ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
+        let configs = self.configs()?;
+        let tenant_keys: Vec<String> = configs
+            .get_string_or_default(CONFIG_TENANT_KEY)
+            .split('.')
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+            .collect();
+        let Some(tenant) = tenant_of(&value, &tenant_keys, &self.tenant) else {
+            return Err(AgentError::InvalidValue(
+                "No tenant id for the value".into(),
+            ));
+        };
+        let ctx = ctx.with_var(VAR_TENANT.to_string(), AgentValue::string(tenant));
+        self.output(self.traced(ctx), PORT_VALUE, value).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use im::hashmap;
+
+    use super::*;
+
+    #[test]
+    fn test_tenant() {
+        let value = AgentValue::object(hashmap! {
+            "customer".to_string() => AgentValue::object(hashmap! {
+                "id".to_string() => AgentValue::integer(42),
+            }),
+        });
+        let keys = vec!["customer".to_string(), "id".to_string()];
+        assert_eq!(tenant_of(&value, &keys, "fixed"), Some("42".to_string()));
+        assert_eq!(tenant_of(&value, &[], " fixed "), Some("fixed".to_string()));
+        assert_eq!(tenant_of(&value, &["missing".to_string()], "fixed"), None);
+        assert_eq!(tenant_of(&value, &[], ""), None);
+
+        let ctx = AgentContext::new();
+        assert_eq!(tenant_key(&ctx, true), "");
+        let ctx = ctx.with_var(VAR_TENANT.to_string(), AgentValue::string("acme"));
+        assert_eq!(tenant_key(&ctx, true), "acme");
+        assert_eq!(tenant_key(&ctx, false), "");
+    }
+
+    #[test]
+    fn test_tenant_map() {
+        let mut map = TenantMap::with_capacity(2);
+        *map.entry("a".to_string()) += 1;
+        *map.entry("b".to_string()) += 2;
+        // a is used after b, so b is evicted for c
+        *map.entry("a".to_string()) += 1;
+        *map.entry("c".to_string()) += 3;
+        assert_eq!(map.remove("b"), None);
+        assert_eq!(map.insert("c".to_string(), 5), Some(3));
+        assert_eq!(map.remove("a"), Some(2));
+        assert_eq!(map.values().copied().collect::<Vec<_>>(), vec![5]);
+        map.clear();
+        assert_eq!(map.values().count(), 0);
+
+        let mut queues = TenantMap::default();
+        assert_eq!(queues.push_zip("a".to_string(), 2, 0, 1), None);
+        assert_eq!(queues.push_zip("b".to_string(), 2, 1, 2), None);
+        assert_eq!(queues.push_zip("a".to_string(), 2, 0, 3), None);
+        assert_eq!(queues.push_zip("a".to_string(), 2, 1, 4), Some(vec![1, 4]));
+        assert_eq!(queues.push_zip("b".to_string(), 2, 0, 5), Some(vec![5, 2]));
+        assert_eq!(queues.push_zip("a".to_string(), 2, 1, 6), Some(vec![3, 6]));
+    }
+}
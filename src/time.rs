@@ -1,86 +1,314 @@
+use std::collections::{HashMap, VecDeque};
 use std::str::FromStr;
-use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
 use std::time::Duration;
 use std::vec;
 
 use chrono::{DateTime, Local, Utc};
 use cron::Schedule;
+use im::hashmap;
 use log;
 use modular_agent_core::{
     Agent, AgentContext, AgentData, AgentError, AgentOutput, AgentSpec, AgentStatus, AgentValue,
     AsAgent, ModularAgent, async_trait, modular_agent,
 };
 use regex::Regex;
+use tokio::sync::mpsc;
 use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+use crate::contract::{CONFIG_INPUT_CONTRACT, ContractAgent, InputContract, contract_agents};
+use crate::control::{PORT_PAUSE, PORT_RESUME, PauseState};
+use crate::data::get_nested_value;
+use crate::journal::{CONFIG_JOB, CONFIG_JOURNAL, CONFIG_RESUME, Journal};
+use crate::provenance::{Traced, stamp};
+use crate::quota::admit;
+use crate::scheduler::{Timer, schedule, schedule_supervised};
+use crate::supervisor::{
+    CONFIG_MAX_RESTARTS, CONFIG_TASK_RESTARTS, MAX_RESTARTS_DEFAULT, reset_task_restarts,
+};
+use crate::tenant::{CONFIG_PER_TENANT, TenantMap, tenant_key};
 
 const CATEGORY: &str = "Std/Time";
 
+pub(crate) const PORT_EXPIRED: &str = "expired";
+const PORT_PROGRESS: &str = "progress";
+const PORT_RESULT: &str = "result";
+const PORT_START: &str = "start";
 const PORT_TIME: &str = "time";
+const PORT_TIMEOUT: &str = "timeout";
 const PORT_VALUE: &str = "value";
 const PORT_UNIT: &str = "unit";
 
 const CONFIG_DELAY: &str = "delay";
+const CONFIG_DELAY_KEY: &str = "delay_key";
+const CONFIG_MAX_DELAY: &str = "max_delay";
+const CONFIG_MIN_DELAY: &str = "min_delay";
 const CONFIG_MAX_NUM_DATA: &str = "max_num_data";
+const CONFIG_MODE: &str = "mode";
 const CONFIG_INTERVAL: &str = "interval";
+const CONFIG_REPEAT: &str = "repeat";
 const CONFIG_SCHEDULE: &str = "schedule";
 const CONFIG_TIME: &str = "time";
+const CONFIG_TIMEOUT: &str = "timeout";
+const CONFIG_USE_CTX: &str = "use_ctx";
 
 const DELAY_MS_DEFAULT: i64 = 1000; // 1 second in milliseconds
 const MAX_NUM_DATA_DEFAULT: i64 = 10;
 const INTERVAL_DEFAULT: &str = "10s";
 const TIME_DEFAULT: &str = "1s";
+const KEEPALIVE_TIMEOUT_DEFAULT: &str = "30s";
+const MAX_SUPERVISIONS: usize = 10_000;
+const DEADLINE_TIMEOUT_DEFAULT: &str = "30s";
+
+const VAR_DEADLINE: &str = "deadline";
+
+const MODE_QUEUE: &str = "queue";
+const MODE_LEADING: &str = "leading";
+const MODE_TRAILING: &str = "trailing";
+const MODE_BOTH: &str = "both";
+
+/// Returns the deadline of the context in milliseconds since the epoch, if any.
+pub(crate) fn deadline_ms(ctx: &AgentContext) -> Option<i64> {
+    ctx.get_var(VAR_DEADLINE).and_then(|v| v.as_i64())
+}
+
+/// Returns true if the deadline of the context has passed.
+pub(crate) fn is_expired(ctx: &AgentContext) -> bool {
+    deadline_ms(ctx).is_some_and(|deadline| Utc::now().timestamp_millis() >= deadline)
+}
+
+/// Returns the deadline of the context as an instant, if any.
+pub(crate) fn deadline_instant(ctx: &AgentContext) -> Option<Instant> {
+    let remaining_ms = deadline_ms(ctx)? - Utc::now().timestamp_millis();
+    Some(Instant::now() + Duration::from_millis(remaining_ms.max(0) as u64))
+}
+
+contract_agents!(DeadlineAgent, DelayAgent, ThrottleTimeAgent, KeepaliveAgent);
 
-// Delay Agent
+/// Deadline Agent
+///
+/// Attaches a deadline `timeout` from now to the context, and passes the value on.
+/// A deadline already in the context is kept if it is earlier. A value whose context
+/// has already expired is output on `expired` instead.
+///
+/// Delay, Submit Job, Poll Job Status and Fetch Job Result stop working on values whose
+/// deadline has passed, and output them on their `expired` pin.
+#[modular_agent(
+    title = "Deadline",
+    category = CATEGORY,
+    inputs = [PORT_VALUE],
+    outputs = [PORT_VALUE, PORT_EXPIRED],
+    string_config(name = CONFIG_TIMEOUT, default = DEADLINE_TIMEOUT_DEFAULT, description = "(ex. 10s, 5m, 100ms, 1h, 1d)"),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
+    hint(color=2),
+)]
+struct DeadlineAgent {
+    data: AgentData,
+    contract: InputContract,
+}
+
+#[async_trait]
+impl AsAgent for DeadlineAgent {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
+        Ok(Self {
+            data: AgentData::new(ma, id, spec),
+            contract,
+        })
+    }
+
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
+    }
+
+    async fn process(
+        &mut self,
+        ctx: AgentContext,
+        port: String,
+        value: AgentValue,
+    ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
+        if is_expired(&ctx) {
+            return self.output(self.traced(ctx), PORT_EXPIRED, value).await;
+        }
+        let timeout_ms = parse_duration_to_ms(
+            &self
+                .configs()?
+                .get_string_or(CONFIG_TIMEOUT, DEADLINE_TIMEOUT_DEFAULT),
+        )?;
+        let mut deadline = Utc::now().timestamp_millis() + timeout_ms as i64;
+        if let Some(current) = deadline_ms(&ctx) {
+            deadline = deadline.min(current);
+        }
+        let ctx = ctx.with_var(VAR_DEADLINE.to_string(), AgentValue::integer(deadline));
+        self.output(self.traced(ctx), PORT_VALUE, value).await
+    }
+}
+
+/// Delay Agent
+///
+/// When `delay key` is set and the value has a number at that key path
+/// (ex. `retry_after_ms`), the value is delayed by that many milliseconds,
+/// clamped to `min delay` and `max delay`. Otherwise it is delayed by `delay`.
+///
+/// A value whose context deadline (see Deadline) passes before the delay ends is output
+/// on `expired` at the deadline.
+///
+/// Delayed values are output in turn by a task of the agent, which waits for the
+/// downstream agents. Values that arrive while `max num data` values are waiting or being
+/// output are dropped.
 #[modular_agent(
     title = "Delay",
     description = "Delays output by a specified time",
     category = CATEGORY,
     inputs = [PORT_VALUE],
-    outputs = [PORT_VALUE],
+    outputs = [PORT_VALUE, PORT_EXPIRED],
     integer_config(name = CONFIG_DELAY, default = DELAY_MS_DEFAULT, title = "delay (ms)"),
+    string_config(name = CONFIG_DELAY_KEY, title = "delay key", description = "key path of the delay (ms) in the value"),
+    integer_config(name = CONFIG_MIN_DELAY, title = "min delay (ms)"),
+    integer_config(name = CONFIG_MAX_DELAY, default = -1, title = "max delay (ms)", description = "-1: unlimited"),
     integer_config(name = CONFIG_MAX_NUM_DATA, default = MAX_NUM_DATA_DEFAULT, title = "max num data"),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
     hint(color=2),
 )]
 struct DelayAgent {
     data: AgentData,
-    num_waiting_data: Arc<Mutex<i64>>,
+    contract: InputContract,
+    // timers of the waiting data
+    timers: Vec<Timer>,
+    // number of values waiting or being output
+    pending: Arc<AtomicU64>,
+    // task outputting the values whose delay has ended
+    outputs: Option<(DelayedSender, JoinHandle<()>)>,
+}
+
+type DelayedSender = mpsc::UnboundedSender<(AgentContext, String, AgentValue)>;
+
+impl DelayAgent {
+    // Returns the sender to the output task, starting the task if needed.
+    fn output_sender(&mut self) -> DelayedSender {
+        if let Some((sender, task)) = &self.outputs
+            && !task.is_finished()
+        {
+            return sender.clone();
+        }
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let ma = self.ma().clone();
+        let agent_id = self.id().to_string();
+        let pending = self.pending.clone();
+        let task = tokio::spawn(async move {
+            while let Some((ctx, port, value)) = receiver.recv().await {
+                if let Err(e) = ma.send_agent_out(agent_id.clone(), ctx, port, value).await {
+                    log::error!("Failed to send delayed output: {}", e);
+                }
+                pending.fetch_sub(1, Ordering::Relaxed);
+            }
+        });
+        self.outputs = Some((sender.clone(), task));
+        sender
+    }
+
+    fn delay_ms(&self, value: &AgentValue) -> Result<u64, AgentError> {
+        let config = self.configs()?;
+        let delay_ms = config.get_integer_or(CONFIG_DELAY, DELAY_MS_DEFAULT);
+
+        let delay_key = config.get_string_or_default(CONFIG_DELAY_KEY);
+        let delay_key = delay_key.trim();
+        if delay_key.is_empty() {
+            return Ok(delay_ms.max(0) as u64);
+        }
+        let keys: Vec<&str> = delay_key.split('.').collect();
+        let Some(value_ms) = get_nested_value(value, &keys).and_then(|v| v.as_f64()) else {
+            return Ok(delay_ms.max(0) as u64);
+        };
+
+        let mut delay_ms = value_ms.max(0.0) as i64;
+        let max_delay = config.get_integer_or(CONFIG_MAX_DELAY, -1);
+        if max_delay >= 0 {
+            delay_ms = delay_ms.min(max_delay);
+        }
+        delay_ms = delay_ms.max(config.get_integer_or_default(CONFIG_MIN_DELAY));
+        Ok(delay_ms.max(0) as u64)
+    }
 }
 
 #[async_trait]
 impl AsAgent for DelayAgent {
-    fn new(ma: ModularAgent, id: String, spec: AgentSpec) -> Result<Self, AgentError> {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
         Ok(Self {
             data: AgentData::new(ma, id, spec),
-            num_waiting_data: Arc::new(Mutex::new(0)),
+            contract,
+            timers: Vec::new(),
+            pending: Arc::new(AtomicU64::new(0)),
+            outputs: None,
         })
     }
 
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
+    }
+
+    async fn stop(&mut self) -> Result<(), AgentError> {
+        // Dropping the timers cancels them
+        self.timers.clear();
+        if let Some((_, task)) = self.outputs.take() {
+            task.abort();
+        }
+        self.pending = Arc::new(AtomicU64::new(0));
+        Ok(())
+    }
+
     async fn process(
         &mut self,
         ctx: AgentContext,
         port: String,
         value: AgentValue,
     ) -> Result<(), AgentError> {
-        let config = self.configs()?;
-        let delay_ms = config.get_integer_or(CONFIG_DELAY, DELAY_MS_DEFAULT);
-        let max_num_data = config.get_integer_or(CONFIG_MAX_NUM_DATA, MAX_NUM_DATA_DEFAULT);
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
+        if is_expired(&ctx) {
+            return self.output(self.traced(ctx), PORT_EXPIRED, value).await;
+        }
+        let delay_ms = self.delay_ms(&value)?;
+        let max_num_data = self
+            .configs()?
+            .get_integer_or(CONFIG_MAX_NUM_DATA, MAX_NUM_DATA_DEFAULT);
 
         // To avoid generating too many timers
-        {
-            let num_waiting_data = self.num_waiting_data.clone();
-            let mut num_waiting_data = num_waiting_data.lock().unwrap();
-            if *num_waiting_data >= max_num_data {
-                return Ok(());
-            }
-            *num_waiting_data += 1;
+        self.timers.retain(|timer| timer.is_active());
+        if self.pending.load(Ordering::Relaxed) as i64 >= max_num_data {
+            return Ok(());
         }
 
-        tokio::time::sleep(Duration::from_millis(delay_ms as u64)).await;
-
-        self.output(ctx.clone(), port, value.clone()).await?;
-
-        let mut num_waiting_data = self.num_waiting_data.lock().unwrap();
-        *num_waiting_data -= 1;
+        let agent_id = self.id().to_string();
+        let def_name = self.def_name().to_string();
+        let sender = self.output_sender();
+        let pending = self.pending.clone();
+        pending.fetch_add(1, Ordering::Relaxed);
+
+        let deadline = Instant::now() + Duration::from_millis(delay_ms);
+        // Stop waiting when the context expires first
+        let (deadline, port) = match deadline_instant(&ctx) {
+            Some(expires) if expires < deadline => (expires, PORT_EXPIRED.to_string()),
+            _ => (deadline, port),
+        };
+        let mut data = Some((ctx, port, value));
+        let timer = schedule(self, deadline, move |_| {
+            if let Some((ctx, port, value)) = data.take() {
+                let ctx = stamp(ctx, &agent_id, &def_name);
+                if sender.send((ctx, port, value)).is_err() {
+                    pending.fetch_sub(1, Ordering::Relaxed);
+                }
+            }
+            None
+        });
+        self.timers.push(timer);
 
         Ok(())
     }
@@ -91,62 +319,64 @@ impl AsAgent for DelayAgent {
     title = "Interval Timer",
     description = "Outputs a unit signal at specified intervals",
     category = CATEGORY,
+    inputs = [PORT_PAUSE, PORT_RESUME],
     outputs = [PORT_UNIT],
     string_config(name = CONFIG_INTERVAL, default = INTERVAL_DEFAULT, description = "(ex. 10s, 5m, 100ms, 1h, 1d)"),
+    integer_config(name = CONFIG_MAX_RESTARTS, default = MAX_RESTARTS_DEFAULT, title = "max restarts", description = "-1: unlimited", detail),
+    integer_config(name = CONFIG_TASK_RESTARTS, title = "task restarts", readonly, detail),
+    string_config(name = CONFIG_JOURNAL, description = "JSONL file of the runs; empty: off", detail),
+    string_config(name = CONFIG_JOB, description = "name of the runs in the journal", detail),
+    boolean_config(name = CONFIG_RESUME, description = "trigger unfinished runs again on start", detail),
     hint(color=2),
 )]
 struct IntervalTimerAgent {
     data: AgentData,
-    timer_handle: Arc<Mutex<Option<JoinHandle<()>>>>,
+    timer: Option<Timer>,
     interval_ms: u64,
+    journal: Option<Journal>,
+    paused: PauseState,
 }
 
 impl IntervalTimerAgent {
     fn start_timer(&mut self) -> Result<(), AgentError> {
-        let timer_handle = self.timer_handle.clone();
-        let interval_ms = self.interval_ms;
+        let interval = Duration::from_millis(self.interval_ms);
+        let paused = self.paused.clone();
 
         let ma = self.ma().clone();
         let agent_id = self.id().to_string();
-        let handle = self.runtime().spawn(async move {
-            loop {
-                // Sleep for the configured interval
-                tokio::time::sleep(tokio::time::Duration::from_millis(interval_ms)).await;
-
-                // Check if we've been stopped
-                if let Ok(handle) = timer_handle.lock() {
-                    if handle.is_none() {
-                        break;
-                    }
-                }
-
+        let def_name = self.def_name().to_string();
+        let max_restarts = self
+            .configs()?
+            .get_integer_or(CONFIG_MAX_RESTARTS, MAX_RESTARTS_DEFAULT);
+        let preset_id = self.preset_id().to_string();
+        let journal = self.journal.clone();
+        let deadline = Instant::now() + interval;
+        let timer = schedule_supervised(self, max_restarts, deadline, move |deadline| {
+            if !paused.is_paused() && admit(&preset_id, &agent_id, &AgentValue::unit()) {
+                let ctx = match &journal {
+                    Some(journal) => journal.start_run(AgentContext::new(), &AgentValue::unit()),
+                    None => AgentContext::new(),
+                };
                 // Create a unit output
                 if let Err(e) = ma.try_send_agent_out(
                     agent_id.clone(),
-                    AgentContext::new(),
+                    stamp(ctx, &agent_id, &def_name),
                     PORT_UNIT.to_string(),
                     AgentValue::unit(),
                 ) {
                     log::error!("Failed to send interval timer output: {}", e);
                 }
             }
+            Some(deadline + interval)
         });
-
-        // Store the timer handle
-        if let Ok(mut timer_handle) = self.timer_handle.lock() {
-            *timer_handle = Some(handle);
-        }
+        self.timer = Some(timer);
 
         Ok(())
     }
 
     fn stop_timer(&mut self) -> Result<(), AgentError> {
-        // Cancel the timer
-        if let Ok(mut timer_handle) = self.timer_handle.lock() {
-            if let Some(handle) = timer_handle.take() {
-                handle.abort();
-            }
-        }
+        // Dropping the timer cancels it
+        self.timer = None;
         Ok(())
     }
 }
@@ -160,15 +390,23 @@ impl AsAgent for IntervalTimerAgent {
             .ok_or(AgentError::NoConfig)?
             .get_string_or(CONFIG_INTERVAL, INTERVAL_DEFAULT);
         let interval_ms = parse_duration_to_ms(&interval)?;
+        let journal = Journal::from_configs(spec.configs.as_ref().ok_or(AgentError::NoConfig)?)?;
 
         Ok(Self {
             data: AgentData::new(ma, id, spec),
-            timer_handle: Default::default(),
+            timer: None,
             interval_ms,
+            journal,
+            paused: Default::default(),
         })
     }
 
     async fn start(&mut self) -> Result<(), AgentError> {
+        reset_task_restarts(self)?;
+        self.paused.set_paused(false);
+        if let Some(journal) = &self.journal {
+            journal.spawn_resume(self.ma(), self.id(), self.def_name(), PORT_UNIT);
+        }
         self.start_timer()
     }
 
@@ -176,12 +414,24 @@ impl AsAgent for IntervalTimerAgent {
         self.stop_timer()
     }
 
+    async fn process(
+        &mut self,
+        _ctx: AgentContext,
+        port: String,
+        _value: AgentValue,
+    ) -> Result<(), AgentError> {
+        self.paused.handle_port(&port);
+        Ok(())
+    }
+
     fn configs_changed(&mut self) -> Result<(), AgentError> {
         // Check if interval has changed
         let interval = self.configs()?.get_string(CONFIG_INTERVAL)?;
         let new_interval = parse_duration_to_ms(&interval)?;
-        if new_interval != self.interval_ms {
+        let journal = Journal::from_configs(self.configs()?)?;
+        if new_interval != self.interval_ms || journal != self.journal {
             self.interval_ms = new_interval;
+            self.journal = journal;
             if *self.status() == AgentStatus::Start {
                 // Restart the timer with the new interval
                 self.stop_timer()?;
@@ -202,6 +452,7 @@ impl AsAgent for IntervalTimerAgent {
 )]
 struct OnStartAgent {
     data: AgentData,
+    timer: Option<Timer>,
 }
 
 #[async_trait]
@@ -209,6 +460,7 @@ impl AsAgent for OnStartAgent {
     fn new(ma: ModularAgent, id: String, spec: AgentSpec) -> Result<Self, AgentError> {
         Ok(Self {
             data: AgentData::new(ma, id, spec),
+            timer: None,
         })
     }
 
@@ -218,36 +470,51 @@ impl AsAgent for OnStartAgent {
 
         let ma = self.ma().clone();
         let agent_id = self.id().to_string();
+        let def_name = self.def_name().to_string();
 
-        self.runtime().spawn(async move {
-            tokio::time::sleep(Duration::from_millis(delay_ms as u64)).await;
-
+        let deadline = Instant::now() + Duration::from_millis(delay_ms as u64);
+        let timer = schedule(self, deadline, move |_| {
             if let Err(e) = ma.try_send_agent_out(
-                agent_id,
-                AgentContext::new(),
+                agent_id.clone(),
+                stamp(AgentContext::new(), &agent_id, &def_name),
                 PORT_UNIT.to_string(),
                 AgentValue::unit(),
             ) {
                 log::error!("Failed to send delayed output: {}", e);
             }
+            None
         });
+        self.timer = Some(timer);
 
         Ok(())
     }
+
+    async fn stop(&mut self) -> Result<(), AgentError> {
+        self.timer = None;
+        Ok(())
+    }
 }
 
 // Schedule Timer Agent
 #[modular_agent(
     title = "Schedule Timer",
     category = CATEGORY,
+    inputs = [PORT_PAUSE, PORT_RESUME],
     outputs = [PORT_TIME],
     string_config(name = CONFIG_SCHEDULE, default = "0 0 * * * *", description = "sec min hour day month week year"),
+    integer_config(name = CONFIG_MAX_RESTARTS, default = MAX_RESTARTS_DEFAULT, title = "max restarts", description = "-1: unlimited", detail),
+    integer_config(name = CONFIG_TASK_RESTARTS, title = "task restarts", readonly, detail),
+    string_config(name = CONFIG_JOURNAL, description = "JSONL file of the runs; empty: off", detail),
+    string_config(name = CONFIG_JOB, description = "name of the runs in the journal", detail),
+    boolean_config(name = CONFIG_RESUME, description = "trigger unfinished runs again on start", detail),
     hint(color=2),
 )]
 struct ScheduleTimerAgent {
     data: AgentData,
     cron_schedule: Option<Schedule>,
-    timer_handle: Arc<Mutex<Option<JoinHandle<()>>>>,
+    timer: Option<Timer>,
+    journal: Option<Journal>,
+    paused: PauseState,
 }
 
 impl ScheduleTimerAgent {
@@ -258,80 +525,51 @@ impl ScheduleTimerAgent {
 
         let ma = self.ma().clone();
         let agent_id = self.id().to_string();
-        let timer_handle = self.timer_handle.clone();
+        let def_name = self.def_name().to_string();
         let schedule = schedule.clone();
+        let paused = self.paused.clone();
+        let preset_id = self.preset_id().to_string();
+        let journal = self.journal.clone();
 
-        let handle = self.runtime().spawn(async move {
-            loop {
-                // Calculate the next time this schedule should run
-                let now: DateTime<Utc> = Utc::now();
-                let next = match schedule.upcoming(Utc).next() {
-                    Some(next_time) => next_time,
-                    None => {
-                        log::error!("No upcoming schedule times found");
-                        break;
-                    }
-                };
-
-                // Calculate the duration until the next scheduled time
-                let duration = match (next - now).to_std() {
-                    Ok(duration) => duration,
-                    Err(e) => {
-                        log::error!("Failed to calculate duration until next schedule: {}", e);
-                        // If we can't calculate the duration, sleep for a short time and try again
-                        tokio::time::sleep(Duration::from_secs(60)).await;
-                        continue;
-                    }
-                };
-
-                let next_local = next.with_timezone(&Local);
-                log::debug!(
-                    "Scheduling timer for '{}' to fire at {} (in {:?})",
-                    agent_id,
-                    next_local.format("%Y-%m-%d %H:%M:%S %z"),
-                    duration
-                );
-
-                // Sleep until the next scheduled time
-                tokio::time::sleep(duration).await;
-
-                // Check if we've been stopped
-                if let Ok(handle) = timer_handle.lock() {
-                    if handle.is_none() {
-                        break;
-                    }
-                }
-
+        let Some(deadline) = next_schedule_deadline(&schedule, &agent_id) else {
+            return Ok(());
+        };
+        let max_restarts = self
+            .configs()?
+            .get_integer_or(CONFIG_MAX_RESTARTS, MAX_RESTARTS_DEFAULT);
+        let timer = schedule_supervised(self, max_restarts, deadline, move |_| {
+            if !paused.is_paused() {
                 // Get the current local timestamp (in seconds)
-                let current_local_time = Local::now().timestamp();
-
-                // Output the timestamp as an integer
-                if let Err(e) = ma.try_send_agent_out(
-                    agent_id.clone(),
-                    AgentContext::new(),
-                    PORT_TIME.to_string(),
-                    AgentValue::integer(current_local_time),
-                ) {
-                    log::error!("Failed to send schedule timer output: {}", e);
+                let current_local_time = AgentValue::integer(Local::now().timestamp());
+
+                // Output the timestamp as an integer, unless over the quota
+                if admit(&preset_id, &agent_id, &current_local_time) {
+                    let ctx = match &journal {
+                        Some(journal) => {
+                            journal.start_run(AgentContext::new(), &current_local_time)
+                        }
+                        None => AgentContext::new(),
+                    };
+                    if let Err(e) = ma.try_send_agent_out(
+                        agent_id.clone(),
+                        stamp(ctx, &agent_id, &def_name),
+                        PORT_TIME.to_string(),
+                        current_local_time,
+                    ) {
+                        log::error!("Failed to send schedule timer output: {}", e);
+                    }
                 }
             }
+            next_schedule_deadline(&schedule, &agent_id)
         });
-
-        // Store the timer handle
-        if let Ok(mut timer_handle) = self.timer_handle.lock() {
-            *timer_handle = Some(handle);
-        }
+        self.timer = Some(timer);
 
         Ok(())
     }
 
     fn stop_timer(&mut self) -> Result<(), AgentError> {
-        // Cancel the timer
-        if let Ok(mut timer_handle) = self.timer_handle.lock() {
-            if let Some(handle) = timer_handle.take() {
-                handle.abort();
-            }
-        }
+        // Dropping the timer cancels it
+        self.timer = None;
         Ok(())
     }
 
@@ -357,11 +595,19 @@ impl AsAgent for ScheduleTimerAgent {
             .as_ref()
             .map(|cfg| cfg.get_string(CONFIG_SCHEDULE))
             .transpose()?;
+        let journal = spec
+            .configs
+            .as_ref()
+            .map(Journal::from_configs)
+            .transpose()?
+            .flatten();
 
         let mut agent = Self {
             data: AgentData::new(ma, id, spec),
             cron_schedule: None,
-            timer_handle: Default::default(),
+            timer: None,
+            journal,
+            paused: Default::default(),
         };
 
         if let Some(schedule_str) = schedule_str {
@@ -374,6 +620,11 @@ impl AsAgent for ScheduleTimerAgent {
     }
 
     async fn start(&mut self) -> Result<(), AgentError> {
+        reset_task_restarts(self)?;
+        self.paused.set_paused(false);
+        if let Some(journal) = &self.journal {
+            journal.spawn_resume(self.ma(), self.id(), self.def_name(), PORT_TIME);
+        }
         if self.cron_schedule.is_some() {
             self.start_timer()?;
         }
@@ -384,10 +635,21 @@ impl AsAgent for ScheduleTimerAgent {
         self.stop_timer()
     }
 
+    async fn process(
+        &mut self,
+        _ctx: AgentContext,
+        port: String,
+        _value: AgentValue,
+    ) -> Result<(), AgentError> {
+        self.paused.handle_port(&port);
+        Ok(())
+    }
+
     fn configs_changed(&mut self) -> Result<(), AgentError> {
         // Check if schedule has changed
         let schedule_str = self.configs()?.get_string(CONFIG_SCHEDULE)?;
         self.parse_schedule(&schedule_str)?;
+        self.journal = Journal::from_configs(self.configs()?)?;
 
         if *self.status() == AgentStatus::Start {
             // Restart the timer with the new schedule
@@ -400,85 +662,242 @@ impl AsAgent for ScheduleTimerAgent {
     }
 }
 
+// Calculates the next time the schedule should run
+pub(crate) fn next_schedule_deadline(schedule: &Schedule, agent_id: &str) -> Option<Instant> {
+    let now: DateTime<Utc> = Utc::now();
+    let Some(next) = schedule.upcoming(Utc).next() else {
+        log::error!("No upcoming schedule times found");
+        return None;
+    };
+    // The next time may have just passed while calculating it
+    let duration = (next - now).to_std().unwrap_or_default();
+
+    let next_local = next.with_timezone(&Local);
+    log::debug!(
+        "Scheduling timer for '{}' to fire at {} (in {:?})",
+        agent_id,
+        next_local.format("%Y-%m-%d %H:%M:%S %z"),
+        duration
+    );
+    Some(Instant::now() + duration)
+}
+
 // Throttle agent
+/// Outputs at most one value per `time` window, depending on `mode`:
+///
+/// - `queue`: the first value is output immediately, the values received during the window
+///   are queued (up to `max num data`) and output one per window
+/// - `leading`: the first value is output immediately, the values received during the window
+///   are dropped
+/// - `trailing`: the last value received during the window is output at its end
+/// - `both`: the first value is output immediately, and the last value received during the
+///   window at its end
+///
+/// With `per tenant`, each tenant id in the context is throttled separately.
 #[modular_agent(
     title = "Throttle Time",
     category = CATEGORY,
     inputs = [PORT_VALUE],
     outputs = [PORT_VALUE],
     string_config(name = CONFIG_TIME, default = TIME_DEFAULT, description = "(ex. 10s, 5m, 100ms, 1h, 1d)"),
-    integer_config(name = CONFIG_MAX_NUM_DATA, title = "max num data", description = "0: no data, -1: all data"),
+    string_config(name = CONFIG_MODE, default = MODE_QUEUE, description = "queue, leading, trailing, both"),
+    integer_config(name = CONFIG_MAX_NUM_DATA, title = "max num data", description = "queue mode. 0: no data, -1: all data"),
+    boolean_config(name = CONFIG_PER_TENANT, title = "per tenant"),
+    integer_config(name = CONFIG_MAX_RESTARTS, default = MAX_RESTARTS_DEFAULT, title = "max restarts", description = "-1: unlimited", detail),
+    integer_config(name = CONFIG_TASK_RESTARTS, title = "task restarts", readonly, detail),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
     hint(color=2),
 )]
 struct ThrottleTimeAgent {
     data: AgentData,
-    timer_handle: Arc<Mutex<Option<JoinHandle<()>>>>,
+    contract: InputContract,
     time_ms: u64,
+    mode: ThrottleMode,
     max_num_data: i64,
-    waiting_data: Arc<Mutex<Vec<(AgentContext, String, AgentValue)>>>,
+    // throttled values by tenant id
+    lanes: TenantMap<ThrottleLane>,
+    // tenants whose timer has stopped, to forget on the next value
+    idle: Arc<Mutex<Vec<String>>>,
 }
 
-impl ThrottleTimeAgent {
-    fn start_timer(&mut self) -> Result<(), AgentError> {
-        let timer_handle = self.timer_handle.clone();
-        let time_ms = self.time_ms;
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ThrottleMode {
+    Queue,
+    Leading,
+    Trailing,
+    Both,
+}
 
-        let waiting_data = self.waiting_data.clone();
-        let ma = self.ma().clone();
-        let agent_id = self.id().to_string();
+impl ThrottleMode {
+    fn parse(mode: &str) -> Result<Self, AgentError> {
+        match mode.trim() {
+            "" | MODE_QUEUE => Ok(Self::Queue),
+            MODE_LEADING => Ok(Self::Leading),
+            MODE_TRAILING => Ok(Self::Trailing),
+            MODE_BOTH => Ok(Self::Both),
+            other => Err(AgentError::InvalidConfig(format!(
+                "Unknown mode: {}",
+                other
+            ))),
+        }
+    }
+}
 
-        let handle = self.runtime().spawn(async move {
-            loop {
-                // Sleep for the configured interval
-                tokio::time::sleep(tokio::time::Duration::from_millis(time_ms)).await;
+type Waiting = (AgentContext, String, AgentValue);
+
+// Shared by the agent and its timer. Whether the timer is running is kept under the same
+// lock as the waiting data, so that data queued while the timer stops is not left behind.
+//
+// This is a std mutex rather than a tokio one: the timer callback runs synchronously on
+// the scheduler task and cannot await a lock, and neither side holds it across an await
+// or while sending.
+#[derive(Default)]
+struct ThrottleQueue {
+    running: bool,
+    waiting_data: VecDeque<Waiting>,
+}
 
-                // Check if we've been stopped
-                let mut handle = timer_handle.lock().unwrap();
-                if handle.is_none() {
-                    break;
-                }
+// What `process` does with a value offered to the queue.
+#[derive(Debug)]
+enum Offered {
+    // the window is running; the value waits or is dropped by the mode
+    Held,
+    // a new window starts; the value is output now, or at its end if none is returned
+    Start(Option<Waiting>),
+}
 
-                // process the waiting data
-                let mut wd = waiting_data.lock().unwrap();
-                if wd.len() > 0 {
-                    // If there are data waiting, output the first one
-                    let (ctx, port, data) = wd.remove(0);
-                    ma.try_send_agent_out(agent_id.clone(), ctx, port, data)
-                        .unwrap_or_else(|e| {
-                            log::error!("Failed to send delayed output: {}", e);
-                        });
-                }
+// The window of one tenant.
+#[derive(Default)]
+struct ThrottleLane {
+    timer: Option<Timer>,
+    queue: Arc<Mutex<ThrottleQueue>>,
+}
 
-                // If there are no data waiting, we stop the timer
-                if wd.len() == 0 {
-                    handle.take();
-                    break;
+impl ThrottleLane {
+    fn is_running(&self) -> bool {
+        lock_queue(&self.queue).running && self.timer.as_ref().is_some_and(|t| t.is_active())
+    }
+}
+
+// Locks the queue even if a panicking timer poisoned it, so that the restarted timer
+// does not panic again on the lock.
+fn lock_queue(queue: &Mutex<ThrottleQueue>) -> MutexGuard<'_, ThrottleQueue> {
+    queue.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+impl ThrottleQueue {
+    // Called by `process` with a new value.
+    fn offer(
+        &mut self,
+        mode: ThrottleMode,
+        max_num_data: i64,
+        timer_active: bool,
+        waiting: Waiting,
+    ) -> Offered {
+        // The timer may also have been stopped by panics beyond max restarts
+        if self.running && timer_active {
+            // If the timer is running, we just add the data to the waiting list
+            match mode {
+                ThrottleMode::Queue => {
+                    self.waiting_data.push_back(waiting);
+                    self.truncate(max_num_data);
+                }
+                ThrottleMode::Leading => {}
+                ThrottleMode::Trailing | ThrottleMode::Both => {
+                    // only the last one is output at the end of the window
+                    self.waiting_data.clear();
+                    self.waiting_data.push_back(waiting);
                 }
             }
-        });
+            return Offered::Held;
+        }
+        self.running = true;
+
+        if mode == ThrottleMode::Trailing {
+            // Start the window, and output the data at its end
+            self.waiting_data.push_back(waiting);
+            return Offered::Start(None);
+        }
+        Offered::Start(Some(waiting))
+    }
+
+    // Called by the timer at the end of each window. Returns the data to output, or
+    // none when the timer stops.
+    fn next(&mut self) -> Option<Waiting> {
+        let next = self.waiting_data.pop_front();
+        if next.is_none() {
+            self.running = false;
+        }
+        next
+    }
 
-        // Store the timer handle
-        if let Ok(mut timer_handle) = self.timer_handle.lock() {
-            *timer_handle = Some(handle);
+    fn truncate(&mut self, max_num_data: i64) {
+        if max_num_data >= 0 {
+            // If we have reached the max data to keep, we drop the oldest ones
+            while self.waiting_data.len() > max_num_data as usize {
+                self.waiting_data.pop_front();
+            }
         }
+    }
+}
+
+impl ThrottleTimeAgent {
+    fn start_timer(&mut self, tenant: &str) -> Result<(), AgentError> {
+        let time = Duration::from_millis(self.time_ms);
+
+        let queue = self.lanes.entry(tenant.to_string()).queue.clone();
+        let idle = self.idle.clone();
+        let lane_tenant = tenant.to_string();
+        let ma = self.ma().clone();
+        let agent_id = self.id().to_string();
+        let def_name = self.def_name().to_string();
+
+        let max_restarts = self
+            .configs()?
+            .get_integer_or(CONFIG_MAX_RESTARTS, MAX_RESTARTS_DEFAULT);
+        let deadline = Instant::now() + time;
+        let timer = schedule_supervised(self, max_restarts, deadline, move |deadline| {
+            // process the waiting data
+            let next = {
+                let next = lock_queue(&queue).next();
+                if next.is_none() {
+                    // If there are no data waiting, we stop the timer
+                    idle.lock()
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .push(lane_tenant.clone());
+                }
+                next
+            };
+            let (ctx, port, data) = next?;
+
+            // If there are data waiting, output the first one
+            let ctx = stamp(ctx, &agent_id, &def_name);
+            ma.try_send_agent_out(agent_id.clone(), ctx, port, data)
+                .unwrap_or_else(|e| {
+                    log::error!("Failed to send delayed output: {}", e);
+                });
+            Some(deadline + time)
+        });
+        self.lanes.entry(tenant.to_string()).timer = Some(timer);
 
         Ok(())
     }
 
     fn stop_timer(&mut self) -> Result<(), AgentError> {
-        // Cancel the timer
-        if let Ok(mut timer_handle) = self.timer_handle.lock() {
-            if let Some(handle) = timer_handle.take() {
-                handle.abort();
-            }
-        }
+        // Dropping the timers cancels them
+        self.lanes.clear();
+        self.idle
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clear();
         Ok(())
     }
 }
 
 #[async_trait]
 impl AsAgent for ThrottleTimeAgent {
-    fn new(ma: ModularAgent, id: String, spec: AgentSpec) -> Result<Self, AgentError> {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
         let time = spec
             .configs
             .as_ref()
@@ -486,6 +905,13 @@ impl AsAgent for ThrottleTimeAgent {
             .get_string_or(CONFIG_TIME, TIME_DEFAULT);
         let time_ms = parse_duration_to_ms(&time)?;
 
+        let mode = spec
+            .configs
+            .as_ref()
+            .ok_or(AgentError::NoConfig)?
+            .get_string_or(CONFIG_MODE, MODE_QUEUE);
+        let mode = ThrottleMode::parse(&mode)?;
+
         let max_num_data = spec
             .configs
             .as_ref()
@@ -494,18 +920,25 @@ impl AsAgent for ThrottleTimeAgent {
 
         Ok(Self {
             data: AgentData::new(ma, id, spec),
-            timer_handle: Default::default(),
+            contract,
             time_ms,
+            mode,
             max_num_data,
-            waiting_data: Arc::new(Mutex::new(vec![])),
+            lanes: TenantMap::default(),
+            idle: Default::default(),
         })
     }
 
+    async fn start(&mut self) -> Result<(), AgentError> {
+        reset_task_restarts(self)
+    }
+
     async fn stop(&mut self) -> Result<(), AgentError> {
         self.stop_timer()
     }
 
     fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()?;
         // Check if interval has changed
         let time = self.configs()?.get_string(CONFIG_TIME)?;
         let new_time = parse_duration_to_ms(&time)?;
@@ -513,14 +946,14 @@ impl AsAgent for ThrottleTimeAgent {
             self.time_ms = new_time;
         }
 
+        let mode = self.configs()?.get_string_or(CONFIG_MODE, MODE_QUEUE);
+        self.mode = ThrottleMode::parse(&mode)?;
+
         // Check if max_num_data has changed
         let max_num_data = self.configs()?.get_integer(CONFIG_MAX_NUM_DATA)?;
         if self.max_num_data != max_num_data {
-            let mut wd = self.waiting_data.lock().unwrap();
-            let wd_len = wd.len();
-            if max_num_data >= 0 && wd_len > (max_num_data as usize) {
-                // If we have reached the max data to keep, we drop the oldest one
-                wd.drain(0..(wd_len - (max_num_data as usize)));
+            for lane in self.lanes.values() {
+                lock_queue(&lane.queue).truncate(max_num_data);
             }
             self.max_num_data = max_num_data;
         }
@@ -533,36 +966,222 @@ impl AsAgent for ThrottleTimeAgent {
         port: String,
         value: AgentValue,
     ) -> Result<(), AgentError> {
-        if self.timer_handle.lock().unwrap().is_some() {
-            // If the timer is running, we just add the data to the waiting list
-            let mut wd = self.waiting_data.lock().unwrap();
-
-            // If max_num_data is 0, we don't need to keep any data
-            if self.max_num_data == 0 {
-                return Ok(());
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
+        let tenant = tenant_key(&ctx, self.configs()?.get_bool_or_default(CONFIG_PER_TENANT));
+        // Forget the tenants whose window has ended, unless a new one has started since
+        let idle = std::mem::take(&mut *self.idle.lock().unwrap_or_else(PoisonError::into_inner));
+        for t in idle {
+            if self.lanes.get(&t).is_some_and(|lane| !lane.is_running()) {
+                self.lanes.remove(&t);
             }
+        }
+        let lane = self.lanes.entry(tenant.clone());
+        let timer_active = lane.timer.as_ref().is_some_and(|timer| timer.is_active());
+        let offered = lock_queue(&lane.queue).offer(
+            self.mode,
+            self.max_num_data,
+            timer_active,
+            (ctx, port, value),
+        );
+        let Offered::Start(first) = offered else {
+            return Ok(());
+        };
 
-            wd.push((ctx, port, value));
-            if self.max_num_data > 0 && wd.len() > self.max_num_data as usize {
-                // If we have reached the max data to keep, we drop the oldest one
-                wd.remove(0);
-            }
+        // Start the timer
+        self.start_timer(&tenant)?;
+
+        // Output the data
+        if let Some((ctx, port, value)) = first {
+            self.output(self.traced(ctx), port, value).await?;
+        }
 
+        Ok(())
+    }
+}
+
+/// Keepalive agent
+///
+/// Supervises a long operation: a value on `start` or `progress` expects another signal
+/// within `timeout`. If none arrives, `{value, last_progress, waited_ms, count}` is output
+/// on `timeout`, where `value` is the last start value and `count` the number of
+/// escalations so far; with `repeat`, this is repeated every `timeout` until a signal
+/// arrives. A value on `result` ends the supervision, suppressing pending escalations,
+/// and is passed through on `result`.
+///
+/// A `progress` without a supervision (not started, or ended by `result`) is ignored.
+/// When `use_ctx` is true, each context (its id and map frames) is supervised on its
+/// own, for up to 10,000 contexts at a time.
+#[modular_agent(
+    title = "Keepalive",
+    category = CATEGORY,
+    inputs = [PORT_START, PORT_PROGRESS, PORT_RESULT],
+    outputs = [PORT_RESULT, PORT_TIMEOUT],
+    string_config(name = CONFIG_TIMEOUT, default = KEEPALIVE_TIMEOUT_DEFAULT, description = "(ex. 10s, 5m, 100ms, 1h, 1d)"),
+    boolean_config(name = CONFIG_REPEAT),
+    boolean_config(name = CONFIG_USE_CTX),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
+    hint(color=2),
+)]
+struct KeepaliveAgent {
+    data: AgentData,
+    contract: InputContract,
+    // context key (empty without use_ctx) -> supervision
+    supervisions: HashMap<String, Supervision>,
+}
+
+struct Supervision {
+    timer: Option<Timer>,
+    // incremented when the supervision is re-armed or ended, so that an escalation
+    // already firing can tell it is stale
+    generation: Arc<AtomicU64>,
+    start_value: AgentValue,
+}
+
+impl Drop for Supervision {
+    fn drop(&mut self) {
+        self.generation.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+impl KeepaliveAgent {
+    fn arm(
+        &mut self,
+        key: String,
+        ctx: AgentContext,
+        last_progress: AgentValue,
+    ) -> Result<(), AgentError> {
+        let Some(supervision) = self.supervisions.get(&key) else {
             return Ok(());
+        };
+        let config = self.configs()?;
+        let timeout = Duration::from_millis(parse_duration_to_ms(
+            &config.get_string_or(CONFIG_TIMEOUT, KEEPALIVE_TIMEOUT_DEFAULT),
+        )?);
+        let repeat = config.get_bool_or_default(CONFIG_REPEAT);
+
+        let generation = supervision.generation.fetch_add(1, Ordering::SeqCst) + 1;
+        let current = supervision.generation.clone();
+        let start_value = supervision.start_value.clone();
+        let ma = self.ma().clone();
+        let agent_id = self.id().to_string();
+        let def_name = self.def_name().to_string();
+        let armed_at = Instant::now();
+        let mut count = 0;
+
+        let deadline = armed_at + timeout;
+        let timer = schedule(self, deadline, move |deadline| {
+            if current.load(Ordering::SeqCst) != generation {
+                return None;
+            }
+            count += 1;
+            let escalation = AgentValue::object(hashmap! {
+                "value".to_string() => start_value.clone(),
+                "last_progress".to_string() => last_progress.clone(),
+                "waited_ms".to_string() => AgentValue::integer(armed_at.elapsed().as_millis() as i64),
+                "count".to_string() => AgentValue::integer(count),
+            });
+            if let Err(e) = ma.try_send_agent_out(
+                agent_id.clone(),
+                stamp(ctx.clone(), &agent_id, &def_name),
+                PORT_TIMEOUT.to_string(),
+                escalation,
+            ) {
+                log::error!("Failed to send keepalive timeout: {}", e);
+            }
+            repeat.then(|| deadline + timeout)
+        });
+        if let Some(supervision) = self.supervisions.get_mut(&key) {
+            supervision.timer = Some(timer);
         }
+        Ok(())
+    }
 
-        // Start the timer
-        self.start_timer()?;
+    fn start_supervision(
+        &mut self,
+        key: String,
+        start_value: AgentValue,
+    ) -> Result<(), AgentError> {
+        if !self.supervisions.contains_key(&key) && self.supervisions.len() >= MAX_SUPERVISIONS {
+            // escalated supervisions without `repeat` wait only for a result
+            self.supervisions
+                .retain(|_, s| s.timer.as_ref().is_some_and(|t| t.is_active()));
+            if self.supervisions.len() >= MAX_SUPERVISIONS {
+                return Err(AgentError::InvalidValue(
+                    "Too many supervised contexts".into(),
+                ));
+            }
+        }
+        let generation = self
+            .supervisions
+            .remove(&key)
+            .map(|s| s.generation.clone())
+            .unwrap_or_default();
+        self.supervisions.insert(
+            key,
+            Supervision {
+                timer: None,
+                generation,
+                start_value,
+            },
+        );
+        Ok(())
+    }
+}
 
-        // Output the data
-        self.output(ctx, port, value).await?;
+#[async_trait]
+impl AsAgent for KeepaliveAgent {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
+        Ok(Self {
+            data: AgentData::new(ma, id, spec),
+            contract,
+            supervisions: HashMap::new(),
+        })
+    }
+
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
+    }
 
+    async fn stop(&mut self) -> Result<(), AgentError> {
+        // Dropping the supervisions cancels their timers
+        self.supervisions.clear();
         Ok(())
     }
+
+    async fn process(
+        &mut self,
+        ctx: AgentContext,
+        port: String,
+        value: AgentValue,
+    ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
+        let key = if self.configs()?.get_bool_or_default(CONFIG_USE_CTX) {
+            ctx.ctx_key()?
+        } else {
+            String::new()
+        };
+        match port.as_str() {
+            PORT_START => {
+                self.start_supervision(key.clone(), value)?;
+                self.arm(key, ctx, AgentValue::unit())
+            }
+            PORT_PROGRESS => self.arm(key, ctx, value),
+            PORT_RESULT => {
+                self.supervisions.remove(&key);
+                self.output(self.traced(ctx), PORT_RESULT, value).await
+            }
+            _ => Err(AgentError::InvalidPin(port)),
+        }
+    }
 }
 
 // Parse time duration strings like "2s", "10m", "200ms"
-fn parse_duration_to_ms(duration_str: &str) -> Result<u64, AgentError> {
+pub(crate) fn parse_duration_to_ms(duration_str: &str) -> Result<u64, AgentError> {
     const MIN_DURATION: u64 = 10;
 
     // Regular expression to match number followed by optional unit
@@ -607,3 +1226,129 @@ fn parse_duration_to_ms(duration_str: &str) -> Result<u64, AgentError> {
         Ok(std::cmp::max(value * 1000, MIN_DURATION)) // Convert to ms
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WINDOW: Duration = Duration::from_millis(100);
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    // Plays the agent's part: offers `value` to the queue and, when a window starts, runs
+    // a timer that outputs one waiting value at the end of each window, like the
+    // callback of `start_timer`.
+    fn offer(
+        queue: &Arc<Mutex<ThrottleQueue>>,
+        mode: ThrottleMode,
+        value: i64,
+        out: &mpsc::UnboundedSender<(Instant, i64)>,
+    ) {
+        let waiting = (
+            AgentContext::new(),
+            PORT_VALUE.to_string(),
+            AgentValue::integer(value),
+        );
+        let Offered::Start(first) = lock_queue(queue).offer(mode, -1, true, waiting) else {
+            return;
+        };
+        if let Some((_, _, value)) = first {
+            out.send((Instant::now(), value.as_i64().unwrap())).unwrap();
+        }
+        let queue = queue.clone();
+        let out = out.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(WINDOW).await;
+                let Some((_, _, value)) = lock_queue(&queue).next() else {
+                    break;
+                };
+                out.send((Instant::now(), value.as_i64().unwrap())).unwrap();
+            }
+        });
+    }
+
+    // Offers the values from concurrent tasks, each at its delay from now, and returns
+    // the outputs once every window has ended.
+    async fn run(mode: ThrottleMode, offers: Vec<(Duration, i64)>) -> Vec<(Duration, i64)> {
+        let queue = Arc::new(Mutex::new(ThrottleQueue::default()));
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let start = Instant::now();
+        let tasks = offers
+            .into_iter()
+            .map(|(delay, value)| {
+                let queue = queue.clone();
+                let tx = tx.clone();
+                tokio::spawn(async move {
+                    tokio::time::sleep(delay).await;
+                    offer(&queue, mode, value, &tx);
+                })
+            })
+            .collect::<Vec<_>>();
+        for task in tasks {
+            task.await.unwrap();
+        }
+        drop(tx);
+
+        let mut outputs = Vec::new();
+        while let Some((at, value)) = rx.recv().await {
+            outputs.push((at - start, value));
+        }
+        let queue = lock_queue(&queue);
+        assert!(!queue.running);
+        assert!(queue.waiting_data.is_empty());
+        outputs
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_queue_at_window_ends() {
+        // Values arrive on the very instants the windows end, racing the timer
+        // as it empties the queue and stops.
+        let offers = (0..20)
+            .map(|i| (WINDOW * (i / 2) as u32, i))
+            .collect::<Vec<_>>();
+        let outputs = run(ThrottleMode::Queue, offers).await;
+
+        let mut values = outputs.iter().map(|(_, v)| *v).collect::<Vec<_>>();
+        values.sort();
+        assert_eq!(values, (0..20).collect::<Vec<_>>());
+        for pair in outputs.windows(2) {
+            assert!(pair[1].0 - pair[0].0 >= WINDOW);
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_queue_after_stop() {
+        // The second value arrives just as the timer finds the queue empty.
+        let outputs = run(
+            ThrottleMode::Queue,
+            vec![(Duration::ZERO, 0), (WINDOW, 1), (WINDOW * 3, 2)],
+        )
+        .await;
+        assert_eq!(
+            outputs,
+            vec![(Duration::ZERO, 0), (WINDOW, 1), (WINDOW * 3, 2)]
+        );
+    }
+
+    // Three bursts of three values, starting mid-window and with an idle window between.
+    fn bursts() -> Vec<(Duration, i64)> {
+        (0..9)
+            .map(|i| (ms(300 * (i / 3) as u64 + 50 + i as u64 % 3), i))
+            .collect()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_trailing_bursts() {
+        let outputs = run(ThrottleMode::Trailing, bursts()).await;
+        assert_eq!(outputs, vec![(ms(150), 2), (ms(450), 5), (ms(750), 8)]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_leading_bursts() {
+        let outputs = run(ThrottleMode::Leading, bursts()).await;
+        assert_eq!(outputs, vec![(ms(50), 0), (ms(350), 3), (ms(650), 6)]);
+    }
+}
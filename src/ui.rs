@@ -3,6 +3,8 @@ use modular_agent_core::{
     async_trait, modular_agent,
 };
 
+use crate::provenance::Traced;
+
 const CATEGORY: &str = "Std/UI";
 
 const NOTE: &str = "note";
@@ -53,6 +55,6 @@ impl AsAgent for RouterAgent {
         port: String,
         value: AgentValue,
     ) -> Result<(), AgentError> {
-        self.output(ctx, port, value).await
+        self.output(self.traced(ctx), port, value).await
     }
 }
@@ -1,19 +1,51 @@
+use std::collections::{BTreeMap, VecDeque};
 use std::vec;
 
+use chrono::Utc;
+use im::hashmap;
 use modular_agent_core::{
     Agent, AgentContext, AgentData, AgentError, AgentOutput, AgentSpec, AgentValue, AsAgent,
     ModularAgent, async_trait, modular_agent,
 };
 
+use crate::contract::{CONFIG_INPUT_CONTRACT, ContractAgent, InputContract, contract_agents};
+use crate::data::get_nested_value;
+use crate::provenance::Traced;
+use crate::tenant::{CONFIG_PER_TENANT, TenantMap, tenant_key};
+
 const CATEGORY: &str = "Std/Utils";
 
+const PORT_BUDGET_EXCEEDED: &str = "budget_exceeded";
 const PORT_IN: &str = "in";
 const PORT_RESET: &str = "reset";
 const PORT_COUNT: &str = "count";
+const PORT_PROFILE: &str = "profile";
+const PORT_USAGE: &str = "usage";
+const PORT_VALUE: &str = "value";
+
+const CONFIG_BUDGET: &str = "budget";
+const CONFIG_COST: &str = "cost";
+const CONFIG_COST_KEYS: &str = "cost_keys";
+const CONFIG_MAX_SAMPLES: &str = "max_samples";
+const CONFIG_REPORT_EVERY: &str = "report_every";
+const CONFIG_SAMPLE_EVERY: &str = "sample_every";
+const CONFIG_TAG: &str = "tag";
+const CONFIG_WINDOW_SEC: &str = "window_sec";
 
 const DISPLAY_COUNT: &str = "count";
+const DISPLAY_PROFILE: &str = "profile";
+const DISPLAY_USAGE: &str = "usage";
+
+const MAX_SAMPLES_DEFAULT: i64 = 1000;
+const REPORT_EVERY_DEFAULT: i64 = 100;
+const WINDOW_SEC_DEFAULT: i64 = 3600;
+
+contract_agents!(CounterAgent, ProfileValuesAgent, MeterAgent);
 
 /// Counter
+///
+/// With `per tenant`, each tenant id in the context has its own count, and `reset`
+/// resets the count of its tenant only.
 #[modular_agent(
     title = "Counter",
     category = CATEGORY,
@@ -24,24 +56,34 @@ const DISPLAY_COUNT: &str = "count";
         readonly,
         hide_title,
     ),
+    boolean_config(name = CONFIG_PER_TENANT, title = "per tenant"),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
     hint(color=6),
 )]
 struct CounterAgent {
     data: AgentData,
-    count: i64,
+    contract: InputContract,
+    // count by tenant id
+    counts: TenantMap<i64>,
 }
 
 #[async_trait]
 impl AsAgent for CounterAgent {
-    fn new(ma: ModularAgent, id: String, spec: AgentSpec) -> Result<Self, AgentError> {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
         Ok(Self {
             data: AgentData::new(ma, id, spec),
-            count: 0,
+            contract,
+            counts: TenantMap::default(),
         })
     }
 
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
+    }
+
     async fn start(&mut self) -> Result<(), AgentError> {
-        self.count = 0;
+        self.counts.clear();
         self.set_config(DISPLAY_COUNT.to_string(), AgentValue::integer(0))?;
         self.emit_config_updated(DISPLAY_COUNT, AgentValue::integer(0));
         Ok(())
@@ -51,18 +93,610 @@ impl AsAgent for CounterAgent {
         &mut self,
         ctx: AgentContext,
         port: String,
-        _value: AgentValue,
+        value: AgentValue,
     ) -> Result<(), AgentError> {
+        if port != PORT_RESET && self.check_input(&ctx, &port, value).await?.is_none() {
+            return Ok(());
+        }
+        let tenant = tenant_key(&ctx, self.configs()?.get_bool_or_default(CONFIG_PER_TENANT));
+        let count = self.counts.entry(tenant);
         if port == PORT_RESET {
-            self.count = 0;
+            *count = 0;
         } else if port == PORT_IN {
-            self.count += 1;
+            *count += 1;
         }
-        self.set_config(DISPLAY_COUNT.to_string(), AgentValue::integer(self.count))?;
-        self.output(ctx, PORT_COUNT, AgentValue::integer(self.count))
+        let count = *count;
+        self.set_config(DISPLAY_COUNT.to_string(), AgentValue::integer(count))?;
+        self.output(self.traced(ctx), PORT_COUNT, AgentValue::integer(count))
             .await?;
-        self.emit_config_updated(DISPLAY_COUNT, AgentValue::integer(self.count));
+        self.emit_config_updated(DISPLAY_COUNT, AgentValue::integer(count));
+
+        Ok(())
+    }
+}
+
+/// Profiles the values of a stream.
+///
+/// Every `sample_every`-th value is sampled. After every `report_every` samples, a report
+/// of the samples since the start (or the last reset) is emitted on `profile` and shown:
+///
+/// - `types`: count of each value type
+/// - `keys`: count of each key path in objects, with `[]` for array elements
+///   (ex. `items[].name`), to spot fields that appear, disappear or are optional
+/// - `string_length`, `array_length`, `json_bytes`: p50, p90, p99 and max of the string
+///   lengths, array lengths (anywhere in the values) and serialized sizes of the values
+///
+/// Percentiles are computed over the last `max_samples` sizes of each kind.
+#[modular_agent(
+    title = "Profile Values",
+    category = CATEGORY,
+    inputs = [PORT_VALUE, PORT_RESET],
+    outputs = [PORT_PROFILE],
+    object_config(
+        name = DISPLAY_PROFILE,
+        readonly,
+        hide_title,
+    ),
+    integer_config(name = CONFIG_SAMPLE_EVERY, default = 1, title = "sample every"),
+    integer_config(name = CONFIG_REPORT_EVERY, default = REPORT_EVERY_DEFAULT, title = "report every"),
+    integer_config(name = CONFIG_MAX_SAMPLES, default = MAX_SAMPLES_DEFAULT, title = "max samples", detail),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
+    hint(color=6),
+)]
+struct ProfileValuesAgent {
+    data: AgentData,
+    contract: InputContract,
+    seen: i64,
+    profile: ValueProfile,
+}
+
+#[async_trait]
+impl AsAgent for ProfileValuesAgent {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
+        Ok(Self {
+            data: AgentData::new(ma, id, spec),
+            contract,
+            seen: 0,
+            profile: ValueProfile::default(),
+        })
+    }
+
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
+    }
+
+    async fn start(&mut self) -> Result<(), AgentError> {
+        self.seen = 0;
+        self.profile = ValueProfile::default();
+        Ok(())
+    }
+
+    async fn process(
+        &mut self,
+        ctx: AgentContext,
+        port: String,
+        value: AgentValue,
+    ) -> Result<(), AgentError> {
+        if port == PORT_RESET {
+            self.seen = 0;
+            self.profile = ValueProfile::default();
+            return Ok(());
+        }
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
+
+        let config = self.configs()?;
+        let sample_every = config.get_integer_or(CONFIG_SAMPLE_EVERY, 1).max(1);
+        let report_every = config
+            .get_integer_or(CONFIG_REPORT_EVERY, REPORT_EVERY_DEFAULT)
+            .max(1);
+        let max_samples = config
+            .get_integer_or(CONFIG_MAX_SAMPLES, MAX_SAMPLES_DEFAULT)
+            .max(1) as usize;
+
+        self.seen += 1;
+        if (self.seen - 1) % sample_every != 0 {
+            return Ok(());
+        }
+        self.profile.add(&value, max_samples);
+        if self.profile.count % report_every != 0 {
+            return Ok(());
+        }
+
+        let report = self.profile.report();
+        self.set_config(DISPLAY_PROFILE.to_string(), report.clone())?;
+        self.emit_config_updated(DISPLAY_PROFILE, report.clone());
+        self.output(self.traced(ctx), PORT_PROFILE, report).await
+    }
+}
+
+#[derive(Default)]
+struct ValueProfile {
+    count: i64,
+    types: BTreeMap<&'static str, i64>,
+    keys: BTreeMap<String, i64>,
+    string_lengths: VecDeque<usize>,
+    array_lengths: VecDeque<usize>,
+    json_bytes: VecDeque<usize>,
+}
+
+impl ValueProfile {
+    fn add(&mut self, value: &AgentValue, max_samples: usize) {
+        self.count += 1;
+        *self.types.entry(type_name(value)).or_default() += 1;
+
+        let mut string_lengths = Vec::new();
+        let mut array_lengths = Vec::new();
+        let mut keys = Vec::new();
+        walk_value(
+            value,
+            "",
+            &mut keys,
+            &mut string_lengths,
+            &mut array_lengths,
+        );
+        // count each key path once per value
+        keys.sort();
+        keys.dedup();
+        for key in keys {
+            *self.keys.entry(key).or_default() += 1;
+        }
+
+        push_samples(&mut self.string_lengths, string_lengths, max_samples);
+        push_samples(&mut self.array_lengths, array_lengths, max_samples);
+        let bytes = serde_json::to_string(value).map(|s| s.len()).unwrap_or(0);
+        push_samples(&mut self.json_bytes, [bytes], max_samples);
+    }
+
+    fn report(&self) -> AgentValue {
+        AgentValue::object(hashmap! {
+            "count".to_string() => AgentValue::integer(self.count),
+            "types".to_string() => count_object(&self.types),
+            "keys".to_string() => count_object(&self.keys),
+            "string_length".to_string() => size_stats(&self.string_lengths),
+            "array_length".to_string() => size_stats(&self.array_lengths),
+            "json_bytes".to_string() => size_stats(&self.json_bytes),
+        })
+    }
+}
+
+fn count_object<K: ToString>(counts: &BTreeMap<K, i64>) -> AgentValue {
+    AgentValue::object(
+        counts
+            .iter()
+            .map(|(k, n)| (k.to_string(), AgentValue::integer(*n)))
+            .collect(),
+    )
+}
+
+fn type_name(value: &AgentValue) -> &'static str {
+    match value {
+        AgentValue::Unit => "unit",
+        AgentValue::Boolean(_) => "boolean",
+        AgentValue::Integer(_) => "integer",
+        AgentValue::Number(_) => "number",
+        AgentValue::String(_) => "string",
+        AgentValue::Image(_) => "image",
+        AgentValue::Array(_) => "array",
+        AgentValue::Object(_) => "object",
+        AgentValue::Tensor(_) => "tensor",
+        AgentValue::Message(_) => "message",
+        AgentValue::Error(_) => "error",
+    }
+}
+
+fn walk_value(
+    value: &AgentValue,
+    path: &str,
+    keys: &mut Vec<String>,
+    string_lengths: &mut Vec<usize>,
+    array_lengths: &mut Vec<usize>,
+) {
+    match value {
+        AgentValue::String(s) => string_lengths.push(s.chars().count()),
+        AgentValue::Array(arr) => {
+            array_lengths.push(arr.len());
+            let path = format!("{}[]", path);
+            for v in arr {
+                walk_value(v, &path, keys, string_lengths, array_lengths);
+            }
+        }
+        AgentValue::Object(obj) => {
+            for (k, v) in obj {
+                let path = if path.is_empty() {
+                    k.clone()
+                } else {
+                    format!("{}.{}", path, k)
+                };
+                keys.push(path.clone());
+                walk_value(v, &path, keys, string_lengths, array_lengths);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn push_samples(
+    samples: &mut VecDeque<usize>,
+    new: impl IntoIterator<Item = usize>,
+    max_samples: usize,
+) {
+    samples.extend(new);
+    while samples.len() > max_samples {
+        samples.pop_front();
+    }
+}
+
+// Nearest-rank percentiles of the samples, or unit when there are none.
+fn size_stats(samples: &VecDeque<usize>) -> AgentValue {
+    if samples.is_empty() {
+        return AgentValue::unit();
+    }
+    let mut sorted: Vec<usize> = samples.iter().copied().collect();
+    sorted.sort_unstable();
+    let percentile = |p: usize| {
+        let rank = (p * sorted.len()).div_ceil(100).max(1);
+        AgentValue::integer(sorted[rank - 1] as i64)
+    };
+    AgentValue::object(hashmap! {
+        "p50".to_string() => percentile(50),
+        "p90".to_string() => percentile(90),
+        "p99".to_string() => percentile(99),
+        "max".to_string() => AgentValue::integer(*sorted.last().unwrap() as i64),
+    })
+}
+
+/// Meters the cost of the values passing through.
+///
+/// The cost of a value is `cost` plus, for each `path*rate` in `cost keys`, the
+/// number at the key path times the rate (1 when omitted), e.g.
+/// `usage.input_tokens*0.000003, usage.output_tokens*0.000015`. Missing or
+/// non-numeric paths cost nothing.
+///
+/// Totals are kept since the start (or the last reset) and for the current
+/// window of `window sec` seconds (0: no window), overall and by the value at
+/// the `tag` key path. They are shown and output on `usage` as
+/// `{count, total, tags, window: {start, count, total, tags}}`.
+///
+/// Values are passed to `value` until the total of the window (or of all time
+/// without a window) reaches `budget` (0: unlimited); from then on they are
+/// routed to `budget_exceeded` instead.
+///
+/// With `per tenant`, each tenant id in the context has its own totals and budget,
+/// and `reset` resets the totals of its tenant only.
+#[modular_agent(
+    title = "Meter",
+    category = CATEGORY,
+    inputs = [PORT_VALUE, PORT_RESET],
+    outputs = [PORT_VALUE, PORT_USAGE, PORT_BUDGET_EXCEEDED],
+    object_config(
+        name = DISPLAY_USAGE,
+        readonly,
+        hide_title,
+    ),
+    number_config(name = CONFIG_COST, description = "fixed cost per value"),
+    string_config(name = CONFIG_COST_KEYS, title = "cost keys", description = "comma separated path*rate"),
+    string_config(name = CONFIG_TAG, description = "key path"),
+    integer_config(name = CONFIG_WINDOW_SEC, default = WINDOW_SEC_DEFAULT, title = "window sec", description = "0: no window"),
+    number_config(name = CONFIG_BUDGET, description = "0: unlimited"),
+    boolean_config(name = CONFIG_PER_TENANT, title = "per tenant"),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
+    hint(color=6),
+)]
+struct MeterAgent {
+    data: AgentData,
+    contract: InputContract,
+    // meter by tenant id
+    meters: TenantMap<Meter>,
+}
+
+#[async_trait]
+impl AsAgent for MeterAgent {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
+        Ok(Self {
+            data: AgentData::new(ma, id, spec),
+            contract,
+            meters: TenantMap::default(),
+        })
+    }
+
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
+    }
 
+    async fn start(&mut self) -> Result<(), AgentError> {
+        self.meters.clear();
         Ok(())
     }
+
+    async fn process(
+        &mut self,
+        ctx: AgentContext,
+        port: String,
+        value: AgentValue,
+    ) -> Result<(), AgentError> {
+        let value = if port == PORT_RESET {
+            value
+        } else {
+            let Some(value) = self.check_input(&ctx, &port, value).await? else {
+                return Ok(());
+            };
+            value
+        };
+        let config = self.configs()?;
+        let window_ms = config
+            .get_integer_or(CONFIG_WINDOW_SEC, WINDOW_SEC_DEFAULT)
+            .max(0)
+            * 1000;
+        let budget = config.get_number_or_default(CONFIG_BUDGET);
+        let now = Utc::now().timestamp_millis();
+        let tenant = tenant_key(&ctx, config.get_bool_or_default(CONFIG_PER_TENANT));
+
+        // (cost, tag) of the value, or None on reset
+        let added = if port == PORT_RESET {
+            None
+        } else {
+            let cost_keys = parse_cost_keys(&config.get_string_or_default(CONFIG_COST_KEYS))?;
+            let cost = value_cost(
+                &value,
+                config.get_number_or_default(CONFIG_COST),
+                &cost_keys,
+            );
+            let tag_path = config.get_string_or_default(CONFIG_TAG);
+            let tag = if tag_path.is_empty() {
+                None
+            } else {
+                let keys: Vec<&str> = tag_path.split('.').collect();
+                get_nested_value(&value, &keys).map(|v| match v.as_str() {
+                    Some(s) => s.to_string(),
+                    None => v.to_json().to_string(),
+                })
+            };
+            Some((cost, tag))
+        };
+
+        let meter = self.meters.entry(tenant);
+        let out_port = match added {
+            None => {
+                *meter = Meter::default();
+                None
+            }
+            Some((cost, tag)) => {
+                meter.add(cost, tag, now, window_ms);
+                if budget > 0.0 && meter.budget_total(window_ms) >= budget {
+                    Some(PORT_BUDGET_EXCEEDED)
+                } else {
+                    Some(PORT_VALUE)
+                }
+            }
+        };
+
+        let usage = meter.usage(now, window_ms);
+        self.set_config(DISPLAY_USAGE.to_string(), usage.clone())?;
+        self.emit_config_updated(DISPLAY_USAGE, usage.clone());
+        if let Some(out_port) = out_port {
+            self.output(self.traced(ctx.clone()), out_port, value)
+                .await?;
+        }
+        self.output(self.traced(ctx), PORT_USAGE, usage).await
+    }
+}
+
+// (key path, rate) of each term of `cost keys`.
+fn parse_cost_keys(s: &str) -> Result<Vec<(Vec<String>, f64)>, AgentError> {
+    s.split(',')
+        .map(str::trim)
+        .filter(|term| !term.is_empty())
+        .map(|term| {
+            let (path, rate) = match term.split_once('*') {
+                Some((path, rate)) => {
+                    let rate = rate.trim().parse::<f64>().map_err(|_| {
+                        AgentError::InvalidConfig(format!("invalid rate in cost keys: {}", term))
+                    })?;
+                    (path.trim(), rate)
+                }
+                None => (term, 1.0),
+            };
+            Ok((path.split('.').map(|s| s.to_string()).collect(), rate))
+        })
+        .collect()
+}
+
+fn value_cost(value: &AgentValue, fixed: f64, cost_keys: &[(Vec<String>, f64)]) -> f64 {
+    cost_keys.iter().fold(fixed, |cost, (keys, rate)| {
+        let n = get_nested_value(value, keys)
+            .and_then(AgentValue::as_f64)
+            .unwrap_or(0.0);
+        cost + n * rate
+    })
+}
+
+#[derive(Default)]
+struct MeterTotals {
+    count: i64,
+    total: f64,
+    tags: BTreeMap<String, f64>,
+}
+
+impl MeterTotals {
+    fn add(&mut self, cost: f64, tag: Option<&String>) {
+        self.count += 1;
+        self.total += cost;
+        if let Some(tag) = tag {
+            *self.tags.entry(tag.clone()).or_default() += cost;
+        }
+    }
+
+    fn entries(&self) -> im::HashMap<String, AgentValue> {
+        hashmap! {
+            "count".to_string() => AgentValue::integer(self.count),
+            "total".to_string() => AgentValue::number(self.total),
+            "tags".to_string() => AgentValue::object(
+                self.tags
+                    .iter()
+                    .map(|(tag, total)| (tag.clone(), AgentValue::number(*total)))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Meter {
+    all: MeterTotals,
+    // start (ms) of the current window
+    window_start: i64,
+    window: MeterTotals,
+}
+
+impl Meter {
+    // Moves to the window containing `now`, forgetting the totals of older windows.
+    fn roll(&mut self, now: i64, window_ms: i64) {
+        if window_ms <= 0 {
+            return;
+        }
+        let start = now - now.rem_euclid(window_ms);
+        if start != self.window_start {
+            self.window_start = start;
+            self.window = MeterTotals::default();
+        }
+    }
+
+    fn add(&mut self, cost: f64, tag: Option<String>, now: i64, window_ms: i64) {
+        self.roll(now, window_ms);
+        self.all.add(cost, tag.as_ref());
+        if window_ms > 0 {
+            self.window.add(cost, tag.as_ref());
+        }
+    }
+
+    // The total checked against the budget.
+    fn budget_total(&self, window_ms: i64) -> f64 {
+        if window_ms > 0 {
+            self.window.total
+        } else {
+            self.all.total
+        }
+    }
+
+    fn usage(&mut self, now: i64, window_ms: i64) -> AgentValue {
+        self.roll(now, window_ms);
+        let mut usage = self.all.entries();
+        if window_ms > 0 {
+            let mut window = self.window.entries();
+            window.insert("start".to_string(), AgentValue::integer(self.window_start));
+            usage.insert("window".to_string(), AgentValue::object(window));
+        }
+        AgentValue::object(usage)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use im::vector;
+
+    use super::*;
+
+    #[test]
+    fn test_value_profile() {
+        let mut profile = ValueProfile::default();
+        profile.add(
+            &AgentValue::object(hashmap! {
+                "name".to_string() => AgentValue::string("abc"),
+                "items".to_string() => AgentValue::array(vector![
+                    AgentValue::object(hashmap! {"id".to_string() => AgentValue::integer(1)}),
+                    AgentValue::object(hashmap! {"id".to_string() => AgentValue::integer(2)}),
+                ]),
+            }),
+            10,
+        );
+        profile.add(
+            &AgentValue::object(hashmap! {"name".to_string() => AgentValue::string("a")}),
+            10,
+        );
+        profile.add(&AgentValue::string("hello"), 10);
+
+        let report = profile.report();
+        assert_eq!(report.get_i64("count"), Some(3));
+        assert_eq!(
+            report.get("types").unwrap(),
+            &AgentValue::object(hashmap! {
+                "object".to_string() => AgentValue::integer(2),
+                "string".to_string() => AgentValue::integer(1),
+            })
+        );
+        assert_eq!(
+            report.get("keys").unwrap(),
+            &AgentValue::object(hashmap! {
+                "name".to_string() => AgentValue::integer(2),
+                "items".to_string() => AgentValue::integer(1),
+                "items[].id".to_string() => AgentValue::integer(1),
+            })
+        );
+        assert_eq!(
+            report.get("string_length").unwrap(),
+            &AgentValue::object(hashmap! {
+                "p50".to_string() => AgentValue::integer(3),
+                "p90".to_string() => AgentValue::integer(5),
+                "p99".to_string() => AgentValue::integer(5),
+                "max".to_string() => AgentValue::integer(5),
+            })
+        );
+        assert_eq!(report.get("array_length").unwrap().get_i64("max"), Some(2));
+    }
+
+    #[test]
+    fn test_meter() {
+        let cost_keys = parse_cost_keys("usage.input*0.5, usage.output * 2, calls").unwrap();
+        assert!(parse_cost_keys("usage.input*x").is_err());
+        assert!(parse_cost_keys(" ").unwrap().is_empty());
+
+        let value = AgentValue::object(hashmap! {
+            "model".to_string() => AgentValue::string("small"),
+            "usage".to_string() => AgentValue::object(hashmap! {
+                "input".to_string() => AgentValue::integer(10),
+                "output".to_string() => AgentValue::number(1.5),
+            }),
+        });
+        assert_eq!(value_cost(&value, 1.0, &cost_keys), 1.0 + 5.0 + 3.0);
+        assert_eq!(value_cost(&AgentValue::string("x"), 1.0, &cost_keys), 1.0);
+
+        let mut meter = Meter::default();
+        meter.add(2.0, Some("a".to_string()), 1_000, 10_000);
+        meter.add(3.0, Some("b".to_string()), 9_999, 10_000);
+        meter.add(4.0, None, 10_000, 10_000);
+        assert_eq!(meter.budget_total(10_000), 4.0);
+        assert_eq!(meter.budget_total(0), 9.0);
+
+        let usage = meter.usage(10_500, 10_000);
+        assert_eq!(usage.get_i64("count"), Some(3));
+        assert_eq!(usage.get_f64("total"), Some(9.0));
+        assert_eq!(
+            usage.get("tags").unwrap(),
+            &AgentValue::object(hashmap! {
+                "a".to_string() => AgentValue::number(2.0),
+                "b".to_string() => AgentValue::number(3.0),
+            })
+        );
+        let window = usage.get("window").unwrap();
+        assert_eq!(window.get_i64("start"), Some(10_000));
+        assert_eq!(window.get_i64("count"), Some(1));
+        assert_eq!(window.get_f64("total"), Some(4.0));
+
+        // a window without values has no totals
+        let usage = meter.usage(20_000, 10_000);
+        assert_eq!(usage.get("window").unwrap().get_i64("count"), Some(0));
+        assert!(meter.usage(20_000, 0).get("window").is_none());
+    }
+
+    #[test]
+    fn test_push_samples_keeps_latest() {
+        let mut samples = VecDeque::new();
+        push_samples(&mut samples, [1, 2, 3], 2);
+        push_samples(&mut samples, [4], 2);
+        assert_eq!(samples, VecDeque::from([3, 4]));
+    }
 }
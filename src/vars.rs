@@ -0,0 +1,433 @@
+//! Graph-level variables.
+//!
+//! A Vars Input agent declares named variables for its preset, with their defaults
+//! in the `vars` object config. The type of each default is the type of the variable.
+//! Var agents anywhere in the same preset reference a variable by name and emit its
+//! value when they start and whenever it changes.
+//!
+//! Each Vars Input agent owns its variables: they are removed when it stops or when
+//! they are removed from its config. If several Vars Input agents of a preset declare
+//! the same name, the one with the lowest agent id is used.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{LazyLock, Mutex};
+
+use modular_agent_core::{
+    Agent, AgentContext, AgentData, AgentError, AgentOutput, AgentSpec, AgentStatus, AgentValue,
+    AsAgent, ModularAgent, async_trait, modular_agent,
+};
+
+use crate::contract::{CONFIG_INPUT_CONTRACT, ContractAgent, InputContract, contract_agents};
+use crate::profile::resolve_value;
+use crate::provenance::Traced;
+
+const CATEGORY: &str = "Std/Vars";
+
+const PORT_UNIT: &str = "unit";
+const PORT_VALUE: &str = "value";
+const PORT_VARS: &str = "vars";
+
+const CONFIG_NAME: &str = "name";
+const CONFIG_VARS: &str = "vars";
+
+#[derive(Default)]
+struct PresetVars {
+    // Vars Input agent id -> its variables
+    values: BTreeMap<String, HashMap<String, AgentValue>>,
+    // variable name -> ids of the Var agents referencing it
+    subscribers: HashMap<String, Vec<String>>,
+}
+
+impl PresetVars {
+    // Returns the value of a variable, from the first Vars Input agent declaring it.
+    fn get(&self, name: &str) -> Option<&AgentValue> {
+        self.values.values().find_map(|vars| vars.get(name))
+    }
+}
+
+static VARS: LazyLock<Mutex<HashMap<String, PresetVars>>> = LazyLock::new(Default::default);
+
+/// Registers a Var agent and returns the current value of the variable, if defined.
+fn subscribe(preset_id: &str, name: &str, agent_id: &str) -> Option<AgentValue> {
+    let mut vars = VARS.lock().unwrap();
+    let preset = vars.entry(preset_id.to_string()).or_default();
+    let subscribers = preset.subscribers.entry(name.to_string()).or_default();
+    if !subscribers.iter().any(|id| id == agent_id) {
+        subscribers.push(agent_id.to_string());
+    }
+    preset.get(name).cloned()
+}
+
+fn unsubscribe(preset_id: &str, name: &str, agent_id: &str) {
+    let mut vars = VARS.lock().unwrap();
+    if let Some(preset) = vars.get_mut(preset_id) {
+        if let Some(subscribers) = preset.subscribers.get_mut(name) {
+            subscribers.retain(|id| id != agent_id);
+        }
+        if preset.values.is_empty() && preset.subscribers.values().all(|s| s.is_empty()) {
+            vars.remove(preset_id);
+        }
+    }
+}
+
+/// Sets the variables of the Vars Input agent `owner`, replacing all its previous ones.
+///
+/// Returns the (agent id, value) pairs to notify, for the variables whose value changed.
+fn set_vars(
+    preset_id: &str,
+    owner: &str,
+    values: impl IntoIterator<Item = (String, AgentValue)>,
+) -> Vec<(String, AgentValue)> {
+    let mut vars = VARS.lock().unwrap();
+    let preset = vars.entry(preset_id.to_string()).or_default();
+    let values: HashMap<String, AgentValue> = values.into_iter().collect();
+
+    let mut names: Vec<String> = values.keys().cloned().collect();
+    if let Some(prev) = preset.values.get(owner) {
+        names.extend(prev.keys().filter(|n| !values.contains_key(*n)).cloned());
+    }
+    let before: Vec<Option<AgentValue>> = names.iter().map(|n| preset.get(n).cloned()).collect();
+    if values.is_empty() {
+        preset.values.remove(owner);
+    } else {
+        preset.values.insert(owner.to_string(), values);
+    }
+
+    let mut notify = Vec::new();
+    for (name, before) in names.iter().zip(before) {
+        let Some(value) = preset.get(name) else {
+            continue;
+        };
+        if before.as_ref() == Some(value) {
+            continue;
+        }
+        if let Some(subscribers) = preset.subscribers.get(name) {
+            notify.extend(subscribers.iter().map(|id| (id.clone(), value.clone())));
+        }
+    }
+    if preset.values.is_empty() && preset.subscribers.values().all(|s| s.is_empty()) {
+        vars.remove(preset_id);
+    }
+    notify
+}
+
+fn same_type(a: &AgentValue, b: &AgentValue) -> bool {
+    match (a, b) {
+        (AgentValue::Integer(_) | AgentValue::Number(_), AgentValue::Integer(_)) => true,
+        (AgentValue::Number(_), AgentValue::Number(_)) => true,
+        _ => std::mem::discriminant(a) == std::mem::discriminant(b),
+    }
+}
+
+contract_agents!(VarsInputAgent);
+
+/// Declares graph-level variables.
+///
+/// The `vars` config maps variable names to their defaults. An object on the `vars`
+/// pin overrides the values of declared variables until the agent is restarted;
+/// each value must have the type of the default (integers are accepted for numbers).
+/// `${env:NAME}` and `${profile:key}` placeholders in the values are resolved.
+/// The current variables are emitted as an object on every change.
+#[modular_agent(
+    title = "Vars Input",
+    category = CATEGORY,
+    inputs = [PORT_VARS],
+    outputs = [PORT_VARS],
+    object_config(name = CONFIG_VARS),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
+    hint(color=2),
+)]
+struct VarsInputAgent {
+    data: AgentData,
+    contract: InputContract,
+    overrides: HashMap<String, AgentValue>,
+}
+
+impl VarsInputAgent {
+    fn current_vars(&self) -> Result<im::HashMap<String, AgentValue>, AgentError> {
+        let mut vars = self.configs()?.get_object_or_default(CONFIG_VARS);
+        for (name, value) in &self.overrides {
+            if vars.contains_key(name) {
+                vars.insert(name.clone(), value.clone());
+            }
+        }
+        vars.into_iter()
+            .map(|(name, value)| Ok((name, resolve_value(&value)?)))
+            .collect()
+    }
+
+    fn publish(&self) -> Result<(), AgentError> {
+        let vars = self.current_vars()?;
+        for (agent_id, value) in set_vars(self.preset_id(), self.id(), vars.clone()) {
+            self.ma().try_send_agent_out(
+                agent_id,
+                AgentContext::new(),
+                PORT_VALUE.to_string(),
+                value,
+            )?;
+        }
+        self.try_output(
+            self.traced(AgentContext::new()),
+            PORT_VARS,
+            AgentValue::object(vars),
+        )
+    }
+}
+
+#[async_trait]
+impl AsAgent for VarsInputAgent {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
+        Ok(Self {
+            data: AgentData::new(ma, id, spec),
+            contract,
+            overrides: HashMap::new(),
+        })
+    }
+
+    async fn start(&mut self) -> Result<(), AgentError> {
+        self.overrides.clear();
+        self.publish()
+    }
+
+    async fn stop(&mut self) -> Result<(), AgentError> {
+        // a variable also declared by another Vars Input agent now takes its value
+        for (agent_id, value) in set_vars(self.preset_id(), self.id(), []) {
+            if let Err(e) = self.ma().try_send_agent_out(
+                agent_id,
+                AgentContext::new(),
+                PORT_VALUE.to_string(),
+                value,
+            ) {
+                log::error!("Failed to send var: {}", e);
+            }
+        }
+        Ok(())
+    }
+
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()?;
+        if *self.status() == AgentStatus::Start {
+            self.publish()?;
+        }
+        Ok(())
+    }
+
+    async fn process(
+        &mut self,
+        ctx: AgentContext,
+        port: String,
+        value: AgentValue,
+    ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
+        let Some(values) = value.as_object() else {
+            return Err(AgentError::InvalidValue(
+                "Vars must be an object".to_string(),
+            ));
+        };
+        let defaults = self.configs()?.get_object_or_default(CONFIG_VARS);
+        for (name, value) in values {
+            let Some(default) = defaults.get(name) else {
+                return Err(AgentError::InvalidValue(format!(
+                    "Unknown variable: {}",
+                    name
+                )));
+            };
+            if !same_type(default, value) {
+                return Err(AgentError::InvalidValue(format!(
+                    "Type mismatch for variable: {}",
+                    name
+                )));
+            }
+        }
+        for (name, value) in values {
+            self.overrides.insert(name.clone(), value.clone());
+        }
+        self.publish()
+    }
+}
+
+/// References a graph-level variable declared by a Vars Input agent in the same preset.
+///
+/// Emits the value when started (if already defined) and whenever it changes.
+/// A value on the `unit` pin emits the current value again.
+#[modular_agent(
+    title = "Var",
+    category = CATEGORY,
+    inputs = [PORT_UNIT],
+    outputs = [PORT_VALUE],
+    string_config(name = CONFIG_NAME),
+    hint(color=2),
+)]
+struct VarAgent {
+    data: AgentData,
+    subscribed: Option<String>,
+}
+
+impl VarAgent {
+    fn subscribe(&mut self) -> Result<(), AgentError> {
+        let name = self.configs()?.get_string_or_default(CONFIG_NAME);
+        if name.is_empty() {
+            return Ok(());
+        }
+        let value = subscribe(self.preset_id(), &name, self.id());
+        self.subscribed = Some(name);
+        if let Some(value) = value {
+            self.try_output(self.traced(AgentContext::new()), PORT_VALUE, value)?;
+        }
+        Ok(())
+    }
+
+    fn unsubscribe(&mut self) {
+        if let Some(name) = self.subscribed.take() {
+            unsubscribe(self.preset_id(), &name, self.id());
+        }
+    }
+}
+
+#[async_trait]
+impl AsAgent for VarAgent {
+    fn new(ma: ModularAgent, id: String, spec: AgentSpec) -> Result<Self, AgentError> {
+        Ok(Self {
+            data: AgentData::new(ma, id, spec),
+            subscribed: None,
+        })
+    }
+
+    async fn start(&mut self) -> Result<(), AgentError> {
+        self.subscribe()
+    }
+
+    async fn stop(&mut self) -> Result<(), AgentError> {
+        self.unsubscribe();
+        Ok(())
+    }
+
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        if *self.status() == AgentStatus::Start {
+            self.unsubscribe();
+            self.subscribe()?;
+        }
+        Ok(())
+    }
+
+    async fn process(
+        &mut self,
+        ctx: AgentContext,
+        _port: String,
+        _value: AgentValue,
+    ) -> Result<(), AgentError> {
+        let Some(name) = &self.subscribed else {
+            return Ok(());
+        };
+        let value = VARS
+            .lock()
+            .unwrap()
+            .get(self.preset_id())
+            .and_then(|preset| preset.get(name).cloned());
+        if let Some(value) = value {
+            self.output(self.traced(ctx), PORT_VALUE, value).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_vars_notifies_changes() {
+        let preset = "test_set_vars_notifies_changes";
+        assert_eq!(subscribe(preset, "url", "var1"), None);
+        subscribe(preset, "url", "var2");
+
+        let notify = set_vars(
+            preset,
+            "vars1",
+            [
+                ("url".to_string(), AgentValue::string("http://a")),
+                ("key".to_string(), AgentValue::string("k")),
+            ],
+        );
+        assert_eq!(
+            notify,
+            vec![
+                ("var1".to_string(), AgentValue::string("http://a")),
+                ("var2".to_string(), AgentValue::string("http://a")),
+            ]
+        );
+
+        // unchanged values are not notified
+        let notify = set_vars(
+            preset,
+            "vars1",
+            [("url".to_string(), AgentValue::string("http://a"))],
+        );
+        assert!(notify.is_empty());
+
+        unsubscribe(preset, "url", "var1");
+        let notify = set_vars(
+            preset,
+            "vars1",
+            [("url".to_string(), AgentValue::string("http://b"))],
+        );
+        assert_eq!(
+            notify,
+            vec![("var2".to_string(), AgentValue::string("http://b"))]
+        );
+        assert_eq!(subscribe(preset, "key", "var3"), None);
+    }
+
+    #[test]
+    fn test_set_vars_by_owner() {
+        let preset = "test_set_vars_by_owner";
+        subscribe(preset, "url", "var1");
+        let url = |s: &str| ("url".to_string(), AgentValue::string(s));
+        let key = |s: &str| ("key".to_string(), AgentValue::string(s));
+
+        set_vars(preset, "vars2", [url("http://b"), key("k")]);
+        assert_eq!(
+            subscribe(preset, "key", "var2"),
+            Some(AgentValue::string("k"))
+        );
+
+        // the Vars Input agent with the lower id wins
+        let notify = set_vars(preset, "vars1", [url("http://a")]);
+        assert_eq!(
+            notify,
+            vec![("var1".to_string(), AgentValue::string("http://a"))]
+        );
+        assert!(set_vars(preset, "vars2", [url("http://c"), key("k")]).is_empty());
+
+        // a variable removed from the config is unset
+        set_vars(preset, "vars2", [url("http://c")]);
+        assert_eq!(subscribe(preset, "key", "var2"), None);
+
+        // and stopping an agent falls back to the other one
+        let notify = set_vars(preset, "vars1", []);
+        assert_eq!(
+            notify,
+            vec![("var1".to_string(), AgentValue::string("http://c"))]
+        );
+    }
+
+    #[test]
+    fn test_same_type() {
+        assert!(same_type(&AgentValue::number(1.0), &AgentValue::integer(2)));
+        assert!(!same_type(
+            &AgentValue::integer(1),
+            &AgentValue::number(2.0)
+        ));
+        assert!(!same_type(
+            &AgentValue::string("a"),
+            &AgentValue::integer(2)
+        ));
+        assert!(same_type(
+            &AgentValue::string("a"),
+            &AgentValue::string("b")
+        ));
+    }
+}
@@ -0,0 +1,340 @@
+#![cfg(feature = "wasm")]
+
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use modular_agent_core::{
+    Agent, AgentContext, AgentData, AgentError, AgentOutput, AgentSpec, AgentValue, AsAgent,
+    ModularAgent, async_trait, modular_agent,
+};
+use wasmtime::{Config, Engine, Instance, Module, Store, StoreLimits, StoreLimitsBuilder, Trap};
+
+use crate::contract::{CONFIG_INPUT_CONTRACT, ContractAgent, InputContract, contract_agents};
+use crate::profile::ProfileConfigs;
+use crate::provenance::Traced;
+
+const CATEGORY: &str = "Std/Script";
+
+const PORT_VALUE: &str = "value";
+
+const CONFIG_FUEL: &str = "fuel";
+const CONFIG_MAX_MEMORY: &str = "max_memory";
+const CONFIG_PATH: &str = "path";
+
+const FUEL_DEFAULT: i64 = 10_000_000;
+const MAX_MEMORY_DEFAULT: i64 = 16;
+
+contract_agents!(WasmTransformAgent);
+
+/// WASM Transform
+///
+/// Transforms each input with a WebAssembly module loaded from `path` (`.wasm` or `.wat`).
+/// The module is reloaded when the file changes; if the new file fails to load, the
+/// module loaded before is kept.
+///
+/// The module has no imports and exports:
+///
+/// - `memory`
+/// - `alloc(len: i32) -> i32`: returns a buffer of `len` bytes for the input
+/// - `transform(ptr: i32, len: i32) -> i64`: takes the input as JSON and returns the
+///   output JSON as `ptr << 32 | len`, or 0 for no output
+///
+/// Each call runs in a new instance, limited to `fuel` units of work and `max memory` MiB.
+#[modular_agent(
+    title = "WASM Transform",
+    category = CATEGORY,
+    inputs = [PORT_VALUE],
+    outputs = [PORT_VALUE],
+    string_config(name = CONFIG_PATH, description = "WASM module file"),
+    integer_config(name = CONFIG_FUEL, default = FUEL_DEFAULT),
+    integer_config(name = CONFIG_MAX_MEMORY, default = MAX_MEMORY_DEFAULT, title = "max memory", description = "MiB"),
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
+    hint(color=3),
+)]
+struct WasmTransformAgent {
+    data: AgentData,
+    contract: InputContract,
+    engine: Engine,
+    plugin: Option<WasmPlugin>,
+}
+
+#[async_trait]
+impl AsAgent for WasmTransformAgent {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
+        Ok(Self {
+            data: AgentData::new(ma, id, spec),
+            contract,
+            engine: wasm_engine()?,
+            plugin: None,
+        })
+    }
+
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()?;
+        // Loaded again on the next input
+        self.plugin = None;
+        Ok(())
+    }
+
+    async fn process(
+        &mut self,
+        ctx: AgentContext,
+        port: String,
+        value: AgentValue,
+    ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
+        let configs = self.configs()?;
+        let path = PathBuf::from(configs.get_string_resolved(CONFIG_PATH)?);
+        let fuel = configs.get_integer_or(CONFIG_FUEL, FUEL_DEFAULT);
+        let max_memory = configs.get_integer_or(CONFIG_MAX_MEMORY, MAX_MEMORY_DEFAULT);
+        if path.as_os_str().is_empty() {
+            return Err(AgentError::InvalidConfig("path is not set".into()));
+        }
+        if fuel <= 0 || max_memory <= 0 {
+            return Err(AgentError::InvalidConfig(
+                "fuel and max_memory must be greater than 0".into(),
+            ));
+        }
+
+        let engine = self.engine.clone();
+        let mut plugin = self.plugin.take();
+        let input = value.to_json().to_string();
+        let (plugin, output) = tokio::task::spawn_blocking(move || {
+            let output = WasmPlugin::load(&engine, &path, &mut plugin).and_then(|module| {
+                run_wasm(
+                    &engine,
+                    module,
+                    input.as_bytes(),
+                    fuel as u64,
+                    max_memory as usize * 1024 * 1024,
+                )
+            });
+            (plugin, output)
+        })
+        .await
+        .map_err(|e| AgentError::InvalidValue(format!("Failed to run WASM module: {}", e)))?;
+        self.plugin = plugin;
+
+        let Some(output) = output? else {
+            return Ok(());
+        };
+        let json: serde_json::Value = serde_json::from_slice(&output)
+            .map_err(|e| AgentError::InvalidValue(format!("Invalid WASM output: {}", e)))?;
+        self.output(self.traced(ctx), PORT_VALUE, AgentValue::from_json(json)?)
+            .await
+    }
+}
+
+fn wasm_engine() -> Result<Engine, AgentError> {
+    let mut config = Config::new();
+    config.consume_fuel(true);
+    Engine::new(&config)
+        .map_err(|e| AgentError::InvalidValue(format!("Failed to create WASM engine: {}", e)))
+}
+
+// A compiled module and the file it was loaded from.
+struct WasmPlugin {
+    path: PathBuf,
+    // modified time and size of the file
+    stamp: Option<(SystemTime, u64)>,
+    module: Module,
+}
+
+impl WasmPlugin {
+    // Compiles the file again into `loaded` if it has changed, and returns the module.
+    // When the file fails to load, the module loaded before from the same path is kept.
+    fn load<'a>(
+        engine: &Engine,
+        path: &Path,
+        loaded: &'a mut Option<Self>,
+    ) -> Result<&'a Module, AgentError> {
+        if loaded.as_ref().is_some_and(|plugin| plugin.path != path) {
+            *loaded = None;
+        }
+        match Self::compile(engine, path, loaded.as_ref()) {
+            Ok(Some(plugin)) => *loaded = Some(plugin),
+            Ok(None) => {}
+            Err(e) if loaded.is_some() => {
+                log::warn!("{}; keeping the module loaded before", e);
+            }
+            Err(e) => return Err(e),
+        }
+        loaded
+            .as_ref()
+            .map(|plugin| &plugin.module)
+            .ok_or_else(|| AgentError::InvalidValue("WASM module is not loaded".into()))
+    }
+
+    // Compiles the file, or returns None if it has not changed since `loaded`.
+    fn compile(
+        engine: &Engine,
+        path: &Path,
+        loaded: Option<&Self>,
+    ) -> Result<Option<Self>, AgentError> {
+        let metadata = std::fs::metadata(path)
+            .map_err(|e| AgentError::InvalidValue(format!("Failed to read {:?}: {}", path, e)))?;
+        let stamp = metadata.modified().ok().map(|t| (t, metadata.len()));
+        if stamp.is_some() && loaded.is_some_and(|plugin| plugin.stamp == stamp) {
+            return Ok(None);
+        }
+        let module = Module::from_file(engine, path).map_err(|e| {
+            AgentError::InvalidValue(format!("Failed to load WASM module {:?}: {:#}", path, e))
+        })?;
+        Ok(Some(Self {
+            path: path.to_path_buf(),
+            stamp,
+            module,
+        }))
+    }
+}
+
+fn run_wasm(
+    engine: &Engine,
+    module: &Module,
+    input: &[u8],
+    fuel: u64,
+    max_memory: usize,
+) -> Result<Option<Vec<u8>>, AgentError> {
+    let wasm_error = |e: wasmtime::Error| {
+        if e.downcast_ref::<Trap>() == Some(&Trap::OutOfFuel) {
+            AgentError::InvalidValue("WASM module ran out of fuel".into())
+        } else {
+            AgentError::InvalidValue(format!("WASM error: {:#}", e))
+        }
+    };
+    let memory_error = |e| AgentError::InvalidValue(format!("WASM memory error: {}", e));
+
+    let limits = StoreLimitsBuilder::new().memory_size(max_memory).build();
+    let mut store: Store<StoreLimits> = Store::new(engine, limits);
+    store.limiter(|limits| limits);
+    store.set_fuel(fuel).map_err(wasm_error)?;
+
+    let instance = Instance::new(&mut store, module, &[]).map_err(wasm_error)?;
+    let memory = instance
+        .get_memory(&mut store, "memory")
+        .ok_or_else(|| AgentError::InvalidValue("WASM module does not export memory".into()))?;
+    let alloc = instance
+        .get_typed_func::<i32, i32>(&mut store, "alloc")
+        .map_err(wasm_error)?;
+    let transform = instance
+        .get_typed_func::<(i32, i32), i64>(&mut store, "transform")
+        .map_err(wasm_error)?;
+
+    let len = i32::try_from(input.len())
+        .map_err(|_| AgentError::InvalidValue("Input too large for WASM".into()))?;
+    let ptr = alloc.call(&mut store, len).map_err(wasm_error)?;
+    memory
+        .write(&mut store, ptr as u32 as usize, input)
+        .map_err(memory_error)?;
+
+    let packed = transform.call(&mut store, (ptr, len)).map_err(wasm_error)?;
+    if packed == 0 {
+        return Ok(None);
+    }
+    let (out_ptr, out_len) = ((packed >> 32) as u32 as usize, packed as u32 as usize);
+    // the length comes from the module: check it before allocating
+    if out_ptr
+        .checked_add(out_len)
+        .is_none_or(|end| end > memory.data_size(&store))
+    {
+        return Err(AgentError::InvalidValue(
+            "WASM output is out of the module memory".into(),
+        ));
+    }
+    let mut output = vec![0; out_len];
+    memory
+        .read(&store, out_ptr, &mut output)
+        .map_err(memory_error)?;
+    Ok(Some(output))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Returns the input as is, or nothing for `null`.
+    const ECHO: &str = r#"
+(module
+  (memory (export "memory") 1)
+  (global $next (mut i32) (i32.const 1024))
+  (func (export "alloc") (param $len i32) (result i32)
+    (local $ptr i32)
+    (local.set $ptr (global.get $next))
+    (global.set $next (i32.add (global.get $next) (local.get $len)))
+    (local.get $ptr))
+  (func (export "transform") (param $ptr i32) (param $len i32) (result i64)
+    (if (i32.eq (i32.load8_u (local.get $ptr)) (i32.const 110))
+      (then (return (i64.const 0))))
+    (i64.or
+      (i64.shl (i64.extend_i32_u (local.get $ptr)) (i64.const 32))
+      (i64.extend_i32_u (local.get $len)))))
+"#;
+
+    const LOOP: &str = r#"
+(module
+  (memory (export "memory") 1)
+  (func (export "alloc") (param i32) (result i32) (i32.const 0))
+  (func (export "transform") (param i32 i32) (result i64)
+    (loop $l (br $l))
+    (i64.const 0)))
+"#;
+
+    // Claims an output of 4 GiB.
+    const HUGE_OUTPUT: &str = r#"
+(module
+  (memory (export "memory") 1)
+  (func (export "alloc") (param i32) (result i32) (i32.const 0))
+  (func (export "transform") (param i32 i32) (result i64)
+    (i64.const 0xffffffff)))
+"#;
+
+    #[test]
+    fn test_wasm_transform() {
+        let dir =
+            std::env::temp_dir().join(format!("modular_agent_std_wasm_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("plugin.wat");
+        std::fs::write(&path, ECHO).unwrap();
+
+        let engine = wasm_engine().unwrap();
+        let mut plugin = None;
+        let run = |module: &Module, input: &str| {
+            run_wasm(&engine, module, input.as_bytes(), 100_000, 1 << 20)
+        };
+        let module = WasmPlugin::load(&engine, &path, &mut plugin).unwrap();
+        assert_eq!(
+            run(module, r#"{"a":1}"#).unwrap(),
+            Some(br#"{"a":1}"#.to_vec())
+        );
+        assert_eq!(run(module, "null").unwrap(), None);
+
+        // the module loaded before is kept when the file is broken
+        std::fs::write(&path, "(module").unwrap();
+        let module = WasmPlugin::load(&engine, &path, &mut plugin).unwrap();
+        assert_eq!(run(module, "1").unwrap(), Some(b"1".to_vec()));
+
+        // reloaded when the file changes
+        std::fs::write(&path, LOOP).unwrap();
+        let module = WasmPlugin::load(&engine, &path, &mut plugin).unwrap();
+        let err = run(module, "1").unwrap_err();
+        assert!(err.to_string().contains("out of fuel"), "{}", err);
+
+        // an output out of the memory is rejected before it is allocated
+        std::fs::write(&path, HUGE_OUTPUT).unwrap();
+        let module = WasmPlugin::load(&engine, &path, &mut plugin).unwrap();
+        let err = run(module, "1").unwrap_err();
+        assert!(
+            err.to_string().contains("out of the module memory"),
+            "{}",
+            err
+        );
+
+        let mut none = None;
+        assert!(WasmPlugin::load(&engine, &dir.join("missing.wat"), &mut none).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
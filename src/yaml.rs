@@ -7,40 +7,56 @@ use modular_agent_core::{
     async_trait, modular_agent,
 };
 
+use crate::contract::{CONFIG_INPUT_CONTRACT, ContractAgent, InputContract, contract_agents};
+use crate::provenance::Traced;
+
 const CATEGORY: &str = "Std/Yaml";
 
 const PORT_DATA: &str = "data";
 const PORT_YAML: &str = "yaml";
 
+contract_agents!(ToYamlAgent, FromYamlAgent);
+
 // To YAML
 #[modular_agent(
     title = "To YAML",
     category = CATEGORY,
     inputs = [PORT_DATA],
     outputs = [PORT_YAML],
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
     hint(color=5),
 )]
 struct ToYamlAgent {
     data: AgentData,
+    contract: InputContract,
 }
 
 #[async_trait]
 impl AsAgent for ToYamlAgent {
-    fn new(ma: ModularAgent, id: String, spec: AgentSpec) -> Result<Self, AgentError> {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
         Ok(Self {
             data: AgentData::new(ma, id, spec),
+            contract,
         })
     }
 
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
+    }
+
     async fn process(
         &mut self,
         ctx: AgentContext,
-        _port: String,
+        port: String,
         value: AgentValue,
     ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
         let yaml = serde_yaml_ng::to_string(&value)
             .map_err(|e| AgentError::InvalidValue(e.to_string()))?;
-        self.output(ctx, PORT_YAML, AgentValue::string(yaml))
+        self.output(self.traced(ctx), PORT_YAML, AgentValue::string(yaml))
             .await?;
         Ok(())
     }
@@ -52,33 +68,44 @@ impl AsAgent for ToYamlAgent {
     category = CATEGORY,
     inputs = [PORT_YAML],
     outputs = [PORT_DATA],
+    object_config(name = CONFIG_INPUT_CONTRACT, title = "input contract", detail),
     hint(color=5),
 )]
 struct FromYamlAgent {
     data: AgentData,
+    contract: InputContract,
 }
 
 #[async_trait]
 impl AsAgent for FromYamlAgent {
-    fn new(ma: ModularAgent, id: String, spec: AgentSpec) -> Result<Self, AgentError> {
+    fn new(ma: ModularAgent, id: String, mut spec: AgentSpec) -> Result<Self, AgentError> {
+        let contract = InputContract::update_spec(&mut spec)?;
         Ok(Self {
             data: AgentData::new(ma, id, spec),
+            contract,
         })
     }
 
+    fn configs_changed(&mut self) -> Result<(), AgentError> {
+        self.reload_contract()
+    }
+
     async fn process(
         &mut self,
         ctx: AgentContext,
-        _port: String,
+        port: String,
         value: AgentValue,
     ) -> Result<(), AgentError> {
+        let Some(value) = self.check_input(&ctx, &port, value).await? else {
+            return Ok(());
+        };
         let s = value
             .as_str()
             .ok_or_else(|| AgentError::InvalidValue("not a string".to_string()))?;
         let v: serde_json::Value =
             serde_yaml_ng::from_str(s).map_err(|e| AgentError::InvalidValue(e.to_string()))?;
         let value = AgentValue::from_json(v)?;
-        self.output(ctx, PORT_DATA, value).await?;
+        self.output(self.traced(ctx), PORT_DATA, value).await?;
         Ok(())
     }
 }
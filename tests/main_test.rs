@@ -3,6 +3,13 @@
 extern crate modular_agent_std;
 
 mod suites {
+    mod data_test;
+    mod flow_test;
     mod input_test;
+    mod meta_test;
+    mod sequence_test;
     mod string_test;
+    mod system_test;
+    mod time_test;
+    mod vars_test;
 }
@@ -0,0 +1,208 @@
+extern crate modular_agent_core as ma;
+
+use im::{hashmap, vector};
+use ma::{AgentValue, test_utils};
+
+#[tokio::test]
+async fn test_zip_to_object_pin_keys() {
+    let ma = test_utils::setup_modular_agent().await;
+
+    let preset_id = test_utils::open_and_start_preset(&ma, "tests/presets/Std_Data_test.json")
+        .await
+        .unwrap();
+
+    // Blank keys default to the source pin names
+    test_utils::write_and_expect_local_value(
+        &ma,
+        &preset_id,
+        "zip_pins_in1",
+        AgentValue::integer(1),
+    )
+    .await
+    .unwrap();
+    test_utils::write_and_expect_local_value(
+        &ma,
+        &preset_id,
+        "zip_pins_in2",
+        AgentValue::string("hello"),
+    )
+    .await
+    .unwrap();
+    test_utils::expect_local_value(
+        &preset_id,
+        "zip_pins_out",
+        &AgentValue::object(hashmap! {
+            "json".to_string() => AgentValue::string("1"),
+            "t".to_string() => AgentValue::string("hello"),
+        }),
+    )
+    .await
+    .unwrap();
+
+    ma.quit();
+}
+
+#[tokio::test]
+async fn test_zip_to_object_key_template() {
+    let ma = test_utils::setup_modular_agent().await;
+
+    let preset_id = test_utils::open_and_start_preset(&ma, "tests/presets/Std_Data_test.json")
+        .await
+        .unwrap();
+
+    let a = AgentValue::object(hashmap! {
+        "id".to_string() => AgentValue::string("a"),
+        "v".to_string() => AgentValue::integer(1),
+    });
+    let b = AgentValue::object(hashmap! {
+        "id".to_string() => AgentValue::string("b"),
+        "v".to_string() => AgentValue::integer(2),
+    });
+
+    // Keys are rendered from each value
+    test_utils::write_and_expect_local_value(&ma, &preset_id, "zip_template_in1", a.clone())
+        .await
+        .unwrap();
+    test_utils::write_and_expect_local_value(&ma, &preset_id, "zip_template_in2", b.clone())
+        .await
+        .unwrap();
+    test_utils::expect_local_value(
+        &preset_id,
+        "zip_template_out",
+        &AgentValue::object(hashmap! {
+            "a".to_string() => a,
+            "b".to_string() => b,
+        }),
+    )
+    .await
+    .unwrap();
+
+    ma.quit();
+}
+
+#[tokio::test]
+async fn test_coalesce() {
+    let ma = test_utils::setup_modular_agent().await;
+
+    let preset_id = test_utils::open_and_start_preset(&ma, "tests/presets/Std_Data_test.json")
+        .await
+        .unwrap();
+
+    // Empty string is skipped
+    test_utils::write_and_expect_local_value(
+        &ma,
+        &preset_id,
+        "coalesce_in1",
+        AgentValue::string(""),
+    )
+    .await
+    .unwrap();
+    test_utils::write_and_expect_local_value(
+        &ma,
+        &preset_id,
+        "coalesce_in2",
+        AgentValue::string("fallback"),
+    )
+    .await
+    .unwrap();
+    test_utils::expect_local_value(&preset_id, "coalesce_out", &AgentValue::string("fallback"))
+        .await
+        .unwrap();
+
+    // Array with only empty values -> none
+    let arr = AgentValue::array(vector![AgentValue::unit(), AgentValue::array_default()]);
+    test_utils::write_and_expect_local_value(&ma, &preset_id, "coalesce_array", arr)
+        .await
+        .unwrap();
+    test_utils::expect_local_value(&preset_id, "coalesce_none", &AgentValue::unit())
+        .await
+        .unwrap();
+
+    ma.quit();
+}
+
+#[tokio::test]
+async fn test_input_contract() {
+    let ma = test_utils::setup_modular_agent().await;
+
+    let preset_id = test_utils::open_and_start_preset(&ma, "tests/presets/Std_Data_test.json")
+        .await
+        .unwrap();
+
+    let record = AgentValue::object(hashmap! {
+        "id".to_string() => AgentValue::integer(1),
+    });
+    test_utils::write_and_expect_local_value(&ma, &preset_id, "contract_in", record.clone())
+        .await
+        .unwrap();
+    test_utils::expect_local_value(&preset_id, "contract_out", &record)
+        .await
+        .unwrap();
+
+    // a record without id is routed to the generated invalid pin
+    let record = AgentValue::object(hashmap! {
+        "name".to_string() => AgentValue::string("a"),
+    });
+    test_utils::write_and_expect_local_value(&ma, &preset_id, "contract_in", record.clone())
+        .await
+        .unwrap();
+    test_utils::expect_local_value(
+        &preset_id,
+        "contract_invalid",
+        &AgentValue::object(hashmap! {
+            "pin".to_string() => AgentValue::string("value"),
+            "value".to_string() => record,
+            "errors".to_string() => AgentValue::array(vector![AgentValue::string("missing id")]),
+        }),
+    )
+    .await
+    .unwrap();
+
+    ma.quit();
+}
+
+#[tokio::test]
+async fn test_aggregate() {
+    let ma = test_utils::setup_modular_agent().await;
+
+    let preset_id = test_utils::open_and_start_preset(&ma, "tests/presets/Std_Data_test.json")
+        .await
+        .unwrap();
+
+    let records = [("a", 10), ("b", 30), ("a", 20)];
+    for (host, ms) in records {
+        let value = AgentValue::object(hashmap! {
+            "host".to_string() => AgentValue::string(host),
+            "ms".to_string() => AgentValue::integer(ms),
+        });
+        test_utils::write_and_expect_local_value(&ma, &preset_id, "aggregate_in", value)
+            .await
+            .unwrap();
+    }
+
+    // One value per group when the window closes, in order of first appearance
+    test_utils::expect_local_value(
+        &preset_id,
+        "aggregate_out",
+        &AgentValue::object(hashmap! {
+            "group".to_string() => AgentValue::string("a"),
+            "value".to_string() => AgentValue::integer(20),
+            "count".to_string() => AgentValue::integer(2),
+        }),
+    )
+    .await
+    .unwrap();
+    test_utils::expect_local_value(
+        &preset_id,
+        "aggregate_out",
+        &AgentValue::object(hashmap! {
+            "group".to_string() => AgentValue::string("b"),
+            "value".to_string() => AgentValue::integer(30),
+            "count".to_string() => AgentValue::integer(1),
+        }),
+    )
+    .await
+    .unwrap();
+
+    ma.quit();
+}
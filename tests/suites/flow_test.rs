@@ -0,0 +1,282 @@
+extern crate modular_agent_core as ma;
+
+use std::time::Duration;
+
+use im::hashmap;
+use ma::{AgentValue, test_utils};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+
+#[tokio::test]
+async fn test_publish_subscribe() {
+    let ma = test_utils::setup_modular_agent().await;
+
+    let preset_id = test_utils::open_and_start_preset(&ma, "tests/presets/Std_Flow_test.json")
+        .await
+        .unwrap();
+
+    test_utils::write_and_expect_local_value(
+        &ma,
+        &preset_id,
+        "publish_in",
+        AgentValue::number(21.5),
+    )
+    .await
+    .unwrap();
+    test_utils::expect_local_value(
+        &preset_id,
+        "subscribe_out",
+        &AgentValue::object(hashmap! {
+            "topic".to_string() => AgentValue::string("flow_test/room1/temp"),
+            "value".to_string() => AgentValue::number(21.5),
+        }),
+    )
+    .await
+    .unwrap();
+
+    ma.quit();
+}
+
+#[tokio::test]
+async fn test_publish_skips_failing_subscriber() {
+    // The subscriber of a runtime that has quit can no longer be delivered to
+    let quit = test_utils::setup_modular_agent().await;
+    test_utils::open_and_start_preset(&quit, "tests/presets/Std_Flow_test.json")
+        .await
+        .unwrap();
+    quit.quit();
+
+    let ma = test_utils::setup_modular_agent().await;
+
+    let preset_id = test_utils::open_and_start_preset(&ma, "tests/presets/Std_Flow_test.json")
+        .await
+        .unwrap();
+
+    // It does not keep the message from the subscribers after it
+    test_utils::write_and_expect_local_value(
+        &ma,
+        &preset_id,
+        "publish_in",
+        AgentValue::number(22.5),
+    )
+    .await
+    .unwrap();
+    test_utils::expect_local_value(
+        &preset_id,
+        "subscribe_out",
+        &AgentValue::object(hashmap! {
+            "topic".to_string() => AgentValue::string("flow_test/room1/temp"),
+            "value".to_string() => AgentValue::number(22.5),
+        }),
+    )
+    .await
+    .unwrap();
+
+    ma.quit();
+}
+
+#[tokio::test]
+async fn test_bridge() {
+    let ma = test_utils::setup_modular_agent().await;
+
+    let preset_id =
+        test_utils::open_and_start_preset(&ma, "tests/presets/Std_Flow_Bridge_test.json")
+            .await
+            .unwrap();
+
+    // Connect to the listening bridge as a peer
+    let mut stream = None;
+    for _ in 0..20 {
+        match TcpStream::connect("127.0.0.1:47391").await {
+            Ok(s) => {
+                stream = Some(s);
+                break;
+            }
+            Err(_) => tokio::time::sleep(Duration::from_millis(50)).await,
+        }
+    }
+    let (reader, mut writer) = stream.unwrap().into_split();
+    let mut lines = BufReader::new(reader).lines();
+
+    // Messages from the peer are published locally
+    writer
+        .write_all(b"{\"topic\": \"bridge_test/in/a\", \"value\": 1}\n")
+        .await
+        .unwrap();
+    test_utils::expect_local_value(&preset_id, "bridge_subscribe_out", &AgentValue::integer(1))
+        .await
+        .unwrap();
+
+    // Local publications of forwarded topics are sent to the peer
+    test_utils::write_and_expect_local_value(
+        &ma,
+        &preset_id,
+        "bridge_publish_in",
+        AgentValue::string("hello"),
+    )
+    .await
+    .unwrap();
+    let line = tokio::time::timeout(Duration::from_secs(1), lines.next_line())
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+    let msg: serde_json::Value = serde_json::from_str(&line).unwrap();
+    assert_eq!(
+        msg,
+        serde_json::json!({"topic": "bridge_test/out/x", "value": "hello"})
+    );
+
+    // Forwarded topics from one peer are relayed to the other peers, not echoed back
+    let (reader2, mut writer2) = TcpStream::connect("127.0.0.1:47391")
+        .await
+        .unwrap()
+        .into_split();
+    let mut lines2 = BufReader::new(reader2).lines();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    writer2
+        .write_all(b"{\"topic\": \"bridge_test/out/y\", \"value\": 2}\n")
+        .await
+        .unwrap();
+    let line = tokio::time::timeout(Duration::from_secs(1), lines.next_line())
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+    let msg: serde_json::Value = serde_json::from_str(&line).unwrap();
+    assert_eq!(
+        msg,
+        serde_json::json!({"topic": "bridge_test/out/y", "value": 2})
+    );
+    assert!(
+        tokio::time::timeout(Duration::from_millis(200), lines2.next_line())
+            .await
+            .is_err()
+    );
+
+    // A peer sending a too long line is disconnected
+    writer2.write_all(&vec![b'x'; 2 * 1024 * 1024]).await.ok();
+    let line = tokio::time::timeout(Duration::from_secs(1), lines2.next_line())
+        .await
+        .unwrap();
+    assert!(!matches!(line, Ok(Some(_))));
+
+    // Stopping the bridge disconnects its peers, and nothing they send is published
+    let bridge_id = ma
+        .get_preset_spec(&preset_id)
+        .await
+        .unwrap()
+        .agents
+        .into_iter()
+        .find(|agent| agent.def_name == "modular_agent_std::flow::BridgeAgent")
+        .unwrap()
+        .id;
+    ma.stop_agent(&bridge_id).await.unwrap();
+    writer
+        .write_all(b"{\"topic\": \"bridge_test/in/a\", \"value\": 3}\n")
+        .await
+        .ok();
+    assert!(
+        test_utils::recv_external_output_with_timeout(Duration::from_millis(200))
+            .await
+            .is_err()
+    );
+    let line = tokio::time::timeout(Duration::from_secs(1), lines.next_line())
+        .await
+        .unwrap();
+    assert!(!matches!(line, Ok(Some(_))));
+
+    ma.quit();
+}
+
+#[tokio::test]
+async fn test_saga() {
+    let ma = test_utils::setup_modular_agent().await;
+
+    let preset_id = test_utils::open_and_start_preset(&ma, "tests/presets/Std_Flow_test.json")
+        .await
+        .unwrap();
+
+    // the failure after both steps emits their compensations from the last one
+    let value = AgentValue::object(hashmap! {"name".to_string() => AgentValue::string("vm1")});
+    test_utils::write_and_expect_local_value(&ma, &preset_id, "saga_in", value.clone())
+        .await
+        .unwrap();
+    test_utils::expect_local_value(
+        &preset_id,
+        "saga_compensation_out",
+        &AgentValue::object(hashmap! {
+            "step".to_string() => AgentValue::string("attach"),
+            "compensation".to_string() => value.clone(),
+        }),
+    )
+    .await
+    .unwrap();
+    test_utils::expect_local_value(
+        &preset_id,
+        "saga_compensation_out",
+        &AgentValue::object(hashmap! {
+            "step".to_string() => AgentValue::string("create"),
+            "compensation".to_string() => AgentValue::object(hashmap! {
+                "delete".to_string() => AgentValue::string("vm1"),
+            }),
+        }),
+    )
+    .await
+    .unwrap();
+    test_utils::expect_local_value(&preset_id, "saga_aborted_out", &value)
+        .await
+        .unwrap();
+
+    ma.quit();
+}
+
+#[tokio::test]
+async fn test_tenant() {
+    let ma = test_utils::setup_modular_agent().await;
+
+    let preset_id = test_utils::open_and_start_preset(&ma, "tests/presets/Std_Flow_test.json")
+        .await
+        .unwrap();
+
+    // the counter keeps a count per customer
+    let event = |customer: &str| {
+        AgentValue::object(hashmap! {"customer".to_string() => AgentValue::string(customer)})
+    };
+    for (customer, count) in [("acme", 1), ("acme", 2), ("globex", 1), ("acme", 3)] {
+        test_utils::write_and_expect_local_value(&ma, &preset_id, "tenant_in", event(customer))
+            .await
+            .unwrap();
+        test_utils::expect_local_value(&preset_id, "tenant_count_out", &AgentValue::integer(count))
+            .await
+            .unwrap();
+    }
+
+    ma.quit();
+}
+
+#[tokio::test]
+async fn test_quorum() {
+    let ma = test_utils::setup_modular_agent().await;
+
+    let preset_id = test_utils::open_and_start_preset(&ma, "tests/presets/Std_Flow_test.json")
+        .await
+        .unwrap();
+
+    // both branches of the same context agree
+    for i in 0..3 {
+        test_utils::write_and_expect_local_value(
+            &ma,
+            &preset_id,
+            "quorum_in",
+            AgentValue::integer(i),
+        )
+        .await
+        .unwrap();
+        test_utils::expect_local_value(&preset_id, "quorum_out", &AgentValue::integer(i))
+            .await
+            .unwrap();
+    }
+
+    ma.quit();
+}
@@ -0,0 +1,244 @@
+extern crate modular_agent_core as ma;
+
+use std::time::Duration;
+
+use im::hashmap;
+use ma::{AgentValue, test_utils};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+#[tokio::test]
+async fn test_get_and_set_config() {
+    let ma = test_utils::setup_modular_agent().await;
+
+    let preset_id = test_utils::open_and_start_preset(&ma, "tests/presets/Std_Meta_test.json")
+        .await
+        .unwrap();
+
+    // the target is referred to by its title, since ids change on every load
+    test_utils::write_and_expect_local_value(&ma, &preset_id, "get", AgentValue::unit())
+        .await
+        .unwrap();
+    test_utils::expect_local_value(&preset_id, "config", &AgentValue::string("a"))
+        .await
+        .unwrap();
+
+    test_utils::write_and_expect_local_value(&ma, &preset_id, "set", AgentValue::string("b"))
+        .await
+        .unwrap();
+    test_utils::expect_local_value(
+        &preset_id,
+        "updated",
+        &AgentValue::object(hashmap! {
+            "key".to_string() => AgentValue::string("b"),
+        }),
+    )
+    .await
+    .unwrap();
+
+    // configs are applied by the target's own task
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    test_utils::write_and_expect_local_value(&ma, &preset_id, "get", AgentValue::unit())
+        .await
+        .unwrap();
+    test_utils::expect_local_value(&preset_id, "config", &AgentValue::string("b"))
+        .await
+        .unwrap();
+
+    // a config of the wrong type is rejected
+    test_utils::write_and_expect_local_value(&ma, &preset_id, "set", AgentValue::integer(1))
+        .await
+        .unwrap();
+    assert!(
+        test_utils::recv_external_output_with_timeout(Duration::from_millis(200))
+            .await
+            .is_err()
+    );
+
+    ma.quit();
+}
+
+#[tokio::test]
+async fn test_export_and_import_configs() {
+    let ma = test_utils::setup_modular_agent().await;
+
+    let preset_id =
+        test_utils::open_and_start_preset(&ma, "tests/presets/Std_Meta_Configs_test.json")
+            .await
+            .unwrap();
+
+    let export = |preset_id: String| {
+        let ma = ma.clone();
+        async move {
+            test_utils::write_and_expect_local_value(&ma, &preset_id, "export", AgentValue::unit())
+                .await
+                .unwrap();
+            let yaml = recv_local(&preset_id, "exported").await;
+            yaml.as_str().unwrap().to_string()
+        }
+    };
+    let yaml = export(preset_id.clone()).await;
+    assert!(yaml.contains("Integer Input:\n    integer: 3"), "{}", yaml);
+    assert!(yaml.contains("Set Value:\n    key: a"), "{}", yaml);
+
+    // a dry run outputs the diff only
+    let doc = yaml
+        .replace("integer: 3", "integer: 5")
+        .replace("key: a", "key: b");
+    test_utils::write_and_expect_local_value(
+        &ma,
+        &preset_id,
+        "import",
+        AgentValue::string(doc.clone()),
+    )
+    .await
+    .unwrap();
+    let diff = recv_local(&preset_id, "diff").await;
+    assert_eq!(diff.as_array().map(|d| d.len()), Some(2), "{:?}", diff);
+    assert_eq!(export(preset_id.clone()).await, yaml);
+
+    test_utils::write_and_expect_local_value(&ma, &preset_id, "apply", AgentValue::string(doc))
+        .await
+        .unwrap();
+    // the Integer Input outputs its new value, maybe before the diff is output
+    let mut outputs = Vec::new();
+    for _ in 0..2 {
+        outputs.push(
+            test_utils::recv_external_output_with_timeout(Duration::from_secs(2))
+                .await
+                .unwrap(),
+        );
+    }
+    let output = |name: &str| {
+        let name = format!("%{}/{}", preset_id, name);
+        outputs
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, v)| v.clone())
+    };
+    let diff = output("diff").unwrap();
+    assert_eq!(diff.as_array().map(|d| d.len()), Some(2), "{:?}", diff);
+    assert_eq!(output("integer"), Some(AgentValue::integer(5)));
+    let yaml = export(preset_id.clone()).await;
+    assert!(yaml.contains("integer: 5"), "{}", yaml);
+    assert!(yaml.contains("key: b"), "{}", yaml);
+
+    ma.quit();
+}
+
+async fn recv_local(preset_id: &str, name: &str) -> AgentValue {
+    let (out, value) = test_utils::recv_external_output_with_timeout(Duration::from_secs(2))
+        .await
+        .unwrap();
+    assert_eq!(out, format!("%{}/{}", preset_id, name), "{:?}", value);
+    value
+}
+
+#[tokio::test]
+async fn test_preset_control() {
+    let ma = test_utils::setup_modular_agent().await;
+
+    let path = "tests/presets/Std_Meta_test.json";
+    let preset_id = test_utils::open_and_start_preset(&ma, path).await.unwrap();
+
+    // reload restores the configs of the file
+    test_utils::write_and_expect_local_value(&ma, &preset_id, "set", AgentValue::string("b"))
+        .await
+        .unwrap();
+    recv_local(&preset_id, "updated").await;
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    test_utils::write_and_expect_local_value(&ma, &preset_id, "reload", AgentValue::unit())
+        .await
+        .unwrap();
+    let reloaded = recv_local(&preset_id, "reloaded").await;
+    assert_eq!(reloaded.get_str("preset"), Some(preset_id.as_str()));
+    assert_eq!(
+        reloaded
+            .get("updated")
+            .and_then(|u| u.as_array())
+            .map(|u| u.len()),
+        Some(1)
+    );
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    test_utils::write_and_expect_local_value(&ma, &preset_id, "get", AgentValue::unit())
+        .await
+        .unwrap();
+    test_utils::expect_local_value(&preset_id, "config", &AgentValue::string("a"))
+        .await
+        .unwrap();
+
+    // a preset file is opened, started and stopped by its path
+    test_utils::write_and_expect_local_value(&ma, &preset_id, "start", AgentValue::unit())
+        .await
+        .unwrap();
+    let started = recv_local(&preset_id, "started").await;
+    let worker_id = started.get_str("preset").unwrap().to_string();
+    assert_ne!(worker_id, preset_id);
+    assert_eq!(started.get_str("preset_name"), Some(path));
+    assert!(ma.get_preset_info(&worker_id).await.unwrap().running);
+
+    test_utils::write_and_expect_local_value(&ma, &preset_id, "stop", AgentValue::unit())
+        .await
+        .unwrap();
+    let stopped = recv_local(&preset_id, "stopped").await;
+    assert_eq!(stopped.get_str("preset"), Some(worker_id.as_str()));
+    assert!(!ma.get_preset_info(&worker_id).await.unwrap().running);
+
+    test_utils::write_and_expect_local_value(&ma, &preset_id, "stop_unknown", AgentValue::unit())
+        .await
+        .unwrap();
+    let failure = recv_local(&preset_id, "failure").await;
+    assert_eq!(failure.get_str("preset"), Some("unknown"));
+
+    ma.quit();
+}
+
+async fn probe(path: &str) -> (u16, serde_json::Value) {
+    let mut stream = TcpStream::connect("127.0.0.1:18931").await.unwrap();
+    stream
+        .write_all(format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).as_bytes())
+        .await
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    let code = head.split_whitespace().nth(1).unwrap().parse().unwrap();
+    (code, serde_json::from_str(body).unwrap())
+}
+
+#[tokio::test]
+async fn test_health_server() {
+    let ma = test_utils::setup_modular_agent().await;
+
+    let preset_id =
+        test_utils::open_and_start_preset(&ma, "tests/presets/Std_Meta_Health_test.json")
+            .await
+            .unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    let (code, body) = probe("/readyz").await;
+    assert_eq!(code, 200, "{}", body);
+    assert_eq!(body["agents"]["started"], body["agents"]["total"]);
+
+    test_utils::write_and_expect_local_value(&ma, &preset_id, "problem", AgentValue::string("hot"))
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let (code, body) = probe("/healthz").await;
+    assert_eq!(code, 200);
+    assert_eq!(body["status"], "degraded");
+    let (code, body) = probe("/readyz").await;
+    assert_eq!(code, 503);
+    assert_eq!(body["problem"], "hot");
+    assert_eq!(body["problems"][""], "hot");
+
+    test_utils::write_and_expect_local_value(&ma, &preset_id, "ok", AgentValue::unit())
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(probe("/readyz").await.0, 200);
+    assert_eq!(probe("/metrics").await.0, 404);
+
+    ma.stop_preset(&preset_id).await.unwrap();
+    ma.quit();
+}
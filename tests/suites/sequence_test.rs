@@ -0,0 +1,227 @@
+extern crate modular_agent_core as ma;
+
+use im::{hashmap, vector};
+use ma::{AgentValue, test_utils};
+
+#[tokio::test]
+async fn test_sample_every() {
+    let ma = test_utils::setup_modular_agent().await;
+
+    let preset_id = test_utils::open_and_start_preset(&ma, "tests/presets/Std_Sequence_test.json")
+        .await
+        .unwrap();
+
+    // n = 2: only every 2nd value passes
+    for i in 1..=4 {
+        test_utils::write_and_expect_local_value(
+            &ma,
+            &preset_id,
+            "sample_every_in",
+            AgentValue::integer(i),
+        )
+        .await
+        .unwrap();
+        if i % 2 == 0 {
+            test_utils::expect_local_value(&preset_id, "sample_every_out", &AgentValue::integer(i))
+                .await
+                .unwrap();
+        }
+    }
+
+    // 5th value is dropped, reset restarts the count
+    test_utils::write_and_expect_local_value(
+        &ma,
+        &preset_id,
+        "sample_every_in",
+        AgentValue::integer(5),
+    )
+    .await
+    .unwrap();
+    test_utils::write_and_expect_local_value(
+        &ma,
+        &preset_id,
+        "sample_every_reset",
+        AgentValue::unit(),
+    )
+    .await
+    .unwrap();
+    test_utils::write_and_expect_local_value(
+        &ma,
+        &preset_id,
+        "sample_every_in",
+        AgentValue::integer(6),
+    )
+    .await
+    .unwrap();
+    test_utils::write_and_expect_local_value(
+        &ma,
+        &preset_id,
+        "sample_every_in",
+        AgentValue::integer(7),
+    )
+    .await
+    .unwrap();
+    test_utils::expect_local_value(&preset_id, "sample_every_out", &AgentValue::integer(7))
+        .await
+        .unwrap();
+
+    ma.quit();
+}
+
+#[tokio::test]
+async fn test_sample_every_contract() {
+    let ma = test_utils::setup_modular_agent().await;
+
+    let preset_id = test_utils::open_and_start_preset(&ma, "tests/presets/Std_Sequence_test.json")
+        .await
+        .unwrap();
+
+    // input_contract "*" takes integers only: a string is rejected, but reset still
+    // resets the count
+    test_utils::write_and_expect_local_value(
+        &ma,
+        &preset_id,
+        "sample_every_in",
+        AgentValue::integer(1),
+    )
+    .await
+    .unwrap();
+    test_utils::write_and_expect_local_value(
+        &ma,
+        &preset_id,
+        "sample_every_in",
+        AgentValue::string("a"),
+    )
+    .await
+    .unwrap();
+    test_utils::write_and_expect_local_value(
+        &ma,
+        &preset_id,
+        "sample_every_reset",
+        AgentValue::unit(),
+    )
+    .await
+    .unwrap();
+    for i in 2..=3 {
+        test_utils::write_and_expect_local_value(
+            &ma,
+            &preset_id,
+            "sample_every_in",
+            AgentValue::integer(i),
+        )
+        .await
+        .unwrap();
+    }
+    test_utils::expect_local_value(&preset_id, "sample_every_out", &AgentValue::integer(3))
+        .await
+        .unwrap();
+
+    ma.quit();
+}
+
+#[tokio::test]
+async fn test_pairwise() {
+    let ma = test_utils::setup_modular_agent().await;
+
+    let preset_id = test_utils::open_and_start_preset(&ma, "tests/presets/Std_Sequence_test.json")
+        .await
+        .unwrap();
+
+    // The first value only becomes the previous one
+    test_utils::write_and_expect_local_value(
+        &ma,
+        &preset_id,
+        "pairwise_in",
+        AgentValue::integer(1),
+    )
+    .await
+    .unwrap();
+    test_utils::write_and_expect_local_value(
+        &ma,
+        &preset_id,
+        "pairwise_in",
+        AgentValue::integer(3),
+    )
+    .await
+    .unwrap();
+    test_utils::expect_local_value(
+        &preset_id,
+        "pairwise_out",
+        &AgentValue::object(hashmap! {
+            "previous".to_string() => AgentValue::integer(1),
+            "current".to_string() => AgentValue::integer(3),
+        }),
+    )
+    .await
+    .unwrap();
+
+    ma.quit();
+}
+
+#[tokio::test]
+async fn test_repeat() {
+    let ma = test_utils::setup_modular_agent().await;
+
+    let preset_id = test_utils::open_and_start_preset(&ma, "tests/presets/Std_Sequence_test.json")
+        .await
+        .unwrap();
+
+    // map frames let Collect reassemble the copies
+    test_utils::write_and_expect_local_value(&ma, &preset_id, "repeat_in", AgentValue::string("x"))
+        .await
+        .unwrap();
+    test_utils::expect_local_value(
+        &preset_id,
+        "repeat_out",
+        &AgentValue::array(vector![AgentValue::string("x"), AgentValue::string("x")]),
+    )
+    .await
+    .unwrap();
+
+    // the n pin overrides the config
+    test_utils::write_and_expect_local_value(&ma, &preset_id, "repeat_n", AgentValue::integer(3))
+        .await
+        .unwrap();
+    test_utils::write_and_expect_local_value(&ma, &preset_id, "repeat_in", AgentValue::integer(1))
+        .await
+        .unwrap();
+    test_utils::expect_local_value(
+        &preset_id,
+        "repeat_out",
+        &AgentValue::array(vector![
+            AgentValue::integer(1),
+            AgentValue::integer(1),
+            AgentValue::integer(1)
+        ]),
+    )
+    .await
+    .unwrap();
+
+    ma.quit();
+}
+
+#[tokio::test]
+async fn test_split_traffic() {
+    let ma = test_utils::setup_modular_agent().await;
+
+    let preset_id = test_utils::open_and_start_preset(&ma, "tests/presets/Std_Sequence_test.json")
+        .await
+        .unwrap();
+
+    // weights = "0, 1, 0": every value goes to out2, with or without the sticky key
+    let values = [
+        AgentValue::object(hashmap! {"user".into() => AgentValue::string("alice")}),
+        AgentValue::object(hashmap! {"user".into() => AgentValue::string("bob")}),
+        AgentValue::integer(3),
+    ];
+    for value in values {
+        test_utils::write_and_expect_local_value(&ma, &preset_id, "split_in", value.clone())
+            .await
+            .unwrap();
+        test_utils::expect_local_value(&preset_id, "split_out2", &value)
+            .await
+            .unwrap();
+    }
+
+    ma.quit();
+}
@@ -1,6 +1,6 @@
 extern crate modular_agent_core as ma;
 
-use im::vector;
+use im::{hashmap, vector};
 use ma::{AgentValue, test_utils};
 
 #[tokio::test]
@@ -292,3 +292,192 @@ async fn test_template_array() {
 
     ma.quit();
 }
+
+#[tokio::test]
+async fn test_template_value() {
+    let ma = test_utils::setup_modular_agent().await;
+
+    let preset_id = test_utils::open_and_start_preset(&ma, "tests/presets/Std_String_test.json")
+        .await
+        .unwrap();
+
+    let expected = AgentValue::object(hashmap! {
+        "name".to_string() => AgentValue::string("bob"),
+        "tags".to_string() => AgentValue::array(vector![AgentValue::integer(1), AgentValue::integer(2)]),
+    });
+
+    // Rendered JSON -> object
+    test_utils::write_and_expect_local_value(
+        &ma,
+        &preset_id,
+        "template_value_json_in",
+        AgentValue::string("bob"),
+    )
+    .await
+    .unwrap();
+    test_utils::expect_local_value(&preset_id, "template_value_json_out", &expected)
+        .await
+        .unwrap();
+
+    // Quotes and newlines are escaped inside the JSON string
+    let name = "say \"hi\"\nbob";
+    test_utils::write_and_expect_local_value(
+        &ma,
+        &preset_id,
+        "template_value_json_in",
+        AgentValue::string(name),
+    )
+    .await
+    .unwrap();
+    test_utils::expect_local_value(
+        &preset_id,
+        "template_value_json_out",
+        &AgentValue::object(hashmap! {
+            "name".to_string() => AgentValue::string(name),
+            "tags".to_string() => AgentValue::array(vector![AgentValue::integer(1), AgentValue::integer(2)]),
+        }),
+    )
+    .await
+    .unwrap();
+
+    // Rendered YAML -> object
+    test_utils::write_and_expect_local_value(
+        &ma,
+        &preset_id,
+        "template_value_yaml_in",
+        AgentValue::string("bob"),
+    )
+    .await
+    .unwrap();
+    test_utils::expect_local_value(&preset_id, "template_value_yaml_out", &expected)
+        .await
+        .unwrap();
+
+    ma.quit();
+}
+
+#[tokio::test]
+async fn test_sentiment() {
+    let ma = test_utils::setup_modular_agent().await;
+
+    let preset_id = test_utils::open_and_start_preset(&ma, "tests/presets/Std_String_test.json")
+        .await
+        .unwrap();
+
+    let text = AgentValue::string("Thanks, the new release is really great");
+    test_utils::write_and_expect_local_value(&ma, &preset_id, "sentiment_in", text.clone())
+        .await
+        .unwrap();
+    test_utils::expect_local_value(&preset_id, "sentiment_positive_out", &text)
+        .await
+        .unwrap();
+
+    // negated, and a word from the configured lexicon
+    let text = AgentValue::string("The dashboard is not good, latency everywhere");
+    test_utils::write_and_expect_local_value(&ma, &preset_id, "sentiment_in", text.clone())
+        .await
+        .unwrap();
+    test_utils::expect_local_value(&preset_id, "sentiment_negative_out", &text)
+        .await
+        .unwrap();
+
+    ma.quit();
+}
+
+#[tokio::test]
+async fn test_extract_keywords() {
+    let ma = test_utils::setup_modular_agent().await;
+
+    let preset_id = test_utils::open_and_start_preset(&ma, "tests/presets/Std_String_test.json")
+        .await
+        .unwrap();
+
+    test_utils::write_and_expect_local_value(
+        &ma,
+        &preset_id,
+        "keywords_in",
+        AgentValue::string(
+            "Disk full on the server. Disk cleanup failed, the server disk is at 99%.",
+        ),
+    )
+    .await
+    .unwrap();
+    test_utils::expect_local_value(
+        &preset_id,
+        "keywords_out",
+        &AgentValue::array(vector![
+            AgentValue::object(hashmap! {
+                "term".to_string() => AgentValue::string("disk"),
+                "score".to_string() => AgentValue::number(0.5),
+            }),
+            AgentValue::object(hashmap! {
+                "term".to_string() => AgentValue::string("full"),
+                "score".to_string() => AgentValue::number(1.0 / 6.0),
+            }),
+        ]),
+    )
+    .await
+    .unwrap();
+
+    ma.quit();
+}
+
+#[tokio::test]
+async fn test_text_diff() {
+    let ma = test_utils::setup_modular_agent().await;
+
+    let preset_id = test_utils::open_and_start_preset(&ma, "tests/presets/Std_String_test.json")
+        .await
+        .unwrap();
+
+    test_utils::write_and_expect_local_value(
+        &ma,
+        &preset_id,
+        "text_diff_old_in",
+        AgentValue::string("a\nb\nc\nd\ne"),
+    )
+    .await
+    .unwrap();
+    test_utils::write_and_expect_local_value(
+        &ma,
+        &preset_id,
+        "text_diff_new_in",
+        AgentValue::string("a\nb\nX\nd\ne"),
+    )
+    .await
+    .unwrap();
+    test_utils::expect_local_value(
+        &preset_id,
+        "text_diff_out",
+        &AgentValue::string("--- old\n+++ new\n@@ -2,3 +2,3 @@\n b\n-c\n+X\n d\n"),
+    )
+    .await
+    .unwrap();
+
+    let change = |op: &str, text: &str| {
+        AgentValue::object(hashmap! {
+            "op".to_string() => AgentValue::string(op),
+            "text".to_string() => AgentValue::string(text),
+        })
+    };
+    test_utils::expect_local_value(
+        &preset_id,
+        "text_diff_hunks_out",
+        &AgentValue::array(vector![AgentValue::object(hashmap! {
+            "old_start".to_string() => AgentValue::integer(2),
+            "old_count".to_string() => AgentValue::integer(3),
+            "new_start".to_string() => AgentValue::integer(2),
+            "new_count".to_string() => AgentValue::integer(3),
+            "changes".to_string() => AgentValue::array(vector![
+                change("equal", "b"),
+                change("delete", "c"),
+                change("insert", "X"),
+                change("equal", "d"),
+            ]),
+        })]),
+    )
+    .await
+    .unwrap();
+
+    ma.quit();
+}
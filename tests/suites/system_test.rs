@@ -0,0 +1,26 @@
+extern crate modular_agent_core as ma;
+
+use im::hashmap;
+use ma::{AgentValue, test_utils};
+
+#[tokio::test]
+async fn test_stdout_write() {
+    let ma = test_utils::setup_modular_agent().await;
+
+    let preset_id = test_utils::open_and_start_preset(&ma, "tests/presets/Std_System_test.json")
+        .await
+        .unwrap();
+
+    // The value is written to stdout and passed through
+    let value = AgentValue::object(hashmap! {
+        "n".to_string() => AgentValue::integer(1),
+    });
+    test_utils::write_and_expect_local_value(&ma, &preset_id, "stdout_in", value.clone())
+        .await
+        .unwrap();
+    test_utils::expect_local_value(&preset_id, "stdout_out", &value)
+        .await
+        .unwrap();
+
+    ma.quit();
+}
@@ -0,0 +1,327 @@
+extern crate modular_agent_core as ma;
+
+use std::time::Duration;
+
+use im::hashmap;
+use ma::{AgentError, AgentValue, test_utils};
+use tokio::time::Instant;
+
+// Receives the next `n` values output to the local variable `var_name`,
+// skipping the echoes of the written inputs.
+async fn recv_local_values(
+    preset_id: &str,
+    var_name: &str,
+    n: usize,
+) -> Result<Vec<AgentValue>, AgentError> {
+    let name = format!("%{}/{}", preset_id, var_name);
+    let mut values = Vec::new();
+    while values.len() < n {
+        let (out_name, value) =
+            test_utils::recv_external_output_with_timeout(Duration::from_secs(2)).await?;
+        if out_name == name {
+            values.push(value);
+        }
+    }
+    Ok(values)
+}
+
+#[tokio::test]
+async fn test_delay() {
+    let ma = test_utils::setup_modular_agent().await;
+
+    let preset_id = test_utils::open_and_start_preset(&ma, "tests/presets/Std_Time_test.json")
+        .await
+        .unwrap();
+
+    let start = Instant::now();
+    for i in 0..5 {
+        ma.write_local_input(&preset_id, "delay_in", AgentValue::integer(i))
+            .await
+            .unwrap();
+    }
+    let values = recv_local_values(&preset_id, "delay_out", 5).await.unwrap();
+    assert_eq!(values, (0..5).map(AgentValue::integer).collect::<Vec<_>>());
+    assert!(start.elapsed() >= Duration::from_millis(50));
+
+    ma.quit();
+}
+
+#[tokio::test]
+async fn test_delay_from_key() {
+    let ma = test_utils::setup_modular_agent().await;
+
+    let preset_id = test_utils::open_and_start_preset(&ma, "tests/presets/Std_Time_test.json")
+        .await
+        .unwrap();
+
+    let retry = |ms: i64| {
+        AgentValue::object(hashmap! {
+            "retry".to_string() => AgentValue::object(hashmap! {
+                "after_ms".to_string() => AgentValue::integer(ms),
+            }),
+        })
+    };
+
+    // 200ms from the value, 5000ms clamped to the max (300ms), 0ms clamped to the min (50ms)
+    let start = Instant::now();
+    for ms in [200, 5000, 0] {
+        ma.write_local_input(&preset_id, "delay_key_in", retry(ms))
+            .await
+            .unwrap();
+    }
+    let values = recv_local_values(&preset_id, "delay_key_out", 3)
+        .await
+        .unwrap();
+    assert_eq!(values, vec![retry(0), retry(200), retry(5000)]);
+    let elapsed = start.elapsed();
+    assert!(elapsed >= Duration::from_millis(300));
+    assert!(elapsed < Duration::from_millis(1000), "took {:?}", elapsed);
+
+    // without the key, the fixed delay (1000ms) is used
+    let start = Instant::now();
+    ma.write_local_input(&preset_id, "delay_key_in", AgentValue::integer(1))
+        .await
+        .unwrap();
+    let values = recv_local_values(&preset_id, "delay_key_out", 1)
+        .await
+        .unwrap();
+    assert_eq!(values, vec![AgentValue::integer(1)]);
+    assert!(start.elapsed() >= Duration::from_millis(1000));
+
+    ma.quit();
+}
+
+#[tokio::test]
+async fn test_deadline() {
+    let ma = test_utils::setup_modular_agent().await;
+
+    let preset_id = test_utils::open_and_start_preset(&ma, "tests/presets/Std_Time_test.json")
+        .await
+        .unwrap();
+
+    // the deadline (100ms) expires before the delay (2000ms) ends
+    let start = Instant::now();
+    ma.write_local_input(&preset_id, "deadline_in", AgentValue::integer(1))
+        .await
+        .unwrap();
+    let values = recv_local_values(&preset_id, "deadline_expired_out", 1)
+        .await
+        .unwrap();
+    assert_eq!(values, vec![AgentValue::integer(1)]);
+    let elapsed = start.elapsed();
+    assert!(elapsed >= Duration::from_millis(100));
+    assert!(elapsed < Duration::from_millis(1000), "took {:?}", elapsed);
+
+    ma.quit();
+}
+
+#[tokio::test]
+async fn test_throttle() {
+    let ma = test_utils::setup_modular_agent().await;
+
+    let preset_id = test_utils::open_and_start_preset(&ma, "tests/presets/Std_Time_test.json")
+        .await
+        .unwrap();
+
+    let start = Instant::now();
+    for i in 0..4 {
+        ma.write_local_input(&preset_id, "throttle_in", AgentValue::integer(i))
+            .await
+            .unwrap();
+    }
+    let values = recv_local_values(&preset_id, "throttle_out", 4)
+        .await
+        .unwrap();
+    assert_eq!(values, (0..4).map(AgentValue::integer).collect::<Vec<_>>());
+    // the first value is output immediately, the rest are spaced by the throttle time
+    assert!(start.elapsed() >= Duration::from_millis(150));
+
+    ma.quit();
+}
+
+#[tokio::test]
+async fn test_throttle_does_not_stall() {
+    let ma = test_utils::setup_modular_agent().await;
+
+    let preset_id = test_utils::open_and_start_preset(&ma, "tests/presets/Std_Time_test.json")
+        .await
+        .unwrap();
+
+    // Writes in bursts timed around the throttle time, so that values arrive
+    // while the timer is about to stop.
+    let mut expected = Vec::new();
+    for i in 0..10 {
+        ma.write_local_input(&preset_id, "throttle_in", AgentValue::integer(i))
+            .await
+            .unwrap();
+        expected.push(AgentValue::integer(i));
+        let values = recv_local_values(&preset_id, "throttle_out", 1)
+            .await
+            .unwrap();
+        assert_eq!(values[0], expected[i as usize]);
+        tokio::time::sleep(Duration::from_millis(45 + (i as u64 % 3) * 5)).await;
+    }
+
+    ma.quit();
+}
+
+#[tokio::test]
+async fn test_throttle_leading() {
+    let ma = test_utils::setup_modular_agent().await;
+
+    let preset_id = test_utils::open_and_start_preset(&ma, "tests/presets/Std_Time_test.json")
+        .await
+        .unwrap();
+
+    // only the first value of the window is output
+    for i in 0..3 {
+        ma.write_local_input(&preset_id, "throttle_leading_in", AgentValue::integer(i))
+            .await
+            .unwrap();
+    }
+    let values = recv_local_values(&preset_id, "throttle_leading_out", 1)
+        .await
+        .unwrap();
+    assert_eq!(values, vec![AgentValue::integer(0)]);
+
+    // the next window starts with the next value
+    tokio::time::sleep(Duration::from_millis(150)).await;
+    ma.write_local_input(&preset_id, "throttle_leading_in", AgentValue::integer(3))
+        .await
+        .unwrap();
+    let values = recv_local_values(&preset_id, "throttle_leading_out", 1)
+        .await
+        .unwrap();
+    assert_eq!(values, vec![AgentValue::integer(3)]);
+
+    ma.quit();
+}
+
+#[tokio::test]
+async fn test_throttle_trailing() {
+    let ma = test_utils::setup_modular_agent().await;
+
+    let preset_id = test_utils::open_and_start_preset(&ma, "tests/presets/Std_Time_test.json")
+        .await
+        .unwrap();
+
+    // only the last value of the window is output, at its end
+    let start = Instant::now();
+    for i in 0..3 {
+        ma.write_local_input(&preset_id, "throttle_trailing_in", AgentValue::integer(i))
+            .await
+            .unwrap();
+    }
+    let values = recv_local_values(&preset_id, "throttle_trailing_out", 1)
+        .await
+        .unwrap();
+    assert_eq!(values, vec![AgentValue::integer(2)]);
+    assert!(start.elapsed() >= Duration::from_millis(100));
+
+    ma.quit();
+}
+
+#[tokio::test]
+async fn test_keepalive() {
+    let ma = test_utils::setup_modular_agent().await;
+
+    let preset_id = test_utils::open_and_start_preset(&ma, "tests/presets/Std_Time_test.json")
+        .await
+        .unwrap();
+
+    // escalates when no progress arrives within the timeout
+    ma.write_local_input(&preset_id, "keepalive_start", AgentValue::string("job"))
+        .await
+        .unwrap();
+    let values = recv_local_values(&preset_id, "keepalive_timeout_out", 1)
+        .await
+        .unwrap();
+    assert_eq!(values[0].get_str("value"), Some("job"));
+    assert_eq!(values[0].get_i64("count"), Some(1));
+
+    // progress keeps it alive, and the result suppresses the escalation
+    ma.write_local_input(&preset_id, "keepalive_start", AgentValue::string("job2"))
+        .await
+        .unwrap();
+    for i in 0..4 {
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        ma.write_local_input(&preset_id, "keepalive_progress", AgentValue::integer(i))
+            .await
+            .unwrap();
+    }
+    ma.write_local_input(&preset_id, "keepalive_result", AgentValue::string("done"))
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let mut outputs = Vec::new();
+    while let Ok((name, value)) =
+        test_utils::recv_external_output_with_timeout(Duration::from_millis(100)).await
+    {
+        if name.ends_with("_out") {
+            outputs.push((name, value));
+        }
+    }
+    assert_eq!(
+        outputs,
+        vec![(
+            format!("%{}/keepalive_result_out", preset_id),
+            AgentValue::string("done")
+        )]
+    );
+
+    // progress after the result does not start a new supervision
+    ma.write_local_input(&preset_id, "keepalive_progress", AgentValue::integer(4))
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    let timeout_out = format!("%{}/keepalive_timeout_out", preset_id);
+    while let Ok((name, _)) =
+        test_utils::recv_external_output_with_timeout(Duration::from_millis(100)).await
+    {
+        assert_ne!(name, timeout_out);
+    }
+
+    ma.quit();
+}
+
+#[tokio::test]
+async fn test_interval_timer_pause() {
+    let ma = test_utils::setup_modular_agent().await;
+
+    let preset_id =
+        test_utils::open_and_start_preset(&ma, "tests/presets/Std_Time_Pause_test.json")
+            .await
+            .unwrap();
+    let tick_out = format!("%{}/tick_out", preset_id);
+    recv_local_values(&preset_id, "tick_out", 1).await.unwrap();
+
+    // ticks are dropped while paused, not held until resumed
+    ma.write_local_input(&preset_id, "pause_in", AgentValue::unit())
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    while test_utils::recv_external_output_with_timeout(Duration::from_millis(10))
+        .await
+        .is_ok()
+    {}
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    let mut ticks = 0;
+    while let Ok((name, _)) =
+        test_utils::recv_external_output_with_timeout(Duration::from_millis(10)).await
+    {
+        if name == tick_out {
+            ticks += 1;
+        }
+    }
+    assert_eq!(ticks, 0);
+
+    ma.write_local_input(&preset_id, "resume_in", AgentValue::unit())
+        .await
+        .unwrap();
+    let start = Instant::now();
+    recv_local_values(&preset_id, "tick_out", 1).await.unwrap();
+    assert!(start.elapsed() < Duration::from_millis(500));
+
+    ma.quit();
+}
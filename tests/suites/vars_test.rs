@@ -0,0 +1,35 @@
+extern crate modular_agent_core as ma;
+
+use im::hashmap;
+use ma::{AgentValue, test_utils};
+
+#[tokio::test]
+async fn test_vars() {
+    let ma = test_utils::setup_modular_agent().await;
+
+    let preset_id = test_utils::open_and_start_preset(&ma, "tests/presets/Std_Vars_test.json")
+        .await
+        .unwrap();
+
+    // Var emits the default on start
+    test_utils::expect_local_value(&preset_id, "var_url", &AgentValue::string("http://dev"))
+        .await
+        .unwrap();
+
+    // and re-emits when the variable is overridden
+    test_utils::write_and_expect_local_value(
+        &ma,
+        &preset_id,
+        "vars_in",
+        AgentValue::object(hashmap! {
+            "url".to_string() => AgentValue::string("http://prod"),
+        }),
+    )
+    .await
+    .unwrap();
+    test_utils::expect_local_value(&preset_id, "var_url", &AgentValue::string("http://prod"))
+        .await
+        .unwrap();
+
+    ma.quit();
+}